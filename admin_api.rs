@@ -0,0 +1,578 @@
+// 本地管理 API（运行时查看与控制）
+//
+// 独立端口（默认 127.0.0.1:8789），供 GUI / CLI 查看与调整运行状态，无需直接改配置文件：
+// - GET  /_amp/admin/state：Profile 选择（含运行时切换）、路由不可用标记（熔断状态）、各缓存统计
+// - GET  /_amp/admin/requests：最近请求（内存环形缓冲）；带 route / model / since_ms 等参数时查询请求日志
+// - POST /_amp/admin/profile {"route":"claude","profile":"<ProxyConfigManager 工具 ID>"}：
+//   运行时切换该路由的 Profile（profile 为 null 时恢复），优先级低于路由规则与模型映射，重启后失效
+// - POST /_amp/admin/breaker/reset {"route":"claude"}：清除路由不可用标记（省略 route 时全部清除）
+// - POST /_amp/admin/cache/flush {"cache":"response|tool|poll|dns|all"}：清空缓存
+// - POST /_amp/admin/reload：丢弃缓存的 Profile / 代理配置 / 处理器配置，按磁盘配置重新加载
+// - GET  /_amp/admin/tokens：各账号 AMP Token 状态；POST /_amp/admin/tokens/check 立即检查一次
+// - GET  /_amp/admin/accounting：用量计数完整性检查；POST /_amp/admin/accounting/compact 手动压缩 WAL
+// - GET  /_amp/admin/versions：当前生效的客户端版本清单（见 client_versions.rs）
+// - POST /_amp/admin/versions/update {"url":"https://..."}：拉取新清单，校验通过后写入覆盖文件
+// - POST /_amp/admin/versions/reset：删除覆盖文件，恢复内置清单
+// 其余 GET 路径交给 dashboard::handle（与观测面板的只读接口一致）。
+// 鉴权：每个请求都须携带 Authorization: Bearer <token>。未配置 token 时首次启动生成随机 token，
+// 写入 ~/.duckcoding/amp/admin_token（仅当前用户可读），GUI / CLI 从该文件读取；
+// 未配置 token 时只允许监听回环地址。防止浏览器中的网页借用本机端口（CSRF / DNS 重绑定）：
+// Host 须为 localhost、IP 地址或监听地址（DNS 重绑定只能使用域名），带 Origin 头的请求一律拒绝，
+// POST 须为 Content-Type: application/json。
+// 监听服务需要 feature = "admin"；未启用时只保留配置结构与运行时 Profile 切换的查询，enabled 时告警。
+
+use super::app_state::AppState;
+#[cfg(feature = "admin")]
+use super::diagnostic_bundle::{mask_json, route_table};
+#[cfg(feature = "admin")]
+use super::inbound_auth::constant_time_eq;
+#[cfg(feature = "admin")]
+use super::secret_store;
+#[cfg(feature = "admin")]
+use super::{
+    amp_accounting, amp_poll_cache, audit_log, client_versions, dashboard, dns_cache, maintenance,
+    response_cache, token_health, tool_cache,
+};
+#[cfg(feature = "admin")]
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "admin")]
+use serde_json::{json, Value};
+#[cfg(feature = "admin")]
+use std::path::PathBuf;
+use std::sync::Arc;
+#[cfg(feature = "admin")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Handle;
+
+#[cfg(feature = "admin")]
+const PREFIX: &str = "/_amp/admin";
+/// 请求头上限
+#[cfg(feature = "admin")]
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+/// 请求体上限（管理操作只有很小的 JSON）
+#[cfg(feature = "admin")]
+const MAX_REQUEST_BODY: usize = 64 * 1024;
+/// 可切换 Profile 的路由
+#[cfg(feature = "admin")]
+const ROUTES: [&str; 3] = ["claude", "codex", "gemini"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminSettings {
+    pub enabled: bool,
+    pub listen: String,
+    /// Bearer Token；未配置时使用自动生成的 token（见 admin_token 文件），监听非回环地址时必须配置
+    pub token: Option<String>,
+}
+
+impl Default for AdminSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "127.0.0.1:8789".to_string(),
+            token: None,
+        }
+    }
+}
+
+#[cfg(feature = "admin")]
+fn overview(state: &AppState) -> Value {
+    let mut routes = route_table(state);
+    mask_json(&mut routes);
+    let overrides = state.profile_overrides();
+    json!({
+        "now_ms": audit_log::now_ms(),
+        "routes": routes,
+        "profile_overrides": overrides,
+        "breakers": maintenance::down_routes()
+            .into_iter()
+            .map(|(route, remaining_secs)| json!({ "route": route, "open_for_secs": remaining_secs }))
+            .collect::<Vec<_>>(),
+        "caches": dashboard::cache_stats(),
+    })
+}
+
+#[cfg(feature = "admin")]
+#[derive(Deserialize)]
+struct ProfileSwitch {
+    route: String,
+    profile: Option<String>,
+}
+
+#[cfg(feature = "admin")]
+fn switch_profile(state: &AppState, body: &[u8]) -> Result<Value> {
+    let req: ProfileSwitch =
+        serde_json::from_slice(body).map_err(|e| anyhow!("请求体无效: {}", e))?;
+    if !ROUTES.contains(&req.route.as_str()) {
+        return Err(anyhow!(
+            "不支持的路由: {}（可选 claude / codex / gemini）",
+            req.route
+        ));
+    }
+    let profile = req.profile.filter(|p| !p.is_empty());
+    match &profile {
+        Some(profile) => {
+            let exists = state
+                .proxy_config_manager()?
+                .get_config(profile)
+                .map_err(|e| anyhow!("读取配置失败: {}", e))?
+                .is_some();
+            if !exists {
+                return Err(anyhow!("配置 {} 不存在", profile));
+            }
+            tracing::info!("管理 API：{} 路由切换到配置 {}", req.route, profile);
+        }
+        None => tracing::info!("管理 API：{} 路由恢复默认 Profile", req.route),
+    }
+    state.set_profile_override(&req.route, profile.clone());
+    Ok(json!({ "route": req.route, "profile": profile }))
+}
+
+#[cfg(feature = "admin")]
+fn reset_breaker(body: &[u8]) -> Value {
+    let route = serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|v| v["route"].as_str().map(str::to_string));
+    let routes: Vec<String> = match route {
+        Some(route) => vec![route],
+        None => maintenance::down_routes()
+            .into_iter()
+            .map(|(r, _)| r)
+            .collect(),
+    };
+    for route in &routes {
+        maintenance::mark_up(route);
+    }
+    json!({ "reset": routes })
+}
+
+#[cfg(feature = "admin")]
+fn flush_cache(body: &[u8]) -> Result<Value> {
+    let cache = serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|v| v["cache"].as_str().map(str::to_string))
+        .unwrap_or_else(|| "all".to_string());
+    let all = cache == "all";
+    let mut flushed = serde_json::Map::new();
+    if all || cache == "response" {
+        flushed.insert("response".into(), json!(response_cache::clear()));
+    }
+    if all || cache == "tool" {
+        flushed.insert("tool".into(), json!(tool_cache::clear()));
+    }
+    if all || cache == "poll" {
+        flushed.insert("poll".into(), json!(amp_poll_cache::clear()));
+    }
+    if all || cache == "dns" {
+        let entries = dns_cache::shared().stats().entries;
+        dns_cache::shared().clear();
+        flushed.insert("dns".into(), json!(entries));
+    }
+    if flushed.is_empty() {
+        return Err(anyhow!(
+            "未知缓存: {}（可选 response / tool / poll / dns / all）",
+            cache
+        ));
+    }
+    tracing::info!("管理 API：清空缓存 {}", cache);
+    Ok(json!({ "flushed": flushed }))
+}
+
+/// 用量计数的管理操作会等待落盘，放到阻塞线程池执行
+#[cfg(feature = "admin")]
+async fn accounting<T: serde::Serialize + Send + 'static>(op: fn() -> Result<T>) -> Result<Value> {
+    let result = tokio::task::spawn_blocking(op)
+        .await
+        .map_err(|e| anyhow!("用量操作异常结束: {}", e))??;
+    Ok(serde_json::to_value(result)?)
+}
+
+#[cfg(feature = "admin")]
+async fn update_versions(state: &AppState, body: &[u8]) -> Result<Value> {
+    let url = serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|v| v["url"].as_str().map(str::to_string))
+        .ok_or_else(|| anyhow!("请求体缺少 url"))?;
+    let client = state.outbound().tool_client(&state.settings());
+    let manifest = client_versions::update_from_url(&client, &url).await?;
+    state.invalidate_versions();
+    Ok(serde_json::to_value(manifest)?)
+}
+
+#[cfg(feature = "admin")]
+fn reset_versions(state: &AppState) -> Result<Value> {
+    client_versions::reset()?;
+    state.invalidate_versions();
+    tracing::info!("管理 API：版本清单已恢复内置值");
+    Ok(serde_json::to_value(&*state.versions())?)
+}
+
+#[cfg(feature = "admin")]
+fn json_response(result: Result<Value>) -> (u16, &'static str, Vec<u8>) {
+    match result {
+        Ok(value) => (
+            200,
+            "application/json",
+            serde_json::to_vec(&value).unwrap_or_default(),
+        ),
+        Err(e) => (
+            400,
+            "application/json",
+            serde_json::to_vec(&json!({ "error": e.to_string() })).unwrap_or_default(),
+        ),
+    }
+}
+
+/// 按方法与路径生成响应：(状态码, content-type, 响应体)
+#[cfg(feature = "admin")]
+pub(crate) async fn handle(
+    state: &Arc<AppState>,
+    method: &str,
+    path: &str,
+    body: &[u8],
+) -> (u16, &'static str, Vec<u8>) {
+    let (route, query) = match path.split_once('?') {
+        Some((route, query)) => (route, Some(query)),
+        None => (path, None),
+    };
+    match (method, route.strip_prefix(PREFIX)) {
+        ("GET", Some("/state")) => json_response(Ok(overview(state))),
+        ("GET", Some("/requests")) => match query {
+            Some(query) => {
+                dashboard::handle_blocking(state.clone(), format!("/api/requests?{}", query)).await
+            }
+            None => json_response(Ok(dashboard::snapshot(state)["recent_requests"].take())),
+        },
+        ("POST", Some("/profile")) => json_response(switch_profile(state, body)),
+        ("POST", Some("/breaker/reset")) => json_response(Ok(reset_breaker(body))),
+        ("POST", Some("/cache/flush")) => json_response(flush_cache(body)),
+        ("GET", Some("/tokens")) => {
+            json_response(serde_json::to_value(token_health::snapshot()).map_err(Into::into))
+        }
+        ("POST", Some("/tokens/check")) => {
+            token_health::check_all(state).await;
+            json_response(serde_json::to_value(token_health::snapshot()).map_err(Into::into))
+        }
+        ("GET", Some("/accounting")) => {
+            json_response(accounting(amp_accounting::check_integrity).await)
+        }
+        ("POST", Some("/accounting/compact")) => {
+            let result = accounting(amp_accounting::compact).await;
+            if result.is_ok() {
+                tracing::info!("管理 API：用量 WAL 已压缩");
+            }
+            json_response(result.map(|_| json!({ "compacted": true })))
+        }
+        ("GET", Some("/versions")) => {
+            json_response(serde_json::to_value(&*state.versions()).map_err(Into::into))
+        }
+        ("POST", Some("/versions/update")) => json_response(update_versions(state, body).await),
+        ("POST", Some("/versions/reset")) => json_response(reset_versions(state)),
+        ("POST", Some("/reload")) => {
+            state.invalidate();
+            // 处理器配置立即重新读取，配置有误时报告错误
+            json_response(state.reload_settings().map(|_| json!({ "reloaded": true })))
+        }
+        ("GET", None) => dashboard::handle_blocking(state.clone(), path.to_string()).await,
+        ("GET", Some(_)) | ("POST", _) => (
+            404,
+            "text/plain; charset=utf-8",
+            "Not Found".as_bytes().to_vec(),
+        ),
+        _ => (
+            405,
+            "text/plain; charset=utf-8",
+            b"Method Not Allowed".to_vec(),
+        ),
+    }
+}
+
+/// 去掉端口与 IPv6 方括号后的主机名
+#[cfg(any(feature = "admin", feature = "dashboard"))]
+fn host_name(authority: &str) -> &str {
+    let host = match authority.rsplit_once(':') {
+        Some((h, port)) if !h.ends_with(':') && port.parse::<u16>().is_ok() => h,
+        _ => authority,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// 监听地址是否为回环地址（观测面板同样使用）
+#[cfg(any(feature = "admin", feature = "dashboard"))]
+pub(crate) fn is_loopback(listen: &str) -> bool {
+    let host = host_name(listen);
+    host == "localhost"
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+#[cfg(feature = "admin")]
+fn token_path() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".duckcoding")
+        .join("amp")
+        .join("admin_token")
+}
+
+/// 自动生成的 token：文件已存在时沿用，否则生成并写入（unix 上权限 0600）
+#[cfg(feature = "admin")]
+fn generated_token() -> Result<String> {
+    let path = token_path();
+    if let Ok(existing) = std::fs::read_to_string(&path) {
+        let existing = existing.trim();
+        if !existing.is_empty() {
+            return Ok(existing.to_string());
+        }
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| anyhow!("创建目录失败: {}", e))?;
+    }
+    let token = format!(
+        "dc-admin-{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(&path)
+        .map_err(|e| anyhow!("写入 {} 失败: {}", path.display(), e))?;
+    std::io::Write::write_all(&mut file, token.as_bytes())
+        .map_err(|e| anyhow!("写入 {} 失败: {}", path.display(), e))?;
+    tracing::info!("管理 API 已生成 token: {}", path.display());
+    Ok(token)
+}
+
+/// 鉴权前的来源检查，不通过时返回 (状态码, 原因)
+#[cfg(feature = "admin")]
+fn reject_origin(
+    method: &str,
+    header: &dyn Fn(&str) -> Option<String>,
+    listen: &str,
+) -> Option<(u16, &'static str)> {
+    let host_allowed = header("host").is_some_and(|host| {
+        let name = host_name(&host);
+        host == listen || name == "localhost" || name.parse::<std::net::IpAddr>().is_ok()
+    });
+    if !host_allowed {
+        return Some((403, "Host 须为 localhost 或 IP 地址"));
+    }
+    if header("origin").is_some() {
+        return Some((403, "不接受浏览器跨站请求"));
+    }
+    let json = header("content-type").is_some_and(|v| {
+        v.split(';')
+            .next()
+            .is_some_and(|t| t.trim().eq_ignore_ascii_case("application/json"))
+    });
+    if method == "POST" && !json {
+        return Some((415, "POST 须为 application/json"));
+    }
+    None
+}
+
+/// 启用时在后台监听（每个 AppState 只启动一次），管理操作作用于 state
+#[cfg(feature = "admin")]
+pub(crate) fn ensure_started(state: &Arc<AppState>, runtime: &Handle) {
+    let settings = state.settings().admin.clone();
+    if !settings.enabled {
+        return;
+    }
+    let configured = secret_store::reveal_opt(settings.token.clone()).filter(|t| !t.is_empty());
+    if configured.is_none() && !is_loopback(&settings.listen) {
+        tracing::warn!(
+            "管理 API 监听非回环地址 {} 但未配置 token，拒绝启动",
+            settings.listen
+        );
+        return;
+    }
+    let token = match configured.map_or_else(generated_token, Ok) {
+        Ok(token) => token,
+        Err(e) => {
+            tracing::warn!("管理 API 无法生成 token，拒绝启动: {}", e);
+            return;
+        }
+    };
+    if !state.claim_started("admin_api") {
+        return;
+    }
+    let state = state.clone();
+    runtime.spawn(async move {
+        if let Err(e) = serve(state.clone(), &settings.listen, token).await {
+            tracing::warn!("管理 API 启动失败: {}", e);
+            state.release_started("admin_api");
+        }
+    });
+}
+
+#[cfg(not(feature = "admin"))]
+pub(crate) fn ensure_started(state: &Arc<AppState>, _runtime: &Handle) {
+    if state.settings().admin.enabled {
+        tracing::warn!("管理 API 需要启用 admin 特性，当前构建不包含");
+    }
+}
+
+#[cfg(feature = "admin")]
+async fn serve(state: Arc<AppState>, listen: &str, token: String) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| anyhow!("监听 {} 失败: {}", listen, e))?;
+    tracing::info!("管理 API: http://{}{}/state", listen, PREFIX);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let (state, listen, token) = (state.clone(), listen.to_string(), token.clone());
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(&state, stream, &listen, &token).await {
+                tracing::debug!("管理 API 连接错误: {}", e);
+            }
+        });
+    }
+}
+
+/// 极简 HTTP/1.1：每个连接只处理一个请求
+#[cfg(feature = "admin")]
+async fn serve_connection(
+    state: &Arc<AppState>,
+    mut stream: tokio::net::TcpStream,
+    listen: &str,
+    token: &str,
+) -> Result<()> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let head_end = loop {
+        if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if data.len() > MAX_REQUEST_HEAD {
+            return Err(anyhow!("请求头过大"));
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        data.extend_from_slice(&buf[..n]);
+    };
+    let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+    let mut lines = head.lines();
+    let mut parts = lines.next().unwrap_or_default().split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or("/"));
+    let header = |name: &str| {
+        head.lines().skip(1).find_map(|line| {
+            let (k, v) = line.split_once(':')?;
+            k.trim()
+                .eq_ignore_ascii_case(name)
+                .then(|| v.trim().to_string())
+        })
+    };
+    let content_length: usize = header("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    if content_length > MAX_REQUEST_BODY {
+        return Err(anyhow!("请求体过大"));
+    }
+    while data.len() < head_end + content_length {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    let body = &data[head_end..data.len().min(head_end + content_length)];
+
+    let authorized = header("authorization")
+        .and_then(|v| v.strip_prefix("Bearer ").map(str::to_string))
+        .is_some_and(|v| constant_time_eq(&v, token));
+    let (status, content_type, body) = match reject_origin(method, &header, listen) {
+        Some((status, reason)) => {
+            tracing::warn!("管理 API 拒绝请求 {} {}: {}", method, path, reason);
+            (
+                status,
+                "text/plain; charset=utf-8",
+                reason.as_bytes().to_vec(),
+            )
+        }
+        None if !authorized => (401, "text/plain; charset=utf-8", b"Unauthorized".to_vec()),
+        None => handle(state, method, path, body).await,
+    };
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        415 => "Unsupported Media Type",
+        _ => "Service Unavailable",
+    };
+    let header = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "admin"))]
+mod tests {
+    use super::*;
+
+    fn check(method: &str, headers: &[(&str, &str)]) -> Option<u16> {
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.to_string())
+        };
+        reject_origin(method, &header, "127.0.0.1:8789").map(|(status, _)| status)
+    }
+
+    #[test]
+    fn browser_requests_rejected() {
+        let json = ("content-type", "application/json");
+        assert_eq!(check("GET", &[("host", "127.0.0.1:8789")]), None);
+        assert_eq!(check("POST", &[("host", "localhost:8789"), json]), None);
+        // DNS 重绑定：Host 为攻击者域名
+        assert_eq!(check("GET", &[("host", "evil.example:8789")]), Some(403));
+        assert_eq!(check("GET", &[]), Some(403));
+        // 跨站请求带 Origin
+        assert_eq!(
+            check(
+                "POST",
+                &[
+                    ("host", "127.0.0.1:8789"),
+                    ("origin", "https://evil.example"),
+                    json
+                ]
+            ),
+            Some(403)
+        );
+        // text/plain 表单 POST
+        assert_eq!(
+            check(
+                "POST",
+                &[("host", "127.0.0.1:8789"), ("content-type", "text/plain")]
+            ),
+            Some(415)
+        );
+        assert_eq!(
+            check(
+                "POST",
+                &[
+                    ("host", "[::1]:8789"),
+                    ("content-type", "application/json; charset=utf-8")
+                ]
+            ),
+            None
+        );
+    }
+}
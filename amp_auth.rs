@@ -0,0 +1,187 @@
+// AmpInternal 鉴权
+//
+// 按端点区分鉴权要求：可匿名访问的端点在未配置 Access Token 时直接匿名转发，
+// 仅对确实需要登录的端点报错，并给出配置提示。
+//
+// 多账号：accounts 中配置多个 Access Token，按请求选择：
+// 控制头 x-dc-amp-account > 工作区映射（workspace_header 指定的请求头）> AMP Code 代理配置中的默认 Token。
+//
+// OAuth Token 的自动刷新见 amp_oauth.rs（oauth 配置项）。
+
+use super::amp_oauth::AmpOAuthSettings;
+use hyper::HeaderMap as HyperHeaderMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 控制头：显式指定使用的 AMP 账号名
+pub(crate) const ACCOUNT_HEADER: &str = "x-dc-amp-account";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AuthRequirement {
+    /// 必须携带 Token
+    Required,
+    /// 有 Token 则携带，没有则匿名转发
+    Optional,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AmpAuthSettings {
+    /// 可匿名访问的路径前缀
+    pub anonymous_prefixes: Vec<String>,
+    /// 账号名 → Access Token
+    pub accounts: HashMap<String, String>,
+    /// 工作区标识 → 账号名
+    pub workspace_accounts: HashMap<String, String>,
+    /// 携带工作区标识的请求头
+    pub workspace_header: String,
+    /// Token 有效性检查间隔（秒），0 关闭
+    pub check_interval_secs: u64,
+    /// 用于检查的已鉴权轻量端点
+    pub check_path: String,
+    /// JWT Token 距过期少于该天数时告警
+    pub expiry_warn_days: u64,
+    /// OAuth Token 自动刷新
+    pub oauth: AmpOAuthSettings,
+}
+
+impl Default for AmpAuthSettings {
+    fn default() -> Self {
+        Self {
+            anonymous_prefixes: vec![
+                "/api/health".to_string(),
+                "/api/status".to_string(),
+                "/news".to_string(),
+                "/static/".to_string(),
+                "/_app/".to_string(),
+                "/favicon".to_string(),
+            ],
+            accounts: HashMap::new(),
+            workspace_accounts: HashMap::new(),
+            workspace_header: "x-dc-workspace".to_string(),
+            check_interval_secs: 3600,
+            check_path: "/api/user".to_string(),
+            expiry_warn_days: 7,
+            oauth: AmpOAuthSettings::default(),
+        }
+    }
+}
+
+impl AmpAuthSettings {
+    pub(crate) fn requirement(&self, path: &str) -> AuthRequirement {
+        if self
+            .anonymous_prefixes
+            .iter()
+            .any(|p| path.starts_with(p.as_str()))
+        {
+            AuthRequirement::Optional
+        } else {
+            AuthRequirement::Required
+        }
+    }
+
+    /// 解析当前请求使用的账号，返回 (账号名, Token)；未命中时由调用方使用默认 Token
+    pub(crate) fn select_account(&self, headers: &HyperHeaderMap) -> Option<(&str, &str)> {
+        if self.accounts.is_empty() {
+            return None;
+        }
+        let header_value = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+        let name = match header_value(ACCOUNT_HEADER) {
+            Some(n) => n,
+            None => {
+                let workspace = header_value(&self.workspace_header)?;
+                self.workspace_accounts.get(workspace)?.as_str()
+            }
+        };
+
+        match self.accounts.get_key_value(name) {
+            Some((k, v)) => Some((k.as_str(), v.as_str())),
+            None => {
+                tracing::warn!("未找到 AMP 账号: {}，使用默认 Token", name);
+                None
+            }
+        }
+    }
+}
+
+/// 缺少 Token 时的错误提示
+pub(crate) fn missing_token_error(path: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "AMP Code Access Token 未配置，端点 {} 需要登录。请在 ampcode.com → Settings 中生成 Access Token，并填入 AMP Code 代理配置",
+        path
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> AmpAuthSettings {
+        let mut settings = AmpAuthSettings::default();
+        settings
+            .accounts
+            .insert("work".to_string(), "tok-work".to_string());
+        settings
+            .accounts
+            .insert("home".to_string(), "tok-home".to_string());
+        settings
+            .workspace_accounts
+            .insert("repo-a".to_string(), "work".to_string());
+        settings
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HyperHeaderMap {
+        let mut headers = HyperHeaderMap::new();
+        for (k, v) in pairs {
+            headers.insert(*k, v.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn anonymous_prefixes_are_optional() {
+        let settings = AmpAuthSettings::default();
+        assert_eq!(
+            settings.requirement("/api/health"),
+            AuthRequirement::Optional
+        );
+        assert_eq!(
+            settings.requirement("/static/app.js"),
+            AuthRequirement::Optional
+        );
+        assert_eq!(
+            settings.requirement("/api/threads"),
+            AuthRequirement::Required
+        );
+    }
+
+    #[test]
+    fn account_header_wins_over_workspace() {
+        let settings = settings();
+        let selected = settings.select_account(&headers(&[
+            (ACCOUNT_HEADER, "home"),
+            ("x-dc-workspace", "repo-a"),
+        ]));
+        assert_eq!(selected, Some(("home", "tok-home")));
+        let selected = settings.select_account(&headers(&[("x-dc-workspace", "repo-a")]));
+        assert_eq!(selected, Some(("work", "tok-work")));
+    }
+
+    #[test]
+    fn unknown_account_falls_back_to_default() {
+        let settings = settings();
+        assert_eq!(
+            settings.select_account(&headers(&[(ACCOUNT_HEADER, "nobody")])),
+            None
+        );
+        assert_eq!(
+            settings.select_account(&headers(&[("x-dc-workspace", "repo-b")])),
+            None
+        );
+        assert_eq!(
+            AmpAuthSettings::default().select_account(&headers(&[])),
+            None
+        );
+    }
+}
@@ -0,0 +1,235 @@
+// 类型化配置构建器
+//
+// 嵌入方与测试不必先把 JSON 写到 ~/.duckcoding/processor_settings.json，
+// 可直接在代码中组装处理器配置：
+//   AmpConfig::builder()
+//       .claude_profile(ProfileSettings { react_shim: true, ..Default::default() })
+//       .routing_rule(Rule::new("gpt 走 codex").model("^gpt-").route(Route::Codex))
+//       .build()?
+// - 路由名、槽位用枚举表示，字段取自各模块的配置结构体，拼写错误在编译期发现
+// - build() 复用 config_preview 的校验（正则、请求头、别名成环等），错误时返回 Err，告警写日志
+// - install(state) 安装到传入的 AppState，之后使用该实例的环节都读取它（不再读写文件），
+//   也可交给 AmpEngineBuilder::config() 在 build 时安装到引擎持有的 AppState
+
+use super::app_state::AppState;
+use super::azure_openai::AzureSettings;
+use super::config_preview;
+use super::maintenance::MaintenanceSettings;
+use super::processor_settings::{ProcessorSettings, ProfileSettings, TransformSettings};
+use super::routing_rules::RoutingRule;
+use super::upstream::{FailoverSettings, RetrySettings};
+use anyhow::{anyhow, Result};
+
+/// 路由（与 routing_rules::ROUTE_NAMES 一致）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Route {
+    Amp,
+    Claude,
+    Codex,
+    Gemini,
+    Azure,
+}
+
+impl Route {
+    pub fn name(self) -> &'static str {
+        match self {
+            Route::Amp => "amp",
+            Route::Claude => "claude",
+            Route::Codex => "codex",
+            Route::Gemini => "gemini",
+            Route::Azure => "azure",
+        }
+    }
+}
+
+/// 路由规则构建器（见 routing_rules.rs），默认启用
+#[derive(Debug, Clone)]
+pub struct Rule(RoutingRule);
+
+impl Rule {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(RoutingRule {
+            name: name.into(),
+            ..Default::default()
+        })
+    }
+
+    /// 路径前缀（不区分大小写）
+    pub fn path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.0.path_prefix = Some(prefix.into());
+        self
+    }
+
+    /// 需存在的请求头
+    pub fn header(mut self, name: impl Into<String>) -> Self {
+        self.0.header = Some(name.into());
+        self
+    }
+
+    /// 请求头存在且值匹配正则
+    pub fn header_matches(mut self, name: impl Into<String>, pattern: impl Into<String>) -> Self {
+        self.0.header = Some(name.into());
+        self.0.header_value = Some(pattern.into());
+        self
+    }
+
+    /// 模型名正则
+    pub fn model(mut self, pattern: impl Into<String>) -> Self {
+        self.0.model = Some(pattern.into());
+        self
+    }
+
+    /// 目标路由
+    pub fn route(mut self, route: Route) -> Self {
+        self.0.route = Some(route.name().to_string());
+        self
+    }
+
+    /// 使用 ProxyConfigManager 中该工具 ID 的上游
+    pub fn profile(mut self, tool_id: impl Into<String>) -> Self {
+        self.0.profile = Some(tool_id.into());
+        self
+    }
+
+    pub fn disabled(mut self) -> Self {
+        self.0.enabled = false;
+        self
+    }
+}
+
+impl From<Rule> for RoutingRule {
+    fn from(rule: Rule) -> Self {
+        rule.0
+    }
+}
+
+/// 校验通过的处理器配置
+#[derive(Debug, Clone)]
+pub struct AmpConfig {
+    settings: ProcessorSettings,
+}
+
+impl AmpConfig {
+    /// 从默认配置开始构建
+    pub fn builder() -> AmpConfigBuilder {
+        AmpConfigBuilder::new()
+    }
+
+    pub fn settings(&self) -> &ProcessorSettings {
+        &self.settings
+    }
+
+    pub fn into_settings(self) -> ProcessorSettings {
+        self.settings
+    }
+
+    /// 安装到 state（取代配置文件）
+    pub fn install(self, state: &AppState) {
+        state.install_settings(self.settings);
+    }
+
+    /// 移除 state 上安装的配置，恢复读写配置文件
+    pub fn uninstall(state: &AppState) {
+        state.use_file();
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AmpConfigBuilder {
+    settings: ProcessorSettings,
+}
+
+impl AmpConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 以 state 当前生效的配置（配置文件或已安装的配置）为基础修改
+    pub fn from_current(state: &AppState) -> Result<Self> {
+        Ok(Self {
+            settings: state.load_settings()?,
+        })
+    }
+
+    /// claude 槽位配置（整体替换）
+    pub fn claude_profile(self, profile: ProfileSettings) -> Self {
+        self.slot("claude", profile)
+    }
+
+    /// codex 槽位配置（整体替换）
+    pub fn codex_profile(self, profile: ProfileSettings) -> Self {
+        self.slot("codex", profile)
+    }
+
+    /// gemini 槽位配置（整体替换）
+    pub fn gemini_profile(self, profile: ProfileSettings) -> Self {
+        self.slot("gemini", profile)
+    }
+
+    /// azure 槽位配置（请求头、出站绑定等；资源地址与 Key 见 azure()）
+    pub fn azure_profile(self, profile: ProfileSettings) -> Self {
+        self.slot("azure", profile)
+    }
+
+    /// Azure OpenAI 资源
+    pub fn azure(mut self, azure: AzureSettings) -> Self {
+        self.settings.azure = azure;
+        self
+    }
+
+    /// 追加路由规则（按添加顺序匹配）
+    pub fn routing_rule(mut self, rule: impl Into<RoutingRule>) -> Self {
+        self.settings.routing.rules.push(rule.into());
+        self
+    }
+
+    /// tool_id 的请求体转换开关
+    pub fn transforms(mut self, tool_id: &str, transforms: TransformSettings) -> Self {
+        self.settings
+            .tools
+            .entry(tool_id.to_string())
+            .or_default()
+            .transforms = Some(transforms);
+        self
+    }
+
+    pub fn maintenance(mut self, maintenance: MaintenanceSettings) -> Self {
+        self.settings.maintenance = maintenance;
+        self
+    }
+
+    pub fn failover(mut self, failover: FailoverSettings) -> Self {
+        self.settings.failover = failover;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetrySettings) -> Self {
+        self.settings.retry = retry;
+        self
+    }
+
+    /// 修改其余配置项
+    pub fn with(mut self, f: impl FnOnce(&mut ProcessorSettings)) -> Self {
+        f(&mut self.settings);
+        self
+    }
+
+    /// 校验配置：有错误时返回 Err，告警写日志
+    pub fn build(self) -> Result<AmpConfig> {
+        let (warnings, errors) = config_preview::validate(&self.settings);
+        for warning in &warnings {
+            tracing::warn!("处理器配置告警: {}", warning);
+        }
+        if !errors.is_empty() {
+            return Err(anyhow!("配置校验失败: {}", errors.join("；")));
+        }
+        Ok(AmpConfig {
+            settings: self.settings,
+        })
+    }
+
+    fn slot(mut self, slot: &str, profile: ProfileSettings) -> Self {
+        self.settings.profiles.insert(slot.to_string(), profile);
+        self
+    }
+}
@@ -0,0 +1,538 @@
+// AMP Access Token 自动刷新（OAuth refresh_token）
+//
+// ampcode.com 的 Access Token 为带过期时间的 OAuth Token 时，配置对应账号的 refresh_token 后：
+// - access_token()：forward_to_amp 取 Token 时调用；有刷新记录时使用最新 Token，
+//   距过期不足 refresh_before_secs 时先刷新（JWT exp 或 token 端点返回的 expires_in）
+// - refreshed_headers()：上游返回 401 且 Token 由本模块管理时刷新并返回换好 Token 的请求头，
+//   调用方重发一次，不把错误交给 agent；转发层（upstream::forward，AmpInternal 请求带 amp 路由标记）
+//   与 send()（引擎直发、工具列表过滤、轮询缓存等）共用
+// - 刷新结果按账号写入存储（amp/oauth 命名空间），Token 经 secret_store 保护；
+//   配置中的 Token 被用户更换后，旧刷新记录自动作废
+// - 同一时间只进行一次刷新，并发请求等待后直接使用新 Token；token 端点请求与等待刷新锁均有超时，
+//   端点无响应时不会阻塞其余请求
+// - 刷新记录缓存、已下发 Token 来源与刷新锁存放在 AppState 上（OAuthState），各实例独立；
+//   持久化的刷新记录仍按账号共用存储
+// - 401 刷新后只替换请求中携带旧 Token 的请求头（x-api-key 或 Authorization），不额外添加另一种
+// - 已下发 Token 的来源记录在 Token 过期 ISSUED_GRACE_MS 后淘汰（无过期时间的保留 ISSUED_TTL_MS），
+//   宽限期内过期 Token 的 401 仍可定位账号并刷新
+// 账号名与多账号配置一致，代理配置中的默认 Token 为 default。
+
+use super::amp_auth::AmpAuthSettings;
+use super::app_state::AppState;
+use super::audit_log::{self, AuditRecord};
+use super::secret_store;
+use super::storage;
+use super::token_health;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use hyper::HeaderMap as HyperHeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const NAMESPACE: &str = "amp/oauth";
+
+/// token 端点请求超时
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// 等待其他请求完成刷新的上限（略长于一次刷新）
+const REFRESH_LOCK_WAIT: Duration = Duration::from_secs(20);
+
+/// 无过期时间的已下发 Token 记录保留时长
+const ISSUED_TTL_MS: u64 = 24 * 3600 * 1000;
+
+/// 已下发 Token 过期后记录的保留时长
+const ISSUED_GRACE_MS: u64 = 3600 * 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AmpOAuthSettings {
+    /// token 端点：以 / 开头时拼接 AMP 代理配置的地址，也可填完整 URL
+    pub token_path: String,
+    pub client_id: Option<String>,
+    /// 账号名 → refresh_token（default 为代理配置中的默认 Token；支持 keychain: 引用）
+    pub refresh_tokens: HashMap<String, String>,
+    /// 距过期少于该秒数时提前刷新
+    pub refresh_before_secs: u64,
+}
+
+impl Default for AmpOAuthSettings {
+    fn default() -> Self {
+        Self {
+            token_path: "/oauth/token".to_string(),
+            client_id: None,
+            refresh_tokens: HashMap::new(),
+            refresh_before_secs: 300,
+        }
+    }
+}
+
+/// 持久化的刷新记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TokenRecord {
+    /// 配置中 Token 的指纹，配置更换后记录作废
+    source: String,
+    /// 最新 Access Token（可能是 keychain: 引用）
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_at_ms: Option<u64>,
+}
+
+/// 已下发 Token 的来源，用于 401 时定位账号
+#[derive(Debug, Clone)]
+struct Issued {
+    account: String,
+    configured: String,
+    base_url: String,
+    /// 记录淘汰时间（毫秒）
+    evict_at_ms: u64,
+}
+
+/// 实例上的刷新状态（见 AppState::oauth）
+#[derive(Default)]
+pub(crate) struct OAuthState {
+    /// 账号 → 刷新记录（None 表示存储中没有有效记录）
+    records: Mutex<HashMap<String, Option<TokenRecord>>>,
+    /// 已下发 Token → 来源
+    issued: Mutex<HashMap<String, Issued>>,
+    /// 串行化刷新（refresh_token 通常只能使用一次）
+    refresh_lock: tokio::sync::Mutex<()>,
+}
+
+impl OAuthState {
+    /// 记录已下发 Token 的来源，顺带淘汰已过期的记录
+    fn remember(&self, token: String, mut issued: Issued, expires_at_ms: Option<u64>) {
+        let now = audit_log::now_ms();
+        issued.evict_at_ms = expires_at_ms
+            .map(|exp| exp + ISSUED_GRACE_MS)
+            .unwrap_or(now + ISSUED_TTL_MS);
+        let mut map = self.issued.lock().unwrap_or_else(|e| e.into_inner());
+        map.retain(|_, i| i.evict_at_ms > now);
+        map.insert(token, issued);
+    }
+
+    fn issued(&self, token: &str) -> Option<Issued> {
+        self.issued
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(token)
+            .cloned()
+    }
+
+    /// 账号的有效刷新记录（配置 Token 已更换时作废）
+    fn record(&self, account: &str, configured: &str) -> Option<TokenRecord> {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        let record = records
+            .entry(account.to_string())
+            .or_insert_with(|| {
+                storage::get_json::<TokenRecord>(NAMESPACE, account)
+                    .map_err(|e| tracing::warn!("读取 AMP Token 刷新记录失败: {}", e))
+                    .ok()
+                    .flatten()
+            })
+            .clone()?;
+        if record.source != fingerprint(configured) {
+            records.insert(account.to_string(), None);
+            let _ = storage::shared().delete(NAMESPACE, account);
+            secret_store::forget(&record.access_token);
+            if let Some(refresh_token) = &record.refresh_token {
+                secret_store::forget(refresh_token);
+            }
+            return None;
+        }
+        Some(record)
+    }
+
+    /// 保存刷新记录，并作废该账号此前下发的 Token 来源
+    fn save(&self, account: &str, record: TokenRecord) {
+        if let Err(e) = storage::put_json(NAMESPACE, account, &record) {
+            tracing::warn!("保存 AMP Token 刷新记录失败: {}", e);
+        }
+        self.records
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(account.to_string(), Some(record));
+        self.issued
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|_, issued| issued.account != account);
+    }
+}
+
+fn fingerprint(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn refresh_token_for(
+    settings: &AmpAuthSettings,
+    account: &str,
+    record: Option<&TokenRecord>,
+) -> Option<String> {
+    record
+        .and_then(|r| r.refresh_token.as_deref())
+        .or_else(|| {
+            settings
+                .oauth
+                .refresh_tokens
+                .get(account)
+                .map(String::as_str)
+        })
+        .and_then(secret_store::reveal)
+        .filter(|t| !t.is_empty())
+}
+
+/// 账号当前应使用的 Token；临近过期且可刷新时先刷新，刷新失败时沿用现有 Token
+pub(crate) async fn access_token(
+    state: &AppState,
+    base_url: &str,
+    account: &str,
+    configured: String,
+) -> String {
+    let processor_settings = state.settings();
+    let settings = &processor_settings.amp_auth;
+    let oauth = state.oauth();
+    let record = oauth.record(account, &configured);
+    if record.is_none() && !settings.oauth.refresh_tokens.contains_key(account) {
+        return configured;
+    }
+    let token = record
+        .as_ref()
+        .and_then(|r| secret_store::reveal(&r.access_token))
+        .unwrap_or_else(|| configured.clone());
+    let expires_at_ms = record
+        .as_ref()
+        .and_then(|r| r.expires_at_ms)
+        .or_else(|| token_health::jwt_expiry_ms(&token));
+    let due = expires_at_ms.is_some_and(|exp| {
+        exp.saturating_sub(audit_log::now_ms()) < settings.oauth.refresh_before_secs * 1000
+    });
+    let token = if due {
+        match refresh(state, base_url, account, &configured, &token).await {
+            Ok(fresh) => fresh,
+            Err(e) => {
+                tracing::warn!("AMP 账号 {} 的 Token 即将过期，刷新失败: {}", account, e);
+                token
+            }
+        }
+    } else {
+        token
+    };
+    let expires_at_ms = if due {
+        oauth
+            .record(account, &configured)
+            .and_then(|r| r.expires_at_ms)
+            .or_else(|| token_health::jwt_expiry_ms(&token))
+    } else {
+        expires_at_ms
+    };
+    oauth.remember(
+        token.clone(),
+        Issued {
+            account: account.to_string(),
+            configured,
+            base_url: base_url.to_string(),
+            evict_at_ms: 0,
+        },
+        expires_at_ms,
+    );
+    token
+}
+
+/// 用 refresh_token 换取新 Token 并持久化；stale 为调用方手中已失效的 Token
+async fn refresh(
+    state: &AppState,
+    base_url: &str,
+    account: &str,
+    configured: &str,
+    stale: &str,
+) -> Result<String> {
+    let oauth = state.oauth();
+    let _guard = tokio::time::timeout(REFRESH_LOCK_WAIT, oauth.refresh_lock.lock())
+        .await
+        .map_err(|_| anyhow!("等待其他请求刷新 Token 超时"))?;
+    let record = oauth.record(account, configured);
+    // 等待期间已被其他请求刷新
+    if let Some(current) = record
+        .as_ref()
+        .and_then(|r| secret_store::reveal(&r.access_token))
+        .filter(|t| t != stale)
+    {
+        return Ok(current);
+    }
+    let processor_settings = state.settings();
+    let settings = &processor_settings.amp_auth;
+    let refresh_token = refresh_token_for(settings, account, record.as_ref())
+        .ok_or_else(|| anyhow!("账号 {} 未配置 refresh_token", account))?;
+
+    let url = if settings.oauth.token_path.starts_with('/') {
+        format!(
+            "{}{}",
+            base_url.trim_end_matches('/'),
+            settings.oauth.token_path
+        )
+    } else {
+        settings.oauth.token_path.clone()
+    };
+    let mut form = vec![
+        ("grant_type", "refresh_token".to_string()),
+        ("refresh_token", refresh_token.clone()),
+    ];
+    if let Some(client_id) = &settings.oauth.client_id {
+        form.push(("client_id", client_id.clone()));
+    }
+    let resp = state
+        .outbound()
+        .client_for_profile(&processor_settings, "amp")
+        .post(&url)
+        .form(&form)
+        .timeout(REFRESH_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| anyhow!("请求 token 端点失败: {}", e))?;
+    let status = resp.status();
+    if !status.is_success() {
+        audit_log::append(
+            &AuditRecord::new("token_refresh", "amp", account)
+                .with("ok", json!(false))
+                .with("status", json!(status.as_u16())),
+        );
+        return Err(anyhow!("token 端点返回 HTTP {}", status.as_u16()));
+    }
+    let data: Value = resp
+        .json()
+        .await
+        .map_err(|e| anyhow!("token 端点响应解析失败: {}", e))?;
+    let access_token = data["access_token"]
+        .as_str()
+        .filter(|t| !t.is_empty())
+        .ok_or_else(|| anyhow!("token 端点响应缺少 access_token"))?
+        .to_string();
+    let expires_at_ms = data["expires_in"]
+        .as_u64()
+        .map(|secs| audit_log::now_ms() + secs * 1000)
+        .or_else(|| token_health::jwt_expiry_ms(&access_token));
+    // 未返回新 refresh_token 时沿用原值
+    let next_refresh = data["refresh_token"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or(refresh_token);
+
+    let key_account = secret_store::proxy_account("amp-code", &format!("oauth/{}", account));
+    oauth.save(
+        account,
+        TokenRecord {
+            source: fingerprint(configured),
+            access_token: secret_store::protect(
+                &format!("{}/access_token", key_account),
+                &access_token,
+            ),
+            refresh_token: Some(secret_store::protect(
+                &format!("{}/refresh_token", key_account),
+                &next_refresh,
+            )),
+            expires_at_ms,
+        },
+    );
+    token_health::record_refreshed(account, expires_at_ms);
+    audit_log::append(
+        &AuditRecord::new("token_refresh", "amp", account)
+            .with("ok", json!(true))
+            .with("expires_at_ms", json!(expires_at_ms)),
+    );
+    tracing::info!("AMP 账号 {} 的 Access Token 已刷新", account);
+    Ok(access_token)
+}
+
+/// 请求头中携带的 Token（x-api-key 优先，其次 Bearer）
+fn sent_token(headers: &HyperHeaderMap) -> Option<String> {
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key.to_string());
+    }
+    headers
+        .get(hyper::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::to_string)
+}
+
+/// 把携带 stale 的请求头（x-api-key / Authorization: Bearer）换成 fresh，其余请求头不变；
+/// Token 不能作为请求头值时返回 None
+fn replace_token(headers: &HyperHeaderMap, stale: &str, fresh: &str) -> Option<HyperHeaderMap> {
+    let carries = |name: hyper::header::HeaderName, prefix: &str| {
+        headers
+            .get(&name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix(prefix))
+            .is_some_and(|v| v == stale)
+    };
+    let mut replaced = headers.clone();
+    if carries(hyper::header::HeaderName::from_static("x-api-key"), "") {
+        replaced.insert("x-api-key", fresh.parse().ok()?);
+    }
+    if carries(hyper::header::AUTHORIZATION, "Bearer ") {
+        let bearer = format!("Bearer {}", fresh).parse().ok()?;
+        replaced.insert(hyper::header::AUTHORIZATION, bearer);
+    }
+    Some(replaced)
+}
+
+/// 上游返回 401 后调用：请求携带的 Token 由本模块下发且可刷新时刷新，
+/// 返回换好新 Token 的请求头（调用方重发一次）；无法刷新时返回 None
+pub(crate) async fn refreshed_headers(
+    state: &AppState,
+    headers: &HyperHeaderMap,
+) -> Option<HyperHeaderMap> {
+    let stale = sent_token(headers)?;
+    let oauth = state.oauth();
+    let issued = oauth.issued(&stale)?;
+    refresh_token_for(
+        &state.settings().amp_auth,
+        &issued.account,
+        oauth.record(&issued.account, &issued.configured).as_ref(),
+    )?;
+    let fresh = match refresh(
+        state,
+        &issued.base_url,
+        &issued.account,
+        &issued.configured,
+        &stale,
+    )
+    .await
+    {
+        Ok(fresh) => fresh,
+        Err(e) => {
+            tracing::warn!(
+                "AMP 账号 {} 返回 401，Token 刷新失败: {}",
+                issued.account,
+                e
+            );
+            return None;
+        }
+    };
+    let Some(headers) = replace_token(headers, &stale, &fresh) else {
+        tracing::warn!("AMP 账号 {} 刷新得到的 Token 含非法字符", issued.account);
+        return None;
+    };
+    let expires_at_ms = oauth
+        .record(&issued.account, &issued.configured)
+        .and_then(|r| r.expires_at_ms)
+        .or_else(|| token_health::jwt_expiry_ms(&fresh));
+    oauth.remember(fresh, issued, expires_at_ms);
+    tracing::info!("AMP 请求 401，Token 已刷新，重发一次");
+    Some(headers)
+}
+
+/// 发送 AmpInternal 请求；401 且 Token 可刷新时刷新后重发一次
+pub(crate) async fn send(
+    state: &AppState,
+    method: reqwest::Method,
+    target_url: &str,
+    mut headers: HyperHeaderMap,
+    body: Bytes,
+) -> Result<reqwest::Response> {
+    headers.remove(hyper::header::HOST);
+    headers.remove(hyper::header::CONTENT_LENGTH);
+    let client = state
+        .outbound()
+        .client_for_profile(&state.settings(), "amp");
+    let attempt = |headers: HyperHeaderMap| {
+        let mut request = client.request(method.clone(), target_url).headers(headers);
+        if !body.is_empty() {
+            request = request.body(body.clone());
+        }
+        request.send()
+    };
+    let response = attempt(headers.clone())
+        .await
+        .map_err(|e| anyhow!("请求 {} 失败: {}", target_url, e))?;
+    if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+        return Ok(response);
+    }
+    let Some(headers) = refreshed_headers(state, &headers).await else {
+        return Ok(response);
+    };
+    attempt(headers)
+        .await
+        .map_err(|e| anyhow!("请求 {} 失败: {}", target_url, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::processor_settings::ProcessorSettings;
+    use super::*;
+
+    fn issued(account: &str) -> Issued {
+        Issued {
+            account: account.to_string(),
+            configured: "configured".to_string(),
+            base_url: "https://ampcode.com".to_string(),
+            evict_at_ms: 0,
+        }
+    }
+
+    #[test]
+    fn expired_issued_tokens_evicted() {
+        let oauth = OAuthState::default();
+        let now = audit_log::now_ms();
+        oauth.remember(
+            "old".to_string(),
+            issued("a"),
+            Some(now - ISSUED_GRACE_MS - 1),
+        );
+        oauth.remember("grace".to_string(), issued("b"), Some(now - 1));
+        oauth.remember("new".to_string(), issued("c"), None);
+        assert!(oauth.issued("old").is_none());
+        assert!(oauth.issued("grace").is_some());
+        assert_eq!(oauth.issued("new").unwrap().account, "c");
+        // 其他实例互不可见
+        assert!(OAuthState::default().issued("new").is_none());
+    }
+
+    #[test]
+    fn only_header_carrying_stale_token_replaced() {
+        let mut headers = HyperHeaderMap::new();
+        headers.insert("x-api-key", "stale".parse().unwrap());
+        let replaced = replace_token(&headers, "stale", "fresh").unwrap();
+        assert_eq!(replaced["x-api-key"], "fresh");
+        assert!(replaced.get("authorization").is_none());
+
+        let mut headers = HyperHeaderMap::new();
+        headers.insert("authorization", "Bearer stale".parse().unwrap());
+        headers.insert("x-amp-thread", "t1".parse().unwrap());
+        let replaced = replace_token(&headers, "stale", "fresh").unwrap();
+        assert_eq!(replaced["authorization"], "Bearer fresh");
+        assert_eq!(replaced["x-amp-thread"], "t1");
+        assert!(replaced.get("x-api-key").is_none());
+
+        // 另一请求头携带的不是旧 Token 时保持不变
+        headers.insert("x-api-key", "other".parse().unwrap());
+        let replaced = replace_token(&headers, "stale", "fresh").unwrap();
+        assert_eq!(replaced["x-api-key"], "other");
+        assert_eq!(replaced["authorization"], "Bearer fresh");
+
+        assert!(replace_token(&headers, "stale", "bad\nvalue").is_none());
+    }
+
+    #[test]
+    fn sent_token_prefers_api_key() {
+        let mut headers = HyperHeaderMap::new();
+        headers.insert("authorization", "Bearer bearer-token".parse().unwrap());
+        assert_eq!(sent_token(&headers).as_deref(), Some("bearer-token"));
+        headers.insert("x-api-key", "key-token".parse().unwrap());
+        assert_eq!(sent_token(&headers).as_deref(), Some("key-token"));
+        assert_eq!(sent_token(&HyperHeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn unknown_token_not_refreshed() {
+        let mut headers = HyperHeaderMap::new();
+        headers.insert("x-api-key", "never-issued".parse().unwrap());
+        let state = AppState::fixed(ProcessorSettings::default(), (None, None, None));
+        assert!(refreshed_headers(&state, &headers).await.is_none());
+    }
+}
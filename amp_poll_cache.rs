@@ -0,0 +1,216 @@
+// AmpInternal 轮询合并与短 TTL 缓存
+//
+// AMP 会高频轮询 ampcode.com 的配置 / 功能开关接口。对幂等的 GET（无请求体）：
+// - 同一 TTL 窗口内相同请求只发一次上游，其余复用结果（含并发中的请求）
+// - 缓存键包含 Token 指纹，多账号互不串用
+// - 仅缓存 2xx 响应；非 2xx 或请求失败时回退为正常转发
+// 命中时以本地响应返回。
+
+use super::amp_oauth;
+use super::app_state::AppState;
+use super::outcome::ProcessOutcome;
+use super::ProcessedRequest;
+use bytes::Bytes;
+use hyper::HeaderMap as HyperHeaderMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PollCacheSettings {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    /// 可缓存的路径前缀
+    pub path_prefixes: Vec<String>,
+    /// 排除的路径前缀（优先于 path_prefixes）
+    pub exclude_prefixes: Vec<String>,
+}
+
+impl Default for PollCacheSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl_secs: 5,
+            path_prefixes: vec!["/api/".to_string()],
+            exclude_prefixes: vec!["/api/provider/".to_string(), "/api/threads".to_string()],
+        }
+    }
+}
+
+impl PollCacheSettings {
+    pub(crate) fn is_cacheable(&self, path: &str, body: &[u8]) -> bool {
+        self.enabled
+            && self.ttl_secs > 0
+            && body.is_empty()
+            && self
+                .path_prefixes
+                .iter()
+                .any(|p| path.starts_with(p.as_str()))
+            && !self
+                .exclude_prefixes
+                .iter()
+                .any(|p| path.starts_with(p.as_str()))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CachedResponse {
+    content_type: Option<String>,
+    body: Bytes,
+}
+
+struct Slot {
+    created: Instant,
+    cell: Arc<OnceCell<Option<CachedResponse>>>,
+}
+
+static SLOTS: Lazy<Mutex<HashMap<String, Slot>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn cache_key(target_url: &str, headers: &HyperHeaderMap) -> String {
+    let token = headers
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let mut hasher = Sha256::new();
+    hasher.update(token);
+    format!("{:x}|{}", hasher.finalize(), target_url)
+}
+
+/// 合并请求：返回 Some(本地响应) 表示命中缓存或由本次请求获取成功，None 表示应正常转发
+pub(crate) async fn fetch_coalesced(
+    state: &AppState,
+    settings: &PollCacheSettings,
+    forward: &ProcessedRequest,
+) -> Option<ProcessOutcome> {
+    let key = cache_key(&forward.target_url, &forward.headers);
+    let ttl = Duration::from_secs(settings.ttl_secs);
+
+    let (cell, is_fresh_slot) = {
+        let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+        slots.retain(|_, s| s.created.elapsed() < ttl);
+        match slots.get(&key) {
+            Some(slot) => (slot.cell.clone(), false),
+            None => {
+                let cell = Arc::new(OnceCell::new());
+                slots.insert(
+                    key.clone(),
+                    Slot {
+                        created: Instant::now(),
+                        cell: cell.clone(),
+                    },
+                );
+                (cell, true)
+            }
+        }
+    };
+
+    let cached = cell
+        .get_or_init(|| fetch_upstream(state, forward))
+        .await
+        .clone();
+
+    match cached {
+        Some(resp) => {
+            tracing::debug!(
+                "AMP 轮询{}: {}",
+                if is_fresh_slot {
+                    "缓存写入"
+                } else {
+                    "合并命中"
+                },
+                forward.target_url
+            );
+            let mut headers = HyperHeaderMap::new();
+            if let Some(ct) = resp.content_type.and_then(|ct| ct.parse().ok()) {
+                headers.insert("content-type", ct);
+            }
+            Some(ProcessOutcome::local("amp-poll-cache", headers, resp.body))
+        }
+        None => {
+            // 失败结果不缓存，后续请求重新获取
+            SLOTS.lock().unwrap_or_else(|e| e.into_inner()).remove(&key);
+            None
+        }
+    }
+}
+
+async fn fetch_upstream(state: &AppState, forward: &ProcessedRequest) -> Option<CachedResponse> {
+    let resp = match amp_oauth::send(
+        state,
+        reqwest::Method::GET,
+        &forward.target_url,
+        forward.headers.clone(),
+        Bytes::new(),
+    )
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::debug!("AMP 轮询获取失败，回退正常转发: {}", e);
+            return None;
+        }
+    };
+    if !resp.status().is_success() {
+        tracing::debug!("AMP 轮询返回 {}，不缓存", resp.status());
+        return None;
+    }
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+    let body = resp.bytes().await.ok()?;
+    Some(CachedResponse { content_type, body })
+}
+
+/// 当前缓存的请求数
+pub(crate) fn len() -> usize {
+    SLOTS.lock().unwrap_or_else(|e| e.into_inner()).len()
+}
+
+/// 清空缓存，返回清除的条目数（进行中的请求不受影响）
+#[cfg(feature = "admin")]
+pub(crate) fn clear() -> usize {
+    let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+    let n = slots.len();
+    slots.clear();
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_bodyless_included_paths_are_cacheable() {
+        let settings = PollCacheSettings::default();
+        assert!(settings.is_cacheable("/api/user/features", b""));
+        assert!(!settings.is_cacheable("/api/user/features", b"{}"));
+        assert!(!settings.is_cacheable("/api/threads/T-1", b""));
+        assert!(!settings.is_cacheable("/api/provider/anthropic/v1/messages", b""));
+        assert!(!settings.is_cacheable("/news", b""));
+        let disabled = PollCacheSettings {
+            ttl_secs: 0,
+            ..Default::default()
+        };
+        assert!(!disabled.is_cacheable("/api/user/features", b""));
+    }
+
+    #[test]
+    fn cache_key_separates_accounts() {
+        let url = "https://ampcode.com/api/user/features";
+        let mut a = HyperHeaderMap::new();
+        a.insert(hyper::header::AUTHORIZATION, "Bearer a".parse().unwrap());
+        let mut b = HyperHeaderMap::new();
+        b.insert(hyper::header::AUTHORIZATION, "Bearer b".parse().unwrap());
+        assert_eq!(cache_key(url, &a), cache_key(url, &a.clone()));
+        assert_ne!(cache_key(url, &a), cache_key(url, &b));
+        assert!(!cache_key(url, &a).contains("Bearer"));
+        assert!(cache_key(url, &a).ends_with(url));
+    }
+}
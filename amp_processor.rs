@@ -1,40 +1,97 @@
 // AMP Code 请求处理器
 //
 // 路由逻辑：
-// 0. 本地工具拦截：webSearch2 / extractWebPageContent → 本地处理
+// 0. 本地工具拦截：webSearch2 / extractWebPageContent / summarize / translate / evaluateExpression /
+//    currentTime / getWeather / httpRequest / lookupCrate / lookupNpmPackage / lookupPypiPackage /
+//    searchDocs → 本地处理
 // 1. /api/provider/anthropic/* → Claude Profile（提取 /v1/messages）
 // 2. /api/provider/openai/* → Codex Profile（提取 /v1/responses 或 /v1/chat/completions）
 // 3. /api/provider/google/* → Gemini Profile（提取 /v1beta/...）
+// 3.1 /api/provider/azure/* → Azure OpenAI Profile（api-key 鉴权，追加 api-version）
 // 4. 其他 /api/* → ampcode.com（使用 AMP Access Token）
 // 5. 直接 LLM 路径 → 按路径/headers/model 判断
 
+use super::admin_api;
+use super::amp_accounting::{self, UsageCounters};
+use super::amp_auth::{self, AuthRequirement};
+use super::amp_oauth;
+use super::amp_poll_cache;
+use super::amp_session;
+use super::anthropic_version;
+use super::app_state::{AppState, SelectedProfile};
+use super::azure_openai;
+use super::bandwidth::{self, Subject};
+use super::bedrock_processor::BedrockHeadersProcessor;
+use super::budget::{self, BudgetDecision};
+use super::cache_diff;
+use super::calculator;
+use super::capacity;
+use super::charset;
+use super::cheap_model;
+use super::citation_check;
+use super::client_versions::VersionsManifest;
+use super::dashboard;
+use super::debug_capture;
+use super::docs_search;
+use super::error_lookup;
+use super::experiments;
+use super::extract_services;
+use super::gemini_stream;
+use super::http_tool;
+use super::inbound_auth;
+use super::local_corpus;
+use super::log_redact::redact;
+use super::maintenance;
+use super::memory_store;
+use super::metrics;
+use super::mock_upstream;
+use super::model_guard;
+use super::openai_translate::{self, UpstreamProtocol};
+use super::outbound;
+use super::outcome::{OutcomeProcessor, ProcessOutcome};
+use super::package_registry;
+use super::pdf_text;
+use super::presets;
+use super::processor_settings::{InjectionFlags, ProcessorSettings, ProfileSettings};
+use super::react_shim;
+use super::request_schema;
+use super::response_rewrite::{Conversion, ResponsePlan};
+use super::responses_downgrade;
+use super::routing_rules;
+use super::sampling_policy;
+use super::schema_drift;
+use super::search_providers;
+use super::secret_store;
+use super::stall_guard::{self, StallSettings};
+use super::telemetry::Span;
+use super::token_health;
+use super::tool_batching::{self, BatchOutcome};
+use super::tool_cache;
+use super::tool_credits;
+use super::tool_toggle;
+use super::transform_middleware::{self, normalize_cache_control, TransformTarget};
+use super::transform_validation::StageChecker;
+use super::upstream;
+use super::url_policy::UrlPolicySettings;
+use super::user_fingerprint;
+use super::utility_tools;
+use super::web_extract::{self, ExtractMode};
 use super::{
     ClaudeHeadersProcessor, CodexHeadersProcessor, GeminiHeadersProcessor, ProcessedRequest,
     RequestProcessor,
 };
-use crate::services::profile_manager::ProfileManager;
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures_util::StreamExt;
 use hyper::HeaderMap as HyperHeaderMap;
 use once_cell::sync::Lazy;
-use reqwest::redirect::Policy;
 use serde_json::{json, Map, Value};
-use sha2::{Digest, Sha256};
+use std::future::Future;
 use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio_util::sync::CancellationToken;
 use url::Url;
-use uuid::Uuid;
-
-/// 全局 HTTP Client（复用连接池，禁止重定向，允许系统代理）
-static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
-    reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(15))
-        .connect_timeout(std::time::Duration::from_secs(10))
-        .redirect(Policy::none()) // 禁止重定向，防止 SSRF 绕过
-        .build()
-        .expect("Failed to create HTTP client")
-});
 
 static MCP_NAME_PREFIX_RE: Lazy<regex::Regex> = Lazy::new(|| {
     regex::Regex::new(r#""name"\s*:\s*"mcp_([^"]+)""#).expect("mcp name 前缀正则非法")
@@ -57,23 +114,55 @@ fn sanitize_brand_text(s: &str) -> String {
     BRAND_SANITIZE_RE.replace_all(s, "Claude Code").into_owned()
 }
 
-/// 统一 cache_control 为标准 5m ttl
-fn normalize_cache_control(item: &mut Value) {
-    if let Some(obj) = item.as_object_mut() {
-        if obj.contains_key("cache_control") {
-            obj.insert(
-                "cache_control".to_string(),
-                json!({ "type": "ephemeral", "ttl": "5m" }),
-            );
+tokio::task_local! {
+    /// 当前请求的取消令牌：代理层以 `REQUEST_CANCEL.scope(token, fut)` 包裹请求处理，
+    /// 客户端断开时 cancel，本地工具的外部请求随之中止
+    pub(crate) static REQUEST_CANCEL: CancellationToken;
+}
+
+/// 在当前请求的取消作用域内执行（未设置作用域时直接执行）
+async fn run_cancellable<T, F>(fut: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let Ok(token) = REQUEST_CANCEL.try_with(|t| t.clone()) else {
+        return fut.await;
+    };
+    tokio::select! {
+        biased;
+        _ = token.cancelled() => {
+            tracing::info!("请求已取消，中止进行中的外部请求");
+            Err(anyhow!("请求已取消"))
         }
+        result = fut => result,
     }
 }
 
 /// 最大响应体大小（5MB）
 const MAX_RESPONSE_SIZE: usize = 5 * 1024 * 1024;
 
-#[derive(Debug)]
-pub struct AmpHeadersProcessor;
+/// 本地工具最多跟随的重定向次数
+const MAX_REDIRECTS: usize = 5;
+
+pub struct AmpHeadersProcessor {
+    state: Arc<AppState>,
+    /// 后台任务所在的运行时（引擎指定）；None 时使用处理请求的运行时
+    runtime: Option<Handle>,
+}
+
+impl std::fmt::Debug for AmpHeadersProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AmpHeadersProcessor")
+            .finish_non_exhaustive()
+    }
+}
+
+/// 默认使用独立的 AppState（按磁盘配置加载）
+impl Default for AmpHeadersProcessor {
+    fn default() -> Self {
+        Self::new(Arc::new(AppState::new()))
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum ApiType {
@@ -81,9 +170,62 @@ enum ApiType {
     Claude,
     Codex,
     Gemini,
+    AzureOpenAI,
+}
+
+impl ApiType {
+    fn route_name(self) -> &'static str {
+        match self {
+            ApiType::AmpInternal => "amp",
+            ApiType::Claude => "claude",
+            ApiType::Codex => "codex",
+            ApiType::Gemini => "gemini",
+            ApiType::AzureOpenAI => "azure",
+        }
+    }
+
+    /// LLM 路由对应的协议（AMP 内部请求为 None）
+    fn transform_target(self) -> Option<TransformTarget> {
+        match self {
+            ApiType::AmpInternal => None,
+            ApiType::Claude => Some(TransformTarget::Claude),
+            ApiType::Codex | ApiType::AzureOpenAI => Some(TransformTarget::Codex),
+            ApiType::Gemini => Some(TransformTarget::Gemini),
+        }
+    }
+
+    fn from_route_name(name: &str) -> Option<Self> {
+        match name {
+            "amp" => Some(ApiType::AmpInternal),
+            "claude" => Some(ApiType::Claude),
+            "codex" => Some(ApiType::Codex),
+            "gemini" => Some(ApiType::Gemini),
+            "azure" => Some(ApiType::AzureOpenAI),
+            _ => None,
+        }
+    }
 }
 
 impl AmpHeadersProcessor {
+    /// 注入共享状态（嵌入方可传入自己的 AppState）
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self {
+            state,
+            runtime: None,
+        }
+    }
+
+    /// 指定后台任务（配置轮询、面板 / 管理端口、Token 检查、语料索引）所在的运行时
+    pub fn with_runtime(mut self, handle: Handle) -> Self {
+        self.runtime = Some(handle);
+        self
+    }
+
+    /// 后台任务所在的运行时（仅在请求处理流程内调用，此时必处于运行时上下文）
+    fn runtime(&self) -> Handle {
+        self.runtime.clone().unwrap_or_else(Handle::current)
+    }
+
     fn detect_api_type(path: &str, headers: &HyperHeaderMap, body: &[u8]) -> ApiType {
         let path_lower = path.to_lowercase();
 
@@ -97,6 +239,9 @@ impl AmpHeadersProcessor {
         if path_lower.starts_with("/api/provider/google") {
             return ApiType::Gemini;
         }
+        if path_lower.starts_with(azure_openai::AZURE_PATH_PREFIX) {
+            return ApiType::AzureOpenAI;
+        }
 
         // 2. 其他 /api/* → ampcode.com
         if path_lower.starts_with("/api/") {
@@ -176,6 +321,41 @@ impl AmpHeadersProcessor {
         "gemini-2.0-flash".to_string()
     }
 
+    /// 路由规则指定 profile 时，改用 ProxyConfigManager 中该工具配置的上游地址与 Key
+    /// source 为日志中的来源描述（路由规则 / 模型映射）
+    fn override_profile(&self, slot: &mut Option<SelectedProfile>, source: &str, profile_id: &str) {
+        let config = self
+            .state
+            .proxy_config_manager()
+            .ok()
+            .and_then(|mgr| mgr.get_config(profile_id).ok().flatten());
+        let (Some(base_url), Some(api_key)) = (
+            config.as_ref().and_then(|c| c.real_base_url.clone()),
+            config
+                .as_ref()
+                .and_then(|c| secret_store::reveal_opt(c.real_api_key.clone())),
+        ) else {
+            tracing::warn!(
+                "{} 指定的配置 {} 缺少上游地址或 Key，沿用当前 Profile",
+                source,
+                profile_id
+            );
+            return;
+        };
+        let Some(p) = slot.as_mut() else {
+            tracing::warn!(
+                "{}：当前路由未选择 Profile，无法套用配置 {}",
+                source,
+                profile_id
+            );
+            return;
+        };
+        tracing::info!("{}：改用配置 {}", source, profile_id);
+        p.name = profile_id.to_string();
+        p.base_url = base_url;
+        p.api_key = api_key;
+    }
+
     /// 提取 LLM API 路径：/api/provider/xxx/v1/... → /v1/...
     /// Gemini 特殊处理：/v1beta1/publishers/google/models/xxx → /v1beta/models/xxx
     fn extract_llm_path(path: &str) -> String {
@@ -196,21 +376,27 @@ impl AmpHeadersProcessor {
         path.to_string()
     }
 
-    fn get_user_agent(api_type: ApiType, path: &str, body: &[u8]) -> String {
-        match api_type {
-            ApiType::Claude => "claude-cli/2.1.2 (external, cli)".to_string(),
-            ApiType::Codex => {
-                "codex_cli_rs/0.77.0 (Mac OS 15.7.2; arm64) Apple_Terminal/455.1".to_string()
-            }
+    /// 伪装的 user-agent；Gemini 的模型名来自请求，可能含无法放入请求头的字符
+    fn get_user_agent(
+        versions: &VersionsManifest,
+        api_type: ApiType,
+        path: &str,
+        body: &[u8],
+    ) -> Result<hyper::header::HeaderValue> {
+        let user_agent = match api_type {
+            ApiType::Claude => versions.claude_user_agent.clone(),
+            ApiType::Codex | ApiType::AzureOpenAI => versions.codex_user_agent.clone(),
             ApiType::Gemini => {
                 let model = Self::extract_model_name(path, body);
-                format!("GeminiCLI/0.22.5/{} (darwin; arm64)", model)
+                versions.gemini_user_agent_for(&model)
             }
             ApiType::AmpInternal => unreachable!(),
-        }
+        };
+        hyper::header::HeaderValue::from_str(&user_agent)
+            .map_err(|_| anyhow!("user-agent 不是合法的请求头值: {:?}", user_agent))
     }
 
-    fn add_tool_prefix(body: &[u8]) -> Vec<u8> {
+    fn add_tool_prefix(body: &[u8], flags: &InjectionFlags, language: Option<&str>) -> Vec<u8> {
         const TOOL_PREFIX: &str = "mcp_";
 
         if body.is_empty() {
@@ -235,9 +421,13 @@ impl AmpHeadersProcessor {
             match system {
                 serde_json::Value::Array(items) => {
                     for item in items.iter_mut() {
-                        normalize_cache_control(item);
+                        if flags.cache_control {
+                            normalize_cache_control(item);
+                        }
 
-                        if item.get("type").and_then(|t| t.as_str()) != Some("text") {
+                        if !flags.brand_sanitize
+                            || item.get("type").and_then(|t| t.as_str()) != Some("text")
+                        {
                             continue;
                         }
                         if let Some(text) = item.get("text").and_then(|t| t.as_str()) {
@@ -253,13 +443,17 @@ impl AmpHeadersProcessor {
                             .first()
                             .and_then(|v| v.get("text").and_then(|t| t.as_str()))
                             == Some(CLAUDE_CODE_PREAMBLE);
-                    if !already_prefixed {
+                    if flags.system_preamble && !already_prefixed {
                         items.insert(0, json!({ "type": "text", "text": CLAUDE_CODE_PREAMBLE }));
                     }
                 }
                 serde_json::Value::String(s) => {
-                    let cleaned = sanitize_brand_text(s);
-                    if !cleaned.starts_with(CLAUDE_CODE_PREAMBLE) {
+                    let cleaned = if flags.brand_sanitize {
+                        sanitize_brand_text(s)
+                    } else {
+                        s.clone()
+                    };
+                    if flags.system_preamble && !cleaned.starts_with(CLAUDE_CODE_PREAMBLE) {
                         *s = format!("{}\n{}", CLAUDE_CODE_PREAMBLE, cleaned);
                     } else {
                         *s = cleaned;
@@ -269,15 +463,42 @@ impl AmpHeadersProcessor {
                     // 其他格式不处理
                 }
             }
-        } else {
+        } else if flags.system_preamble {
             json["system"] = json!([{ "type": "text", "text": CLAUDE_CODE_PREAMBLE }]);
         }
 
+        // 回复语言约束：紧跟身份声明之后
+        if let Some(language) = language {
+            let instruction = presets::language_instruction(language);
+            match json.get_mut("system") {
+                Some(serde_json::Value::Array(items)) => {
+                    let pos = usize::from(
+                        items
+                            .first()
+                            .and_then(|v| v.get("text"))
+                            .and_then(|t| t.as_str())
+                            == Some(CLAUDE_CODE_PREAMBLE),
+                    );
+                    items.insert(pos, json!({ "type": "text", "text": instruction }));
+                }
+                Some(serde_json::Value::String(s)) => {
+                    s.push_str("\n\n");
+                    s.push_str(&instruction);
+                }
+                _ => json["system"] = json!([{ "type": "text", "text": instruction }]),
+            }
+        }
+
         // 1) tools[].name 加前缀 + 统一 cache_control
         if let Some(tools) = json.get_mut("tools").and_then(|t| t.as_array_mut()) {
             for tool in tools.iter_mut() {
-                normalize_cache_control(tool);
+                if flags.cache_control {
+                    normalize_cache_control(tool);
+                }
 
+                if !flags.mcp_prefix {
+                    continue;
+                }
                 if let Some(name) = tool.get("name").and_then(|n| n.as_str()) {
                     if !name.starts_with(TOOL_PREFIX) {
                         tool["name"] =
@@ -299,9 +520,13 @@ impl AmpHeadersProcessor {
                 };
 
                 for item in arr.iter_mut() {
-                    normalize_cache_control(item);
+                    if flags.cache_control {
+                        normalize_cache_control(item);
+                    }
 
-                    if item.get("type").and_then(|t| t.as_str()) != Some("tool_use") {
+                    if !flags.mcp_prefix
+                        || item.get("type").and_then(|t| t.as_str()) != Some("tool_use")
+                    {
                         continue;
                     }
 
@@ -319,43 +544,68 @@ impl AmpHeadersProcessor {
     }
 
     async fn forward_to_amp(
+        &self,
+        settings: &ProcessorSettings,
         path: &str,
         query: Option<&str>,
         headers: &HyperHeaderMap,
         body: &[u8],
     ) -> Result<ProcessedRequest> {
-        let proxy_mgr = crate::services::proxy_config_manager::ProxyConfigManager::new()
-            .map_err(|e| anyhow!("ProxyConfigManager 初始化失败: {}", e))?;
+        let proxy_mgr = self.state.proxy_config_manager()?;
 
         let config = proxy_mgr
             .get_config("amp-code")
             .map_err(|e| anyhow!("读取配置失败: {}", e))?
             .ok_or_else(|| anyhow!("AMP Code 代理未配置"))?;
 
-        let token = config
-            .real_api_key
-            .ok_or_else(|| anyhow!("AMP Code Access Token 未配置"))?;
-
+        let auth = &settings.amp_auth;
+        token_health::ensure_checker(&self.state, &self.runtime());
         let base_url = config
             .real_base_url
             .unwrap_or_else(|| "https://ampcode.com".to_string());
 
+        let selected = match auth.select_account(headers) {
+            Some((name, token)) => {
+                tracing::debug!("AmpInternal 使用账号: {}", name);
+                Some((name.to_string(), token.to_string()))
+            }
+            None => secret_store::reveal_opt(config.real_api_key)
+                .map(|token| (token_health::DEFAULT_ACCOUNT.to_string(), token)),
+        };
+        let token = match (selected, auth.requirement(path)) {
+            // OAuth Token 临近过期时先刷新
+            (Some((account, token)), _) => {
+                let token = amp_oauth::access_token(&self.state, &base_url, &account, token).await;
+                Some((account, token))
+            }
+            (None, AuthRequirement::Optional) => None,
+            (None, AuthRequirement::Required) => return Err(amp_auth::missing_token_error(path)),
+        };
+
         let target_url = match query {
             Some(q) => format!("{}{}?{}", base_url, path, q),
             None => format!("{}{}", base_url, path),
         };
 
-        tracing::info!("AMP Code → ampcode.com: {}", target_url);
+        tracing::info!(
+            "AMP Code → ampcode.com: {}{}",
+            target_url,
+            if token.is_some() { "" } else { "（匿名）" }
+        );
 
         let mut new_headers = headers.clone();
+        upstream::strip_control_headers(&mut new_headers);
         new_headers.remove(hyper::header::AUTHORIZATION);
         let x_api_key = hyper::header::HeaderName::from_static("x-api-key");
         new_headers.remove(&x_api_key);
-        new_headers.insert(
-            hyper::header::AUTHORIZATION,
-            format!("Bearer {}", token).parse().unwrap(),
-        );
-        new_headers.insert(x_api_key, token.parse().unwrap());
+        if let Some((account, token)) = token {
+            let invalid = || anyhow!("AMP 账号 {} 的 Token 含非法字符，无法写入请求头", account);
+            new_headers.insert(
+                hyper::header::AUTHORIZATION,
+                format!("Bearer {}", token).parse().map_err(|_| invalid())?,
+            );
+            new_headers.insert(x_api_key, token.parse().map_err(|_| invalid())?);
+        }
 
         Ok(ProcessedRequest {
             target_url,
@@ -364,6 +614,82 @@ impl AmpHeadersProcessor {
         })
     }
 
+    /// Azure OpenAI：api-key 鉴权，路径改写为部署形式并补 api-version
+    async fn forward_to_azure(
+        &self,
+        settings: &ProcessorSettings,
+        path: &str,
+        query: Option<&str>,
+        headers: &HyperHeaderMap,
+        body: &[u8],
+    ) -> Result<ProcessOutcome> {
+        if !settings.azure.is_configured() || maintenance::down_remaining("azure").is_some() {
+            return Self::unavailable(
+                settings,
+                ApiType::AzureOpenAI,
+                "Azure OpenAI",
+                path,
+                query,
+                body,
+            );
+        }
+        amp_session::capture(&settings.amp_header_capture, "azure", "azure", headers);
+        Self::record_request(settings, "azure", "azure");
+
+        let transforms = settings.transforms_for(self.tool_id());
+        let transformed = transform_middleware::apply(
+            &transforms,
+            TransformTarget::Codex,
+            headers,
+            &settings.azure.api_key,
+            body,
+        );
+        let body = transformed.as_deref().unwrap_or(body);
+        let (target_url, rewritten) =
+            azure_openai::build_target(&settings.azure, path, query, body)?;
+        let final_body = rewritten.unwrap_or_else(|| body.to_vec());
+        Self::account_egress(settings, "azure", final_body.len())?;
+        tracing::info!("AMP Code → Azure OpenAI: {}", redact(&target_url));
+
+        let mut new_headers = headers.clone();
+        upstream::strip_control_headers(&mut new_headers);
+        let amp_headers: Vec<_> = new_headers
+            .keys()
+            .filter(|k| k.as_str().starts_with("x-amp-"))
+            .cloned()
+            .collect();
+        for key in amp_headers {
+            new_headers.remove(&key);
+        }
+        new_headers.remove(hyper::header::AUTHORIZATION);
+        new_headers.remove("x-api-key");
+        new_headers.remove(hyper::header::HOST);
+        new_headers.remove("content-length");
+        new_headers.remove("transfer-encoding");
+        new_headers.insert(
+            "api-key",
+            settings
+                .azure
+                .api_key
+                .parse()
+                .map_err(|_| anyhow!("Azure OpenAI api_key 含非法字符"))?,
+        );
+        Self::apply_openai_org_headers(settings.profile("azure"), &mut new_headers)?;
+        Self::apply_static_headers(settings.profile("azure"), &mut new_headers)?;
+
+        let result = ProcessedRequest {
+            target_url,
+            headers: new_headers,
+            body: Bytes::from(final_body),
+        };
+        Ok(upstream::tag(
+            result,
+            "azure",
+            "azure",
+            &settings.azure.base_url,
+        ))
+    }
+
     /// 检测是否为本地工具请求（精确匹配，避免误判）
     fn detect_local_tool(query: Option<&str>) -> Option<&'static str> {
         let q = query?;
@@ -375,6 +701,16 @@ impl AmpHeadersProcessor {
             match key {
                 "webSearch2" => return Some("webSearch2"),
                 "extractWebPageContent" => return Some("extractWebPageContent"),
+                "summarize" => return Some("summarize"),
+                "translate" => return Some("translate"),
+                "evaluateExpression" => return Some("evaluateExpression"),
+                "currentTime" => return Some("currentTime"),
+                "getWeather" => return Some("getWeather"),
+                "httpRequest" => return Some("httpRequest"),
+                "lookupCrate" => return Some("lookupCrate"),
+                "lookupNpmPackage" => return Some("lookupNpmPackage"),
+                "lookupPypiPackage" => return Some("lookupPypiPackage"),
+                "searchDocs" => return Some("searchDocs"),
                 _ => continue,
             }
         }
@@ -383,22 +719,75 @@ impl AmpHeadersProcessor {
 
     /// 处理本地工具请求
     async fn handle_local_tool(
+        &self,
+        settings: &ProcessorSettings,
         tool_name: &str,
         body: &[u8],
         tavily_api_key: Option<&str>,
-    ) -> Result<ProcessedRequest> {
-        match tool_name {
-            "webSearch2" => Self::handle_web_search(body, tavily_api_key).await,
-            "extractWebPageContent" => Self::handle_extract_web_page(body).await,
-            _ => Err(anyhow!("未知的本地工具: {}", tool_name)),
+    ) -> Result<ProcessOutcome> {
+        if settings.tool_toggle.is_disabled(tool_name) {
+            return Err(anyhow!("本地工具 {} 已禁用", tool_name));
         }
+        bandwidth::check_egress_cap(
+            Subject::Tool(tool_name),
+            settings.tools_egress_cap_bytes_per_day,
+            0,
+        )?;
+
+        // 取消时 select 丢弃处理 future，进行中的外部请求随之中止
+        // 费用在作用域内累计，build_local_response 据此填写 creditsConsumed
+        let client = self.state.outbound().tool_client(settings);
+        let (result, cost) = tool_credits::scope(run_cancellable(async {
+            match tool_name {
+                "webSearch2" => self.handle_web_search(settings, body, tavily_api_key).await,
+                "extractWebPageContent" => self.handle_extract_web_page(settings, body).await,
+                "summarize" | "translate" => {
+                    self.handle_cheap_model(settings, tool_name, body).await
+                }
+                "evaluateExpression" => {
+                    Self::build_local_response(tool_name, calculator::handle(body)?)
+                }
+                "currentTime" => {
+                    Self::build_local_response(tool_name, utility_tools::current_time(body)?)
+                }
+                "getWeather" => {
+                    let response = utility_tools::weather(&client, &settings.weather, body).await?;
+                    Self::build_local_response(tool_name, response)
+                }
+                "httpRequest" => self.handle_http_request(settings, body).await,
+                "searchDocs" => {
+                    let response =
+                        docs_search::handle(&client, &settings.docs_search, body).await?;
+                    Self::build_local_response(tool_name, response)
+                }
+                "lookupCrate" | "lookupNpmPackage" | "lookupPypiPackage" => {
+                    let response = package_registry::handle(
+                        &client,
+                        &settings.package_registry,
+                        tool_name,
+                        body,
+                    )
+                    .await?;
+                    Self::build_local_response(tool_name, response)
+                }
+                _ => Err(anyhow!("未知的本地工具: {}", tool_name)),
+            }
+        }))
+        .await;
+        if cost > 0.0 {
+            tracing::info!("本地工具 {} 费用: ${:.6}", tool_name, cost);
+        }
+        metrics::record_tool(tool_name, result.is_ok());
+        result
     }
 
     /// 处理网页搜索请求
     async fn handle_web_search(
+        &self,
+        settings: &ProcessorSettings,
         body: &[u8],
         tavily_api_key: Option<&str>,
-    ) -> Result<ProcessedRequest> {
+    ) -> Result<ProcessOutcome> {
         // 解析请求 JSON（不吞掉错误）
         let req_json: Value =
             serde_json::from_slice(body).map_err(|e| anyhow!("请求 JSON 解析失败: {}", e))?;
@@ -411,307 +800,425 @@ impl AmpHeadersProcessor {
             .unwrap_or_default();
         let max_results = params["maxResults"].as_i64().unwrap_or(5) as usize;
 
+        let cache_key = tool_cache::key("webSearch2", params);
+        if let Some(cached) = tool_cache::get(&settings.tool_cache, "webSearch2", &cache_key) {
+            tracing::info!("本地搜索命中缓存: objective={:?}", objective);
+            return Self::build_local_response("webSearch2", cached);
+        }
+
         // 构建查询列表
-        let queries: Vec<&str> = if search_queries.is_empty() && !objective.is_empty() {
+        let mut queries: Vec<&str> = if search_queries.is_empty() && !objective.is_empty() {
             vec![objective]
         } else {
             search_queries
         };
 
+        // 报错 / 堆栈类查询：归一化后优先查 Stack Overflow 与 GitHub Issues
+        let error_query = settings
+            .error_lookup
+            .enabled
+            .then(|| {
+                std::iter::once(objective)
+                    .chain(queries.iter().copied())
+                    .find(|q| error_lookup::looks_like_error(q))
+            })
+            .flatten()
+            .map(error_lookup::normalize)
+            .filter(|q| !q.is_empty());
+        if let Some(query) = &error_query {
+            tracing::info!("本地搜索: 识别为报错信息，归一化查询: {}", query);
+            queries = vec![query.as_str()];
+        }
+
         tracing::info!(
             "本地搜索: queries={:?}, max_results={}",
             queries,
             max_results
         );
 
-        // 尝试 Tavily，无 Key 则降级 DuckDuckGo
-        let (results, provider) = if let Some(api_key) = tavily_api_key {
-            tracing::info!("使用 Tavily 搜索服务");
-            match Self::search_tavily(&queries, max_results, api_key).await {
-                Ok(r) => (r, "tavily"),
+        // 按 search.providers 顺序尝试，失败时降级下一个；全部失败或 params.offline 时查本地文档库
+        let client = self.state.outbound().tool_client(settings);
+        let offline = params["offline"].as_bool().unwrap_or(false);
+        let (mut results, provider) = if offline {
+            if !local_corpus::is_ready() {
+                return Err(anyhow!("本地文档库未配置或索引尚未完成"));
+            }
+            (local_corpus::search(&queries, max_results), "local_corpus")
+        } else {
+            let chain = search_providers::build_chain(&settings.search, tavily_api_key, &client);
+            match search_providers::search(&chain, &settings.tool_credits, &queries, max_results)
+                .await
+            {
+                Ok(found) => found,
+                Err(e) if local_corpus::is_ready() => {
+                    tracing::warn!("网络搜索全部失败，改查本地文档库: {}", e);
+                    (local_corpus::search(&queries, max_results), "local_corpus")
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        let online = provider != "local_corpus";
+
+        // 编程类查询：权威文档结果排在网页结果之前
+        let docs = &settings.docs_search;
+        if online && docs.merge_into_web_search && docs.web_search_slots > 0 {
+            if let Some(query) = queries
+                .iter()
+                .find(|q| docs_search::looks_like_programming(q))
+            {
+                let hits =
+                    docs_search::search(&client, docs, query, None, docs.web_search_slots).await;
+                let doc_urls: Vec<&str> = hits.iter().map(|h| h.url.as_str()).collect();
+                results.retain(|r| !doc_urls.contains(&r["url"].as_str().unwrap_or("")));
+                results.splice(0..0, hits.iter().map(|h| h.to_web_result()));
+                results.truncate(max_results);
+            }
+        }
+        if let Some(query) = error_query.as_ref().filter(|_| online) {
+            let hits = error_lookup::search(&client, &settings.error_lookup, query).await;
+            let hit_urls: Vec<&str> = hits.iter().filter_map(|h| h["url"].as_str()).collect();
+            results.retain(|r| !hit_urls.contains(&r["url"].as_str().unwrap_or("")));
+            results.splice(0..0, hits.iter().cloned());
+            results.truncate(max_results.max(hits.len()));
+        }
+
+        // 可选：廉价模型基于前几条结果生成带引用的简答（params.synthesize 覆盖配置）
+        let synthesize = params["synthesize"]
+            .as_bool()
+            .unwrap_or(settings.search.synthesize);
+        let question = if objective.is_empty() {
+            queries.join(" ")
+        } else {
+            objective.to_string()
+        };
+        let sources = &results[..results.len().min(settings.search.synthesis_sources)];
+        let answer = if synthesize && !sources.is_empty() {
+            match cheap_model::synthesize(&self.state, &settings.cheap_model, &question, sources)
+                .await
+            {
+                Ok(answer) => Some(answer),
                 Err(e) => {
-                    tracing::warn!("Tavily 搜索失败，降级 DuckDuckGo: {}", e);
-                    (
-                        Self::search_duckduckgo(&queries, max_results).await?,
-                        "local-duckduckgo",
-                    )
+                    tracing::warn!("搜索答案综合失败，仅返回原始结果: {}", e);
+                    None
                 }
             }
         } else {
-            tracing::info!("使用 DuckDuckGo 本地搜索（未配置 Tavily API Key）");
-            (
-                Self::search_duckduckgo(&queries, max_results).await?,
-                "local-duckduckgo",
-            )
+            None
         };
 
-        let response = json!({
+        let mut response = json!({
             "ok": true,
             "result": {
                 "results": results,
                 "provider": provider,
                 "showParallelAttribution": false
-            },
-            "creditsConsumed": "0"
+            }
         });
+        if let Some(mut answer) = answer {
+            // 核查引用：删除越界编号与结果外链接，标注找不到出处的引文
+            if settings.search.verify_citations {
+                let (checked, issues) = citation_check::check(&answer, sources);
+                if !issues.is_empty() {
+                    tracing::warn!("搜索答案引用核查发现 {} 处问题", issues.len());
+                }
+                answer = checked;
+                response["result"]["citations"] = json!({ "issues": issues });
+            }
+            response["result"]["provider"] = json!("synthesized");
+            response["result"]["sourceProvider"] = json!(provider);
+            response["result"]["answer"] = json!(answer);
+        }
 
+        tool_cache::put(&settings.tool_cache, "webSearch2", &cache_key, &response);
         tracing::info!("本地搜索完成: {} 条结果", results.len());
         Self::build_local_response("webSearch2", response)
     }
 
-    /// Tavily 搜索（使用全局 Client）
-    async fn search_tavily(
-        queries: &[&str],
-        max_results: usize,
-        api_key: &str,
-    ) -> Result<Vec<Value>> {
-        let mut all_results = Vec::new();
-        let mut seen_urls = std::collections::HashSet::new();
-
-        for query in queries {
-            if all_results.len() >= max_results {
-                break;
-            }
-
-            let request_body = json!({
-                "api_key": api_key,
-                "query": query,
-                "search_depth": "basic",
-                "max_results": max_results.min(10),
-                "include_answer": false
-            });
-
-            let resp = HTTP_CLIENT
-                .post("https://api.tavily.com/search")
-                .header("Content-Type", "application/json")
-                .json(&request_body)
-                .send()
-                .await?;
-
-            if !resp.status().is_success() {
-                let status = resp.status();
-                let text = resp.text().await.unwrap_or_default();
-                return Err(anyhow!("Tavily API 错误: {} - {}", status, text));
-            }
-
-            let data: Value = resp.json().await?;
-            if let Some(results) = data["results"].as_array() {
-                for r in results {
-                    let url = r["url"].as_str().unwrap_or("");
-                    if seen_urls.contains(url) {
-                        continue;
-                    }
-                    seen_urls.insert(url.to_string());
+    /// 处理网页内容提取请求（增强 SSRF 防护 + 流式读取）
+    async fn handle_extract_web_page(
+        &self,
+        settings: &ProcessorSettings,
+        body: &[u8],
+    ) -> Result<ProcessOutcome> {
+        // 解析请求 JSON（不吞掉错误）
+        let req_json: Value =
+            serde_json::from_slice(body).map_err(|e| anyhow!("请求 JSON 解析失败: {}", e))?;
+        let target_url = req_json["params"]["url"]
+            .as_str()
+            .ok_or_else(|| anyhow!("缺少 URL 参数"))?;
 
-                    all_results.push(json!({
-                        "title": r["title"].as_str().unwrap_or(""),
-                        "url": url,
-                        "excerpts": [r["content"].as_str().unwrap_or("")]
-                    }));
+        // SSRF 防护：使用 URL 解析进行精确校验
+        Self::validate_url_security(target_url, &settings.url_policy).await?;
 
-                    if all_results.len() >= max_results {
-                        break;
-                    }
-                }
-            }
+        let cache_key = tool_cache::key("extractWebPageContent", &req_json["params"]);
+        if let Some(cached) =
+            tool_cache::get(&settings.tool_cache, "extractWebPageContent", &cache_key)
+        {
+            tracing::info!("本地网页提取命中缓存: {}", redact(target_url));
+            return Self::build_local_response("extractWebPageContent", cached);
         }
 
-        Ok(all_results)
-    }
-
-    /// DuckDuckGo HTML 搜索（降级方案，使用全局 Client）
-    async fn search_duckduckgo(queries: &[&str], max_results: usize) -> Result<Vec<Value>> {
-        let mut all_results = Vec::new();
-        let mut seen_urls = std::collections::HashSet::new();
-
-        for query in queries {
-            if all_results.len() >= max_results {
-                break;
+        let requested_mode = req_json["params"]["mode"]
+            .as_str()
+            .and_then(ExtractMode::parse);
+        let (content, provider) = self
+            .fetch_page_content(settings, target_url, requested_mode)
+            .await?;
+        let response = json!({
+            "ok": true,
+            "result": {
+                "fullContent": content,
+                "excerpts": [],
+                "provider": provider
             }
+        });
+        tool_cache::put(
+            &settings.tool_cache,
+            "extractWebPageContent",
+            &cache_key,
+            &response,
+        );
+        Self::build_local_response("extractWebPageContent", response)
+    }
 
-            let url = format!(
-                "https://html.duckduckgo.com/html/?q={}",
-                urlencoding::encode(query)
-            );
-
-            let resp = HTTP_CLIENT
-                .get(&url)
-                .header("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36")
-                .header("Accept", "text/html")
-                .header("Accept-Language", "zh-CN,zh;q=0.9,en;q=0.8")
-                .send()
-                .await?;
-
-            let html = resp.text().await?;
-            let parsed = Self::parse_duckduckgo_html(&html);
-
-            for r in parsed {
-                if seen_urls.contains(&r.url) {
-                    continue;
+    /// 获取页面正文：远程阅读器（可选）→ 本地抓取（PDF / HTML），返回 (内容, 提供方)
+    async fn fetch_page_content(
+        &self,
+        all_settings: &ProcessorSettings,
+        target_url: &str,
+        requested_mode: Option<ExtractMode>,
+    ) -> Result<(String, &'static str)> {
+        let settings = &all_settings.web_extract;
+        // 外部提取服务（JS 渲染页面），失败时回退本地抓取
+        let client = self.state.outbound().tool_client(all_settings);
+        let service = extract_services::build(&settings.reader, &client)
+            .filter(|_| requested_mode != Some(ExtractMode::Raw));
+        if let Some(service) = service {
+            match service.extract(target_url).await {
+                Ok(content) => {
+                    tool_credits::charge_call(
+                        &all_settings.tool_credits,
+                        "extractWebPageContent",
+                        service.label(),
+                    );
+                    tracing::info!(
+                        "外部提取服务 {} 完成: {} bytes",
+                        service.label(),
+                        content.len()
+                    );
+                    return Ok((content, service.label()));
                 }
-                seen_urls.insert(r.url.clone());
-
-                all_results.push(json!({
-                    "title": r.title,
-                    "url": r.url,
-                    "excerpts": if r.snippet.is_empty() { vec![] } else { vec![r.snippet] }
-                }));
-
-                if all_results.len() >= max_results {
-                    break;
+                Err(e) => {
+                    tracing::warn!("外部提取服务 {} 失败，回退本地抓取: {}", service.label(), e)
                 }
             }
         }
 
-        Ok(all_results)
-    }
-
-    /// 解析 DuckDuckGo HTML 结果
-    fn parse_duckduckgo_html(html: &str) -> Vec<DuckDuckGoResult> {
-        let mut results = Vec::new();
-
-        // 简单解析：查找 class="result__a" 的链接
-        for part in html.split("class=\"result__a\"").skip(1) {
-            // 提取 URL
-            let url = if let Some(start) = part.find("href=\"") {
-                let after = &part[start + 6..];
-                if let Some(end) = after.find('"') {
-                    Self::extract_ddg_actual_url(&after[..end])
-                } else {
-                    continue;
-                }
-            } else {
-                continue;
-            };
-
-            if url.is_empty() {
-                continue;
-            }
-
-            // 提取标题
-            let title = if let Some(start) = part.find('>') {
-                let after = &part[start + 1..];
-                if let Some(end) = after.find("</a>") {
-                    Self::clean_html(&after[..end])
-                } else {
-                    String::new()
-                }
-            } else {
-                String::new()
-            };
-
-            // 提取摘要
-            let snippet = if let Some(snip_start) = part.find("result__snippet") {
-                let snip_part = &part[snip_start..];
-                if let Some(start) = snip_part.find('>') {
-                    let after = &snip_part[start + 1..];
-                    if let Some(end) = after.find("</a>") {
-                        Self::clean_html(&after[..end])
-                    } else {
-                        String::new()
-                    }
-                } else {
-                    String::new()
-                }
-            } else {
-                String::new()
-            };
+        tracing::info!("本地网页提取: {}", redact(target_url));
+
+        let headers = [
+            ("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36"),
+            ("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,application/pdf;q=0.8,*/*;q=0.7"),
+            ("Accept-Language", "zh-CN,zh;q=0.9,en;q=0.8"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let (resp, final_url) = self
+            .send_following_redirects(
+                all_settings,
+                reqwest::Method::GET,
+                target_url,
+                &headers,
+                None,
+                None,
+                |_| true,
+            )
+            .await?;
 
-            results.push(DuckDuckGoResult {
-                title,
-                url,
-                snippet,
-            });
+        if !resp.status().is_success() {
+            return Err(anyhow!("HTTP {}", resp.status()));
         }
 
-        results
-    }
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
 
-    /// 从 DuckDuckGo 重定向 URL 提取实际 URL
-    fn extract_ddg_actual_url(ddg_url: &str) -> String {
-        if ddg_url.contains("uddg=") {
-            if let Some(pos) = ddg_url.find("uddg=") {
-                let encoded = &ddg_url[pos + 5..];
-                let end = encoded.find('&').unwrap_or(encoded.len());
-                if let Ok(decoded) = urlencoding::decode(&encoded[..end]) {
-                    return decoded.into_owned();
-                }
-            }
-        }
-        if ddg_url.starts_with("http") {
-            ddg_url.to_string()
+        // 流式读取并限制大小（防止 chunked 编码绕过 Content-Length 检查）；PDF 单独放宽上限
+        let max_size = if pdf_text::is_pdf(content_type.as_deref(), &[]) {
+            settings.pdf.max_bytes
         } else {
-            String::new()
+            MAX_RESPONSE_SIZE
+        };
+        let data = Self::read_bytes_with_limit(resp, max_size, &all_settings.tools_stall).await?;
+        bandwidth::record(Subject::Tool("extractWebPageContent"), 0, data.len() as u64);
+
+        if pdf_text::is_pdf(content_type.as_deref(), &data) {
+            let size = data.len();
+            let text = pdf_text::extract_blocking(data, settings.pdf.clone()).await?;
+            tracing::info!(
+                "本地 PDF 提取完成: {} bytes → {} 字符",
+                size,
+                text.chars().count()
+            );
+            return Ok((text, "local"));
         }
-    }
+        let html = charset::decode(content_type.as_deref(), data).await?;
 
-    /// 清理 HTML 标签和实体
-    fn clean_html(s: &str) -> String {
-        let mut result = s.to_string();
-        // 移除 HTML 标签
-        while let Some(start) = result.find('<') {
-            if let Some(end) = result[start..].find('>') {
-                result = format!("{}{}", &result[..start], &result[start + end + 1..]);
-            } else {
-                break;
-            }
-        }
-        // 解码常见 HTML 实体
-        result = result
-            .replace("&amp;", "&")
-            .replace("&lt;", "<")
-            .replace("&gt;", ">")
-            .replace("&quot;", "\"")
-            .replace("&#39;", "'")
-            .replace("&nbsp;", " ");
-        result.trim().to_string()
+        // 默认返回原始 HTML（与 AMP-Manager 行为一致）；可配置为正文提取 / Markdown，params.mode 可覆盖
+        let mode = requested_mode.unwrap_or(settings.mode);
+        // 相对链接按重定向后的最终地址解析
+        let content = web_extract::extract(&html, mode, &final_url);
+        tracing::info!(
+            "本地网页提取完成: {} bytes → {} bytes ({:?})",
+            html.len(),
+            content.len(),
+            mode
+        );
+        Ok((content, "local"))
     }
 
-    /// 处理网页内容提取请求（增强 SSRF 防护 + 流式读取）
-    async fn handle_extract_web_page(body: &[u8]) -> Result<ProcessedRequest> {
-        // 解析请求 JSON（不吞掉错误）
-        let req_json: Value =
-            serde_json::from_slice(body).map_err(|e| anyhow!("请求 JSON 解析失败: {}", e))?;
-        let target_url = req_json["params"]["url"]
-            .as_str()
-            .ok_or_else(|| anyhow!("缺少 URL 参数"))?;
-
-        // SSRF 防护：使用 URL 解析进行精确校验
-        Self::validate_url_security(target_url)?;
-
-        tracing::info!("本地网页提取: {}", target_url);
+    /// 处理摘要 / 翻译请求（交给配置的廉价模型或离线翻译服务）
+    async fn handle_cheap_model(
+        &self,
+        settings: &ProcessorSettings,
+        tool_name: &str,
+        body: &[u8],
+    ) -> Result<ProcessOutcome> {
+        let response = match tool_name {
+            "translate" => cheap_model::translate(&self.state, &settings.cheap_model, body).await?,
+            _ => cheap_model::summarize(&self.state, &settings.cheap_model, body).await?,
+        };
+        bandwidth::record(Subject::Tool(tool_name), body.len() as u64, 0);
+        Self::build_local_response(tool_name, response)
+    }
 
-        let resp = HTTP_CLIENT
-            .get(target_url)
-            .header("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36")
-            .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")
-            .header("Accept-Language", "zh-CN,zh;q=0.9,en;q=0.8")
-            .send()
+    /// 处理通用 HTTP 请求（域名 / 方法允许列表 + SSRF 防护 + 大小限制）
+    async fn handle_http_request(
+        &self,
+        all_settings: &ProcessorSettings,
+        body: &[u8],
+    ) -> Result<ProcessOutcome> {
+        let settings = &all_settings.http_request;
+        let call = http_tool::parse(settings, body)?;
+        Self::validate_url_security(&call.url, &all_settings.url_policy).await?;
+
+        tracing::info!("本地 HTTP 请求: {} {}", call.method, redact(&call.url));
+        let sent = call.body.as_ref().map_or(0, |b| b.len() as u64);
+        // 重定向目标同样须在域名允许列表中
+        let (resp, _) = self
+            .send_following_redirects(
+                all_settings,
+                call.method,
+                &call.url,
+                &call.headers,
+                call.body,
+                Some(std::time::Duration::from_secs(settings.timeout_secs.max(1))),
+                |host| settings.domain_allowed(host),
+            )
             .await?;
 
-        if !resp.status().is_success() {
-            return Err(anyhow!("HTTP {}", resp.status()));
-        }
+        let status = resp.status().as_u16();
+        let content_type = resp
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let headers: serde_json::Map<String, Value> = resp
+            .headers()
+            .iter()
+            .filter(|(k, _)| !k.as_str().starts_with("set-cookie"))
+            .filter_map(|(k, v)| Some((k.to_string(), json!(v.to_str().ok()?))))
+            .collect();
+        let text = Self::read_response_with_limit(
+            resp,
+            settings.max_response_bytes,
+            &all_settings.tools_stall,
+        )
+        .await?;
+        bandwidth::record(Subject::Tool("httpRequest"), sent, text.len() as u64);
 
-        // 流式读取并限制大小（防止 chunked 编码绕过 Content-Length 检查）
-        let html = Self::read_response_with_limit(resp, MAX_RESPONSE_SIZE).await?;
+        tracing::info!("本地 HTTP 请求完成: {} ({} bytes)", status, text.len());
+        let response = http_tool::respond(status, &content_type, Value::Object(headers), text);
+        Self::build_local_response("httpRequest", response)
+    }
 
-        // 返回原始 HTML（与 AMP-Manager 行为一致）
-        let response = json!({
-            "ok": true,
-            "result": {
-                "fullContent": html,
-                "excerpts": [],
-                "provider": "local"
+    /// 发送本地工具请求并手动跟随重定向（最多 MAX_REDIRECTS 跳），返回 (响应, 最终 URL)
+    ///
+    /// 工具 Client 禁止自动重定向；这里每一跳都重新做 SSRF 校验，host_allowed 为额外的主机检查。
+    /// 301 / 302 / 303 改为不带请求体的 GET（HEAD 除外），307 / 308 保持原方法与请求体；
+    /// 跳转到其他主机时去掉 Authorization / Cookie。
+    #[allow(clippy::too_many_arguments)]
+    async fn send_following_redirects(
+        &self,
+        settings: &ProcessorSettings,
+        mut method: reqwest::Method,
+        url: &str,
+        headers: &[(String, String)],
+        mut body: Option<Vec<u8>>,
+        timeout: Option<std::time::Duration>,
+        host_allowed: impl Fn(&str) -> bool,
+    ) -> Result<(reqwest::Response, String)> {
+        let mut current = Url::parse(url).map_err(|e| anyhow!("URL 解析失败: {}", e))?;
+        let mut headers = headers.to_vec();
+        // 每一跳的校验与连接使用同一份策略
+        let client = self.state.outbound().fetch_client(settings);
+        for hop in 0..=MAX_REDIRECTS {
+            Self::validate_url_security(current.as_str(), &settings.url_policy).await?;
+            let host = current.host_str().unwrap_or_default();
+            if !host_allowed(host) {
+                return Err(anyhow!("重定向目标 {} 不在允许列表中", host));
             }
-        });
 
-        tracing::info!("本地网页提取完成: {} bytes", html.len());
-        Self::build_local_response("extractWebPageContent", response)
+            let mut request = client.request(method.clone(), current.as_str());
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+            for (name, value) in &headers {
+                request = request.header(name.as_str(), value.as_str());
+            }
+            if let Some(payload) = &body {
+                request = request.body(payload.clone());
+            }
+            let resp = request.send().await?;
+
+            let status = resp.status();
+            let location = resp
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|v| v.to_str().ok());
+            let (true, Some(location)) = (status.is_redirection(), location) else {
+                return Ok((resp, current.to_string()));
+            };
+            if hop == MAX_REDIRECTS {
+                break;
+            }
+            let next = current
+                .join(location)
+                .map_err(|e| anyhow!("重定向地址无效: {}", e))?;
+            tracing::info!("本地工具重定向 {}: {} → {}", status.as_u16(), current, next);
+
+            if matches!(status.as_u16(), 301..=303) && method != reqwest::Method::HEAD {
+                method = reqwest::Method::GET;
+                body = None;
+                headers.retain(|(k, _)| !k.eq_ignore_ascii_case("content-type"));
+            }
+            if next.host_str() != current.host_str() {
+                headers.retain(|(k, _)| {
+                    !k.eq_ignore_ascii_case("authorization") && !k.eq_ignore_ascii_case("cookie")
+                });
+            }
+            current = next;
+        }
+        Err(anyhow!("重定向次数超过 {} 次", MAX_REDIRECTS))
     }
 
     /// URL 安全校验（SSRF 防护）
-    fn validate_url_security(url_str: &str) -> Result<()> {
+    ///
+    /// 域名会先解析并校验全部地址；实际连接由 Outbound::fetch_client 的解析器再次校验并
+    /// 只连接已校验的地址（防止 DNS rebinding）。
+    async fn validate_url_security(url_str: &str, policy: &UrlPolicySettings) -> Result<()> {
         // 解析 URL
         let url = Url::parse(url_str).map_err(|e| anyhow!("URL 解析失败: {}", e))?;
 
@@ -729,6 +1236,14 @@ impl AmpHeadersProcessor {
         // 检查 host
         let host = url.host_str().ok_or_else(|| anyhow!("URL 缺少主机名"))?;
 
+        // 企业策略：禁止列表优先，允许列表中的内部主机跳过内网检查
+        if policy.is_denied(host) {
+            return Err(anyhow!("域名在禁止列表中: {}", host));
+        }
+        if policy.is_allowed_internal(host) {
+            return Ok(());
+        }
+
         // 检查是否为 IP 地址
         if let Ok(ip) = host.parse::<IpAddr>() {
             if Self::is_private_ip(&ip) {
@@ -744,13 +1259,16 @@ impl AmpHeadersProcessor {
             {
                 return Err(anyhow!("禁止访问内网域名"));
             }
+            outbound::resolve_public(host, policy)
+                .await
+                .map_err(|e| anyhow!(e))?;
         }
 
         Ok(())
     }
 
     /// 检查是否为私有/保留 IP 地址
-    fn is_private_ip(ip: &IpAddr) -> bool {
+    pub(crate) fn is_private_ip(ip: &IpAddr) -> bool {
         match ip {
             IpAddr::V4(ipv4) => {
                 ipv4.is_loopback()           // 127.0.0.0/8
@@ -763,6 +1281,10 @@ impl AmpHeadersProcessor {
                 // 100.64.0.0/10 (CGN)
             }
             IpAddr::V6(ipv6) => {
+                // IPv4 映射地址（::ffff:a.b.c.d）按 IPv4 规则判断
+                if let Some(ipv4) = ipv6.to_ipv4_mapped() {
+                    return Self::is_private_ip(&IpAddr::V4(ipv4));
+                }
                 ipv6.is_loopback()      // ::1
                     || ipv6.is_unspecified() // ::
                     || ipv6.is_multicast()
@@ -773,163 +1295,204 @@ impl AmpHeadersProcessor {
         }
     }
 
-    /// 流式读取响应并限制大小
-    async fn read_response_with_limit(resp: reqwest::Response, max_size: usize) -> Result<String> {
+    /// 流式读取响应并限制大小，按检测到的字符集转码为 UTF-8
+    async fn read_response_with_limit(
+        resp: reqwest::Response,
+        max_size: usize,
+        stall: &StallSettings,
+    ) -> Result<String> {
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let data = Self::read_bytes_with_limit(resp, max_size, stall).await?;
+        charset::decode(content_type.as_deref(), data).await
+    }
+
+    /// 流式读取原始字节并限制大小
+    async fn read_bytes_with_limit(
+        resp: reqwest::Response,
+        max_size: usize,
+        stall: &StallSettings,
+    ) -> Result<Vec<u8>> {
         let mut stream = resp.bytes_stream();
         let mut data = Vec::new();
 
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|e| anyhow!("读取响应失败: {}", e))?;
+        // 连续无数据超过 tools_stall.idle_secs 时中止（慢速输出不会触发总超时）
+        while let Some(chunk) = stall_guard::next_chunk(stall, &mut stream).await? {
             if data.len() + chunk.len() > max_size {
                 return Err(anyhow!("响应体过大，超过 {} bytes 限制", max_size));
             }
             data.extend_from_slice(&chunk);
         }
 
-        String::from_utf8(data).map_err(|e| anyhow!("响应不是有效的 UTF-8: {}", e))
+        Ok(data)
     }
 
     /// 构建本地处理响应
-    fn build_local_response(tool_name: &str, response: Value) -> Result<ProcessedRequest> {
+    fn build_local_response(tool_name: &str, mut response: Value) -> Result<ProcessOutcome> {
+        // 本次调用实际消耗的费用（命中缓存时为 0）
+        if let Some(obj) = response.as_object_mut() {
+            obj.insert(
+                "creditsConsumed".into(),
+                json!(tool_credits::format_credits(tool_credits::current())),
+            );
+        }
         let body_bytes = serde_json::to_vec(&response)?;
         let mut headers = HyperHeaderMap::new();
         headers.insert("content-type", "application/json".parse().unwrap());
 
-        Ok(ProcessedRequest {
-            target_url: format!("dc-local://{}", tool_name),
-            headers,
-            body: Bytes::from(body_bytes),
-        })
+        Ok(ProcessOutcome::local(tool_name, headers, body_bytes))
     }
 
-    /// 生成 64 位 hex 用户指纹：SHA256(API_Key + UA)
-    /// 使用完整 API Key 避免不同 key 碰撞，UA 作为辅助区分
-    fn generate_user_hash(headers: &HyperHeaderMap, api_key: &str) -> String {
-        let ua = headers
-            .get("user-agent")
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("unknown");
-
-        let mut hasher = Sha256::new();
-        // 使用完整 API Key 作为主要标识，UA 作为辅助
-        hasher.update(format!("{}:{}", api_key, ua));
-        format!("{:x}", hasher.finalize())
+    /// 按会话分配 A/B 实验变体并改写 system，返回 None 表示未命中或无需改写
+    fn apply_experiment(
+        experiments: &[experiments::ExperimentSettings],
+        body: &[u8],
+    ) -> Option<Vec<u8>> {
+        if experiments.is_empty() {
+            return None;
+        }
+        let json: Value = serde_json::from_slice(body).ok()?;
+        let session_id = transform_middleware::generate_session_uuid(&json["messages"]);
+        let (assignment, variant) = experiments::assign(experiments, &session_id)?;
+        let is_first_turn = json["messages"].as_array().map(|m| m.len()) == Some(1);
+
+        tracing::debug!(
+            "AMP Code 实验: {}={} (session={})",
+            assignment.experiment_id,
+            assignment.variant,
+            session_id
+        );
+        experiments::record_hit(&assignment, &session_id, is_first_turn);
+        experiments::apply_variant(body, CLAUDE_CODE_PREAMBLE, variant)
     }
 
-    /// 生成 UUID 格式会话标识
-    /// - 有消息内容：基于前3条消息的 SHA256（支持会话复用）
-    /// - 无消息内容：使用随机 UUID v4（避免碰撞）
-    fn generate_session_uuid(messages: &Value) -> String {
-        let content = messages
-            .as_array()
-            .map(|arr| {
-                arr.iter()
-                    .take(3)
-                    .filter_map(|m| {
-                        // 支持字符串和数组格式的 content
-                        let c = m.get("content")?;
-                        if let Some(s) = c.as_str() {
-                            Some(s.to_string())
-                        } else {
-                            // 多模态内容：提取 text 类型
-                            c.as_array().map(|arr| {
-                                arr.iter()
-                                    .filter_map(|item| {
-                                        if item.get("type")?.as_str()? == "text" {
-                                            item.get("text")?.as_str().map(|s| s.to_string())
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                    .collect::<Vec<_>>()
-                                    .join("")
-                            })
-                        }
-                    })
-                    .collect::<Vec<_>>()
-                    .join("|")
-            })
-            .unwrap_or_default();
-
-        // 空消息时使用随机 UUID，避免所有空请求共享同一 session
-        if content.is_empty() {
-            return Uuid::new_v4().to_string();
-        }
+    /// 按控制头 / 工作区映射应用 Profile 预设，返回 None 表示未命中或无需改写
+    fn apply_preset(
+        profile: &ProfileSettings,
+        target: TransformTarget,
+        headers: &HyperHeaderMap,
+        body: &[u8],
+    ) -> Option<Vec<u8>> {
+        let (name, preset) = profile.presets.select(headers)?;
+        tracing::debug!("AMP Code 预设: {} ({:?})", name, target);
+        presets::apply(target, preset, body)
+    }
 
-        let mut hasher = Sha256::new();
-        hasher.update(&content);
-        let hash = format!("{:x}", hasher.finalize());
+    /// 当前请求的回复语言：命中预设的设置优先，其次 Profile 设置
+    fn response_language(profile: &ProfileSettings, headers: &HyperHeaderMap) -> Option<String> {
+        profile
+            .presets
+            .select(headers)
+            .and_then(|(_, preset)| preset.response_language.clone())
+            .or_else(|| profile.response_language.clone())
+    }
 
-        // 转 UUID 格式：8-4-4-4-12
-        format!(
-            "{}-{}-{}-{}-{}",
-            &hash[0..8],
-            &hash[8..12],
-            &hash[12..16],
-            &hash[16..20],
-            &hash[20..32]
-        )
+    /// Codex / Gemini 路由的回复语言约束（Claude 在身份层注入）
+    fn apply_response_language(
+        profile: &ProfileSettings,
+        target: TransformTarget,
+        headers: &HyperHeaderMap,
+        body: &[u8],
+    ) -> Option<Vec<u8>> {
+        let language = Self::response_language(profile, headers)?;
+        let instruction = presets::PresetSettings {
+            system: Some(presets::language_instruction(&language)),
+            ..Default::default()
+        };
+        presets::apply(target, &instruction, body)
     }
 
-    /// 注入 metadata.user_id 并保持字段顺序
-    fn inject_metadata_with_order(body: &[u8], user_id: &str) -> Result<Vec<u8>> {
-        let json: Value = serde_json::from_slice(body)?;
-        let obj = json
-            .as_object()
-            .ok_or_else(|| anyhow!("请求体不是 JSON 对象"))?;
-
-        // 定义字段顺序（官方顺序）
-        let field_order = [
-            "model",
-            "system",
-            "messages",
-            "tools",
-            "metadata",
-            "max_tokens",
-            "temperature",
-            "top_p",
-            "top_k",
-            "thinking",
-            "stream",
-        ];
-
-        let mut ordered: Map<String, Value> = Map::new();
-
-        // 按顺序插入已有字段
-        for &key in &field_order {
-            if let Some(val) = obj.get(key) {
-                if key == "metadata" {
-                    // 注入 user_id 到 metadata
-                    let mut meta = val.as_object().cloned().unwrap_or_default();
-                    if !meta.contains_key("user_id") {
-                        meta.insert("user_id".into(), json!(user_id));
-                    }
-                    ordered.insert(key.into(), Value::Object(meta));
-                } else {
-                    ordered.insert(key.into(), val.clone());
-                }
-            } else if key == "metadata" {
-                // metadata 不存在则创建
-                ordered.insert(key.into(), json!({ "user_id": user_id }));
+    /// 按 Profile 配置设置 OpenAI-Organization / OpenAI-Project（覆盖客户端传入值）
+    fn apply_openai_org_headers(
+        profile: &ProfileSettings,
+        headers: &mut HyperHeaderMap,
+    ) -> Result<()> {
+        for (name, value) in [
+            ("openai-organization", &profile.openai_organization),
+            ("openai-project", &profile.openai_project),
+        ] {
+            if let Some(v) = value.as_deref().filter(|v| !v.is_empty()) {
+                headers.insert(name, v.parse().map_err(|_| anyhow!("{} 含非法字符", name))?);
             }
         }
+        Ok(())
+    }
 
-        // 保留其他未知字段（放末尾）
-        for (k, v) in obj.iter() {
-            if !ordered.contains_key(k) {
-                ordered.insert(k.clone(), v.clone());
+    /// 写入 Profile 固定请求头（最后一步，覆盖同名头；连接相关头不允许设置）
+    fn apply_static_headers(profile: &ProfileSettings, headers: &mut HyperHeaderMap) -> Result<()> {
+        for (name, value) in &profile.static_headers {
+            let lower = name.to_ascii_lowercase();
+            if matches!(
+                lower.as_str(),
+                "host" | "content-length" | "transfer-encoding" | "connection"
+            ) {
+                tracing::warn!("忽略固定请求头 {}：不允许覆盖连接相关头", name);
+                continue;
             }
+            let header_name = hyper::header::HeaderName::from_bytes(lower.as_bytes())
+                .map_err(|_| anyhow!("固定请求头名非法: {}", name))?;
+            headers.insert(
+                header_name,
+                value
+                    .parse()
+                    .map_err(|_| anyhow!("固定请求头 {} 的值含非法字符", name))?,
+            );
         }
+        Ok(())
+    }
 
-        Ok(serde_json::to_vec(&ordered)?)
+    /// 路由无可用 Profile：启用维护应答时返回本地说明消息，否则报错
+    fn unavailable(
+        settings: &ProcessorSettings,
+        api_type: ApiType,
+        label: &str,
+        path: &str,
+        query: Option<&str>,
+        body: &[u8],
+    ) -> Result<ProcessOutcome> {
+        if !settings.maintenance.enabled {
+            let message = format!("{} Profile 未配置或暂不可用", label);
+            dashboard::record_error(api_type.route_name(), &message);
+            return Err(anyhow!(message));
+        }
+        let target = api_type
+            .transform_target()
+            .expect("unavailable 仅用于 LLM 路由");
+        tracing::warn!("AMP Code → {}: 无可用 Profile，返回维护应答", label);
+        Ok(maintenance::respond(
+            &settings.maintenance,
+            target,
+            api_type.route_name(),
+            path,
+            query,
+            body,
+        ))
     }
-}
 
-/// DuckDuckGo 搜索结果
-struct DuckDuckGoResult {
-    title: String,
-    url: String,
-    snippet: String,
+    /// 检查 Profile 每日出站上限并记录本次发送字节（接收字节由响应侧补记）
+    fn account_egress(settings: &ProcessorSettings, slot: &str, len: usize) -> Result<()> {
+        let cap = settings.profile(slot).egress_cap_bytes_per_day;
+        bandwidth::check_egress_cap(Subject::Profile(slot), cap, len as u64)?;
+        bandwidth::record(Subject::Profile(slot), len as u64, 0);
+        Ok(())
+    }
+
+    /// 记录一次转发请求，profile 为 Profile 名（Azure 为 azure），与响应侧补记 token 用量的键一致
+    fn record_request(settings: &ProcessorSettings, route: &str, profile: &str) {
+        dashboard::record_request(route, profile);
+        metrics::record_request(route, profile);
+        capacity::record_request(&settings.capacity, route, profile);
+        amp_accounting::record_usage(
+            &amp_accounting::counter_key(route, profile),
+            UsageCounters {
+                requests: 1,
+                ..Default::default()
+            },
+        );
+    }
 }
 
 #[async_trait]
@@ -938,6 +1501,7 @@ impl RequestProcessor for AmpHeadersProcessor {
         "amp-code"
     }
 
+    /// RequestProcessor 只能表示转发请求：本地响应返回错误，完整结果经 process() 取得
     async fn process_outgoing_request(
         &self,
         _base_url: &str,
@@ -947,71 +1511,389 @@ impl RequestProcessor for AmpHeadersProcessor {
         original_headers: &HyperHeaderMap,
         body: &[u8],
     ) -> Result<ProcessedRequest> {
-        // 0. 本地工具拦截：webSearch2 / extractWebPageContent
+        self.process(path, query, original_headers, body)
+            .await?
+            .into_forward()
+    }
+}
+
+#[async_trait]
+impl OutcomeProcessor for AmpHeadersProcessor {
+    async fn process_outcome(
+        &self,
+        _base_url: &str,
+        _api_key: &str,
+        path: &str,
+        query: Option<&str>,
+        original_headers: &HyperHeaderMap,
+        body: &[u8],
+    ) -> Result<ProcessOutcome> {
+        self.process(path, query, original_headers, body).await
+    }
+}
+
+impl AmpHeadersProcessor {
+    /// 处理一个 AMP 请求，返回转发 / 本地响应 / 拒绝（AmpEngine 经 OutcomeProcessor 调用）
+    pub async fn process(
+        &self,
+        path: &str,
+        query: Option<&str>,
+        original_headers: &HyperHeaderMap,
+        body: &[u8],
+    ) -> Result<ProcessOutcome> {
+        // 整个请求使用同一份配置快照，向下传递，不在各环节重复读取
+        let settings = self.state.settings();
+        let trace = Span::root(
+            &settings.telemetry,
+            "process_outgoing_request",
+            original_headers,
+        );
+        trace.attr("http.path", path);
+
+        // 入站鉴权：校验本地签发的虚拟 Key，通过后才会替换为真实 Key
+        let virtual_key =
+            match inbound_auth::check(&settings.inbound_auth, path, original_headers, query) {
+                Ok(key) => key,
+                Err(e) => {
+                    trace.fail(&e.to_string());
+                    return Err(e);
+                }
+            };
+        if let Some(name) = virtual_key.as_deref() {
+            trace.attr("virtual_key", name);
+        }
+        // 虚拟 Key 只用于入站鉴权，请求头与 key 查询参数中的 Key 不得发往上游
+        let stripped = virtual_key.is_some().then(|| {
+            let mut headers = original_headers.clone();
+            let query = inbound_auth::strip_credentials(&mut headers, query);
+            (headers, query)
+        });
+        let (original_headers, query) = match &stripped {
+            Some((headers, query)) => (headers, query.as_deref()),
+            None => (original_headers, query),
+        };
+
+        let mut result = self
+            .process_traced(&settings, path, query, original_headers, body, &trace)
+            .await;
+        match &mut result {
+            // 仅交给上游转发层的请求携带上下文（AMP 内部与本地应答不经过转发层）
+            Ok(outcome) => {
+                if let Some(tag) = outcome.upstream_tag_mut() {
+                    tag.traceparent = trace.traceparent();
+                    // 转发层按虚拟 Key 限流
+                    tag.virtual_key = virtual_key;
+                }
+            }
+            Err(e) => trace.fail(&e.to_string()),
+        }
+        result
+    }
+
+    /// 请求处理主流程（trace 为根 span）
+    async fn process_traced(
+        &self,
+        settings: &ProcessorSettings,
+        path: &str,
+        query: Option<&str>,
+        original_headers: &HyperHeaderMap,
+        body: &[u8],
+        trace: &Span,
+    ) -> Result<ProcessOutcome> {
+        let runtime = self.runtime();
+        self.state.ensure_watching(&runtime);
+        dashboard::ensure_started(&self.state, &runtime);
+        admin_api::ensure_started(&self.state, &runtime);
+        local_corpus::ensure_indexed(&settings.search.local_corpus, &runtime);
+
+        // 0. 本地工具拦截（webSearch2 / extractWebPageContent 及其他本地工具）
         if let Some(tool_name) = Self::detect_local_tool(query) {
             tracing::info!("AMP Code 本地工具: {}", tool_name);
 
             // 获取 Tavily API Key（如果配置了）
-            let tavily_api_key = crate::services::proxy_config_manager::ProxyConfigManager::new()
+            let tavily_api_key = self
+                .state
+                .proxy_config_manager()
                 .ok()
                 .and_then(|mgr| mgr.get_config("amp-code").ok().flatten())
-                .and_then(|cfg| cfg.tavily_api_key);
-
-            return Self::handle_local_tool(tool_name, body, tavily_api_key.as_deref()).await;
+                .and_then(|cfg| secret_store::reveal_opt(cfg.tavily_api_key));
+
+            let span = trace.child("local_tool");
+            span.attr("tool", tool_name);
+            let result = self
+                .handle_local_tool(settings, tool_name, body, tavily_api_key.as_deref())
+                .await;
+            if let Err(e) = &result {
+                span.fail(&e.to_string());
+            }
+            return result;
         }
 
-        let api_type = Self::detect_api_type(path, original_headers, body);
+        let detect = trace.child("detect_route");
+        let decision = routing_rules::evaluate(&settings.routing, path, original_headers, body);
+        let api_type = decision
+            .as_ref()
+            .and_then(|d| d.route.as_deref())
+            .and_then(ApiType::from_route_name)
+            .unwrap_or_else(|| Self::detect_api_type(path, original_headers, body));
         tracing::debug!("AMP Code 路由: path={}, type={:?}", path, api_type);
+        detect.attr("route", api_type.route_name());
+        drop(detect);
+        schema_drift::observe(&settings.schema_drift, api_type.route_name(), path, body);
+        request_schema::enforce(
+            settings.request_validation,
+            api_type.route_name(),
+            &Self::extract_llm_path(path),
+            body,
+        )?;
+
+        // 模拟上游：该槽位开启 mock 时本地构造响应，不经过 Profile 解析与转发
+        let mock = &settings.profile(api_type.route_name()).mock;
+        if let Some(target) = api_type.transform_target().filter(|_| mock.enabled) {
+            tracing::info!("AMP Code → {}: 模拟上游应答", api_type.route_name());
+            return Ok(mock_upstream::respond(
+                mock,
+                target,
+                api_type.route_name(),
+                &Self::extract_llm_path(path),
+                query,
+                body,
+            ));
+        }
 
         if api_type == ApiType::AmpInternal {
-            return Self::forward_to_amp(path, query, original_headers, body).await;
+            let forward = self
+                .forward_to_amp(settings, path, query, original_headers, body)
+                .await?;
+            let toggle = &settings.tool_toggle;
+            if toggle.is_tool_list(query) {
+                if let Some(local) =
+                    tool_toggle::fetch_filtered(&self.state, toggle, &forward).await
+                {
+                    return Ok(local);
+                }
+            }
+            let poll_cache = &settings.amp_poll_cache;
+            if poll_cache.is_cacheable(path, body) {
+                if let Some(local) =
+                    amp_poll_cache::fetch_coalesced(&self.state, poll_cache, &forward).await
+                {
+                    return Ok(local);
+                }
+            }
+            // 交给转发层发送（401 刷新重发、重试、日志等），ampcode.com 无备用上游
+            return Ok(upstream::tag(forward, "amp", "amp", ""));
         }
 
-        // LLM 请求 → 用户配置的 Profile
-        let profile_mgr =
-            ProfileManager::new().map_err(|e| anyhow!("ProfileManager 初始化失败: {}", e))?;
+        if api_type == ApiType::AzureOpenAI {
+            return self
+                .forward_to_azure(settings, path, query, original_headers, body)
+                .await;
+        }
 
-        let (claude, codex, gemini) = profile_mgr
-            .resolve_amp_selection()
-            .map_err(|e| anyhow!("Profile 解析失败: {}", e))?;
+        // LLM 请求 → 用户配置的 Profile
+        let (mut claude, mut codex, mut gemini) = self.state.amp_selection()?;
+        let mut llm_path = Self::extract_llm_path(path);
+
+        // 管理 API 的运行时切换替换默认选择；路由规则指定的配置优先，其次按模型名映射
+        let slot = match api_type {
+            ApiType::Claude => &mut claude,
+            ApiType::Codex => &mut codex,
+            _ => &mut gemini,
+        };
+        if let Some(profile_id) = self.state.profile_override(api_type.route_name()) {
+            self.override_profile(slot, "管理 API 切换", &profile_id);
+        }
+        if let Some(d) = decision.as_ref() {
+            if let Some(profile_id) = d.profile.as_deref() {
+                self.override_profile(slot, &format!("路由规则 {}", d.rule), profile_id);
+            }
+        }
+        let model = routing_rules::request_model(path, body);
+        let slot_settings = settings.profile(api_type.route_name());
+        if decision.as_ref().and_then(|d| d.profile.as_ref()).is_none() {
+            if let Some((pattern, profile_id)) = model
+                .as_deref()
+                .and_then(|m| routing_rules::model_profile(&slot_settings.model_profiles, m))
+            {
+                self.override_profile(slot, &format!("模型映射 {}", pattern), profile_id);
+            }
+        }
+        // 只解析本请求使用的 Profile：其他槽位的钥匙串条目缺失不影响本请求
+        if let Some(p) = slot.as_mut() {
+            p.api_key = secret_store::reveal_required(&p.api_key, &format!("Profile {}", p.name))?;
+        }
+        model_guard::check(
+            &slot_settings.model_guard,
+            api_type.route_name(),
+            model.as_deref(),
+            original_headers,
+        )?;
+        let fallback_body;
+        let body = match budget::check(
+            &slot_settings.budget,
+            api_type.route_name(),
+            model.as_deref(),
+        )? {
+            BudgetDecision::Allow => body,
+            // Gemini 模型在路径中，其余在请求体
+            BudgetDecision::Fallback(target) if api_type == ApiType::Gemini => {
+                if let Some(current) = model.as_deref() {
+                    llm_path = llm_path.replace(
+                        &format!("/models/{}", current),
+                        &format!("/models/{}", target),
+                    );
+                }
+                body
+            }
+            BudgetDecision::Fallback(target) => {
+                fallback_body = budget::with_model(body, &target)?;
+                &fallback_body[..]
+            }
+        };
+        let transforms = settings.transforms_for(self.tool_id());
+        let versions = self.state.versions();
 
-        let llm_path = Self::extract_llm_path(path);
+        let route = api_type.route_name();
+        amp_session::capture(&settings.amp_header_capture, route, route, original_headers);
+        let rewrite = trace.child("rewrite_body");
+        rewrite.attr("route", route);
+        rewrite.attr("body.bytes", body.len() as u64);
 
         match api_type {
             ApiType::Claude => {
-                let p = claude.ok_or_else(|| anyhow!("未配置 Claude Profile"))?;
-                tracing::info!("AMP Code → Claude: {}{}", p.base_url, llm_path);
-                let prefixed_body = Self::add_tool_prefix(body);
-
-                // 检查并注入 metadata.user_id
-                let final_body = if let Ok(json) = serde_json::from_slice::<Value>(&prefixed_body) {
-                    let has_user_id = json
-                        .get("metadata")
-                        .and_then(|m| m.get("user_id"))
-                        .and_then(|u| u.as_str())
-                        .map(|s| !s.is_empty())
-                        .unwrap_or(false);
-
-                    if has_user_id {
-                        // 已有 user_id，保持原样
-                        prefixed_body
-                    } else {
-                        // 生成 user_id: user_{64位hex}_account__session_{uuid}
-                        let user_hash = Self::generate_user_hash(original_headers, &p.api_key);
-                        let session_uuid = Self::generate_session_uuid(&json["messages"]);
-                        let user_id =
-                            format!("user_{}_account__session_{}", user_hash, session_uuid);
-
-                        tracing::debug!("AMP Code 生成 user_id: {}", user_id);
-
-                        // 注入并保持字段顺序
-                        Self::inject_metadata_with_order(&prefixed_body, &user_id)
-                            .unwrap_or(prefixed_body)
-                    }
+                let Some(p) = claude.filter(|_| maintenance::down_remaining("claude").is_none())
+                else {
+                    return Self::unavailable(settings, api_type, "Claude", path, query, body);
+                };
+                tracing::info!(
+                    "AMP Code → Claude: {}",
+                    redact(&format!("{}{}", p.base_url, llm_path))
+                );
+                let batched = match tool_batching::coalesce(&settings.tool_batching, body).await {
+                    BatchOutcome::Superseded(local) => return Ok(*local),
+                    BatchOutcome::Forward(merged) => merged,
+                };
+                let body = batched.as_deref().unwrap_or(body);
+                Self::record_request(settings, "claude", &p.name);
+                let flags = &settings.profile("claude").injections;
+                let language =
+                    Self::response_language(settings.profile("claude"), original_headers);
+                let checker = StageChecker::new(settings.strict_mode, TransformTarget::Claude);
+                let identity = Self::add_tool_prefix(body, flags, language.as_deref());
+                let prefixed_body = checker
+                    .check_stage("identity", body, Some(identity))?
+                    .unwrap_or_else(|| body.to_vec());
+
+                let mut claude_transforms = transforms.clone();
+                claude_transforms.inject_metadata &= flags.metadata;
+                claude_transforms.normalize_cache_control &= flags.cache_control;
+                let fingerprint = user_fingerprint::identity(
+                    settings.profile("claude").opaque_user_id,
+                    &p.name,
+                    &p.api_key,
+                );
+                let transformed = transform_middleware::apply(
+                    &claude_transforms,
+                    TransformTarget::Claude,
+                    original_headers,
+                    &fingerprint,
+                    &prefixed_body,
+                );
+                let final_body = checker
+                    .check_stage("transforms", &prefixed_body, transformed)?
+                    .unwrap_or(prefixed_body);
+                let experimented = Self::apply_experiment(&settings.experiments, &final_body);
+                let final_body = checker
+                    .check_stage("experiment", &final_body, experimented)?
+                    .unwrap_or(final_body);
+                let project = original_headers
+                    .get(settings.memory.project_header.as_str())
+                    .and_then(|v| v.to_str().ok());
+                let with_memory = memory_store::inject(&settings.memory, project, &final_body);
+                let final_body = checker
+                    .check_stage("memory", &final_body, with_memory)?
+                    .unwrap_or(final_body);
+                let with_preset = Self::apply_preset(
+                    settings.profile("claude"),
+                    TransformTarget::Claude,
+                    original_headers,
+                    &final_body,
+                );
+                let final_body = checker
+                    .check_stage("preset", &final_body, with_preset)?
+                    .unwrap_or(final_body);
+                let react_shim = settings.profile("claude").react_shim;
+                let shimmed = if react_shim {
+                    react_shim::apply_request(&final_body)
                 } else {
-                    prefixed_body
+                    None
                 };
-
+                let final_body = checker
+                    .check_stage("react_shim", &final_body, shimmed)?
+                    .unwrap_or(final_body);
+                let sampled = sampling_policy::apply(
+                    &settings.profile("claude").sampling,
+                    TransformTarget::Claude,
+                    None,
+                    &final_body,
+                );
+                let final_body = checker
+                    .check_stage("sampling", &final_body, sampled)?
+                    .unwrap_or(final_body);
+                let session_id = original_headers
+                    .get(settings.amp_header_capture.session_header.as_str())
+                    .and_then(|v| v.to_str().ok());
+                cache_diff::record(&settings.cache_diff, session_id, &final_body);
+
+                Self::account_egress(settings, "claude", final_body.len())?;
+                if settings.profile("claude").upstream_protocol == UpstreamProtocol::OpenaiChat {
+                    tracing::info!("AMP Code → OpenAI 兼容后端（协议转换）: {}", p.base_url);
+                    let mut result = openai_translate::build_request(
+                        &p.base_url,
+                        &p.api_key,
+                        openai_translate::translate_request(&final_body)?,
+                    )?;
+                    Self::apply_static_headers(settings.profile("claude"), &mut result.headers)?;
+                    let plan = ResponsePlan {
+                        target: Some(TransformTarget::Claude),
+                        conversion: Conversion::OpenAiChat,
+                        react_shim,
+                        ..Default::default()
+                    };
+                    return Ok(upstream::tag_planned(
+                        result,
+                        "claude",
+                        &p.name,
+                        &p.base_url,
+                        plan,
+                    ));
+                }
+                // Bedrock 请求已签名，后续不再改写请求头
+                if let Some(bedrock) = settings.bedrock.get(&p.name).filter(|b| b.is_configured()) {
+                    let mut result = BedrockHeadersProcessor {
+                        settings: bedrock.clone(),
+                    }
+                    .process_outgoing_request(
+                        &p.base_url,
+                        &p.api_key,
+                        &llm_path,
+                        query,
+                        original_headers,
+                        &final_body,
+                    )
+                    .await?;
+                    // 不参与签名的附加头可安全写入
+                    Self::apply_static_headers(settings.profile("claude"), &mut result.headers)?;
+                    let plan = ResponsePlan {
+                        target: Some(TransformTarget::Claude),
+                        conversion: Conversion::Bedrock,
+                        react_shim,
+                        ..Default::default()
+                    };
+                    // 已签名请求无法改写到备用上游，上游地址留空即不参与故障转移
+                    return Ok(upstream::tag_planned(result, "claude", &p.name, "", plan));
+                }
                 let mut result = ClaudeHeadersProcessor
                     .process_outgoing_request(
                         &p.base_url,
@@ -1032,20 +1914,24 @@ impl RequestProcessor for AmpHeadersProcessor {
                 for key in amp_headers {
                     result.headers.remove(&key);
                 }
+                upstream::strip_control_headers(&mut result.headers);
 
                 result.headers.remove("content-length");
                 result.headers.remove("transfer-encoding");
 
-                result.headers.insert(
-                    "user-agent",
-                    Self::get_user_agent(api_type, path, body).parse().unwrap(),
-                );
-                result.headers.insert("x-app", "cli".parse().unwrap());
+                if flags.user_agent {
+                    result.headers.insert(
+                        "user-agent",
+                        Self::get_user_agent(&versions, api_type, path, body)?,
+                    );
+                    if let Ok(x_app) = versions.x_app.parse() {
+                        result.headers.insert("x-app", x_app);
+                    }
+                }
+                anthropic_version::apply("claude", settings.profile("claude"), &mut result.headers);
 
                 // 保留调用方传入的 anthropic-beta，同时确保必需 beta 存在（对齐 JS 插件行为）
-                {
-                    const REQUIRED_BETAS: [&str; 2] =
-                        ["oauth-2025-04-20", "interleaved-thinking-2025-05-14"];
+                if flags.beta_merge {
                     let incoming = result
                         .headers
                         .get("anthropic-beta")
@@ -1060,30 +1946,50 @@ impl RequestProcessor for AmpHeadersProcessor {
                     {
                         betas.insert(b.to_string());
                     }
-                    for b in REQUIRED_BETAS {
-                        betas.insert(b.to_string());
+                    for b in &versions.required_betas {
+                        betas.insert(b.clone());
                     }
 
                     if !betas.is_empty() {
                         let merged = betas.into_iter().collect::<Vec<_>>().join(",");
-                        result
-                            .headers
-                            .insert("anthropic-beta", merged.parse().unwrap());
+                        let merged =
+                            hyper::header::HeaderValue::from_str(&merged).map_err(|_| {
+                                anyhow!("anthropic-beta 不是合法的请求头值: {:?}", merged)
+                            })?;
+                        result.headers.insert("anthropic-beta", merged);
                     }
                 }
 
-                if !result.target_url.contains("beta=true") {
+                if flags.beta_query
+                    && versions.beta_query
+                    && !result.target_url.contains("beta=true")
+                {
                     if result.target_url.contains('?') {
                         result.target_url.push_str("&beta=true");
                     } else {
                         result.target_url.push_str("?beta=true");
                     }
                 }
-
-                Ok(result)
+                Self::apply_static_headers(settings.profile("claude"), &mut result.headers)?;
+                let plan = ResponsePlan {
+                    target: Some(TransformTarget::Claude),
+                    react_shim,
+                    ..Default::default()
+                };
+                Ok(upstream::tag_planned(
+                    result,
+                    "claude",
+                    &p.name,
+                    &p.base_url,
+                    plan,
+                ))
             }
             ApiType::Codex => {
-                let p = codex.ok_or_else(|| anyhow!("未配置 Codex Profile"))?;
+                let Some(p) = codex.filter(|_| maintenance::down_remaining("codex").is_none())
+                else {
+                    return Self::unavailable(settings, api_type, "Codex", path, query, body);
+                };
+                Self::record_request(settings, "codex", &p.name);
                 let cleaned_body = if body.is_empty() {
                     None
                 } else {
@@ -1158,7 +2064,75 @@ impl RequestProcessor for AmpHeadersProcessor {
                         Err(_) => None,
                     }
                 };
+                let cleaned_body = transform_middleware::apply(
+                    &transforms,
+                    TransformTarget::Codex,
+                    original_headers,
+                    &p.api_key,
+                    cleaned_body.as_deref().unwrap_or(body),
+                )
+                .or(cleaned_body);
+                let cleaned_body = debug_capture::inject_request_fields(
+                    settings.profile("codex"),
+                    TransformTarget::Codex,
+                    &llm_path,
+                    cleaned_body.as_deref().unwrap_or(body),
+                )
+                .or(cleaned_body);
+                let cleaned_body = Self::apply_preset(
+                    settings.profile("codex"),
+                    TransformTarget::Codex,
+                    original_headers,
+                    cleaned_body.as_deref().unwrap_or(body),
+                )
+                .or(cleaned_body);
+                let cleaned_body = Self::apply_response_language(
+                    settings.profile("codex"),
+                    TransformTarget::Codex,
+                    original_headers,
+                    cleaned_body.as_deref().unwrap_or(body),
+                )
+                .or(cleaned_body);
+                let cleaned_body = sampling_policy::apply(
+                    &settings.profile("codex").sampling,
+                    TransformTarget::Codex,
+                    None,
+                    cleaned_body.as_deref().unwrap_or(body),
+                )
+                .or(cleaned_body);
                 let body_to_forward: &[u8] = cleaned_body.as_deref().unwrap_or(body);
+                if let Some(out) = cleaned_body.as_deref() {
+                    StageChecker::new(settings.strict_mode, TransformTarget::Codex)
+                        .check("pipeline", body, out)?;
+                }
+                Self::account_egress(settings, "codex", body_to_forward.len())?;
+                if settings.profile("codex").upstream_protocol == UpstreamProtocol::OpenaiChat
+                    && responses_downgrade::applies_to(&llm_path)
+                {
+                    tracing::info!(
+                        "AMP Code → Codex（Responses 降级为 chat/completions）: {}",
+                        p.base_url
+                    );
+                    let mut result = openai_translate::build_request(
+                        &p.base_url,
+                        &p.api_key,
+                        responses_downgrade::translate_request(body_to_forward)?,
+                    )?;
+                    Self::apply_openai_org_headers(settings.profile("codex"), &mut result.headers)?;
+                    Self::apply_static_headers(settings.profile("codex"), &mut result.headers)?;
+                    let plan = ResponsePlan {
+                        target: Some(TransformTarget::Codex),
+                        conversion: Conversion::ResponsesDowngrade,
+                        ..Default::default()
+                    };
+                    return Ok(upstream::tag_planned(
+                        result,
+                        "codex",
+                        &p.name,
+                        &p.base_url,
+                        plan,
+                    ));
+                }
                 let mut result = CodexHeadersProcessor
                     .process_outgoing_request(
                         &p.base_url,
@@ -1173,33 +2147,128 @@ impl RequestProcessor for AmpHeadersProcessor {
                     result.headers.remove("content-length");
                     result.headers.remove("transfer-encoding");
                 }
-                tracing::info!("AMP Code → Codex: {}", result.target_url);
-                result.headers.insert(
-                    "user-agent",
-                    Self::get_user_agent(api_type, path, body).parse().unwrap(),
-                );
-                Ok(result)
+                upstream::strip_control_headers(&mut result.headers);
+                Self::apply_openai_org_headers(settings.profile("codex"), &mut result.headers)?;
+                tracing::info!("AMP Code → Codex: {}", redact(&result.target_url));
+                if settings.profile("codex").injections.user_agent {
+                    result.headers.insert(
+                        "user-agent",
+                        Self::get_user_agent(&versions, api_type, path, body)?,
+                    );
+                }
+                Self::apply_static_headers(settings.profile("codex"), &mut result.headers)?;
+                let plan = ResponsePlan {
+                    target: Some(TransformTarget::Codex),
+                    ..Default::default()
+                };
+                Ok(upstream::tag_planned(
+                    result,
+                    "codex",
+                    &p.name,
+                    &p.base_url,
+                    plan,
+                ))
             }
             ApiType::Gemini => {
-                let p = gemini.ok_or_else(|| anyhow!("未配置 Gemini Profile"))?;
-                tracing::info!("AMP Code → Gemini: {}{}", p.base_url, llm_path);
+                let Some(p) = gemini.filter(|_| maintenance::down_remaining("gemini").is_none())
+                else {
+                    return Self::unavailable(settings, api_type, "Gemini", path, query, body);
+                };
+                let gemini_settings = settings.profile("gemini");
+                let gemini_preset = gemini_settings.presets.select(original_headers);
+                let gemini_path = transform_middleware::apply_model_alias_to_path(
+                    &llm_path,
+                    &transforms.model_aliases,
+                );
+                let gemini_path = match gemini_preset {
+                    Some((_, preset)) => presets::apply_to_gemini_path(preset, &gemini_path),
+                    None => gemini_path,
+                };
+                tracing::info!(
+                    "AMP Code → Gemini: {}",
+                    redact(&format!("{}{}", p.base_url, gemini_path))
+                );
+                Self::record_request(settings, "gemini", &p.name);
+                let preset_body = gemini_preset
+                    .and_then(|(_, preset)| presets::apply(TransformTarget::Gemini, preset, body));
+                let transformed = transform_middleware::apply(
+                    &transforms,
+                    TransformTarget::Gemini,
+                    original_headers,
+                    &p.api_key,
+                    preset_body.as_deref().unwrap_or(body),
+                )
+                .or(preset_body);
+                let debug_body = debug_capture::inject_request_fields(
+                    gemini_settings,
+                    TransformTarget::Gemini,
+                    &gemini_path,
+                    transformed.as_deref().unwrap_or(body),
+                )
+                .or(transformed);
+                let debug_body = Self::apply_response_language(
+                    gemini_settings,
+                    TransformTarget::Gemini,
+                    original_headers,
+                    debug_body.as_deref().unwrap_or(body),
+                )
+                .or(debug_body);
+                let gemini_model = Self::extract_model_name(&gemini_path, body);
+                let gemini_body = sampling_policy::apply(
+                    &gemini_settings.sampling,
+                    TransformTarget::Gemini,
+                    Some(&gemini_model),
+                    debug_body.as_deref().unwrap_or(body),
+                )
+                .or(debug_body);
+                if let Some(out) = gemini_body.as_deref() {
+                    StageChecker::new(settings.strict_mode, TransformTarget::Gemini)
+                        .check("pipeline", body, out)?;
+                }
+                Self::account_egress(
+                    settings,
+                    "gemini",
+                    gemini_body.as_deref().unwrap_or(body).len(),
+                )?;
                 let mut result = GeminiHeadersProcessor
                     .process_outgoing_request(
                         &p.base_url,
                         &p.api_key,
-                        &llm_path,
+                        &gemini_path,
                         query,
                         original_headers,
-                        body,
+                        gemini_body.as_deref().unwrap_or(body),
                     )
                     .await?;
-                result.headers.insert(
-                    "user-agent",
-                    Self::get_user_agent(api_type, path, body).parse().unwrap(),
-                );
-                Ok(result)
+                if gemini_body.is_some() {
+                    result.headers.remove("content-length");
+                    result.headers.remove("transfer-encoding");
+                }
+                upstream::strip_control_headers(&mut result.headers);
+                if gemini_settings.injections.user_agent {
+                    result.headers.insert(
+                        "user-agent",
+                        Self::get_user_agent(&versions, api_type, path, body)?,
+                    );
+                }
+                Self::apply_static_headers(gemini_settings, &mut result.headers)?;
+                let plan = ResponsePlan {
+                    target: Some(TransformTarget::Gemini),
+                    gemini_stream: gemini_path
+                        .to_lowercase()
+                        .contains(":streamgeneratecontent")
+                        .then(|| gemini_stream::expected_format(query)),
+                    ..Default::default()
+                };
+                Ok(upstream::tag_planned(
+                    result,
+                    "gemini",
+                    &p.name,
+                    &p.base_url,
+                    plan,
+                ))
             }
-            ApiType::AmpInternal => unreachable!(),
+            ApiType::AmpInternal | ApiType::AzureOpenAI => unreachable!(),
         }
     }
 }
@@ -0,0 +1,155 @@
+// AMP 会话元数据
+//
+// 处理器会剥离全部 x-amp-* 请求头。剥离前按配置采集部分值（线程 ID、Agent 模式等），
+// 写入进程内会话表与审计日志（kind=amp_headers），便于将请求关联到 AMP 线程。
+// 会话以 session_header（默认 x-amp-thread-id）的值为键，无该头时只写审计日志。
+// 会话同时写入持久化后端（storage 命名空间 amp/sessions），重启后可按 ID 查询。
+
+use super::audit_log::{self, AuditRecord};
+use super::storage;
+use hyper::HeaderMap as HyperHeaderMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// 会话表上限，超出时淘汰最久未活动的会话
+const MAX_SESSIONS: usize = 1000;
+/// 单个头值保留的最大长度
+const MAX_VALUE_LEN: usize = 256;
+const SESSIONS_NAMESPACE: &str = "amp/sessions";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderCaptureSettings {
+    pub enabled: bool,
+    /// 需要采集的请求头（小写）
+    pub headers: Vec<String>,
+    /// 作为会话键的请求头
+    pub session_header: String,
+}
+
+impl Default for HeaderCaptureSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            headers: vec![
+                "x-amp-thread-id".to_string(),
+                "x-amp-agent-mode".to_string(),
+                "x-amp-client-version".to_string(),
+            ],
+            session_header: "x-amp-thread-id".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SessionMeta {
+    pub session_id: String,
+    pub first_seen_ms: u64,
+    pub last_seen_ms: u64,
+    pub request_count: u64,
+    /// 最近一次采集到的头值
+    pub fields: Map<String, Value>,
+}
+
+static SESSIONS: Lazy<Mutex<HashMap<String, SessionMeta>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 采集配置中的 x-amp-* 头（须在剥离前调用）
+pub(crate) fn capture(
+    settings: &HeaderCaptureSettings,
+    route: &str,
+    profile: &str,
+    headers: &HyperHeaderMap,
+) {
+    if !settings.enabled || settings.headers.is_empty() {
+        return;
+    }
+
+    let mut fields = Map::new();
+    for name in &settings.headers {
+        if let Some(value) = headers.get(name.as_str()).and_then(|v| v.to_str().ok()) {
+            let value: String = value.chars().take(MAX_VALUE_LEN).collect();
+            fields.insert(name.clone(), Value::String(value));
+        }
+    }
+    if fields.is_empty() {
+        return;
+    }
+
+    let session_id = headers
+        .get(settings.session_header.as_str())
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+    if let Some(id) = &session_id {
+        touch(id, &fields);
+    }
+
+    let mut record = AuditRecord::new("amp_headers", route, profile);
+    record.data = fields;
+    audit_log::append(&record);
+}
+
+fn touch(session_id: &str, fields: &Map<String, Value>) {
+    let now = audit_log::now_ms();
+    let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+
+    if !sessions.contains_key(session_id) && sessions.len() >= MAX_SESSIONS {
+        if let Some(oldest) = sessions
+            .values()
+            .min_by_key(|s| s.last_seen_ms)
+            .map(|s| s.session_id.clone())
+        {
+            sessions.remove(&oldest);
+            if let Err(e) = storage::shared().delete(SESSIONS_NAMESPACE, &oldest) {
+                tracing::debug!("删除过期会话失败: {}", e);
+            }
+        }
+    }
+
+    let entry = sessions
+        .entry(session_id.to_string())
+        .or_insert_with(|| SessionMeta {
+            session_id: session_id.to_string(),
+            first_seen_ms: now,
+            last_seen_ms: now,
+            request_count: 0,
+            fields: Map::new(),
+        });
+    entry.last_seen_ms = now;
+    entry.request_count += 1;
+    entry
+        .fields
+        .extend(fields.iter().map(|(k, v)| (k.clone(), v.clone())));
+    if let Err(e) = storage::put_json(SESSIONS_NAMESPACE, session_id, &*entry) {
+        tracing::debug!("会话持久化失败: {}", e);
+    }
+}
+
+/// 进程内未命中时查持久化后端
+pub(crate) fn get(session_id: &str) -> Option<SessionMeta> {
+    let cached = SESSIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(session_id)
+        .cloned();
+    cached.or_else(|| {
+        storage::get_json(SESSIONS_NAMESPACE, session_id)
+            .ok()
+            .flatten()
+    })
+}
+
+/// 全部会话，按最近活动倒序
+pub(crate) fn list() -> Vec<SessionMeta> {
+    let mut list: Vec<_> = SESSIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .values()
+        .cloned()
+        .collect();
+    list.sort_by_key(|s| std::cmp::Reverse(s.last_seen_ms));
+    list
+}
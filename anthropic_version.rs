@@ -0,0 +1,183 @@
+// anthropic-version 协商
+//
+// - Profile 固定版本：覆盖客户端传入的 anthropic-version
+// - 自动降级：后端返回"不支持的版本"错误后，记住该 Profile 的降级版本，
+//   后续请求直接使用（进程内有效，重启后重新探测）
+
+use super::processor_settings::ProfileSettings;
+use hyper::HeaderMap as HyperHeaderMap;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// 已知版本（新 → 旧），降级时依次尝试
+const KNOWN_VERSIONS: [&str; 2] = ["2023-06-01", "2023-01-01"];
+
+/// Profile → 降级后的版本
+static DOWNGRADES: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 按配置与降级记录改写 anthropic-version 头
+pub(crate) fn apply(profile_key: &str, settings: &ProfileSettings, headers: &mut HyperHeaderMap) {
+    let downgraded = if settings.anthropic_version_auto_downgrade {
+        DOWNGRADES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(profile_key)
+            .cloned()
+    } else {
+        None
+    };
+
+    let Some(version) = downgraded.or_else(|| settings.anthropic_version.clone()) else {
+        return;
+    };
+
+    match version.parse() {
+        Ok(v) => {
+            headers.insert("anthropic-version", v);
+        }
+        Err(_) => tracing::warn!("anthropic-version 配置非法: {}", version),
+    }
+}
+
+/// 响应侧调用：识别版本不支持错误并记录降级，返回 true 表示已降级（调用方可重试一次）
+pub(crate) fn note_rejection(
+    profile_key: &str,
+    settings: &ProfileSettings,
+    sent_version: Option<&str>,
+    status: u16,
+    body: &[u8],
+) -> bool {
+    if !settings.anthropic_version_auto_downgrade || status != 400 {
+        return false;
+    }
+    if !is_version_rejection(body) {
+        return false;
+    }
+
+    let current = sent_version.unwrap_or(KNOWN_VERSIONS[0]);
+    let Some(next) = next_older_version(current) else {
+        tracing::warn!(
+            "Profile {} 拒绝 anthropic-version {}，已无更旧版本可降级",
+            profile_key,
+            current
+        );
+        return false;
+    };
+
+    tracing::warn!(
+        "Profile {} 不支持 anthropic-version {}，降级为 {}",
+        profile_key,
+        current,
+        next
+    );
+    DOWNGRADES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(profile_key.to_string(), next.to_string());
+    true
+}
+
+/// 清除降级记录（Profile 配置变更后调用）
+pub(crate) fn reset(profile_key: &str) {
+    DOWNGRADES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(profile_key);
+}
+
+fn is_version_rejection(body: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(body).to_lowercase();
+    text.contains("anthropic-version")
+        && (text.contains("invalid")
+            || text.contains("unsupported")
+            || text.contains("not supported")
+            || text.contains("unknown"))
+}
+
+fn next_older_version(current: &str) -> Option<&'static str> {
+    // 未知版本（通常更新）从最新已知版本开始尝试
+    match KNOWN_VERSIONS.iter().position(|v| *v == current) {
+        Some(idx) => KNOWN_VERSIONS.get(idx + 1).copied(),
+        None => Some(KNOWN_VERSIONS[0]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auto_downgrade() -> ProfileSettings {
+        ProfileSettings {
+            anthropic_version_auto_downgrade: true,
+            ..Default::default()
+        }
+    }
+
+    const REJECTION: &[u8] =
+        br#"{"error":{"message":"anthropic-version: 2023-06-01 is not supported"}}"#;
+
+    #[test]
+    fn recognises_version_rejection() {
+        assert!(is_version_rejection(REJECTION));
+        assert!(!is_version_rejection(
+            br#"{"error":{"message":"invalid model"}}"#
+        ));
+    }
+
+    #[test]
+    fn next_older_version_walks_known_list() {
+        assert_eq!(next_older_version("2099-01-01"), Some("2023-06-01"));
+        assert_eq!(next_older_version("2023-06-01"), Some("2023-01-01"));
+        assert_eq!(next_older_version("2023-01-01"), None);
+    }
+
+    #[test]
+    fn rejection_records_downgrade_for_profile() {
+        let key = "anthropic-version-test-downgrade";
+        let settings = auto_downgrade();
+        assert!(!note_rejection(
+            key,
+            &settings,
+            Some("2023-06-01"),
+            500,
+            REJECTION
+        ));
+        assert!(note_rejection(
+            key,
+            &settings,
+            Some("2023-06-01"),
+            400,
+            REJECTION
+        ));
+
+        let mut headers = HyperHeaderMap::new();
+        headers.insert("anthropic-version", "2023-06-01".parse().unwrap());
+        apply(key, &settings, &mut headers);
+        assert_eq!(headers["anthropic-version"], "2023-01-01");
+
+        reset(key);
+        let mut headers = HyperHeaderMap::new();
+        apply(key, &settings, &mut headers);
+        assert!(headers.get("anthropic-version").is_none());
+    }
+
+    #[test]
+    fn pinned_version_overrides_client() {
+        let settings = ProfileSettings {
+            anthropic_version: Some("2023-01-01".to_string()),
+            ..Default::default()
+        };
+        let mut headers = HyperHeaderMap::new();
+        headers.insert("anthropic-version", "2023-06-01".parse().unwrap());
+        apply("anthropic-version-test-pinned", &settings, &mut headers);
+        assert_eq!(headers["anthropic-version"], "2023-01-01");
+        assert!(!note_rejection(
+            "anthropic-version-test-pinned",
+            &settings,
+            Some("2023-01-01"),
+            400,
+            REJECTION
+        ));
+    }
+}
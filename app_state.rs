@@ -0,0 +1,515 @@
+// 应用级共享状态（ProfileManager / ProxyConfigManager / 处理器配置与版本清单缓存 / 出站 Client 池）
+//
+// 两个管理器与处理器配置构造时都要读盘解析，原先每个请求（以及路由覆盖、故障转移、本地工具等环节）
+// 各自读取一次。AppState 缓存构造好的实例（首次使用时构造，之后共享 Arc）：
+// - 引擎（见 engine.rs）持有一个 AppState，注入内置处理器、转发层与各后台子系统
+//   （备用链、Token 健康检查、观测面板、管理 API、辅助模型等）；不提供进程级实例，
+//   同一进程内的多个引擎各自使用自己的配置、Client 池、后台子系统与运行时 Profile 切换，
+//   需要时可显式共享同一个 Arc。以下状态仍是进程级的，多个引擎共用：密钥存储（见 secret_store.rs）、
+//   路由不可用标记（maintenance.rs）、入站限流桶（rate_limit.rs）、响应 / 工具缓存、
+//   Token 健康状态、面板最近请求 / 错误、mock 轮流进度、anthropic-version 降级记录
+// - AMP Token 刷新状态（刷新记录缓存、已下发 Token 来源、刷新锁，见 amp_oauth.rs）随实例存放
+// - 热加载：ensure_watching() 启动后台轮询，~/.duckcoding 下 *.json 的修改时间 / 数量变化时
+//   invalidate()，下次使用时重新构造；已取出的旧实例在当前请求内继续有效
+// - 管理端写入配置后可直接调用 invalidate()，不必等待下一次轮询；写入处理器配置的管理操作
+//   （虚拟 Key、配置应用、插件导入等）接收 AppState，经 save_settings() 写入并使缓存失效，
+//   reload_settings() 立即重新读取并在配置有误时返回错误（保留旧配置）
+// - install_settings() 安装代码中构建的处理器配置（AmpConfig，见 amp_config.rs），配置存放在实例上：
+//   此后本实例的 settings / load_settings / save_settings 不再读写配置文件，其他实例不受影响；
+//   use_file() 恢复读写文件。读写处理器配置的管理操作都经 load_settings / save_settings
+// - 客户端版本清单（见 client_versions.rs）同样缓存，随 invalidate() 重新读取覆盖文件；
+//   管理 API 更新 / 重置清单后调用 invalidate_versions()
+// - ProxyConfigManager 构造时透明迁移代理配置中的明文密钥；Profile 存储与桌面端共用，不改写（见 secret_store.rs）
+// - 出站 Client 池（见 outbound.rs）随 AppState 创建与释放，不同实例之间不共享连接
+// - AppState::fixed() 使用给定的处理器配置与 Profile 选择、内置版本清单，不读盘、不轮询（快照测试等）
+
+use super::amp_oauth::OAuthState;
+use super::client_versions::VersionsManifest;
+use super::outbound::Outbound;
+use super::processor_settings::ProcessorSettings;
+use super::secret_store;
+use crate::services::profile_manager::{ProfileData, ProfileManager};
+use crate::services::proxy_config_manager::ProxyConfigManager;
+use anyhow::{anyhow, bail, Result};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+use tokio::runtime::Handle;
+
+/// 配置变化检查间隔
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 配置目录指纹：(*.json 文件数, 最新修改时间)
+type Fingerprint = (usize, Option<SystemTime>);
+
+/// 本模块使用的 Profile 字段（取自 ProfileManager 的 ProfileData）
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SelectedProfile {
+    pub name: String,
+    pub base_url: String,
+    pub api_key: String,
+}
+
+impl From<ProfileData> for SelectedProfile {
+    fn from(p: ProfileData) -> Self {
+        Self {
+            name: p.name,
+            base_url: p.base_url,
+            api_key: p.api_key,
+        }
+    }
+}
+
+/// AMP 各槽位选中的 Profile：(Claude, Codex, Gemini)
+pub(crate) type AmpSelection = (
+    Option<SelectedProfile>,
+    Option<SelectedProfile>,
+    Option<SelectedProfile>,
+);
+
+/// 固定的配置与 Profile 选择
+struct Fixed {
+    settings: Arc<ProcessorSettings>,
+    selection: AmpSelection,
+}
+
+pub struct AppState {
+    fixed: Option<Fixed>,
+    profiles: RwLock<Option<Arc<ProfileManager>>>,
+    proxy_configs: RwLock<Option<Arc<ProxyConfigManager>>>,
+    settings: RwLock<Option<Arc<ProcessorSettings>>>,
+    /// 安装的处理器配置（取代配置文件）
+    installed: RwLock<Option<Arc<ProcessorSettings>>>,
+    versions: RwLock<Option<Arc<VersionsManifest>>>,
+    outbound: Outbound,
+    oauth: OAuthState,
+    watching: AtomicBool,
+    /// 已在本实例上启动的后台子系统（管理 API、观测面板、Token 检查）
+    started: Mutex<HashSet<&'static str>>,
+    /// 管理 API 的运行时 Profile 切换：路由 → ProxyConfigManager 工具 ID
+    profile_overrides: RwLock<HashMap<String, String>>,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        Self {
+            fixed: None,
+            profiles: RwLock::new(None),
+            proxy_configs: RwLock::new(None),
+            settings: RwLock::new(None),
+            installed: RwLock::new(None),
+            versions: RwLock::new(None),
+            outbound: Outbound::default(),
+            oauth: OAuthState::default(),
+            watching: AtomicBool::new(false),
+            started: Mutex::new(HashSet::new()),
+            profile_overrides: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// 使用给定的处理器配置与 Profile 选择（不读取配置文件，也不启动轮询）
+    pub(crate) fn fixed(settings: ProcessorSettings, selection: AmpSelection) -> Self {
+        Self {
+            fixed: Some(Fixed {
+                settings: Arc::new(settings),
+                selection,
+            }),
+            ..Self::new()
+        }
+    }
+
+    pub fn profile_manager(&self) -> Result<Arc<ProfileManager>> {
+        cached(&self.profiles, || {
+            ProfileManager::new().map_err(|e| anyhow!("ProfileManager 初始化失败: {}", e))
+        })
+    }
+
+    /// AMP 各槽位选中的 Profile
+    pub(crate) fn amp_selection(&self) -> Result<AmpSelection> {
+        if let Some(fixed) = &self.fixed {
+            return Ok(fixed.selection.clone());
+        }
+        let (claude, codex, gemini) = self
+            .profile_manager()?
+            .resolve_amp_selection()
+            .map_err(|e| anyhow!("Profile 解析失败: {}", e))?;
+        Ok((
+            claude.map(Into::into),
+            codex.map(Into::into),
+            gemini.map(Into::into),
+        ))
+    }
+
+    /// 代理配置管理器；构造时把本模块读取的代理配置中的明文密钥迁移到密钥存储
+    pub fn proxy_config_manager(&self) -> Result<Arc<ProxyConfigManager>> {
+        cached(&self.proxy_configs, || {
+            let manager = ProxyConfigManager::new()
+                .map_err(|e| anyhow!("ProxyConfigManager 初始化失败: {}", e))?;
+            for tool_id in self.secret_tool_ids() {
+                match secret_store::migrate_proxy_config(&manager, &tool_id) {
+                    Ok(true) => tracing::info!("代理配置 {} 的明文密钥已迁移到密钥存储", tool_id),
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!("{}", e),
+                }
+            }
+            Ok(manager)
+        })
+    }
+
+    /// 本模块读取密钥的代理配置：amp-code 与故障转移 / 模型映射引用的配置
+    fn secret_tool_ids(&self) -> Vec<String> {
+        let settings = self.settings();
+        let mut ids = vec!["amp-code".to_string()];
+        let referenced = settings.failover.chains.values().flatten().chain(
+            settings
+                .profiles
+                .values()
+                .flat_map(|p| p.model_profiles.values()),
+        );
+        for id in referenced {
+            if !ids.contains(id) {
+                ids.push(id.clone());
+            }
+        }
+        ids
+    }
+
+    /// 处理器配置；读取失败时告警并使用默认值（同样缓存，配置修正后由轮询或 reload 失效）
+    pub fn settings(&self) -> Arc<ProcessorSettings> {
+        if let Some(settings) = self.pinned() {
+            return settings;
+        }
+        let loaded: Result<Arc<ProcessorSettings>> =
+            cached(&self.settings, || Ok(ProcessorSettings::load_or_default()));
+        loaded.unwrap_or_default()
+    }
+
+    /// 出站 Client 池
+    pub fn outbound(&self) -> &Outbound {
+        &self.outbound
+    }
+
+    /// AMP Token 刷新状态
+    pub(crate) fn oauth(&self) -> &OAuthState {
+        &self.oauth
+    }
+
+    /// 立即重新读取处理器配置；解析失败时返回错误并保留当前配置
+    pub fn reload_settings(&self) -> Result<Arc<ProcessorSettings>> {
+        if let Some(settings) = self.pinned() {
+            return Ok(settings);
+        }
+        let settings = Arc::new(ProcessorSettings::load()?);
+        *self.settings.write().unwrap_or_else(|e| e.into_inner()) = Some(settings.clone());
+        Ok(settings)
+    }
+
+    /// 读取处理器配置供修改（文件损坏时返回错误）；使用安装的配置时返回其副本
+    pub fn load_settings(&self) -> Result<ProcessorSettings> {
+        match self.pinned() {
+            Some(settings) => Ok((*settings).clone()),
+            None => ProcessorSettings::load(),
+        }
+    }
+
+    /// 写回处理器配置：使用安装的配置时只替换实例上的配置，否则写入配置文件；缓存的配置随之失效
+    pub fn save_settings(&self, settings: &ProcessorSettings) -> Result<()> {
+        if self.fixed.is_some() {
+            bail!("固定配置的 AppState 不支持写入处理器配置");
+        }
+        let in_memory = {
+            let mut installed = self.installed.write().unwrap_or_else(|e| e.into_inner());
+            let in_memory = installed.is_some();
+            if in_memory {
+                *installed = Some(Arc::new(settings.clone()));
+            }
+            in_memory
+        };
+        if !in_memory {
+            settings.save()?;
+        }
+        self.invalidate_settings();
+        Ok(())
+    }
+
+    /// 安装代码中构建的处理器配置，之后本实例不再读写配置文件
+    pub fn install_settings(&self, settings: ProcessorSettings) {
+        *self.installed.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(settings));
+        self.invalidate_settings();
+    }
+
+    /// 移除安装的配置，恢复读写配置文件
+    pub fn use_file(&self) {
+        *self.installed.write().unwrap_or_else(|e| e.into_inner()) = None;
+        self.invalidate_settings();
+    }
+
+    /// 是否使用安装的（或固定的）配置，而不是配置文件
+    pub fn is_in_memory(&self) -> bool {
+        self.pinned().is_some()
+    }
+
+    /// 不来自配置文件的处理器配置：固定配置优先，其次安装的配置
+    fn pinned(&self) -> Option<Arc<ProcessorSettings>> {
+        if let Some(fixed) = &self.fixed {
+            return Some(fixed.settings.clone());
+        }
+        self.installed
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// 客户端版本清单（内置值 + 覆盖文件）；固定配置时只用内置清单
+    pub(crate) fn versions(&self) -> Arc<VersionsManifest> {
+        let loaded: Result<Arc<VersionsManifest>> = cached(&self.versions, || {
+            Ok(match self.fixed {
+                Some(_) => VersionsManifest::default(),
+                None => VersionsManifest::load_or_default(),
+            })
+        });
+        loaded.unwrap_or_default()
+    }
+
+    /// 丢弃缓存的版本清单，下次使用时重新读取
+    pub(crate) fn invalidate_versions(&self) {
+        *self.versions.write().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// 丢弃缓存的处理器配置，下次使用时重新读取
+    pub fn invalidate_settings(&self) {
+        *self.settings.write().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// 丢弃缓存的管理器与处理器配置，下次使用时按磁盘配置重新构造
+    pub fn invalidate(&self) {
+        *self.profiles.write().unwrap_or_else(|e| e.into_inner()) = None;
+        *self
+            .proxy_configs
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = None;
+        self.invalidate_versions();
+        self.invalidate_settings();
+    }
+
+    /// 标记子系统已在本实例上启动；已启动时返回 false
+    pub(crate) fn claim_started(&self, subsystem: &'static str) -> bool {
+        self.started
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(subsystem)
+    }
+
+    /// 子系统启动失败或退出后清除标记，下次可重新启动
+    pub(crate) fn release_started(&self, subsystem: &'static str) {
+        self.started
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(subsystem);
+    }
+
+    /// 路由当前的运行时 Profile 切换
+    pub(crate) fn profile_override(&self, route: &str) -> Option<String> {
+        self.profile_overrides
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(route)
+            .cloned()
+    }
+
+    pub(crate) fn profile_overrides(&self) -> HashMap<String, String> {
+        self.profile_overrides
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// 设置（Some）或清除（None）路由的运行时 Profile 切换
+    pub(crate) fn set_profile_override(&self, route: &str, profile: Option<String>) {
+        let mut overrides = self
+            .profile_overrides
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        match profile {
+            Some(profile) => overrides.insert(route.to_string(), profile),
+            None => overrides.remove(route),
+        };
+    }
+
+    /// 首次调用时在 runtime 上启动配置变化轮询；AppState 释放后轮询自行结束
+    pub fn ensure_watching(self: &Arc<Self>, runtime: &Handle) {
+        if self.fixed.is_some() || self.watching.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let state: Weak<AppState> = Arc::downgrade(self);
+        runtime.spawn(async move {
+            let mut last = fingerprint().await;
+            loop {
+                tokio::time::sleep(WATCH_INTERVAL).await;
+                let Some(state) = state.upgrade() else {
+                    return;
+                };
+                let current = fingerprint().await;
+                if current != last {
+                    tracing::info!("检测到配置文件变化，重新加载 Profile / 代理配置 / 处理器配置");
+                    state.invalidate();
+                    last = current;
+                }
+            }
+        });
+    }
+}
+
+fn cached<T>(slot: &RwLock<Option<Arc<T>>>, init: impl FnOnce() -> Result<T>) -> Result<Arc<T>> {
+    if let Some(value) = slot.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return Ok(value.clone());
+    }
+    let mut guard = slot.write().unwrap_or_else(|e| e.into_inner());
+    if let Some(value) = guard.as_ref() {
+        return Ok(value.clone());
+    }
+    let value = Arc::new(init()?);
+    *guard = Some(value.clone());
+    Ok(value)
+}
+
+fn config_dir() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".duckcoding")
+}
+
+/// 配置目录（含一级子目录）下 *.json 的数量与最新修改时间
+async fn fingerprint() -> Fingerprint {
+    let task = tokio::task::spawn_blocking(|| {
+        let mut count = 0;
+        let mut latest = None;
+        let mut dirs = vec![(config_dir(), 0)];
+        while let Some((dir, depth)) = dirs.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+                if meta.is_dir() {
+                    if depth == 0 {
+                        dirs.push((path, depth + 1));
+                    }
+                } else if path.extension().is_some_and(|e| e == "json") {
+                    count += 1;
+                    latest = latest.max(meta.modified().ok());
+                }
+            }
+        }
+        (count, latest)
+    });
+    task.await.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_cached_until_invalidated() {
+        // 直接预置缓存槽，不读取真实的 ~/.duckcoding
+        let state = AppState::new();
+        let first = Arc::new(ProcessorSettings::default());
+        *state.settings.write().unwrap() = Some(first.clone());
+        assert!(Arc::ptr_eq(&first, &state.settings()));
+        state.invalidate_settings();
+        assert!(state.settings.read().unwrap().is_none());
+        *state.settings.write().unwrap() = Some(first);
+        state.invalidate();
+        assert!(state.settings.read().unwrap().is_none());
+
+        // 槽位为空时才构造
+        let slot = RwLock::new(None);
+        let built = std::cell::Cell::new(0);
+        let build = |value| {
+            built.set(built.get() + 1);
+            Ok(value)
+        };
+        let a = cached(&slot, || build(1)).unwrap();
+        let b = cached(&slot, || build(2)).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!((*b, built.get()), (1, 1));
+    }
+
+    #[test]
+    fn fixed_state_ignores_invalidation() {
+        let profile = SelectedProfile {
+            name: "p".to_string(),
+            ..Default::default()
+        };
+        let state = AppState::fixed(ProcessorSettings::default(), (Some(profile), None, None));
+        let first = state.settings();
+        state.invalidate();
+        assert!(Arc::ptr_eq(&first, &state.settings()));
+        assert!(Arc::ptr_eq(&first, &state.reload_settings().unwrap()));
+        let (claude, codex, _) = state.amp_selection().unwrap();
+        assert_eq!(claude.unwrap().name, "p");
+        assert!(codex.is_none());
+        assert!(state.save_settings(&ProcessorSettings::default()).is_err());
+        assert_eq!(*state.versions(), VersionsManifest::default());
+    }
+
+    #[test]
+    fn installed_settings_stay_on_instance() {
+        // 安装前不调用 settings()，use_file() 之后也不再读取，整个测试不读写 ~/.duckcoding
+        let state = AppState::new();
+        let other = AppState::new();
+        state.install_settings(ProcessorSettings {
+            tools_egress_cap_bytes_per_day: Some(42),
+            ..Default::default()
+        });
+        assert!(state.is_in_memory());
+        assert!(!other.is_in_memory());
+        state.invalidate();
+        assert_eq!(state.settings().tools_egress_cap_bytes_per_day, Some(42));
+
+        // 写回只替换实例上的配置，不写文件
+        let mut changed = state.load_settings().unwrap();
+        changed.tools_egress_cap_bytes_per_day = Some(7);
+        state.save_settings(&changed).unwrap();
+        assert_eq!(state.settings().tools_egress_cap_bytes_per_day, Some(7));
+        assert_eq!(
+            state
+                .reload_settings()
+                .unwrap()
+                .tools_egress_cap_bytes_per_day,
+            Some(7)
+        );
+
+        state.use_file();
+        assert!(!state.is_in_memory());
+    }
+
+    #[test]
+    fn subsystems_and_overrides_are_per_instance() {
+        let state = AppState::fixed(ProcessorSettings::default(), (None, None, None));
+        let other = AppState::fixed(ProcessorSettings::default(), (None, None, None));
+        assert!(state.claim_started("admin_api"));
+        assert!(!state.claim_started("admin_api"));
+        assert!(other.claim_started("admin_api"));
+        state.release_started("admin_api");
+        assert!(state.claim_started("admin_api"));
+
+        state.set_profile_override("claude", Some("backup".to_string()));
+        assert_eq!(state.profile_override("claude").as_deref(), Some("backup"));
+        assert!(other.profile_override("claude").is_none());
+        state.set_profile_override("claude", None);
+        assert!(state.profile_overrides().is_empty());
+    }
+}
@@ -0,0 +1,106 @@
+// AMP Code 审计日志
+//
+// JSON Lines 追加写入 ~/.duckcoding/amp/audit.jsonl，仅供本地排查，不回传客户端。
+// 写入失败只告警，不影响请求转发。
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// 串行化写入，避免多请求并发时行交错
+static WRITE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+pub fn path() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".duckcoding")
+        .join("amp")
+        .join("audit.jsonl")
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Unix 毫秒时间戳
+    pub ts_ms: u64,
+    /// 记录类型，如 "debug"
+    pub kind: String,
+    /// 路由：claude / codex / gemini / amp
+    pub route: String,
+    pub profile: String,
+    pub model: Option<String>,
+    /// 类型相关的附加字段
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub data: Map<String, Value>,
+}
+
+impl AuditRecord {
+    pub fn new(kind: &str, route: &str, profile: &str) -> Self {
+        Self {
+            ts_ms: now_ms(),
+            kind: kind.to_string(),
+            route: route.to_string(),
+            profile: profile.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_model(mut self, model: Option<&str>) -> Self {
+        self.model = model.map(|m| m.to_string());
+        self
+    }
+
+    pub fn with(mut self, key: &str, value: Value) -> Self {
+        self.data.insert(key.to_string(), value);
+        self
+    }
+}
+
+/// 当前时间（确定性模式下为固定值）
+pub fn now_ms() -> u64 {
+    super::determinism::clock().now_ms()
+}
+
+/// 追加一条审计记录
+pub fn append(record: &AuditRecord) {
+    let line = match serde_json::to_string(record) {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::warn!("审计记录序列化失败: {}", e);
+            return;
+        }
+    };
+
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = path();
+    if let Some(dir) = path.parent() {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            tracing::warn!("创建审计日志目录失败: {}", e);
+            return;
+        }
+    }
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| writeln!(f, "{}", line));
+    if let Err(e) = result {
+        tracing::warn!("写入审计日志失败: {}", e);
+    }
+}
+
+/// 逐条读取审计日志（无法解析的行跳过）
+pub fn scan(mut f: impl FnMut(AuditRecord)) {
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let Ok(file) = std::fs::File::open(path()) else {
+        return;
+    };
+    for line in BufReader::new(file).lines().map_while(|l| l.ok()) {
+        if let Ok(record) = serde_json::from_str::<AuditRecord>(&line) {
+            f(record);
+        }
+    }
+}
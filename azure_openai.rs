@@ -0,0 +1,194 @@
+// Azure OpenAI 路由
+//
+// /api/provider/azure/* → Azure OpenAI Profile：
+// - 已是 Azure 形式（/openai/deployments/{dep}/...、/openai/responses）的路径原样转发
+// - OpenAI 形式（/v1/chat/completions 等）改写为 /openai/deployments/{dep}/...，
+//   dep 由 deployments 映射（模型名 → 部署名）得到，未映射时直接使用模型名
+// - /v1/responses 改写为 /openai/responses，请求体 model 替换为部署名
+// - 请求未带 api-version 时追加配置的版本；鉴权使用 api-key 头而非 Bearer
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub(crate) const AZURE_PATH_PREFIX: &str = "/api/provider/azure";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AzureSettings {
+    /// 资源地址，如 https://my-resource.openai.azure.com
+    pub base_url: String,
+    pub api_key: String,
+    /// 请求未指定时使用的 api-version
+    pub api_version: String,
+    /// 模型名 → 部署名
+    pub deployments: HashMap<String, String>,
+}
+
+impl Default for AzureSettings {
+    fn default() -> Self {
+        Self {
+            base_url: String::new(),
+            api_key: String::new(),
+            api_version: "2024-10-21".to_string(),
+            deployments: HashMap::new(),
+        }
+    }
+}
+
+impl AzureSettings {
+    pub(crate) fn is_configured(&self) -> bool {
+        !self.base_url.is_empty() && !self.api_key.is_empty()
+    }
+
+    fn deployment_for<'a>(&'a self, model: &'a str) -> &'a str {
+        self.deployments
+            .get(model)
+            .map(|d| d.as_str())
+            .unwrap_or(model)
+    }
+}
+
+/// 计算目标 URL，必要时改写请求体（返回 Some 表示已改写）
+pub(crate) fn build_target(
+    settings: &AzureSettings,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+) -> Result<(String, Option<Vec<u8>>)> {
+    let rest = path
+        .get(AZURE_PATH_PREFIX.len()..)
+        .filter(|_| path.to_lowercase().starts_with(AZURE_PATH_PREFIX))
+        .unwrap_or(path);
+    let mut json: Option<Value> = serde_json::from_slice(body).ok();
+    let model = json
+        .as_ref()
+        .and_then(|j| j["model"].as_str())
+        .map(|m| m.to_string());
+
+    let mut body_modified = false;
+    let azure_path = if let Some(pos) = rest.find("/openai/") {
+        rest[pos..].to_string()
+    } else {
+        let endpoint = rest.strip_prefix("/v1").unwrap_or(rest);
+        if endpoint.starts_with("/responses") {
+            if let (Some(json), Some(model)) = (json.as_mut(), model.as_deref()) {
+                let deployment = settings.deployment_for(model).to_string();
+                if deployment != model {
+                    json["model"] = Value::String(deployment);
+                    body_modified = true;
+                }
+            }
+            format!("/openai{}", endpoint)
+        } else {
+            let model = model
+                .as_deref()
+                .ok_or_else(|| anyhow!("Azure OpenAI 请求缺少 model，无法确定部署名"))?;
+            format!(
+                "/openai/deployments/{}{}",
+                urlencoding::encode(settings.deployment_for(model)),
+                endpoint
+            )
+        }
+    };
+
+    let has_version = query
+        .map(|q| q.split('&').any(|p| p.starts_with("api-version=")))
+        .unwrap_or(false);
+    let query = match (query.filter(|q| !q.is_empty()), has_version) {
+        (Some(q), true) => q.to_string(),
+        (Some(q), false) => format!("{}&api-version={}", q, settings.api_version),
+        (None, _) => format!("api-version={}", settings.api_version),
+    };
+
+    let target_url = format!(
+        "{}{}?{}",
+        settings.base_url.trim_end_matches('/'),
+        azure_path,
+        query
+    );
+    let new_body = if body_modified {
+        json.and_then(|j| serde_json::to_vec(&j).ok())
+    } else {
+        None
+    };
+    Ok((target_url, new_body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> AzureSettings {
+        AzureSettings {
+            base_url: "https://res.openai.azure.com/".to_string(),
+            api_key: "k".to_string(),
+            deployments: HashMap::from([("gpt-4.1".to_string(), "gpt41 prod".to_string())]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chat_path_uses_mapped_deployment() {
+        let body = br#"{"model":"gpt-4.1","messages":[]}"#;
+        let (url, rewritten) = build_target(
+            &settings(),
+            "/api/provider/azure/v1/chat/completions",
+            None,
+            body,
+        )
+        .unwrap();
+        assert_eq!(
+            url,
+            "https://res.openai.azure.com/openai/deployments/gpt41%20prod/chat/completions?api-version=2024-10-21"
+        );
+        assert!(rewritten.is_none());
+    }
+
+    #[test]
+    fn responses_path_rewrites_model_to_deployment() {
+        let body = br#"{"model":"gpt-4.1","input":"hi"}"#;
+        let (url, rewritten) = build_target(
+            &settings(),
+            "/api/provider/azure/v1/responses",
+            Some("stream=true"),
+            body,
+        )
+        .unwrap();
+        assert_eq!(
+            url,
+            "https://res.openai.azure.com/openai/responses?stream=true&api-version=2024-10-21"
+        );
+        let json: Value = serde_json::from_slice(&rewritten.unwrap()).unwrap();
+        assert_eq!(json["model"], "gpt41 prod");
+    }
+
+    #[test]
+    fn explicit_openai_path_and_version_are_kept() {
+        let (url, _) = build_target(
+            &settings(),
+            "/api/provider/azure/openai/deployments/custom/embeddings",
+            Some("api-version=2025-01-01"),
+            b"{}",
+        )
+        .unwrap();
+        assert_eq!(
+            url,
+            "https://res.openai.azure.com/openai/deployments/custom/embeddings?api-version=2025-01-01"
+        );
+    }
+
+    #[test]
+    fn deployment_path_requires_model() {
+        assert!(build_target(
+            &settings(),
+            "/api/provider/azure/v1/chat/completions",
+            None,
+            b"{}"
+        )
+        .is_err());
+        assert!(!AzureSettings::default().is_configured());
+        assert!(settings().is_configured());
+    }
+}
@@ -0,0 +1,126 @@
+// 流量统计与每日出站上限
+//
+// 按 Profile 槽位与本地工具统计每日收发字节（落在 amp_accounting 中，崩溃安全），
+// 可选按天限制出站字节数，超出后拒绝请求，避免按流量计费的网络超支。
+// 计数键：bandwidth:{YYYY-MM-DD}:profile:{slot} / bandwidth:{YYYY-MM-DD}:tool:{name}
+
+use super::amp_accounting::{self, UsageCounters};
+use super::metrics;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+const KEY_PREFIX: &str = "bandwidth:";
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Subject<'a> {
+    Profile(&'a str),
+    Tool(&'a str),
+}
+
+fn key_for(day: &str, subject: Subject) -> String {
+    match subject {
+        Subject::Profile(slot) => format!("{}{}:profile:{}", KEY_PREFIX, day, slot),
+        Subject::Tool(name) => format!("{}{}:tool:{}", KEY_PREFIX, day, name),
+    }
+}
+
+/// 记录收发字节
+pub(crate) fn record(subject: Subject, bytes_sent: u64, bytes_received: u64) {
+    if bytes_sent == 0 && bytes_received == 0 {
+        return;
+    }
+    match subject {
+        Subject::Profile(slot) => {
+            metrics::record_bytes("profile", slot, bytes_sent, bytes_received)
+        }
+        Subject::Tool(name) => metrics::record_bytes("tool", name, bytes_sent, bytes_received),
+    }
+    amp_accounting::record_usage(
+        &key_for(&amp_accounting::utc_day(), subject),
+        UsageCounters {
+            bytes_sent,
+            bytes_received,
+            ..Default::default()
+        },
+    );
+}
+
+/// 出站前检查当日上限（cap 为 None 表示不限制）
+pub(crate) fn check_egress_cap(subject: Subject, cap: Option<u64>, outgoing: u64) -> Result<()> {
+    let Some(cap) = cap else {
+        return Ok(());
+    };
+    let key = key_for(&amp_accounting::utc_day(), subject);
+    let used = amp_accounting::snapshot_counters()
+        .get(&key)
+        .map(|c| c.bytes_sent)
+        .unwrap_or(0);
+    if used.saturating_add(outgoing) > cap {
+        let name = match subject {
+            Subject::Profile(s) => format!("Profile {}", s),
+            Subject::Tool(t) => format!("本地工具 {}", t),
+        };
+        return Err(anyhow!(
+            "{} 已达今日出站流量上限（已用 {} / 上限 {} bytes），请明日再试或调整 egress_cap_bytes_per_day",
+            name,
+            used,
+            cap
+        ));
+    }
+    Ok(())
+}
+
+/// 流量统计条目（供指标导出）
+#[derive(Debug, Clone, Serialize)]
+pub struct BandwidthReport {
+    pub day: String,
+    /// "profile" / "tool"
+    pub kind: String,
+    pub name: String,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// 汇总流量统计（day 为 None 时返回全部日期）
+pub fn report(day: Option<&str>) -> Vec<BandwidthReport> {
+    amp_accounting::snapshot_counters()
+        .into_iter()
+        .filter_map(|(key, c)| {
+            let rest = key.strip_prefix(KEY_PREFIX)?;
+            let mut parts = rest.splitn(3, ':');
+            let (d, kind, name) = (parts.next()?, parts.next()?, parts.next()?);
+            if day.is_some_and(|want| want != d) {
+                return None;
+            }
+            Some(BandwidthReport {
+                day: d.to_string(),
+                kind: kind.to_string(),
+                name: name.to_string(),
+                bytes_sent: c.bytes_sent,
+                bytes_received: c.bytes_received,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_namespaced_by_day_and_kind() {
+        assert_eq!(
+            key_for("2025-01-01", Subject::Profile("claude")),
+            "bandwidth:2025-01-01:profile:claude"
+        );
+        assert_eq!(
+            key_for("2025-01-01", Subject::Tool("webSearch2")),
+            "bandwidth:2025-01-01:tool:webSearch2"
+        );
+    }
+
+    #[test]
+    fn no_cap_always_allows() {
+        assert!(check_egress_cap(Subject::Profile("claude"), None, u64::MAX).is_ok());
+    }
+}
@@ -0,0 +1,479 @@
+// AWS Bedrock 请求处理器（Claude 后端）
+//
+// 处理器配置 bedrock 中登记了 Claude 槽位当前选中的 Profile（按 ProfileManager 中的 Profile 名）时，
+// Claude 路由改用本处理器：
+// - /v1/messages → POST /model/{modelId}/invoke（stream=true 时 invoke-with-response-stream）
+// - 请求体移除 model / stream，补 anthropic_version，anthropic-beta 头转为 anthropic_beta 字段
+// - SigV4 签名（service=bedrock），支持临时凭证 session token
+// 流式响应为 AWS event stream 二进制帧，由 BedrockStreamConverter 还原为 Anthropic SSE。
+
+use super::amp_accounting::civil_from_days;
+use super::audit_log;
+use super::sse;
+use super::token_health::base64url_decode;
+use super::user_fingerprint::hmac_sha256;
+use super::{ProcessedRequest, RequestProcessor};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use hyper::HeaderMap as HyperHeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+const BEDROCK_ANTHROPIC_VERSION: &str = "bedrock-2023-05-31";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BedrockSettings {
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// 临时凭证（STS）时填写
+    pub session_token: Option<String>,
+    /// Anthropic 模型名 → Bedrock modelId / 推理配置文件 ARN
+    pub model_map: HashMap<String, String>,
+    /// 自定义端点（VPC endpoint 等），默认 https://bedrock-runtime.{region}.amazonaws.com
+    pub endpoint: Option<String>,
+}
+
+impl BedrockSettings {
+    pub(crate) fn is_configured(&self) -> bool {
+        !self.region.is_empty()
+            && !self.access_key_id.is_empty()
+            && !self.secret_access_key.is_empty()
+    }
+
+    fn endpoint(&self) -> String {
+        self.endpoint
+            .clone()
+            .unwrap_or_else(|| format!("https://bedrock-runtime.{}.amazonaws.com", self.region))
+    }
+}
+
+#[derive(Debug)]
+pub struct BedrockHeadersProcessor {
+    pub settings: BedrockSettings,
+}
+
+#[async_trait]
+impl RequestProcessor for BedrockHeadersProcessor {
+    fn tool_id(&self) -> &str {
+        "bedrock"
+    }
+
+    async fn process_outgoing_request(
+        &self,
+        _base_url: &str,
+        _api_key: &str,
+        _path: &str,
+        _query: Option<&str>,
+        original_headers: &HyperHeaderMap,
+        body: &[u8],
+    ) -> Result<ProcessedRequest> {
+        let mut json: Value =
+            serde_json::from_slice(body).map_err(|e| anyhow!("Bedrock 请求体解析失败: {}", e))?;
+        let obj = json
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("Bedrock 请求体必须是对象"))?;
+
+        let model = obj
+            .remove("model")
+            .and_then(|m| m.as_str().map(|s| s.to_string()))
+            .ok_or_else(|| anyhow!("Bedrock 请求缺少 model"))?;
+        let model_id = self
+            .settings
+            .model_map
+            .get(&model)
+            .cloned()
+            .unwrap_or(model);
+        let stream = obj.remove("stream").and_then(|s| s.as_bool()) == Some(true);
+        obj.insert("anthropic_version".into(), json!(BEDROCK_ANTHROPIC_VERSION));
+
+        let betas: Vec<&str> = original_headers
+            .get("anthropic-beta")
+            .and_then(|v| v.to_str().ok())
+            .map(|s| {
+                s.split(',')
+                    .map(|b| b.trim())
+                    .filter(|b| !b.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if !betas.is_empty() {
+            obj.insert("anthropic_beta".into(), json!(betas));
+        }
+
+        let payload = serde_json::to_vec(&json)?;
+        let action = if stream {
+            "invoke-with-response-stream"
+        } else {
+            "invoke"
+        };
+        let uri_path = format!("/model/{}/{}", uri_encode(&model_id), action);
+        let endpoint = self.settings.endpoint();
+        let host = endpoint
+            .split("://")
+            .nth(1)
+            .unwrap_or(&endpoint)
+            .trim_end_matches('/')
+            .to_string();
+
+        let headers = sign_v4(
+            &self.settings,
+            &host,
+            &uri_path,
+            &payload,
+            audit_log::now_ms(),
+        )?;
+        tracing::info!("AMP Code → Bedrock: {} ({})", model_id, action);
+
+        Ok(ProcessedRequest {
+            target_url: format!("{}{}", endpoint.trim_end_matches('/'), uri_path),
+            headers,
+            body: Bytes::from(payload),
+        })
+    }
+}
+
+/// URI 编码（RFC 3986 非保留字符之外全部编码）
+fn uri_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SigV4 签名，返回完整请求头
+fn sign_v4(
+    settings: &BedrockSettings,
+    host: &str,
+    uri_path: &str,
+    payload: &[u8],
+    now_ms: u64,
+) -> Result<HyperHeaderMap> {
+    let secs = now_ms / 1000;
+    let (y, m, d) = civil_from_days((secs / 86_400) as i64);
+    let tod = secs % 86_400;
+    let date = format!("{:04}{:02}{:02}", y, m, d);
+    let amz_date = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        tod / 3600,
+        tod % 3600 / 60,
+        tod % 60
+    );
+    let payload_hash = hex(&Sha256::digest(payload));
+
+    let mut signed: Vec<(&str, String)> = vec![
+        ("content-type", "application/json".to_string()),
+        ("host", host.to_string()),
+        ("x-amz-content-sha256", payload_hash.clone()),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if let Some(token) = &settings.session_token {
+        signed.push(("x-amz-security-token", token.clone()));
+    }
+    let canonical_headers: String = signed
+        .iter()
+        .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+        .collect();
+    let signed_headers = signed.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(";");
+
+    // 非 S3 服务的规范 URI 需对已编码路径再编码一次
+    let canonical_uri = uri_path
+        .split('/')
+        .map(uri_encode)
+        .collect::<Vec<_>>()
+        .join("/");
+    let canonical_request = format!(
+        "POST\n{}\n\n{}\n{}\n{}",
+        canonical_uri, canonical_headers, signed_headers, payload_hash
+    );
+
+    let scope = format!("{}/{}/bedrock/aws4_request", date, settings.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let k_date = hmac_sha256(
+        format!("AWS4{}", settings.secret_access_key).as_bytes(),
+        date.as_bytes(),
+    );
+    let k_region = hmac_sha256(&k_date, settings.region.as_bytes());
+    let k_service = hmac_sha256(&k_region, b"bedrock");
+    let k_signing = hmac_sha256(&k_service, b"aws4_request");
+    let signature = hex(&hmac_sha256(&k_signing, string_to_sign.as_bytes()));
+
+    let mut headers = HyperHeaderMap::new();
+    for (k, v) in &signed {
+        if *k == "host" {
+            continue;
+        }
+        headers.insert(
+            *k,
+            v.parse()
+                .map_err(|_| anyhow!("Bedrock 请求头非法: {}", k))?,
+        );
+    }
+    headers.insert("accept", "application/json".parse().unwrap());
+    headers.insert(
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            settings.access_key_id, scope, signed_headers, signature
+        )
+        .parse()
+        .map_err(|_| anyhow!("Bedrock access_key_id 含非法字符"))?,
+    );
+    Ok(headers)
+}
+
+/// AWS event stream → Anthropic SSE
+#[derive(Default)]
+pub(crate) struct BedrockStreamConverter {
+    buffer: Vec<u8>,
+}
+
+impl BedrockStreamConverter {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        self.buffer.extend_from_slice(chunk);
+        let mut out = Vec::new();
+        loop {
+            if self.buffer.len() < 12 {
+                break;
+            }
+            let total = u32::from_be_bytes(self.buffer[0..4].try_into().unwrap()) as usize;
+            if total < 16 {
+                tracing::warn!("Bedrock event stream 帧长度非法: {}", total);
+                self.buffer.clear();
+                break;
+            }
+            if self.buffer.len() < total {
+                break;
+            }
+            let frame: Vec<u8> = self.buffer.drain(..total).collect();
+            if let Some(sse) = decode_frame(&frame) {
+                out.extend_from_slice(sse.as_bytes());
+            }
+        }
+        Bytes::from(out)
+    }
+}
+
+/// 解析单帧：prelude(12) + headers + payload + crc(4)
+fn decode_frame(frame: &[u8]) -> Option<String> {
+    let headers_len = u32::from_be_bytes(frame[4..8].try_into().ok()?) as usize;
+    let headers_end = 12 + headers_len;
+    let payload = frame.get(headers_end..frame.len() - 4)?;
+    let headers = parse_headers(frame.get(12..headers_end)?);
+
+    let message_type = headers.get(":message-type").map(|s| s.as_str());
+    if message_type == Some("exception") || message_type == Some("error") {
+        let kind = headers
+            .get(":exception-type")
+            .or_else(|| headers.get(":error-code"))
+            .cloned()
+            .unwrap_or_else(|| "api_error".to_string());
+        let message = serde_json::from_slice::<Value>(payload)
+            .ok()
+            .and_then(|v| v["message"].as_str().map(|s| s.to_string()))
+            .unwrap_or_else(|| String::from_utf8_lossy(payload).into_owned());
+        let event = json!({ "type": "error", "error": { "type": kind, "message": message } });
+        return Some(sse::format(Some("error"), event));
+    }
+
+    let wrapper: Value = serde_json::from_slice(payload).ok()?;
+    let decoded = base64url_decode(wrapper["bytes"].as_str()?)?;
+    let event: Value = serde_json::from_slice(&decoded).ok()?;
+    let name = event["type"].as_str().unwrap_or("message");
+    Some(sse::format(Some(name), &event))
+}
+
+fn parse_headers(mut data: &[u8]) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    while let Some((&name_len, rest)) = data.split_first() {
+        let name_len = name_len as usize;
+        let Some(name) = rest.get(..name_len) else {
+            break;
+        };
+        let Some(&value_type) = rest.get(name_len) else {
+            break;
+        };
+        let rest = &rest[name_len + 1..];
+        let (value, consumed) = match value_type {
+            0 | 1 => (None, 0),
+            2 => (None, 1),
+            3 => (None, 2),
+            4 => (None, 4),
+            5 | 8 => (None, 8),
+            9 => (None, 16),
+            6 | 7 => {
+                let Some(len) = rest.get(..2) else {
+                    break;
+                };
+                let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+                let Some(bytes) = rest.get(2..2 + len) else {
+                    break;
+                };
+                (
+                    (value_type == 7).then(|| String::from_utf8_lossy(bytes).into_owned()),
+                    2 + len,
+                )
+            }
+            _ => break,
+        };
+        if let Some(v) = value {
+            headers.insert(String::from_utf8_lossy(name).into_owned(), v);
+        }
+        let Some(next) = rest.get(consumed..) else {
+            break;
+        };
+        data = next;
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(session_token: Option<&str>) -> BedrockSettings {
+        BedrockSettings {
+            region: "us-east-1".to_string(),
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: session_token.map(|s| s.to_string()),
+            ..Default::default()
+        }
+    }
+
+    const HOST: &str = "bedrock-runtime.us-east-1.amazonaws.com";
+    const PATH: &str = "/model/anthropic.claude-3-5-sonnet-20240620-v1%3A0/invoke";
+    /// 2015-08-30T12:36:00Z
+    const NOW_MS: u64 = 1_440_938_160_000;
+
+    fn authorization(headers: &HyperHeaderMap) -> &str {
+        headers["authorization"].to_str().unwrap()
+    }
+
+    #[test]
+    fn uri_encode_keeps_unreserved_only() {
+        assert_eq!(uri_encode("a-b_c.d~e"), "a-b_c.d~e");
+        assert_eq!(uri_encode("v1:0"), "v1%3A0");
+        assert_eq!(uri_encode("a/b c"), "a%2Fb%20c");
+        assert_eq!(uri_encode("%3A"), "%253A");
+    }
+
+    #[test]
+    fn sign_v4_matches_reference_signature() {
+        let headers = sign_v4(&settings(None), HOST, PATH, b"{}", NOW_MS).unwrap();
+        assert_eq!(
+            authorization(&headers),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/bedrock/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-content-sha256;x-amz-date, \
+             Signature=3c0f530f0cd7500a29b38c535cf65a13b52087dbf42c831e34003f260085efb1"
+        );
+        assert_eq!(headers["x-amz-date"], "20150830T123600Z");
+        assert_eq!(
+            headers["x-amz-content-sha256"],
+            "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+        );
+        assert!(headers.get("host").is_none());
+    }
+
+    #[test]
+    fn sign_v4_signs_session_token() {
+        let headers = sign_v4(&settings(Some("session-token")), HOST, PATH, b"{}", NOW_MS).unwrap();
+        assert_eq!(headers["x-amz-security-token"], "session-token");
+        let auth = authorization(&headers);
+        assert!(auth.contains(
+            "SignedHeaders=content-type;host;x-amz-content-sha256;x-amz-date;x-amz-security-token"
+        ));
+        assert!(auth.ends_with(
+            "Signature=a322b7e983411061b2b7ad324fcd45ec03e58a2ac47ad944716f07e45f845969"
+        ));
+    }
+
+    /// 构造 event stream 帧（CRC 不校验，填 0）
+    fn frame(headers: &[(&str, &str)], payload: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for (name, value) in headers {
+            encoded.push(name.len() as u8);
+            encoded.extend_from_slice(name.as_bytes());
+            encoded.push(7);
+            encoded.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            encoded.extend_from_slice(value.as_bytes());
+        }
+        let total = 12 + encoded.len() + payload.len() + 4;
+        let mut out = Vec::new();
+        out.extend_from_slice(&(total as u32).to_be_bytes());
+        out.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&encoded);
+        out.extend_from_slice(payload);
+        out.extend_from_slice(&[0; 4]);
+        out
+    }
+
+    #[test]
+    fn stream_converter_reassembles_split_frames() {
+        let chunk = frame(
+            &[(":message-type", "event"), (":event-type", "chunk")],
+            br#"{"bytes":"eyJ0eXBlIjoibWVzc2FnZV9zdG9wIn0="}"#,
+        );
+        let mut converter = BedrockStreamConverter::new();
+        let (head, tail) = chunk.split_at(20);
+        assert!(converter.feed(head).is_empty());
+        assert_eq!(
+            converter.feed(tail),
+            Bytes::from("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n")
+        );
+    }
+
+    #[test]
+    fn stream_converter_maps_exception_to_error_event() {
+        let chunk = frame(
+            &[
+                (":message-type", "exception"),
+                (":exception-type", "throttlingException"),
+            ],
+            br#"{"message":"Too many requests"}"#,
+        );
+        let out = BedrockStreamConverter::new().feed(&chunk);
+        let text = String::from_utf8(out.to_vec()).unwrap();
+        assert!(text.starts_with("event: error\n"));
+        assert!(text.contains("\"type\":\"throttlingException\""));
+        assert!(text.contains("Too many requests"));
+    }
+
+    #[test]
+    fn stream_converter_drops_invalid_length() {
+        let mut converter = BedrockStreamConverter::new();
+        let mut bad = vec![0, 0, 0, 4];
+        bad.extend_from_slice(&[0; 8]);
+        assert!(converter.feed(&bad).is_empty());
+        let chunk = frame(
+            &[(":message-type", "event")],
+            br#"{"bytes":"eyJ0eXBlIjoibWVzc2FnZV9zdG9wIn0="}"#,
+        );
+        assert!(!converter.feed(&chunk).is_empty());
+    }
+}
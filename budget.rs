@@ -0,0 +1,348 @@
+// Profile 预算与配额
+//
+// 按 Profile 槽位设置每日 / 每月 token 或美元上限。用量在转发层响应结束时
+// 从响应体解析（与请求日志共用解析器），按 模型 记入 amp_accounting：
+//   budget:{YYYY-MM-DD}:{slot}:{model}
+// 美元金额按 prices（模型名或以 * 结尾的前缀 → 每百万 token 单价）计算，未配置时使用内置参考价。
+// 本地工具的按次费用（搜索 API 等，见 tool_credits.rs）记为 budget:{day}:{slot}:tool:{工具名}，
+// 只计入美元上限。
+// 超出任一上限后：action=reject 直接拒绝；action=fallback 改用 fallback_model（更便宜的模型）继续。
+
+use super::amp_accounting::{self, UsageCounters};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const KEY_PREFIX: &str = "budget:";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetAction {
+    #[default]
+    Reject,
+    Fallback,
+}
+
+/// 每百万 token 单价（美元）
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetSettings {
+    pub daily_tokens: Option<u64>,
+    pub monthly_tokens: Option<u64>,
+    pub daily_usd: Option<f64>,
+    pub monthly_usd: Option<f64>,
+    pub action: BudgetAction,
+    /// action=fallback 时改用的模型
+    pub fallback_model: Option<String>,
+    /// 模型名（或以 * 结尾的前缀）→ 单价，覆盖内置参考价
+    pub prices: HashMap<String, ModelPrice>,
+}
+
+impl BudgetSettings {
+    pub fn is_configured(&self) -> bool {
+        self.daily_tokens.is_some()
+            || self.monthly_tokens.is_some()
+            || self.daily_usd.is_some()
+            || self.monthly_usd.is_some()
+    }
+}
+
+/// 检查结果
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum BudgetDecision {
+    Allow,
+    /// 已超出，改用该模型
+    Fallback(String),
+}
+
+/// 内置参考价（关键字 → 输入 / 输出每百万 token 美元），按顺序匹配
+const REFERENCE_PRICES: &[(&str, f64, f64)] = &[
+    ("opus", 15.0, 75.0),
+    ("sonnet", 3.0, 15.0),
+    ("haiku", 1.0, 5.0),
+    ("gpt-5-mini", 0.25, 2.0),
+    ("gpt-5-nano", 0.05, 0.4),
+    ("gpt-5", 1.25, 10.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1", 2.0, 8.0),
+    ("flash-lite", 0.1, 0.4),
+    ("flash", 0.3, 2.5),
+    ("gemini", 1.25, 10.0),
+];
+
+fn price_of(settings: &BudgetSettings, model: &str) -> ModelPrice {
+    let lower = model.to_lowercase();
+    let custom = settings
+        .prices
+        .iter()
+        .filter_map(|(pattern, price)| {
+            let pattern = pattern.to_lowercase();
+            if pattern == lower {
+                return Some((usize::MAX, *price));
+            }
+            let prefix = pattern.strip_suffix('*')?;
+            lower.starts_with(prefix).then_some((prefix.len(), *price))
+        })
+        .max_by_key(|(len, _)| *len);
+    if let Some((_, price)) = custom {
+        return price;
+    }
+    REFERENCE_PRICES
+        .iter()
+        .find(|(marker, _, _)| lower.contains(marker))
+        .map(|(_, input, output)| ModelPrice {
+            input_per_mtok: *input,
+            output_per_mtok: *output,
+        })
+        .unwrap_or_default()
+}
+
+/// 按 token 计算的美元费用
+pub(crate) fn cost_usd(
+    settings: &BudgetSettings,
+    model: &str,
+    input_tokens: u64,
+    output_tokens: u64,
+) -> f64 {
+    let price = price_of(settings, model);
+    (input_tokens as f64 * price.input_per_mtok + output_tokens as f64 * price.output_per_mtok)
+        / 1_000_000.0
+}
+
+/// 记入本地工具的按次费用（美元）
+pub(crate) fn record_tool_cost(slot: &str, tool: &str, usd: f64) {
+    let micro = (usd * 1_000_000.0).round();
+    if micro < 1.0 {
+        return;
+    }
+    amp_accounting::record_usage(
+        &format!(
+            "{}{}:{}:tool:{}",
+            KEY_PREFIX,
+            amp_accounting::utc_day(),
+            slot,
+            tool
+        ),
+        UsageCounters {
+            cost_micro_usd: micro as u64,
+            ..Default::default()
+        },
+    );
+}
+
+/// 响应结束后记入用量（输入含缓存读写 token）
+pub(crate) fn record(slot: &str, model: Option<&str>, input_tokens: u64, output_tokens: u64) {
+    if input_tokens + output_tokens == 0 {
+        return;
+    }
+    amp_accounting::record_usage(
+        &format!(
+            "{}{}:{}:{}",
+            KEY_PREFIX,
+            amp_accounting::utc_day(),
+            slot,
+            model.unwrap_or("unknown")
+        ),
+        UsageCounters {
+            input_tokens,
+            output_tokens,
+            ..Default::default()
+        },
+    );
+}
+
+/// 当前已用量
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BudgetUsage {
+    pub daily_tokens: u64,
+    pub monthly_tokens: u64,
+    pub daily_usd: f64,
+    pub monthly_usd: f64,
+}
+
+pub fn usage(settings: &BudgetSettings, slot: &str) -> BudgetUsage {
+    let day = amp_accounting::utc_day();
+    let month = &day[..7];
+    let mut usage = BudgetUsage::default();
+    for (key, c) in amp_accounting::snapshot_counters() {
+        let Some(rest) = key.strip_prefix(KEY_PREFIX) else {
+            continue;
+        };
+        let mut parts = rest.splitn(3, ':');
+        let (Some(d), Some(s), Some(model)) = (parts.next(), parts.next(), parts.next()) else {
+            continue;
+        };
+        if s != slot || !d.starts_with(month) {
+            continue;
+        }
+        let tokens = c.input_tokens + c.output_tokens;
+        let usd = cost_usd(settings, model, c.input_tokens, c.output_tokens)
+            + c.cost_micro_usd as f64 / 1_000_000.0;
+        usage.monthly_tokens += tokens;
+        usage.monthly_usd += usd;
+        if d == day {
+            usage.daily_tokens += tokens;
+            usage.daily_usd += usd;
+        }
+    }
+    usage
+}
+
+/// 第一个被突破的上限描述
+fn exceeded(settings: &BudgetSettings, usage: &BudgetUsage) -> Option<String> {
+    if let Some(cap) = settings.daily_tokens.filter(|c| usage.daily_tokens >= *c) {
+        return Some(format!(
+            "今日 token 已用 {} / 上限 {}",
+            usage.daily_tokens, cap
+        ));
+    }
+    if let Some(cap) = settings
+        .monthly_tokens
+        .filter(|c| usage.monthly_tokens >= *c)
+    {
+        return Some(format!(
+            "本月 token 已用 {} / 上限 {}",
+            usage.monthly_tokens, cap
+        ));
+    }
+    if let Some(cap) = settings.daily_usd.filter(|c| usage.daily_usd >= *c) {
+        return Some(format!(
+            "今日费用 ${:.2} / 上限 ${:.2}",
+            usage.daily_usd, cap
+        ));
+    }
+    if let Some(cap) = settings.monthly_usd.filter(|c| usage.monthly_usd >= *c) {
+        return Some(format!(
+            "本月费用 ${:.2} / 上限 ${:.2}",
+            usage.monthly_usd, cap
+        ));
+    }
+    None
+}
+
+/// 请求前检查：未超出放行；超出时拒绝或返回降级模型
+pub(crate) fn check(
+    settings: &BudgetSettings,
+    slot: &str,
+    model: Option<&str>,
+) -> Result<BudgetDecision> {
+    if !settings.is_configured() {
+        return Ok(BudgetDecision::Allow);
+    }
+    let Some(reason) = exceeded(settings, &usage(settings, slot)) else {
+        return Ok(BudgetDecision::Allow);
+    };
+    match (settings.action, settings.fallback_model.as_deref()) {
+        (BudgetAction::Fallback, Some(fallback)) if model != Some(fallback) => {
+            tracing::warn!("{} 预算已超出（{}），改用 {}", slot, reason, fallback);
+            Ok(BudgetDecision::Fallback(fallback.to_string()))
+        }
+        // 已经在用降级模型：继续放行，避免降级后仍被拒绝
+        (BudgetAction::Fallback, Some(_)) => Ok(BudgetDecision::Allow),
+        _ => Err(anyhow!(
+            "Profile {} 已超出预算（{}），请求已拒绝；可在 budget 配置中调整上限或设置 fallback_model",
+            slot,
+            reason
+        )),
+    }
+}
+
+/// 改写请求体中的 model
+pub(crate) fn with_model(body: &[u8], model: &str) -> Result<Vec<u8>> {
+    let mut json: serde_json::Value =
+        serde_json::from_slice(body).map_err(|e| anyhow!("请求 JSON 解析失败: {}", e))?;
+    if let Some(obj) = json.as_object_mut() {
+        obj.insert("model".into(), serde_json::Value::String(model.to_string()));
+    }
+    serde_json::to_vec(&json).map_err(|e| anyhow!("请求 JSON 序列化失败: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(input: f64, output: f64) -> ModelPrice {
+        ModelPrice {
+            input_per_mtok: input,
+            output_per_mtok: output,
+        }
+    }
+
+    #[test]
+    fn reference_prices_match_in_order() {
+        let settings = BudgetSettings::default();
+        assert_eq!(cost_usd(&settings, "claude-sonnet-4-5", 1_000_000, 0), 3.0);
+        assert_eq!(cost_usd(&settings, "gpt-5-mini", 0, 1_000_000), 2.0);
+        assert_eq!(cost_usd(&settings, "gpt-5", 1_000_000, 1_000_000), 11.25);
+        assert_eq!(
+            cost_usd(&settings, "unknown-model", 1_000_000, 1_000_000),
+            0.0
+        );
+    }
+
+    #[test]
+    fn custom_prices_prefer_exact_then_longest_prefix() {
+        let mut settings = BudgetSettings::default();
+        settings
+            .prices
+            .insert("claude-*".to_string(), price(1.0, 1.0));
+        settings
+            .prices
+            .insert("claude-sonnet-*".to_string(), price(2.0, 2.0));
+        settings
+            .prices
+            .insert("Claude-Sonnet-4-5".to_string(), price(4.0, 4.0));
+        assert_eq!(cost_usd(&settings, "claude-sonnet-4-5", 1_000_000, 0), 4.0);
+        assert_eq!(cost_usd(&settings, "claude-sonnet-4", 1_000_000, 0), 2.0);
+        assert_eq!(cost_usd(&settings, "claude-haiku-4-5", 1_000_000, 0), 1.0);
+    }
+
+    #[test]
+    fn exceeded_reports_first_broken_limit() {
+        let settings = BudgetSettings {
+            daily_tokens: Some(100),
+            monthly_usd: Some(5.0),
+            ..Default::default()
+        };
+        let usage = BudgetUsage {
+            daily_tokens: 50,
+            monthly_tokens: 50,
+            daily_usd: 1.0,
+            monthly_usd: 6.0,
+        };
+        assert_eq!(
+            exceeded(&settings, &usage).as_deref(),
+            Some("本月费用 $6.00 / 上限 $5.00")
+        );
+        let usage = BudgetUsage {
+            daily_tokens: 100,
+            ..usage
+        };
+        assert_eq!(
+            exceeded(&settings, &usage).as_deref(),
+            Some("今日 token 已用 100 / 上限 100")
+        );
+        assert!(exceeded(&settings, &BudgetUsage::default()).is_none());
+    }
+
+    #[test]
+    fn unconfigured_budget_allows_and_model_rewrite() {
+        assert_eq!(
+            check(&BudgetSettings::default(), "claude", Some("claude-opus-4")).unwrap(),
+            BudgetDecision::Allow
+        );
+        let body = with_model(br#"{"model":"a","stream":true}"#, "b").unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["model"], "b");
+        assert_eq!(json["stream"], true);
+        assert!(with_model(b"not json", "b").is_err());
+    }
+}
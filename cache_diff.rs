@@ -0,0 +1,329 @@
+// 多轮请求前缀比对（缓存排查）
+//
+// Anthropic 提示缓存按前缀命中：tools → system → messages 依次排列，
+// 到最近的 cache_control 断点为止的前缀完全一致才能读缓存。启用后按会话
+// （amp_header_capture.session_header）记录每次发往上游的 Claude 请求的块序列
+// （每块的哈希、估算 token 数、是否为断点），analyze() 逐轮比对：
+// - 首个不同的块（如 messages[3].content[1]）及变化类型
+// - 共同前缀 token 数、距上一轮最近断点的 token 距离
+// - 预计可读缓存 / 需重新写入缓存的 token 数
+// 用于解释意外的缓存写入费用。仅保存哈希与统计，不保存内容；只在进程内保留。
+
+use super::transform_middleware::estimate_tokens;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+const MAX_SESSIONS: usize = 100;
+const MAX_TURNS: usize = 50;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheDiffSettings {
+    pub enabled: bool,
+}
+
+/// 前缀中的一个块
+#[derive(Debug, Clone, Serialize)]
+struct Block {
+    /// 位置，如 tools[0] / system[1] / messages[2].content[0]
+    path: String,
+    hash: String,
+    tokens: usize,
+    breakpoint: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Turn {
+    ts_ms: u64,
+    model: String,
+    blocks: Vec<Block>,
+}
+
+/// 相邻两轮的比对结果
+#[derive(Debug, Clone, Serialize)]
+pub struct TurnDiff {
+    pub turn: usize,
+    pub ts_ms: u64,
+    pub model: String,
+    pub total_tokens: usize,
+    /// 首个不同的块（None 表示本轮只在末尾追加）
+    pub first_divergence: Option<String>,
+    /// changed / removed / inserted / appended / model_changed
+    pub change: String,
+    /// 与上一轮相同的前缀 token 数
+    pub common_prefix_tokens: usize,
+    /// 分歧点距上一轮最近断点（在分歧点之前）的 token 数；该段已无法读缓存
+    pub tokens_past_breakpoint: Option<usize>,
+    /// 预计可读缓存的 token 数（到分歧点前的最后一个断点）
+    pub cacheable_tokens: usize,
+    /// 预计需重新写入缓存的 token 数（到本轮最后一个断点）
+    pub cache_write_tokens: usize,
+}
+
+static SESSIONS: Lazy<Mutex<HashMap<String, VecDeque<Turn>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn block(path: String, value: &Value) -> Block {
+    let text = value.to_string();
+    let mut without_cc = value.clone();
+    if let Some(obj) = without_cc.as_object_mut() {
+        obj.remove("cache_control");
+    }
+    Block {
+        path,
+        hash: format!("{:x}", Sha256::digest(without_cc.to_string().as_bytes()))[..16].to_string(),
+        tokens: estimate_tokens(&text),
+        breakpoint: value.get("cache_control").is_some(),
+    }
+}
+
+/// 按缓存前缀顺序展开请求体
+fn blocks_of(json: &Value) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    for (i, tool) in json["tools"].as_array().into_iter().flatten().enumerate() {
+        blocks.push(block(format!("tools[{}]", i), tool));
+    }
+    match &json["system"] {
+        Value::String(s) => blocks.push(block("system".into(), &Value::String(s.clone()))),
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                blocks.push(block(format!("system[{}]", i), item));
+            }
+        }
+        _ => {}
+    }
+    for (m, message) in json["messages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        match &message["content"] {
+            Value::Array(items) => {
+                for (c, item) in items.iter().enumerate() {
+                    blocks.push(block(format!("messages[{}].content[{}]", m, c), item));
+                }
+            }
+            other => blocks.push(block(format!("messages[{}].content", m), other)),
+        }
+    }
+    blocks
+}
+
+/// 记录一次发往上游的 Claude 请求体
+pub(crate) fn record(settings: &CacheDiffSettings, session_id: Option<&str>, body: &[u8]) {
+    let Some(session_id) = session_id.filter(|_| settings.enabled) else {
+        return;
+    };
+    let Ok(json) = serde_json::from_slice::<Value>(body) else {
+        return;
+    };
+    let turn = Turn {
+        ts_ms: super::audit_log::now_ms(),
+        model: json["model"].as_str().unwrap_or_default().to_string(),
+        blocks: blocks_of(&json),
+    };
+    let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+    if !sessions.contains_key(session_id) && sessions.len() >= MAX_SESSIONS {
+        let oldest = sessions
+            .iter()
+            .min_by_key(|(_, turns)| turns.back().map(|t| t.ts_ms).unwrap_or(0))
+            .map(|(id, _)| id.clone());
+        if let Some(oldest) = oldest {
+            sessions.remove(&oldest);
+        }
+    }
+    let turns = sessions.entry(session_id.to_string()).or_default();
+    if turns.len() >= MAX_TURNS {
+        turns.pop_front();
+    }
+    turns.push_back(turn);
+}
+
+fn compare(index: usize, prev: &Turn, cur: &Turn) -> TurnDiff {
+    let common = prev
+        .blocks
+        .iter()
+        .zip(&cur.blocks)
+        .take_while(|(a, b)| a.path == b.path && a.hash == b.hash)
+        .count();
+    let prefix_tokens = |blocks: &[Block], n: usize| blocks[..n].iter().map(|b| b.tokens).sum();
+    let common_prefix_tokens: usize = prefix_tokens(&cur.blocks, common);
+
+    // 模型不同缓存完全不共享
+    let same_model = prev.model == cur.model;
+    let last_breakpoint = |blocks: &[Block], n: usize| {
+        blocks[..n]
+            .iter()
+            .rposition(|b| b.breakpoint)
+            .map(|i| i + 1)
+    };
+    let cached_end = if same_model {
+        last_breakpoint(&prev.blocks, common)
+    } else {
+        None
+    };
+    let cacheable_tokens = cached_end.map_or(0, |end| prefix_tokens(&cur.blocks, end));
+    let write_end = last_breakpoint(&cur.blocks, cur.blocks.len()).unwrap_or(0);
+    let cache_write_tokens = prefix_tokens(&cur.blocks, write_end).saturating_sub(cacheable_tokens);
+
+    let (first_divergence, change) = if !same_model {
+        (None, "model_changed")
+    } else if common == prev.blocks.len() {
+        (None, "appended")
+    } else if common == cur.blocks.len() {
+        (Some(prev.blocks[common].path.clone()), "removed")
+    } else if prev.blocks[common].path != cur.blocks[common].path
+        && cur.blocks[common + 1..]
+            .iter()
+            .any(|b| b.hash == prev.blocks[common].hash)
+    {
+        (Some(cur.blocks[common].path.clone()), "inserted")
+    } else {
+        (Some(cur.blocks[common].path.clone()), "changed")
+    };
+    let tokens_past_breakpoint = first_divergence
+        .as_ref()
+        .map(|_| common_prefix_tokens - cacheable_tokens.min(common_prefix_tokens));
+
+    TurnDiff {
+        turn: index,
+        ts_ms: cur.ts_ms,
+        model: cur.model.clone(),
+        total_tokens: cur.blocks.iter().map(|b| b.tokens).sum(),
+        first_divergence,
+        change: change.to_string(),
+        common_prefix_tokens,
+        tokens_past_breakpoint,
+        cacheable_tokens,
+        cache_write_tokens,
+    }
+}
+
+/// 逐轮比对会话中的请求（首轮无比对对象，从第 2 轮开始）
+pub fn analyze(session_id: &str) -> Option<Vec<TurnDiff>> {
+    let sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+    let turns = sessions.get(session_id)?;
+    Some(
+        turns
+            .iter()
+            .zip(turns.iter().skip(1))
+            .enumerate()
+            .map(|(i, (prev, cur))| compare(i + 1, prev, cur))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const ENABLED: CacheDiffSettings = CacheDiffSettings { enabled: true };
+
+    fn body(model: &str, messages: Value) -> Vec<u8> {
+        json!({
+            "model": model,
+            "system": [{ "type": "text", "text": "You are Amp.", "cache_control": { "type": "ephemeral" } }],
+            "messages": messages,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn user(text: &str) -> Value {
+        json!({ "role": "user", "content": [{ "type": "text", "text": text }] })
+    }
+
+    fn diffs(session: &str, bodies: &[Vec<u8>]) -> Vec<TurnDiff> {
+        for b in bodies {
+            record(&ENABLED, Some(session), b);
+        }
+        analyze(session).unwrap()
+    }
+
+    #[test]
+    fn appended_turn_reads_cache_up_to_breakpoint() {
+        let diffs = diffs(
+            "cache-diff-test-append",
+            &[
+                body("m", json!([user("one")])),
+                body("m", json!([user("one"), user("two")])),
+            ],
+        );
+        assert_eq!(diffs.len(), 1);
+        let diff = &diffs[0];
+        assert_eq!(diff.change, "appended");
+        assert!(diff.first_divergence.is_none());
+        assert!(diff.cacheable_tokens > 0);
+        assert_eq!(diff.cache_write_tokens, 0);
+    }
+
+    #[test]
+    fn reports_first_changed_block() {
+        let diffs = diffs(
+            "cache-diff-test-change",
+            &[
+                body("m", json!([user("one"), user("two")])),
+                body("m", json!([user("one"), user("TWO")])),
+            ],
+        );
+        assert_eq!(diffs[0].change, "changed");
+        assert_eq!(
+            diffs[0].first_divergence.as_deref(),
+            Some("messages[1].content[0]")
+        );
+    }
+
+    #[test]
+    fn detects_insertion_and_model_switch() {
+        let diffs = diffs(
+            "cache-diff-test-insert",
+            &[
+                body("m", json!([user("one")])),
+                body(
+                    "m",
+                    json!([{ "role": "user", "content": "new" }, user("one")]),
+                ),
+                body(
+                    "other",
+                    json!([{ "role": "user", "content": "new" }, user("one")]),
+                ),
+            ],
+        );
+        assert_eq!(diffs[0].change, "inserted");
+        assert_eq!(
+            diffs[0].first_divergence.as_deref(),
+            Some("messages[0].content")
+        );
+        assert_eq!(diffs[1].change, "model_changed");
+        assert_eq!(diffs[1].cacheable_tokens, 0);
+    }
+
+    #[test]
+    fn cache_control_does_not_change_block_hash() {
+        let plain = block("x".into(), &json!({ "type": "text", "text": "a" }));
+        let marked = block(
+            "x".into(),
+            &json!({ "type": "text", "text": "a", "cache_control": { "type": "ephemeral" } }),
+        );
+        assert_eq!(plain.hash, marked.hash);
+        assert!(!plain.breakpoint && marked.breakpoint);
+    }
+
+    #[test]
+    fn disabled_or_sessionless_requests_are_ignored() {
+        let b = body("m", json!([user("one")]));
+        record(
+            &CacheDiffSettings::default(),
+            Some("cache-diff-test-off"),
+            &b,
+        );
+        record(&ENABLED, None, &b);
+        assert!(analyze("cache-diff-test-off").is_none());
+    }
+}
@@ -0,0 +1,659 @@
+// 本地计算工具（evaluateExpression）
+//
+// AMP 以本地工具方式调用（?evaluateExpression），params.expression 为表达式。
+// 纯 Rust 递归下降求值，不执行任何代码，避免 Agent 心算出错或为简单计算动用代码执行：
+// - 算术：+ - * / %（取模）^（乘方，右结合）、括号、一元正负号
+// - 函数：sqrt abs exp ln log10 log2 sin cos tan asin acos atan floor ceil round min max pow
+//   log(x) 为自然对数，log(x, b) 为以 b 为底；常量 pi、e
+// - 单位：数字后跟单位（长度 / 质量 / 时间 / 数据量 / 体积 / 温度），同量纲可加减，
+//   结尾 `to` / `in` / `as` 单位 转换，如 `3 ft + 2 in to cm`、`98.6 F to C`、`1.5 GiB to MB`
+// - 日期：YYYY-MM-DD 或 today，日期 ± 天数 / 时间量，日期 - 日期 得到天数
+// 表达式长度与嵌套深度有上限，防止恶意输入耗尽资源。
+
+use super::amp_accounting::{civil_from_days, days_from_civil};
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value as Json};
+
+/// 表达式最大长度（字符）
+const MAX_EXPRESSION_CHARS: usize = 1000;
+/// 最大嵌套深度
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dim {
+    Length,
+    Mass,
+    Time,
+    Data,
+    Volume,
+    Temperature,
+}
+
+impl Dim {
+    fn name(self) -> &'static str {
+        match self {
+            Dim::Length => "长度",
+            Dim::Mass => "质量",
+            Dim::Time => "时间",
+            Dim::Data => "数据量",
+            Dim::Volume => "体积",
+            Dim::Temperature => "温度",
+        }
+    }
+}
+
+/// 单位：基准值 = 数值 × factor + offset（基准为 m / kg / s / B / L / K）
+#[derive(Debug)]
+struct Unit {
+    names: &'static [&'static str],
+    dim: Dim,
+    factor: f64,
+    offset: f64,
+}
+
+const fn unit(names: &'static [&'static str], dim: Dim, factor: f64) -> Unit {
+    Unit {
+        names,
+        dim,
+        factor,
+        offset: 0.0,
+    }
+}
+
+static UNITS: &[Unit] = &[
+    unit(
+        &["m", "meter", "meters", "metre", "metres"],
+        Dim::Length,
+        1.0,
+    ),
+    unit(&["km", "kilometer", "kilometers"], Dim::Length, 1000.0),
+    unit(&["cm", "centimeter", "centimeters"], Dim::Length, 0.01),
+    unit(&["mm", "millimeter", "millimeters"], Dim::Length, 0.001),
+    unit(&["mi", "mile", "miles"], Dim::Length, 1609.344),
+    unit(&["yd", "yard", "yards"], Dim::Length, 0.9144),
+    unit(&["ft", "foot", "feet"], Dim::Length, 0.3048),
+    unit(&["in", "inch", "inches"], Dim::Length, 0.0254),
+    unit(&["nmi"], Dim::Length, 1852.0),
+    unit(&["kg", "kilogram", "kilograms"], Dim::Mass, 1.0),
+    unit(&["g", "gram", "grams"], Dim::Mass, 0.001),
+    unit(&["mg", "milligram", "milligrams"], Dim::Mass, 1e-6),
+    unit(&["t", "tonne", "tonnes"], Dim::Mass, 1000.0),
+    unit(&["lb", "lbs", "pound", "pounds"], Dim::Mass, 0.453_592_37),
+    unit(&["oz", "ounce", "ounces"], Dim::Mass, 0.028_349_523_125),
+    unit(&["ms", "millisecond", "milliseconds"], Dim::Time, 0.001),
+    unit(&["s", "sec", "secs", "second", "seconds"], Dim::Time, 1.0),
+    unit(&["min", "mins", "minute", "minutes"], Dim::Time, 60.0),
+    unit(&["h", "hr", "hrs", "hour", "hours"], Dim::Time, 3600.0),
+    unit(&["days", "day", "d"], Dim::Time, 86_400.0),
+    unit(&["wk", "week", "weeks"], Dim::Time, 604_800.0),
+    unit(&["yr", "year", "years"], Dim::Time, 31_536_000.0),
+    unit(&["bit", "bits"], Dim::Data, 0.125),
+    unit(&["B", "byte", "bytes"], Dim::Data, 1.0),
+    unit(&["KB", "kB"], Dim::Data, 1e3),
+    unit(&["MB"], Dim::Data, 1e6),
+    unit(&["GB"], Dim::Data, 1e9),
+    unit(&["TB"], Dim::Data, 1e12),
+    unit(&["KiB"], Dim::Data, 1024.0),
+    unit(&["MiB"], Dim::Data, 1_048_576.0),
+    unit(&["GiB"], Dim::Data, 1_073_741_824.0),
+    unit(&["TiB"], Dim::Data, 1_099_511_627_776.0),
+    unit(
+        &["l", "L", "liter", "liters", "litre", "litres"],
+        Dim::Volume,
+        1.0,
+    ),
+    unit(
+        &["ml", "mL", "milliliter", "milliliters"],
+        Dim::Volume,
+        0.001,
+    ),
+    unit(&["gal", "gallon", "gallons"], Dim::Volume, 3.785_411_784),
+    Unit {
+        names: &["K", "kelvin"],
+        dim: Dim::Temperature,
+        factor: 1.0,
+        offset: 0.0,
+    },
+    Unit {
+        names: &["C", "°C", "celsius"],
+        dim: Dim::Temperature,
+        factor: 1.0,
+        offset: 273.15,
+    },
+    Unit {
+        names: &["F", "°F", "fahrenheit"],
+        dim: Dim::Temperature,
+        factor: 5.0 / 9.0,
+        offset: 273.15 - 32.0 * 5.0 / 9.0,
+    },
+];
+
+fn find_unit(name: &str) -> Option<&'static Unit> {
+    UNITS.iter().find(|u| u.names.contains(&name))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    /// 数值及可选单位
+    Num(f64, Option<&'static Unit>),
+    /// 日期（Unix 天数）
+    Date(i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Date(i64),
+    Ident(String),
+    Op(char),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        // YYYY-MM-DD 日期字面量优先于减法
+        if chars.len() >= i + 10
+            && chars[i..i + 4].iter().all(char::is_ascii_digit)
+            && chars[i + 4] == '-'
+            && chars[i + 5..i + 7].iter().all(char::is_ascii_digit)
+            && chars[i + 7] == '-'
+            && chars[i + 8..i + 10].iter().all(char::is_ascii_digit)
+        {
+            let text: String = chars[i..i + 10].iter().collect();
+            tokens.push(Token::Date(parse_date(&text)?));
+            i += 10;
+            continue;
+        }
+        if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_digit() || chars[i] == '.' || chars[i] == '_')
+            {
+                i += 1;
+            }
+            // 科学计数法：1e6 / 2.5E-3
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().filter(|c| **c != '_').collect();
+            let value = text
+                .parse()
+                .map_err(|_| anyhow!("无法解析数字: {}", text))?;
+            tokens.push(Token::Num(value));
+            continue;
+        }
+        if c.is_alphabetic() || c == '°' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            continue;
+        }
+        match c {
+            '+' | '-' | '*' | '/' | '%' | '^' | '(' | ')' | ',' => tokens.push(Token::Op(c)),
+            '×' => tokens.push(Token::Op('*')),
+            '÷' => tokens.push(Token::Op('/')),
+            _ => bail!("无法识别的字符: {}", c),
+        }
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+fn parse_date(text: &str) -> Result<i64> {
+    let parts: Vec<i64> = text.split('-').filter_map(|p| p.parse().ok()).collect();
+    let [y, m, d] = parts.as_slice() else {
+        bail!("无法解析日期: {}", text);
+    };
+    let days = days_from_civil(*y, *m as u32, *d as u32);
+    // 往返校验，拒绝 2024-02-30 之类的日期
+    if civil_from_days(days) != (*y, *m as u32, *d as u32) {
+        bail!("无效日期: {}", text);
+    }
+    Ok(days)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_op(&self, op: char) -> bool {
+        self.peek() == Some(&Token::Op(op))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect_op(&mut self, op: char) -> Result<()> {
+        match self.next() {
+            Some(Token::Op(c)) if c == op => Ok(()),
+            Some(other) => bail!("此处应为 '{}'，实际为 {:?}", op, other),
+            None => bail!("缺少 '{}'", op),
+        }
+    }
+
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            bail!("表达式嵌套过深");
+        }
+        Ok(())
+    }
+
+    /// 顶层：表达式 [to|in|as 单位]
+    fn parse(&mut self) -> Result<Value> {
+        let value = self.expr()?;
+        let value = match self.next() {
+            None => return Ok(value),
+            Some(Token::Ident(kw)) if matches!(kw.as_str(), "to" | "in" | "as") => {
+                let Some(Token::Ident(name)) = self.next() else {
+                    bail!("{} 之后应为单位", kw);
+                };
+                let target = find_unit(&name).ok_or_else(|| anyhow!("未知单位: {}", name))?;
+                convert(value, target)?
+            }
+            Some(token) => bail!("多余的内容: {:?}", token),
+        };
+        match self.next() {
+            None => Ok(value),
+            Some(token) => bail!("多余的内容: {:?}", token),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Value> {
+        let mut left = self.term()?;
+        loop {
+            if self.peek_op('+') {
+                self.pos += 1;
+                left = add(left, self.term()?, 1.0)?;
+            } else if self.peek_op('-') {
+                self.pos += 1;
+                left = add(left, self.term()?, -1.0)?;
+            } else {
+                return Ok(left);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Value> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(c)) if matches!(c, '*' | '/' | '%') => *c,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = mul(left, self.unary()?, op)?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<Value> {
+        self.enter()?;
+        let value = if self.peek_op('-') {
+            self.pos += 1;
+            match self.unary()? {
+                Value::Num(v, unit) => Value::Num(-v, unit),
+                Value::Date(_) => bail!("日期不能取负"),
+            }
+        } else if self.peek_op('+') {
+            self.pos += 1;
+            self.unary()?
+        } else {
+            self.power()?
+        };
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    fn power(&mut self) -> Result<Value> {
+        let base = self.postfix()?;
+        if !self.peek_op('^') {
+            return Ok(base);
+        }
+        self.pos += 1;
+        let exponent = self.unary()?;
+        match (base, exponent) {
+            (Value::Num(b, None), Value::Num(e, None)) => Ok(Value::Num(b.powf(e), None)),
+            _ => bail!("乘方只支持无单位数值"),
+        }
+    }
+
+    /// 数字后可跟单位
+    fn postfix(&mut self) -> Result<Value> {
+        let is_number = matches!(self.peek(), Some(Token::Num(_)));
+        let value = self.primary()?;
+        if !is_number {
+            return Ok(value);
+        }
+        if let (Value::Num(v, None), Some(Token::Ident(name))) = (value, self.peek()) {
+            if let Some(unit) = find_unit(name) {
+                self.pos += 1;
+                return Ok(Value::Num(v, Some(unit)));
+            }
+        }
+        Ok(value)
+    }
+
+    fn primary(&mut self) -> Result<Value> {
+        match self.next() {
+            Some(Token::Num(v)) => Ok(Value::Num(v, None)),
+            Some(Token::Date(days)) => Ok(Value::Date(days)),
+            Some(Token::Op('(')) => {
+                self.enter()?;
+                let value = self.expr()?;
+                self.expect_op(')')?;
+                self.depth -= 1;
+                Ok(value)
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek_op('(') {
+                    self.pos += 1;
+                    let mut args = Vec::new();
+                    if !self.peek_op(')') {
+                        loop {
+                            args.push(self.expr()?);
+                            if self.peek_op(',') {
+                                self.pos += 1;
+                                continue;
+                            }
+                            break;
+                        }
+                    }
+                    self.expect_op(')')?;
+                    return call(&name, &args);
+                }
+                match name.as_str() {
+                    "pi" | "PI" | "π" => Ok(Value::Num(std::f64::consts::PI, None)),
+                    "e" => Ok(Value::Num(std::f64::consts::E, None)),
+                    "today" | "now" => Ok(Value::Date(
+                        (super::audit_log::now_ms() / 86_400_000) as i64,
+                    )),
+                    _ => bail!("未知标识符: {}", name),
+                }
+            }
+            Some(token) => bail!("此处不应出现 {:?}", token),
+            None => bail!("表达式不完整"),
+        }
+    }
+}
+
+fn call(name: &str, args: &[Value]) -> Result<Value> {
+    let nums: Vec<f64> = args
+        .iter()
+        .map(|a| match a {
+            Value::Num(v, None) => Ok(*v),
+            _ => Err(anyhow!("函数 {} 只接受无单位数值", name)),
+        })
+        .collect::<Result<_>>()?;
+    let unary = |f: fn(f64) -> f64| match nums.as_slice() {
+        [x] => Ok(f(*x)),
+        _ => Err(anyhow!("函数 {} 需要 1 个参数", name)),
+    };
+    let result = match name {
+        "sqrt" => unary(f64::sqrt)?,
+        "abs" => unary(f64::abs)?,
+        "exp" => unary(f64::exp)?,
+        "ln" => unary(f64::ln)?,
+        "log10" => unary(f64::log10)?,
+        "log2" => unary(f64::log2)?,
+        "sin" => unary(f64::sin)?,
+        "cos" => unary(f64::cos)?,
+        "tan" => unary(f64::tan)?,
+        "asin" => unary(f64::asin)?,
+        "acos" => unary(f64::acos)?,
+        "atan" => unary(f64::atan)?,
+        "floor" => unary(f64::floor)?,
+        "ceil" => unary(f64::ceil)?,
+        "round" => unary(f64::round)?,
+        "log" => match nums.as_slice() {
+            [x] => x.ln(),
+            [x, base] => x.log(*base),
+            _ => bail!("函数 log 需要 1 或 2 个参数"),
+        },
+        "pow" => match nums.as_slice() {
+            [x, y] => x.powf(*y),
+            _ => bail!("函数 pow 需要 2 个参数"),
+        },
+        "min" | "max" if !nums.is_empty() => nums
+            .iter()
+            .copied()
+            .reduce(if name == "min" { f64::min } else { f64::max })
+            .unwrap_or_default(),
+        "min" | "max" => bail!("函数 {} 至少需要 1 个参数", name),
+        _ => bail!("未知函数: {}", name),
+    };
+    Ok(Value::Num(result, None))
+}
+
+fn to_base(v: f64, unit: &Unit) -> f64 {
+    v * unit.factor + unit.offset
+}
+
+fn from_base(base: f64, unit: &Unit) -> f64 {
+    (base - unit.offset) / unit.factor
+}
+
+/// 时间量 / 无单位数值 → 天数
+fn as_days(value: Value) -> Result<f64> {
+    match value {
+        Value::Num(v, None) => Ok(v),
+        Value::Num(v, Some(unit)) if unit.dim == Dim::Time => Ok(to_base(v, unit) / 86_400.0),
+        _ => bail!("日期只能加减天数或时间量"),
+    }
+}
+
+/// 加减（sign 为 ±1）
+fn add(left: Value, right: Value, sign: f64) -> Result<Value> {
+    match (left, right) {
+        (Value::Num(a, None), Value::Num(b, None)) => Ok(Value::Num(a + sign * b, None)),
+        (Value::Num(a, Some(ua)), Value::Num(b, Some(ub))) => {
+            if ua.dim != ub.dim {
+                bail!("{} 与 {} 不能相加减", ua.dim.name(), ub.dim.name());
+            }
+            // 温度带偏移，换算后相加没有意义，只允许同单位
+            if ua.dim == Dim::Temperature && !std::ptr::eq(ua, ub) {
+                bail!("不同温度单位不能直接相加减，请先转换");
+            }
+            let b_in_a = b * ub.factor / ua.factor;
+            Ok(Value::Num(a + sign * b_in_a, Some(ua)))
+        }
+        (Value::Num(..), Value::Num(..)) => bail!("带单位与无单位数值不能相加减"),
+        (Value::Date(d), other @ Value::Num(..)) => {
+            let days = as_days(other)?;
+            if days.fract() != 0.0 {
+                bail!("日期只能加减整天");
+            }
+            Ok(Value::Date(d + (sign * days) as i64))
+        }
+        (Value::Date(a), Value::Date(b)) if sign < 0.0 => {
+            Ok(Value::Num((a - b) as f64, find_unit("days")))
+        }
+        _ => bail!("不支持的日期运算"),
+    }
+}
+
+fn mul(left: Value, right: Value, op: char) -> Result<Value> {
+    let (Value::Num(a, ua), Value::Num(b, ub)) = (left, right) else {
+        bail!("日期不能参与乘除");
+    };
+    let apply = |x: f64, y: f64| -> Result<f64> {
+        match op {
+            '*' => Ok(x * y),
+            '/' if y == 0.0 => bail!("除数为 0"),
+            '/' => Ok(x / y),
+            '%' if y == 0.0 => bail!("取模除数为 0"),
+            _ => Ok(x % y),
+        }
+    };
+    match (ua, ub) {
+        (None, None) => Ok(Value::Num(apply(a, b)?, None)),
+        (Some(u), None) => Ok(Value::Num(apply(a, b)?, Some(u))),
+        (None, Some(u)) if op == '*' => Ok(Value::Num(a * b, Some(u))),
+        // 同量纲相除得到比值
+        (Some(x), Some(y)) if op == '/' && x.dim == y.dim && x.dim != Dim::Temperature => {
+            Ok(Value::Num(apply(to_base(a, x), to_base(b, y))?, None))
+        }
+        _ => bail!("不支持的单位运算"),
+    }
+}
+
+fn convert(value: Value, target: &'static Unit) -> Result<Value> {
+    match value {
+        Value::Num(v, Some(unit)) if unit.dim == target.dim => Ok(Value::Num(
+            from_base(to_base(v, unit), target),
+            Some(target),
+        )),
+        Value::Num(_, Some(unit)) => bail!(
+            "{} 不能转换为{}单位 {}",
+            unit.dim.name(),
+            target.dim.name(),
+            target.names[0]
+        ),
+        Value::Num(_, None) => bail!("无单位数值不能转换为 {}", target.names[0]),
+        Value::Date(_) => bail!("日期不能做单位转换"),
+    }
+}
+
+/// 保留 12 位有效数字，去掉多余的 0；极大 / 极小值用科学计数法
+fn format_number(v: f64) -> String {
+    if v == 0.0 {
+        return "0".to_string();
+    }
+    if !(1e-6..1e15).contains(&v.abs()) {
+        return format!("{:.11e}", v);
+    }
+    let magnitude = v.abs().log10().floor() as i32;
+    let decimals = (11 - magnitude).clamp(0, 17) as usize;
+    let text = format!("{:.*}", decimals, v);
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        text
+    }
+}
+
+fn format_date(days: i64) -> String {
+    let (y, m, d) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+/// 求值并返回本地工具应答
+pub(crate) fn handle(body: &[u8]) -> Result<Json> {
+    let req_json: Json =
+        serde_json::from_slice(body).map_err(|e| anyhow!("请求 JSON 解析失败: {}", e))?;
+    let expression = req_json["params"]["expression"]
+        .as_str()
+        .filter(|e| !e.trim().is_empty())
+        .ok_or_else(|| anyhow!("缺少 expression 参数"))?;
+    if expression.chars().count() > MAX_EXPRESSION_CHARS {
+        bail!("表达式过长（上限 {} 字符）", MAX_EXPRESSION_CHARS);
+    }
+
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        pos: 0,
+        depth: 0,
+    };
+    let result = match parser.parse()? {
+        Value::Num(v, _) if !v.is_finite() => bail!("结果不是有限数值"),
+        Value::Num(v, unit) => {
+            let unit = unit.map(|u| u.names[0]);
+            let text = match unit {
+                Some(u) => format!("{} {}", format_number(v), u),
+                None => format_number(v),
+            };
+            json!({ "expression": expression, "value": v, "unit": unit, "text": text })
+        }
+        Value::Date(days) => {
+            let text = format_date(days);
+            json!({ "expression": expression, "value": text, "unit": null, "text": text })
+        }
+    };
+    tracing::info!("本地计算: {} = {}", expression, result["text"]);
+    Ok(json!({ "ok": true, "result": result }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> Result<String> {
+        let body = json!({ "params": { "expression": expression } }).to_string();
+        let reply = handle(body.as_bytes())?;
+        Ok(reply["result"]["text"].as_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn arithmetic_precedence() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), "7");
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), "9");
+        assert_eq!(eval("2 ^ 3 ^ 2").unwrap(), "512");
+        assert_eq!(eval("7 % 3").unwrap(), "1");
+        assert_eq!(eval("-3 + 5").unwrap(), "2");
+        assert_eq!(eval("0.1 + 0.2").unwrap(), "0.3");
+    }
+
+    #[test]
+    fn functions_and_constants() {
+        assert_eq!(eval("sqrt(16) + max(1, 5)").unwrap(), "9");
+        assert_eq!(eval("log(8, 2)").unwrap(), "3");
+        assert_eq!(eval("round(pi * 100)").unwrap(), "314");
+    }
+
+    #[test]
+    fn unit_conversion() {
+        assert_eq!(eval("3 ft + 2 in to cm").unwrap(), "96.52 cm");
+        assert_eq!(eval("98.6 F to C").unwrap(), "37 C");
+        assert_eq!(eval("1.5 GiB to MB").unwrap(), "1610.612736 MB");
+    }
+
+    #[test]
+    fn rejects_mixed_dimensions() {
+        assert!(eval("1 m + 1 kg").is_err());
+        assert!(eval("5 to cm").is_err());
+    }
+
+    #[test]
+    fn date_arithmetic() {
+        assert_eq!(eval("2024-02-28 + 2 days").unwrap(), "2024-03-01");
+        assert!(eval("2024-03-01 - 2024-02-01").unwrap().starts_with("29"));
+        assert!(eval("2023-02-29 + 1 day").is_err());
+    }
+
+    #[test]
+    fn rejects_non_finite_and_oversized_input() {
+        assert!(eval("1 / 0").is_err());
+        assert!(eval("1 +").is_err());
+        let nested = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+        assert!(eval(&nested).is_err());
+        assert!(eval(&"1+".repeat(600)).is_err());
+    }
+}
@@ -0,0 +1,417 @@
+// 容量规划：令牌桶模拟
+//
+// 按分钟记录各路由 / Profile 的请求数与 token 用量（落在 amp_accounting 中），
+// simulate() 用历史流量回放不同的限流 / Key 池 / 预算配置，报告预期排队与 429 比例，
+// 便于在修改配置前评估 Key 池规模。
+// 计数键：traffic:{Unix 分钟}:{route}:{profile}
+//
+// 模拟方式：同一分钟内的请求在 60 秒内均匀到达；每个场景维护请求桶（rpm）与
+// token 桶（tpm），容量按 Key 池大小等比放大。桶不足时排队等待补充，
+// 等待超过 max_queue_wait_secs 视为 429；超出每日 token 预算的请求视为拒绝。
+
+use super::amp_accounting::{self, UsageCounters};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+const KEY_PREFIX: &str = "traffic:";
+/// 推荐 Key 池规模时的搜索上限
+const MAX_SUGGESTED_POOL: u32 = 64;
+/// 推荐 Key 池时可接受的 429 比例
+const ACCEPTABLE_REJECT_RATE: f64 = 0.01;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CapacitySettings {
+    /// 是否按分钟记录流量（关闭后无法模拟）
+    pub record_traffic: bool,
+    /// 模拟场景
+    pub scenarios: Vec<Scenario>,
+}
+
+/// 一个待评估的配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    pub name: String,
+    /// 仅回放该路由（claude / codex / gemini / azure），None 表示全部
+    pub route: Option<String>,
+    /// 仅回放该 Profile，None 表示全部
+    pub profile: Option<String>,
+    /// 单个 Key 每分钟请求数上限
+    pub requests_per_minute: Option<u32>,
+    /// 单个 Key 每分钟 token 上限（输入 + 输出）
+    pub tokens_per_minute: Option<u64>,
+    /// 突发容量（请求数），默认等于 requests_per_minute
+    pub burst: Option<u32>,
+    /// Key 池大小
+    pub key_pool: u32,
+    /// 每日 token 预算（整个池）
+    pub daily_token_budget: Option<u64>,
+    /// 客户端可接受的最长排队时间（秒）
+    pub max_queue_wait_secs: f64,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            route: None,
+            profile: None,
+            requests_per_minute: None,
+            tokens_per_minute: None,
+            burst: None,
+            key_pool: 1,
+            daily_token_budget: None,
+            max_queue_wait_secs: 30.0,
+        }
+    }
+}
+
+/// 单个场景的模拟结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScenarioReport {
+    pub name: String,
+    pub requests: u64,
+    /// 无需等待直接发出
+    pub immediate: u64,
+    /// 排队后发出
+    pub queued: u64,
+    /// 排队超时（预期 429）
+    pub rejected: u64,
+    /// 超出每日预算
+    pub over_budget: u64,
+    pub reject_rate: f64,
+    pub avg_wait_secs: f64,
+    pub p95_wait_secs: f64,
+    pub max_wait_secs: f64,
+    /// 历史峰值（请求 / 分钟）
+    pub peak_rpm: u64,
+    /// 429 比例低于 1% 所需的最小 Key 池（搜索上限内无解时为 None）
+    pub suggested_key_pool: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CapacityReport {
+    /// 回放的时间范围（Unix 分钟）
+    pub from_minute: Option<u64>,
+    pub to_minute: Option<u64>,
+    pub total_requests: u64,
+    pub scenarios: Vec<ScenarioReport>,
+}
+
+fn key_for(minute: u64, route: &str, profile: &str) -> String {
+    format!("{}{}:{}:{}", KEY_PREFIX, minute, route, profile)
+}
+
+fn current_minute() -> u64 {
+    super::audit_log::now_ms() / 60_000
+}
+
+/// 记录一次请求到达（未开启 record_traffic 时忽略）
+pub(crate) fn record_request(settings: &CapacitySettings, route: &str, profile: &str) {
+    if settings.record_traffic {
+        amp_accounting::record_usage(
+            &key_for(current_minute(), route, profile),
+            UsageCounters {
+                requests: 1,
+                ..Default::default()
+            },
+        );
+    }
+}
+
+/// 响应侧补记 token 用量（未开启 record_traffic 时忽略）
+pub(crate) fn record_tokens(
+    settings: &CapacitySettings,
+    route: &str,
+    profile: &str,
+    input_tokens: u64,
+    output_tokens: u64,
+) {
+    if settings.record_traffic && input_tokens + output_tokens > 0 {
+        amp_accounting::record_usage(
+            &key_for(current_minute(), route, profile),
+            UsageCounters {
+                input_tokens,
+                output_tokens,
+                ..Default::default()
+            },
+        );
+    }
+}
+
+/// 历史流量：Unix 分钟 → (请求数, token 数)，按场景过滤
+fn load_traffic(scenario: &Scenario, since_minute: u64) -> BTreeMap<u64, (u64, u64)> {
+    let mut minutes: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+    for (key, c) in amp_accounting::snapshot_counters() {
+        let Some(rest) = key.strip_prefix(KEY_PREFIX) else {
+            continue;
+        };
+        let mut parts = rest.splitn(3, ':');
+        let (Some(minute), Some(route), Some(profile)) = (
+            parts.next().and_then(|m| m.parse::<u64>().ok()),
+            parts.next(),
+            parts.next(),
+        ) else {
+            continue;
+        };
+        if minute < since_minute
+            || scenario.route.as_deref().is_some_and(|r| r != route)
+            || scenario.profile.as_deref().is_some_and(|p| p != profile)
+        {
+            continue;
+        }
+        let entry = minutes.entry(minute).or_default();
+        entry.0 += c.requests;
+        entry.1 += c.input_tokens + c.output_tokens;
+    }
+    minutes
+}
+
+/// 令牌桶（允许透支表示排队预约）
+struct Bucket {
+    capacity: f64,
+    per_sec: f64,
+    level: f64,
+    last: f64,
+}
+
+impl Bucket {
+    fn new(capacity: f64, per_minute: f64) -> Self {
+        Self {
+            capacity,
+            per_sec: per_minute / 60.0,
+            level: capacity,
+            last: 0.0,
+        }
+    }
+
+    fn refill(&mut self, now: f64) {
+        self.level = (self.level + (now - self.last) * self.per_sec).min(self.capacity);
+        self.last = now;
+    }
+
+    /// 取走 cost 需等待的秒数
+    fn wait_for(&self, cost: f64) -> f64 {
+        if self.level >= cost {
+            0.0
+        } else {
+            (cost - self.level) / self.per_sec
+        }
+    }
+}
+
+fn run_scenario(
+    scenario: &Scenario,
+    pool: u32,
+    traffic: &BTreeMap<u64, (u64, u64)>,
+) -> ScenarioReport {
+    let pool = pool.max(1) as f64;
+    let mut requests_bucket = scenario.requests_per_minute.map(|rpm| {
+        let burst = scenario.burst.unwrap_or(rpm) as f64;
+        Bucket::new(burst * pool, rpm as f64 * pool)
+    });
+    let mut tokens_bucket = scenario
+        .tokens_per_minute
+        .map(|tpm| Bucket::new(tpm as f64 * pool, tpm as f64 * pool));
+    let start = traffic.keys().next().copied().unwrap_or(0);
+    let mut report = ScenarioReport {
+        name: scenario.name.clone(),
+        ..Default::default()
+    };
+    let mut waits = Vec::new();
+    let mut budget_day = u64::MAX;
+    let mut budget_used = 0u64;
+
+    for (&minute, &(count, tokens)) in traffic {
+        report.peak_rpm = report.peak_rpm.max(count);
+        let per_request = tokens.checked_div(count).unwrap_or(0);
+        let day = minute / 1440;
+        if day != budget_day {
+            budget_day = day;
+            budget_used = 0;
+        }
+        for i in 0..count {
+            report.requests += 1;
+            if scenario
+                .daily_token_budget
+                .is_some_and(|b| budget_used + per_request > b)
+            {
+                report.over_budget += 1;
+                continue;
+            }
+            let now = (minute - start) as f64 * 60.0 + i as f64 * 60.0 / count as f64;
+            let mut wait = 0.0f64;
+            if let Some(b) = requests_bucket.as_mut() {
+                b.refill(now);
+                wait = wait.max(b.wait_for(1.0));
+            }
+            if let Some(b) = tokens_bucket.as_mut() {
+                b.refill(now);
+                wait = wait.max(b.wait_for(per_request as f64));
+            }
+            if wait > scenario.max_queue_wait_secs {
+                report.rejected += 1;
+                continue;
+            }
+            if let Some(b) = requests_bucket.as_mut() {
+                b.level -= 1.0;
+            }
+            if let Some(b) = tokens_bucket.as_mut() {
+                b.level -= per_request as f64;
+            }
+            budget_used += per_request;
+            if wait > 0.0 {
+                report.queued += 1;
+            } else {
+                report.immediate += 1;
+            }
+            waits.push(wait);
+        }
+    }
+
+    if report.requests > 0 {
+        report.reject_rate = (report.rejected + report.over_budget) as f64 / report.requests as f64;
+    }
+    if !waits.is_empty() {
+        waits.sort_by(|a, b| a.total_cmp(b));
+        report.avg_wait_secs = waits.iter().sum::<f64>() / waits.len() as f64;
+        report.p95_wait_secs = waits[(waits.len() - 1) * 95 / 100];
+        report.max_wait_secs = waits[waits.len() - 1];
+    }
+    report
+}
+
+/// 回放最近 days 天的流量，逐个场景模拟
+pub fn simulate(scenarios: &[Scenario], days: u64) -> CapacityReport {
+    let since = current_minute().saturating_sub(days.max(1) * 1440);
+    let mut report = CapacityReport::default();
+    for scenario in scenarios {
+        let traffic = load_traffic(scenario, since);
+        if scenario.route.is_none() && scenario.profile.is_none() {
+            report.total_requests = traffic.values().map(|(n, _)| n).sum();
+            report.from_minute = traffic.keys().next().copied();
+            report.to_minute = traffic.keys().next_back().copied();
+        }
+        let mut result = run_scenario(scenario, scenario.key_pool, &traffic);
+        // 预算拒绝与 Key 池规模无关，只按排队超时评估
+        result.suggested_key_pool = (1..=MAX_SUGGESTED_POOL).find(|&pool| {
+            let r = run_scenario(scenario, pool, &traffic);
+            r.requests == 0 || (r.rejected as f64 / r.requests as f64) < ACCEPTABLE_REJECT_RATE
+        });
+        report.scenarios.push(result);
+    }
+    report
+}
+
+/// 文本报告（命令行输出）
+pub fn format_report(report: &CapacityReport) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "容量模拟报告（历史请求 {} 次）", report.total_requests);
+    for s in &report.scenarios {
+        let _ = writeln!(out, "\n[{}]", s.name);
+        let _ = writeln!(
+            out,
+            "  请求 {}：直接发出 {}，排队 {}，预期 429 {}，超预算 {}（拒绝率 {:.2}%）",
+            s.requests,
+            s.immediate,
+            s.queued,
+            s.rejected,
+            s.over_budget,
+            s.reject_rate * 100.0
+        );
+        let _ = writeln!(
+            out,
+            "  排队等待：平均 {:.1}s，P95 {:.1}s，最长 {:.1}s；峰值 {} 次/分钟",
+            s.avg_wait_secs, s.p95_wait_secs, s.max_wait_secs, s.peak_rpm
+        );
+        match s.suggested_key_pool {
+            Some(pool) => {
+                let _ = writeln!(out, "  建议 Key 池：{} 个（429 < 1%）", pool);
+            }
+            None => {
+                let _ = writeln!(
+                    out,
+                    "  建议 Key 池：{} 个以内无法将 429 降到 1% 以下",
+                    MAX_SUGGESTED_POOL
+                );
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenario(rpm: u32, max_wait: f64) -> Scenario {
+        Scenario {
+            name: "test".to_string(),
+            requests_per_minute: Some(rpm),
+            max_queue_wait_secs: max_wait,
+            ..Default::default()
+        }
+    }
+
+    fn traffic(minutes: &[(u64, u64, u64)]) -> BTreeMap<u64, (u64, u64)> {
+        minutes.iter().map(|&(m, n, t)| (m, (n, t))).collect()
+    }
+
+    #[test]
+    fn traffic_within_limit_is_immediate() {
+        let report = run_scenario(&scenario(60, 0.0), 1, &traffic(&[(0, 30, 0), (1, 30, 0)]));
+        assert_eq!(report.requests, 60);
+        assert_eq!(report.immediate, 60);
+        assert_eq!(report.rejected, 0);
+        assert_eq!(report.peak_rpm, 30);
+        assert_eq!(report.max_wait_secs, 0.0);
+    }
+
+    #[test]
+    fn burst_beyond_limit_queues_then_rejects() {
+        let burst = traffic(&[(0, 100, 0)]);
+        let report = run_scenario(&scenario(10, 30.0), 1, &burst);
+        assert_eq!(
+            report.immediate + report.queued + report.rejected,
+            report.requests
+        );
+        assert!(report.queued > 0);
+        assert!(report.rejected > 0);
+        assert!(report.max_wait_secs <= 30.0);
+
+        // Key 池扩大后拒绝减少
+        let larger = run_scenario(&scenario(10, 30.0), 8, &burst);
+        assert!(larger.rejected < report.rejected);
+    }
+
+    #[test]
+    fn daily_budget_resets_per_day() {
+        let s = Scenario {
+            daily_token_budget: Some(250),
+            ..scenario(1000, 30.0)
+        };
+        let report = run_scenario(&s, 1, &traffic(&[(0, 4, 400), (1440, 4, 400)]));
+        assert_eq!(report.over_budget, 4);
+        assert_eq!(report.immediate, 4);
+        assert_eq!(report.reject_rate, 0.5);
+    }
+
+    #[test]
+    fn report_mentions_suggested_pool() {
+        let report = CapacityReport {
+            total_requests: 3,
+            scenarios: vec![ScenarioReport {
+                name: "s".to_string(),
+                suggested_key_pool: Some(2),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let text = format_report(&report);
+        assert!(text.contains("[s]"));
+        assert!(text.contains("建议 Key 池：2 个"));
+        assert_eq!(key_for(7, "claude", "p"), "traffic:7:claude:p");
+    }
+}
@@ -0,0 +1,263 @@
+// 故障注入（韧性验证）
+//
+// 在测试 / 预发配置中启用，用于验证重试、故障转移与流中断处理：
+// - 随机延迟：按比例在发送前等待 [delay_min_ms, delay_max_ms]
+// - 注入错误：按比例不访问上游，直接返回 error_statuses 中的状态码
+// - 断流：按比例在响应体输出 drop_after_bytes 字节后中断连接
+// 设置 seed 后决策序列可复现（同一进程内按请求顺序确定）；未设置则每次随机。
+// 仅对 profiles 中列出的路由 / Profile / 配置名生效（为空时对全部生效）。
+// 环境变量 DC_CHAOS=off 可强制关闭，防止误带到生产。
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const CHAOS_ENV: &str = "DC_CHAOS";
+
+/// 决策计数器（有 seed 时与 seed 组合生成确定序列）
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChaosSettings {
+    pub enabled: bool,
+    /// 随机种子；设置后注入决策可复现
+    pub seed: Option<u64>,
+    /// 生效范围：路由名或 Profile / 配置名，空表示全部
+    pub profiles: Vec<String>,
+    /// 延迟注入比例（0~100）
+    pub delay_percent: f64,
+    pub delay_min_ms: u64,
+    pub delay_max_ms: u64,
+    /// 错误注入比例（0~100）
+    pub error_percent: f64,
+    /// 注入的状态码，按顺序轮换
+    pub error_statuses: Vec<u16>,
+    /// 断流比例（0~100）
+    pub drop_percent: f64,
+    /// 断流前放行的字节数
+    pub drop_after_bytes: usize,
+}
+
+impl Default for ChaosSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            seed: None,
+            profiles: Vec::new(),
+            delay_percent: 0.0,
+            delay_min_ms: 200,
+            delay_max_ms: 2_000,
+            error_percent: 0.0,
+            error_statuses: vec![429, 500],
+            drop_percent: 0.0,
+            drop_after_bytes: 512,
+        }
+    }
+}
+
+impl ChaosSettings {
+    fn applies_to(&self, route: &str, name: &str) -> bool {
+        if !self.enabled || std::env::var(CHAOS_ENV).is_ok_and(|v| v == "off") {
+            return false;
+        }
+        self.profiles.is_empty() || self.profiles.iter().any(|p| p == route || p == name)
+    }
+
+    /// 下一个 [0, 1) 随机数
+    fn next_unit(&self) -> f64 {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let raw = match self.seed {
+            Some(seed) => splitmix64(seed ^ n.wrapping_mul(0x9E37_79B9_7F4A_7C15)),
+            None => uuid::Uuid::new_v4().as_u128() as u64,
+        };
+        (raw >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn roll(&self, percent: f64) -> bool {
+        percent > 0.0 && self.next_unit() * 100.0 < percent
+    }
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// 发送前调用：可能等待一段时间，或返回注入的错误响应（不再访问上游）
+pub(crate) async fn before_send(
+    settings: &ChaosSettings,
+    route: &str,
+    name: &str,
+) -> Option<reqwest::Response> {
+    if !settings.applies_to(route, name) {
+        return None;
+    }
+    if settings.roll(settings.delay_percent) {
+        let span = settings.delay_max_ms.saturating_sub(settings.delay_min_ms);
+        let delay = settings.delay_min_ms + (settings.next_unit() * span as f64) as u64;
+        tracing::warn!("故障注入: {} 延迟 {}ms", name, delay);
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+    }
+    if settings.error_statuses.is_empty() || !settings.roll(settings.error_percent) {
+        return None;
+    }
+    let index = COUNTER.load(Ordering::Relaxed) as usize % settings.error_statuses.len();
+    let status = settings.error_statuses[index];
+    tracing::warn!("故障注入: {} 返回 {}", name, status);
+    let body = json!({
+        "type": "error",
+        "error": { "type": "chaos_injected", "message": format!("故障注入状态码 {}", status) }
+    });
+    let mut builder = hyper::http::Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if matches!(status, 429 | 529) {
+        builder = builder.header("retry-after", "1");
+    }
+    builder
+        .body(reqwest::Body::from(body.to_string()))
+        .ok()
+        .map(reqwest::Response::from)
+}
+
+/// 收到响应后调用：可能包装响应体，在放行 drop_after_bytes 字节后中断
+pub(crate) fn after_response(
+    settings: &ChaosSettings,
+    route: &str,
+    name: &str,
+    response: reqwest::Response,
+) -> reqwest::Response {
+    if !settings.applies_to(route, name) || !settings.roll(settings.drop_percent) {
+        return response;
+    }
+    tracing::warn!(
+        "故障注入: {} 响应体将在 {} 字节后中断",
+        name,
+        settings.drop_after_bytes
+    );
+    let mut builder = hyper::http::Response::builder().status(response.status());
+    for (key, value) in response.headers() {
+        if key != hyper::header::CONTENT_LENGTH {
+            builder = builder.header(key, value);
+        }
+    }
+    let mut remaining = settings.drop_after_bytes;
+    let truncated = response.bytes_stream().map(move |chunk| -> Result<Bytes> {
+        let chunk = chunk.map_err(|e| anyhow!("上游读取失败: {}", e))?;
+        if remaining == 0 {
+            return Err(anyhow!("故障注入: 连接已中断"));
+        }
+        let take = chunk.len().min(remaining);
+        remaining -= take;
+        Ok(chunk.slice(..take))
+    });
+    // 放行部分后追加一次错误，确保下游看到中断而非正常结束
+    let failing = truncated
+        .chain(futures_util::stream::once(async {
+            Err(anyhow!("故障注入: 连接已中断"))
+        }))
+        .scan(false, |stopped, item| {
+            let out = if *stopped {
+                None
+            } else {
+                *stopped = item.is_err();
+                Some(item)
+            };
+            async move { out }
+        });
+    match builder.body(reqwest::Body::wrap_stream(failing)) {
+        Ok(resp) => reqwest::Response::from(resp),
+        Err(e) => {
+            tracing::warn!("故障注入: 构造断流响应失败: {}", e);
+            reqwest::Response::from(hyper::http::Response::new(reqwest::Body::from(Vec::new())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> ChaosSettings {
+        ChaosSettings {
+            enabled: true,
+            seed: Some(7),
+            ..Default::default()
+        }
+    }
+
+    fn response(body: &'static str) -> reqwest::Response {
+        reqwest::Response::from(
+            hyper::http::Response::builder()
+                .status(200)
+                .header("content-length", body.len())
+                .body(reqwest::Body::from(body))
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn scope_limits_injection() {
+        let mut settings = enabled();
+        assert!(settings.applies_to("claude", "any"));
+        settings.profiles = vec!["backup".to_string()];
+        assert!(settings.applies_to("codex", "backup"));
+        assert!(!settings.applies_to("claude", "primary"));
+        assert!(!ChaosSettings::default().applies_to("claude", "any"));
+    }
+
+    #[test]
+    fn rolls_respect_bounds() {
+        let settings = enabled();
+        for _ in 0..100 {
+            let unit = settings.next_unit();
+            assert!((0.0..1.0).contains(&unit));
+            assert!(!settings.roll(0.0));
+            assert!(settings.roll(100.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn injected_error_skips_upstream() {
+        let settings = ChaosSettings {
+            error_percent: 100.0,
+            error_statuses: vec![429],
+            ..enabled()
+        };
+        let resp = before_send(&settings, "claude", "p").await.unwrap();
+        assert_eq!(resp.status(), 429);
+        assert_eq!(resp.headers()["retry-after"], "1");
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "chaos_injected");
+        assert!(before_send(&enabled(), "claude", "p").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn dropped_stream_ends_with_error() {
+        let settings = ChaosSettings {
+            drop_percent: 100.0,
+            drop_after_bytes: 4,
+            ..enabled()
+        };
+        let resp = after_response(&settings, "claude", "p", response("0123456789"));
+        assert!(resp.headers().get("content-length").is_none());
+        let mut stream = resp.bytes_stream();
+        let mut received = Vec::new();
+        let mut failed = false;
+        while let Some(item) = stream.next().await {
+            match item {
+                Ok(chunk) => received.extend_from_slice(&chunk),
+                Err(_) => failed = true,
+            }
+        }
+        assert_eq!(received, b"0123");
+        assert!(failed);
+    }
+}
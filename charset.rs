@@ -0,0 +1,134 @@
+// 响应字符集检测与转码
+//
+// 本地工具读取的网页不一定是 UTF-8（GBK / Shift-JIS / ISO-8859-1 等），直接按 UTF-8 解析会失败。
+// 字符集按以下顺序确定，再转码为 UTF-8：
+// 1. 字节序标记（BOM）
+// 2. Content-Type 响应头的 charset 参数
+// 3. 文档开头的 <meta charset> / <meta http-equiv="Content-Type"> 声明
+// 4. 都没有时：合法 UTF-8 按 UTF-8，否则按 windows-1252（与浏览器默认一致）
+// 转码复用 reqwest 的 charset 支持（encoding_rs），标签按 WHATWG 规则解析（如 gb2312 → GBK）。
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// 查找 meta 声明的字节范围
+const SNIFF_BYTES: usize = 1024;
+
+static META_CHARSET: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(?i)<meta[^>]+charset\s*=\s*["']?\s*([a-z0-9_\-:.]+)"#).unwrap());
+
+/// 从 Content-Type 取 charset 参数
+fn from_content_type(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches(['"', '\'']).to_string())
+            .filter(|v| !v.is_empty())
+    })
+}
+
+/// 从文档开头的 meta 标签取 charset
+fn from_meta(data: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(&data[..data.len().min(SNIFF_BYTES)]);
+    META_CHARSET.captures(&head).map(|caps| caps[1].to_string())
+}
+
+fn is_utf8_label(label: &str) -> bool {
+    matches!(label.to_ascii_lowercase().as_str(), "utf-8" | "utf8")
+}
+
+/// 检测字符集并转码为 UTF-8
+pub(crate) async fn decode(content_type: Option<&str>, data: Vec<u8>) -> Result<String> {
+    let has_bom = data.starts_with(&[0xEF, 0xBB, 0xBF])
+        || data.starts_with(&[0xFF, 0xFE])
+        || data.starts_with(&[0xFE, 0xFF]);
+    let declared = content_type
+        .and_then(from_content_type)
+        .or_else(|| from_meta(&data));
+    let label = match declared {
+        Some(label) => label,
+        None if !has_bom => match String::from_utf8(data) {
+            Ok(text) => return Ok(text),
+            Err(e) => {
+                tracing::debug!("响应未声明字符集且不是 UTF-8，按 windows-1252 解码");
+                return transcode(e.into_bytes(), "windows-1252").await;
+            }
+        },
+        None => "utf-8".to_string(),
+    };
+    if is_utf8_label(&label) && !has_bom {
+        if let Ok(text) = std::str::from_utf8(&data) {
+            return Ok(text.to_string());
+        }
+    }
+    tracing::debug!("响应字符集: {}", label);
+    transcode(data, &label).await
+}
+
+/// 按标签转码（未知标签按 UTF-8 容错解码）；BOM 优先于标签
+async fn transcode(data: Vec<u8>, label: &str) -> Result<String> {
+    let response = hyper::http::Response::builder()
+        .body(reqwest::Body::from(data))
+        .map_err(|e| anyhow!("构造转码响应失败: {}", e))?;
+    reqwest::Response::from(response)
+        .text_with_charset(label)
+        .await
+        .map_err(|e| anyhow!("响应转码失败: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// "中文" 的 GBK 编码
+    const GBK: [u8; 4] = [0xD6, 0xD0, 0xCE, 0xC4];
+
+    #[test]
+    fn parses_declarations() {
+        assert_eq!(
+            from_content_type("text/html; charset=\"GB2312\"").as_deref(),
+            Some("GB2312")
+        );
+        assert_eq!(from_content_type("text/html"), None);
+        assert_eq!(
+            from_meta(br#"<html><head><meta charset="shift_jis">"#).as_deref(),
+            Some("shift_jis")
+        );
+        assert_eq!(
+            from_meta(br#"<meta http-equiv="Content-Type" content="text/html; charset=gbk">"#)
+                .as_deref(),
+            Some("gbk")
+        );
+    }
+
+    #[tokio::test]
+    async fn transcodes_declared_charset() {
+        let text = decode(Some("text/html; charset=gb2312"), GBK.to_vec())
+            .await
+            .unwrap();
+        assert_eq!(text, "中文");
+        let mut page = br#"<meta charset="gbk"><p>"#.to_vec();
+        page.extend_from_slice(&GBK);
+        assert!(decode(None, page).await.unwrap().ends_with("<p>中文"));
+    }
+
+    #[tokio::test]
+    async fn undeclared_falls_back_to_utf8_then_windows_1252() {
+        assert_eq!(
+            decode(None, "中文".as_bytes().to_vec()).await.unwrap(),
+            "中文"
+        );
+        assert_eq!(
+            decode(None, vec![b'c', b'a', b'f', 0xE9]).await.unwrap(),
+            "café"
+        );
+    }
+
+    #[tokio::test]
+    async fn bom_wins_over_label() {
+        let utf16 = vec![0xFF, 0xFE, b'h', 0, b'i', 0];
+        assert_eq!(decode(Some("text/plain"), utf16).await.unwrap(), "hi");
+    }
+}
@@ -0,0 +1,390 @@
+// 廉价模型本地工具（summarize / translate），以及 webSearch2 的答案综合
+//
+// AMP 以本地工具方式调用（?summarize / ?translate），由配置的廉价模型完成压缩或翻译，
+// 让 Agent 不必注册外部工具即可卸载这类工作。
+// - summarize：params.text 为待压缩文本，可选 params.focus 指定关注点，返回简明摘要
+// - translate：params.text 为原文，params.targetLanguage 为目标语言，可选 params.sourceLanguage；
+//   配置了 offline_translate_url（LibreTranslate 兼容接口）时优先离线翻译，不消耗模型额度
+// 上游取自 profile 指定的代理配置，未指定时沿用 AMP 当前为 slot 槽位选择的 Profile；
+// 请求格式按槽位决定：claude → Messages，codex → Chat Completions，gemini → generateContent。
+// 用量计入该槽位的 Profile 预算（见 budget.rs），金额同时计入本次工具调用的费用（见 tool_credits.rs）。
+// synthesize 供 webSearch2 使用：把前几条搜索结果编号后交给廉价模型，生成带 [n] 引用的简短回答。
+
+use super::app_state::AppState;
+use super::budget;
+use super::secret_store;
+use super::stall_guard;
+use super::tool_credits;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// 摘要固定提示词
+const SUMMARIZE_PROMPT: &str =
+    "You are a summarization tool. Summarize the text provided by the user \
+concisely and faithfully. Keep concrete facts, names, numbers, file paths, identifiers and \
+error messages; drop repetition and filler. Do not add information that is not in the text. \
+Reply with the summary only, in the same language as the text.";
+
+/// 翻译固定提示词（目标语言追加在后）
+const TRANSLATE_PROMPT: &str = "You are a translation tool. Translate faithfully and completely, \
+preserving formatting, code, identifiers, URLs and proper nouns. Reply with the translation only.";
+
+/// 搜索答案综合提示词
+const SYNTHESIZE_PROMPT: &str = "You are a search answer tool. Answer the user's question using \
+only the numbered sources provided. Be brief (at most a few short paragraphs), cite sources inline \
+as [n] after the statements they support, and do not cite sources that are not listed. If the \
+sources do not answer the question, say so. Reply in the same language as the question.";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CheapModelSettings {
+    /// 使用的代理配置 ID（None 时沿用 slot 槽位当前 Profile）
+    pub profile: Option<String>,
+    /// 槽位：claude / codex / gemini，决定请求格式
+    pub slot: String,
+    pub model: String,
+    /// 最大输出 token
+    pub max_tokens: u64,
+    /// 输入文本最大字符数，超出截断
+    pub max_input_chars: usize,
+    /// 离线翻译服务地址（LibreTranslate 兼容，如 http://127.0.0.1:5000）
+    pub offline_translate_url: Option<String>,
+}
+
+impl Default for CheapModelSettings {
+    fn default() -> Self {
+        Self {
+            profile: None,
+            slot: "claude".to_string(),
+            model: "claude-haiku-4-5".to_string(),
+            max_tokens: 1024,
+            max_input_chars: 200_000,
+            offline_translate_url: None,
+        }
+    }
+}
+
+/// 解析上游地址与 Key
+fn resolve_upstream(state: &AppState, settings: &CheapModelSettings) -> Result<(String, String)> {
+    if let Some(profile_id) = settings.profile.as_deref() {
+        let config = state
+            .proxy_config_manager()
+            .ok()
+            .and_then(|mgr| mgr.get_config(profile_id).ok().flatten())
+            .ok_or_else(|| anyhow!("廉价模型配置 {} 不存在", profile_id))?;
+        return match (
+            config.real_base_url,
+            secret_store::reveal_opt(config.real_api_key),
+        ) {
+            (Some(base_url), Some(api_key)) => Ok((base_url, api_key)),
+            _ => Err(anyhow!("廉价模型配置 {} 缺少上游地址或 Key", profile_id)),
+        };
+    }
+    let (claude, codex, gemini) = state.amp_selection()?;
+    let slot = match settings.slot.as_str() {
+        "claude" => claude,
+        "codex" => codex,
+        "gemini" => gemini,
+        other => bail!("廉价模型槽位无效: {}", other),
+    };
+    let p = slot.ok_or_else(|| anyhow!("廉价模型：{} 槽位未选择 Profile", settings.slot))?;
+    let api_key = secret_store::reveal_required(&p.api_key, &format!("Profile {}", p.name))?;
+    Ok((p.base_url, api_key))
+}
+
+/// 解析工具参数，返回 (params, 截断后的 text)
+fn parse_params(settings: &CheapModelSettings, body: &[u8]) -> Result<(Value, String)> {
+    let req_json: Value =
+        serde_json::from_slice(body).map_err(|e| anyhow!("请求 JSON 解析失败: {}", e))?;
+    let text = req_json["params"]["text"]
+        .as_str()
+        .filter(|t| !t.trim().is_empty())
+        .ok_or_else(|| anyhow!("缺少 text 参数"))?
+        .chars()
+        .take(settings.max_input_chars)
+        .collect();
+    Ok((req_json["params"].clone(), text))
+}
+
+/// 以 system + 单条 user 消息调用廉价模型，返回文本回复
+async fn complete(
+    state: &AppState,
+    settings: &CheapModelSettings,
+    system: &str,
+    user: &str,
+) -> Result<String> {
+    let (base_url, api_key) = resolve_upstream(state, settings)?;
+    let base = base_url.trim_end_matches('/');
+    let processor_settings = state.settings();
+    let client = state
+        .outbound()
+        .client_for_profile(&processor_settings, &settings.slot);
+    let request = match settings.slot.as_str() {
+        "codex" => client
+            .post(format!("{}/v1/chat/completions", base))
+            .bearer_auth(&api_key)
+            .json(&json!({
+                "model": settings.model,
+                "max_tokens": settings.max_tokens,
+                "messages": [
+                    { "role": "system", "content": system },
+                    { "role": "user", "content": user }
+                ]
+            })),
+        "gemini" => client
+            .post(format!(
+                "{}/v1beta/models/{}:generateContent",
+                base, settings.model
+            ))
+            .header("x-goog-api-key", &api_key)
+            .json(&json!({
+                "systemInstruction": { "parts": [{ "text": system }] },
+                "contents": [{ "role": "user", "parts": [{ "text": user }] }],
+                "generationConfig": { "maxOutputTokens": settings.max_tokens }
+            })),
+        _ => client
+            .post(format!("{}/v1/messages", base))
+            .header("x-api-key", &api_key)
+            .header("anthropic-version", "2023-06-01")
+            .json(&json!({
+                "model": settings.model,
+                "max_tokens": settings.max_tokens,
+                "system": system,
+                "messages": [{ "role": "user", "content": user }]
+            })),
+    };
+
+    tracing::info!(
+        "本地摘要: {} 字符 → {}",
+        user.chars().count(),
+        settings.model
+    );
+    let resp = request.send().await?;
+    let status = resp.status();
+    // 转发 Client 无总超时：按 tools_stall 检测停滞，避免工具调用一直挂起
+    let stall = &processor_settings.tools_stall;
+    let mut stream = resp.bytes_stream();
+    let mut raw = Vec::new();
+    while let Some(chunk) = stall_guard::next_chunk(stall, &mut stream).await? {
+        raw.extend_from_slice(&chunk);
+    }
+    let json: Value = serde_json::from_slice(&raw)?;
+    if !status.is_success() {
+        bail!("廉价模型返回 HTTP {}: {}", status, json["error"]);
+    }
+
+    let (text, input, output) = match settings.slot.as_str() {
+        "codex" => (
+            json["choices"][0]["message"]["content"]
+                .as_str()
+                .map(str::to_string),
+            json["usage"]["prompt_tokens"].as_u64(),
+            json["usage"]["completion_tokens"].as_u64(),
+        ),
+        "gemini" => (
+            json["candidates"][0]["content"]["parts"]
+                .as_array()
+                .map(|parts| parts.iter().filter_map(|p| p["text"].as_str()).collect()),
+            json["usageMetadata"]["promptTokenCount"].as_u64(),
+            json["usageMetadata"]["candidatesTokenCount"].as_u64(),
+        ),
+        _ => (
+            json["content"].as_array().map(|blocks| {
+                blocks
+                    .iter()
+                    .filter_map(|b| b["text"].as_str())
+                    .collect::<Vec<_>>()
+                    .join("")
+            }),
+            json["usage"]["input_tokens"].as_u64(),
+            json["usage"]["output_tokens"].as_u64(),
+        ),
+    };
+    budget::record(
+        &settings.slot,
+        Some(&settings.model),
+        input.unwrap_or(0),
+        output.unwrap_or(0),
+    );
+    tool_credits::charge_tokens(
+        &processor_settings.profile(&settings.slot).budget,
+        &settings.model,
+        input.unwrap_or(0),
+        output.unwrap_or(0),
+    );
+    text.map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| anyhow!("廉价模型未返回文本"))
+}
+
+/// summarize：生成摘要，返回本地工具应答
+pub(crate) async fn summarize(
+    state: &AppState,
+    settings: &CheapModelSettings,
+    body: &[u8],
+) -> Result<Value> {
+    let (params, text) = parse_params(settings, body)?;
+    let user = match params["focus"].as_str() {
+        Some(focus) => format!("Focus on: {}\n\n{}", focus, text),
+        None => text,
+    };
+    tracing::info!(
+        "本地摘要: {} 字符 → {}",
+        user.chars().count(),
+        settings.model
+    );
+    let summary = complete(state, settings, SUMMARIZE_PROMPT, &user).await?;
+    Ok(json!({
+        "ok": true,
+        "result": {
+            "summary": summary,
+            "model": settings.model,
+            "provider": "local"
+        }
+    }))
+}
+
+/// webSearch2 答案综合：基于编号的搜索结果生成带引用的简答
+pub(crate) async fn synthesize(
+    state: &AppState,
+    settings: &CheapModelSettings,
+    question: &str,
+    results: &[Value],
+) -> Result<String> {
+    if results.is_empty() {
+        bail!("没有可用于综合的搜索结果");
+    }
+    let mut user = format!("Question: {}\n\nSources:\n", question);
+    for (i, result) in results.iter().enumerate() {
+        let excerpts: Vec<&str> = result["excerpts"]
+            .as_array()
+            .map(|e| e.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        user.push_str(&format!(
+            "[{}] {}\n{}\n{}\n\n",
+            i + 1,
+            result["title"].as_str().unwrap_or(""),
+            result["url"].as_str().unwrap_or(""),
+            excerpts.join("\n")
+        ));
+    }
+    let user: String = user.chars().take(settings.max_input_chars).collect();
+    tracing::info!(
+        "搜索答案综合: {} 条结果 → {}",
+        results.len(),
+        settings.model
+    );
+    complete(state, settings, SYNTHESIZE_PROMPT, &user).await
+}
+
+/// translate：翻译到目标语言，返回本地工具应答
+pub(crate) async fn translate(
+    state: &AppState,
+    settings: &CheapModelSettings,
+    body: &[u8],
+) -> Result<Value> {
+    let (params, text) = parse_params(settings, body)?;
+    let target = params["targetLanguage"]
+        .as_str()
+        .filter(|t| !t.trim().is_empty())
+        .ok_or_else(|| anyhow!("缺少 targetLanguage 参数"))?;
+    let source = params["sourceLanguage"].as_str();
+
+    if let Some(url) = settings.offline_translate_url.as_deref() {
+        tracing::info!(
+            "本地翻译（离线）: {} 字符 → {}",
+            text.chars().count(),
+            target
+        );
+        let client = state.outbound().tool_client(&state.settings());
+        let translation = translate_offline(&client, url, &text, source, target).await?;
+        return Ok(json!({
+            "ok": true,
+            "result": {
+                "translation": translation,
+                "targetLanguage": target,
+                "provider": "offline"
+            }
+        }));
+    }
+
+    let system = format!(
+        "{} Translate the text provided by the user into {}{}.",
+        TRANSLATE_PROMPT,
+        target,
+        source
+            .map(|s| format!(" (source language: {})", s))
+            .unwrap_or_default()
+    );
+    tracing::info!(
+        "本地翻译: {} 字符 → {} ({})",
+        text.chars().count(),
+        target,
+        settings.model
+    );
+    let translation = complete(state, settings, &system, &text).await?;
+    Ok(json!({
+        "ok": true,
+        "result": {
+            "translation": translation,
+            "targetLanguage": target,
+            "model": settings.model,
+            "provider": "local"
+        }
+    }))
+}
+
+/// LibreTranslate 兼容接口：POST /translate
+async fn translate_offline(
+    client: &reqwest::Client,
+    base_url: &str,
+    text: &str,
+    source: Option<&str>,
+    target: &str,
+) -> Result<String> {
+    let resp = client
+        .post(format!("{}/translate", base_url.trim_end_matches('/')))
+        .json(&json!({
+            "q": text,
+            "source": source.unwrap_or("auto"),
+            "target": target,
+            "format": "text"
+        }))
+        .send()
+        .await?;
+    let status = resp.status();
+    let json: Value = resp.json().await?;
+    if !status.is_success() {
+        bail!("离线翻译服务返回 HTTP {}: {}", status, json["error"]);
+    }
+    json["translatedText"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("离线翻译服务未返回译文"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_params_truncates_text() {
+        let settings = CheapModelSettings {
+            max_input_chars: 3,
+            ..Default::default()
+        };
+        let body = json!({ "params": { "text": "中文文本", "language": "en" } }).to_string();
+        let (params, text) = parse_params(&settings, body.as_bytes()).unwrap();
+        assert_eq!(text, "中文文");
+        assert_eq!(params["language"], "en");
+    }
+
+    #[test]
+    fn parse_params_requires_text() {
+        let settings = CheapModelSettings::default();
+        assert!(parse_params(&settings, br#"{"params":{"text":"  "}}"#).is_err());
+        assert!(parse_params(&settings, br#"{"params":{}}"#).is_err());
+        assert!(parse_params(&settings, b"nope").is_err());
+    }
+}
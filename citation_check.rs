@@ -0,0 +1,196 @@
+// 搜索答案引用核查（webSearch2 答案综合之后）
+//
+// 廉价模型可能编造引用，返回给 Agent 前逐项核查：
+// - [n] 编号超出来源范围：删除该标记
+// - 答案中出现的 URL 不在本次结果里：删除该链接
+// - 引号内的引文（≥ MIN_QUOTE_CHARS 字符）在对应来源（紧随 [n] 时）或任一来源的标题 / 摘录中找不到：
+//   保留原文但在其后标注 [未核实]
+// 比对前统一大小写并折叠空白；发现的问题随应答以 citations.issues 返回。
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::{json, Value};
+
+/// 参与核查的最短引文长度
+const MIN_QUOTE_CHARS: usize = 20;
+/// 未核实引文的标注
+const UNVERIFIED_MARK: &str = " [未核实]";
+
+static CITATION: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[(\d+)\]").unwrap());
+static URL: Lazy<Regex> = Lazy::new(|| Regex::new(r#"https?://[^\s<>"'）)\]]+"#).unwrap());
+static QUOTE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#""([^"\n]+)"|“([^”\n]+)”|「([^」\n]+)」"#).unwrap());
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn normalize_url(url: &str) -> String {
+    url.trim_end_matches(['.', ',', ';', ':', '/'])
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_start_matches("www.")
+        .to_lowercase()
+}
+
+/// 单个来源的可比对文本（标题 + 摘录）
+fn source_text(source: &Value) -> String {
+    let mut text = source["title"].as_str().unwrap_or("").to_string();
+    if let Some(excerpts) = source["excerpts"].as_array() {
+        for excerpt in excerpts.iter().filter_map(Value::as_str) {
+            text.push('\n');
+            text.push_str(excerpt);
+        }
+    }
+    normalize(&text)
+}
+
+/// 核查答案中的引用，返回 (修正后的答案, 问题列表)
+pub(crate) fn check(answer: &str, sources: &[Value]) -> (String, Vec<Value>) {
+    let mut issues = Vec::new();
+
+    // 1. 越界编号
+    let answer = CITATION
+        .replace_all(answer, |caps: &regex::Captures| {
+            let n: usize = caps[1].parse().unwrap_or(0);
+            if (1..=sources.len()).contains(&n) {
+                caps[0].to_string()
+            } else {
+                issues.push(json!({"type": "invalid_citation", "citation": n}));
+                String::new()
+            }
+        })
+        .into_owned();
+
+    // 2. 不在结果中的链接
+    let known: Vec<String> = sources
+        .iter()
+        .filter_map(|s| s["url"].as_str())
+        .map(normalize_url)
+        .collect();
+    let answer = URL
+        .replace_all(&answer, |caps: &regex::Captures| {
+            let url = &caps[0];
+            if known.contains(&normalize_url(url)) {
+                url.to_string()
+            } else {
+                issues.push(json!({"type": "unknown_url", "url": url}));
+                String::new()
+            }
+        })
+        .into_owned();
+
+    // 3. 找不到出处的引文
+    let texts: Vec<String> = sources.iter().map(source_text).collect();
+    let mut out = String::with_capacity(answer.len());
+    let mut last = 0;
+    for caps in QUOTE.captures_iter(&answer) {
+        let Some(whole) = caps.get(0) else { continue };
+        let quote = caps
+            .iter()
+            .skip(1)
+            .flatten()
+            .next()
+            .map(|m| m.as_str())
+            .unwrap_or("");
+        out.push_str(&answer[last..whole.end()]);
+        last = whole.end();
+        if quote.chars().count() < MIN_QUOTE_CHARS {
+            continue;
+        }
+        // 紧随其后的 [n] 指定了出处时只在该来源中查找
+        let cited = CITATION
+            .captures(answer[whole.end()..].trim_start())
+            .filter(|c| c.get(0).is_some_and(|m| m.start() == 0))
+            .and_then(|c| c[1].parse::<usize>().ok());
+        let needle = normalize(quote);
+        let found = match cited {
+            Some(n) => n
+                .checked_sub(1)
+                .and_then(|i| texts.get(i))
+                .is_some_and(|t| t.contains(&needle)),
+            None => texts.iter().any(|t| t.contains(&needle)),
+        };
+        if !found {
+            issues.push(json!({"type": "unverified_quote", "quote": quote, "citation": cited}));
+            out.push_str(UNVERIFIED_MARK);
+        }
+    }
+    out.push_str(&answer[last..]);
+
+    // 删除链接后可能留下空括号与多余空格
+    let cleaned = out
+        .replace("()", "")
+        .replace("<>", "")
+        .lines()
+        .map(|l| {
+            // 保留行首缩进（列表嵌套），折叠行内多余空格
+            let body = l.trim_start();
+            let indent = &l[..l.len() - body.len()];
+            let words: Vec<&str> = body.split(' ').filter(|w| !w.is_empty()).collect();
+            let body = words.join(" ").replace(" .", ".").replace(" ,", ",");
+            format!("{}{}", indent, body)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    (cleaned.trim().to_string(), issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources() -> Vec<Value> {
+        vec![
+            json!({
+                "title": "Rust 1.80 released",
+                "url": "https://blog.rust-lang.org/2024/07/25/Rust-1.80.0.html",
+                "excerpts": ["LazyCell and LazyLock are now   stable in the standard library."]
+            }),
+            json!({ "title": "Tokio docs", "url": "https://tokio.rs/", "excerpts": [] }),
+        ]
+    }
+
+    #[test]
+    fn drops_out_of_range_citations() {
+        let (answer, issues) = check("Released in July [1][3].", &sources());
+        assert_eq!(answer, "Released in July [1].");
+        assert_eq!(
+            issues,
+            vec![json!({"type": "invalid_citation", "citation": 3})]
+        );
+    }
+
+    #[test]
+    fn removes_unknown_urls_and_keeps_known() {
+        let (answer, issues) = check(
+            "See (https://made.up/page) and https://www.tokio.rs.",
+            &sources(),
+        );
+        assert_eq!(answer, "See and https://www.tokio.rs.");
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0]["url"], "https://made.up/page");
+    }
+
+    #[test]
+    fn marks_quotes_missing_from_cited_source() {
+        let text = "\"LazyCell and LazyLock are now stable\" [1], but \"LazyLock is deprecated in favour of X\" [2].";
+        let (answer, issues) = check(text, &sources());
+        assert_eq!(
+            answer,
+            "\"LazyCell and LazyLock are now stable\" [1], but \"LazyLock is deprecated in favour of X\" [未核实] [2]."
+        );
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0]["type"], "unverified_quote");
+        assert_eq!(issues[0]["citation"], 2);
+    }
+
+    #[test]
+    fn short_quotes_and_clean_answers_pass() {
+        let text = "Use \"tokio\" for async [2].";
+        assert_eq!(check(text, &sources()), (text.to_string(), Vec::new()));
+    }
+}
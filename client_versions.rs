@@ -0,0 +1,201 @@
+// 客户端版本清单
+//
+// 上游会按最低客户端版本开放功能。伪装用的 user-agent、x-app 与必需 anthropic-beta
+// 集中在版本清单中：内置默认值 + 用户覆盖文件 ~/.duckcoding/amp/versions.json
+// （缺失字段沿用内置值）。可由管理接口从 URL 拉取新清单写入覆盖文件，无需发布新版本
+// （POST /_amp/admin/versions/update、/versions/reset，见 admin_api.rs）。
+// 请求路径经 AppState::versions() 读取缓存的清单，覆盖文件变化后随配置热加载重新读取。
+// 清单中的值都会写入请求头：加载与拉取时逐项校验为合法的 HTTP 头值，任一项不合法即整体拒绝
+// （覆盖文件回退内置清单，拉取不写入），避免转发时才发现无法构造请求头。
+
+use anyhow::{anyhow, Result};
+use hyper::header::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VersionsManifest {
+    /// 清单版本号，拉取更新时只接受不低于当前值的清单
+    pub manifest_version: u32,
+    pub claude_user_agent: String,
+    pub codex_user_agent: String,
+    /// {model} 替换为请求的模型名
+    pub gemini_user_agent: String,
+    pub x_app: String,
+    /// Claude 路由必需的 anthropic-beta
+    pub required_betas: Vec<String>,
+    /// Claude 路由目标 URL 追加 beta=true
+    pub beta_query: bool,
+}
+
+impl Default for VersionsManifest {
+    fn default() -> Self {
+        Self {
+            manifest_version: 1,
+            claude_user_agent: "claude-cli/2.1.2 (external, cli)".to_string(),
+            codex_user_agent: "codex_cli_rs/0.77.0 (Mac OS 15.7.2; arm64) Apple_Terminal/455.1"
+                .to_string(),
+            gemini_user_agent: "GeminiCLI/0.22.5/{model} (darwin; arm64)".to_string(),
+            x_app: "cli".to_string(),
+            required_betas: vec![
+                "oauth-2025-04-20".to_string(),
+                "interleaved-thinking-2025-05-14".to_string(),
+            ],
+            beta_query: true,
+        }
+    }
+}
+
+impl VersionsManifest {
+    pub fn path() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(".duckcoding")
+            .join("amp")
+            .join("versions.json")
+    }
+
+    /// 读取覆盖文件；不存在、解析失败或校验失败时使用内置清单
+    pub fn load_or_default() -> Self {
+        let path = Self::path();
+        let Ok(data) = std::fs::read(&path) else {
+            return Self::default();
+        };
+        let manifest: Self = match serde_json::from_slice(&data) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                tracing::warn!("版本清单解析失败: {}，使用内置清单", e);
+                return Self::default();
+            }
+        };
+        match manifest.validate() {
+            Ok(()) => manifest,
+            Err(e) => {
+                tracing::warn!("{}，使用内置清单", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// 校验清单中的全部值都能作为 HTTP 头值
+    pub fn validate(&self) -> Result<()> {
+        let check = |field: &str, value: &str| {
+            HeaderValue::from_str(value)
+                .map(drop)
+                .map_err(|_| anyhow!("版本清单字段 {} 不是合法的请求头值: {:?}", field, value))
+        };
+        if self.claude_user_agent.is_empty() || self.codex_user_agent.is_empty() {
+            return Err(anyhow!("版本清单缺少 user-agent"));
+        }
+        check("claude_user_agent", &self.claude_user_agent)?;
+        check("codex_user_agent", &self.codex_user_agent)?;
+        check("gemini_user_agent", &self.gemini_user_agent)?;
+        check("x_app", &self.x_app)?;
+        for beta in &self.required_betas {
+            if beta.trim().is_empty() || beta.contains(',') {
+                return Err(anyhow!("版本清单 required_betas 含无效项: {:?}", beta));
+            }
+            check("required_betas", beta)?;
+        }
+        Ok(())
+    }
+
+    pub fn gemini_user_agent_for(&self, model: &str) -> String {
+        self.gemini_user_agent.replace("{model}", model)
+    }
+
+    #[cfg(feature = "admin")]
+    fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| anyhow!("创建版本清单目录失败: {}", e))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .map_err(|e| anyhow!("写入版本清单失败: {}", e))?;
+        std::fs::rename(&tmp, &path).map_err(|e| anyhow!("替换版本清单失败: {}", e))
+    }
+}
+
+/// 管理接口：从 URL 拉取清单并写入覆盖文件，返回新清单
+#[cfg(feature = "admin")]
+pub(crate) async fn update_from_url(
+    client: &reqwest::Client,
+    url: &str,
+) -> Result<VersionsManifest> {
+    let resp = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| anyhow!("拉取版本清单失败: {}", e))?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("拉取版本清单失败: HTTP {}", resp.status()));
+    }
+    let manifest: VersionsManifest = resp
+        .json()
+        .await
+        .map_err(|e| anyhow!("版本清单格式错误: {}", e))?;
+
+    let current = VersionsManifest::load_or_default();
+    if manifest.manifest_version < current.manifest_version {
+        return Err(anyhow!(
+            "拉取的版本清单较旧（{} < {}），未更新",
+            manifest.manifest_version,
+            current.manifest_version
+        ));
+    }
+    manifest.validate().map_err(|e| anyhow!("{}，未更新", e))?;
+    manifest.save()?;
+    tracing::info!("版本清单已更新到 v{}", manifest.manifest_version);
+    Ok(manifest)
+}
+
+/// 管理接口：删除覆盖文件，恢复内置清单
+#[cfg(feature = "admin")]
+pub(crate) fn reset() -> Result<()> {
+    let path = VersionsManifest::path();
+    if path.exists() {
+        std::fs::remove_file(&path).map_err(|e| anyhow!("删除版本清单失败: {}", e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_manifest_is_valid() {
+        VersionsManifest::default().validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_values_that_cannot_be_headers() {
+        let cases = [
+            VersionsManifest {
+                claude_user_agent: "claude-cli/2.1\r\nx-injected: 1".to_string(),
+                ..Default::default()
+            },
+            VersionsManifest {
+                gemini_user_agent: "GeminiCLI/\u{7f}/{model}".to_string(),
+                ..Default::default()
+            },
+            VersionsManifest {
+                x_app: "cli\n".to_string(),
+                ..Default::default()
+            },
+            VersionsManifest {
+                required_betas: vec!["a,b".to_string()],
+                ..Default::default()
+            },
+            VersionsManifest {
+                codex_user_agent: String::new(),
+                ..Default::default()
+            },
+        ];
+        for manifest in cases {
+            assert!(manifest.validate().is_err(), "{:?}", manifest);
+        }
+    }
+}
@@ -0,0 +1,416 @@
+// 配置变更预览与原子应用
+//
+// 管理端编辑处理器配置时，先 preview() 计算候选配置与当前生效配置的差异：
+// 变更字段（密钥已掩码）、受影响的 Profile 槽位与路由、校验告警 / 错误；
+// 确认后 apply() 写入，写入后重新读取校验，失败则回滚到原文件
+// （AppState 安装了代码中构建的配置时直接替换该配置，无需回滚）。写入后传入的 AppState 缓存的配置失效。
+
+use super::app_state::AppState;
+use super::diagnostic_bundle::mask_json;
+use super::processor_settings::{ProcessorSettings, ProfileSettings};
+use super::routing_rules;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+/// 串行化 apply，避免并发写入互相覆盖备份
+static APPLY_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+const LLM_ROUTES: [&str; 3] = ["claude", "codex", "gemini"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigChange {
+    /// 点分路径，如 profiles.claude.static_headers.x-gateway
+    pub path: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConfigPreview {
+    pub changes: Vec<ConfigChange>,
+    /// 配置变化的 Profile 槽位
+    pub affected_profiles: Vec<String>,
+    /// 行为可能变化的路由（claude / codex / gemini / azure / amp / local_tools）
+    pub affected_routes: Vec<String>,
+    pub warnings: Vec<String>,
+    /// 非空时 apply 拒绝写入
+    pub errors: Vec<String>,
+}
+
+impl ConfigPreview {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// 计算候选配置（完整 JSON）相对当前配置的差异
+pub fn preview(state: &AppState, candidate: &Value) -> Result<ConfigPreview> {
+    let current = state.load_settings()?;
+    let parsed: ProcessorSettings = serde_json::from_value(candidate.clone())
+        .map_err(|e| anyhow!("候选配置解析失败: {}", e))?;
+    Ok(diff(&current, &parsed))
+}
+
+/// 校验通过后写入；写入后重读校验失败则回滚
+pub fn apply(state: &AppState, candidate: &Value) -> Result<ConfigPreview> {
+    let _guard = APPLY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let preview = preview(state, candidate)?;
+    if !preview.is_valid() {
+        return Err(anyhow!("配置校验失败: {}", preview.errors.join("；")));
+    }
+    if preview.changes.is_empty() {
+        return Ok(preview);
+    }
+    let settings: ProcessorSettings = serde_json::from_value(candidate.clone())?;
+    if state.is_in_memory() {
+        state.save_settings(&settings)?;
+        return Ok(preview);
+    }
+
+    let path = ProcessorSettings::path();
+    let backup = if path.exists() {
+        Some(std::fs::read(&path).map_err(|e| anyhow!("备份处理器配置失败: {}", e))?)
+    } else {
+        None
+    };
+    let result = settings.save().and_then(|_| {
+        let reloaded = ProcessorSettings::load()?;
+        let errors = validate(&reloaded).1;
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("写入后校验失败: {}", errors.join("；")))
+        }
+    });
+
+    if let Err(e) = result {
+        tracing::warn!("应用处理器配置失败，回滚: {}", e);
+        let restored = match &backup {
+            Some(data) => std::fs::write(&path, data),
+            None => std::fs::remove_file(&path),
+        };
+        if let Err(re) = restored {
+            return Err(anyhow!("{}；回滚失败: {}", e, re));
+        }
+        return Err(e);
+    }
+    state.invalidate_settings();
+    tracing::info!("处理器配置已更新（{} 项变更）", preview.changes.len());
+    Ok(preview)
+}
+
+fn diff(current: &ProcessorSettings, candidate: &ProcessorSettings) -> ConfigPreview {
+    let before = serde_json::to_value(current).unwrap_or(Value::Null);
+    let after = serde_json::to_value(candidate).unwrap_or(Value::Null);
+    let mut paths = Vec::new();
+    collect_paths(&before, &after, String::new(), &mut paths);
+
+    // 先比较原值再掩码，密钥变化仍会列出（值显示为 ***）
+    let mut masked_before = before.clone();
+    let mut masked_after = after.clone();
+    mask_json(&mut masked_before);
+    mask_json(&mut masked_after);
+
+    let mut profiles = BTreeSet::new();
+    let mut routes = BTreeSet::new();
+    let changes = paths
+        .into_iter()
+        .map(|path| {
+            let segments: Vec<&str> = path.split('.').collect();
+            if segments[0] == "profiles" {
+                if let Some(slot) = segments.get(1) {
+                    profiles.insert(slot.to_string());
+                    routes.insert(slot.to_string());
+                }
+            } else {
+                routes.extend(routes_for(segments[0]).iter().map(|r| r.to_string()));
+            }
+            ConfigChange {
+                before: lookup(&masked_before, &segments),
+                after: lookup(&masked_after, &segments),
+                path,
+            }
+        })
+        .collect();
+
+    let (warnings, errors) = validate(candidate);
+    ConfigPreview {
+        changes,
+        affected_profiles: profiles.into_iter().collect(),
+        affected_routes: routes.into_iter().collect(),
+        warnings,
+        errors,
+    }
+}
+
+/// 顶层配置项影响的路由
+fn routes_for(key: &str) -> &'static [&'static str] {
+    match key {
+        "tools" | "strict_mode" | "request_validation" | "schema_drift" | "maintenance" => {
+            &LLM_ROUTES
+        }
+        "experiments" | "memory" | "tool_batching" | "cache_diff" | "overload" | "bedrock" => {
+            &["claude"]
+        }
+        "amp_poll_cache" | "amp_auth" | "amp_header_capture" => &["amp"],
+        "tools_outbound"
+        | "tools_egress_cap_bytes_per_day"
+        | "tools_stall"
+        | "cheap_model"
+        | "search"
+        | "weather"
+        | "http_request"
+        | "package_registry"
+        | "docs_search"
+        | "web_extract"
+        | "error_lookup"
+        | "tool_cache"
+        | "url_policy"
+        | "tool_credits" => &["local_tools"],
+        "tool_toggle" => &["local_tools", "amp"],
+        "azure" => &["azure"],
+        "failover" | "retry" | "chaos" => &["claude", "codex", "gemini", "azure"],
+        "routing" => &["claude", "codex", "gemini", "azure", "amp"],
+        "inbound_auth" => &["claude", "codex", "gemini", "azure", "amp", "local_tools"],
+        _ => &[],
+    }
+}
+
+/// 递归比较，输出发生变化的叶子路径（对象逐键展开，数组整体比较）
+fn collect_paths(before: &Value, after: &Value, prefix: String, out: &mut Vec<String>) {
+    if before == after {
+        return;
+    }
+    match (before, after) {
+        (Value::Object(a), Value::Object(b)) => {
+            let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                collect_paths(
+                    a.get(key).unwrap_or(&Value::Null),
+                    b.get(key).unwrap_or(&Value::Null),
+                    path,
+                    out,
+                );
+            }
+        }
+        _ => out.push(prefix),
+    }
+}
+
+fn lookup(root: &Value, segments: &[&str]) -> Option<Value> {
+    segments
+        .iter()
+        .try_fold(root, |v, s| v.get(*s))
+        .filter(|v| !v.is_null())
+        .cloned()
+}
+
+/// 返回（告警，错误）
+pub(crate) fn validate(settings: &ProcessorSettings) -> (Vec<String>, Vec<String>) {
+    let mut warnings = Vec::new();
+    let mut errors = Vec::new();
+
+    for (slot, profile) in &settings.profiles {
+        validate_profile(slot, profile, &mut warnings, &mut errors);
+    }
+
+    if settings.azure.is_configured() {
+        if !settings.azure.base_url.starts_with("https://") {
+            warnings.push("azure.base_url 不是 https 地址".to_string());
+        }
+    } else if !settings.azure.base_url.is_empty() || !settings.azure.api_key.is_empty() {
+        warnings.push("azure 配置不完整（需同时填写 base_url 与 api_key），路由不会启用".into());
+    }
+
+    for (name, bedrock) in &settings.bedrock {
+        if !bedrock.is_configured() {
+            warnings.push(format!(
+                "bedrock.{} 配置不完整，将按原 Claude 后端转发",
+                name
+            ));
+        }
+    }
+
+    if settings.chaos.enabled {
+        warnings.push("chaos 故障注入已启用，仅应在测试 / 预发环境使用".into());
+    }
+
+    errors.extend(routing_rules::validate(&settings.routing));
+    for rule in &settings.routing.rules {
+        if rule.profile.is_some() && matches!(rule.route.as_deref(), Some("azure") | Some("amp")) {
+            warnings.push(format!(
+                "路由规则 {} 的 profile 对 azure / amp 路由无效",
+                rule.name
+            ));
+        }
+    }
+
+    for (tool_id, tool) in &settings.tools {
+        let Some(transforms) = &tool.transforms else {
+            continue;
+        };
+        for (from, to) in &transforms.model_aliases {
+            if transforms.model_aliases.get(to) == Some(from) && from < to {
+                errors.push(format!("tools.{} 模型别名成环: {} ↔ {}", tool_id, from, to));
+            }
+        }
+    }
+    (warnings, errors)
+}
+
+fn validate_profile(
+    slot: &str,
+    profile: &ProfileSettings,
+    warnings: &mut Vec<String>,
+    errors: &mut Vec<String>,
+) {
+    for (name, value) in &profile.static_headers {
+        if hyper::header::HeaderName::from_bytes(name.to_ascii_lowercase().as_bytes()).is_err() {
+            errors.push(format!("profiles.{} 固定请求头名非法: {}", slot, name));
+        }
+        if hyper::header::HeaderValue::from_str(value).is_err() {
+            errors.push(format!(
+                "profiles.{} 固定请求头 {} 的值含非法字符",
+                slot, name
+            ));
+        }
+    }
+    if profile.upstream_protocol != Default::default() && !matches!(slot, "claude" | "codex") {
+        warnings.push(format!(
+            "profiles.{}.upstream_protocol 仅 claude / codex 槽位生效",
+            slot
+        ));
+    }
+    if (profile.openai_organization.is_some() || profile.openai_project.is_some())
+        && !matches!(slot, "codex" | "azure")
+    {
+        warnings.push(format!(
+            "profiles.{} 的 OpenAI-Organization / Project 仅 codex / azure 槽位生效",
+            slot
+        ));
+    }
+    let rules =
+        std::iter::once(&profile.sampling.rules).chain(profile.sampling.model_overrides.values());
+    for rule in rules {
+        for (name, param) in [
+            ("temperature", &rule.temperature),
+            ("top_p", &rule.top_p),
+            ("top_k", &rule.top_k),
+        ] {
+            if let (Some(min), Some(max)) = (param.min, param.max) {
+                if min > max {
+                    errors.push(format!(
+                        "profiles.{}.sampling.{} 下限 {} 大于上限 {}",
+                        slot, name, min, max
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::processor_settings::{ToolSettings, TransformSettings};
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diff_lists_changed_leaves_and_affected_routes() {
+        let current = ProcessorSettings::default();
+        let mut candidate = ProcessorSettings::default();
+        candidate.profiles.insert(
+            "codex".to_string(),
+            ProfileSettings {
+                static_headers: [("x-gateway".to_string(), "a".to_string())].into(),
+                ..Default::default()
+            },
+        );
+        candidate.azure.base_url = "https://res.openai.azure.com".to_string();
+        candidate.azure.api_key = "secret-azure-key".to_string();
+
+        let preview = diff(&current, &candidate);
+        let paths: Vec<&str> = preview.changes.iter().map(|c| c.path.as_str()).collect();
+        assert!(paths.contains(&"azure.base_url"));
+        assert!(paths.contains(&"azure.api_key"));
+        assert!(paths.iter().any(|p| p.starts_with("profiles.codex")));
+        assert_eq!(preview.affected_profiles, ["codex"]);
+        assert_eq!(preview.affected_routes, ["azure", "codex"]);
+        assert!(preview.is_valid());
+
+        let key = preview
+            .changes
+            .iter()
+            .find(|c| c.path == "azure.api_key")
+            .unwrap();
+        assert_ne!(key.after, Some(json!("secret-azure-key")));
+    }
+
+    #[test]
+    fn unchanged_settings_have_no_changes() {
+        let settings = ProcessorSettings::default();
+        let preview = diff(&settings, &settings.clone());
+        assert!(preview.changes.is_empty());
+        assert!(preview.affected_routes.is_empty());
+    }
+
+    #[test]
+    fn validate_rejects_bad_headers_and_alias_cycles() {
+        let mut settings = ProcessorSettings::default();
+        settings.profiles.insert(
+            "claude".to_string(),
+            ProfileSettings {
+                static_headers: [("bad header".to_string(), "v".to_string())].into(),
+                ..Default::default()
+            },
+        );
+        let mut transforms = TransformSettings::default();
+        transforms
+            .model_aliases
+            .insert("a".to_string(), "b".to_string());
+        transforms
+            .model_aliases
+            .insert("b".to_string(), "a".to_string());
+        settings.tools.insert(
+            "amp-code".to_string(),
+            ToolSettings {
+                transforms: Some(transforms),
+            },
+        );
+        let (_, errors) = validate(&settings);
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors.iter().any(|e| e.contains("固定请求头名非法")));
+        assert!(errors.iter().any(|e| e.contains("模型别名成环: a ↔ b")));
+    }
+
+    #[test]
+    fn validate_warns_on_partial_azure_and_chaos() {
+        let mut settings = ProcessorSettings::default();
+        settings.azure.api_key = "k".to_string();
+        settings.chaos.enabled = true;
+        let (warnings, errors) = validate(&settings);
+        assert!(errors.is_empty());
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+    }
+
+    #[test]
+    fn collect_paths_expands_objects_only() {
+        let mut out = Vec::new();
+        collect_paths(
+            &json!({ "a": { "b": 1, "c": [1] }, "d": 1 }),
+            &json!({ "a": { "b": 2, "c": [1, 2] }, "d": 1, "e": true }),
+            String::new(),
+            &mut out,
+        );
+        assert_eq!(out, ["a.b", "a.c", "e"]);
+    }
+}
@@ -0,0 +1,491 @@
+// 只读观测面板
+//
+// 未安装桌面端时，可在浏览器中查看代理状态：最近请求、Profile 与 Token 健康、
+// 用量 / 流量统计、最近错误。页面为编译进二进制的单文件 HTML，
+// 每 2 秒轮询 /api/snapshot（数据已脱敏）。仅提供 GET，不含任何写操作。
+// /api/capacity?days=N 按 capacity.scenarios 回放最近 N 天流量（默认 7 天）。
+// /api/request-id?id=xxx 按提供方请求 ID 反查本地审计记录。
+// /metrics 输出 Prometheus 指标（见 metrics.rs）。
+// /api/cache-diff?session=xxx 逐轮比对会话请求前缀（需启用 cache_diff）。
+// /api/requests?route=&model=&profile=&status=&since_ms=&limit= 查询请求日志（需 sqlite 特性）。
+// handle() 供管理端口复用；未接入管理端口时 ensure_started() 单独监听。
+// 未配置 token 时只允许监听回环地址；配置后每个请求都须携带 Authorization: Bearer <token>
+// 或 token 查询参数（浏览器以 /?token=xxx 打开页面，页面轮询时带上）。
+// 请求日志查询与容量模拟会读盘 / 回放大量记录，两个监听端口都经 handle_blocking() 在阻塞线程池中执行。
+// 页面与独立监听需要 feature = "dashboard"；未启用时 handle() 的 JSON 接口仍可经管理端口访问。
+
+#[cfg(feature = "dashboard")]
+use super::admin_api;
+use super::amp_accounting;
+use super::amp_poll_cache;
+use super::app_state::AppState;
+use super::audit_log;
+use super::bandwidth;
+use super::cache_diff;
+use super::capacity;
+use super::diagnostic_bundle::{mask_json, mask_text, route_table};
+use super::dns_cache;
+#[cfg(feature = "dashboard")]
+use super::inbound_auth::constant_time_eq;
+use super::maintenance;
+use super::metrics;
+use super::provider_request_id;
+use super::request_log::{self, RequestQuery};
+use super::response_cache;
+#[cfg(feature = "dashboard")]
+use super::secret_store;
+use super::token_health;
+use super::tool_cache;
+#[cfg(feature = "dashboard")]
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "dashboard")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Handle;
+
+const MAX_RECENT_REQUESTS: usize = 200;
+const MAX_RECENT_ERRORS: usize = 50;
+/// 请求头上限（只读面板无请求体）
+#[cfg(feature = "dashboard")]
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DashboardSettings {
+    pub enabled: bool,
+    /// 默认仅本机访问；暴露到局域网需显式改为 0.0.0.0，并配置 token
+    pub listen: String,
+    /// 访问 token；未配置时只允许监听回环地址
+    pub token: Option<String>,
+}
+
+impl Default for DashboardSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "127.0.0.1:8788".to_string(),
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RequestEvent {
+    ts_ms: u64,
+    route: String,
+    profile: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ErrorEvent {
+    ts_ms: u64,
+    route: String,
+    message: String,
+}
+
+static RECENT_REQUESTS: Lazy<Mutex<VecDeque<RequestEvent>>> =
+    Lazy::new(|| Mutex::new(VecDeque::new()));
+static RECENT_ERRORS: Lazy<Mutex<VecDeque<ErrorEvent>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+fn push_bounded<T>(queue: &Mutex<VecDeque<T>>, item: T, max: usize) {
+    let mut q = queue.lock().unwrap_or_else(|e| e.into_inner());
+    if q.len() >= max {
+        q.pop_front();
+    }
+    q.push_back(item);
+}
+
+/// 记录一次转发（面板“最近请求”）
+pub(crate) fn record_request(route: &str, profile: &str) {
+    push_bounded(
+        &RECENT_REQUESTS,
+        RequestEvent {
+            ts_ms: audit_log::now_ms(),
+            route: route.to_string(),
+            profile: profile.to_string(),
+        },
+        MAX_RECENT_REQUESTS,
+    );
+}
+
+/// 记录一次错误（处理器内部失败或代理层上游错误）
+pub(crate) fn record_error(route: &str, message: &str) {
+    push_bounded(
+        &RECENT_ERRORS,
+        ErrorEvent {
+            ts_ms: audit_log::now_ms(),
+            route: route.to_string(),
+            message: mask_text(message),
+        },
+        MAX_RECENT_ERRORS,
+    );
+}
+
+/// 面板数据快照（已脱敏）
+pub(crate) fn snapshot(state: &AppState) -> Value {
+    let requests: Vec<RequestEvent> = RECENT_REQUESTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .rev()
+        .cloned()
+        .collect();
+    let errors: Vec<ErrorEvent> = RECENT_ERRORS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .rev()
+        .cloned()
+        .collect();
+    let mut routes = route_table(state);
+    mask_json(&mut routes);
+    json!({
+        "now_ms": audit_log::now_ms(),
+        "recent_requests": requests,
+        "recent_errors": errors,
+        "routes": routes,
+        "tokens": token_health::snapshot(),
+        "down_routes": maintenance::down_routes(),
+        "usage": amp_accounting::snapshot_counters(),
+        "bandwidth": bandwidth::report(None),
+        "caches": cache_stats(),
+    })
+}
+
+/// 各缓存的条目统计
+pub(crate) fn cache_stats() -> Value {
+    let (response_entries, response_bytes) = response_cache::stats();
+    json!({
+        "response": { "entries": response_entries, "bytes": response_bytes },
+        "tool": { "entries": tool_cache::len() },
+        "poll": { "entries": amp_poll_cache::len() },
+        "dns": dns_cache::shared().stats(),
+    })
+}
+
+/// 按路径生成响应：(状态码, content-type, 响应体)
+// 两个特性都未启用时没有调用方
+#[cfg_attr(not(any(feature = "dashboard", feature = "admin")), allow(dead_code))]
+pub(crate) fn handle(state: &AppState, path: &str) -> (u16, &'static str, Vec<u8>) {
+    match path.split('?').next().unwrap_or(path) {
+        #[cfg(feature = "dashboard")]
+        "/" | "/index.html" => (
+            200,
+            "text/html; charset=utf-8",
+            INDEX_HTML.as_bytes().to_vec(),
+        ),
+        "/api/snapshot" => (
+            200,
+            "application/json",
+            serde_json::to_vec(&snapshot(state)).unwrap_or_default(),
+        ),
+        "/api/capacity" => {
+            let days = query_param(path, "days")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(7);
+            let scenarios = state.settings().capacity.scenarios.clone();
+            (
+                200,
+                "application/json",
+                serde_json::to_vec(&capacity::simulate(&scenarios, days)).unwrap_or_default(),
+            )
+        }
+        "/metrics" => (
+            200,
+            "text/plain; version=0.0.4",
+            metrics::render().into_bytes(),
+        ),
+        "/api/cache-diff" => {
+            let session = query_param(path, "session").unwrap_or_default();
+            match cache_diff::analyze(&session) {
+                Some(diffs) => (
+                    200,
+                    "application/json",
+                    serde_json::to_vec(&diffs).unwrap_or_default(),
+                ),
+                None => (
+                    404,
+                    "text/plain; charset=utf-8",
+                    "未找到该会话的请求记录".as_bytes().to_vec(),
+                ),
+            }
+        }
+        "/api/requests" => {
+            let q = RequestQuery {
+                since_ms: query_param(path, "since_ms").and_then(|v| v.parse().ok()),
+                until_ms: query_param(path, "until_ms").and_then(|v| v.parse().ok()),
+                route: query_param(path, "route"),
+                model: query_param(path, "model"),
+                profile: query_param(path, "profile"),
+                status: query_param(path, "status").and_then(|v| v.parse().ok()),
+                limit: query_param(path, "limit").and_then(|v| v.parse().ok()),
+            };
+            let settings = state.settings().request_log.clone();
+            let result = request_log::totals(&settings, &q).and_then(|totals| {
+                Ok(json!({ "totals": totals, "requests": request_log::query(&settings, &q)? }))
+            });
+            match result {
+                Ok(value) => (
+                    200,
+                    "application/json",
+                    serde_json::to_vec(&value).unwrap_or_default(),
+                ),
+                Err(e) => (503, "text/plain; charset=utf-8", e.to_string().into_bytes()),
+            }
+        }
+        "/api/request-id" => {
+            let id = query_param(path, "id").unwrap_or_default();
+            let mut records = serde_json::to_value(provider_request_id::lookup(&id))
+                .unwrap_or_else(|_| json!([]));
+            mask_json(&mut records);
+            (
+                200,
+                "application/json",
+                serde_json::to_vec(&records).unwrap_or_default(),
+            )
+        }
+        _ => (
+            404,
+            "text/plain; charset=utf-8",
+            "Not Found".as_bytes().to_vec(),
+        ),
+    }
+}
+
+/// 在阻塞线程池中执行 handle()，不占用异步工作线程
+#[cfg(any(feature = "dashboard", feature = "admin"))]
+pub(crate) async fn handle_blocking(
+    state: Arc<AppState>,
+    path: String,
+) -> (u16, &'static str, Vec<u8>) {
+    tokio::task::spawn_blocking(move || handle(&state, &path))
+        .await
+        .unwrap_or_else(|e| {
+            (
+                503,
+                "text/plain; charset=utf-8",
+                format!("面板请求异常结束: {}", e).into_bytes(),
+            )
+        })
+}
+
+/// 未配置 token 时放行；否则须携带 Authorization: Bearer <token> 或 token 查询参数
+#[cfg(feature = "dashboard")]
+fn authorized(token: Option<&str>, authorization: Option<&str>, path: &str) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    authorization
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .or_else(|| query_param(path, "token"))
+        .is_some_and(|v| constant_time_eq(&v, token))
+}
+
+fn query_param(path: &str, name: &str) -> Option<String> {
+    let query = path.split_once('?')?.1;
+    url::form_urlencoded::parse(query.as_bytes())
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.into_owned())
+}
+
+/// 启用时在后台监听（每个 AppState 只启动一次），面板数据取自 state
+#[cfg(feature = "dashboard")]
+pub(crate) fn ensure_started(state: &Arc<AppState>, runtime: &Handle) {
+    let settings = state.settings().dashboard.clone();
+    if !settings.enabled {
+        return;
+    }
+    let token = secret_store::reveal_opt(settings.token.clone()).filter(|t| !t.is_empty());
+    if token.is_none() && !admin_api::is_loopback(&settings.listen) {
+        tracing::warn!(
+            "观测面板监听非回环地址 {} 但未配置 token，拒绝启动",
+            settings.listen
+        );
+        return;
+    }
+    if !state.claim_started("dashboard") {
+        return;
+    }
+    let state = state.clone();
+    runtime.spawn(async move {
+        if let Err(e) = serve(state.clone(), &settings.listen, token).await {
+            tracing::warn!("观测面板启动失败: {}", e);
+            state.release_started("dashboard");
+        }
+    });
+}
+
+#[cfg(not(feature = "dashboard"))]
+pub(crate) fn ensure_started(state: &Arc<AppState>, _runtime: &Handle) {
+    if state.settings().dashboard.enabled {
+        tracing::warn!("观测面板需要启用 dashboard 特性，当前构建不包含");
+    }
+}
+
+#[cfg(feature = "dashboard")]
+async fn serve(state: Arc<AppState>, listen: &str, token: Option<String>) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| anyhow!("监听 {} 失败: {}", listen, e))?;
+    tracing::info!("观测面板: http://{}/", listen);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let (state, token) = (state.clone(), token.clone());
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(state, stream, token.as_deref()).await {
+                tracing::debug!("观测面板连接错误: {}", e);
+            }
+        });
+    }
+}
+
+/// 极简 HTTP/1.1：每个连接只处理一个 GET 请求
+#[cfg(feature = "dashboard")]
+async fn serve_connection(
+    state: Arc<AppState>,
+    mut stream: tokio::net::TcpStream,
+    token: Option<&str>,
+) -> Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        head.extend_from_slice(&buf[..n]);
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err(anyhow!("请求头过大"));
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut parts = head.lines().next().unwrap_or_default().split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or("/"));
+    let authorization = head.lines().skip(1).find_map(|line| {
+        let (k, v) = line.split_once(':')?;
+        k.trim()
+            .eq_ignore_ascii_case("authorization")
+            .then(|| v.trim())
+    });
+
+    let (status, content_type, body) = if method != "GET" {
+        (
+            405,
+            "text/plain; charset=utf-8",
+            b"Method Not Allowed".to_vec(),
+        )
+    } else if !authorized(token, authorization, path) {
+        (401, "text/plain; charset=utf-8", b"Unauthorized".to_vec())
+    } else {
+        handle_blocking(state, path.to_string()).await
+    };
+    let reason = match status {
+        200 => "OK",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+    let header = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(feature = "dashboard")]
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html lang="zh-CN">
+<head>
+<meta charset="utf-8">
+<title>AMP 代理观测面板</title>
+<style>
+body { font-family: -apple-system, "Segoe UI", sans-serif; margin: 24px; color: #222; background: #f6f7f9; }
+h1 { font-size: 20px; }
+h2 { font-size: 15px; margin: 0 0 8px; }
+.grid { display: grid; grid-template-columns: repeat(auto-fit, minmax(420px, 1fr)); gap: 16px; }
+.card { background: #fff; border-radius: 8px; padding: 12px 16px; box-shadow: 0 1px 2px rgba(0,0,0,.08); overflow: auto; max-height: 420px; }
+table { border-collapse: collapse; width: 100%; font-size: 12px; }
+td, th { text-align: left; padding: 3px 6px; border-bottom: 1px solid #eee; white-space: nowrap; }
+.bar { background: #4f7cff; height: 10px; border-radius: 2px; }
+.bad { color: #c0392b; }
+.ok { color: #2e8b57; }
+#status { font-size: 12px; color: #888; }
+</style>
+</head>
+<body>
+<h1>AMP 代理观测面板 <span id="status"></span></h1>
+<div class="grid">
+  <div class="card"><h2>最近请求</h2><table id="requests"></table></div>
+  <div class="card"><h2>Profile 与健康状态</h2><table id="health"></table></div>
+  <div class="card"><h2>用量（按路由 / Profile）</h2><table id="usage"></table></div>
+  <div class="card"><h2>最近错误</h2><table id="errors"></table></div>
+</div>
+<script>
+const esc = s => String(s ?? "").replace(/[&<>"]/g, c => ({"&":"&amp;","<":"&lt;",">":"&gt;","\"":"&quot;"}[c]));
+const time = ms => new Date(ms).toLocaleTimeString();
+const rows = (el, head, data) => {
+  document.getElementById(el).innerHTML =
+    "<tr>" + head.map(h => "<th>" + h + "</th>").join("") + "</tr>" +
+    (data.length ? data.join("") : "<tr><td colspan=" + head.length + ">暂无</td></tr>");
+};
+const token = new URLSearchParams(location.search).get("token");
+async function refresh() {
+  try {
+    const s = await (await fetch("/api/snapshot", token ? { headers: { Authorization: "Bearer " + token } } : {})).json();
+    rows("requests", ["时间", "路由", "上游"], s.recent_requests.slice(0, 50).map(r =>
+      `<tr><td>${time(r.ts_ms)}</td><td>${esc(r.route)}</td><td>${esc(r.profile)}</td></tr>`));
+    const sel = (s.routes && s.routes.selection) || {};
+    const down = Object.fromEntries((s.down_routes || []).map(([r, secs]) => [r, secs]));
+    const health = ["claude", "codex", "gemini"].map(r => {
+      const p = sel[r];
+      const state = down[r] ? `<span class="bad">不可用（${down[r]}s）</span>` : (p ? '<span class="ok">正常</span>' : "未配置");
+      return `<tr><td>${r}</td><td>${esc(p && p.name)}</td><td>${state}</td></tr>`;
+    }).concat((s.tokens || []).map(t =>
+      `<tr><td>token</td><td>${esc(t.account)}</td><td>${esc(JSON.stringify(t.state))}</td></tr>`));
+    rows("health", ["路由", "Profile / 账号", "状态"], health);
+    const usage = Object.entries(s.usage || {});
+    const max = Math.max(1, ...usage.map(([, u]) => u.requests));
+    rows("usage", ["键", "请求", "输入", "输出", "错误", ""], usage.map(([k, u]) =>
+      `<tr><td>${esc(k)}</td><td>${u.requests}</td><td>${u.input_tokens}</td><td>${u.output_tokens}</td><td>${u.errors}</td>` +
+      `<td style="width:40%"><div class="bar" style="width:${(u.requests / max * 100).toFixed(1)}%"></div></td></tr>`));
+    rows("errors", ["时间", "路由", "信息"], s.recent_errors.map(e =>
+      `<tr><td>${time(e.ts_ms)}</td><td>${esc(e.route)}</td><td class="bad">${esc(e.message)}</td></tr>`));
+    document.getElementById("status").textContent = "更新于 " + time(s.now_ms);
+  } catch (e) {
+    document.getElementById("status").textContent = "连接失败: " + e;
+  }
+}
+refresh();
+setInterval(refresh, 2000);
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(all(test, feature = "dashboard"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_required_when_configured() {
+        assert!(authorized(None, None, "/api/snapshot"));
+        assert!(!authorized(Some("t0k"), None, "/api/snapshot"));
+        assert!(!authorized(Some("t0k"), Some("Bearer nope"), "/"));
+        assert!(authorized(Some("t0k"), Some("Bearer t0k"), "/api/snapshot"));
+        // 浏览器打开页面时经查询参数携带
+        assert!(authorized(Some("t0k"), None, "/?token=t0k"));
+        assert!(!authorized(Some("t0k"), None, "/?token=t0"));
+    }
+}
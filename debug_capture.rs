@@ -0,0 +1,180 @@
+// Profile 调试模式
+//
+// 开启后：
+// - 请求侧：为非流式请求附加 logprobs 等调试参数（Anthropic 无对应参数，仅采集响应头）
+// - 响应侧：采集 request id / server-timing 等调试头与 logprobs 写入审计日志，
+//   并从返回给 AMP 的响应体中移除调试字段，避免泄漏到客户端
+// 流式请求不附加调试参数，保证逐块透传时不会混入额外字段。
+
+use super::audit_log::{self, AuditRecord};
+use super::processor_settings::ProfileSettings;
+use super::transform_middleware::TransformTarget;
+use hyper::HeaderMap as HyperHeaderMap;
+use serde_json::{json, Map, Value};
+
+/// 采集的调试响应头
+const DEBUG_HEADERS: [&str; 8] = [
+    "request-id",
+    "x-request-id",
+    "server-timing",
+    "openai-processing-ms",
+    "x-envoy-upstream-service-time",
+    "cf-ray",
+    "x-goog-request-id",
+    "anthropic-organization-id",
+];
+
+const DEFAULT_TOP_LOGPROBS: u8 = 5;
+
+fn is_streaming(target: TransformTarget, path: &str, json: &Value) -> bool {
+    match target {
+        TransformTarget::Gemini => path.to_lowercase().contains(":streamgeneratecontent"),
+        _ => json
+            .get("stream")
+            .and_then(|s| s.as_bool())
+            .unwrap_or(false),
+    }
+}
+
+/// 请求侧：按 Profile 调试开关附加 logprobs 参数，返回 None 表示未改写
+pub(crate) fn inject_request_fields(
+    settings: &ProfileSettings,
+    target: TransformTarget,
+    path: &str,
+    body: &[u8],
+) -> Option<Vec<u8>> {
+    if !settings.debug || body.is_empty() {
+        return None;
+    }
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    if is_streaming(target, path, &json) {
+        return None;
+    }
+    let top = settings.debug_top_logprobs.unwrap_or(DEFAULT_TOP_LOGPROBS);
+    let obj = json.as_object_mut()?;
+
+    match target {
+        TransformTarget::Codex => {
+            // 仅 chat/completions 支持 logprobs；responses 接口通过 include 请求
+            if path.contains("/chat/completions") {
+                obj.insert("logprobs".into(), json!(true));
+                obj.insert("top_logprobs".into(), json!(top));
+            } else {
+                let include = obj.entry("include").or_insert_with(|| json!([]));
+                if let Some(arr) = include.as_array_mut() {
+                    let item = json!("message.output_text.logprobs");
+                    if !arr.contains(&item) {
+                        arr.push(item);
+                    }
+                }
+                obj.insert("top_logprobs".into(), json!(top));
+            }
+        }
+        TransformTarget::Gemini => {
+            let config = obj.entry("generationConfig").or_insert_with(|| json!({}));
+            if let Some(cfg) = config.as_object_mut() {
+                cfg.insert("responseLogprobs".into(), json!(true));
+                cfg.insert("logprobs".into(), json!(top));
+            }
+        }
+        TransformTarget::Claude => return None,
+    }
+
+    serde_json::to_vec(&json).ok()
+}
+
+/// 响应侧：采集调试信息写入审计日志，并从响应体移除调试字段
+/// 返回 None 表示响应体无需改写
+pub(crate) fn capture_response(
+    settings: &ProfileSettings,
+    target: TransformTarget,
+    profile_key: &str,
+    headers: &HyperHeaderMap,
+    body: &[u8],
+) -> Option<Vec<u8>> {
+    if !settings.debug {
+        return None;
+    }
+
+    let mut captured_headers = Map::new();
+    for name in DEBUG_HEADERS {
+        if let Some(v) = headers.get(name).and_then(|v| v.to_str().ok()) {
+            captured_headers.insert(name.to_string(), json!(v));
+        }
+    }
+
+    let mut json: Option<Value> = serde_json::from_slice(body).ok();
+    let logprobs = json
+        .as_mut()
+        .map(|j| strip_debug_fields(target, j))
+        .unwrap_or_default();
+
+    let model = json
+        .as_ref()
+        .and_then(|j| j.get("model").or_else(|| j.get("modelVersion")))
+        .and_then(|m| m.as_str());
+    let route = match target {
+        TransformTarget::Claude => "claude",
+        TransformTarget::Codex => "codex",
+        TransformTarget::Gemini => "gemini",
+    };
+    audit_log::append(
+        &AuditRecord::new("debug", route, profile_key)
+            .with_model(model)
+            .with("headers", Value::Object(captured_headers))
+            .with("logprobs", Value::Array(logprobs.clone())),
+    );
+
+    if logprobs.is_empty() {
+        return None;
+    }
+    serde_json::to_vec(&json?).ok()
+}
+
+/// 移除并返回响应体中的 logprobs 字段
+fn strip_debug_fields(target: TransformTarget, json: &mut Value) -> Vec<Value> {
+    let mut removed = Vec::new();
+    match target {
+        TransformTarget::Codex => {
+            if let Some(choices) = json.get_mut("choices").and_then(|c| c.as_array_mut()) {
+                for choice in choices {
+                    if let Some(lp) = choice.as_object_mut().and_then(|o| o.remove("logprobs")) {
+                        if !lp.is_null() {
+                            removed.push(lp);
+                        }
+                    }
+                }
+            }
+            if let Some(output) = json.get_mut("output").and_then(|o| o.as_array_mut()) {
+                for item in output {
+                    let Some(content) = item.get_mut("content").and_then(|c| c.as_array_mut())
+                    else {
+                        continue;
+                    };
+                    for part in content {
+                        if let Some(lp) = part.as_object_mut().and_then(|o| o.remove("logprobs")) {
+                            removed.push(lp);
+                        }
+                    }
+                }
+            }
+        }
+        TransformTarget::Gemini => {
+            if let Some(candidates) = json.get_mut("candidates").and_then(|c| c.as_array_mut()) {
+                for cand in candidates {
+                    let Some(obj) = cand.as_object_mut() else {
+                        continue;
+                    };
+                    if let Some(lp) = obj.remove("logprobsResult") {
+                        removed.push(lp);
+                    }
+                    if let Some(avg) = obj.remove("avgLogprobs") {
+                        removed.push(json!({ "avgLogprobs": avg }));
+                    }
+                }
+            }
+        }
+        TransformTarget::Claude => {}
+    }
+    removed
+}
@@ -0,0 +1,192 @@
+// 确定性模式
+//
+// 会话 UUID 的随机兜底、合成 ID 与时间戳让转换结果不可复现，不便排查与快照比对。
+// 这些值统一经 Clock / IdGen 获取：默认为系统时钟与随机 UUID；
+// 确定性模式下改为固定时钟与按种子递增的 ID。
+// 启用方式：set_deterministic(seed, fixed_ms)，或环境变量 DC_DETERMINISTIC=<seed>；
+// scoped(seed, fixed_ms, fut) 只对 fut 所在任务生效，并行运行的快照 / 测试互不干扰。
+// 仅影响转换与记录用的时间戳 / ID，TTL 等单调计时仍使用 Instant。
+
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+pub(crate) trait Clock: Send + Sync {
+    /// Unix 毫秒时间戳
+    fn now_ms(&self) -> u64;
+}
+
+pub(crate) trait IdGen: Send + Sync {
+    fn new_uuid(&self) -> Uuid;
+}
+
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+pub(crate) struct RandomIdGen;
+
+impl IdGen for RandomIdGen {
+    fn new_uuid(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// 固定时钟
+pub(crate) struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now_ms(&self) -> u64 {
+        self.0
+    }
+}
+
+/// 按种子与序号派生 UUID：sha256(seed:n) 的前 16 字节，标记为 v4 格式
+pub(crate) struct SeededIdGen {
+    seed: u64,
+    counter: AtomicU64,
+}
+
+impl SeededIdGen {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            counter: AtomicU64::new(0),
+        }
+    }
+}
+
+impl IdGen for SeededIdGen {
+    fn new_uuid(&self) -> Uuid {
+        let n = self.counter.fetch_add(1, Ordering::Relaxed);
+        let digest = Sha256::digest(format!("{}:{}", self.seed, n).as_bytes());
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&digest[..16]);
+        uuid::Builder::from_random_bytes(bytes).into_uuid()
+    }
+}
+
+/// 确定性模式下的默认固定时间：2025-01-01T00:00:00Z
+const DEFAULT_FIXED_MS: u64 = 1_735_689_600_000;
+
+struct Sources {
+    clock: Arc<dyn Clock>,
+    ids: Arc<dyn IdGen>,
+}
+
+static SOURCES: Lazy<RwLock<Sources>> = Lazy::new(|| {
+    let sources = match std::env::var("DC_DETERMINISTIC")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+    {
+        Some(seed) => {
+            tracing::info!("确定性模式已启用（DC_DETERMINISTIC={}）", seed);
+            deterministic_sources(seed, DEFAULT_FIXED_MS)
+        }
+        None => system_sources(),
+    };
+    RwLock::new(sources)
+});
+
+tokio::task_local! {
+    /// 任务内的确定性来源，优先于进程级设置
+    static SCOPED: Sources;
+}
+
+fn system_sources() -> Sources {
+    Sources {
+        clock: Arc::new(SystemClock),
+        ids: Arc::new(RandomIdGen),
+    }
+}
+
+fn deterministic_sources(seed: u64, fixed_ms: u64) -> Sources {
+    Sources {
+        clock: Arc::new(FixedClock(fixed_ms)),
+        ids: Arc::new(SeededIdGen::new(seed)),
+    }
+}
+
+pub(crate) fn clock() -> Arc<dyn Clock> {
+    if let Ok(clock) = SCOPED.try_with(|s| s.clock.clone()) {
+        return clock;
+    }
+    SOURCES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clock
+        .clone()
+}
+
+pub(crate) fn ids() -> Arc<dyn IdGen> {
+    if let Ok(ids) = SCOPED.try_with(|s| s.ids.clone()) {
+        return ids;
+    }
+    SOURCES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .ids
+        .clone()
+}
+
+/// 注入自定义时钟与 ID 生成器
+pub(crate) fn install(clock: Arc<dyn Clock>, ids: Arc<dyn IdGen>) {
+    *SOURCES.write().unwrap_or_else(|e| e.into_inner()) = Sources { clock, ids };
+}
+
+/// 启用确定性模式（ID 序号从 0 重新开始）
+pub(crate) fn set_deterministic(seed: u64, fixed_ms: Option<u64>) {
+    let sources = deterministic_sources(seed, fixed_ms.unwrap_or(DEFAULT_FIXED_MS));
+    install(sources.clock, sources.ids);
+}
+
+/// 在 fut 内启用确定性模式（仅当前任务，不改变进程级设置）
+pub(crate) async fn scoped<F: Future>(seed: u64, fixed_ms: Option<u64>, fut: F) -> F::Output {
+    let sources = deterministic_sources(seed, fixed_ms.unwrap_or(DEFAULT_FIXED_MS));
+    SCOPED.scope(sources, fut).await
+}
+
+/// 恢复系统时钟与随机 ID
+pub(crate) fn reset() {
+    let sources = system_sources();
+    install(sources.clock, sources.ids);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_ids_are_reproducible() {
+        let a = SeededIdGen::new(42);
+        let b = SeededIdGen::new(42);
+        let first: Vec<Uuid> = (0..3).map(|_| a.new_uuid()).collect();
+        let second: Vec<Uuid> = (0..3).map(|_| b.new_uuid()).collect();
+        assert_eq!(first, second);
+        assert_ne!(first[0], first[1]);
+        assert_eq!(first[0].get_version_num(), 4);
+        assert_ne!(first[0], SeededIdGen::new(43).new_uuid());
+    }
+
+    #[tokio::test]
+    async fn scoped_sources_stay_in_task() {
+        let (now, id) = scoped(7, Some(1_000), async {
+            (clock().now_ms(), ids().new_uuid())
+        })
+        .await;
+        assert_eq!(now, 1_000);
+        assert_eq!(id, SeededIdGen::new(7).new_uuid());
+        // 作用域外恢复进程级来源（默认系统时钟）
+        assert_ne!(clock().now_ms(), 1_000);
+    }
+}
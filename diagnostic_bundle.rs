@@ -0,0 +1,374 @@
+// 诊断包生成
+//
+// 管理操作：汇总排查所需信息打包为单个 zip，供附加到问题反馈。
+// 分两步：prepare() 生成文件清单（名称、大小、说明）供用户逐项确认，
+// write() 再写出 ~/.duckcoding/amp/diagnostics/dc-diagnostics-{时间戳}.zip。
+// 所有内容在 prepare 阶段完成脱敏：配置中的 key / token / secret / password 字段整体掩码，
+// 日志文本中的常见密钥格式按正则掩码。zip 使用 stored（不压缩）格式，无额外依赖。
+
+use super::amp_accounting;
+use super::app_state::{AppState, SelectedProfile};
+use super::audit_log;
+use super::dns_cache;
+use super::maintenance;
+use super::schema_drift;
+use super::token_health;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+/// 审计日志最多收录的行数（取末尾）
+const MAX_LOG_LINES: usize = 500;
+
+static SECRET_TEXT_RE: Lazy<regex::Regex> = Lazy::new(|| {
+    regex::Regex::new(
+        r"(?i)(sk-[a-z0-9_\-]{8,}|bearer\s+[a-z0-9._\-]{8,}|AIza[0-9a-z_\-]{20,}|tvly-[a-z0-9_\-]{8,})",
+    )
+    .expect("密钥掩码正则非法")
+});
+
+const SECRET_KEY_HINTS: [&str; 5] = ["key", "token", "secret", "password", "authorization"];
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct BundleEntry {
+    pub name: String,
+    pub description: String,
+    pub size: usize,
+    #[serde(skip)]
+    data: Vec<u8>,
+}
+
+/// 待确认的诊断包内容
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct BundlePlan {
+    pub created_ms: u64,
+    pub entries: Vec<BundleEntry>,
+}
+
+impl BundlePlan {
+    fn add(&mut self, name: &str, description: &str, data: Vec<u8>) {
+        self.entries.push(BundleEntry {
+            name: name.to_string(),
+            description: description.to_string(),
+            size: data.len(),
+            data,
+        });
+    }
+
+    fn add_json(&mut self, name: &str, description: &str, value: &Value) {
+        let data = serde_json::to_vec_pretty(value).unwrap_or_default();
+        self.add(name, description, data);
+    }
+
+    /// 确认前剔除用户不希望包含的文件
+    pub(crate) fn exclude(&mut self, name: &str) {
+        self.entries.retain(|e| e.name != name);
+    }
+}
+
+/// 文本中的密钥掩码
+pub(crate) fn mask_text(text: &str) -> String {
+    SECRET_TEXT_RE.replace_all(text, "***").into_owned()
+}
+
+/// JSON 中疑似密钥字段整体掩码，其余字符串按文本规则掩码
+pub(crate) fn mask_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                let lower = k.to_lowercase();
+                if SECRET_KEY_HINTS.iter().any(|h| lower.contains(h)) && !v.is_null() {
+                    if v.is_string() || v.is_number() {
+                        *v = json!("***");
+                        continue;
+                    }
+                    // 账号表等：保留键名，掩码全部值
+                    if let Value::Object(inner) = v {
+                        for iv in inner.values_mut() {
+                            *iv = json!("***");
+                        }
+                        continue;
+                    }
+                }
+                mask_json(v);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(mask_json),
+        Value::String(s) => *s = mask_text(s),
+        _ => {}
+    }
+}
+
+/// 生成诊断包清单（已脱敏），待用户确认；路由表按 state 的 Profile 选择生成
+pub(crate) fn prepare(state: &AppState) -> BundlePlan {
+    let mut plan = BundlePlan {
+        created_ms: audit_log::now_ms(),
+        entries: Vec::new(),
+    };
+
+    plan.add_json(
+        "version.json",
+        "版本与运行环境",
+        &json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "os": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+        }),
+    );
+
+    let mut settings = match state.load_settings() {
+        Ok(s) => serde_json::to_value(s).unwrap_or(Value::Null),
+        Err(e) => json!({ "error": e.to_string() }),
+    };
+    mask_json(&mut settings);
+    plan.add_json(
+        "config/processor_settings.json",
+        "处理器配置（密钥已掩码）",
+        &settings,
+    );
+
+    plan.add_json(
+        "routes.json",
+        "路由表与当前 Profile 选择",
+        &route_table(state),
+    );
+
+    let integrity = amp_accounting::check_integrity()
+        .map(|r| serde_json::to_value(r).unwrap_or(Value::Null))
+        .unwrap_or_else(|e| json!({ "error": e.to_string() }));
+    plan.add_json(
+        "health.json",
+        "健康快照：Token 状态、不可用路由、DNS 缓存、结构漂移、计量完整性",
+        &json!({
+            "tokens": token_health::snapshot(),
+            "down_routes": maintenance::down_routes(),
+            "dns_cache": dns_cache::shared().stats(),
+            "schema_drift": schema_drift::findings(),
+            "accounting_integrity": integrity,
+        }),
+    );
+
+    match std::fs::read_to_string(audit_log::path()) {
+        Ok(text) => {
+            let lines: Vec<&str> = text.lines().collect();
+            let start = lines.len().saturating_sub(MAX_LOG_LINES);
+            let masked: Vec<String> = lines[start..]
+                .iter()
+                .map(|line| match serde_json::from_str::<Value>(line) {
+                    Ok(mut v) => {
+                        mask_json(&mut v);
+                        v.to_string()
+                    }
+                    Err(_) => mask_text(line),
+                })
+                .collect();
+            plan.add(
+                "logs/audit.jsonl",
+                "最近的审计日志（已脱敏）",
+                (masked.join("\n") + "\n").into_bytes(),
+            );
+        }
+        Err(e) => tracing::debug!("诊断包跳过审计日志: {}", e),
+    }
+
+    plan
+}
+
+pub(crate) fn route_table(state: &AppState) -> Value {
+    let selection = state
+        .amp_selection()
+        .map(|(claude, codex, gemini)| {
+            let describe = |p: Option<SelectedProfile>| {
+                p.map(|p| json!({ "name": p.name, "base_url": p.base_url }))
+            };
+            json!({
+                "claude": describe(claude),
+                "codex": describe(codex),
+                "gemini": describe(gemini),
+            })
+        })
+        .unwrap_or_else(|e| json!({ "error": e.to_string() }));
+
+    json!({
+        "rules": [
+            { "match": "?webSearch2 / ?extractWebPageContent", "route": "local_tool" },
+            { "match": "/api/provider/anthropic/*", "route": "claude" },
+            { "match": "/api/provider/openai/*", "route": "codex" },
+            { "match": "/api/provider/google/*", "route": "gemini" },
+            { "match": "/api/*", "route": "amp" },
+            { "match": "LLM 路径 / anthropic-version / model", "route": "claude|codex|gemini" },
+        ],
+        "selection": selection,
+    })
+}
+
+/// 写出已确认的诊断包，返回 zip 路径
+pub(crate) fn write(plan: &BundlePlan) -> Result<PathBuf> {
+    let dir = dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".duckcoding")
+        .join("amp")
+        .join("diagnostics");
+    std::fs::create_dir_all(&dir).map_err(|e| anyhow!("创建诊断目录失败: {}", e))?;
+    let path = dir.join(format!("dc-diagnostics-{}.zip", plan.created_ms));
+
+    let files: Vec<(&str, &[u8])> = plan
+        .entries
+        .iter()
+        .map(|e| (e.name.as_str(), e.data.as_slice()))
+        .collect();
+    std::fs::write(&path, build_zip(&files)).map_err(|e| anyhow!("写入诊断包失败: {}", e))?;
+    tracing::info!("诊断包已生成: {}", path.display());
+    Ok(path)
+}
+
+/// 生成 stored 格式 zip
+fn build_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    // 1980-01-01 00:00
+    const DOS_TIME: u16 = 0;
+    const DOS_DATE: u16 = 0x21;
+
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (name, data) in files {
+        let offset = out.len() as u32;
+        let crc = crc32(data);
+        let size = data.len() as u32;
+        let name = name.as_bytes();
+
+        out.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        out.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        out.extend_from_slice(&0x0800u16.to_le_bytes()); // UTF-8 文件名
+        out.extend_from_slice(&0u16.to_le_bytes()); // stored
+        out.extend_from_slice(&DOS_TIME.to_le_bytes());
+        out.extend_from_slice(&DOS_DATE.to_le_bytes());
+        out.extend_from_slice(&crc.to_le_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(name);
+        out.extend_from_slice(data);
+
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        central.extend_from_slice(&20u16.to_le_bytes());
+        central.extend_from_slice(&0x0800u16.to_le_bytes());
+        central.extend_from_slice(&0u16.to_le_bytes());
+        central.extend_from_slice(&DOS_TIME.to_le_bytes());
+        central.extend_from_slice(&DOS_DATE.to_le_bytes());
+        central.extend_from_slice(&crc.to_le_bytes());
+        central.extend_from_slice(&size.to_le_bytes());
+        central.extend_from_slice(&size.to_le_bytes());
+        central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        central.extend_from_slice(&[0u8; 12]); // extra / comment / disk / attrs
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name);
+    }
+
+    let central_offset = out.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    out.extend_from_slice(&[0u8; 4]); // disk numbers
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    out.extend_from_slice(&central_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(data: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([data[at], data[at + 1]])
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn masks_secret_text() {
+        assert_eq!(
+            mask_text("key sk-ant-abcdef123456 and Bearer abc.def.ghi9"),
+            "key *** and ***"
+        );
+        assert_eq!(mask_text("nothing here"), "nothing here");
+    }
+
+    #[test]
+    fn masks_secret_fields_and_nested_accounts() {
+        let mut value = json!({
+            "azure": { "api_key": "plain", "base_url": "https://x" },
+            "amp_auth": { "accounts": { "work": "t1" } },
+            "oauth": { "refresh_tokens": { "work": "r1" } },
+            "notes": ["uses tvly-abcdefgh1234"],
+            "token_ttl": null
+        });
+        mask_json(&mut value);
+        assert_eq!(value["azure"]["api_key"], "***");
+        assert_eq!(value["azure"]["base_url"], "https://x");
+        assert_eq!(value["amp_auth"]["accounts"]["work"], "t1");
+        assert_eq!(value["oauth"]["refresh_tokens"]["work"], "***");
+        assert_eq!(value["notes"][0], "uses ***");
+        assert!(value["token_ttl"].is_null());
+    }
+
+    #[test]
+    fn crc32_matches_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn zip_has_consistent_directory() {
+        let zip = build_zip(&[("a.txt", b"hello"), ("dir/b.json", b"{}")]);
+        assert_eq!(u32_at(&zip, 0), 0x0403_4b50);
+        assert_eq!(u32_at(&zip, 14), crc32(b"hello"));
+        assert_eq!(&zip[30..35], b"a.txt");
+        assert_eq!(&zip[35..40], b"hello");
+
+        let eocd = zip.len() - 22;
+        assert_eq!(u32_at(&zip, eocd), 0x0605_4b50);
+        assert_eq!(u16_at(&zip, eocd + 10), 2);
+        let central = u32_at(&zip, eocd + 16) as usize;
+        assert_eq!(u32_at(&zip, central), 0x0201_4b50);
+        assert_eq!(central + u32_at(&zip, eocd + 12) as usize, eocd);
+        // 第二个文件的本地头偏移
+        let second = central + 46 + "a.txt".len();
+        let offset = u32_at(&zip, second + 42) as usize;
+        assert_eq!(u32_at(&zip, offset), 0x0403_4b50);
+        assert_eq!(&zip[offset + 30..offset + 40], b"dir/b.json");
+    }
+
+    #[test]
+    fn excluded_entries_are_dropped() {
+        let mut plan = BundlePlan {
+            created_ms: 0,
+            entries: Vec::new(),
+        };
+        plan.add("a.txt", "A", b"1".to_vec());
+        plan.add_json("b.json", "B", &json!({ "x": 1 }));
+        plan.exclude("a.txt");
+        assert_eq!(plan.entries.len(), 1);
+        assert_eq!(plan.entries[0].name, "b.json");
+        assert_eq!(plan.entries[0].size, plan.entries[0].data.len());
+    }
+}
@@ -0,0 +1,234 @@
+// 主机级 DNS 缓存
+//
+// 转发 Client 与本地工具 Client 共用：
+// - 正向结果按记录 TTL 缓存（上下限截断，避免 TTL=0 击穿或长期不刷新）
+// - 解析失败按 SOA 负缓存 TTL 缓存（无 SOA 时使用默认值）
+// - 命中率统计供指标导出
+// 也供 SSRF 校验复用：校验与实际连接使用同一份解析结果。
+
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::TokioAsyncResolver;
+use once_cell::sync::Lazy;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MIN_TTL: Duration = Duration::from_secs(5);
+const MAX_TTL: Duration = Duration::from_secs(600);
+const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(30);
+/// 超过该条目数时清理过期项
+const MAX_ENTRIES: usize = 1024;
+
+/// 全局共享实例
+static SHARED: Lazy<Arc<DnsCache>> = Lazy::new(|| Arc::new(DnsCache::new()));
+
+/// 共享缓存（用于 reqwest::ClientBuilder::dns_resolver）
+pub fn shared() -> Arc<DnsCache> {
+    SHARED.clone()
+}
+
+#[derive(Debug, Clone)]
+enum CacheEntry {
+    Positive(Vec<IpAddr>, Instant),
+    Negative(String, Instant),
+}
+
+/// 命中率统计
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DnsCacheStats {
+    pub hits: u64,
+    pub negative_hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub hit_rate: f64,
+}
+
+pub struct DnsCache {
+    resolver: Option<TokioAsyncResolver>,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    hits: AtomicU64,
+    negative_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl DnsCache {
+    fn new() -> Self {
+        let resolver = match TokioAsyncResolver::tokio_from_system_conf() {
+            Ok(r) => Some(r),
+            Err(e) => {
+                tracing::warn!("读取系统 DNS 配置失败，回退系统解析（不缓存）: {}", e);
+                None
+            }
+        };
+        Self {
+            resolver,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            negative_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// 解析主机名（优先缓存）
+    pub async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, String> {
+        let key = host.to_ascii_lowercase();
+        let now = Instant::now();
+
+        let cached = self
+            .entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+            .cloned();
+        match cached {
+            Some(CacheEntry::Positive(ips, until)) if until > now => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(ips);
+            }
+            Some(CacheEntry::Negative(err, until)) if until > now => {
+                self.negative_hits.fetch_add(1, Ordering::Relaxed);
+                return Err(err);
+            }
+            _ => {}
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let Some(resolver) = &self.resolver else {
+            return tokio::net::lookup_host((key.as_str(), 0))
+                .await
+                .map(|addrs| addrs.map(|a| a.ip()).collect())
+                .map_err(|e| format!("DNS 解析失败 {}: {}", host, e));
+        };
+
+        let entry = match resolver.lookup_ip(key.as_str()).await {
+            Ok(lookup) => {
+                let ips: Vec<IpAddr> = lookup.iter().collect();
+                let ttl = lookup
+                    .valid_until()
+                    .saturating_duration_since(now)
+                    .clamp(MIN_TTL, MAX_TTL);
+                CacheEntry::Positive(ips, now + ttl)
+            }
+            Err(e) => {
+                let ttl = match e.kind() {
+                    ResolveErrorKind::NoRecordsFound {
+                        negative_ttl: Some(t),
+                        ..
+                    } => Duration::from_secs(*t as u64).clamp(MIN_TTL, MAX_TTL),
+                    _ => DEFAULT_NEGATIVE_TTL,
+                };
+                CacheEntry::Negative(format!("DNS 解析失败 {}: {}", host, e), now + ttl)
+            }
+        };
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, v| match v {
+                CacheEntry::Positive(_, until) | CacheEntry::Negative(_, until) => *until > now,
+            });
+        }
+        entries.insert(key, entry.clone());
+        drop(entries);
+
+        match entry {
+            CacheEntry::Positive(ips, _) => Ok(ips),
+            CacheEntry::Negative(err, _) => Err(err),
+        }
+    }
+
+    pub fn stats(&self) -> DnsCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let negative_hits = self.negative_hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let total = hits + negative_hits + misses;
+        DnsCacheStats {
+            hits,
+            negative_hits,
+            misses,
+            entries: self.entries.lock().unwrap_or_else(|e| e.into_inner()).len(),
+            hit_rate: if total == 0 {
+                0.0
+            } else {
+                (hits + negative_hits) as f64 / total as f64
+            },
+        }
+    }
+
+    /// 清空缓存（网络环境变化时调用）
+    pub fn clear(&self) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+}
+
+impl Resolve for DnsCache {
+    fn resolve(&self, name: Name) -> Resolving {
+        // 仅存在全局实例（构造函数私有），future 需 'static，故取共享 Arc
+        let cache = SHARED.clone();
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let ips = cache.lookup(&host).await?;
+            let addrs: Addrs = Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_with(entries: Vec<(&str, CacheEntry)>) -> DnsCache {
+        DnsCache {
+            resolver: None,
+            entries: Mutex::new(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v))
+                    .collect(),
+            ),
+            hits: AtomicU64::new(0),
+            negative_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    #[tokio::test]
+    async fn cached_entries_are_served_case_insensitively() {
+        let until = Instant::now() + Duration::from_secs(60);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let cache = cache_with(vec![
+            ("api.example.com", CacheEntry::Positive(vec![ip], until)),
+            (
+                "gone.example.com",
+                CacheEntry::Negative("NXDOMAIN".into(), until),
+            ),
+        ]);
+
+        assert_eq!(cache.lookup("API.Example.com").await.unwrap(), vec![ip]);
+        assert_eq!(
+            cache.lookup("gone.example.com").await.unwrap_err(),
+            "NXDOMAIN"
+        );
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.negative_hits, stats.misses), (1, 1, 0));
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.hit_rate, 1.0);
+    }
+
+    #[test]
+    fn empty_stats_and_clear() {
+        let until = Instant::now() + Duration::from_secs(60);
+        let cache = cache_with(vec![("a", CacheEntry::Positive(Vec::new(), until))]);
+        assert_eq!(cache.stats().hit_rate, 0.0);
+        cache.clear();
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
@@ -0,0 +1,358 @@
+// 文档搜索本地工具（searchDocs）
+//
+// 编程类问题优先查权威文档，而不是通用网页结果：
+// - MDN：developer.mozilla.org 站内搜索 API
+// - devdocs.io：按 devdocs 配置的文档集（javascript、python~3.12、rust 等）下载索引，
+//   在本地按名称匹配打分；Rust 标准库文档走 devdocs 的 rust 文档集
+//   （doc.rust-lang.org 的搜索索引为前端 JS，不便直接查询）
+// AMP 以本地工具方式调用（?searchDocs）：params.query，可选 params.sources（如 ["mdn", "rust"]）
+// 与 params.maxResults。merge_into_web_search 开启时，webSearch2 对看起来是编程问题的查询
+// 把文档结果排在网页结果之前。devdocs 索引在内存缓存 index_ttl_secs 秒。
+
+use super::bandwidth::{self, Subject};
+use anyhow::{anyhow, Result};
+use futures_util::future::{join_all, BoxFuture};
+use futures_util::FutureExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DocsSearchSettings {
+    /// 查询的 devdocs 文档集
+    pub devdocs: Vec<String>,
+    /// 是否查询 MDN
+    pub mdn: bool,
+    /// webSearch2 遇到编程类查询时把文档结果排在前面
+    pub merge_into_web_search: bool,
+    /// 合并到 webSearch2 的文档结果条数上限
+    pub web_search_slots: usize,
+    /// devdocs 索引缓存时间（秒）
+    pub index_ttl_secs: u64,
+}
+
+impl Default for DocsSearchSettings {
+    fn default() -> Self {
+        Self {
+            devdocs: vec![
+                "javascript".to_string(),
+                "dom".to_string(),
+                "css".to_string(),
+                "html".to_string(),
+                "python~3.12".to_string(),
+                "rust".to_string(),
+            ],
+            mdn: true,
+            merge_into_web_search: true,
+            web_search_slots: 3,
+            index_ttl_secs: 86_400,
+        }
+    }
+}
+
+/// 文档搜索结果
+pub(crate) struct DocHit {
+    pub title: String,
+    pub url: String,
+    pub source: String,
+    pub summary: String,
+    score: u32,
+}
+
+impl DocHit {
+    fn to_json(&self) -> Value {
+        json!({
+            "title": self.title,
+            "url": self.url,
+            "source": self.source,
+            "summary": self.summary
+        })
+    }
+
+    /// webSearch2 结果格式
+    pub(crate) fn to_web_result(&self) -> Value {
+        json!({
+            "title": format!("{} — {}", self.title, self.source),
+            "url": self.url,
+            "excerpts": if self.summary.is_empty() { vec![] } else { vec![self.summary.clone()] }
+        })
+    }
+}
+
+/// devdocs 索引条目：(名称, 小写名称, 路径, 类型)
+type Index = Arc<Vec<(String, String, String, String)>>;
+
+static INDEXES: Lazy<Mutex<HashMap<String, (Instant, Index)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 编程相关的提示词
+const PROGRAMMING_HINTS: [&str; 24] = [
+    "rust",
+    "python",
+    "javascript",
+    "typescript",
+    "js",
+    "css",
+    "html",
+    "dom",
+    "api",
+    "function",
+    "method",
+    "class",
+    "struct",
+    "trait",
+    "module",
+    "error",
+    "exception",
+    "syntax",
+    "async",
+    "array",
+    "string",
+    "regex",
+    "std",
+    "npm",
+];
+
+/// 粗略判断查询是否为编程问题
+pub(crate) fn looks_like_programming(query: &str) -> bool {
+    let lower = query.to_lowercase();
+    if ["::", "()", "=>", ".prototype", "#[", "</", "`"]
+        .iter()
+        .any(|s| lower.contains(s))
+    {
+        return true;
+    }
+    lower
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| PROGRAMMING_HINTS.contains(&word))
+}
+
+async fn devdocs_index(client: &reqwest::Client, slug: &str, ttl: Duration) -> Result<Index> {
+    if let Some((at, index)) = INDEXES.lock().unwrap_or_else(|e| e.into_inner()).get(slug) {
+        if at.elapsed() < ttl {
+            return Ok(index.clone());
+        }
+    }
+    let resp = client
+        .get(format!("https://devdocs.io/docs/{}/index.json", slug))
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("devdocs {} 索引返回 HTTP {}", slug, resp.status()));
+    }
+    let raw = resp.bytes().await?;
+    bandwidth::record(Subject::Tool("searchDocs"), 0, raw.len() as u64);
+    let data: Value = serde_json::from_slice(&raw)?;
+    let entries: Vec<_> = data["entries"]
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|e| {
+                    let name = e["name"].as_str()?;
+                    Some((
+                        name.to_string(),
+                        name.to_lowercase(),
+                        e["path"].as_str()?.to_string(),
+                        e["type"].as_str().unwrap_or("").to_string(),
+                    ))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let index = Arc::new(entries);
+    INDEXES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(slug.to_string(), (Instant::now(), index.clone()));
+    Ok(index)
+}
+
+/// 名称匹配打分：完全匹配 > 末段匹配 > 前缀 > 包含；所有查询词都需命中
+fn score(name: &str, query: &str, words: &[&str]) -> Option<u32> {
+    if !words.iter().all(|w| name.contains(w)) {
+        return None;
+    }
+    let last = name.rsplit(['.', ':', '/', ' ']).next().unwrap_or(name);
+    let base = if name == query {
+        100
+    } else if last == query {
+        90
+    } else if name.starts_with(query) {
+        75
+    } else if name.contains(query) {
+        60
+    } else {
+        40
+    };
+    // 名称越短越接近目标条目
+    Some(base + 20u32.saturating_sub(name.len() as u32 / 4))
+}
+
+async fn search_devdocs(
+    client: &reqwest::Client,
+    slug: &str,
+    query: &str,
+    limit: usize,
+    ttl: Duration,
+) -> Result<Vec<DocHit>> {
+    let index = devdocs_index(client, slug, ttl).await?;
+    let query = query.to_lowercase();
+    let words: Vec<&str> = query.split_whitespace().collect();
+    let mut hits: Vec<DocHit> = index
+        .iter()
+        .filter_map(|(name, lower, path, kind)| {
+            Some(DocHit {
+                title: name.clone(),
+                url: format!("https://devdocs.io/{}/{}", slug, path),
+                source: format!("devdocs:{}", slug),
+                summary: kind.clone(),
+                score: score(lower, &query, &words)?,
+            })
+        })
+        .collect();
+    hits.sort_by_key(|h| std::cmp::Reverse(h.score));
+    hits.truncate(limit);
+    Ok(hits)
+}
+
+async fn search_mdn(client: &reqwest::Client, query: &str, limit: usize) -> Result<Vec<DocHit>> {
+    let resp = client
+        .get("https://developer.mozilla.org/api/v1/search")
+        .query(&[("q", query), ("locale", "en-US")])
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("MDN 搜索返回 HTTP {}", resp.status()));
+    }
+    let raw = resp.bytes().await?;
+    bandwidth::record(Subject::Tool("searchDocs"), 0, raw.len() as u64);
+    let data: Value = serde_json::from_slice(&raw)?;
+    Ok(data["documents"]
+        .as_array()
+        .map(|docs| {
+            docs.iter()
+                .take(limit)
+                .enumerate()
+                .map(|(rank, d)| DocHit {
+                    title: d["title"].as_str().unwrap_or("").to_string(),
+                    url: format!(
+                        "https://developer.mozilla.org{}",
+                        d["mdn_url"].as_str().unwrap_or("")
+                    ),
+                    source: "mdn".to_string(),
+                    summary: d["summary"].as_str().unwrap_or("").to_string(),
+                    // MDN 自身已排序，按名次给分
+                    score: 95u32.saturating_sub(rank as u32 * 5),
+                })
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// 查询全部（或 sources 指定的）文档源，合并后按分数排序
+pub(crate) async fn search(
+    client: &reqwest::Client,
+    settings: &DocsSearchSettings,
+    query: &str,
+    sources: Option<&[String]>,
+    limit: usize,
+) -> Vec<DocHit> {
+    let wanted = |name: &str| sources.is_none_or(|s| s.iter().any(|x| x == name));
+    let ttl = Duration::from_secs(settings.index_ttl_secs);
+    let mut tasks: Vec<BoxFuture<'_, Result<Vec<DocHit>>>> = Vec::new();
+    if settings.mdn && wanted("mdn") {
+        tasks.push(search_mdn(client, query, limit).boxed());
+    }
+    for slug in &settings.devdocs {
+        // 文档集名可按 "rust" 或 "python~3.12" / "python" 指定
+        let family = slug.split('~').next().unwrap_or(slug);
+        if wanted(slug) || wanted(family) {
+            tasks.push(search_devdocs(client, slug, query, limit, ttl).boxed());
+        }
+    }
+
+    let mut hits: Vec<DocHit> = Vec::new();
+    for result in join_all(tasks).await {
+        match result {
+            Ok(found) => hits.extend(found),
+            Err(e) => tracing::warn!("文档搜索源失败: {}", e),
+        }
+    }
+    hits.sort_by_key(|h| std::cmp::Reverse(h.score));
+    hits.truncate(limit);
+    hits
+}
+
+/// searchDocs：返回本地工具应答
+pub(crate) async fn handle(
+    client: &reqwest::Client,
+    settings: &DocsSearchSettings,
+    body: &[u8],
+) -> Result<Value> {
+    let req_json: Value =
+        serde_json::from_slice(body).map_err(|e| anyhow!("请求 JSON 解析失败: {}", e))?;
+    let params = &req_json["params"];
+    let query = params["query"]
+        .as_str()
+        .map(str::trim)
+        .filter(|q| !q.is_empty())
+        .ok_or_else(|| anyhow!("缺少 query 参数"))?;
+    let sources: Option<Vec<String>> = params["sources"].as_array().map(|items| {
+        items
+            .iter()
+            .filter_map(|s| s.as_str().map(str::to_string))
+            .collect()
+    });
+    let limit = params["maxResults"].as_u64().unwrap_or(8).clamp(1, 50) as usize;
+
+    tracing::info!("本地文档搜索: {}", query);
+    let hits = search(client, settings, query, sources.as_deref(), limit).await;
+    tracing::info!("本地文档搜索完成: {} 条结果", hits.len());
+    Ok(json!({
+        "ok": true,
+        "result": {
+            "results": hits.iter().map(DocHit::to_json).collect::<Vec<_>>(),
+            "provider": "local-docs"
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rank(name: &str, query: &str) -> Option<u32> {
+        let words: Vec<&str> = query.split_whitespace().collect();
+        score(name, query, &words)
+    }
+
+    #[test]
+    fn detects_programming_queries() {
+        assert!(looks_like_programming("Rust trait objects"));
+        assert!(looks_like_programming("Vec::retain"));
+        assert!(looks_like_programming("Array.prototype.flat"));
+        assert!(!looks_like_programming("weather in paris tomorrow"));
+        assert!(!looks_like_programming("trusty old bicycle"));
+    }
+
+    #[test]
+    fn score_prefers_exact_then_last_segment() {
+        let exact = rank("vec::retain", "vec::retain").unwrap();
+        let last = rank("std::vec::vec::retain", "retain").unwrap();
+        let prefix = rank("retain_mut", "retain").unwrap();
+        let contains = rank("hashmap::retain_mut", "retain").unwrap();
+        assert!(exact > last && last > prefix && prefix > contains);
+        assert!(rank("hashmap", "retain").is_none());
+    }
+
+    #[test]
+    fn score_requires_every_word() {
+        assert!(rank("array.prototype.flat", "array flat").is_some());
+        assert!(rank("array.prototype.map", "array flat").is_none());
+    }
+}
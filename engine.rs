@@ -0,0 +1,390 @@
+// 嵌入用门面（稳定 API）
+//
+// 其他 Rust 程序把 AMP 路由引擎作为库嵌入时只依赖本模块，不直接引用内部模块路径
+// （内部模块在版本间会调整，不属于稳定 API）：
+// - AmpEngine::builder() 组装处理器注册表（默认含 amp-code / claude-code / codex / gemini-cli），
+//   可追加或替换自定义处理器，按需启用后台子系统（配置热加载、观测面板、管理 API、Token 健康检查、本地语料索引）
+// - ProfileManager / ProxyConfigManager 由 AppState 缓存（builder 默认新建一个，可用 state() 注入），
+//   内置处理器、转发层与各子系统都使用引擎持有的这一个 AppState；管理 API、观测面板与
+//   Token 检查按 AppState 各启动一次。密钥存储、缓存、熔断标记、限流桶等仍为进程级（见 app_state.rs）；
+//   build() 时即构造一次，配置损坏时立即报错，而不是等到第一个请求才失败
+// - process() 按 tool_id 调用处理器，返回 ProcessOutcome（见 outcome.rs），process_outcome() 把错误并入 Reject；
+//   processor() 注册的 RequestProcessor 结果包装为转发请求，需要本地响应的处理器经 outcome_processor() 注册；
+//   dispatch() 按类别分发：本地响应直接构造（可带状态码 / 流式响应体），
+//   带转发层标记的请求（LLM 路由与 AmpInternal）经 upstream::forward（故障转移、限流、日志、
+//   AmpInternal 401 刷新 Token 重发等全部生效），未标记的请求以中性路由 direct 同样经转发层发送
+//   （默认 Profile 配置，无备用链，不刷新 AMP Token），拒绝返回错误；send() 接收 ProcessedRequest，handle() 为组合
+// - settings() / save_settings() 读写处理器配置（~/.duckcoding/processor_settings.json）；
+//   config(AmpConfig) 改用代码中构建的配置（安装到引擎持有的 AppState，见 amp_config.rs），不读写文件
+// - runtime(handle) 指定后台任务所在的运行时，build() 可在运行时外调用；句柄显式传给各子系统与
+//   内置 amp-code 处理器（请求中按需启动的后台任务同样派发到该运行时），不使用进程级全局句柄。
+//   未指定时 build() 启动的子系统使用调用 build() 时所在的运行时，请求中启动的使用处理请求的运行时；
+//   请求路径内部的派生任务（流式包装、过载排队等）直接使用当前运行时，current_thread 运行时同样可用
+//   （不使用 block_in_place / block_on）
+// - 宿主日志输出可用 redacting_log_writer 包装（见 log_redact.rs），按行掩码密钥与 user_id
+// - 集成测试 / 演示可在槽位配置中开启 mock（见 mock_upstream.rs），reset_mock() 重置脚本轮流进度
+// 宿主 crate 根需 pub use 本模块的导出项（lib 入口不在本仓库内）。
+//
+// Cargo 特性：宿主 Cargo.toml 须原样声明（桌面端构建全部启用）：
+//   [features]
+//   default = []
+//   sqlite = ["dep:rusqlite"]   # 请求日志与 SQLite 存储后端（见 request_log.rs、storage.rs）
+//   keychain = ["dep:keyring"]  # Profile / 代理配置密钥存入系统钥匙串（见 secret_store.rs）
+//   dashboard = []              # 观测面板页面与独立监听端口（见 dashboard.rs）
+//   admin = []                  # 本地管理 API 监听端口（见 admin_api.rs）
+//   [dependencies]
+//   rusqlite = { version = "0.31", features = ["bundled"], optional = true }
+//   keyring = { version = "2", optional = true }
+// dashboard / admin 只用到必需依赖（tokio 的 net 特性，DNS 缓存同样需要），无额外 crate。
+// 未启用的特性只保留配置结构，配置中开启时记录告警，不影响其余功能；
+// 启用后是否监听仍由各自配置的 enabled 决定。
+// DEFLATE 解码依赖 miniz_oxide（见 inflate.rs），同样需要在宿主 Cargo.toml 中声明。
+
+use super::admin_api;
+use super::amp_processor::AmpHeadersProcessor;
+use super::app_state::AppState;
+use super::dashboard;
+use super::local_corpus;
+use super::mock_upstream;
+use super::secret_store;
+use super::token_health;
+use super::upstream;
+use super::{
+    ClaudeHeadersProcessor, CodexHeadersProcessor, GeminiHeadersProcessor, RequestProcessor,
+};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use hyper::HeaderMap;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+pub use super::amp_config::{AmpConfig, AmpConfigBuilder, Route, Rule};
+pub use super::log_redact::{writer as redacting_log_writer, RedactingWriter};
+pub use super::mock_upstream::{MockStep, MockUpstreamSettings};
+pub use super::outcome::{ForwardOnly, LocalBody, LocalStream, OutcomeProcessor, ProcessOutcome};
+pub use super::processor_settings::ProcessorSettings as EngineSettings;
+pub use super::processor_settings::{ProfileSettings, TransformSettings};
+pub use super::routing_rules::RoutingRule;
+pub use super::secret_store::{MemoryStore, SecretStore};
+pub use super::upstream::{Forwarded, UpstreamTag, SERVED_BY_HEADER};
+pub use super::ProcessedRequest;
+
+const AMP_TOOL_ID: &str = "amp-code";
+
+/// 入站请求（宿主代理收到的原始请求）
+#[derive(Debug, Clone)]
+pub struct EngineRequest {
+    pub method: reqwest::Method,
+    /// 不含 query 的路径
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// 可选后台子系统
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Subsystems {
+    /// 配置文件变化时重新加载 Profile / 代理配置（见 app_state.rs）
+    pub config_watch: bool,
+    /// 观测面板（按 dashboard 配置监听，需 dashboard 特性）
+    pub dashboard: bool,
+    /// 本地管理 API（按 admin 配置监听，需 admin 特性）
+    pub admin: bool,
+    /// AMP 账号 Token 健康检查
+    pub token_health: bool,
+    /// 本地语料索引（search.local_corpus 配置目录后生效）
+    pub local_corpus: bool,
+}
+
+pub struct AmpEngineBuilder {
+    processors: BTreeMap<String, Arc<dyn OutcomeProcessor>>,
+    /// 是否注册内置 amp-code 处理器（build 时按 state 构造）
+    builtin_amp: bool,
+    state: Arc<AppState>,
+    subsystems: Subsystems,
+    runtime: Option<tokio::runtime::Handle>,
+    config: Option<AmpConfig>,
+    secret_store: Option<Arc<dyn SecretStore>>,
+}
+
+impl Default for AmpEngineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AmpEngineBuilder {
+    /// 默认注册内置处理器，不启用后台子系统
+    pub fn new() -> Self {
+        let builder = Self {
+            processors: BTreeMap::new(),
+            builtin_amp: true,
+            state: Arc::new(AppState::new()),
+            subsystems: Subsystems::default(),
+            runtime: None,
+            config: None,
+            secret_store: None,
+        };
+        builder
+            .processor(Arc::new(ClaudeHeadersProcessor))
+            .processor(Arc::new(CodexHeadersProcessor))
+            .processor(Arc::new(GeminiHeadersProcessor))
+    }
+
+    /// 注册处理器（结果包装为转发请求）；同一 tool_id 已存在时替换
+    pub fn processor(self, processor: Arc<dyn RequestProcessor>) -> Self {
+        let tool_id = processor.tool_id().to_string();
+        self.outcome_processor(&tool_id, Arc::new(ForwardOnly(processor)))
+    }
+
+    /// 注册可返回本地响应 / 拒绝的处理器；同一 tool_id 已存在时替换
+    pub fn outcome_processor(
+        mut self,
+        tool_id: &str,
+        processor: Arc<dyn OutcomeProcessor>,
+    ) -> Self {
+        if tool_id == AMP_TOOL_ID {
+            self.builtin_amp = false;
+        }
+        self.processors.insert(tool_id.to_string(), processor);
+        self
+    }
+
+    /// 移除处理器（如嵌入方只需要 amp-code）
+    pub fn without_processor(mut self, tool_id: &str) -> Self {
+        if tool_id == AMP_TOOL_ID {
+            self.builtin_amp = false;
+        }
+        self.processors.remove(tool_id);
+        self
+    }
+
+    /// 使用指定的共享状态（默认新建 AppState；多个引擎可共享同一实例）
+    pub fn state(mut self, state: Arc<AppState>) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn subsystems(mut self, subsystems: Subsystems) -> Self {
+        self.subsystems = subsystems;
+        self
+    }
+
+    /// 使用代码中构建的配置（build() 时安装到引擎持有的 AppState，不再读写配置文件）
+    pub fn config(mut self, config: AmpConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// 替换密钥存储（默认系统钥匙串，需 keychain 特性）；测试可用 MemoryStore。
+    /// 密钥存储是进程级的，build() 时安装后对同一进程内的所有引擎生效
+    pub fn secret_store(mut self, store: Arc<dyn SecretStore>) -> Self {
+        self.secret_store = Some(store);
+        self
+    }
+
+    /// 后台任务所在的运行时（如宿主已有的运行时句柄）
+    pub fn runtime(mut self, handle: tokio::runtime::Handle) -> Self {
+        self.runtime = Some(handle);
+        self
+    }
+
+    /// 校验配置并启用所选子系统；未指定 runtime 且启用了子系统时须在 tokio 运行时内调用
+    pub fn build(mut self) -> Result<AmpEngine> {
+        if let Some(store) = self.secret_store.take() {
+            secret_store::install(store);
+        }
+        if let Some(config) = self.config.take() {
+            config.install(&self.state);
+        }
+        self.state.profile_manager()?;
+        self.state.proxy_config_manager()?;
+        let settings = self.state.load_settings()?;
+
+        let explicit = self.runtime.take();
+        if self.builtin_amp {
+            let mut amp = AmpHeadersProcessor::new(self.state.clone());
+            if let Some(handle) = explicit.clone() {
+                amp = amp.with_runtime(handle);
+            }
+            self.processors
+                .insert(AMP_TOOL_ID.to_string(), Arc::new(amp));
+        }
+        if explicit.is_some() {
+            // HTTP Client 的连接任务属于创建它的运行时，切换运行时后重建
+            self.state.outbound().reset();
+        }
+
+        let s = self.subsystems;
+        if s.config_watch || s.dashboard || s.admin || s.token_health || s.local_corpus {
+            let runtime = explicit
+                .or_else(|| tokio::runtime::Handle::try_current().ok())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "启用后台子系统需在 tokio 运行时内调用 build()，或用 runtime() 指定运行时"
+                    )
+                })?;
+            if s.config_watch {
+                self.state.ensure_watching(&runtime);
+            }
+            if s.dashboard {
+                dashboard::ensure_started(&self.state, &runtime);
+            }
+            if s.admin {
+                admin_api::ensure_started(&self.state, &runtime);
+            }
+            if s.token_health {
+                token_health::ensure_checker(&self.state, &runtime);
+            }
+            if s.local_corpus {
+                local_corpus::ensure_indexed(&settings.search.local_corpus, &runtime);
+            }
+        }
+        Ok(AmpEngine {
+            processors: self.processors,
+            state: self.state,
+        })
+    }
+}
+
+/// AMP 路由引擎
+pub struct AmpEngine {
+    processors: BTreeMap<String, Arc<dyn OutcomeProcessor>>,
+    state: Arc<AppState>,
+}
+
+impl AmpEngine {
+    pub fn builder() -> AmpEngineBuilder {
+        AmpEngineBuilder::new()
+    }
+
+    /// 已注册的 tool_id
+    pub fn tool_ids(&self) -> impl Iterator<Item = &str> {
+        self.processors.keys().map(String::as_str)
+    }
+
+    pub fn processor(&self, tool_id: &str) -> Option<Arc<dyn OutcomeProcessor>> {
+        self.processors.get(tool_id).cloned()
+    }
+
+    pub fn state(&self) -> &Arc<AppState> {
+        &self.state
+    }
+
+    /// 当前处理器配置（文件损坏时返回错误）
+    pub fn settings(&self) -> Result<EngineSettings> {
+        self.state.load_settings()
+    }
+
+    /// 写回处理器配置，后续请求即按新配置处理
+    pub fn save_settings(&self, settings: &EngineSettings) -> Result<()> {
+        self.state.save_settings(settings)
+    }
+
+    /// 重置模拟上游的脚本轮流进度（测试用例之间调用）
+    pub fn reset_mock(&self) {
+        mock_upstream::reset();
+    }
+
+    /// 调用 tool_id 对应处理器；上游地址与 Key 取自 ProxyConfigManager 中该工具的配置
+    pub async fn process(&self, tool_id: &str, request: &EngineRequest) -> Result<ProcessOutcome> {
+        let processor = self
+            .processor(tool_id)
+            .ok_or_else(|| anyhow!("未注册的处理器: {}", tool_id))?;
+        let config = self
+            .state
+            .proxy_config_manager()?
+            .get_config(tool_id)
+            .map_err(|e| anyhow!("读取配置失败: {}", e))?;
+        let base_url = config
+            .as_ref()
+            .and_then(|c| c.real_base_url.clone())
+            .unwrap_or_default();
+        let api_key = config
+            .and_then(|c| secret_store::reveal_opt(c.real_api_key))
+            .unwrap_or_default();
+        processor
+            .process_outcome(
+                &base_url,
+                &api_key,
+                &request.path,
+                request.query.as_deref(),
+                &request.headers,
+                &request.body,
+            )
+            .await
+    }
+
+    /// 调用处理器并归类结果（转发 / 本地响应 / 拒绝）
+    pub async fn process_outcome(&self, tool_id: &str, request: &EngineRequest) -> ProcessOutcome {
+        self.process(tool_id, request).await.into()
+    }
+
+    /// 发送处理结果
+    pub async fn send(
+        &self,
+        method: reqwest::Method,
+        request: ProcessedRequest,
+    ) -> Result<Forwarded> {
+        self.dispatch(method, request.into()).await
+    }
+
+    /// 按处理结果分发：本地响应直接构造，转发请求经 upstream::forward（未标记的使用中性标记）
+    pub async fn dispatch(
+        &self,
+        method: reqwest::Method,
+        outcome: ProcessOutcome,
+    ) -> Result<Forwarded> {
+        let (target_url, headers, body, tag) = match outcome {
+            ProcessOutcome::Forward {
+                target_url,
+                headers,
+                body,
+                upstream,
+            } => (target_url, headers, body, upstream),
+            ProcessOutcome::LocalResponse {
+                source,
+                status,
+                headers,
+                body,
+            } => return Ok(local_response(&source, status, headers, body)),
+            ProcessOutcome::Reject { error } => return Err(error),
+        };
+        let request = ProcessedRequest {
+            target_url,
+            headers,
+            body,
+        };
+        let tag = tag.unwrap_or_else(UpstreamTag::direct);
+        upstream::forward(&self.state, method, request, tag).await
+    }
+
+    /// process + dispatch
+    pub async fn handle(&self, tool_id: &str, request: EngineRequest) -> Result<Forwarded> {
+        let method = request.method.clone();
+        let outcome = self.process_outcome(tool_id, &request).await;
+        self.dispatch(method, outcome).await
+    }
+}
+
+/// 本地响应：served_by 取来源的第一段（如 maintenance/claude → maintenance）
+fn local_response(
+    source: &str,
+    status: hyper::StatusCode,
+    headers: HeaderMap,
+    body: LocalBody,
+) -> Forwarded {
+    let served_by = source.split('/').next().unwrap_or("local").to_string();
+    let body = match body {
+        LocalBody::Full(bytes) => reqwest::Body::from(bytes),
+        LocalBody::Stream(stream) => reqwest::Body::wrap_stream(stream),
+    };
+    let mut response = hyper::http::Response::new(body);
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    Forwarded {
+        response: reqwest::Response::from(response),
+        served_by,
+    }
+}
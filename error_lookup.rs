@@ -0,0 +1,400 @@
+// 报错 / 堆栈查询增强（webSearch2）
+//
+// 搜索目标看起来像报错信息或堆栈时，原样搜索效果很差（路径、地址、行号都是噪声）。
+// 此时走专门流程：
+// 1. 归一化：取最有信息量的一行（Python traceback 取最后一行），去掉文件路径、
+//    十六进制地址、UUID、时间戳与长数字
+// 2. 优先查询 Stack Overflow（Stack Exchange API，取采纳或最高票答案正文）
+//    与 GitHub Issues（已关闭的 issue 附带最后一条评论，通常是解决方案）
+// 3. 结果带答案摘录，排在通用网页结果之前；通用搜索也改用归一化后的查询
+// Stack Exchange API 始终返回 gzip 压缩内容，见 inflate::maybe_gunzip。
+
+use super::bandwidth::{self, Subject};
+use super::inflate;
+use super::search_providers::clean_html;
+use super::secret_store;
+use anyhow::{anyhow, Result};
+use futures_util::future::join_all;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// 归一化后查询的最大字符数
+const MAX_QUERY_CHARS: usize = 200;
+/// 答案摘录的最大字符数
+const MAX_EXCERPT_CHARS: usize = 800;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ErrorLookupSettings {
+    /// 是否对报错类查询启用专门流程
+    pub enabled: bool,
+    /// 查询 Stack Overflow
+    pub stackoverflow: bool,
+    /// 查询 GitHub Issues
+    pub github_issues: bool,
+    /// GitHub token（可选，未配置时受匿名频率限制）
+    pub github_token: Option<String>,
+    /// 排在网页结果之前的条数上限
+    pub slots: usize,
+}
+
+impl Default for ErrorLookupSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            stackoverflow: true,
+            github_issues: true,
+            github_token: None,
+            slots: 4,
+        }
+    }
+}
+
+static ERROR_PATTERNS: Lazy<Vec<Regex>> = Lazy::new(|| {
+    [
+        r"Traceback \(most recent call last\)",
+        r"\b[A-Z]\w*(Error|Exception)\b:",
+        r"\bError:",
+        r"panicked at",
+        r"error\[E\d{4}\]",
+        r"(?m)^\s+at .+[(\s].+:\d+(:\d+)?\)?$",
+        r"npm ERR!",
+        r"Segmentation fault|core dumped",
+        r"fatal error:",
+        r"undefined reference to",
+        r"\bE[A-Z]{3,}\b: ",
+        r"Cannot find module",
+        r"Uncaught \w+",
+    ]
+    .iter()
+    .map(|p| Regex::new(p).unwrap())
+    .collect()
+});
+
+static KEY_LINE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(Error|Exception|panicked|error\[E|ERR!|fatal|failed|Uncaught)").unwrap()
+});
+
+static NOISE: Lazy<Vec<Regex>> = Lazy::new(|| {
+    [
+        // 时间戳
+        r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}\S*",
+        // URL 与文件路径（含行号 / 列号）
+        r"\w+://\S+",
+        r"(?:[A-Za-z]:)?[\\/]?(?:[\w.\-~@]+[\\/])+[\w.\-]+(?::\d+)*",
+        // 十六进制地址、UUID、长数字
+        r"0x[0-9a-fA-F]+",
+        r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b",
+        r"\b\d{4,}\b",
+        r"\bline \d+",
+    ]
+    .iter()
+    .map(|p| Regex::new(p).unwrap())
+    .collect()
+});
+
+/// 文本是否像报错信息或堆栈
+pub(crate) fn looks_like_error(text: &str) -> bool {
+    ERROR_PATTERNS.iter().any(|p| p.is_match(text))
+}
+
+fn strip_noise(line: &str) -> String {
+    let mut out = line.to_string();
+    for pattern in NOISE.iter() {
+        out = pattern.replace_all(&out, " ").into_owned();
+    }
+    out.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches(|c: char| c == ':' || c == ',' || c.is_whitespace())
+        .to_string()
+}
+
+/// 归一化报错文本为适合搜索的查询
+pub(crate) fn normalize(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    let key = if text.contains("Traceback (most recent call last)") {
+        // Python：异常类型与信息在最后一个非缩进行
+        lines
+            .iter()
+            .rposition(|l| !l.starts_with(char::is_whitespace))
+            .unwrap_or(0)
+    } else {
+        lines.iter().position(|l| KEY_LINE.is_match(l)).unwrap_or(0)
+    };
+
+    let mut query = lines.get(key).map(|l| strip_noise(l)).unwrap_or_default();
+    // Rust panic 等关键行以冒号结尾或过短时，信息通常在下一行
+    let continued = lines.get(key).is_some_and(|l| l.trim_end().ends_with(':'));
+    if continued || query.chars().count() < 25 {
+        if let Some(next) = lines.get(key + 1) {
+            let next = strip_noise(next);
+            if !next.is_empty() {
+                query = format!("{} {}", query, next);
+            }
+        }
+    }
+    query.chars().take(MAX_QUERY_CHARS).collect()
+}
+
+fn excerpt(html: &str) -> String {
+    let text = clean_html(html)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if text.chars().count() > MAX_EXCERPT_CHARS {
+        format!(
+            "{}…",
+            text.chars().take(MAX_EXCERPT_CHARS).collect::<String>()
+        )
+    } else {
+        text
+    }
+}
+
+async fn get_json(request: reqwest::RequestBuilder) -> Result<Value> {
+    let resp = request.send().await?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("HTTP {}", resp.status()));
+    }
+    let raw = resp.bytes().await?;
+    bandwidth::record(Subject::Tool("webSearch2"), 0, raw.len() as u64);
+    Ok(serde_json::from_slice(&inflate::maybe_gunzip(
+        raw.to_vec(),
+    ))?)
+}
+
+async fn search_stackoverflow(
+    client: &reqwest::Client,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<Value>> {
+    let base = "https://api.stackexchange.com/2.3";
+    let pagesize = limit.to_string();
+    let data = get_json(client.get(format!("{}/search/advanced", base)).query(&[
+        ("order", "desc"),
+        ("sort", "relevance"),
+        ("q", query),
+        ("answers", "1"),
+        ("site", "stackoverflow"),
+        ("pagesize", pagesize.as_str()),
+    ]))
+    .await?;
+    let questions: Vec<&Value> = data["items"]
+        .as_array()
+        .map(|items| items.iter().collect())
+        .unwrap_or_default();
+    if questions.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let ids: Vec<String> = questions
+        .iter()
+        .filter_map(|q| q["question_id"].as_u64().map(|id| id.to_string()))
+        .collect();
+    let answers = get_json(
+        client
+            .get(format!("{}/questions/{}/answers", base, ids.join(";")))
+            .query(&[
+                ("order", "desc"),
+                ("sort", "votes"),
+                ("site", "stackoverflow"),
+                ("filter", "withbody"),
+                ("pagesize", "100"),
+            ]),
+    )
+    .await
+    .unwrap_or(Value::Null);
+    let answers: Vec<&Value> = answers["items"]
+        .as_array()
+        .map(|items| items.iter().collect())
+        .unwrap_or_default();
+
+    Ok(questions
+        .iter()
+        .map(|q| {
+            let id = q["question_id"].as_u64();
+            // 采纳答案优先，否则取最高票（answers 已按票数排序）
+            let best = answers
+                .iter()
+                .find(|a| a["question_id"].as_u64() == id && a["is_accepted"] == true)
+                .or_else(|| answers.iter().find(|a| a["question_id"].as_u64() == id));
+            let mut excerpts = Vec::new();
+            if let Some(answer) = best {
+                let label = if answer["is_accepted"].as_bool().unwrap_or(false) {
+                    "采纳答案"
+                } else {
+                    "最高票答案"
+                };
+                excerpts.push(format!(
+                    "{}（{} 票）：{}",
+                    label,
+                    answer["score"].as_i64().unwrap_or(0),
+                    excerpt(answer["body"].as_str().unwrap_or(""))
+                ));
+            }
+            json!({
+                "title": format!("{} — Stack Overflow", clean_html(q["title"].as_str().unwrap_or(""))),
+                "url": q["link"],
+                "excerpts": excerpts
+            })
+        })
+        .collect())
+}
+
+async fn search_github_issues(
+    client: &reqwest::Client,
+    settings: &ErrorLookupSettings,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<Value>> {
+    let github = |url: &str| {
+        let mut request = client
+            .get(url)
+            .header("User-Agent", "duckcoding")
+            .header("Accept", "application/vnd.github+json");
+        if let Some(token) =
+            secret_store::reveal_opt(settings.github_token.clone()).filter(|t| !t.is_empty())
+        {
+            request = request.bearer_auth(token);
+        }
+        request
+    };
+    let per_page = limit.to_string();
+    let data = get_json(github("https://api.github.com/search/issues").query(&[
+        ("q", format!("{} is:issue", query).as_str()),
+        ("per_page", per_page.as_str()),
+    ]))
+    .await?;
+    let issues: Vec<&Value> = data["items"]
+        .as_array()
+        .map(|items| items.iter().collect())
+        .unwrap_or_default();
+
+    // 已关闭的 issue 取最后一条评论作为答案摘录
+    let comments = join_all(issues.iter().map(|issue| {
+        let url = issue["comments_url"].as_str().unwrap_or("").to_string();
+        let closed = issue["state"] == "closed" && issue["comments"].as_u64().unwrap_or(0) > 0;
+        let request = github(&url).query(&[("per_page", "100")]);
+        async move {
+            if !closed || url.is_empty() {
+                return None;
+            }
+            let list = get_json(request).await.ok()?;
+            list.as_array()?.last()?["body"]
+                .as_str()
+                .map(str::to_string)
+        }
+    }))
+    .await;
+
+    Ok(issues
+        .iter()
+        .zip(comments)
+        .map(|(issue, last_comment)| {
+            let mut excerpts = vec![format!(
+                "[{}，{} 条评论] {}",
+                issue["state"].as_str().unwrap_or(""),
+                issue["comments"].as_u64().unwrap_or(0),
+                excerpt(issue["body"].as_str().unwrap_or(""))
+            )];
+            if let Some(comment) = last_comment {
+                excerpts.push(format!("最后一条评论：{}", excerpt(&comment)));
+            }
+            json!({
+                "title": format!("{} — GitHub Issue", issue["title"].as_str().unwrap_or("")),
+                "url": issue["html_url"],
+                "excerpts": excerpts
+            })
+        })
+        .collect())
+}
+
+/// 查询 Stack Overflow 与 GitHub Issues，返回 webSearch2 结果格式（两者交替排列）
+pub(crate) async fn search(
+    client: &reqwest::Client,
+    settings: &ErrorLookupSettings,
+    query: &str,
+) -> Vec<Value> {
+    let limit = settings.slots.max(1);
+    let (so, gh) = futures_util::join!(
+        async {
+            if settings.stackoverflow {
+                search_stackoverflow(client, query, limit).await
+            } else {
+                Ok(Vec::new())
+            }
+        },
+        async {
+            if settings.github_issues {
+                search_github_issues(client, settings, query, limit).await
+            } else {
+                Ok(Vec::new())
+            }
+        }
+    );
+    let so = so.unwrap_or_else(|e| {
+        tracing::warn!("Stack Overflow 查询失败: {}", e);
+        Vec::new()
+    });
+    let gh = gh.unwrap_or_else(|e| {
+        tracing::warn!("GitHub Issues 查询失败: {}", e);
+        Vec::new()
+    });
+
+    let mut results = Vec::new();
+    let (mut so, mut gh) = (so.into_iter(), gh.into_iter());
+    while results.len() < settings.slots {
+        match (so.next(), gh.next()) {
+            (None, None) => break,
+            (a, b) => results.extend(a.into_iter().chain(b)),
+        }
+    }
+    results.truncate(settings.slots);
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_error_text() {
+        assert!(looks_like_error("TypeError: x is not a function"));
+        assert!(looks_like_error(
+            "thread 'main' panicked at src/main.rs:3:5"
+        ));
+        assert!(looks_like_error("error[E0382]: borrow of moved value"));
+        assert!(looks_like_error("npm ERR! code ERESOLVE"));
+        assert!(!looks_like_error("how to sort a vec in rust"));
+    }
+
+    #[test]
+    fn python_traceback_uses_last_line() {
+        let text = "Traceback (most recent call last):\n  File \"/home/u/app/main.py\", line 12, in <module>\n    run()\nKeyError: 'user_id'";
+        assert_eq!(normalize(text), "KeyError: 'user_id'");
+    }
+
+    #[test]
+    fn strips_paths_addresses_and_timestamps() {
+        let text = "2024-05-01T10:00:00Z ERROR Segmentation fault at 0x7ffd1234 in /usr/lib/libfoo.so.1:42 request 550e8400-e29b-41d4-a716-446655440000";
+        assert_eq!(normalize(text), "ERROR Segmentation fault at in request");
+    }
+
+    #[test]
+    fn short_key_line_takes_next_line() {
+        let text = "thread 'main' panicked at src/main.rs:10:5:\ncalled `Option::unwrap()` on a `None` value";
+        assert_eq!(
+            normalize(text),
+            "thread 'main' panicked at called `Option::unwrap()` on a `None` value"
+        );
+    }
+
+    #[test]
+    fn query_is_capped() {
+        let text = format!("Error: {}", "word ".repeat(100));
+        assert_eq!(normalize(&text).chars().count(), MAX_QUERY_CHARS);
+    }
+}
@@ -0,0 +1,326 @@
+// 提示词 A/B 实验
+//
+// - 变体：替换注入的身份声明（preamble）和/或追加 system 文本
+// - 分组：按会话标识哈希确定性分配（同一会话始终命中同一变体），按 weight 加权
+// - 标记：命中后写入审计日志，并在用量计数中按 experiment:{id}:{variant} 累计
+// - 报告：按变体汇总请求数（轮次）、会话数、token 与错误率
+
+use super::amp_accounting::{self, UsageCounters};
+use super::audit_log::{self, AuditRecord};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExperimentSettings {
+    pub id: String,
+    pub enabled: bool,
+    pub variants: Vec<VariantSettings>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VariantSettings {
+    pub name: String,
+    /// 分配权重，0 表示不参与分配
+    pub weight: u32,
+    /// 替换默认身份声明（None 保持不变）
+    pub preamble: Option<String>,
+    /// 追加到 system 末尾的文本
+    pub system_append: Option<String>,
+}
+
+/// 会话命中的实验变体
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Assignment {
+    pub experiment_id: String,
+    pub variant: String,
+}
+
+impl Assignment {
+    fn counter_key(&self) -> String {
+        format!("experiment:{}:{}", self.experiment_id, self.variant)
+    }
+
+    fn session_counter_key(&self) -> String {
+        format!("{}:sessions", self.counter_key())
+    }
+}
+
+/// 按会话标识确定性分配变体（取第一个启用且有可用变体的实验）
+pub(crate) fn assign<'a>(
+    experiments: &'a [ExperimentSettings],
+    session_id: &str,
+) -> Option<(Assignment, &'a VariantSettings)> {
+    let exp = experiments
+        .iter()
+        .find(|e| e.enabled && e.variants.iter().any(|v| v.weight > 0))?;
+    let total: u64 = exp.variants.iter().map(|v| v.weight as u64).sum();
+
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}:{}", exp.id, session_id));
+    let digest = hasher.finalize();
+    let mut bucket_bytes = [0u8; 8];
+    bucket_bytes.copy_from_slice(&digest[..8]);
+    let mut bucket = u64::from_be_bytes(bucket_bytes) % total;
+
+    for variant in &exp.variants {
+        let w = variant.weight as u64;
+        if bucket < w {
+            return Some((
+                Assignment {
+                    experiment_id: exp.id.clone(),
+                    variant: variant.name.clone(),
+                },
+                variant,
+            ));
+        }
+        bucket -= w;
+    }
+    None
+}
+
+/// 将变体应用到 Claude 请求体（system 已由身份层规范化为数组或字符串）
+pub(crate) fn apply_variant(
+    body: &[u8],
+    default_preamble: &str,
+    variant: &VariantSettings,
+) -> Option<Vec<u8>> {
+    if variant.preamble.is_none() && variant.system_append.is_none() {
+        return None;
+    }
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+
+    match json.get_mut("system") {
+        Some(Value::Array(items)) => {
+            if let Some(preamble) = &variant.preamble {
+                if let Some(first) = items.first_mut() {
+                    if first.get("text").and_then(|t| t.as_str()) == Some(default_preamble) {
+                        first["text"] = Value::String(preamble.clone());
+                    }
+                }
+            }
+            if let Some(extra) = &variant.system_append {
+                items.push(json!({ "type": "text", "text": extra }));
+            }
+        }
+        Some(Value::String(s)) => {
+            if let Some(preamble) = &variant.preamble {
+                if let Some(rest) = s.strip_prefix(default_preamble) {
+                    *s = format!("{}{}", preamble, rest);
+                }
+            }
+            if let Some(extra) = &variant.system_append {
+                s.push_str("\n\n");
+                s.push_str(extra);
+            }
+        }
+        _ => return None,
+    }
+
+    serde_json::to_vec(&json).ok()
+}
+
+/// 记录一次变体命中；首轮请求（仅 1 条消息）计为新会话
+pub(crate) fn record_hit(assignment: &Assignment, session_id: &str, is_first_turn: bool) {
+    amp_accounting::record_usage(
+        &assignment.counter_key(),
+        UsageCounters {
+            requests: 1,
+            ..Default::default()
+        },
+    );
+    if is_first_turn {
+        amp_accounting::record_usage(
+            &assignment.session_counter_key(),
+            UsageCounters {
+                requests: 1,
+                ..Default::default()
+            },
+        );
+    }
+    audit_log::append(
+        &AuditRecord::new("experiment", "claude", "")
+            .with("experiment", json!(assignment.experiment_id))
+            .with("variant", json!(assignment.variant))
+            .with("session", json!(session_id)),
+    );
+}
+
+/// 响应侧补记 token 用量与错误
+pub(crate) fn record_outcome(
+    assignment: &Assignment,
+    input_tokens: u64,
+    output_tokens: u64,
+    is_error: bool,
+) {
+    amp_accounting::record_usage(
+        &assignment.counter_key(),
+        UsageCounters {
+            input_tokens,
+            output_tokens,
+            errors: is_error as u64,
+            ..Default::default()
+        },
+    );
+}
+
+/// 单个变体的对比指标
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VariantReport {
+    pub variant: String,
+    pub sessions: u64,
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub errors: u64,
+    pub avg_turns_per_session: f64,
+    pub avg_tokens_per_request: f64,
+    pub error_rate: f64,
+}
+
+/// 汇总实验各变体指标
+pub fn report(experiment: &ExperimentSettings) -> Vec<VariantReport> {
+    let counters = amp_accounting::snapshot_counters();
+    experiment
+        .variants
+        .iter()
+        .map(|v| {
+            let assignment = Assignment {
+                experiment_id: experiment.id.clone(),
+                variant: v.name.clone(),
+            };
+            let usage = counters
+                .get(&assignment.counter_key())
+                .cloned()
+                .unwrap_or_default();
+            let sessions = counters
+                .get(&assignment.session_counter_key())
+                .map(|c| c.requests)
+                .unwrap_or(0);
+            let ratio = |num: u64, den: u64| {
+                if den == 0 {
+                    0.0
+                } else {
+                    num as f64 / den as f64
+                }
+            };
+            VariantReport {
+                variant: v.name.clone(),
+                sessions,
+                requests: usage.requests,
+                input_tokens: usage.input_tokens,
+                output_tokens: usage.output_tokens,
+                errors: usage.errors,
+                avg_turns_per_session: ratio(usage.requests, sessions),
+                avg_tokens_per_request: ratio(
+                    usage.input_tokens + usage.output_tokens,
+                    usage.requests,
+                ),
+                error_rate: ratio(usage.errors, usage.requests),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(name: &str, weight: u32) -> VariantSettings {
+        VariantSettings {
+            name: name.into(),
+            weight,
+            ..Default::default()
+        }
+    }
+
+    fn experiment(id: &str, enabled: bool, variants: Vec<VariantSettings>) -> ExperimentSettings {
+        ExperimentSettings {
+            id: id.into(),
+            enabled,
+            variants,
+        }
+    }
+
+    #[test]
+    fn assignment_is_stable_and_skips_disabled() {
+        let experiments = vec![
+            experiment("off", false, vec![variant("a", 1)]),
+            experiment("zero", true, vec![variant("a", 0)]),
+            experiment(
+                "exp",
+                true,
+                vec![variant("control", 1), variant("treatment", 1)],
+            ),
+        ];
+        let (first, _) = assign(&experiments, "T-1").unwrap();
+        assert_eq!(first.experiment_id, "exp");
+        for _ in 0..5 {
+            assert_eq!(assign(&experiments, "T-1").unwrap().0, first);
+        }
+        assert!(assign(&experiments[..2], "T-1").is_none());
+    }
+
+    #[test]
+    fn zero_weight_variant_is_never_assigned() {
+        let experiments = vec![experiment(
+            "exp",
+            true,
+            vec![variant("never", 0), variant("always", 3)],
+        )];
+        for i in 0..50 {
+            let (a, _) = assign(&experiments, &format!("T-{}", i)).unwrap();
+            assert_eq!(a.variant, "always");
+        }
+    }
+
+    #[test]
+    fn weights_split_sessions() {
+        let experiments = vec![experiment(
+            "exp",
+            true,
+            vec![variant("a", 1), variant("b", 1)],
+        )];
+        let a = (0..400)
+            .filter(|i| assign(&experiments, &format!("T-{}", i)).unwrap().0.variant == "a")
+            .count();
+        assert!((120..=280).contains(&a), "a = {}", a);
+    }
+
+    #[test]
+    fn applies_preamble_and_append_to_array_system() {
+        let body = json!({
+            "system": [{ "type": "text", "text": "You are Amp." }, { "type": "text", "text": "rules" }]
+        });
+        let v = VariantSettings {
+            preamble: Some("You are Amp (B).".into()),
+            system_append: Some("be brief".into()),
+            ..variant("b", 1)
+        };
+        let out: Value = serde_json::from_slice(
+            &apply_variant(body.to_string().as_bytes(), "You are Amp.", &v).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(out["system"][0]["text"], "You are Amp (B).");
+        assert_eq!(out["system"][1]["text"], "rules");
+        assert_eq!(out["system"][2]["text"], "be brief");
+    }
+
+    #[test]
+    fn applies_to_string_system_and_ignores_noop() {
+        let body = json!({ "system": "You are Amp. rules" }).to_string();
+        let v = VariantSettings {
+            preamble: Some("Hi.".into()),
+            system_append: Some("extra".into()),
+            ..variant("b", 1)
+        };
+        let out: Value =
+            serde_json::from_slice(&apply_variant(body.as_bytes(), "You are Amp.", &v).unwrap())
+                .unwrap();
+        assert_eq!(out["system"], "Hi. rules\n\nextra");
+        assert!(apply_variant(body.as_bytes(), "You are Amp.", &variant("a", 1)).is_none());
+        assert!(apply_variant(b"{}", "You are Amp.", &v).is_none());
+    }
+}
@@ -0,0 +1,301 @@
+// 外部网页提取服务（extractWebPageContent）
+//
+// 部分用户已购买提取服务，希望获得一致的提取质量。reader.provider 选择服务，
+// 每个服务实现 ExtractionService；请求失败、超时或返回空内容时由调用方回退本地抓取流程。
+// - jina：GET `{endpoint}{url}`（默认 https://r.jina.ai/），返回 Markdown
+// - firecrawl：POST `{endpoint}/v1/scrape`（默认 https://api.firecrawl.dev），取 data.markdown
+// - custom：自建 readability 等端点，POST {"url": ...}；JSON 响应按 content_pointer 取正文，
+//   否则整个响应体即正文。headers 可附加鉴权等请求头
+// api_key 以 Bearer 发送。响应大小受 max_bytes 限制。
+
+use super::bandwidth::{self, Subject};
+use super::secret_store;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReaderSettings {
+    pub enabled: bool,
+    /// 服务：jina / firecrawl / custom
+    pub provider: String,
+    /// 服务地址（None 使用该服务的默认地址；custom 必填）
+    pub endpoint: Option<String>,
+    /// 可选 API Key（Bearer）
+    pub api_key: Option<String>,
+    /// custom 服务 JSON 响应中正文的 JSON Pointer
+    pub content_pointer: String,
+    /// 附加请求头
+    pub headers: HashMap<String, String>,
+    /// 远程超时（秒），超时即回退本地
+    pub timeout_secs: u64,
+    /// 返回内容大小上限
+    pub max_bytes: usize,
+}
+
+impl Default for ReaderSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            provider: "jina".to_string(),
+            endpoint: None,
+            api_key: None,
+            content_pointer: "/content".to_string(),
+            headers: HashMap::new(),
+            timeout_secs: 30,
+            max_bytes: 2 * 1024 * 1024,
+        }
+    }
+}
+
+#[async_trait]
+pub(crate) trait ExtractionService: Send + Sync {
+    /// 响应中的 provider 标识
+    fn label(&self) -> &'static str;
+
+    /// 获取页面正文
+    async fn extract(&self, page_url: &str) -> Result<String>;
+}
+
+/// 按配置构造服务（经 client 发送请求）；未启用或配置不完整时返回 None
+pub(crate) fn build(
+    settings: &ReaderSettings,
+    client: &reqwest::Client,
+) -> Option<Box<dyn ExtractionService>> {
+    if !settings.enabled {
+        return None;
+    }
+    let endpoint = settings.endpoint.clone().filter(|e| !e.is_empty());
+    match settings.provider.as_str() {
+        "jina" => Some(Box::new(Jina {
+            endpoint: endpoint.unwrap_or_else(|| "https://r.jina.ai/".to_string()),
+            settings: settings.clone(),
+            client: client.clone(),
+        })),
+        "firecrawl" => Some(Box::new(Firecrawl {
+            endpoint: endpoint.unwrap_or_else(|| "https://api.firecrawl.dev".to_string()),
+            settings: settings.clone(),
+            client: client.clone(),
+        })),
+        "custom" => match endpoint {
+            Some(endpoint) => Some(Box::new(Custom {
+                endpoint,
+                settings: settings.clone(),
+                client: client.clone(),
+            })),
+            None => {
+                tracing::warn!("reader.provider 为 custom 但未配置 endpoint，跳过外部提取");
+                None
+            }
+        },
+        other => {
+            tracing::warn!("未知的外部提取服务: {}", other);
+            None
+        }
+    }
+}
+
+/// 附加通用参数：超时、Bearer、附加请求头
+fn prepare(
+    settings: &ReaderSettings,
+    mut request: reqwest::RequestBuilder,
+) -> reqwest::RequestBuilder {
+    request = request.timeout(Duration::from_secs(settings.timeout_secs));
+    if let Some(key) = secret_store::reveal_opt(settings.api_key.clone()).filter(|k| !k.is_empty())
+    {
+        request = request.bearer_auth(key);
+    }
+    for (name, value) in &settings.headers {
+        request = request.header(name, value);
+    }
+    request
+}
+
+/// 发送并读取响应体（限制大小）
+async fn send(
+    settings: &ReaderSettings,
+    label: &str,
+    request: reqwest::RequestBuilder,
+) -> Result<(Option<String>, Vec<u8>)> {
+    let resp = prepare(settings, request).send().await?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("{} 返回 HTTP {}", label, resp.status()));
+    }
+    if resp
+        .content_length()
+        .is_some_and(|len| len as usize > settings.max_bytes)
+    {
+        return Err(anyhow!(
+            "{} 响应超过 {} bytes 限制",
+            label,
+            settings.max_bytes
+        ));
+    }
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let raw = resp.bytes().await?;
+    bandwidth::record(Subject::Tool("extractWebPageContent"), 0, raw.len() as u64);
+    if raw.len() > settings.max_bytes {
+        return Err(anyhow!(
+            "{} 响应超过 {} bytes 限制",
+            label,
+            settings.max_bytes
+        ));
+    }
+    Ok((content_type, raw.to_vec()))
+}
+
+fn non_empty(label: &str, text: &str) -> Result<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(anyhow!("{} 返回空内容", label));
+    }
+    Ok(text.to_string())
+}
+
+/// Jina Reader（或兼容的前缀式阅读器）
+struct Jina {
+    endpoint: String,
+    settings: ReaderSettings,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl ExtractionService for Jina {
+    fn label(&self) -> &'static str {
+        "jina"
+    }
+
+    async fn extract(&self, page_url: &str) -> Result<String> {
+        let request = self
+            .client
+            .get(format!("{}{}", self.endpoint, page_url))
+            .header("Accept", "text/plain")
+            .header("X-Return-Format", "markdown");
+        let (_, raw) = send(&self.settings, "Jina Reader", request).await?;
+        non_empty("Jina Reader", &String::from_utf8_lossy(&raw))
+    }
+}
+
+/// Firecrawl scrape API
+struct Firecrawl {
+    endpoint: String,
+    settings: ReaderSettings,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl ExtractionService for Firecrawl {
+    fn label(&self) -> &'static str {
+        "firecrawl"
+    }
+
+    async fn extract(&self, page_url: &str) -> Result<String> {
+        let request = self
+            .client
+            .post(format!("{}/v1/scrape", self.endpoint.trim_end_matches('/')))
+            .json(&json!({
+                "url": page_url,
+                "formats": ["markdown"],
+                "onlyMainContent": true
+            }));
+        let (_, raw) = send(&self.settings, "Firecrawl", request).await?;
+        let data: Value = serde_json::from_slice(&raw)?;
+        if data["success"] == false {
+            return Err(anyhow!(
+                "Firecrawl 提取失败: {}",
+                data["error"].as_str().unwrap_or("未知错误")
+            ));
+        }
+        non_empty("Firecrawl", data["data"]["markdown"].as_str().unwrap_or(""))
+    }
+}
+
+/// 自建提取端点
+struct Custom {
+    endpoint: String,
+    settings: ReaderSettings,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl ExtractionService for Custom {
+    fn label(&self) -> &'static str {
+        "custom"
+    }
+
+    async fn extract(&self, page_url: &str) -> Result<String> {
+        let request = self
+            .client
+            .post(&self.endpoint)
+            .json(&json!({ "url": page_url }));
+        let (content_type, raw) = send(&self.settings, "自定义提取服务", request).await?;
+        if content_type.is_some_and(|ct| ct.contains("json")) {
+            let data: Value = serde_json::from_slice(&raw)?;
+            let content = data
+                .pointer(&self.settings.content_pointer)
+                .and_then(Value::as_str)
+                .ok_or_else(|| {
+                    anyhow!("自定义提取服务响应中没有 {}", self.settings.content_pointer)
+                })?;
+            return non_empty("自定义提取服务", content);
+        }
+        non_empty("自定义提取服务", &String::from_utf8_lossy(&raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(provider: &str, endpoint: Option<&str>) -> ReaderSettings {
+        ReaderSettings {
+            enabled: true,
+            provider: provider.into(),
+            endpoint: endpoint.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn build_with(settings: &ReaderSettings) -> Option<Box<dyn ExtractionService>> {
+        build(settings, &reqwest::Client::new())
+    }
+
+    #[test]
+    fn builds_configured_provider() {
+        assert_eq!(build_with(&settings("jina", None)).unwrap().label(), "jina");
+        assert_eq!(
+            build_with(&settings("firecrawl", Some("")))
+                .unwrap()
+                .label(),
+            "firecrawl"
+        );
+        assert_eq!(
+            build_with(&settings("custom", Some("http://127.0.0.1:3000/extract")))
+                .unwrap()
+                .label(),
+            "custom"
+        );
+    }
+
+    #[test]
+    fn skips_disabled_incomplete_or_unknown() {
+        assert!(build_with(&ReaderSettings::default()).is_none());
+        assert!(build_with(&settings("custom", None)).is_none());
+        assert!(build_with(&settings("custom", Some(""))).is_none());
+        assert!(build_with(&settings("diffbot", None)).is_none());
+    }
+
+    #[test]
+    fn empty_content_is_an_error() {
+        assert_eq!(non_empty("jina", "  # Title\n").unwrap(), "# Title");
+        assert!(non_empty("jina", " \n\t").is_err());
+    }
+}
@@ -0,0 +1,285 @@
+// Gemini 流式响应格式转换
+//
+// Gemini 兼容后端的流式格式不统一：
+// - alt=sse：`data: {...}\n\n` 事件流
+// - 默认：分块输出的 JSON 数组 `[{...}\n,\r\n{...}]`（部分后端为 NDJSON）
+// 客户端期望的格式由请求 query 是否带 alt=sse 决定，此处检测后端实际格式并按需转换。
+
+use super::sse::{SseEvent, SseParser};
+use bytes::Bytes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GeminiStreamFormat {
+    Sse,
+    /// JSON 数组或 NDJSON（按对象边界切分，两者可统一处理）
+    Json,
+}
+
+/// 客户端期望的格式：query 含 alt=sse 时为 SSE
+pub(crate) fn expected_format(query: Option<&str>) -> GeminiStreamFormat {
+    let is_sse = query
+        .map(|q| {
+            q.split('&')
+                .any(|part| part.eq_ignore_ascii_case("alt=sse"))
+        })
+        .unwrap_or(false);
+    if is_sse {
+        GeminiStreamFormat::Sse
+    } else {
+        GeminiStreamFormat::Json
+    }
+}
+
+/// 根据 content-type 与首个非空白字节判断后端格式
+pub(crate) fn detect_format(content_type: Option<&str>, first_chunk: &[u8]) -> GeminiStreamFormat {
+    if let Some(ct) = content_type {
+        if ct.to_ascii_lowercase().contains("text/event-stream") {
+            return GeminiStreamFormat::Sse;
+        }
+    }
+    match first_chunk.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'[') | Some(b'{') => GeminiStreamFormat::Json,
+        _ => GeminiStreamFormat::Sse,
+    }
+}
+
+/// 增量转换器：按块输入，按块输出（跨块边界的对象/事件会缓存到下一块）
+pub(crate) struct GeminiStreamConverter {
+    from: GeminiStreamFormat,
+    to: GeminiStreamFormat,
+    buffer: Vec<u8>,
+    sse: SseParser,
+    /// 输出 JSON 数组时是否已写出首个元素
+    emitted_any: bool,
+}
+
+impl GeminiStreamConverter {
+    pub(crate) fn new(from: GeminiStreamFormat, to: GeminiStreamFormat) -> Self {
+        Self {
+            from,
+            to,
+            buffer: Vec::new(),
+            sse: SseParser::new(),
+            emitted_any: false,
+        }
+    }
+
+    pub(crate) fn is_passthrough(&self) -> bool {
+        self.from == self.to
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        if self.is_passthrough() {
+            return Bytes::copy_from_slice(chunk);
+        }
+        let objects = match self.from {
+            GeminiStreamFormat::Json => {
+                self.buffer.extend_from_slice(chunk);
+                self.drain_json_objects()
+            }
+            GeminiStreamFormat::Sse => sse_objects(self.sse.feed(chunk)),
+        };
+        self.emit(objects)
+    }
+
+    /// 流结束：输出残余数据与收尾符
+    pub(crate) fn finish(&mut self) -> Bytes {
+        if self.is_passthrough() {
+            return Bytes::new();
+        }
+        let objects = match self.from {
+            GeminiStreamFormat::Json => self.drain_json_objects(),
+            // 末尾事件可能缺少空行结束符
+            GeminiStreamFormat::Sse => sse_objects(self.sse.finish()),
+        };
+        if !self
+            .buffer
+            .iter()
+            .all(|b| b.is_ascii_whitespace() || *b == b']')
+        {
+            tracing::warn!(
+                "Gemini 流结束时存在无法解析的残余数据: {} bytes",
+                self.buffer.len()
+            );
+        }
+        self.buffer.clear();
+
+        let mut out = self.emit(objects).to_vec();
+        if self.to == GeminiStreamFormat::Json {
+            if self.emitted_any {
+                out.extend_from_slice(b"]");
+            } else {
+                out.extend_from_slice(b"[]");
+            }
+        }
+        Bytes::from(out)
+    }
+
+    fn emit(&mut self, objects: Vec<Vec<u8>>) -> Bytes {
+        let mut out = Vec::new();
+        for obj in objects {
+            match self.to {
+                GeminiStreamFormat::Sse => {
+                    out.extend_from_slice(b"data: ");
+                    out.extend_from_slice(&obj);
+                    out.extend_from_slice(b"\r\n\r\n");
+                }
+                GeminiStreamFormat::Json => {
+                    out.extend_from_slice(if self.emitted_any { b",\r\n" } else { b"[" });
+                    out.extend_from_slice(&obj);
+                    self.emitted_any = true;
+                }
+            }
+        }
+        Bytes::from(out)
+    }
+
+    /// 从缓冲区切出完整的顶层 JSON 对象（跳过数组括号、逗号与空白）
+    fn drain_json_objects(&mut self) -> Vec<Vec<u8>> {
+        let mut objects = Vec::new();
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut start = None;
+        let mut consumed = 0;
+
+        for (i, &b) in self.buffer.iter().enumerate() {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if b == b'\\' {
+                    escaped = true;
+                } else if b == b'"' {
+                    in_string = false;
+                }
+                continue;
+            }
+            match b {
+                b'"' => in_string = true,
+                b'{' => {
+                    if depth == 0 {
+                        start = Some(i);
+                    }
+                    depth += 1;
+                }
+                b'}' => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        if let Some(s) = start.take() {
+                            objects.push(self.buffer[s..=i].to_vec());
+                            consumed = i + 1;
+                        }
+                    }
+                }
+                _ => {
+                    if depth == 0 {
+                        consumed = i + 1;
+                    }
+                }
+            }
+        }
+
+        self.buffer.drain(..consumed);
+        objects
+    }
+}
+
+/// 取每个 SSE 事件的 data 内容（跳过空 data 与 [DONE]）
+fn sse_objects(events: impl IntoIterator<Item = SseEvent>) -> Vec<Vec<u8>> {
+    events
+        .into_iter()
+        .filter_map(|event| {
+            let data = event.data()?;
+            let data = data.trim();
+            (!data.is_empty() && !event.is_done()).then(|| data.as_bytes().to_vec())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(from: GeminiStreamFormat, to: GeminiStreamFormat, chunks: &[&[u8]]) -> String {
+        let mut converter = GeminiStreamConverter::new(from, to);
+        let mut out = Vec::new();
+        for chunk in chunks {
+            out.extend_from_slice(&converter.feed(chunk));
+        }
+        out.extend_from_slice(&converter.finish());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn detects_expected_and_backend_format() {
+        assert_eq!(
+            expected_format(Some("key=x&alt=sse")),
+            GeminiStreamFormat::Sse
+        );
+        assert_eq!(expected_format(Some("alt=json")), GeminiStreamFormat::Json);
+        assert_eq!(expected_format(None), GeminiStreamFormat::Json);
+
+        assert_eq!(
+            detect_format(Some("text/event-stream; charset=utf-8"), b"["),
+            GeminiStreamFormat::Sse
+        );
+        assert_eq!(detect_format(None, b"\r\n [{"), GeminiStreamFormat::Json);
+        assert_eq!(detect_format(None, b"data: {}"), GeminiStreamFormat::Sse);
+    }
+
+    #[test]
+    fn json_array_split_across_chunks_becomes_sse() {
+        let out = convert(
+            GeminiStreamFormat::Json,
+            GeminiStreamFormat::Sse,
+            &[
+                b"[{\"text\":\"a}\\\"{\"",
+                b"}\n,\r\n{\"n\":",
+                b"{\"x\":1}}]",
+            ],
+        );
+        assert_eq!(
+            out,
+            "data: {\"text\":\"a}\\\"{\"}\r\n\r\ndata: {\"n\":{\"x\":1}}\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn ndjson_becomes_sse() {
+        let out = convert(
+            GeminiStreamFormat::Json,
+            GeminiStreamFormat::Sse,
+            &[b"{\"a\":1}\n{\"b\":2}\n"],
+        );
+        assert_eq!(out, "data: {\"a\":1}\r\n\r\ndata: {\"b\":2}\r\n\r\n");
+    }
+
+    #[test]
+    fn sse_becomes_json_array() {
+        let out = convert(
+            GeminiStreamFormat::Sse,
+            GeminiStreamFormat::Json,
+            &[
+                b"data: {\"a\":1}\n\nda",
+                b"ta: {\"b\":2}\n\ndata: [DONE]\n\ndata: {\"c\":3}",
+            ],
+        );
+        assert_eq!(out, "[{\"a\":1},\r\n{\"b\":2},\r\n{\"c\":3}]");
+    }
+
+    #[test]
+    fn empty_stream_and_passthrough() {
+        assert_eq!(
+            convert(GeminiStreamFormat::Sse, GeminiStreamFormat::Json, &[]),
+            "[]"
+        );
+        assert_eq!(
+            convert(
+                GeminiStreamFormat::Sse,
+                GeminiStreamFormat::Sse,
+                &[b"data: x\n\n"]
+            ),
+            "data: x\n\n"
+        );
+    }
+}
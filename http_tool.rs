@@ -0,0 +1,246 @@
+// 通用 HTTP 请求本地工具（httpRequest）
+//
+// webSearch2 / extractWebPageContent 只能 GET 网页；httpRequest 让 Agent 直接调用 JSON API
+// （如包管理仓库），返回结构化结果。AMP 以本地工具方式调用（?httpRequest）：
+// params.url、params.method（默认 GET）、params.headers（对象）、params.body（字符串或 JSON）。
+// - 只允许 allowed_domains 中的域名（精确匹配，或 *.example.com 匹配其子域名），列表为空时工具不可用
+// - 只允许 allowed_methods 中的方法；请求体与响应体均有大小上限
+// - URL 另经处理器的 SSRF 校验（协议 / userinfo / 内网地址）
+// - 重定向由处理器手动跟随（最多 5 跳），每一跳重新做 SSRF 校验与域名允许列表检查
+// - 响应为 JSON 时返回解析后的 json 字段，否则返回 text
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use url::Url;
+
+/// 不允许客户端设置的请求头
+const FORBIDDEN_HEADERS: [&str; 7] = [
+    "host",
+    "content-length",
+    "transfer-encoding",
+    "connection",
+    "upgrade",
+    "proxy-authorization",
+    "te",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpRequestSettings {
+    /// 允许访问的域名（为空时工具不可用）
+    pub allowed_domains: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub max_request_bytes: usize,
+    pub max_response_bytes: usize,
+    pub timeout_secs: u64,
+}
+
+impl Default for HttpRequestSettings {
+    fn default() -> Self {
+        Self {
+            allowed_domains: Vec::new(),
+            allowed_methods: vec!["GET".to_string(), "HEAD".to_string()],
+            max_request_bytes: 64 * 1024,
+            max_response_bytes: 2 * 1024 * 1024,
+            timeout_secs: 30,
+        }
+    }
+}
+
+impl HttpRequestSettings {
+    pub(crate) fn domain_allowed(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.allowed_domains.iter().any(|pattern| {
+            let pattern = pattern.to_ascii_lowercase();
+            match pattern.strip_prefix("*.") {
+                Some(suffix) => host.ends_with(&format!(".{}", suffix)),
+                None => host == pattern,
+            }
+        })
+    }
+}
+
+/// 校验后的请求
+pub(crate) struct HttpCall {
+    pub method: reqwest::Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+/// 解析并校验工具参数（SSRF 校验由调用方完成）
+pub(crate) fn parse(settings: &HttpRequestSettings, body: &[u8]) -> Result<HttpCall> {
+    let req_json: Value =
+        serde_json::from_slice(body).map_err(|e| anyhow!("请求 JSON 解析失败: {}", e))?;
+    let params = &req_json["params"];
+    let url_str = params["url"]
+        .as_str()
+        .ok_or_else(|| anyhow!("缺少 URL 参数"))?;
+    let url = Url::parse(url_str).map_err(|e| anyhow!("URL 解析失败: {}", e))?;
+    let host = url.host_str().ok_or_else(|| anyhow!("URL 缺少主机名"))?;
+    if !settings.domain_allowed(host) {
+        bail!("域名 {} 不在 httpRequest 允许列表中", host);
+    }
+
+    let method = params["method"]
+        .as_str()
+        .unwrap_or("GET")
+        .to_ascii_uppercase();
+    if !settings
+        .allowed_methods
+        .iter()
+        .any(|m| m.eq_ignore_ascii_case(&method))
+    {
+        bail!("不允许的请求方法: {}", method);
+    }
+    let method =
+        reqwest::Method::from_bytes(method.as_bytes()).map_err(|_| anyhow!("无效的请求方法"))?;
+
+    let mut headers = Vec::new();
+    if let Some(map) = params["headers"].as_object() {
+        for (name, value) in map {
+            if FORBIDDEN_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                bail!("不允许设置请求头: {}", name);
+            }
+            let value = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            headers.push((name.clone(), value));
+        }
+    }
+
+    let body = match &params["body"] {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone().into_bytes()),
+        other => {
+            if !headers
+                .iter()
+                .any(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+            {
+                headers.push(("content-type".to_string(), "application/json".to_string()));
+            }
+            Some(serde_json::to_vec(other)?)
+        }
+    };
+    if body
+        .as_ref()
+        .is_some_and(|b| b.len() > settings.max_request_bytes)
+    {
+        bail!("请求体过大，超过 {} bytes 限制", settings.max_request_bytes);
+    }
+
+    Ok(HttpCall {
+        method,
+        url: url.to_string(),
+        headers,
+        body,
+    })
+}
+
+/// 构造工具应答：JSON 响应解析为 json 字段，否则返回 text
+pub(crate) fn respond(status: u16, content_type: &str, headers: Value, text: String) -> Value {
+    let parsed = content_type
+        .contains("json")
+        .then(|| serde_json::from_str::<Value>(&text).ok())
+        .flatten();
+    let mut result = json!({
+        "status": status,
+        "ok": (200..300).contains(&status),
+        "headers": headers,
+    });
+    match parsed {
+        Some(json) => result["json"] = json,
+        None => result["text"] = json!(text),
+    }
+    json!({ "ok": true, "result": result })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> HttpRequestSettings {
+        HttpRequestSettings {
+            allowed_domains: vec!["registry.npmjs.org".into(), "*.example.com".into()],
+            allowed_methods: vec!["GET".into(), "POST".into()],
+            max_request_bytes: 32,
+            ..Default::default()
+        }
+    }
+
+    fn call(params: Value) -> Result<HttpCall> {
+        parse(
+            &settings(),
+            json!({ "params": params }).to_string().as_bytes(),
+        )
+    }
+
+    #[test]
+    fn domain_allow_list() {
+        let s = settings();
+        assert!(s.domain_allowed("Registry.NPMjs.org"));
+        assert!(s.domain_allowed("api.example.com"));
+        assert!(!s.domain_allowed("example.com"));
+        assert!(!s.domain_allowed("evilexample.com"));
+        assert!(!s.domain_allowed("npmjs.org"));
+        assert!(!HttpRequestSettings::default().domain_allowed("registry.npmjs.org"));
+    }
+
+    #[test]
+    fn parses_get_with_headers() {
+        let c = call(json!({
+            "url": "https://registry.npmjs.org/react",
+            "headers": { "Accept": "application/json", "X-Retry": 2 }
+        }))
+        .unwrap();
+        assert_eq!(c.method, reqwest::Method::GET);
+        assert_eq!(c.url, "https://registry.npmjs.org/react");
+        assert!(c
+            .headers
+            .contains(&("X-Retry".to_string(), "2".to_string())));
+        assert!(c.body.is_none());
+    }
+
+    #[test]
+    fn json_body_gets_content_type() {
+        let c = call(json!({
+            "url": "https://api.example.com/q",
+            "method": "post",
+            "body": { "q": 1 }
+        }))
+        .unwrap();
+        assert_eq!(c.method, reqwest::Method::POST);
+        assert_eq!(c.body.as_deref(), Some(&b"{\"q\":1}"[..]));
+        assert!(c
+            .headers
+            .contains(&("content-type".to_string(), "application/json".to_string())));
+    }
+
+    #[test]
+    fn rejects_disallowed_requests() {
+        assert!(call(json!({ "url": "https://other.org/" })).is_err());
+        assert!(call(json!({ "url": "https://api.example.com", "method": "DELETE" })).is_err());
+        assert!(
+            call(json!({ "url": "https://api.example.com", "headers": { "Host": "x" } })).is_err()
+        );
+        assert!(call(
+            json!({ "url": "https://api.example.com", "method": "POST", "body": "x".repeat(33) })
+        )
+        .is_err());
+        assert!(call(json!({})).is_err());
+    }
+
+    #[test]
+    fn respond_parses_json_only_for_json_content() {
+        let r = respond(200, "application/json", json!({}), "{\"a\":1}".into());
+        assert_eq!(r["result"]["json"]["a"], 1);
+        assert_eq!(r["result"]["ok"], true);
+        let r = respond(404, "text/html", json!({}), "<p>no</p>".into());
+        assert_eq!(r["result"]["text"], "<p>no</p>");
+        assert_eq!(r["result"]["ok"], false);
+        let r = respond(200, "application/json", json!({}), "not json".into());
+        assert_eq!(r["result"]["text"], "not json");
+    }
+}
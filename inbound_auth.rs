@@ -0,0 +1,283 @@
+// 代理入站鉴权（虚拟 Key）
+//
+// 开启后，所有经过处理器的请求须携带本地签发的虚拟 Key，校验通过后才会替换为真实的
+// Profile Key 转发，避免本机（或局域网）上的任意程序直接使用上游 Key。
+// Key 可放在 Authorization: Bearer、x-api-key、x-goog-api-key 请求头或 key 查询参数中，
+// 与各家客户端的习惯一致。配置中只保存 Key 的 sha256，明文仅在签发时返回一次。
+// 校验通过后 strip_credentials() 移除这些请求头与 key 查询参数，虚拟 Key 不会随请求发往上游。
+
+use super::app_state::AppState;
+use super::audit_log::{self, AuditRecord};
+use super::rate_limit::{self, RateLimit};
+use anyhow::{anyhow, bail, Result};
+use hyper::HeaderMap as HyperHeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// 签发的 Key 前缀
+const KEY_PREFIX: &str = "dc-vk-";
+/// 可携带入站 Key 的请求头
+const CREDENTIAL_HEADERS: [&str; 3] = ["authorization", "x-api-key", "x-goog-api-key"];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InboundAuthSettings {
+    pub enabled: bool,
+    pub keys: Vec<VirtualKey>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VirtualKey {
+    /// 名称（唯一）
+    pub name: String,
+    /// Key 的 sha256（hex）
+    pub key_hash: String,
+    /// Key 开头若干字符，便于辨认
+    pub hint: String,
+    pub enabled: bool,
+    /// 路由名（"*" 为默认）→ 限流额度（见 rate_limit.rs）
+    pub rate_limits: HashMap<String, RateLimit>,
+}
+
+impl Default for VirtualKey {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            key_hash: String::new(),
+            hint: String::new(),
+            enabled: true,
+            rate_limits: HashMap::new(),
+        }
+    }
+}
+
+pub(crate) fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// 定长比较，避免按前缀逐字节猜测
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// 从请求中取出客户端提供的 Key
+fn presented_key(headers: &HyperHeaderMap, query: Option<&str>) -> Option<String> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+    };
+    if let Some(auth) = header("authorization") {
+        let token = auth
+            .strip_prefix("Bearer ")
+            .or_else(|| auth.strip_prefix("bearer "))
+            .unwrap_or(auth);
+        return Some(token.trim().to_string());
+    }
+    if let Some(key) = header("x-api-key").or_else(|| header("x-goog-api-key")) {
+        return Some(key.to_string());
+    }
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == "key")
+        .and_then(|(_, v)| urlencoding::decode(v).ok())
+        .map(|v| v.into_owned())
+}
+
+/// 校验入站请求，返回命中的虚拟 Key 名；未开启时返回 None
+pub(crate) fn check(
+    settings: &InboundAuthSettings,
+    path: &str,
+    headers: &HyperHeaderMap,
+    query: Option<&str>,
+) -> Result<Option<String>> {
+    if !settings.enabled {
+        return Ok(None);
+    }
+    let reject = |reason: &str| {
+        tracing::warn!("入站鉴权失败: path={}, {}", path, reason);
+        audit_log::append(
+            &AuditRecord::new("inbound_rejected", "", "")
+                .with("path", json!(path))
+                .with("reason", json!(reason)),
+        );
+        anyhow!("入站鉴权失败：{}（请使用本地签发的虚拟 Key）", reason)
+    };
+    let key = presented_key(headers, query).ok_or_else(|| reject("未提供 Key"))?;
+    let hash = hash_key(&key);
+    let matched = settings
+        .keys
+        .iter()
+        .find(|k| constant_time_eq(&k.key_hash, &hash))
+        .ok_or_else(|| reject("Key 无效"))?;
+    if !matched.enabled {
+        return Err(reject(&format!("Key {} 已停用", matched.name)));
+    }
+    Ok(Some(matched.name.clone()))
+}
+
+/// 移除入站凭证：删除可携带 Key 的请求头，返回去掉 key 参数后的查询串（为空时 None）
+pub(crate) fn strip_credentials(
+    headers: &mut HyperHeaderMap,
+    query: Option<&str>,
+) -> Option<String> {
+    for name in CREDENTIAL_HEADERS {
+        headers.remove(name);
+    }
+    let rest: Vec<&str> = query?
+        .split('&')
+        .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some("key"))
+        .collect();
+    (!rest.is_empty()).then(|| rest.join("&"))
+}
+
+/// 签发新的虚拟 Key 并保存配置（state 缓存的配置随之失效），返回明文（仅此一次）
+pub fn issue(state: &AppState, name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("虚拟 Key 名称不能为空");
+    }
+    let mut settings = state.load_settings()?;
+    if settings.inbound_auth.keys.iter().any(|k| k.name == name) {
+        bail!("虚拟 Key 名称已存在: {}", name);
+    }
+    let key = format!("{}{}", KEY_PREFIX, uuid::Uuid::new_v4().simple());
+    settings.inbound_auth.keys.push(VirtualKey {
+        name: name.to_string(),
+        key_hash: hash_key(&key),
+        hint: key.chars().take(KEY_PREFIX.len() + 4).collect(),
+        enabled: true,
+        rate_limits: HashMap::new(),
+    });
+    state.save_settings(&settings)?;
+    tracing::info!("已签发虚拟 Key: {}", name);
+    Ok(key)
+}
+
+/// 启用 / 停用虚拟 Key
+pub fn set_enabled(state: &AppState, name: &str, enabled: bool) -> Result<()> {
+    let mut settings = state.load_settings()?;
+    let key = settings
+        .inbound_auth
+        .keys
+        .iter_mut()
+        .find(|k| k.name == name)
+        .ok_or_else(|| anyhow!("虚拟 Key 不存在: {}", name))?;
+    key.enabled = enabled;
+    state.save_settings(&settings)?;
+    Ok(())
+}
+
+/// 删除虚拟 Key
+pub fn revoke(state: &AppState, name: &str) -> Result<()> {
+    let mut settings = state.load_settings()?;
+    let before = settings.inbound_auth.keys.len();
+    settings.inbound_auth.keys.retain(|k| k.name != name);
+    if settings.inbound_auth.keys.len() == before {
+        bail!("虚拟 Key 不存在: {}", name);
+    }
+    state.save_settings(&settings)?;
+    rate_limit::forget_key(name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HyperHeaderMap {
+        let mut map = HyperHeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    fn settings() -> InboundAuthSettings {
+        InboundAuthSettings {
+            enabled: true,
+            keys: vec![VirtualKey {
+                name: "laptop".into(),
+                key_hash: hash_key("dc-vk-secret"),
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn key_is_read_from_headers_then_query() {
+        let bearer = headers(&[
+            ("authorization", "Bearer dc-vk-a"),
+            ("x-api-key", "dc-vk-b"),
+        ]);
+        assert_eq!(presented_key(&bearer, None).as_deref(), Some("dc-vk-a"));
+        let goog = headers(&[("x-goog-api-key", "dc-vk-c")]);
+        assert_eq!(presented_key(&goog, None).as_deref(), Some("dc-vk-c"));
+        assert_eq!(
+            presented_key(&headers(&[]), Some("alt=sse&key=dc-vk%2Dd")).as_deref(),
+            Some("dc-vk-d")
+        );
+        assert!(presented_key(&headers(&[("x-api-key", " ")]), Some("alt=sse")).is_none());
+    }
+
+    #[test]
+    fn accepts_matching_key() {
+        let name = check(
+            &settings(),
+            "/v1/messages",
+            &headers(&[("x-api-key", "dc-vk-secret")]),
+            None,
+        )
+        .unwrap();
+        assert_eq!(name.as_deref(), Some("laptop"));
+    }
+
+    #[test]
+    fn disabled_auth_is_passthrough() {
+        let settings = InboundAuthSettings {
+            enabled: false,
+            ..settings()
+        };
+        assert!(check(&settings, "/", &headers(&[]), None)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn credentials_stripped() {
+        let mut map = headers(&[
+            ("authorization", "Bearer dc-vk-a"),
+            ("x-goog-api-key", "dc-vk-b"),
+            ("anthropic-version", "2023-06-01"),
+        ]);
+        let query = strip_credentials(&mut map, Some("alt=sse&key=dc-vk-c"));
+        assert_eq!(query.as_deref(), Some("alt=sse"));
+        assert_eq!(map.len(), 1);
+        assert!(strip_credentials(&mut map, Some("key=dc-vk-c")).is_none());
+        assert!(strip_credentials(&mut map, None).is_none());
+    }
+
+    #[test]
+    fn hash_and_compare() {
+        assert_eq!(
+            hash_key("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "ab"));
+    }
+}
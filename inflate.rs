@@ -0,0 +1,146 @@
+// DEFLATE 解压（RFC 1950 / 1951 / 1952）
+//
+// 供 PDF FlateDecode 流与强制 gzip 的接口（如 Stack Exchange API）使用。
+// 解码交给 miniz_oxide（宿主 Cargo.toml 中声明），本模块只处理 zlib / gzip 外层格式与输出上限：
+// - 输出上限防止压缩炸弹，超出时整体放弃
+// - 数据截断（PDF 中常见的损坏流）时返回已解出的部分
+
+use miniz_oxide::inflate::stream::{self, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+
+/// 解压后的大小上限
+const MAX_INFLATED_BYTES: usize = 32 * 1024 * 1024;
+
+fn has_zlib_header(data: &[u8]) -> bool {
+    matches!(data, [cmf, flg, ..]
+        if cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0)
+}
+
+/// 流式解码：超出上限 / 数据无效返回 None；输入截断时返回已解出的部分
+fn decode(data: &[u8], format: DataFormat) -> Option<Vec<u8>> {
+    let mut state = InflateState::new_boxed(format);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut input = data;
+    let mut out = Vec::new();
+    loop {
+        let result = stream::inflate(&mut state, input, &mut buf, MZFlush::None);
+        input = &input[result.bytes_consumed..];
+        out.extend_from_slice(&buf[..result.bytes_written]);
+        if out.len() > MAX_INFLATED_BYTES {
+            return None;
+        }
+        match result.status {
+            Ok(MZStatus::StreamEnd) => return Some(out),
+            Ok(_) if result.bytes_consumed == 0 && result.bytes_written == 0 => break,
+            Ok(_) => {}
+            // 输入耗尽但流未结束：数据截断
+            Err(MZError::Buf) => break,
+            Err(_) => return None,
+        }
+    }
+    (!out.is_empty()).then_some(out)
+}
+
+/// 解压 zlib / raw deflate 数据；数据截断时返回已解出的部分
+pub(crate) fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    if has_zlib_header(data) {
+        // 头部校验可能误判 raw deflate 数据，失败时再按 raw 解码
+        if let Some(out) = decode(data, DataFormat::Zlib) {
+            return Some(out);
+        }
+    }
+    decode(data, DataFormat::Raw)
+}
+
+/// 解压 gzip 数据（跳过 RFC 1952 头部）
+pub(crate) fn gunzip(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0x1f, 0x8b, 8]) {
+        return None;
+    }
+    let flags = *data.get(3)?;
+    let mut pos = 10;
+    if flags & 0x04 != 0 {
+        let extra = u16::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?]) as usize;
+        pos += 2 + extra;
+    }
+    // FNAME / FCOMMENT：以 0 结尾的字符串
+    for flag in [0x08, 0x10] {
+        if flags & flag != 0 {
+            pos += data.get(pos..)?.iter().position(|b| *b == 0)? + 1;
+        }
+    }
+    if flags & 0x02 != 0 {
+        pos += 2;
+    }
+    // 尾部 CRC32 / ISIZE 不参与解码（raw deflate 在最后一个块后结束）
+    decode(data.get(pos..)?, DataFormat::Raw)
+}
+
+/// 响应体若为 gzip 则解压，否则原样返回（兼容 HTTP 客户端已自动解压的情况）
+pub(crate) fn maybe_gunzip(data: Vec<u8>) -> Vec<u8> {
+    match gunzip(&data) {
+        Some(plain) => plain,
+        None => data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::deflate::{compress_to_vec, compress_to_vec_zlib};
+
+    fn sample() -> Vec<u8> {
+        (0..20_000u32)
+            .flat_map(|i| format!("line {} {}\n", i, i.wrapping_mul(2654435761) % 97).into_bytes())
+            .collect()
+    }
+
+    /// 按 RFC 1952 拼装 gzip（带 FNAME），CRC 不参与解码，填 0
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x1f, 0x8b, 8, 0x08, 0, 0, 0, 0, 0, 3];
+        out.extend_from_slice(b"body.json\0");
+        out.extend(compress_to_vec(data, 6));
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn round_trips_every_level() {
+        let data = sample();
+        for level in 0..=10 {
+            assert_eq!(inflate(&compress_to_vec_zlib(&data, level)).unwrap(), data);
+            assert_eq!(inflate(&compress_to_vec(&data, level)).unwrap(), data);
+        }
+        assert_eq!(inflate(&compress_to_vec_zlib(b"", 6)).unwrap(), b"");
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let data = sample();
+        assert_eq!(gunzip(&gzip(&data)).unwrap(), data);
+        assert_eq!(maybe_gunzip(gzip(b"{}")), b"{}");
+        // 非 gzip 内容原样返回
+        assert_eq!(maybe_gunzip(b"{}".to_vec()), b"{}");
+    }
+
+    #[test]
+    fn truncated_stream_returns_prefix() {
+        let data = sample();
+        let packed = compress_to_vec_zlib(&data, 6);
+        let out = inflate(&packed[..packed.len() / 2]).unwrap();
+        assert!(!out.is_empty() && out.len() < data.len());
+        assert!(data.starts_with(&out));
+        for end in 0..64 {
+            let _ = inflate(&packed[..end]);
+        }
+    }
+
+    #[test]
+    fn rejects_garbage_and_bombs() {
+        assert!(inflate(&[0xff; 32]).is_none());
+        assert!(gunzip(&[0x1f, 0x8b, 8, 0x04]).is_none());
+        let bomb = compress_to_vec_zlib(&vec![0u8; MAX_INFLATED_BYTES + 1], 6);
+        assert!(inflate(&bomb).is_none());
+    }
+}
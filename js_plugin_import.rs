@@ -0,0 +1,371 @@
+// JS 版 AMP 代理插件配置导入
+//
+// 读取 JS 插件的配置文件（JSON，或 `module.exports = {...}` / `export default {...}` 包裹的
+// 纯对象字面量），映射为：
+// - 三个 AMP 槽位的 Profile（providers.anthropic / openai / google）
+// - AMP Code 代理配置（Access Token、ampcode.com 地址、Tavily Key）
+// - 自定义提示词 → 各槽位的命名预设；modelMap → amp-code 模型别名
+// 预设与别名直接写入 processor_settings.json；Profile 与代理配置由调用方
+// 交给 ProfileManager / ProxyConfigManager 持久化。无法翻译的字段逐项列入报告。
+
+use super::app_state::AppState;
+use super::presets::PresetSettings;
+use super::processor_settings::ProcessorSettings;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+
+/// 导入的 Profile，slot 为 AMP 槽位（claude / codex / gemini）
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ImportedProfile {
+    pub slot: String,
+    pub name: String,
+    pub base_url: String,
+    /// 密钥不随导入报告序列化
+    #[serde(skip_serializing)]
+    pub api_key: String,
+}
+
+/// 导入的 AMP Code 代理配置（对应 ProxyConfigManager 中 amp-code 条目）
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct ImportedProxyConfig {
+    #[serde(skip_serializing)]
+    pub real_api_key: Option<String>,
+    pub real_base_url: Option<String>,
+    #[serde(skip_serializing)]
+    pub tavily_api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct ImportPlan {
+    pub profiles: Vec<ImportedProfile>,
+    pub proxy_config: ImportedProxyConfig,
+    /// 预设名 → 预设（应用到全部槽位）
+    pub presets: Vec<(String, PresetSettings)>,
+    pub model_aliases: Vec<(String, String)>,
+    /// 无法翻译的字段（JSON 路径 + 原因）
+    pub untranslated: Vec<String>,
+}
+
+/// 解析 JS 插件配置文本
+pub(crate) fn parse(text: &str) -> Result<ImportPlan> {
+    let root: Value = serde_json::from_str(strip_module_wrapper(text))
+        .map_err(|e| anyhow!("JS 插件配置解析失败（仅支持 JSON 形式的对象字面量）: {}", e))?;
+    let obj = root
+        .as_object()
+        .ok_or_else(|| anyhow!("JS 插件配置顶层必须是对象"))?;
+
+    let mut plan = ImportPlan::default();
+    for (key, value) in obj {
+        match key.as_str() {
+            "providers" => import_providers(value, &mut plan),
+            "amp" => import_amp(value, &mut plan),
+            "ampToken" | "accessToken" => {
+                plan.proxy_config.real_api_key = value.as_str().map(|s| s.to_string())
+            }
+            "ampUrl" => plan.proxy_config.real_base_url = value.as_str().map(|s| s.to_string()),
+            "tavilyApiKey" => {
+                plan.proxy_config.tavily_api_key = value.as_str().map(|s| s.to_string())
+            }
+            "systemPrompt" => match value.as_str() {
+                Some(text) => plan
+                    .presets
+                    .push(("imported".to_string(), system_preset(text))),
+                None => plan.untranslated.push("systemPrompt: 非字符串".to_string()),
+            },
+            "prompts" | "customPrompts" => import_prompts(key, value, &mut plan),
+            "modelMap" | "modelMapping" => import_model_map(key, value, &mut plan),
+            _ => plan.untranslated.push(format!("{}: 不支持的配置项", key)),
+        }
+    }
+    Ok(plan)
+}
+
+/// 去掉 `module.exports =` / `export default` 包裹与末尾分号
+fn strip_module_wrapper(text: &str) -> &str {
+    let trimmed = text.trim();
+    let body = trimmed
+        .strip_prefix("module.exports")
+        .and_then(|s| s.trim_start().strip_prefix('='))
+        .or_else(|| trimmed.strip_prefix("export default"))
+        .unwrap_or(trimmed);
+    body.trim().trim_end_matches(';').trim_end()
+}
+
+fn slot_for_provider(provider: &str) -> Option<&'static str> {
+    match provider.to_lowercase().as_str() {
+        "anthropic" | "claude" => Some("claude"),
+        "openai" | "codex" => Some("codex"),
+        "google" | "gemini" => Some("gemini"),
+        _ => None,
+    }
+}
+
+fn import_providers(value: &Value, plan: &mut ImportPlan) {
+    let Some(providers) = value.as_object() else {
+        plan.untranslated.push("providers: 非对象".to_string());
+        return;
+    };
+    for (provider, conf) in providers {
+        let path = format!("providers.{}", provider);
+        let Some(slot) = slot_for_provider(provider) else {
+            plan.untranslated.push(format!(
+                "{}: 未知提供方，AMP 仅支持 anthropic / openai / google",
+                path
+            ));
+            continue;
+        };
+        let field = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|n| conf[*n].as_str())
+                .map(|s| s.to_string())
+        };
+        let (Some(base_url), Some(api_key)) = (
+            field(&["baseUrl", "baseURL", "url"]),
+            field(&["apiKey", "key"]),
+        ) else {
+            plan.untranslated
+                .push(format!("{}: 缺少 baseUrl 或 apiKey", path));
+            continue;
+        };
+        if let Some(extra) = conf.as_object() {
+            for k in extra.keys() {
+                if !["baseUrl", "baseURL", "url", "apiKey", "key", "name"].contains(&k.as_str()) {
+                    plan.untranslated
+                        .push(format!("{}.{}: 不支持的配置项", path, k));
+                }
+            }
+        }
+        plan.profiles.push(ImportedProfile {
+            slot: slot.to_string(),
+            name: field(&["name"]).unwrap_or_else(|| format!("js-plugin-{}", provider)),
+            base_url,
+            api_key,
+        });
+    }
+}
+
+fn import_amp(value: &Value, plan: &mut ImportPlan) {
+    let Some(amp) = value.as_object() else {
+        plan.untranslated.push("amp: 非对象".to_string());
+        return;
+    };
+    for (key, v) in amp {
+        let s = v.as_str().map(|s| s.to_string());
+        match key.as_str() {
+            "accessToken" | "token" | "apiKey" => plan.proxy_config.real_api_key = s,
+            "url" | "baseUrl" => plan.proxy_config.real_base_url = s,
+            "tavilyApiKey" => plan.proxy_config.tavily_api_key = s,
+            _ => plan
+                .untranslated
+                .push(format!("amp.{}: 不支持的配置项", key)),
+        }
+    }
+}
+
+fn system_preset(text: &str) -> PresetSettings {
+    PresetSettings {
+        system: Some(text.to_string()),
+        ..Default::default()
+    }
+}
+
+fn import_prompts(key: &str, value: &Value, plan: &mut ImportPlan) {
+    let Some(prompts) = value.as_object() else {
+        plan.untranslated.push(format!("{}: 非对象", key));
+        return;
+    };
+    for (name, prompt) in prompts {
+        match prompt.as_str() {
+            Some(text) => plan.presets.push((name.clone(), system_preset(text))),
+            None => plan
+                .untranslated
+                .push(format!("{}.{}: 仅支持字符串提示词", key, name)),
+        }
+    }
+}
+
+fn import_model_map(key: &str, value: &Value, plan: &mut ImportPlan) {
+    let Some(map) = value.as_object() else {
+        plan.untranslated.push(format!("{}: 非对象", key));
+        return;
+    };
+    for (from, to) in map {
+        match to.as_str() {
+            Some(to) => plan.model_aliases.push((from.clone(), to.to_string())),
+            None => plan
+                .untranslated
+                .push(format!("{}.{}: 目标模型必须是字符串", key, from)),
+        }
+    }
+}
+
+/// 将预设与模型别名合并进处理器配置（已存在的同名项保留不覆盖，并记入报告）
+pub(crate) fn apply_processor_settings(plan: &mut ImportPlan, settings: &mut ProcessorSettings) {
+    for slot in ["claude", "codex", "gemini"] {
+        let profile = settings.profiles.entry(slot.to_string()).or_default();
+        for (name, preset) in &plan.presets {
+            if profile.presets.presets.contains_key(name) {
+                plan.untranslated
+                    .push(format!("预设 {}（{}）: 已存在，未覆盖", name, slot));
+                continue;
+            }
+            profile.presets.presets.insert(name.clone(), preset.clone());
+        }
+    }
+
+    if plan.model_aliases.is_empty() {
+        return;
+    }
+    let mut transforms = settings.transforms_for("amp-code");
+    for (from, to) in &plan.model_aliases {
+        if transforms.model_aliases.contains_key(from) {
+            plan.untranslated
+                .push(format!("模型别名 {}: 已存在，未覆盖", from));
+            continue;
+        }
+        transforms.model_aliases.insert(from.clone(), to.clone());
+    }
+    settings
+        .tools
+        .entry("amp-code".to_string())
+        .or_default()
+        .transforms = Some(transforms);
+}
+
+/// 读取并导入 JS 插件配置：处理器配置立即保存（state 缓存的配置随之失效），
+/// 返回的计划中 Profile 与代理配置待调用方写入
+pub(crate) fn import_file(state: &AppState, path: &Path) -> Result<ImportPlan> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("读取 JS 插件配置失败 {}: {}", path.display(), e))?;
+    let mut plan = parse(&text)?;
+
+    let mut settings = state.load_settings()?;
+    apply_processor_settings(&mut plan, &mut settings);
+    state.save_settings(&settings)?;
+
+    for item in &plan.untranslated {
+        tracing::warn!("JS 插件配置未导入: {}", item);
+    }
+    tracing::info!(
+        "JS 插件配置导入: {} 个 Profile，{} 个预设，{} 个模型别名，{} 项未翻译",
+        plan.profiles.len(),
+        plan.presets.len(),
+        plan.model_aliases.len(),
+        plan.untranslated.len()
+    );
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"module.exports = {
+        "providers": {
+            "anthropic": { "baseUrl": "https://a.example", "apiKey": "ak", "timeout": 5 },
+            "google": { "url": "https://g.example", "key": "gk", "name": "gem" },
+            "mistral": { "baseUrl": "https://m.example", "apiKey": "mk" },
+            "openai": { "baseUrl": "https://o.example" }
+        },
+        "amp": { "accessToken": "amp-token", "url": "https://ampcode.com", "region": "eu" },
+        "tavilyApiKey": "tvly-1",
+        "systemPrompt": "Be terse.",
+        "modelMap": { "claude-sonnet-4": "claude-opus-4", "bad": 1 },
+        "telemetry": false
+    };"#;
+
+    #[test]
+    fn parses_wrapped_config() {
+        let plan = parse(CONFIG).unwrap();
+        let slots: Vec<_> = plan
+            .profiles
+            .iter()
+            .map(|p| (p.slot.as_str(), p.name.as_str(), p.api_key.as_str()))
+            .collect();
+        assert_eq!(
+            slots,
+            vec![
+                ("claude", "js-plugin-anthropic", "ak"),
+                ("gemini", "gem", "gk")
+            ]
+        );
+        assert_eq!(plan.proxy_config.real_api_key.as_deref(), Some("amp-token"));
+        assert_eq!(
+            plan.proxy_config.real_base_url.as_deref(),
+            Some("https://ampcode.com")
+        );
+        assert_eq!(plan.proxy_config.tavily_api_key.as_deref(), Some("tvly-1"));
+        assert_eq!(plan.presets[0].0, "imported");
+        assert_eq!(
+            plan.model_aliases,
+            vec![("claude-sonnet-4".to_string(), "claude-opus-4".to_string())]
+        );
+
+        let untranslated = plan.untranslated.join("\n");
+        for path in [
+            "providers.anthropic.timeout",
+            "providers.mistral",
+            "providers.openai",
+            "amp.region",
+            "modelMap.bad",
+            "telemetry",
+        ] {
+            assert!(untranslated.contains(path), "{} 未记入报告", path);
+        }
+    }
+
+    #[test]
+    fn wrapper_forms_and_invalid_input() {
+        assert!(parse("export default { \"ampUrl\": \"https://x\" }").is_ok());
+        assert!(parse("{}").unwrap().profiles.is_empty());
+        assert!(parse("[]").is_err());
+        assert!(parse("module.exports = { ampUrl: 'x' }").is_err());
+    }
+
+    #[test]
+    fn report_does_not_serialize_secrets() {
+        let text = serde_json::to_string(&parse(CONFIG).unwrap()).unwrap();
+        assert!(!text.contains("amp-token"));
+        assert!(!text.contains("\"ak\""));
+        assert!(!text.contains("tvly-1"));
+    }
+
+    #[test]
+    fn existing_presets_and_aliases_are_kept() {
+        let mut settings = ProcessorSettings::default();
+        settings
+            .profiles
+            .entry("codex".to_string())
+            .or_default()
+            .presets
+            .presets
+            .insert("imported".to_string(), system_preset("mine"));
+        let mut plan = parse(CONFIG).unwrap();
+        apply_processor_settings(&mut plan, &mut settings);
+        apply_processor_settings(&mut plan, &mut settings);
+
+        assert_eq!(
+            settings.profiles["codex"].presets.presets["imported"]
+                .system
+                .as_deref(),
+            Some("mine")
+        );
+        assert_eq!(
+            settings.profiles["claude"].presets.presets["imported"]
+                .system
+                .as_deref(),
+            Some("Be terse.")
+        );
+        assert_eq!(
+            settings.transforms_for("amp-code").model_aliases["claude-sonnet-4"],
+            "claude-opus-4"
+        );
+        assert!(plan
+            .untranslated
+            .iter()
+            .any(|u| u.starts_with("模型别名 claude-sonnet-4")));
+    }
+}
@@ -0,0 +1,403 @@
+// 本地文档库离线搜索（webSearch2 兜底）
+//
+// search.local_corpus.folders 配置若干本地目录（Markdown / 文本 / PDF），
+// 代理启动后首个请求触发后台建索引（目录配置变化时重建），之后：
+// - 网络搜索全部失败时，webSearch2 改查本地文档库
+// - params.offline 为 true 时直接只查本地文档库
+// 结果 url 为 file:// 路径，摘录取命中查询词最多的段落。
+// 索引为内存倒排表，BM25 打分；英文按单词切分，中日韩文字按二元组切分。
+// 隐藏目录与符号链接跳过，文件数与单文件大小有上限。
+
+use super::pdf_text::{self, PdfSettings};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::runtime::Handle;
+
+/// BM25 参数
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+/// 摘录最大字符数
+const EXCERPT_CHARS: usize = 400;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocalCorpusSettings {
+    /// 文档目录（为空时不启用）
+    pub folders: Vec<String>,
+    /// 收录的文件扩展名
+    pub extensions: Vec<String>,
+    /// 最多收录的文件数
+    pub max_files: usize,
+    /// 单个文件大小上限
+    pub max_file_bytes: u64,
+}
+
+impl Default for LocalCorpusSettings {
+    fn default() -> Self {
+        Self {
+            folders: Vec::new(),
+            extensions: ["md", "markdown", "txt", "rst", "adoc", "pdf"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            max_files: 5000,
+            max_file_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+struct Doc {
+    path: PathBuf,
+    title: String,
+    text: String,
+    len: usize,
+}
+
+struct Index {
+    /// 建索引时的目录配置，变化时重建
+    folders: Vec<String>,
+    docs: Vec<Doc>,
+    /// 词 → [(文档下标, 词频)]
+    postings: HashMap<String, Vec<(usize, u32)>>,
+    avg_len: f64,
+}
+
+static INDEX: Lazy<RwLock<Option<Arc<Index>>>> = Lazy::new(|| RwLock::new(None));
+static INDEXING: AtomicBool = AtomicBool::new(false);
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32, 0x3040..=0x30ff | 0x3400..=0x4dbf | 0x4e00..=0x9fff | 0xac00..=0xd7af)
+}
+
+/// 切词：字母数字串为一个词，中日韩文字取相邻二元组（单字时取单字）
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut cjk: Vec<char> = Vec::new();
+    let flush_cjk = |cjk: &mut Vec<char>, tokens: &mut Vec<String>| {
+        match cjk.len() {
+            0 => {}
+            1 => tokens.push(cjk[0].to_string()),
+            _ => tokens.extend(cjk.windows(2).map(|w| w.iter().collect())),
+        }
+        cjk.clear();
+    };
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            cjk.push(c);
+        } else if c.is_alphanumeric() || c == '_' {
+            flush_cjk(&mut cjk, &mut tokens);
+            word.extend(c.to_lowercase());
+        } else {
+            flush_cjk(&mut cjk, &mut tokens);
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+        }
+    }
+    flush_cjk(&mut cjk, &mut tokens);
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+fn collect_files(settings: &LocalCorpusSettings, dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        tracing::warn!("无法读取文档目录: {}", dir.display());
+        return;
+    };
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        if out.len() >= settings.max_files {
+            return;
+        }
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if file_type.is_dir() {
+            collect_files(settings, &path, out);
+        } else if file_type.is_file() {
+            let ext = path
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let size_ok = entry
+                .metadata()
+                .is_ok_and(|m| m.len() <= settings.max_file_bytes);
+            if size_ok
+                && settings
+                    .extensions
+                    .iter()
+                    .any(|e| e.eq_ignore_ascii_case(&ext))
+            {
+                out.push(path);
+            }
+        }
+    }
+}
+
+fn load_doc(path: &Path) -> Option<Doc> {
+    let data = std::fs::read(path).ok()?;
+    let is_pdf = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("pdf"));
+    let text = if is_pdf {
+        match pdf_text::extract(&data, &PdfSettings::default()) {
+            Ok(text) => text,
+            Err(e) => {
+                tracing::debug!("跳过 PDF {}: {}", path.display(), e);
+                return None;
+            }
+        }
+    } else {
+        String::from_utf8_lossy(&data).into_owned()
+    };
+    // 标题：Markdown 首个标题行，否则文件名
+    let title = text
+        .lines()
+        .map(str::trim)
+        .find(|l| l.starts_with("# "))
+        .map(|l| l.trim_start_matches('#').trim().to_string())
+        .unwrap_or_else(|| {
+            path.file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+    Some(Doc {
+        path: path.to_path_buf(),
+        title,
+        text,
+        len: 0,
+    })
+}
+
+fn build(settings: &LocalCorpusSettings) -> Index {
+    let mut files = Vec::new();
+    for folder in &settings.folders {
+        collect_files(settings, Path::new(folder), &mut files);
+    }
+
+    let mut docs = Vec::new();
+    let mut postings: HashMap<String, Vec<(usize, u32)>> = HashMap::new();
+    let mut total_len = 0usize;
+    for path in files {
+        let Some(mut doc) = load_doc(&path) else {
+            continue;
+        };
+        let id = docs.len();
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        let tokens = tokenize(&format!("{}\n{}", doc.title, doc.text));
+        doc.len = tokens.len();
+        total_len += tokens.len();
+        for token in tokens {
+            *counts.entry(token).or_default() += 1;
+        }
+        for (term, tf) in counts {
+            postings.entry(term).or_default().push((id, tf));
+        }
+        docs.push(doc);
+    }
+    let avg_len = if docs.is_empty() {
+        0.0
+    } else {
+        total_len as f64 / docs.len() as f64
+    };
+    Index {
+        folders: settings.folders.clone(),
+        docs,
+        postings,
+        avg_len,
+    }
+}
+
+/// 首次调用（或目录配置变化）时在后台建索引
+pub(crate) fn ensure_indexed(settings: &LocalCorpusSettings, runtime: &Handle) {
+    if settings.folders.is_empty() {
+        return;
+    }
+    let current = INDEX
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .map(|i| i.folders.clone());
+    if current.as_ref() == Some(&settings.folders) || INDEXING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let settings = settings.clone();
+    runtime.spawn_blocking(move || {
+        let started = std::time::Instant::now();
+        let index = build(&settings);
+        tracing::info!(
+            "本地文档库索引完成: {} 个文件，{} 个词，耗时 {:?}",
+            index.docs.len(),
+            index.postings.len(),
+            started.elapsed()
+        );
+        *INDEX.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(index));
+        INDEXING.store(false, Ordering::SeqCst);
+    });
+}
+
+/// 是否已有可用索引
+pub(crate) fn is_ready() -> bool {
+    INDEX
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .is_some_and(|i| !i.docs.is_empty())
+}
+
+/// 命中查询词最多的段落
+fn best_excerpt(text: &str, terms: &[String]) -> String {
+    let best = text
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .max_by_key(|p| {
+            let tokens = tokenize(p);
+            terms.iter().filter(|t| tokens.contains(t)).count()
+        })
+        .unwrap_or("");
+    let flat = best.split_whitespace().collect::<Vec<_>>().join(" ");
+    if flat.chars().count() > EXCERPT_CHARS {
+        format!("{}…", flat.chars().take(EXCERPT_CHARS).collect::<String>())
+    } else {
+        flat
+    }
+}
+
+/// 搜索本地文档库，返回 webSearch2 结果格式
+pub(crate) fn search(queries: &[&str], max_results: usize) -> Vec<Value> {
+    let Some(index) = INDEX.read().unwrap_or_else(|e| e.into_inner()).clone() else {
+        return Vec::new();
+    };
+    search_index(&index, queries, max_results)
+}
+
+fn search_index(index: &Index, queries: &[&str], max_results: usize) -> Vec<Value> {
+    let mut terms: Vec<String> = queries.iter().flat_map(|q| tokenize(q)).collect();
+    terms.sort();
+    terms.dedup();
+
+    let n = index.docs.len() as f64;
+    let mut scores: HashMap<usize, f64> = HashMap::new();
+    for term in &terms {
+        let Some(postings) = index.postings.get(term) else {
+            continue;
+        };
+        let df = postings.len() as f64;
+        let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+        for (doc, tf) in postings {
+            let len = index.docs[*doc].len as f64;
+            let tf = *tf as f64;
+            let norm = tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * len / index.avg_len.max(1.0)));
+            *scores.entry(*doc).or_default() += idf * norm;
+        }
+    }
+
+    let mut ranked: Vec<(usize, f64)> = scores.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranked
+        .into_iter()
+        .take(max_results)
+        .map(|(id, _)| {
+            let doc = &index.docs[id];
+            json!({
+                "title": doc.title,
+                "url": format!("file://{}", doc.path.display()),
+                "excerpts": [best_excerpt(&doc.text, &terms)]
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corpus_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dc-local-corpus-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (file, content) in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn index(dir: &Path) -> Index {
+        build(&LocalCorpusSettings {
+            folders: vec![dir.to_string_lossy().into_owned()],
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn tokenize_words_and_cjk_bigrams() {
+        assert_eq!(
+            tokenize("Hello, World_1 中文分词"),
+            ["hello", "world_1", "中文", "文分", "分词"]
+        );
+        assert_eq!(tokenize("单"), ["单"]);
+        assert!(tokenize(" -- ").is_empty());
+    }
+
+    #[test]
+    fn bm25_ranks_denser_match_first() {
+        let dir = corpus_dir(
+            "rank",
+            &[
+                (
+                    "async.md",
+                    "# Rust async\n\nTokio runtime spawns async tasks.\n\nUnrelated paragraph.",
+                ),
+                (
+                    "notes.txt",
+                    "tokio appears once in a long note about many other topics and words",
+                ),
+                ("cooking.md", "# Cooking\n\nPasta recipes"),
+                ("code.rs", "tokio tokio tokio async async"),
+                (".hidden/secret.md", "tokio async"),
+            ],
+        );
+        let index = index(&dir);
+        assert_eq!(index.docs.len(), 3);
+
+        let results = search_index(&index, &["tokio async"], 5);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["title"], "Rust async");
+        assert_eq!(
+            results[0]["excerpts"][0],
+            "Tokio runtime spawns async tasks."
+        );
+        assert_eq!(results[1]["title"], "notes.txt");
+        assert!(search_index(&index, &["quantum"], 5).is_empty());
+        assert_eq!(search_index(&index, &["tokio"], 1).len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn excerpt_is_truncated() {
+        let long = "词".repeat(EXCERPT_CHARS + 10);
+        let excerpt = best_excerpt(&long, &["词".to_string()]);
+        assert_eq!(excerpt.chars().count(), EXCERPT_CHARS + 1);
+        assert!(excerpt.ends_with('…'));
+    }
+}
@@ -0,0 +1,148 @@
+// 日志脱敏
+//
+// 处理器日志中可能出现带 ?key= 的 Gemini 目标地址、reqwest 错误里的完整 URL、
+// Authorization / x-api-key 请求头值，以及生成的 user_id 指纹。
+// - redact()：在 diagnostic_bundle::mask_text 的密钥格式之外，再掩码 URL 中的 key / token 类参数、
+//   请求头形式的凭据（authorization / x-api-key / x-goog-api-key / api-key）与 user_id 指纹
+// - RedactingWriter：包装日志输出，按行脱敏后写出。宿主初始化 tracing_subscriber 时
+//   .with_writer(log_redact::writer(std::io::stderr)) 即对所有处理器的日志事件生效
+// - 已知会输出目标地址 / user_id 的日志点在调用处先经 redact()，未接入 writer 时同样不泄露
+
+use super::diagnostic_bundle::mask_text;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::io::{self, Write};
+
+static QUERY_SECRET_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)([?&](?:key|api[_-]?key|access[_-]?token|refresh[_-]?token|token|sig|signature|client[_-]?secret|password)=)[^&#\s]+",
+    )
+    .expect("URL 参数掩码正则非法")
+});
+
+static HEADER_SECRET_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"(?i)\b((?:proxy-)?authorization|x-api-key|x-goog-api-key|api-key)(["']?\s*[:=]\s*["']?)(?:(?:bearer|basic)\s+)?[^\s"',;}]+"#,
+    )
+    .expect("请求头掩码正则非法")
+});
+
+static USER_ID_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(user_)[0-9a-f]{16,}(_account_{1,2}session_)[0-9a-f\-]{8,}")
+        .expect("user_id 掩码正则非法")
+});
+
+/// 文本脱敏（日志行、URL、错误信息）
+pub(crate) fn redact(text: &str) -> String {
+    let text = mask_text(text);
+    let text = QUERY_SECRET_RE.replace_all(&text, "${1}***");
+    let text = HEADER_SECRET_RE.replace_all(&text, "${1}${2}***");
+    USER_ID_RE.replace_all(&text, "${1}***${2}***").into_owned()
+}
+
+/// 按行脱敏的日志输出
+pub struct RedactingWriter<W: Write> {
+    inner: W,
+    /// 尚未遇到换行的部分
+    pending: Vec<u8>,
+}
+
+impl<W: Write> RedactingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            pending: Vec::new(),
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let text = String::from_utf8_lossy(line);
+        self.inner.write_all(redact(&text).as_bytes())
+    }
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(pos) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=pos).collect();
+            self.write_line(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            self.write_line(&line)?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for RedactingWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// 包装日志输出构造函数，可直接作为 tracing_subscriber 的 with_writer 参数
+pub fn writer<W, F>(make: F) -> impl Fn() -> RedactingWriter<W> + Send + Sync + 'static
+where
+    W: Write,
+    F: Fn() -> W + Send + Sync + 'static,
+{
+    move || RedactingWriter::new(make())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_url_secret_params() {
+        let text = redact("GET https://host/v1/models/x:generate?alt=sse&key=AIzaSecret123&x=1");
+        assert!(text.contains("alt=sse&key=***&x=1"), "{}", text);
+        assert!(!text.contains("AIzaSecret123"));
+    }
+
+    #[test]
+    fn masks_credential_headers() {
+        let text = redact(r#"headers: {"authorization": "Bearer abc.def", "x-api-key": "k-123"}"#);
+        assert!(!text.contains("abc.def"), "{}", text);
+        assert!(!text.contains("k-123"), "{}", text);
+        assert!(text.contains(r#""authorization": "***""#), "{}", text);
+        assert_eq!(redact("api-key=plain"), "api-key=***");
+    }
+
+    #[test]
+    fn masks_user_id_fingerprint() {
+        let text = redact(
+            "user_id=user_0123456789abcdef0123_account__session_0a1b2c3d-4e5f-6789-abcd-ef0123456789",
+        );
+        assert_eq!(text, "user_id=user_***_account__session_***");
+    }
+
+    #[test]
+    fn leaves_plain_text_alone() {
+        assert_eq!(
+            redact("AMP Code → Claude: 200 OK"),
+            "AMP Code → Claude: 200 OK"
+        );
+    }
+
+    #[test]
+    fn writer_redacts_per_line_across_writes() {
+        let mut out = Vec::new();
+        {
+            let mut writer = RedactingWriter::new(&mut out);
+            writer.write_all(b"url ?tok").unwrap();
+            writer.write_all(b"en=abc123\nsecond ").unwrap();
+            writer.write_all(b"line").unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "url ?token=***\nsecond line"
+        );
+    }
+}
@@ -0,0 +1,421 @@
+// 维护应答
+//
+// 某路由没有可用 Profile（未配置或被标记为不可用）时，不返回连接错误，
+// 而是按该路由的协议返回一条正常的 assistant 消息，说明故障并建议重试时间，
+// 便于 AMP 界面直接展示。流式请求返回对应协议的 SSE 事件序列。
+// 不可用标记由代理层在连续连接失败时调用 mark_down / mark_up 维护。
+
+use super::gemini_stream::{self, GeminiStreamFormat};
+use super::outcome::ProcessOutcome;
+use super::sse;
+use super::transform_middleware::TransformTarget;
+use hyper::HeaderMap as HyperHeaderMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceSettings {
+    pub enabled: bool,
+    /// 提示文本，支持占位符 {route} 与 {retry_after}（秒）
+    pub message: String,
+    /// 未知恢复时间时建议的重试间隔（秒）
+    pub retry_after_secs: u64,
+}
+
+impl Default for MaintenanceSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            message: "[服务暂不可用] 当前没有可用的 {route} 后端（未配置或暂时故障）。请约 {retry_after} 秒后重试，或在 DuckCoding 中检查 / 切换 Profile。".to_string(),
+            retry_after_secs: 60,
+        }
+    }
+}
+
+/// 路由 → 不可用截止时间
+static DOWN_UNTIL: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 标记路由在 duration 内不可用
+pub(crate) fn mark_down(route: &str, duration: Duration) {
+    tracing::warn!("路由 {} 标记为不可用 {} 秒", route, duration.as_secs());
+    DOWN_UNTIL
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(route.to_string(), Instant::now() + duration);
+}
+
+pub(crate) fn mark_up(route: &str) {
+    DOWN_UNTIL
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(route);
+}
+
+/// 路由剩余不可用时间；可用时返回 None
+pub(crate) fn down_remaining(route: &str) -> Option<Duration> {
+    let mut map = DOWN_UNTIL.lock().unwrap_or_else(|e| e.into_inner());
+    let until = *map.get(route)?;
+    let now = Instant::now();
+    if until <= now {
+        map.remove(route);
+        return None;
+    }
+    Some(until - now)
+}
+
+/// 当前不可用的路由及剩余秒数
+pub(crate) fn down_routes() -> Vec<(String, u64)> {
+    let now = Instant::now();
+    DOWN_UNTIL
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter(|(_, until)| **until > now)
+        .map(|(route, until)| (route.clone(), (*until - now).as_secs()))
+        .collect()
+}
+
+/// 构造维护应答（本地响应）
+pub(crate) fn respond(
+    settings: &MaintenanceSettings,
+    target: TransformTarget,
+    route: &str,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+) -> ProcessOutcome {
+    let retry_after = down_remaining(route)
+        .map(|d| d.as_secs().max(1))
+        .unwrap_or(settings.retry_after_secs);
+    let text = settings
+        .message
+        .replace("{route}", route)
+        .replace("{retry_after}", &retry_after.to_string());
+    let (content_type, body) = assistant_reply(target, "maintenance", path, query, body, &text);
+
+    let mut headers = HyperHeaderMap::new();
+    headers.insert("content-type", content_type.parse().unwrap());
+    headers.insert("retry-after", retry_after.to_string().parse().unwrap());
+    ProcessOutcome::local(&format!("maintenance/{}", route), headers, body)
+}
+
+/// 按目标协议构造一条 assistant 文本回复（流式请求返回 SSE），返回 (content-type, body)；
+/// tag 用于消息 / 响应 id（如 msg_maintenance）
+pub(crate) fn assistant_reply(
+    target: TransformTarget,
+    tag: &str,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+    text: &str,
+) -> (&'static str, Vec<u8>) {
+    let request: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+    let model = request["model"].as_str().unwrap_or("unavailable");
+    match target {
+        TransformTarget::Claude => claude_response(&request, tag, model, text),
+        TransformTarget::Codex => openai_response(&request, tag, model, text),
+        TransformTarget::Gemini => gemini_response(path, query, text),
+    }
+}
+
+fn is_stream(request: &Value) -> bool {
+    request["stream"].as_bool() == Some(true)
+}
+
+fn claude_response(request: &Value, tag: &str, model: &str, text: &str) -> (&'static str, Vec<u8>) {
+    let id = format!("msg_{}", tag);
+    if !is_stream(request) {
+        let body = json!({
+            "id": id,
+            "type": "message",
+            "role": "assistant",
+            "model": model,
+            "content": [{ "type": "text", "text": text }],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": { "input_tokens": 0, "output_tokens": 0 }
+        });
+        return ("application/json", body.to_string().into_bytes());
+    }
+
+    let events = [
+        (
+            "message_start",
+            json!({
+                "type": "message_start",
+                "message": {
+                    "id": id, "type": "message", "role": "assistant", "model": model,
+                    "content": [], "stop_reason": null, "stop_sequence": null,
+                    "usage": { "input_tokens": 0, "output_tokens": 0 }
+                }
+            }),
+        ),
+        (
+            "content_block_start",
+            json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "text", "text": "" } }),
+        ),
+        (
+            "content_block_delta",
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": text } }),
+        ),
+        (
+            "content_block_stop",
+            json!({ "type": "content_block_stop", "index": 0 }),
+        ),
+        (
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": { "stop_reason": "end_turn", "stop_sequence": null },
+                "usage": { "output_tokens": 0 }
+            }),
+        ),
+        ("message_stop", json!({ "type": "message_stop" })),
+    ];
+    let out: String = events
+        .iter()
+        .map(|(name, data)| sse::format(Some(name), data))
+        .collect();
+    ("text/event-stream", out.into_bytes())
+}
+
+fn openai_response(request: &Value, tag: &str, model: &str, text: &str) -> (&'static str, Vec<u8>) {
+    let is_chat = request.get("messages").is_some();
+
+    if is_chat {
+        let id = format!("chatcmpl-{}", tag);
+        if !is_stream(request) {
+            let body = json!({
+                "id": id,
+                "object": "chat.completion",
+                "model": model,
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": text },
+                    "finish_reason": "stop"
+                }],
+                "usage": { "prompt_tokens": 0, "completion_tokens": 0, "total_tokens": 0 }
+            });
+            return ("application/json", body.to_string().into_bytes());
+        }
+        let chunk = |delta: Value, finish: Value| {
+            json!({
+                "id": id,
+                "object": "chat.completion.chunk",
+                "model": model,
+                "choices": [{ "index": 0, "delta": delta, "finish_reason": finish }]
+            })
+        };
+        let mut out = sse::format(
+            None,
+            chunk(json!({ "role": "assistant", "content": text }), Value::Null),
+        );
+        out.push_str(&sse::format(None, chunk(json!({}), json!("stop"))));
+        out.push_str("data: [DONE]\n\n");
+        return ("text/event-stream", out.into_bytes());
+    }
+
+    let item_id = format!("msg_{}", tag);
+    let item = json!({
+        "id": item_id,
+        "type": "message",
+        "status": "completed",
+        "role": "assistant",
+        "content": [{ "type": "output_text", "text": text, "annotations": [] }]
+    });
+    let response = json!({
+        "id": format!("resp_{}", tag),
+        "object": "response",
+        "model": model,
+        "status": "completed",
+        "output": [item.clone()],
+        "usage": { "input_tokens": 0, "output_tokens": 0, "total_tokens": 0 }
+    });
+    if !is_stream(request) {
+        return ("application/json", response.to_string().into_bytes());
+    }
+
+    let mut in_progress = response.clone();
+    in_progress["status"] = json!("in_progress");
+    in_progress["output"] = json!([]);
+    let part = json!({ "type": "output_text", "text": text, "annotations": [] });
+    let events = [
+        json!({ "type": "response.created", "sequence_number": 0, "response": in_progress }),
+        json!({ "type": "response.output_item.added", "sequence_number": 1, "output_index": 0, "item": item }),
+        json!({ "type": "response.content_part.added", "sequence_number": 2, "output_index": 0, "content_index": 0, "item_id": item_id, "part": part }),
+        json!({ "type": "response.output_text.delta", "sequence_number": 3, "output_index": 0, "content_index": 0, "item_id": item_id, "delta": text }),
+        json!({ "type": "response.output_text.done", "sequence_number": 4, "output_index": 0, "content_index": 0, "item_id": item_id, "text": text }),
+        json!({ "type": "response.content_part.done", "sequence_number": 5, "output_index": 0, "content_index": 0, "item_id": item_id, "part": part }),
+        json!({ "type": "response.output_item.done", "sequence_number": 6, "output_index": 0, "item": item }),
+        json!({ "type": "response.completed", "sequence_number": 7, "response": response }),
+    ];
+    let out: String = events
+        .iter()
+        .map(|e| sse::format(e["type"].as_str(), e))
+        .collect();
+    ("text/event-stream", out.into_bytes())
+}
+
+fn gemini_response(path: &str, query: Option<&str>, text: &str) -> (&'static str, Vec<u8>) {
+    let body = json!({
+        "candidates": [{
+            "content": { "role": "model", "parts": [{ "text": text }] },
+            "finishReason": "STOP",
+            "index": 0
+        }],
+        "usageMetadata": { "promptTokenCount": 0, "candidatesTokenCount": 0, "totalTokenCount": 0 }
+    });
+
+    if !path.to_lowercase().contains(":streamgeneratecontent") {
+        return ("application/json", body.to_string().into_bytes());
+    }
+    match gemini_stream::expected_format(query) {
+        GeminiStreamFormat::Sse => ("text/event-stream", sse::format(None, &body).into_bytes()),
+        GeminiStreamFormat::Json => ("application/json", format!("[{}]", body).into_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::outcome::LocalBody;
+    use super::super::sse::SseParser;
+    use super::*;
+
+    fn events(body: &[u8]) -> Vec<Value> {
+        let mut parser = SseParser::new();
+        parser
+            .feed(body)
+            .iter()
+            .filter_map(|event| event.json())
+            .collect()
+    }
+
+    #[test]
+    fn down_marks_expire_and_clear() {
+        mark_down("test-maint-a", Duration::from_secs(30));
+        let remaining = down_remaining("test-maint-a").unwrap();
+        assert!(remaining > Duration::from_secs(28));
+        assert!(down_routes().iter().any(|(r, _)| r == "test-maint-a"));
+        mark_up("test-maint-a");
+        assert!(down_remaining("test-maint-a").is_none());
+
+        mark_down("test-maint-b", Duration::ZERO);
+        assert!(down_remaining("test-maint-b").is_none());
+    }
+
+    #[test]
+    fn respond_fills_placeholders_and_retry_after() {
+        mark_down("test-maint-c", Duration::from_secs(90));
+        let outcome = respond(
+            &MaintenanceSettings::default(),
+            TransformTarget::Claude,
+            "test-maint-c",
+            "/v1/messages",
+            None,
+            br#"{"model":"claude-sonnet-4"}"#,
+        );
+        mark_up("test-maint-c");
+        let ProcessOutcome::LocalResponse {
+            source,
+            headers,
+            body: LocalBody::Full(body),
+            ..
+        } = outcome
+        else {
+            panic!("应为本地响应");
+        };
+        assert_eq!(source, "maintenance/test-maint-c");
+        let retry: u64 = headers["retry-after"].to_str().unwrap().parse().unwrap();
+        assert!((88..=90).contains(&retry));
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["model"], "claude-sonnet-4");
+        let text = body["content"][0]["text"].as_str().unwrap();
+        assert!(text.contains("test-maint-c") && text.contains(&retry.to_string()));
+    }
+
+    #[test]
+    fn claude_stream_is_complete_event_sequence() {
+        let (ct, body) = assistant_reply(
+            TransformTarget::Claude,
+            "t",
+            "/v1/messages",
+            None,
+            br#"{"stream":true}"#,
+            "hi",
+        );
+        assert_eq!(ct, "text/event-stream");
+        let types: Vec<_> = events(&body)
+            .iter()
+            .map(|e| e["type"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop"
+            ]
+        );
+    }
+
+    #[test]
+    fn openai_chat_and_responses_shapes() {
+        let (_, body) = assistant_reply(
+            TransformTarget::Codex,
+            "t",
+            "/v1/chat/completions",
+            None,
+            br#"{"messages":[]}"#,
+            "hi",
+        );
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["choices"][0]["message"]["content"], "hi");
+
+        let (ct, body) = assistant_reply(
+            TransformTarget::Codex,
+            "t",
+            "/v1/responses",
+            None,
+            br#"{"input":"x","stream":true}"#,
+            "hi",
+        );
+        assert_eq!(ct, "text/event-stream");
+        let events = events(&body);
+        assert_eq!(events.len(), 8);
+        assert_eq!(events[3]["delta"], "hi");
+        assert_eq!(
+            events[7]["response"]["output"][0]["content"][0]["text"],
+            "hi"
+        );
+    }
+
+    #[test]
+    fn gemini_stream_follows_alt_query() {
+        let path = "/v1beta/models/gemini-2.5-pro:streamGenerateContent";
+        let (ct, body) = assistant_reply(
+            TransformTarget::Gemini,
+            "t",
+            path,
+            Some("alt=sse"),
+            b"",
+            "hi",
+        );
+        assert_eq!(ct, "text/event-stream");
+        assert_eq!(
+            events(&body)[0]["candidates"][0]["content"]["parts"][0]["text"],
+            "hi"
+        );
+
+        let (ct, body) = assistant_reply(TransformTarget::Gemini, "t", path, None, b"", "hi");
+        assert_eq!(ct, "application/json");
+        assert!(serde_json::from_slice::<Value>(&body).unwrap().is_array());
+    }
+}
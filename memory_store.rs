@@ -0,0 +1,246 @@
+// 长期记忆注入
+//
+// 本地维护用户整理的事实/片段（~/.duckcoding/amp/memory.json），
+// 以紧凑 system 块注入每个 Claude 路由请求：
+// - 按项目作用域筛选：项目条目优先，其次全局条目
+// - 按 token 预算截断（粗略估算，宁少勿多）
+// - 增删改查由管理接口调用本模块函数完成
+
+use super::storage;
+use super::transform_middleware::estimate_tokens;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Mutex;
+
+/// 串行化读改写，避免并发管理操作互相覆盖
+static STORE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+const MEMORY_BLOCK_HEADER: &str = "Persistent notes from the user (apply when relevant):";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemorySettings {
+    pub enabled: bool,
+    /// 注入块的 token 预算
+    pub token_budget: usize,
+    /// 携带项目标识的请求头
+    pub project_header: String,
+}
+
+impl Default for MemorySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            token_budget: 500,
+            project_header: "x-dc-project".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryEntry {
+    pub id: String,
+    pub text: String,
+    /// None 表示全局条目
+    pub project: Option<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    pub created_ms: u64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct MemoryFile {
+    entries: Vec<MemoryEntry>,
+}
+
+const NAMESPACE: &str = "amp";
+const KEY: &str = "memory.json";
+
+fn load() -> Result<MemoryFile> {
+    storage::get_json(NAMESPACE, KEY)
+        .map(Option::unwrap_or_default)
+        .map_err(|e| anyhow!("读取记忆库失败: {}", e))
+}
+
+fn save(file: &MemoryFile) -> Result<()> {
+    storage::put_json(NAMESPACE, KEY, file).map_err(|e| anyhow!("写入记忆库失败: {}", e))
+}
+
+/// 管理接口：列出条目（project 为 None 时返回全部）
+pub fn list(project: Option<&str>) -> Result<Vec<MemoryEntry>> {
+    let _guard = STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(load()?
+        .entries
+        .into_iter()
+        .filter(|e| project.is_none() || e.project.as_deref() == project)
+        .collect())
+}
+
+/// 管理接口：新增条目
+pub fn add(text: &str, project: Option<&str>) -> Result<MemoryEntry> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(anyhow!("记忆内容不能为空"));
+    }
+    let _guard = STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = load()?;
+    let entry = MemoryEntry {
+        id: super::determinism::ids().new_uuid().to_string(),
+        text: text.to_string(),
+        project: project.map(|p| p.to_string()),
+        enabled: true,
+        created_ms: super::audit_log::now_ms(),
+    };
+    file.entries.push(entry.clone());
+    save(&file)?;
+    Ok(entry)
+}
+
+/// 管理接口：修改条目内容或启用状态
+pub fn update(id: &str, text: Option<&str>, enabled: Option<bool>) -> Result<MemoryEntry> {
+    let _guard = STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = load()?;
+    let entry = file
+        .entries
+        .iter_mut()
+        .find(|e| e.id == id)
+        .ok_or_else(|| anyhow!("记忆条目不存在: {}", id))?;
+    if let Some(t) = text {
+        entry.text = t.trim().to_string();
+    }
+    if let Some(en) = enabled {
+        entry.enabled = en;
+    }
+    let updated = entry.clone();
+    save(&file)?;
+    Ok(updated)
+}
+
+/// 管理接口：删除条目
+pub fn remove(id: &str) -> Result<()> {
+    let _guard = STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = load()?;
+    let before = file.entries.len();
+    file.entries.retain(|e| e.id != id);
+    if file.entries.len() == before {
+        return Err(anyhow!("记忆条目不存在: {}", id));
+    }
+    save(&file)
+}
+
+/// 按作用域与预算挑选条目，拼接为注入文本
+fn build_block(entries: &[MemoryEntry], project: Option<&str>, budget: usize) -> Option<String> {
+    let scoped = entries
+        .iter()
+        .filter(|e| e.enabled && project.is_some() && e.project.as_deref() == project);
+    let global = entries.iter().filter(|e| e.enabled && e.project.is_none());
+
+    let mut used = estimate_tokens(MEMORY_BLOCK_HEADER);
+    let mut lines = Vec::new();
+    for entry in scoped.chain(global) {
+        let line = format!("- {}", entry.text.replace('\n', " "));
+        let cost = estimate_tokens(&line);
+        if used + cost > budget {
+            continue;
+        }
+        used += cost;
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    Some(format!("{}\n{}", MEMORY_BLOCK_HEADER, lines.join("\n")))
+}
+
+/// 向 Claude 请求体 system 末尾注入记忆块，返回 None 表示未注入
+pub(crate) fn inject(
+    settings: &MemorySettings,
+    project: Option<&str>,
+    body: &[u8],
+) -> Option<Vec<u8>> {
+    if !settings.enabled {
+        return None;
+    }
+    let entries = {
+        let _guard = STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        match load() {
+            Ok(f) => f.entries,
+            Err(e) => {
+                tracing::warn!("{}，跳过记忆注入", e);
+                return None;
+            }
+        }
+    };
+    let block = build_block(&entries, project, settings.token_budget)?;
+
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    match json.get_mut("system") {
+        Some(Value::Array(items)) => items.push(json!({ "type": "text", "text": block })),
+        Some(Value::String(s)) => {
+            s.push_str("\n\n");
+            s.push_str(&block);
+        }
+        _ => json["system"] = json!([{ "type": "text", "text": block }]),
+    }
+    tracing::debug!(
+        "注入记忆块: project={:?}, ~{} tokens",
+        project,
+        estimate_tokens(&block)
+    );
+    serde_json::to_vec(&json).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(text: &str, project: Option<&str>, enabled: bool) -> MemoryEntry {
+        MemoryEntry {
+            id: text.to_string(),
+            text: text.to_string(),
+            project: project.map(str::to_string),
+            enabled,
+            created_ms: 0,
+        }
+    }
+
+    #[test]
+    fn project_entries_come_first() {
+        let entries = vec![
+            entry("global rule", None, true),
+            entry("other project", Some("web"), true),
+            entry("api rule", Some("api"), true),
+            entry("disabled", None, false),
+        ];
+        let block = build_block(&entries, Some("api"), 500).unwrap();
+        assert_eq!(
+            block,
+            format!("{}\n- api rule\n- global rule", MEMORY_BLOCK_HEADER)
+        );
+
+        let block = build_block(&entries, None, 500).unwrap();
+        assert!(!block.contains("api rule") && block.contains("global rule"));
+    }
+
+    #[test]
+    fn budget_skips_entries_that_do_not_fit() {
+        let long = "word ".repeat(400);
+        let entries = vec![entry(&long, None, true), entry("multi\nline", None, true)];
+        let block = build_block(&entries, None, 60).unwrap();
+        assert!(block.ends_with("\n- multi line"));
+        assert!(!block.contains("word"));
+        assert!(build_block(&entries, None, 1).is_none());
+        assert!(build_block(&[], None, 500).is_none());
+    }
+
+    #[test]
+    fn disabled_settings_skip_injection() {
+        assert!(inject(&MemorySettings::default(), None, b"{}").is_none());
+    }
+}
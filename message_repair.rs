@@ -0,0 +1,229 @@
+// Anthropic 消息顺序修复
+//
+// AMP 编辑 / 回退历史后可能产生后端拒绝的会话结构：
+// - 连续同角色消息 → 合并（user 消息中 tool_result 块排在最前）
+// - 找不到对应 tool_use 的 tool_result、没有对应结果的 tool_use → 删除
+// - 删除后为空的消息 → 移除
+// - 首条消息不是 user → 插入占位 user 消息
+// 每项修复都会记录日志。
+
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+const PLACEHOLDER_USER_TEXT: &str = "(continue)";
+
+/// 修复 messages，返回修复说明（为空表示未改动）
+pub(crate) fn repair(json: &mut Value) -> Vec<String> {
+    let Some(messages) = json.get_mut("messages").and_then(|m| m.as_array_mut()) else {
+        return Vec::new();
+    };
+    let mut repairs = Vec::new();
+
+    merge_consecutive(messages, &mut repairs);
+    drop_orphaned_tool_blocks(messages, &mut repairs);
+
+    let before = messages.len();
+    messages.retain(|m| !is_empty_content(&m["content"]));
+    if messages.len() != before {
+        repairs.push(format!("移除 {} 条空消息", before - messages.len()));
+        merge_consecutive(messages, &mut repairs);
+    }
+
+    if messages
+        .first()
+        .is_some_and(|m| m["role"].as_str() != Some("user"))
+    {
+        messages.insert(
+            0,
+            json!({ "role": "user", "content": [{ "type": "text", "text": PLACEHOLDER_USER_TEXT }] }),
+        );
+        repairs.push("首条消息不是 user，插入占位消息".to_string());
+    }
+
+    for r in &repairs {
+        tracing::info!("消息顺序修复: {}", r);
+    }
+    repairs
+}
+
+fn content_blocks(content: &Value) -> Vec<Value> {
+    match content {
+        Value::String(s) => vec![json!({ "type": "text", "text": s })],
+        Value::Array(items) => items.clone(),
+        _ => Vec::new(),
+    }
+}
+
+fn is_empty_content(content: &Value) -> bool {
+    match content {
+        Value::String(s) => s.is_empty(),
+        Value::Array(items) => items.is_empty(),
+        _ => true,
+    }
+}
+
+fn merge_consecutive(messages: &mut Vec<Value>, repairs: &mut Vec<String>) {
+    let mut merged: Vec<Value> = Vec::with_capacity(messages.len());
+    for msg in messages.drain(..) {
+        let Some(prev) = merged.last_mut() else {
+            merged.push(msg);
+            continue;
+        };
+        if prev["role"] != msg["role"] {
+            merged.push(msg);
+            continue;
+        }
+
+        let mut blocks = content_blocks(&prev["content"]);
+        blocks.extend(content_blocks(&msg["content"]));
+        if prev["role"].as_str() == Some("user") {
+            // tool_result 必须位于 user 消息开头（稳定排序保持原有相对顺序）
+            blocks.sort_by_key(|b| b["type"].as_str() != Some("tool_result"));
+        }
+        repairs.push(format!(
+            "合并连续的 {} 消息",
+            prev["role"].as_str().unwrap_or("?")
+        ));
+        prev["content"] = Value::Array(blocks);
+    }
+    *messages = merged;
+}
+
+fn block_ids<'a>(msg: Option<&'a Value>, block_type: &str, id_field: &str) -> HashSet<&'a str> {
+    msg.and_then(|m| m["content"].as_array())
+        .into_iter()
+        .flatten()
+        .filter(|b| b["type"].as_str() == Some(block_type))
+        .filter_map(|b| b[id_field].as_str())
+        .collect()
+}
+
+fn drop_orphaned_tool_blocks(messages: &mut [Value], repairs: &mut Vec<String>) {
+    // 先收集每条消息需要删除的块 ID，再统一删除，避免借用冲突
+    let mut removals: Vec<(usize, &'static str, &'static str, HashSet<String>)> = Vec::new();
+
+    for i in 0..messages.len() {
+        match messages[i]["role"].as_str() {
+            Some("user") => {
+                let available = if i > 0 && messages[i - 1]["role"].as_str() == Some("assistant") {
+                    block_ids(messages.get(i - 1), "tool_use", "id")
+                } else {
+                    HashSet::new()
+                };
+                let orphans: HashSet<String> =
+                    block_ids(messages.get(i), "tool_result", "tool_use_id")
+                        .into_iter()
+                        .filter(|id| !available.contains(id))
+                        .map(|id| id.to_string())
+                        .collect();
+                if !orphans.is_empty() {
+                    removals.push((i, "tool_result", "tool_use_id", orphans));
+                }
+            }
+            Some("assistant") => {
+                let answered = block_ids(messages.get(i + 1), "tool_result", "tool_use_id");
+                let orphans: HashSet<String> = block_ids(messages.get(i), "tool_use", "id")
+                    .into_iter()
+                    .filter(|id| !answered.contains(id))
+                    .map(|id| id.to_string())
+                    .collect();
+                if !orphans.is_empty() {
+                    removals.push((i, "tool_use", "id", orphans));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (i, block_type, id_field, ids) in removals {
+        if let Some(content) = messages[i]["content"].as_array_mut() {
+            content.retain(|b| {
+                !(b["type"].as_str() == Some(block_type)
+                    && b[id_field].as_str().is_some_and(|id| ids.contains(id)))
+            });
+        }
+        repairs.push(format!(
+            "消息 #{} 删除 {} 个孤立的 {}",
+            i,
+            ids.len(),
+            block_type
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool_use(id: &str) -> Value {
+        json!({ "type": "tool_use", "id": id, "name": "Read", "input": {} })
+    }
+
+    fn tool_result(id: &str) -> Value {
+        json!({ "type": "tool_result", "tool_use_id": id, "content": "ok" })
+    }
+
+    #[test]
+    fn well_formed_conversation_is_untouched() {
+        let mut body = json!({ "messages": [
+            { "role": "user", "content": "read a.rs" },
+            { "role": "assistant", "content": [tool_use("t1")] },
+            { "role": "user", "content": [tool_result("t1")] }
+        ]});
+        let before = body.clone();
+        assert!(repair(&mut body).is_empty());
+        assert_eq!(body, before);
+        assert!(repair(&mut json!({})).is_empty());
+    }
+
+    #[test]
+    fn merges_user_messages_with_tool_results_first() {
+        let mut body = json!({ "messages": [
+            { "role": "user", "content": "go" },
+            { "role": "assistant", "content": [tool_use("t1")] },
+            { "role": "user", "content": "also this" },
+            { "role": "user", "content": [tool_result("t1")] }
+        ]});
+        let repairs = repair(&mut body);
+        assert_eq!(repairs, ["合并连续的 user 消息"]);
+        let last = &body["messages"][2]["content"];
+        assert_eq!(last[0]["type"], "tool_result");
+        assert_eq!(last[1]["text"], "also this");
+    }
+
+    #[test]
+    fn drops_orphans_and_empty_messages() {
+        let mut body = json!({ "messages": [
+            { "role": "user", "content": "go" },
+            { "role": "assistant", "content": [tool_use("t1"), tool_use("t2")] },
+            { "role": "user", "content": [tool_result("t1")] },
+            { "role": "assistant", "content": [{ "type": "text", "text": "done" }] },
+            { "role": "user", "content": [tool_result("stale")] },
+            { "role": "user", "content": "next" }
+        ]});
+        repair(&mut body);
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[1]["content"], json!([tool_use("t1")]));
+        assert_eq!(
+            messages[4]["content"],
+            json!([{ "type": "text", "text": "next" }])
+        );
+    }
+
+    #[test]
+    fn leading_assistant_gets_placeholder_user() {
+        let mut body = json!({ "messages": [
+            { "role": "assistant", "content": "hi" },
+            { "role": "user", "content": "" },
+            { "role": "assistant", "content": "again" }
+        ]});
+        let repairs = repair(&mut body);
+        assert!(repairs.iter().any(|r| r.contains("空消息")));
+        assert!(repairs.iter().any(|r| r.contains("占位")));
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["content"][0]["text"], PLACEHOLDER_USER_TEXT);
+        assert_eq!(messages[1]["content"].as_array().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,287 @@
+// Prometheus 指标
+//
+// 进程内累计，render() 输出 Prometheus 文本格式，由观测面板 /metrics 暴露（代理也可自行挂载）：
+// - dc_requests_total{route,profile}：转发的 LLM / AMP 请求数
+// - dc_upstream_responses_total{route,profile,status}：上游响应状态码（含重试与故障转移中的失败响应）
+// - dc_upstream_latency_seconds{route,profile}：等待上游响应头的耗时直方图
+// - dc_bytes_sent_total / dc_bytes_received_total{kind,name}：收发字节（Profile 槽位 / 本地工具）
+// - dc_tool_invocations_total{tool,outcome}：本地工具（搜索 / 网页提取）调用次数
+// route 即 ApiType 路由名（claude / codex / gemini / azure / amp）。
+
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 延迟直方图桶上限（秒）
+const LATENCY_BUCKETS: [f64; 11] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0];
+
+#[derive(Default)]
+struct Histogram {
+    /// 与 LATENCY_BUCKETS 对应的非累计计数
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct Registry {
+    requests: BTreeMap<(String, String), u64>,
+    statuses: BTreeMap<(String, String, u16), u64>,
+    latency: BTreeMap<(String, String), Histogram>,
+    bytes_sent: BTreeMap<(String, String), u64>,
+    bytes_received: BTreeMap<(String, String), u64>,
+    tools: BTreeMap<(String, String), u64>,
+}
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(|| Mutex::new(Registry::default()));
+
+fn with_registry(f: impl FnOnce(&mut Registry)) {
+    f(&mut REGISTRY.lock().unwrap_or_else(|e| e.into_inner()));
+}
+
+pub(crate) fn record_request(route: &str, profile: &str) {
+    with_registry(|r| {
+        *r.requests
+            .entry((route.into(), profile.into()))
+            .or_default() += 1
+    });
+}
+
+/// 上游响应头到达：状态码与耗时
+pub(crate) fn record_upstream(route: &str, profile: &str, status: u16, elapsed: Duration) {
+    with_registry(|r| {
+        *r.statuses
+            .entry((route.into(), profile.into(), status))
+            .or_default() += 1;
+        let h = r.latency.entry((route.into(), profile.into())).or_default();
+        let secs = elapsed.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|b| secs <= *b) {
+            h.buckets[i] += 1;
+        }
+        h.count += 1;
+        h.sum += secs;
+    });
+}
+
+/// 收发字节（kind 为 profile / tool）
+pub(crate) fn record_bytes(kind: &str, name: &str, sent: u64, received: u64) {
+    with_registry(|r| {
+        let key = (kind.to_string(), name.to_string());
+        *r.bytes_sent.entry(key.clone()).or_default() += sent;
+        *r.bytes_received.entry(key).or_default() += received;
+    });
+}
+
+pub(crate) fn record_tool(tool: &str, ok: bool) {
+    let outcome = if ok { "ok" } else { "error" };
+    with_registry(|r| *r.tools.entry((tool.into(), outcome.into())).or_default() += 1);
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn pair_counter(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    labels: (&str, &str),
+    values: &BTreeMap<(String, String), u64>,
+) {
+    header(out, name, "counter", help);
+    for ((a, b), v) in values {
+        let _ = writeln!(
+            out,
+            "{}{{{}=\"{}\",{}=\"{}\"}} {}",
+            name,
+            labels.0,
+            escape(a),
+            labels.1,
+            escape(b),
+            v
+        );
+    }
+}
+
+/// Prometheus 文本格式（text/plain; version=0.0.4）
+pub fn render() -> String {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = String::new();
+
+    pair_counter(
+        &mut out,
+        "dc_requests_total",
+        "转发的请求数",
+        ("route", "profile"),
+        &registry.requests,
+    );
+
+    header(
+        &mut out,
+        "dc_upstream_responses_total",
+        "counter",
+        "上游响应状态码",
+    );
+    for ((route, profile, status), v) in &registry.statuses {
+        let _ = writeln!(
+            out,
+            "dc_upstream_responses_total{{route=\"{}\",profile=\"{}\",status=\"{}\"}} {}",
+            escape(route),
+            escape(profile),
+            status,
+            v
+        );
+    }
+
+    header(
+        &mut out,
+        "dc_upstream_latency_seconds",
+        "histogram",
+        "等待上游响应头的耗时",
+    );
+    for ((route, profile), h) in &registry.latency {
+        let labels = format!(
+            "route=\"{}\",profile=\"{}\"",
+            escape(route),
+            escape(profile)
+        );
+        let mut cumulative = 0;
+        for (bound, n) in LATENCY_BUCKETS.iter().zip(h.buckets) {
+            cumulative += n;
+            let _ = writeln!(
+                out,
+                "dc_upstream_latency_seconds_bucket{{{},le=\"{}\"}} {}",
+                labels, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "dc_upstream_latency_seconds_bucket{{{},le=\"+Inf\"}} {}",
+            labels, h.count
+        );
+        let _ = writeln!(
+            out,
+            "dc_upstream_latency_seconds_sum{{{}}} {}",
+            labels, h.sum
+        );
+        let _ = writeln!(
+            out,
+            "dc_upstream_latency_seconds_count{{{}}} {}",
+            labels, h.count
+        );
+    }
+
+    pair_counter(
+        &mut out,
+        "dc_bytes_sent_total",
+        "发送字节数",
+        ("kind", "name"),
+        &registry.bytes_sent,
+    );
+    pair_counter(
+        &mut out,
+        "dc_bytes_received_total",
+        "接收字节数",
+        ("kind", "name"),
+        &registry.bytes_received,
+    );
+    pair_counter(
+        &mut out,
+        "dc_tool_invocations_total",
+        "本地工具调用次数",
+        ("tool", "outcome"),
+        &registry.tools,
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+        text.lines()
+            .find(|l| l.starts_with(prefix))
+            .and_then(|l| l.rsplit(' ').next())
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        record_upstream("test-hist", "p", 200, Duration::from_millis(50));
+        record_upstream("test-hist", "p", 502, Duration::from_millis(700));
+        record_upstream("test-hist", "p", 200, Duration::from_secs(300));
+        let out = render();
+        let bucket = |le: &str| {
+            line(
+                &out,
+                &format!(
+                    "dc_upstream_latency_seconds_bucket{{route=\"test-hist\",profile=\"p\",le=\"{}\"}}",
+                    le
+                ),
+            )
+        };
+        assert_eq!(bucket("0.1"), Some("1"));
+        assert_eq!(bucket("0.5"), Some("1"));
+        assert_eq!(bucket("1"), Some("2"));
+        assert_eq!(bucket("120"), Some("2"));
+        assert_eq!(bucket("+Inf"), Some("3"));
+        assert_eq!(
+            line(
+                &out,
+                "dc_upstream_responses_total{route=\"test-hist\",profile=\"p\",status=\"200\"}"
+            ),
+            Some("2")
+        );
+    }
+
+    #[test]
+    fn counters_accumulate_with_escaped_labels() {
+        record_request("test-ctr", "a\"b");
+        record_request("test-ctr", "a\"b");
+        record_bytes("tool", "test-ctr", 10, 5);
+        record_bytes("tool", "test-ctr", 1, 1);
+        record_tool("test-ctr", false);
+        let out = render();
+        assert_eq!(
+            line(
+                &out,
+                "dc_requests_total{route=\"test-ctr\",profile=\"a\\\"b\"}"
+            ),
+            Some("2")
+        );
+        assert_eq!(
+            line(&out, "dc_bytes_sent_total{kind=\"tool\",name=\"test-ctr\"}"),
+            Some("11")
+        );
+        assert_eq!(
+            line(
+                &out,
+                "dc_bytes_received_total{kind=\"tool\",name=\"test-ctr\"}"
+            ),
+            Some("6")
+        );
+        assert_eq!(
+            line(
+                &out,
+                "dc_tool_invocations_total{tool=\"test-ctr\",outcome=\"error\"}"
+            ),
+            Some("1")
+        );
+        assert!(out.contains("# TYPE dc_upstream_latency_seconds histogram"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("a\\b\"c\nd"), "a\\\\b\\\"c\\nd");
+    }
+}
@@ -0,0 +1,246 @@
+// 模拟上游（MockUpstream）
+//
+// Profile 开启 mock 后，该槽位的 LLM 请求（claude / codex / gemini / azure）不再发往真实服务商，
+// 而是按路由协议在本地构造响应（流式请求返回对应协议的 SSE 事件序列），用于：
+// - AmpHeadersProcessor 的集成测试（无需网络与真实 Key）
+// - 演示 AMP，不消耗 token
+// 响应来源按顺序：
+// 1. script 中 when 命中请求体（子串匹配）的第一步
+// 2. script 中未设置 when 的步骤，按请求次数轮流使用
+// 3. reply 固定文本
+// 步骤可给出 text（按协议包装）或 raw（原样返回，如录制的 SSE 流，可含工具调用）。
+// 模拟响应以本地响应（来源 mock/<route>）返回，不计入用量、预算与出站流量；
+// AMP 内部请求（线程、账号等）仍转发到 ampcode.com。
+
+use super::maintenance;
+use super::outcome::ProcessOutcome;
+use super::routing_rules;
+use super::transform_middleware::TransformTarget;
+use hyper::HeaderMap as HyperHeaderMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MockUpstreamSettings {
+    pub enabled: bool,
+    /// 没有脚本步骤可用时的固定回复，支持占位符 {route} 与 {model}
+    pub reply: String,
+    /// 脚本步骤
+    pub script: Vec<MockStep>,
+}
+
+impl Default for MockUpstreamSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            reply:
+                "[模拟响应] 这是 {route} 路由的模拟回复（模型 {model}），请求未发送到真实服务商。"
+                    .to_string(),
+            script: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MockStep {
+    /// 请求体包含该子串时使用本步骤；未设置时参与轮流
+    pub when: Option<String>,
+    /// assistant 文本，按路由协议包装为完整响应
+    pub text: Option<String>,
+    /// 原样返回的响应体，优先于 text
+    pub raw: Option<String>,
+    /// raw 的 content-type，默认 SSE 格式时 text/event-stream，否则 application/json
+    pub content_type: Option<String>,
+}
+
+/// 路由 → 已使用的轮流步骤数
+static TURNS: Lazy<Mutex<HashMap<String, usize>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 构造模拟响应（本地响应）
+pub(crate) fn respond(
+    settings: &MockUpstreamSettings,
+    target: TransformTarget,
+    route: &str,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+) -> ProcessOutcome {
+    let (content_type, response) = match select(settings, route, body) {
+        Some(MockStep {
+            raw: Some(raw),
+            content_type,
+            ..
+        }) => {
+            let content_type =
+                content_type.unwrap_or_else(|| default_content_type(&raw).to_string());
+            (content_type, raw.into_bytes())
+        }
+        Some(MockStep {
+            text: Some(text), ..
+        }) => reply(target, path, query, body, &text),
+        _ => {
+            let model =
+                routing_rules::request_model(path, body).unwrap_or_else(|| "unknown".to_string());
+            let text = settings
+                .reply
+                .replace("{route}", route)
+                .replace("{model}", &model);
+            reply(target, path, query, body, &text)
+        }
+    };
+
+    let mut headers = HyperHeaderMap::new();
+    if let Ok(value) = content_type.parse() {
+        headers.insert("content-type", value);
+    }
+    ProcessOutcome::local(&format!("mock/{}", route), headers, response)
+}
+
+/// 重置轮流计数（集成测试在用例之间调用）
+pub(crate) fn reset() {
+    TURNS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+fn select(settings: &MockUpstreamSettings, route: &str, body: &[u8]) -> Option<MockStep> {
+    let request = String::from_utf8_lossy(body);
+    if let Some(step) = settings
+        .script
+        .iter()
+        .find(|s| s.when.as_deref().is_some_and(|w| request.contains(w)))
+    {
+        return Some(step.clone());
+    }
+    let rotation: Vec<&MockStep> = settings
+        .script
+        .iter()
+        .filter(|s| s.when.is_none())
+        .collect();
+    if rotation.is_empty() {
+        return None;
+    }
+    let mut turns = TURNS.lock().unwrap_or_else(|e| e.into_inner());
+    let turn = turns.entry(route.to_string()).or_insert(0);
+    let step = rotation[*turn % rotation.len()].clone();
+    *turn += 1;
+    Some(step)
+}
+
+fn reply(
+    target: TransformTarget,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+    text: &str,
+) -> (String, Vec<u8>) {
+    let (content_type, response) =
+        maintenance::assistant_reply(target, "mock", path, query, body, text);
+    (content_type.to_string(), response)
+}
+
+fn default_content_type(raw: &str) -> &'static str {
+    let head = raw.trim_start();
+    if head.starts_with("event:") || head.starts_with("data:") {
+        "text/event-stream"
+    } else {
+        "application/json"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::outcome::LocalBody;
+    use super::*;
+    use serde_json::Value;
+
+    fn step(when: Option<&str>, text: &str) -> MockStep {
+        MockStep {
+            when: when.map(str::to_string),
+            text: Some(text.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn local(outcome: ProcessOutcome) -> (String, String, Vec<u8>) {
+        let ProcessOutcome::LocalResponse {
+            source,
+            headers,
+            body: LocalBody::Full(body),
+            ..
+        } = outcome
+        else {
+            panic!("应为本地响应");
+        };
+        let content_type = headers["content-type"].to_str().unwrap().to_string();
+        (source, content_type, body.to_vec())
+    }
+
+    #[test]
+    fn matching_step_wins_then_rotation() {
+        let settings = MockUpstreamSettings {
+            enabled: true,
+            script: vec![
+                step(None, "one"),
+                step(Some("weather"), "sunny"),
+                step(None, "two"),
+            ],
+            ..Default::default()
+        };
+        let texts: Vec<_> = ["a", "what's the weather", "b", "c"]
+            .iter()
+            .map(|req| {
+                select(&settings, "test-mock-rot", req.as_bytes())
+                    .unwrap()
+                    .text
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(texts, ["one", "sunny", "two", "one"]);
+        assert!(select(&MockUpstreamSettings::default(), "test-mock-none", b"").is_none());
+    }
+
+    #[test]
+    fn default_reply_fills_placeholders() {
+        let (source, content_type, body) = local(respond(
+            &MockUpstreamSettings::default(),
+            TransformTarget::Claude,
+            "test-mock-reply",
+            "/v1/messages",
+            None,
+            br#"{"model":"claude-sonnet-4"}"#,
+        ));
+        assert_eq!(source, "mock/test-mock-reply");
+        assert_eq!(content_type, "application/json");
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let text = body["content"][0]["text"].as_str().unwrap();
+        assert!(text.contains("test-mock-reply") && text.contains("claude-sonnet-4"));
+    }
+
+    #[test]
+    fn raw_step_is_returned_verbatim() {
+        let raw = "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n";
+        let settings = MockUpstreamSettings {
+            enabled: true,
+            script: vec![MockStep {
+                raw: Some(raw.to_string()),
+                text: Some("ignored".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let (_, content_type, body) = local(respond(
+            &settings,
+            TransformTarget::Claude,
+            "test-mock-raw",
+            "/v1/messages",
+            None,
+            b"{}",
+        ));
+        assert_eq!(content_type, "text/event-stream");
+        assert_eq!(body, raw.as_bytes());
+        assert_eq!(default_content_type(" {\"id\":1}"), "application/json");
+    }
+}
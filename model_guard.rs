@@ -0,0 +1,125 @@
+// 昂贵模型保护（只读 / 测试模式）
+//
+// 为 Profile 设置允许的最高费用档位，请求的模型超过该档位时在本地拒绝，
+// 防止实验配置意外跑在 opus 等昂贵模型上。请求带 override_header（值为 1 / true）时放行。
+// 档位先查 tiers 自定义映射（精确匹配或以 * 结尾的最长前缀），未命中按模型名内置规则判断。
+// 无法识别模型名的请求不拦截。
+
+use anyhow::{anyhow, Result};
+use hyper::HeaderMap as HyperHeaderMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// 费用档位（按从低到高排序）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CostTier {
+    Economy,
+    #[default]
+    Standard,
+    Premium,
+}
+
+impl fmt::Display for CostTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CostTier::Economy => "economy",
+            CostTier::Standard => "standard",
+            CostTier::Premium => "premium",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelGuardSettings {
+    pub enabled: bool,
+    /// 允许的最高档位
+    pub max_tier: CostTier,
+    /// 模型名（或以 * 结尾的前缀）→ 档位，覆盖内置规则
+    pub tiers: HashMap<String, CostTier>,
+    /// 放行请求头（x-dc-* 控制头，转发前剥离）
+    pub override_header: String,
+}
+
+impl Default for ModelGuardSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_tier: CostTier::Standard,
+            tiers: HashMap::new(),
+            override_header: "x-dc-allow-expensive".to_string(),
+        }
+    }
+}
+
+/// 内置档位规则（按模型名关键字）
+const PREMIUM_MARKERS: &[&str] = &[
+    "opus",
+    "o1-pro",
+    "o3-pro",
+    "gpt-4.5",
+    "gpt-5-pro",
+    "deep-research",
+];
+const ECONOMY_MARKERS: &[&str] = &["haiku", "mini", "nano", "flash", "lite"];
+
+pub(crate) fn tier_of(settings: &ModelGuardSettings, model: &str) -> CostTier {
+    let lower = model.to_lowercase();
+    // 精确匹配优先，其次最长前缀
+    let custom = settings
+        .tiers
+        .iter()
+        .filter_map(|(pattern, tier)| {
+            let pattern = pattern.to_lowercase();
+            if pattern == lower {
+                return Some((usize::MAX, *tier));
+            }
+            let prefix = pattern.strip_suffix('*')?;
+            lower.starts_with(prefix).then_some((prefix.len(), *tier))
+        })
+        .max_by_key(|(len, _)| *len);
+    if let Some((_, tier)) = custom {
+        return tier;
+    }
+    if PREMIUM_MARKERS.iter().any(|m| lower.contains(m)) {
+        CostTier::Premium
+    } else if ECONOMY_MARKERS.iter().any(|m| lower.contains(m)) {
+        CostTier::Economy
+    } else {
+        CostTier::Standard
+    }
+}
+
+/// 超过允许档位且未带放行头时返回错误
+pub(crate) fn check(
+    settings: &ModelGuardSettings,
+    slot: &str,
+    model: Option<&str>,
+    headers: &HyperHeaderMap,
+) -> Result<()> {
+    let Some(model) = model.filter(|_| settings.enabled) else {
+        return Ok(());
+    };
+    let tier = tier_of(settings, model);
+    if tier <= settings.max_tier {
+        return Ok(());
+    }
+    let overridden = headers
+        .get(settings.override_header.as_str())
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true"));
+    if overridden {
+        tracing::warn!("{} 模型保护已由请求头放行: {}（{}）", slot, model, tier);
+        return Ok(());
+    }
+    Err(anyhow!(
+        "Profile {} 处于模型保护模式：模型 {} 属于 {} 档，超过允许的 {} 档；如确需使用，请添加请求头 {}: 1 或调整 model_guard 配置",
+        slot,
+        model,
+        tier,
+        settings.max_tier,
+        settings.override_header
+    ))
+}
@@ -0,0 +1,690 @@
+// Anthropic Messages ↔ OpenAI Chat Completions 协议转换
+//
+// Claude Profile 的 upstream_protocol 为 openai_chat 时（DeepSeek / OpenRouter 等后端），
+// Claude 路由请求转换为 /v1/chat/completions：
+// - system → system 消息；tool_use → assistant.tool_calls；tool_result → tool 消息
+// - tools / tool_choice / stop_sequences / metadata.user_id 对应映射
+// - thinking 块不转发（OpenAI 兼容后端无对应字段）
+// 响应侧由代理按同一配置调用 translate_response / OpenAiStreamTranslator 还原为 Anthropic 格式。
+
+use super::determinism;
+use super::sse::{self, SseEvent, SseParser};
+use super::ProcessedRequest;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use hyper::HeaderMap as HyperHeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamProtocol {
+    /// 与路由协议一致，原样转发
+    #[default]
+    Native,
+    /// 转换为 OpenAI chat/completions（Claude 路由见本模块，Codex 路由见 responses_downgrade）
+    OpenaiChat,
+}
+
+/// 构造转发到 OpenAI 兼容后端 chat/completions 的请求（body 为已转换的请求体）
+pub(crate) fn build_request(
+    base_url: &str,
+    api_key: &str,
+    translated: Vec<u8>,
+) -> Result<ProcessedRequest> {
+    let base = base_url.trim_end_matches('/');
+    let target_url = if base.ends_with("/v1") {
+        format!("{}/chat/completions", base)
+    } else {
+        format!("{}/v1/chat/completions", base)
+    };
+
+    let mut headers = HyperHeaderMap::new();
+    headers.insert("content-type", "application/json".parse().unwrap());
+    headers.insert(
+        "authorization",
+        format!("Bearer {}", api_key)
+            .parse()
+            .map_err(|_| anyhow!("API Key 含非法字符"))?,
+    );
+    Ok(ProcessedRequest {
+        target_url,
+        headers,
+        body: Bytes::from(translated),
+    })
+}
+
+/// Anthropic Messages 请求体 → OpenAI chat/completions 请求体
+pub(crate) fn translate_request(body: &[u8]) -> Result<Vec<u8>> {
+    let src: Value =
+        serde_json::from_slice(body).map_err(|e| anyhow!("Claude 请求体解析失败: {}", e))?;
+    let mut out = Map::new();
+    out.insert("model".into(), src["model"].clone());
+
+    let mut messages = Vec::new();
+    let system = match &src["system"] {
+        Value::String(s) => s.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|b| b["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        _ => String::new(),
+    };
+    if !system.is_empty() {
+        messages.push(json!({ "role": "system", "content": system }));
+    }
+    for msg in src["messages"].as_array().into_iter().flatten() {
+        translate_message(msg, &mut messages);
+    }
+    out.insert("messages".into(), Value::Array(messages));
+
+    for (from, to) in [
+        ("max_tokens", "max_tokens"),
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("stop_sequences", "stop"),
+    ] {
+        if let Some(v) = src.get(from).filter(|v| !v.is_null()) {
+            out.insert(to.into(), v.clone());
+        }
+    }
+    if let Some(user) = src["metadata"]["user_id"].as_str() {
+        out.insert("user".into(), json!(user));
+    }
+    if src["stream"].as_bool() == Some(true) {
+        out.insert("stream".into(), json!(true));
+        out.insert("stream_options".into(), json!({ "include_usage": true }));
+    }
+
+    if let Some(tools) = src["tools"].as_array().filter(|t| !t.is_empty()) {
+        let tools: Vec<Value> = tools
+            .iter()
+            .filter(|t| t.get("input_schema").is_some())
+            .map(|t| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": t["name"],
+                        "description": t["description"].as_str().unwrap_or_default(),
+                        "parameters": t["input_schema"],
+                    }
+                })
+            })
+            .collect();
+        if !tools.is_empty() {
+            out.insert("tools".into(), Value::Array(tools));
+        }
+    }
+    let tool_choice = match src["tool_choice"]["type"].as_str() {
+        Some("auto") => Some(json!("auto")),
+        Some("any") => Some(json!("required")),
+        Some("none") => Some(json!("none")),
+        Some("tool") => Some(json!({
+            "type": "function",
+            "function": { "name": src["tool_choice"]["name"] }
+        })),
+        _ => None,
+    };
+    if let Some(choice) = tool_choice.filter(|_| out.contains_key("tools")) {
+        out.insert("tool_choice".into(), choice);
+    }
+
+    Ok(serde_json::to_vec(&Value::Object(out))?)
+}
+
+fn translate_message(msg: &Value, out: &mut Vec<Value>) {
+    let role = msg["role"].as_str().unwrap_or("user");
+    let blocks = match &msg["content"] {
+        Value::String(s) => {
+            out.push(json!({ "role": role, "content": s }));
+            return;
+        }
+        Value::Array(blocks) => blocks,
+        _ => return,
+    };
+
+    if role == "assistant" {
+        let text: String = blocks
+            .iter()
+            .filter(|b| b["type"].as_str() == Some("text"))
+            .filter_map(|b| b["text"].as_str())
+            .collect();
+        let tool_calls: Vec<Value> = blocks
+            .iter()
+            .filter(|b| b["type"].as_str() == Some("tool_use"))
+            .map(|b| {
+                json!({
+                    "id": b["id"],
+                    "type": "function",
+                    "function": {
+                        "name": b["name"],
+                        "arguments": b["input"].to_string(),
+                    }
+                })
+            })
+            .collect();
+        let mut m = json!({
+            "role": "assistant",
+            "content": if text.is_empty() { Value::Null } else { json!(text) },
+        });
+        if !tool_calls.is_empty() {
+            m["tool_calls"] = Value::Array(tool_calls);
+        }
+        out.push(m);
+        return;
+    }
+
+    // user：tool_result 拆为独立 tool 消息（须紧随 assistant.tool_calls），其余合并为一条
+    let mut parts = Vec::new();
+    for block in blocks {
+        match block["type"].as_str() {
+            Some("tool_result") => {
+                let content = match &block["content"] {
+                    Value::String(s) => s.clone(),
+                    Value::Array(items) => items
+                        .iter()
+                        .filter_map(|i| i["text"].as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    _ => String::new(),
+                };
+                out.push(json!({
+                    "role": "tool",
+                    "tool_call_id": block["tool_use_id"],
+                    "content": content,
+                }));
+            }
+            Some("text") => {
+                parts.push(json!({ "type": "text", "text": block["text"] }));
+            }
+            Some("image") => {
+                let source = &block["source"];
+                let url = match source["type"].as_str() {
+                    Some("base64") => format!(
+                        "data:{};base64,{}",
+                        source["media_type"].as_str().unwrap_or("image/png"),
+                        source["data"].as_str().unwrap_or_default()
+                    ),
+                    _ => source["url"].as_str().unwrap_or_default().to_string(),
+                };
+                parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+            }
+            _ => {}
+        }
+    }
+    if parts.is_empty() {
+        return;
+    }
+    let all_text = parts.iter().all(|p| p["type"].as_str() == Some("text"));
+    let content = if all_text {
+        json!(parts
+            .iter()
+            .filter_map(|p| p["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"))
+    } else {
+        Value::Array(parts)
+    };
+    out.push(json!({ "role": "user", "content": content }));
+}
+
+fn stop_reason(finish_reason: Option<&str>) -> &'static str {
+    match finish_reason {
+        Some("length") => "max_tokens",
+        Some("tool_calls") | Some("function_call") => "tool_use",
+        _ => "end_turn",
+    }
+}
+
+fn new_message_id() -> String {
+    format!("msg_{}", determinism::ids().new_uuid().simple())
+}
+
+/// 非流式响应 / 错误响应 → Anthropic 格式
+pub(crate) fn translate_response(body: &[u8]) -> Option<Vec<u8>> {
+    let src: Value = serde_json::from_slice(body).ok()?;
+    if let Some(err) = src.get("error") {
+        let out = json!({
+            "type": "error",
+            "error": {
+                "type": err["type"].as_str().unwrap_or("api_error"),
+                "message": err["message"].as_str().unwrap_or_default(),
+            }
+        });
+        return serde_json::to_vec(&out).ok();
+    }
+
+    let choice = &src["choices"][0];
+    let message = &choice["message"];
+    let mut content = Vec::new();
+    if let Some(text) = message["content"].as_str().filter(|t| !t.is_empty()) {
+        content.push(json!({ "type": "text", "text": text }));
+    }
+    for call in message["tool_calls"].as_array().into_iter().flatten() {
+        let input = call["function"]["arguments"]
+            .as_str()
+            .and_then(|a| serde_json::from_str::<Value>(a).ok())
+            .unwrap_or_else(|| json!({}));
+        content.push(json!({
+            "type": "tool_use",
+            "id": call["id"],
+            "name": call["function"]["name"],
+            "input": input,
+        }));
+    }
+
+    let out = json!({
+        "id": src["id"].as_str().map(|s| s.to_string()).unwrap_or_else(new_message_id),
+        "type": "message",
+        "role": "assistant",
+        "model": src["model"],
+        "content": content,
+        "stop_reason": stop_reason(choice["finish_reason"].as_str()),
+        "stop_sequence": null,
+        "usage": {
+            "input_tokens": src["usage"]["prompt_tokens"].as_u64().unwrap_or(0),
+            "output_tokens": src["usage"]["completion_tokens"].as_u64().unwrap_or(0),
+        }
+    });
+    serde_json::to_vec(&out).ok()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OpenBlock {
+    Text,
+    /// OpenAI tool_calls[].index
+    Tool(u64),
+}
+
+/// 流式响应转换：OpenAI chat.completion.chunk SSE → Anthropic SSE
+#[derive(Default)]
+pub(crate) struct OpenAiStreamTranslator {
+    parser: SseParser,
+    started: bool,
+    finished: bool,
+    next_index: usize,
+    open: Option<(OpenBlock, usize)>,
+    /// OpenAI tool index → Anthropic 块 index
+    tool_blocks: HashMap<u64, usize>,
+    stop_reason: Option<&'static str>,
+    output_tokens: u64,
+}
+
+impl OpenAiStreamTranslator {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        let mut out = String::new();
+        for event in self.parser.feed(chunk) {
+            self.handle_event(&event, &mut out);
+        }
+        Bytes::from(out)
+    }
+
+    /// 上游未发送 [DONE] 时补齐结束事件
+    pub(crate) fn finish(&mut self) -> Bytes {
+        let mut out = String::new();
+        if let Some(event) = self.parser.finish() {
+            self.handle_event(&event, &mut out);
+        }
+        if self.started {
+            self.finish_message(&mut out);
+        }
+        Bytes::from(out)
+    }
+
+    fn handle_event(&mut self, event: &SseEvent, out: &mut String) {
+        for data in event.data_lines() {
+            let data = data.trim();
+            if data == "[DONE]" {
+                self.finish_message(out);
+                continue;
+            }
+            if let Ok(chunk) = serde_json::from_str::<Value>(data) {
+                self.handle_chunk(&chunk, out);
+            }
+        }
+    }
+
+    fn emit(out: &mut String, event: Value) {
+        let name = event["type"].as_str().unwrap_or("message");
+        out.push_str(&sse::format(Some(name), &event));
+    }
+
+    fn handle_chunk(&mut self, chunk: &Value, out: &mut String) {
+        if let Some(err) = chunk.get("error") {
+            Self::emit(
+                out,
+                json!({
+                    "type": "error",
+                    "error": {
+                        "type": err["type"].as_str().unwrap_or("api_error"),
+                        "message": err["message"].as_str().unwrap_or_default(),
+                    }
+                }),
+            );
+            return;
+        }
+        if !self.started {
+            self.started = true;
+            Self::emit(
+                out,
+                json!({
+                    "type": "message_start",
+                    "message": {
+                        "id": chunk["id"].as_str().map(|s| s.to_string()).unwrap_or_else(new_message_id),
+                        "type": "message",
+                        "role": "assistant",
+                        "model": chunk["model"],
+                        "content": [],
+                        "stop_reason": null,
+                        "stop_sequence": null,
+                        "usage": {
+                            "input_tokens": chunk["usage"]["prompt_tokens"].as_u64().unwrap_or(0),
+                            "output_tokens": 0,
+                        }
+                    }
+                }),
+            );
+        }
+        if let Some(tokens) = chunk["usage"]["completion_tokens"].as_u64() {
+            self.output_tokens = tokens;
+        }
+
+        let choice = &chunk["choices"][0];
+        let delta = &choice["delta"];
+        if let Some(text) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+            let index = self.ensure_block(
+                OpenBlock::Text,
+                out,
+                || json!({ "type": "text", "text": "" }),
+            );
+            Self::emit(
+                out,
+                json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": { "type": "text_delta", "text": text },
+                }),
+            );
+        }
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            let tool_index = call["index"].as_u64().unwrap_or(0);
+            let index = self.ensure_block(OpenBlock::Tool(tool_index), out, || {
+                json!({
+                    "type": "tool_use",
+                    "id": call["id"],
+                    "name": call["function"]["name"],
+                    "input": {},
+                })
+            });
+            if let Some(args) = call["function"]["arguments"]
+                .as_str()
+                .filter(|a| !a.is_empty())
+            {
+                Self::emit(
+                    out,
+                    json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": { "type": "input_json_delta", "partial_json": args },
+                    }),
+                );
+            }
+        }
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            self.stop_reason = Some(stop_reason(Some(reason)));
+        }
+    }
+
+    /// 切换到指定块：关闭当前块，必要时开启新块，返回 Anthropic 块 index
+    fn ensure_block(
+        &mut self,
+        kind: OpenBlock,
+        out: &mut String,
+        start: impl FnOnce() -> Value,
+    ) -> usize {
+        if let Some((open, index)) = self.open {
+            if open == kind {
+                return index;
+            }
+        }
+        // 同一工具调用的后续分片可能在其他块之后到达，沿用原 index
+        if let OpenBlock::Tool(t) = kind {
+            if let Some(&index) = self.tool_blocks.get(&t) {
+                return index;
+            }
+        }
+        self.close_block(out);
+        let index = self.next_index;
+        self.next_index += 1;
+        if let OpenBlock::Tool(t) = kind {
+            self.tool_blocks.insert(t, index);
+        }
+        self.open = Some((kind, index));
+        Self::emit(
+            out,
+            json!({ "type": "content_block_start", "index": index, "content_block": start() }),
+        );
+        index
+    }
+
+    fn close_block(&mut self, out: &mut String) {
+        if let Some((_, index)) = self.open.take() {
+            Self::emit(out, json!({ "type": "content_block_stop", "index": index }));
+        }
+    }
+
+    fn finish_message(&mut self, out: &mut String) {
+        if self.finished || !self.started {
+            return;
+        }
+        self.finished = true;
+        self.close_block(out);
+        Self::emit(
+            out,
+            json!({
+                "type": "message_delta",
+                "delta": {
+                    "stop_reason": self.stop_reason.unwrap_or("end_turn"),
+                    "stop_sequence": null,
+                },
+                "usage": { "output_tokens": self.output_tokens },
+            }),
+        );
+        Self::emit(out, json!({ "type": "message_stop" }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按 size 字节切分输入逐块转换，返回全部 Anthropic 事件
+    fn stream_events(input: &str, size: usize) -> Vec<Value> {
+        let mut translator = OpenAiStreamTranslator::new();
+        let mut out = Vec::new();
+        for chunk in input.as_bytes().chunks(size) {
+            out.extend_from_slice(&translator.feed(chunk));
+        }
+        out.extend_from_slice(&translator.finish());
+        let mut parser = SseParser::new();
+        let mut events = parser.feed(&out);
+        events.extend(parser.finish());
+        events.iter().filter_map(SseEvent::json).collect()
+    }
+
+    fn types(events: &[Value]) -> Vec<&str> {
+        events.iter().filter_map(|e| e["type"].as_str()).collect()
+    }
+
+    #[test]
+    fn request_maps_system_tools_and_results() {
+        let body = json!({
+            "model": "deepseek-chat",
+            "system": [{ "type": "text", "text": "be brief" }],
+            "max_tokens": 64,
+            "stop_sequences": ["END"],
+            "stream": true,
+            "metadata": { "user_id": "u1" },
+            "tools": [{ "name": "read", "description": "read file", "input_schema": { "type": "object" } }],
+            "tool_choice": { "type": "any" },
+            "messages": [
+                { "role": "user", "content": "hi" },
+                { "role": "assistant", "content": [
+                    { "type": "text", "text": "reading" },
+                    { "type": "tool_use", "id": "t1", "name": "read", "input": { "path": "a" } }
+                ]},
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "t1", "content": [{ "type": "text", "text": "data" }] },
+                    { "type": "text", "text": "go on" }
+                ]}
+            ]
+        });
+        let out: Value = serde_json::from_slice(
+            &translate_request(&serde_json::to_vec(&body).unwrap()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            out["messages"][0],
+            json!({ "role": "system", "content": "be brief" })
+        );
+        assert_eq!(
+            out["messages"][1],
+            json!({ "role": "user", "content": "hi" })
+        );
+        let assistant = &out["messages"][2];
+        assert_eq!(assistant["content"], "reading");
+        assert_eq!(assistant["tool_calls"][0]["id"], "t1");
+        assert_eq!(
+            assistant["tool_calls"][0]["function"]["arguments"],
+            r#"{"path":"a"}"#
+        );
+        // tool 消息紧随 assistant.tool_calls，其余文本在其后
+        assert_eq!(
+            out["messages"][3],
+            json!({ "role": "tool", "tool_call_id": "t1", "content": "data" })
+        );
+        assert_eq!(
+            out["messages"][4],
+            json!({ "role": "user", "content": "go on" })
+        );
+        assert_eq!(out["tools"][0]["function"]["name"], "read");
+        assert_eq!(out["tool_choice"], "required");
+        assert_eq!(out["stop"], json!(["END"]));
+        assert_eq!(out["user"], "u1");
+        assert_eq!(out["stream_options"]["include_usage"], true);
+    }
+
+    #[test]
+    fn non_streaming_response_becomes_message() {
+        let body = json!({
+            "id": "chatcmpl-1",
+            "model": "deepseek-chat",
+            "choices": [{
+                "message": {
+                    "content": "ok",
+                    "tool_calls": [{ "id": "call_1", "function": { "name": "read", "arguments": "{\"path\":\"a\"}" } }]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": { "prompt_tokens": 12, "completion_tokens": 5 }
+        });
+        let out: Value = serde_json::from_slice(
+            &translate_response(&serde_json::to_vec(&body).unwrap()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(out["id"], "chatcmpl-1");
+        assert_eq!(out["content"][0], json!({ "type": "text", "text": "ok" }));
+        assert_eq!(out["content"][1]["type"], "tool_use");
+        assert_eq!(out["content"][1]["input"], json!({ "path": "a" }));
+        assert_eq!(out["stop_reason"], "tool_use");
+        assert_eq!(
+            out["usage"],
+            json!({ "input_tokens": 12, "output_tokens": 5 })
+        );
+    }
+
+    #[test]
+    fn error_body_becomes_anthropic_error() {
+        let body = br#"{"error":{"type":"rate_limit_error","message":"slow down"}}"#;
+        let out: Value = serde_json::from_slice(&translate_response(body).unwrap()).unwrap();
+        assert_eq!(
+            out,
+            json!({ "type": "error", "error": { "type": "rate_limit_error", "message": "slow down" } })
+        );
+        assert!(translate_response(b"not json").is_none());
+
+        let events = stream_events("data: {\"error\":{\"message\":\"boom\"}}\n\n", 5);
+        assert_eq!(types(&events), ["error"]);
+        assert_eq!(events[0]["error"]["type"], "api_error");
+        assert_eq!(events[0]["error"]["message"], "boom");
+    }
+
+    #[test]
+    fn split_stream_with_tool_call_deltas() {
+        let input = concat!(
+            "data: {\"id\":\"chatcmpl-2\",\"model\":\"m\",\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"read\",\"arguments\":\"{\\\"pa\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"th\\\":1}\"}}]},\"finish_reason\":\"tool_calls\"}],\"usage\":{\"completion_tokens\":7}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        // 任意切分点结果一致
+        for size in [1, 7, input.len()] {
+            let events = stream_events(input, size);
+            assert_eq!(
+                types(&events),
+                [
+                    "message_start",
+                    "content_block_start",
+                    "content_block_delta",
+                    "content_block_delta",
+                    "content_block_stop",
+                    "content_block_start",
+                    "content_block_delta",
+                    "content_block_delta",
+                    "content_block_stop",
+                    "message_delta",
+                    "message_stop",
+                ],
+                "size={}",
+                size
+            );
+            assert_eq!(events[0]["message"]["id"], "chatcmpl-2");
+            assert_eq!(events[2]["delta"]["text"], "Hel");
+            assert_eq!(events[5]["index"], 1);
+            assert_eq!(events[5]["content_block"]["id"], "call_1");
+            let args: String = events[6..8]
+                .iter()
+                .filter_map(|e| e["delta"]["partial_json"].as_str())
+                .collect();
+            assert_eq!(
+                serde_json::from_str::<Value>(&args).unwrap(),
+                json!({ "path": 1 })
+            );
+            assert_eq!(events[9]["delta"]["stop_reason"], "tool_use");
+            assert_eq!(events[9]["usage"]["output_tokens"], 7);
+        }
+    }
+
+    #[test]
+    fn missing_done_is_completed_on_finish() {
+        let events = stream_events(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"x\"},\"finish_reason\":\"length\"}]}\n\n",
+            4,
+        );
+        assert_eq!(
+            &types(&events)[events.len() - 2..],
+            ["message_delta", "message_stop"]
+        );
+        assert_eq!(
+            events[events.len() - 2]["delta"]["stop_reason"],
+            "max_tokens"
+        );
+    }
+}
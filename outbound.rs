@@ -0,0 +1,327 @@
+// 出站连接绑定
+//
+// 多网卡 / 策略路由场景下，按 Profile 将出站连接绑定到指定源 IP 或网卡：
+// - LLM 转发：转发层按 Profile 槽位调用 client_for_profile 取对应 Client
+// - 本地工具：使用 ProcessorSettings.tools_outbound
+// 相同绑定配置复用同一个 Client（连接池共享）。Client 池由 AppState 持有（AppState::outbound），
+// 处理器、转发层与各子系统经引擎注入的实例取用；工具模块不自行取 Client，由调用方传入。
+// 代理：绑定的 proxy 指定该 Profile / 本地工具使用的代理（"direct" 为直连），未指定时沿用系统代理
+// 环境变量（HTTP_PROXY / HTTPS_PROXY / ALL_PROXY / NO_PROXY）。Client 在创建时固定代理设置，
+// 因此每次取用时比对：绑定被修改或代理环境变量变化时重建并原子替换缓存中的 Client；
+// 进行中的请求持有旧 Client 的引用，不受影响。Outbound::reset 可强制重建（如切换运行时）。
+// 访问用户给定 URL 的本地工具（网页提取 / HTTP 请求）使用 fetch_client：其 DNS 解析器
+// 拒绝解析到内网地址的域名，连接只会建立到已校验的地址，防止 DNS rebinding 绕过 SSRF 校验。
+// url_policy 允许列表中的内部主机不做该检查，禁止列表中的域名直接拒绝；策略在创建 Client 时
+// 传入解析器（策略变化时同绑定变化一样重建 Client），解析时不读取配置文件。
+// fetch_client 始终直连，忽略 tools_outbound.proxy 与系统代理环境变量：经代理时域名由代理解析，
+// 连接不再固定到已校验的地址，上述防护会失效。源 IP / 网卡绑定仍然生效。
+
+use super::amp_processor::AmpHeadersProcessor;
+use super::dns_cache;
+use super::processor_settings::ProcessorSettings;
+use super::url_policy::UrlPolicySettings;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutboundBinding {
+    /// 源 IP 地址
+    pub local_address: Option<IpAddr>,
+    /// 网卡名（Linux / macOS 等支持 SO_BINDTODEVICE / IP_BOUND_IF 的平台）
+    pub interface: Option<String>,
+    /// 代理地址（http / https / socks5），"direct" 为不使用代理；未设置时沿用系统代理
+    pub proxy: Option<String>,
+    /// 不走代理的主机（逗号分隔，格式同 NO_PROXY）
+    pub no_proxy: Option<String>,
+}
+
+impl OutboundBinding {
+    pub fn is_empty(&self) -> bool {
+        self.local_address.is_none() && self.interface.is_none() && self.proxy.is_none()
+    }
+}
+
+/// 影响 reqwest 系统代理的环境变量
+const PROXY_ENV_VARS: &[&str] = &[
+    "HTTP_PROXY",
+    "http_proxy",
+    "HTTPS_PROXY",
+    "https_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+    "NO_PROXY",
+    "no_proxy",
+];
+
+/// Client 用途：决定超时与重定向策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ClientKind {
+    /// 本地工具：短超时、禁止重定向（SSRF 防护）
+    Tool,
+    /// 本地工具访问用户给定 URL：同 Tool，且解析结果经 SSRF 校验
+    Fetch,
+    /// LLM 转发：无总超时（流式响应可能很长）
+    Forward,
+}
+
+/// SSRF 防护解析器：经共享 DNS 缓存解析，任一地址为内网地址即拒绝
+struct GuardedResolver {
+    policy: Arc<UrlPolicySettings>,
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let policy = self.policy.clone();
+        Box::pin(async move {
+            let ips = resolve_public(&host, &policy).await?;
+            let addrs: Addrs = Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+/// 解析主机名并校验全部地址均为公网地址（允许列表中的内部主机除外）
+pub(crate) async fn resolve_public(
+    host: &str,
+    policy: &UrlPolicySettings,
+) -> Result<Vec<IpAddr>, String> {
+    if policy.is_denied(host) {
+        return Err(format!("域名在禁止列表中: {}", host));
+    }
+    let ips = dns_cache::shared().lookup(host).await?;
+    if ips.is_empty() {
+        return Err(format!("DNS 解析无结果: {}", host));
+    }
+    if policy.is_allowed_internal(host) {
+        return Ok(ips);
+    }
+    if let Some(ip) = ips.iter().find(|ip| AmpHeadersProcessor::is_private_ip(ip)) {
+        return Err(format!("禁止访问内网地址: {} 解析到 {}", host, ip));
+    }
+    Ok(ips)
+}
+
+/// 创建 Client 时的配置：绑定与（仅 Fetch 使用的）URL 策略
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ClientConfig {
+    binding: OutboundBinding,
+    policy: UrlPolicySettings,
+}
+
+/// Client 缓存：按 (用途, 槽位) 保存创建时的配置与 Client
+#[derive(Default)]
+struct ClientPool {
+    /// 创建现有 Client 时的代理环境变量
+    proxy_env: String,
+    clients: HashMap<(ClientKind, String), (ClientConfig, reqwest::Client)>,
+}
+
+fn proxy_env() -> String {
+    PROXY_ENV_VARS
+        .iter()
+        .map(|name| std::env::var(name).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn proxy_for(binding: &OutboundBinding) -> Option<Result<reqwest::Proxy, reqwest::Error>> {
+    let url = binding.proxy.as_deref().filter(|p| *p != "direct")?;
+    Some(reqwest::Proxy::all(url).map(|proxy| {
+        proxy.no_proxy(
+            binding
+                .no_proxy
+                .as_deref()
+                .and_then(reqwest::NoProxy::from_string),
+        )
+    }))
+}
+
+fn build_client(kind: ClientKind, config: &ClientConfig) -> reqwest::Client {
+    let binding = &config.binding;
+    let mut builder = reqwest::Client::builder().connect_timeout(Duration::from_secs(10));
+    builder = match kind {
+        ClientKind::Tool => builder
+            .dns_resolver(dns_cache::shared())
+            .timeout(Duration::from_secs(15))
+            .redirect(Policy::none()), // 禁止重定向，防止 SSRF 绕过
+        ClientKind::Fetch => builder
+            .dns_resolver(Arc::new(GuardedResolver {
+                policy: Arc::new(config.policy.clone()),
+            }))
+            .timeout(Duration::from_secs(15))
+            .redirect(Policy::none()),
+        ClientKind::Forward => builder.dns_resolver(dns_cache::shared()),
+    };
+
+    if kind == ClientKind::Fetch {
+        // 代理会自行解析域名，绕过 GuardedResolver 的地址固定
+        if binding.proxy.as_deref().is_some_and(|p| p != "direct") {
+            tracing::debug!("访问用户给定 URL 的请求不走代理，忽略 tools_outbound.proxy");
+        }
+        builder = builder.no_proxy();
+    } else {
+        if binding.proxy.as_deref() == Some("direct") {
+            builder = builder.no_proxy();
+        }
+        match proxy_for(binding) {
+            Some(Ok(proxy)) => builder = builder.proxy(proxy),
+            Some(Err(e)) => tracing::warn!("代理地址无效，沿用系统代理: {}", e),
+            None => {}
+        }
+    }
+    if let Some(addr) = binding.local_address {
+        builder = builder.local_address(addr);
+    }
+    if let Some(iface) = &binding.interface {
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+        {
+            builder = builder.interface(iface);
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+        tracing::warn!("当前平台不支持按网卡绑定，忽略 interface={}", iface);
+    }
+
+    builder.build().unwrap_or_else(|e| {
+        tracing::error!(
+            "按绑定 {:?} 创建 HTTP Client 失败，使用默认配置: {}",
+            binding,
+            e
+        );
+        reqwest::Client::new()
+    })
+}
+
+/// 出站 Client 池：按 (用途, 槽位) 复用 Client，由 AppState 持有（见 AppState::outbound）
+#[derive(Default)]
+pub struct Outbound {
+    pool: Mutex<ClientPool>,
+}
+
+impl Outbound {
+    /// 丢弃全部缓存的 Client，下次取用时按当前设置重建（进行中的请求不受影响）
+    pub fn reset(&self) {
+        self.pool
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clients
+            .clear();
+    }
+
+    /// 本地工具使用的 Client（按 tools_outbound 绑定）
+    pub(crate) fn tool_client(&self, settings: &ProcessorSettings) -> reqwest::Client {
+        self.client_for(
+            ClientKind::Tool,
+            "tools",
+            ClientConfig {
+                binding: settings.tools_outbound.clone(),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// 访问用户给定 URL 的本地工具使用的 Client（解析结果按 policy 经 SSRF 校验，始终直连）
+    pub(crate) fn fetch_client(&self, settings: &ProcessorSettings) -> reqwest::Client {
+        self.client_for(
+            ClientKind::Fetch,
+            "tools",
+            ClientConfig {
+                binding: settings.tools_outbound.clone(),
+                policy: settings.url_policy.clone(),
+            },
+        )
+    }
+
+    /// LLM 转发使用的 Client（按 Profile 槽位绑定）
+    pub fn client_for_profile(
+        &self,
+        settings: &ProcessorSettings,
+        profile_key: &str,
+    ) -> reqwest::Client {
+        self.client_for(
+            ClientKind::Forward,
+            profile_key,
+            ClientConfig {
+                binding: settings.profile(profile_key).outbound.clone(),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn client_for(&self, kind: ClientKind, slot: &str, config: ClientConfig) -> reqwest::Client {
+        let env = proxy_env();
+        let mut pool = self.pool.lock().unwrap_or_else(|e| e.into_inner());
+        if pool.proxy_env != env {
+            if !pool.clients.is_empty() {
+                tracing::info!("系统代理环境变量已变化，重建 HTTP Client");
+            }
+            pool.clients.clear();
+            pool.proxy_env = env;
+        }
+        let key = (kind, slot.to_string());
+        if let Some((built_with, client)) = pool.clients.get(&key) {
+            if *built_with == config {
+                return client.clone();
+            }
+            tracing::info!("{} 出站配置已修改，重建 HTTP Client", slot);
+        }
+        let client = build_client(kind, &config);
+        pool.clients.insert(key, (config, client.clone()));
+        client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolve_public_applies_policy() {
+        let policy = UrlPolicySettings {
+            allow_hosts: vec!["127.0.0.1".to_string()],
+            deny_hosts: vec!["*.blocked.example".to_string()],
+        };
+        assert!(resolve_public("api.blocked.example", &policy)
+            .await
+            .is_err());
+        assert_eq!(
+            resolve_public("127.0.0.1", &policy).await.unwrap(),
+            ["127.0.0.1".parse::<IpAddr>().unwrap()]
+        );
+        let err = resolve_public("127.0.0.1", &UrlPolicySettings::default())
+            .await
+            .unwrap_err();
+        assert!(err.contains("内网"), "{}", err);
+    }
+
+    #[test]
+    fn client_rebuilt_when_policy_changes() {
+        let config = |deny: &str| ClientConfig {
+            binding: OutboundBinding::default(),
+            policy: UrlPolicySettings {
+                deny_hosts: vec![deny.to_string()],
+                ..Default::default()
+            },
+        };
+        let outbound = Outbound::default();
+        let _ = outbound.client_for(ClientKind::Fetch, "test-fetch", config("a.example"));
+        let pool = |deny: &str| {
+            outbound
+                .pool
+                .lock()
+                .unwrap()
+                .clients
+                .get(&(ClientKind::Fetch, "test-fetch".to_string()))
+                .is_some_and(|(built_with, _)| *built_with == config(deny))
+        };
+        assert!(pool("a.example"));
+        let _ = outbound.client_for(ClientKind::Fetch, "test-fetch", config("b.example"));
+        assert!(pool("b.example"));
+    }
+}
@@ -0,0 +1,206 @@
+// 结构化处理结果
+//
+// 处理器结果显式分为三类：
+// - Forward：按 target_url 转发（带转发层标记的 LLM 请求由 upstream::forward 处理）
+// - LocalResponse：本地响应，可指定状态码，响应体为完整字节或流
+// - Reject：处理失败（鉴权、校验、预算等），由宿主转为错误响应
+// 分发代码按变体匹配，不再依赖目标地址前缀；流式本地响应同样可由处理器直接返回。
+// 上级模块的 RequestProcessor 保持返回 ProcessedRequest（只表示转发请求）；需要本地响应 / 拒绝的
+// 处理器（内置 amp-code）另实现 OutcomeProcessor，引擎经 ForwardOnly 包装其余处理器。
+// amp-code 内部调用的直连处理器结果经 upstream::tag（经转发层）或 From（不经转发层）包装为 Forward；
+// 只经 RequestProcessor 调用 amp-code 时结果经 into_forward() 取出，本地响应返回错误。
+// 转发层元数据放在 Forward.upstream 中，不借用请求头，不会随请求发往上游。
+
+use super::upstream::UpstreamTag;
+use super::{ProcessedRequest, RequestProcessor};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use hyper::{HeaderMap, StatusCode};
+use std::sync::Arc;
+
+pub type LocalStream = BoxStream<'static, std::result::Result<Bytes, std::io::Error>>;
+
+/// 本地响应体
+pub enum LocalBody {
+    Full(Bytes),
+    Stream(LocalStream),
+}
+
+impl std::fmt::Debug for LocalBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocalBody::Full(bytes) => write!(f, "Full({} bytes)", bytes.len()),
+            LocalBody::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProcessOutcome {
+    Forward {
+        target_url: String,
+        headers: HeaderMap,
+        body: Bytes,
+        /// Some 时经上游转发层发送（故障转移、限流、日志等），None 时直接发送
+        upstream: Option<UpstreamTag>,
+    },
+    LocalResponse {
+        /// 本地响应来源（如 maintenance/claude、mock/codex、webSearch2）
+        source: String,
+        status: StatusCode,
+        headers: HeaderMap,
+        body: LocalBody,
+    },
+    Reject {
+        error: anyhow::Error,
+    },
+}
+
+/// 返回 ProcessOutcome 的处理器（参数同 RequestProcessor::process_outgoing_request）
+#[async_trait]
+pub trait OutcomeProcessor: Send + Sync {
+    async fn process_outcome(
+        &self,
+        base_url: &str,
+        api_key: &str,
+        path: &str,
+        query: Option<&str>,
+        original_headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<ProcessOutcome>;
+}
+
+/// 只会转发的 RequestProcessor：结果包装为 Forward（不经转发层）
+pub struct ForwardOnly(pub Arc<dyn RequestProcessor>);
+
+#[async_trait]
+impl OutcomeProcessor for ForwardOnly {
+    async fn process_outcome(
+        &self,
+        base_url: &str,
+        api_key: &str,
+        path: &str,
+        query: Option<&str>,
+        original_headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<ProcessOutcome> {
+        self.0
+            .process_outgoing_request(base_url, api_key, path, query, original_headers, body)
+            .await
+            .map(Into::into)
+    }
+}
+
+impl From<ProcessedRequest> for ProcessOutcome {
+    fn from(request: ProcessedRequest) -> Self {
+        ProcessOutcome::Forward {
+            target_url: request.target_url,
+            headers: request.headers,
+            body: request.body,
+            upstream: None,
+        }
+    }
+}
+
+impl From<Result<ProcessOutcome>> for ProcessOutcome {
+    fn from(result: Result<ProcessOutcome>) -> Self {
+        result.unwrap_or_else(|error| ProcessOutcome::Reject { error })
+    }
+}
+
+impl ProcessOutcome {
+    /// 本地响应（200，完整响应体）
+    pub(crate) fn local(source: &str, headers: HeaderMap, body: impl Into<Bytes>) -> Self {
+        ProcessOutcome::LocalResponse {
+            source: source.to_string(),
+            status: StatusCode::OK,
+            headers,
+            body: LocalBody::Full(body.into()),
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        matches!(self, ProcessOutcome::LocalResponse { .. })
+    }
+
+    /// 转发层元数据（仅经转发层发送的请求有）
+    pub fn upstream_tag_mut(&mut self) -> Option<&mut UpstreamTag> {
+        match self {
+            ProcessOutcome::Forward { upstream, .. } => upstream.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// 取出转发请求（丢弃转发层元数据）；本地响应返回错误，拒绝返回其错误
+    pub fn into_forward(self) -> Result<ProcessedRequest> {
+        match self {
+            ProcessOutcome::Forward {
+                target_url,
+                headers,
+                body,
+                ..
+            } => Ok(ProcessedRequest {
+                target_url,
+                headers,
+                body,
+            }),
+            ProcessOutcome::LocalResponse { source, .. } => {
+                Err(anyhow!("期望转发请求，处理器返回了本地响应 {}", source))
+            }
+            ProcessOutcome::Reject { error } => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forward_round_trip() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-test", "1".parse().unwrap());
+        let mut outcome: ProcessOutcome = ProcessedRequest {
+            target_url: "https://api.example/v1/messages".to_string(),
+            headers,
+            body: Bytes::from_static(b"{}"),
+        }
+        .into();
+        assert!(!outcome.is_local());
+        assert!(outcome.upstream_tag_mut().is_none());
+        let request = outcome.into_forward().unwrap();
+        assert_eq!(request.target_url, "https://api.example/v1/messages");
+        assert_eq!(request.headers["x-test"], "1");
+    }
+
+    #[test]
+    fn local_and_reject_are_not_forwarded() {
+        let local = ProcessOutcome::local("mock/claude", HeaderMap::new(), "hi");
+        assert!(local.is_local());
+        let err = local.into_forward().err().unwrap();
+        assert!(err.to_string().contains("mock/claude"));
+
+        let mut rejected: ProcessOutcome = Err::<ProcessOutcome, _>(anyhow!("预算耗尽")).into();
+        assert!(rejected.upstream_tag_mut().is_none());
+        assert_eq!(
+            rejected.into_forward().err().unwrap().to_string(),
+            "预算耗尽"
+        );
+    }
+
+    #[test]
+    fn streaming_local_response_is_representable() {
+        let stream: LocalStream = Box::pin(futures_util::stream::iter(vec![Ok(
+            Bytes::from_static(b"data: {}\n\n"),
+        )]));
+        let outcome = ProcessOutcome::LocalResponse {
+            source: "test".to_string(),
+            status: StatusCode::ACCEPTED,
+            headers: HeaderMap::new(),
+            body: LocalBody::Stream(stream),
+        };
+        assert!(outcome.is_local());
+    }
+}
@@ -0,0 +1,439 @@
+// 单请求输出上限（流式提前截断）
+//
+// Profile 可设置单次响应的输出 token（估算）或字节上限。流式响应逐事件透传并计数，
+// 超出后按上游协议补发正常结束事件再关闭流，同时丢弃上游连接以中止生成：
+// - Anthropic：content_block_stop + message_delta(stop_reason=max_tokens) + message_stop
+// - OpenAI Chat：finish_reason=length 的 chunk + [DONE]
+// - Responses：response.incomplete（reason=max_output_tokens）
+// - Gemini：finishReason=MAX_TOKENS 的 chunk
+// 协议按事件内容识别，与路由无关（协议转换后的上游同样适用）。非流式响应不处理。
+// token 数按增量文本估算（见 transform_middleware::estimate_tokens）。
+
+use super::sse::{self, SseEvent, SseParser};
+use super::transform_middleware::estimate_tokens;
+use super::upstream::Forwarded;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::pin::Pin;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputCapSettings {
+    /// 输出 token 上限（估算）
+    pub max_output_tokens: Option<u64>,
+    /// 响应体字节上限
+    pub max_output_bytes: Option<u64>,
+}
+
+impl OutputCapSettings {
+    fn is_configured(&self) -> bool {
+        self.max_output_tokens.is_some() || self.max_output_bytes.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Protocol {
+    Anthropic,
+    OpenAiChat,
+    Responses,
+    Gemini,
+}
+
+/// 增量文本字段
+const TEXT_KEYS: [&str; 6] = [
+    "text",
+    "partial_json",
+    "thinking",
+    "content",
+    "arguments",
+    "delta",
+];
+
+fn collect_text(value: &Value, out: &mut usize) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map {
+                match v {
+                    Value::String(s) if TEXT_KEYS.contains(&key.as_str()) => {
+                        *out += estimate_tokens(s)
+                    }
+                    _ => collect_text(v, out),
+                }
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|v| collect_text(v, out)),
+        _ => {}
+    }
+}
+
+fn detect(json: &Value) -> Option<Protocol> {
+    let kind = json["type"].as_str().unwrap_or_default();
+    if kind.starts_with("message_") || kind.starts_with("content_block") || kind == "ping" {
+        Some(Protocol::Anthropic)
+    } else if kind.starts_with("response.") {
+        Some(Protocol::Responses)
+    } else if json.get("choices").is_some() {
+        Some(Protocol::OpenAiChat)
+    } else if json.get("candidates").is_some() {
+        Some(Protocol::Gemini)
+    } else {
+        None
+    }
+}
+
+struct CapState {
+    inner: Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>,
+    settings: OutputCapSettings,
+    parser: SseParser,
+    pending: VecDeque<Bytes>,
+    bytes: u64,
+    tokens: u64,
+    protocol: Option<Protocol>,
+    /// Anthropic 当前未关闭的内容块
+    open_block: Option<u64>,
+    /// OpenAI Chat 最近一个 chunk 的 id / model
+    chat_meta: (Value, Value),
+    finished: bool,
+}
+
+impl CapState {
+    fn exceeded(&self) -> bool {
+        self.settings
+            .max_output_tokens
+            .is_some_and(|m| self.tokens > m)
+            || self
+                .settings
+                .max_output_bytes
+                .is_some_and(|m| self.bytes > m)
+    }
+
+    /// 处理一个完整事件（含结尾空行）
+    fn on_event(&mut self, event: SseEvent) {
+        self.bytes += event.raw().len() as u64;
+        if let Some(json) = event.json() {
+            if self.protocol.is_none() {
+                self.protocol = detect(&json);
+            }
+            match json["type"].as_str() {
+                Some("content_block_start") => self.open_block = json["index"].as_u64(),
+                Some("content_block_stop") => self.open_block = None,
+                _ => {}
+            }
+            if self.protocol == Some(Protocol::OpenAiChat) {
+                self.chat_meta = (json["id"].clone(), json["model"].clone());
+            }
+            let mut tokens = 0;
+            collect_text(&json, &mut tokens);
+            self.tokens += tokens as u64;
+        }
+        self.pending.push_back(event.into_raw());
+    }
+
+    fn stop_events(&self) -> Vec<Bytes> {
+        let sse = |event: Option<&str>, data: Value| Bytes::from(sse::format(event, data));
+        match self.protocol {
+            Some(Protocol::Anthropic) => {
+                let mut events = Vec::new();
+                if let Some(index) = self.open_block {
+                    events.push(sse(
+                        Some("content_block_stop"),
+                        json!({ "type": "content_block_stop", "index": index }),
+                    ));
+                }
+                events.push(sse(
+                    Some("message_delta"),
+                    json!({
+                        "type": "message_delta",
+                        "delta": { "stop_reason": "max_tokens", "stop_sequence": null },
+                        "usage": { "output_tokens": self.tokens }
+                    }),
+                ));
+                events.push(sse(Some("message_stop"), json!({ "type": "message_stop" })));
+                events
+            }
+            Some(Protocol::OpenAiChat) => vec![
+                sse(
+                    None,
+                    json!({
+                        "id": self.chat_meta.0,
+                        "object": "chat.completion.chunk",
+                        "model": self.chat_meta.1,
+                        "choices": [{ "index": 0, "delta": {}, "finish_reason": "length" }]
+                    }),
+                ),
+                Bytes::from_static(b"data: [DONE]\n\n"),
+            ],
+            Some(Protocol::Responses) => vec![sse(
+                Some("response.incomplete"),
+                json!({
+                    "type": "response.incomplete",
+                    "response": {
+                        "status": "incomplete",
+                        "incomplete_details": { "reason": "max_output_tokens" }
+                    }
+                }),
+            )],
+            Some(Protocol::Gemini) => vec![sse(
+                None,
+                json!({
+                    "candidates": [{
+                        "content": { "role": "model", "parts": [] },
+                        "finishReason": "MAX_TOKENS",
+                        "index": 0
+                    }]
+                }),
+            )],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// 流式响应套上输出上限；未配置或非流式时原样返回
+pub(crate) fn apply(settings: &OutputCapSettings, route: &str, forwarded: Forwarded) -> Forwarded {
+    let is_stream = forwarded
+        .response
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/event-stream"));
+    if !settings.is_configured() || !is_stream || !forwarded.response.status().is_success() {
+        return forwarded;
+    }
+    let response = forwarded.response;
+    let mut builder = hyper::http::Response::builder().status(response.status());
+    for (key, value) in response.headers() {
+        if key != hyper::header::CONTENT_LENGTH {
+            builder = builder.header(key, value);
+        }
+    }
+    let route = route.to_string();
+    let state = CapState {
+        inner: Box::pin(response.bytes_stream()),
+        settings: settings.clone(),
+        parser: SseParser::new(),
+        pending: VecDeque::new(),
+        bytes: 0,
+        tokens: 0,
+        protocol: None,
+        open_block: None,
+        chat_meta: (Value::Null, Value::Null),
+        finished: false,
+    };
+    let body = futures_util::stream::unfold(state, move |mut state| {
+        let route = route.clone();
+        async move {
+            loop {
+                if let Some(chunk) = state.pending.pop_front() {
+                    return Some((Ok::<Bytes, reqwest::Error>(chunk), state));
+                }
+                if state.finished {
+                    return None;
+                }
+                if let Some(event) = state.parser.next_event() {
+                    state.on_event(event);
+                    if state.exceeded() {
+                        tracing::warn!(
+                            "{} 响应超出输出上限（约 {} token / {} 字节），提前结束并中止上游",
+                            route,
+                            state.tokens,
+                            state.bytes
+                        );
+                        let stops = state.stop_events();
+                        state.pending.extend(stops);
+                        state.finished = true;
+                    }
+                    continue;
+                }
+                match state.inner.next().await {
+                    Some(Ok(bytes)) => state.parser.push(&bytes),
+                    Some(Err(e)) => return Some((Err(e), state)),
+                    None => {
+                        // 末尾不完整事件原样输出
+                        if let Some(rest) = state.parser.finish() {
+                            state.pending.push_back(rest.into_raw());
+                        }
+                        state.finished = true;
+                    }
+                }
+            }
+        }
+    });
+    let response = builder
+        .body(reqwest::Body::wrap_stream(body))
+        .map(reqwest::Response::from)
+        .unwrap_or_else(|_| {
+            reqwest::Response::from(hyper::http::Response::new(reqwest::Body::from("")))
+        });
+    Forwarded {
+        response,
+        served_by: forwarded.served_by,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sse_response(content_type: &str, input: &str, size: usize) -> Forwarded {
+        let chunks: Vec<Result<Bytes, std::io::Error>> = input
+            .as_bytes()
+            .chunks(size)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        Forwarded {
+            response: reqwest::Response::from(
+                hyper::http::Response::builder()
+                    .status(200)
+                    .header("content-type", content_type)
+                    .header("content-length", input.len())
+                    .body(reqwest::Body::wrap_stream(futures_util::stream::iter(
+                        chunks,
+                    )))
+                    .unwrap(),
+            ),
+            served_by: String::new(),
+        }
+    }
+
+    /// 按 size 字节切分输入，返回截断后的原始文本
+    async fn run(settings: &OutputCapSettings, input: &str, size: usize) -> String {
+        let out = apply(
+            settings,
+            "test",
+            sse_response("text/event-stream", input, size),
+        );
+        assert!(out.response.headers().get("content-length").is_none());
+        out.response.text().await.unwrap()
+    }
+
+    fn parse(text: &str) -> Vec<SseEvent> {
+        let mut parser = SseParser::new();
+        let mut events = parser.feed(text.as_bytes());
+        events.extend(parser.finish());
+        events
+    }
+
+    fn tokens(max: u64) -> OutputCapSettings {
+        OutputCapSettings {
+            max_output_tokens: Some(max),
+            max_output_bytes: None,
+        }
+    }
+
+    const ANTHROPIC: &str = concat!(
+        "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"content\":[]}}\n\n",
+        "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+        "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"aaaaaaaa\"}}\n\n",
+        "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"bbbbbbbb\"}}\n\n",
+        "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"cccccccc\"}}\n\n",
+        "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+        "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+    );
+
+    #[tokio::test]
+    async fn anthropic_stream_closed_with_max_tokens() {
+        for size in [1, 7, 64, ANTHROPIC.len()] {
+            let text = run(&tokens(3), ANTHROPIC, size).await;
+            assert!(!text.contains("cccccccc"), "size={}", size);
+            let kinds: Vec<String> = parse(&text)
+                .iter()
+                .filter_map(SseEvent::json)
+                .map(|j| j["type"].as_str().unwrap_or_default().to_string())
+                .collect();
+            assert_eq!(
+                kinds,
+                [
+                    "message_start",
+                    "content_block_start",
+                    "content_block_delta",
+                    "content_block_delta",
+                    "content_block_stop",
+                    "message_delta",
+                    "message_stop"
+                ],
+                "size={}",
+                size
+            );
+            let delta = parse(&text)[5].json().unwrap();
+            assert_eq!(delta["delta"]["stop_reason"], "max_tokens");
+            assert_eq!(delta["usage"]["output_tokens"], 4);
+        }
+    }
+
+    #[tokio::test]
+    async fn under_cap_passes_through_unchanged() {
+        for size in [1, 5, ANTHROPIC.len()] {
+            assert_eq!(run(&tokens(100), ANTHROPIC, size).await, ANTHROPIC);
+        }
+        // 末尾缺少空行的事件原样输出
+        let input = "data: {\"choices\":[]}\n\ndata: [DONE]";
+        assert_eq!(run(&tokens(100), input, 3).await, input);
+    }
+
+    #[tokio::test]
+    async fn openai_chat_byte_cap_finishes_with_length() {
+        let chunk = "data: {\"id\":\"c1\",\"model\":\"gpt\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}]}\n\n";
+        let input = format!("{}{}{}data: [DONE]\n\n", chunk, chunk, chunk);
+        let settings = OutputCapSettings {
+            max_output_tokens: None,
+            max_output_bytes: Some(chunk.len() as u64 + 1),
+        };
+        let text = run(&settings, &input, 9).await;
+        let events = parse(&text);
+        assert_eq!(events.len(), 4, "{}", text);
+        let stop = events[2].json().unwrap();
+        assert_eq!(stop["id"], "c1");
+        assert_eq!(stop["model"], "gpt");
+        assert_eq!(stop["choices"][0]["finish_reason"], "length");
+        assert!(events[3].is_done());
+    }
+
+    #[tokio::test]
+    async fn responses_and_gemini_stop_events() {
+        let input = concat!(
+            "event: response.output_text.delta\ndata: {\"type\":\"response.output_text.delta\",\"delta\":\"aaaaaaaa\"}\n\n",
+            "event: response.output_text.delta\ndata: {\"type\":\"response.output_text.delta\",\"delta\":\"bbbbbbbb\"}\n\n",
+        );
+        let events = parse(&run(&tokens(1), input, 11).await);
+        assert_eq!(events.len(), 2);
+        let stop = events[1].json().unwrap();
+        assert_eq!(stop["type"], "response.incomplete");
+        assert_eq!(
+            stop["response"]["incomplete_details"]["reason"],
+            "max_output_tokens"
+        );
+
+        let input =
+            "data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"aaaaaaaa\"}]}}]}\n\n";
+        let events = parse(&run(&tokens(1), input, 4).await);
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[1].json().unwrap()["candidates"][0]["finishReason"],
+            "MAX_TOKENS"
+        );
+    }
+
+    #[tokio::test]
+    async fn unconfigured_or_non_stream_untouched() {
+        let out = apply(
+            &OutputCapSettings::default(),
+            "test",
+            sse_response("text/event-stream", ANTHROPIC, 8),
+        );
+        assert_eq!(
+            out.response.headers()["content-length"],
+            ANTHROPIC.len().to_string()
+        );
+
+        let body = "{\"content\":\"aaaaaaaaaaaaaaaa\"}";
+        let out = apply(
+            &tokens(1),
+            "test",
+            sse_response("application/json", body, 4),
+        );
+        assert_eq!(out.response.text().await.unwrap(), body);
+    }
+}
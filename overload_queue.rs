@@ -0,0 +1,386 @@
+// Anthropic 过载（529）排队
+//
+// 529 往往成波出现。Claude 路由在重试与故障转移之后仍返回 529 时，不立即让 Agent 回合失败，
+// 而是在 window_secs 窗口内按指数间隔重新发送整条转发链：
+// - 流式请求：立即向客户端返回 200 SSE，排队期间每 progress_interval_secs 发送一次
+//   ping 事件（附带 dc_overload 进度字段，Anthropic 客户端会忽略未知字段），
+//   上游恢复后接着透传真实响应流；窗口耗尽则发送 overloaded_error 错误事件
+// - 非流式请求：静默等待，窗口耗尽后返回最后一次的 529 响应
+// 响应头的 x-dc-served-by 在流式排队时为主 Profile（排队开始前无法确定最终后端）。
+
+use super::audit_log::{self, AuditRecord};
+use super::dashboard;
+use super::sse;
+use super::telemetry::Span;
+use super::upstream::{Chain, Forwarded};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+const OVERLOADED: u16 = 529;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OverloadSettings {
+    pub enabled: bool,
+    /// 最长排队时间（秒）
+    pub window_secs: u64,
+    /// 首次重发间隔（毫秒），之后逐次翻倍
+    pub initial_delay_ms: u64,
+    /// 重发间隔上限（毫秒）
+    pub max_delay_ms: u64,
+    /// 流式排队时进度事件间隔（秒）
+    pub progress_interval_secs: u64,
+}
+
+impl Default for OverloadSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_secs: 120,
+            initial_delay_ms: 2_000,
+            max_delay_ms: 20_000,
+            progress_interval_secs: 5,
+        }
+    }
+}
+
+pub(crate) fn should_queue(
+    settings: &OverloadSettings,
+    route: &str,
+    forwarded: &Forwarded,
+) -> bool {
+    settings.enabled
+        && settings.window_secs > 0
+        && route == "claude"
+        && forwarded.response.status().as_u16() == OVERLOADED
+}
+
+fn is_streaming(body: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|j| j.get("stream").and_then(Value::as_bool))
+        .unwrap_or(false)
+}
+
+fn next_delay(settings: &OverloadSettings, delay: Duration) -> Duration {
+    (delay * 2).min(Duration::from_millis(settings.max_delay_ms.max(1)))
+}
+
+fn record(chain: &Chain, outcome: &str, attempts: u32, waited: Duration) {
+    audit_log::append(
+        &AuditRecord::new("overload_queue", &chain.route, chain.primary())
+            .with("outcome", json!(outcome))
+            .with("attempts", json!(attempts))
+            .with("waited_ms", json!(waited.as_millis() as u64)),
+    );
+}
+
+/// 529 排队；返回给客户端的最终响应
+pub(crate) async fn hold(
+    settings: &OverloadSettings,
+    chain: Arc<Chain>,
+    first: Forwarded,
+    context: Option<String>,
+) -> Forwarded {
+    tracing::warn!(
+        "{} 上游过载（529），开始排队重试，窗口 {}s",
+        chain.route,
+        settings.window_secs
+    );
+    dashboard::record_error(&chain.route, "上游过载（529），请求进入排队");
+    if is_streaming(chain.body()) {
+        stream_queue(settings.clone(), chain, context)
+    } else {
+        quiet_queue(settings, &chain, first, context).await
+    }
+}
+
+async fn quiet_queue(
+    settings: &OverloadSettings,
+    chain: &Chain,
+    mut last: Forwarded,
+    context: Option<String>,
+) -> Forwarded {
+    let span = Span::from_context("upstream.overload_queue", context.as_deref());
+    let window = Duration::from_secs(settings.window_secs);
+    let started = Instant::now();
+    let mut delay = Duration::from_millis(settings.initial_delay_ms);
+    let mut attempts = 0;
+    while started.elapsed() + delay <= window {
+        tokio::time::sleep(delay).await;
+        attempts += 1;
+        match chain.run(&span).await {
+            Ok(f) if f.response.status().as_u16() == OVERLOADED => last = f,
+            Ok(f) => {
+                record(chain, "served", attempts, started.elapsed());
+                return f;
+            }
+            Err(e) => {
+                tracing::warn!("过载排队重发失败: {}", e);
+            }
+        }
+        delay = next_delay(settings, delay);
+    }
+    record(chain, "exhausted", attempts, started.elapsed());
+    span.fail("过载排队窗口耗尽");
+    last
+}
+
+fn stream_queue(
+    settings: OverloadSettings,
+    chain: Arc<Chain>,
+    context: Option<String>,
+) -> Forwarded {
+    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(16);
+    let served_by = chain.primary().to_string();
+
+    tokio::spawn(async move {
+        let span = Span::from_context("upstream.overload_queue", context.as_deref());
+        let window = Duration::from_secs(settings.window_secs);
+        let tick = Duration::from_secs(settings.progress_interval_secs.max(1));
+        let started = Instant::now();
+        let mut delay = Duration::from_millis(settings.initial_delay_ms);
+        let mut attempts = 0u32;
+
+        while started.elapsed() + delay <= window {
+            // 等待期间定期发送进度
+            let wake = Instant::now() + delay;
+            while Instant::now() < wake {
+                let progress = json!({
+                    "type": "ping",
+                    "dc_overload": {
+                        "attempts": attempts,
+                        "waited_secs": started.elapsed().as_secs(),
+                        "window_secs": settings.window_secs,
+                        "message": "上游过载，正在排队重试",
+                    }
+                });
+                if tx.send(Ok(sse::emit("ping", progress))).await.is_err() {
+                    // 客户端已断开
+                    record(&chain, "cancelled", attempts, started.elapsed());
+                    return;
+                }
+                tokio::time::sleep(tick.min(wake.saturating_duration_since(Instant::now()))).await;
+            }
+
+            attempts += 1;
+            let failure = match chain.run(&span).await {
+                Ok(f) if f.response.status().is_success() => {
+                    record(&chain, "served", attempts, started.elapsed());
+                    let mut body = f.response.bytes_stream();
+                    while let Some(chunk) = body.next().await {
+                        let chunk = chunk.map_err(std::io::Error::other);
+                        if tx.send(chunk).await.is_err() {
+                            break;
+                        }
+                    }
+                    return;
+                }
+                Ok(f) if f.response.status().as_u16() == OVERLOADED => None,
+                Ok(f) => {
+                    let status = f.response.status().as_u16();
+                    let text = f.response.text().await.unwrap_or_default();
+                    Some(format!("上游返回 {}: {}", status, text))
+                }
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(message) = failure {
+                record(&chain, "failed", attempts, started.elapsed());
+                let error = json!({
+                    "type": "error",
+                    "error": { "type": "api_error", "message": message }
+                });
+                let _ = tx.send(Ok(sse::emit("error", error))).await;
+                return;
+            }
+            delay = next_delay(&settings, delay);
+        }
+
+        record(&chain, "exhausted", attempts, started.elapsed());
+        span.fail("过载排队窗口耗尽");
+        let error = json!({
+            "type": "error",
+            "error": {
+                "type": "overloaded_error",
+                "message": format!("上游持续过载，已排队 {}s 仍未恢复", started.elapsed().as_secs()),
+            }
+        });
+        let _ = tx.send(Ok(sse::emit("error", error))).await;
+    });
+
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    });
+    let response = hyper::http::Response::builder()
+        .status(200)
+        .header("content-type", "text/event-stream")
+        .header("cache-control", "no-cache")
+        .body(reqwest::Body::wrap_stream(stream))
+        .map(reqwest::Response::from)
+        .unwrap_or_else(|_| {
+            reqwest::Response::from(hyper::http::Response::new(reqwest::Body::from("")))
+        });
+    Forwarded {
+        response,
+        served_by,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn settings(window_secs: u64) -> OverloadSettings {
+        OverloadSettings {
+            window_secs,
+            initial_delay_ms: 20,
+            max_delay_ms: 40,
+            progress_interval_secs: 1,
+            ..Default::default()
+        }
+    }
+
+    fn status(code: u16) -> Forwarded {
+        Forwarded {
+            response: reqwest::Response::from(
+                hyper::http::Response::builder()
+                    .status(code)
+                    .body(reqwest::Body::from("overloaded"))
+                    .unwrap(),
+            ),
+            served_by: String::new(),
+        }
+    }
+
+    /// 本地上游：依次返回 replies 中的响应，用尽后重复最后一个；返回请求地址与已接收请求数
+    async fn upstream(replies: Vec<(u16, &'static str)>) -> (String, Arc<AtomicUsize>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        let seen = count.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                let n = seen.fetch_add(1, Ordering::SeqCst);
+                let (code, body) = replies[n.min(replies.len() - 1)];
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: text/event-stream\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    code,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (format!("http://{}/v1/messages", addr), count)
+    }
+
+    #[test]
+    fn only_claude_529_is_queued() {
+        let s = settings(10);
+        assert!(should_queue(&s, "claude", &status(529)));
+        assert!(!should_queue(&s, "claude", &status(503)));
+        assert!(!should_queue(&s, "codex", &status(529)));
+        let disabled = OverloadSettings {
+            enabled: false,
+            ..settings(10)
+        };
+        assert!(!should_queue(&disabled, "claude", &status(529)));
+        assert!(!should_queue(&settings(0), "claude", &status(529)));
+    }
+
+    #[test]
+    fn delay_doubles_up_to_cap() {
+        let s = settings(10);
+        let d = next_delay(&s, Duration::from_millis(20));
+        assert_eq!(d, Duration::from_millis(40));
+        assert_eq!(next_delay(&s, d), Duration::from_millis(40));
+        assert!(is_streaming(br#"{"stream":true}"#));
+        assert!(!is_streaming(br#"{"stream":"yes"}"#));
+        assert!(!is_streaming(b"not json"));
+    }
+
+    #[tokio::test]
+    async fn quiet_queue_returns_first_recovered_response() {
+        let (url, count) = upstream(vec![(529, ""), (200, "ok")]).await;
+        let chain = Arc::new(Chain::for_test("claude", &[url.as_str()], b"{}"));
+        let out = hold(&settings(10), chain, status(529), None).await;
+        assert_eq!(out.response.status(), 200);
+        assert_eq!(out.response.text().await.unwrap(), "ok");
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn quiet_queue_gives_up_after_window() {
+        let (url, count) = upstream(vec![(529, "still overloaded")]).await;
+        let chain = Arc::new(Chain::for_test("claude", &[url.as_str()], b"{}"));
+        let started = Instant::now();
+        let out = hold(&settings(1), chain, status(529), None).await;
+        assert!(started.elapsed() <= Duration::from_secs(2));
+        assert_eq!(out.response.status().as_u16(), OVERLOADED);
+        assert_eq!(out.response.text().await.unwrap(), "still overloaded");
+        assert!(count.load(Ordering::SeqCst) >= 2);
+    }
+
+    #[tokio::test]
+    async fn stream_queue_sends_progress_then_real_stream() {
+        let (url, _) = upstream(vec![(529, ""), (200, "event: message_stop\ndata: {}\n\n")]).await;
+        let chain = Arc::new(Chain::for_test(
+            "claude",
+            &[url.as_str()],
+            br#"{"stream":true}"#,
+        ));
+        let out = hold(&settings(10), chain, status(529), None).await;
+        assert_eq!(out.served_by, "target0");
+        assert_eq!(out.response.status(), 200);
+        assert_eq!(out.response.headers()["content-type"], "text/event-stream");
+        let text = out.response.text().await.unwrap();
+        assert!(text.starts_with("event: ping\n"), "{}", text);
+        assert!(text.contains("dc_overload"));
+        assert!(
+            text.ends_with("event: message_stop\ndata: {}\n\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_queue_reports_errors_as_events() {
+        let (url, _) = upstream(vec![(400, "bad request")]).await;
+        let chain = Arc::new(Chain::for_test(
+            "claude",
+            &[url.as_str()],
+            br#"{"stream":true}"#,
+        ));
+        let text = hold(&settings(10), chain, status(529), None)
+            .await
+            .response
+            .text()
+            .await
+            .unwrap();
+        assert!(text.contains("event: error\n"), "{}", text);
+        assert!(text.contains("上游返回 400: bad request"), "{}", text);
+
+        let (url, _) = upstream(vec![(529, "")]).await;
+        let chain = Arc::new(Chain::for_test(
+            "claude",
+            &[url.as_str()],
+            br#"{"stream":true}"#,
+        ));
+        let text = hold(&settings(1), chain, status(529), None)
+            .await
+            .response
+            .text()
+            .await
+            .unwrap();
+        assert!(text.contains("overloaded_error"), "{}", text);
+    }
+}
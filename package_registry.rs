@@ -0,0 +1,249 @@
+// 包仓库查询本地工具（lookupCrate / lookupNpmPackage / lookupPypiPackage）
+//
+// 直接调用 crates.io / npm / PyPI 的 JSON API，返回最新版本、简介、主页、仓库与文档链接，
+// 避免 Agent 经通用网页提取抓取仓库 HTML。params.name 为包名，可选 params.version 查询指定版本。
+// 结果按 (仓库, 包名, 版本) 在内存中缓存 cache_ttl_secs 秒。
+
+use super::bandwidth::{self, Subject};
+use anyhow::{anyhow, bail, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 超过该条目数时清理过期项
+const MAX_ENTRIES: usize = 512;
+/// 返回的最近版本数
+const RECENT_VERSIONS: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PackageRegistrySettings {
+    /// 查询结果缓存时间（秒），0 不缓存
+    pub cache_ttl_secs: u64,
+}
+
+impl Default for PackageRegistrySettings {
+    fn default() -> Self {
+        Self {
+            cache_ttl_secs: 3600,
+        }
+    }
+}
+
+static CACHE: Lazy<Mutex<HashMap<String, (Instant, Value)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 本地工具名 → 仓库
+fn registry_for(tool_name: &str) -> Option<&'static str> {
+    match tool_name {
+        "lookupCrate" => Some("crates"),
+        "lookupNpmPackage" => Some("npm"),
+        "lookupPypiPackage" => Some("pypi"),
+        _ => None,
+    }
+}
+
+/// 包名只允许仓库合法字符，防止拼接出其他路径
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 214
+        && !name.contains("..")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@' | '/'))
+}
+
+async fn fetch_json(client: &reqwest::Client, tool_name: &str, url: &str) -> Result<Value> {
+    let resp = client
+        .get(url)
+        // crates.io 要求可识别的 User-Agent
+        .header("User-Agent", "duckcoding-amp (package lookup)")
+        .header("Accept", "application/json")
+        .send()
+        .await?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        bail!("包不存在");
+    }
+    if !resp.status().is_success() {
+        bail!("仓库返回 HTTP {}", resp.status());
+    }
+    let raw = resp.bytes().await?;
+    bandwidth::record(Subject::Tool(tool_name), 0, raw.len() as u64);
+    Ok(serde_json::from_slice(&raw)?)
+}
+
+async fn lookup_crate(
+    client: &reqwest::Client,
+    name: &str,
+    version: Option<&str>,
+) -> Result<Value> {
+    let data = fetch_json(
+        client,
+        "lookupCrate",
+        &format!("https://crates.io/api/v1/crates/{}", name),
+    )
+    .await?;
+    let info = &data["crate"];
+    let latest = info["max_stable_version"]
+        .as_str()
+        .or_else(|| info["max_version"].as_str())
+        .unwrap_or_default();
+    let versions: Vec<&Value> = data["versions"]
+        .as_array()
+        .map(|v| v.iter().collect())
+        .unwrap_or_default();
+    let selected = version.unwrap_or(latest);
+    let entry = versions.iter().find(|v| v["num"] == selected);
+    if version.is_some() && entry.is_none() {
+        bail!("版本 {} 不存在", selected);
+    }
+    Ok(json!({
+        "registry": "crates.io",
+        "name": info["name"],
+        "version": selected,
+        "latestVersion": latest,
+        "description": info["description"],
+        "homepage": info["homepage"],
+        "repository": info["repository"],
+        "documentation": info["documentation"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("https://docs.rs/{}/{}", name, selected)),
+        "license": entry.map(|v| v["license"].clone()),
+        "yanked": entry.map(|v| v["yanked"].clone()),
+        "downloads": info["downloads"],
+        "updatedAt": info["updated_at"],
+        "recentVersions": versions
+            .iter()
+            .take(RECENT_VERSIONS)
+            .map(|v| v["num"].clone())
+            .collect::<Vec<_>>()
+    }))
+}
+
+async fn lookup_npm(client: &reqwest::Client, name: &str, version: Option<&str>) -> Result<Value> {
+    // 作用域包的 / 需要编码
+    let encoded = name.replace('/', "%2F");
+    let tag = version.unwrap_or("latest");
+    let data = fetch_json(
+        client,
+        "lookupNpmPackage",
+        &format!("https://registry.npmjs.org/{}/{}", encoded, tag),
+    )
+    .await?;
+    let repository = match &data["repository"] {
+        Value::Object(repo) => repo.get("url").cloned().unwrap_or(Value::Null),
+        other => other.clone(),
+    };
+    Ok(json!({
+        "registry": "npm",
+        "name": data["name"],
+        "version": data["version"],
+        "description": data["description"],
+        "homepage": data["homepage"],
+        "repository": repository,
+        "documentation": data["homepage"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("https://www.npmjs.com/package/{}", name)),
+        "license": data["license"],
+        "types": data["types"].as_str().or_else(|| data["typings"].as_str()),
+        "engines": data["engines"],
+        "dependencies": data["dependencies"]
+            .as_object()
+            .map(|deps| deps.keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default()
+    }))
+}
+
+async fn lookup_pypi(client: &reqwest::Client, name: &str, version: Option<&str>) -> Result<Value> {
+    let url = match version {
+        Some(v) => format!("https://pypi.org/pypi/{}/{}/json", name, v),
+        None => format!("https://pypi.org/pypi/{}/json", name),
+    };
+    let data = fetch_json(client, "lookupPypiPackage", &url).await?;
+    let info = &data["info"];
+    let urls = &info["project_urls"];
+    let pick = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|k| urls[*k].as_str())
+            .map(str::to_string)
+    };
+    let mut releases: Vec<&String> = data["releases"]
+        .as_object()
+        .map(|r| r.keys().collect())
+        .unwrap_or_default();
+    // 按上传顺序不可得，近似取字典序末尾
+    releases.reverse();
+    Ok(json!({
+        "registry": "pypi",
+        "name": info["name"],
+        "version": info["version"],
+        "description": info["summary"],
+        "homepage": pick(&["Homepage", "homepage", "Home"]).or_else(|| info["home_page"].as_str().map(str::to_string)),
+        "repository": pick(&["Source", "Repository", "source", "Source Code", "GitHub"]),
+        "documentation": pick(&["Documentation", "documentation", "Docs"])
+            .unwrap_or_else(|| format!("https://pypi.org/project/{}/", name)),
+        "license": info["license"],
+        "requiresPython": info["requires_python"],
+        "recentVersions": releases.into_iter().take(RECENT_VERSIONS).collect::<Vec<_>>()
+    }))
+}
+
+/// 查询包信息，返回本地工具应答
+pub(crate) async fn handle(
+    client: &reqwest::Client,
+    settings: &PackageRegistrySettings,
+    tool_name: &str,
+    body: &[u8],
+) -> Result<Value> {
+    let registry =
+        registry_for(tool_name).ok_or_else(|| anyhow!("未知的包查询工具: {}", tool_name))?;
+    let req_json: Value =
+        serde_json::from_slice(body).map_err(|e| anyhow!("请求 JSON 解析失败: {}", e))?;
+    let name = req_json["params"]["name"]
+        .as_str()
+        .map(str::trim)
+        .ok_or_else(|| anyhow!("缺少 name 参数"))?;
+    let version = req_json["params"]["version"]
+        .as_str()
+        .map(str::trim)
+        .filter(|v| !v.is_empty());
+    if !valid_name(name) || version.is_some_and(|v| !valid_name(v) || v.contains('/')) {
+        bail!("无效的包名或版本");
+    }
+
+    let key = format!("{}:{}:{}", registry, name, version.unwrap_or(""));
+    let ttl = Duration::from_secs(settings.cache_ttl_secs);
+    let cached = CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&key)
+        .filter(|(at, _)| at.elapsed() < ttl)
+        .map(|(_, v)| v.clone());
+    let (mut result, hit) = match cached {
+        Some(result) => (result, true),
+        None => {
+            tracing::info!("本地包查询: {} {}", registry, name);
+            let result = match registry {
+                "crates" => lookup_crate(client, name, version).await,
+                "npm" => lookup_npm(client, name, version).await,
+                _ => lookup_pypi(client, name, version).await,
+            }
+            .map_err(|e| anyhow!("{} 查询 {} 失败: {}", registry, name, e))?;
+            if !ttl.is_zero() {
+                let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+                if cache.len() >= MAX_ENTRIES {
+                    cache.retain(|_, (at, _)| at.elapsed() < ttl);
+                }
+                cache.insert(key, (Instant::now(), result.clone()));
+            }
+            (result, false)
+        }
+    };
+    result["cached"] = json!(hit);
+    Ok(json!({ "ok": true, "result": result }))
+}
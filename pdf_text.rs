@@ -0,0 +1,1034 @@
+// PDF 文本提取（extractWebPageContent 遇到 application/pdf 时使用）
+//
+// 不依赖外部 PDF 库的轻量实现，覆盖常见的文本型 PDF：
+// - 扫描 `N G obj` 收集对象，并展开 /ObjStm 压缩对象流（不依赖可能损坏的 xref）
+// - 流过滤器仅支持 FlateDecode（见 inflate.rs），其他过滤器的流跳过
+// - 按 Catalog → Pages 树顺序取页面，解析内容流中的 Tj / TJ / ' / " 文本操作符
+// - 字体带 /ToUnicode 时按 CMap 映射（CID 字体 / 中文 PDF），否则按单字节 Latin-1 解码
+// 加密 PDF、扫描件（纯图片）无法提取文本，返回错误由上层提示。
+
+use super::inflate;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 页面树递归深度上限
+const MAX_TREE_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PdfSettings {
+    /// PDF 下载大小上限（通常比网页大）
+    pub max_bytes: usize,
+    /// 最多提取的页数
+    pub max_pages: usize,
+    /// 提取文本的最大字符数
+    pub max_chars: usize,
+}
+
+impl Default for PdfSettings {
+    fn default() -> Self {
+        Self {
+            max_bytes: 20 * 1024 * 1024,
+            max_pages: 50,
+            max_chars: 200_000,
+        }
+    }
+}
+
+/// 是否为 PDF（Content-Type 或文件头）
+pub(crate) fn is_pdf(content_type: Option<&str>, data: &[u8]) -> bool {
+    content_type.is_some_and(|ct| ct.to_ascii_lowercase().contains("application/pdf"))
+        || data.starts_with(b"%PDF-")
+}
+
+// ==================== 对象解析 ====================
+
+#[derive(Debug, Clone)]
+enum Obj {
+    Null,
+    Bool,
+    Num(f64),
+    Name(String),
+    Str(Vec<u8>),
+    Array(Vec<Obj>),
+    Dict(HashMap<String, Obj>),
+    Ref(u32),
+    Stream(HashMap<String, Obj>, Vec<u8>),
+}
+
+impl Obj {
+    fn as_dict(&self) -> Option<&HashMap<String, Obj>> {
+        match self {
+            Obj::Dict(d) | Obj::Stream(d, _) => Some(d),
+            _ => None,
+        }
+    }
+
+    fn as_name(&self) -> Option<&str> {
+        match self {
+            Obj::Name(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn as_num(&self) -> Option<f64> {
+        match self {
+            Obj::Num(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+enum Token {
+    Obj(Obj),
+    Op(String),
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(
+        b,
+        b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%'
+    )
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(b) = self.peek() {
+            if b == b'%' {
+                while let Some(c) = self.peek() {
+                    if c == b'\n' || c == b'\r' {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+            } else if b.is_ascii_whitespace() || b == 0 {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn word(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            if b.is_ascii_whitespace() || is_delimiter(b) || b == 0 {
+                break;
+            }
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        self.skip_ws();
+        let b = self.peek()?;
+        if b.is_ascii_alphabetic() || b == b'\'' || b == b'"' || b == b'*' {
+            let word = if b == b'\'' || b == b'"' {
+                self.pos += 1;
+                &self.data[self.pos - 1..self.pos]
+            } else {
+                self.word()
+            };
+            return Some(match word {
+                b"true" | b"false" => Token::Obj(Obj::Bool),
+                b"null" => Token::Obj(Obj::Null),
+                _ => Token::Op(String::from_utf8_lossy(word).into_owned()),
+            });
+        }
+        Some(Token::Obj(self.parse_obj()?))
+    }
+
+    fn parse_obj(&mut self) -> Option<Obj> {
+        self.skip_ws();
+        let b = self.peek()?;
+        match b {
+            b'/' => {
+                self.pos += 1;
+                let raw = self.word();
+                Some(Obj::Name(decode_name(raw)))
+            }
+            b'(' => Some(Obj::Str(self.literal_string())),
+            b'<' if self.data.get(self.pos + 1) == Some(&b'<') => {
+                self.pos += 2;
+                let mut dict = HashMap::new();
+                loop {
+                    self.skip_ws();
+                    match self.peek() {
+                        None => break,
+                        Some(b'>') => {
+                            // 截断的 `>`（文件末尾）不能越过数据末尾
+                            self.pos = (self.pos + 2).min(self.data.len());
+                            break;
+                        }
+                        Some(b'/') => {
+                            self.pos += 1;
+                            let key = decode_name(self.word());
+                            let value = self.parse_obj().unwrap_or(Obj::Null);
+                            dict.insert(key, value);
+                        }
+                        Some(_) => {
+                            // 非法内容，跳过一个 token
+                            if self.parse_obj().is_none() {
+                                self.pos += 1;
+                            }
+                        }
+                    }
+                }
+                Some(self.maybe_stream(dict))
+            }
+            b'<' => {
+                self.pos += 1;
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c != b'>') {
+                    self.pos += 1;
+                }
+                // 缺少结束的 `>`：字符串不完整，视为损坏
+                self.peek()?;
+                let hex = &self.data[start..self.pos];
+                self.pos += 1;
+                Some(Obj::Str(decode_hex(hex)))
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_ws();
+                    match self.peek() {
+                        None => break,
+                        Some(b']') => {
+                            self.pos += 1;
+                            break;
+                        }
+                        Some(_) => match self.next_token() {
+                            Some(Token::Obj(obj)) => items.push(obj),
+                            Some(Token::Op(_)) => {}
+                            None => break,
+                        },
+                    }
+                }
+                Some(Obj::Array(items))
+            }
+            b'0'..=b'9' | b'+' | b'-' | b'.' => {
+                let raw = self.word();
+                let num: f64 = std::str::from_utf8(raw).ok()?.parse().unwrap_or(0.0);
+                // 间接引用：`N G R`
+                if raw.iter().all(u8::is_ascii_digit) {
+                    let save = self.pos;
+                    self.skip_ws();
+                    let gen = self.word();
+                    if !gen.is_empty() && gen.iter().all(u8::is_ascii_digit) {
+                        self.skip_ws();
+                        if self.peek() == Some(b'R')
+                            && self
+                                .data
+                                .get(self.pos + 1)
+                                .is_none_or(|c| c.is_ascii_whitespace() || is_delimiter(*c))
+                        {
+                            self.pos += 1;
+                            return Some(Obj::Ref(num as u32));
+                        }
+                    }
+                    self.pos = save;
+                }
+                Some(Obj::Num(num))
+            }
+            _ if b.is_ascii_alphabetic() => {
+                let word = self.word();
+                Some(match word {
+                    b"true" | b"false" => Obj::Bool,
+                    _ => Obj::Null,
+                })
+            }
+            _ => {
+                self.pos += 1;
+                None
+            }
+        }
+    }
+
+    fn maybe_stream(&mut self, dict: HashMap<String, Obj>) -> Obj {
+        let save = self.pos;
+        self.skip_ws();
+        if !self
+            .data
+            .get(self.pos..)
+            .is_some_and(|rest| rest.starts_with(b"stream"))
+        {
+            self.pos = save;
+            return Obj::Dict(dict);
+        }
+        self.pos += 6;
+        if self.peek() == Some(b'\r') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'\n') {
+            self.pos += 1;
+        }
+        let start = self.pos.min(self.data.len());
+        // /Length 可能为负数、小数或超大值：只接受落在数据范围内且其后紧跟 endstream 的长度
+        let declared = dict
+            .get("Length")
+            .and_then(Obj::as_num)
+            .filter(|n| n.is_finite() && *n >= 0.0)
+            .and_then(|n| start.checked_add(n as usize))
+            .filter(|end| {
+                self.data.get(*end..).is_some_and(|rest| {
+                    rest.iter()
+                        .take(16)
+                        .skip_while(|c| c.is_ascii_whitespace())
+                        .take(9)
+                        .copied()
+                        .eq(b"endstream".iter().copied())
+                })
+            });
+        let end = declared.unwrap_or_else(|| {
+            find(&self.data[start..], b"endstream").map_or(self.data.len(), |p| start + p)
+        });
+        let mut content = &self.data[start..end];
+        if declared.is_none() {
+            content = content.strip_suffix(b"\n").unwrap_or(content);
+            content = content.strip_suffix(b"\r").unwrap_or(content);
+        }
+        self.pos = end.saturating_add(9).min(self.data.len());
+        Obj::Stream(dict, content.to_vec())
+    }
+
+    fn literal_string(&mut self) -> Vec<u8> {
+        self.pos += 1;
+        let mut out = Vec::new();
+        let mut depth = 1;
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'(' => {
+                    depth += 1;
+                    out.push(b);
+                }
+                b')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                    out.push(b);
+                }
+                b'\\' => {
+                    let Some(c) = self.peek() else { break };
+                    self.pos += 1;
+                    match c {
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'b' => out.push(8),
+                        b'f' => out.push(12),
+                        b'\r' => {
+                            if self.peek() == Some(b'\n') {
+                                self.pos += 1;
+                            }
+                        }
+                        b'\n' => {}
+                        b'0'..=b'7' => {
+                            let mut value = (c - b'0') as u32;
+                            for _ in 0..2 {
+                                match self.peek() {
+                                    Some(d @ b'0'..=b'7') => {
+                                        value = value * 8 + (d - b'0') as u32;
+                                        self.pos += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            out.push(value as u8);
+                        }
+                        _ => out.push(c),
+                    }
+                }
+                _ => out.push(b),
+            }
+        }
+        out
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn decode_name(raw: &[u8]) -> String {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        // `#xx` 转义：必须是两位十六进制数字（from_str_radix 会接受 "+1" 之类的写法）
+        if raw[i] == b'#' {
+            if let Some(pair) = raw
+                .get(i + 1..i + 3)
+                .filter(|pair| pair.iter().all(u8::is_ascii_hexdigit))
+            {
+                out.push(code_of_hex(pair));
+                i += 3;
+                continue;
+            }
+        }
+        out.push(raw[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn code_of_hex(pair: &[u8]) -> u8 {
+    pair.iter()
+        .filter_map(|c| (*c as char).to_digit(16))
+        .fold(0u8, |acc, d| (acc << 4) | d as u8)
+}
+
+fn decode_hex(hex: &[u8]) -> Vec<u8> {
+    let digits: Vec<u8> = hex
+        .iter()
+        .filter_map(|c| (*c as char).to_digit(16).map(|d| d as u8))
+        .collect();
+    digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair.get(1).copied().unwrap_or(0))
+        .collect()
+}
+
+// ==================== 文档 ====================
+
+static OBJ_HEADER: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d+)\s+(\d+)\s+obj\b").unwrap());
+
+struct Document {
+    objects: HashMap<u32, Obj>,
+}
+
+impl Document {
+    fn parse(data: &[u8]) -> Self {
+        let mut objects = HashMap::new();
+        let mut pos = 0;
+        while let Some(caps) = OBJ_HEADER.captures_at(data, pos) {
+            let whole = caps.get(0).map(|m| m.end()).unwrap_or(data.len());
+            let num: u32 = std::str::from_utf8(&caps[1])
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0);
+            let mut parser = Parser::new(data, whole);
+            if let Some(obj) = parser.parse_obj() {
+                // 增量更新时后出现的对象覆盖前者
+                objects.insert(num, obj);
+            }
+            pos = parser.pos.max(whole);
+        }
+
+        let mut doc = Self { objects };
+        doc.expand_object_streams();
+        doc
+    }
+
+    /// 展开 /Type /ObjStm 对象流
+    fn expand_object_streams(&mut self) {
+        let streams: Vec<(HashMap<String, Obj>, Vec<u8>)> = self
+            .objects
+            .values()
+            .filter_map(|obj| match obj {
+                Obj::Stream(dict, _)
+                    if dict.get("Type").and_then(Obj::as_name) == Some("ObjStm") =>
+                {
+                    Some((dict.clone(), self.stream_data(obj)?))
+                }
+                _ => None,
+            })
+            .collect();
+        for (dict, data) in &streams {
+            let count = dict.get("N").and_then(Obj::as_num).unwrap_or(0.0) as usize;
+            let first = dict.get("First").and_then(Obj::as_num).unwrap_or(0.0) as usize;
+            let mut header = Parser::new(data, 0);
+            for _ in 0..count {
+                let (Some(Obj::Num(num)), Some(Obj::Num(offset))) =
+                    (header.parse_obj(), header.parse_obj())
+                else {
+                    break;
+                };
+                let Some(at) = (offset >= 0.0)
+                    .then(|| first.checked_add(offset as usize))
+                    .flatten()
+                    .filter(|at| *at < data.len())
+                else {
+                    continue;
+                };
+                let mut parser = Parser::new(data, at);
+                if let Some(obj) = parser.parse_obj() {
+                    self.objects.entry(num as u32).or_insert(obj);
+                }
+            }
+        }
+    }
+
+    fn resolve<'b>(&'b self, obj: &'b Obj) -> &'b Obj {
+        let mut current = obj;
+        for _ in 0..8 {
+            match current {
+                Obj::Ref(num) => match self.objects.get(num) {
+                    Some(next) => current = next,
+                    None => return &Obj::Null,
+                },
+                _ => return current,
+            }
+        }
+        &Obj::Null
+    }
+
+    fn get<'b>(&'b self, dict: &'b HashMap<String, Obj>, key: &str) -> Option<&'b Obj> {
+        dict.get(key).map(|v| self.resolve(v))
+    }
+
+    /// 解码流内容；含不支持的过滤器时返回 None
+    fn stream_data(&self, obj: &Obj) -> Option<Vec<u8>> {
+        let Obj::Stream(dict, raw) = self.resolve(obj) else {
+            return None;
+        };
+        let filters: Vec<&str> = match self.get(dict, "Filter") {
+            Some(Obj::Name(name)) => vec![name.as_str()],
+            Some(Obj::Array(items)) => items
+                .iter()
+                .filter_map(|i| self.resolve(i).as_name())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let mut data = raw.clone();
+        for filter in filters {
+            data = match filter {
+                "FlateDecode" | "Fl" => inflate::inflate(&data)?,
+                _ => return None,
+            };
+        }
+        Some(data)
+    }
+
+    fn is_encrypted(data: &[u8]) -> bool {
+        find(data, b"/Encrypt").is_some()
+    }
+
+    /// 按页面树顺序返回页面字典（对象号）
+    fn pages(&self) -> Vec<u32> {
+        let root = self.objects.iter().find(|(_, obj)| {
+            obj.as_dict()
+                .and_then(|d| d.get("Type"))
+                .and_then(Obj::as_name)
+                == Some("Catalog")
+        });
+        let mut pages = Vec::new();
+        if let Some(Obj::Ref(pages_ref)) = root.and_then(|(_, c)| c.as_dict()?.get("Pages")) {
+            self.walk_pages(*pages_ref, 0, &mut pages);
+        }
+        if pages.is_empty() {
+            // 无 Catalog 或页面树损坏时按对象号顺序兜底
+            let mut all: Vec<u32> = self
+                .objects
+                .iter()
+                .filter(|(_, obj)| {
+                    obj.as_dict()
+                        .and_then(|d| d.get("Type"))
+                        .and_then(Obj::as_name)
+                        == Some("Page")
+                })
+                .map(|(num, _)| *num)
+                .collect();
+            all.sort_unstable();
+            pages = all;
+        }
+        pages
+    }
+
+    fn walk_pages(&self, num: u32, depth: usize, out: &mut Vec<u32>) {
+        if depth > MAX_TREE_DEPTH {
+            return;
+        }
+        let Some(dict) = self.objects.get(&num).and_then(Obj::as_dict) else {
+            return;
+        };
+        match dict.get("Kids").map(|k| self.resolve(k)) {
+            Some(Obj::Array(kids)) => {
+                for kid in kids {
+                    if let Obj::Ref(kid) = kid {
+                        self.walk_pages(*kid, depth + 1, out);
+                    }
+                }
+            }
+            _ => out.push(num),
+        }
+    }
+
+    /// 页面属性（支持从父节点继承）
+    fn inherited<'b>(&'b self, page: &'b HashMap<String, Obj>, key: &str) -> Option<&'b Obj> {
+        let mut dict = page;
+        for _ in 0..MAX_TREE_DEPTH {
+            if let Some(value) = self.get(dict, key) {
+                return Some(value);
+            }
+            dict = self.get(dict, "Parent")?.as_dict()?;
+        }
+        None
+    }
+
+    fn page_fonts(&self, page: &HashMap<String, Obj>) -> HashMap<String, Font> {
+        let mut fonts = HashMap::new();
+        let Some(font_dict) = self
+            .inherited(page, "Resources")
+            .and_then(Obj::as_dict)
+            .and_then(|res| self.get(res, "Font"))
+            .and_then(Obj::as_dict)
+        else {
+            return fonts;
+        };
+        for (name, font) in font_dict {
+            let Some(font) = self.resolve(font).as_dict() else {
+                continue;
+            };
+            let cmap = font
+                .get("ToUnicode")
+                .and_then(|s| self.stream_data(s))
+                .map(|data| parse_cmap(&data));
+            let identity = self
+                .get(font, "Encoding")
+                .and_then(Obj::as_name)
+                .is_some_and(|e| e.starts_with("Identity"));
+            fonts.insert(name.clone(), Font { cmap, identity });
+        }
+        fonts
+    }
+
+    fn page_text(&self, num: u32) -> String {
+        let Some(page) = self.objects.get(&num).and_then(Obj::as_dict) else {
+            return String::new();
+        };
+        let mut content = Vec::new();
+        match page.get("Contents") {
+            Some(Obj::Array(parts)) => {
+                for part in parts {
+                    if let Some(data) = self.stream_data(part) {
+                        content.extend_from_slice(&data);
+                        content.push(b'\n');
+                    }
+                }
+            }
+            Some(obj) => match self.resolve(obj) {
+                Obj::Array(parts) => {
+                    for part in parts {
+                        if let Some(data) = self.stream_data(part) {
+                            content.extend_from_slice(&data);
+                            content.push(b'\n');
+                        }
+                    }
+                }
+                _ => {
+                    if let Some(data) = self.stream_data(obj) {
+                        content = data;
+                    }
+                }
+            },
+            None => {}
+        }
+        extract_content_text(&content, &self.page_fonts(page))
+    }
+}
+
+// ==================== 内容流文本 ====================
+
+struct CMap {
+    /// 编码字节宽度（1 或 2）
+    width: usize,
+    map: HashMap<u32, String>,
+}
+
+struct Font {
+    cmap: Option<CMap>,
+    identity: bool,
+}
+
+fn utf16_be(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], c.get(1).copied().unwrap_or(0)]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn code_of(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32)
+}
+
+fn parse_cmap(data: &[u8]) -> CMap {
+    let mut parser = Parser::new(data, 0);
+    let mut operands: Vec<Obj> = Vec::new();
+    let mut map = HashMap::new();
+    let mut width = 0;
+    let mut section = "";
+    while let Some(token) = parser.next_token() {
+        match token {
+            Token::Obj(obj) => {
+                operands.push(obj);
+                match section {
+                    "codespace" if operands.len() == 2 => {
+                        if let Obj::Str(lo) = &operands[0] {
+                            width = width.max(lo.len());
+                        }
+                        operands.clear();
+                    }
+                    "bfchar" if operands.len() == 2 => {
+                        if let (Obj::Str(src), Obj::Str(dst)) = (&operands[0], &operands[1]) {
+                            width = width.max(src.len());
+                            map.insert(code_of(src), utf16_be(dst));
+                        }
+                        operands.clear();
+                    }
+                    "bfrange" if operands.len() == 3 => {
+                        if let (Obj::Str(lo), Obj::Str(hi)) = (&operands[0], &operands[1]) {
+                            width = width.max(lo.len());
+                            let (lo, hi) = (code_of(lo), code_of(hi));
+                            match &operands[2] {
+                                Obj::Str(dst) if hi >= lo && hi - lo < 0x10000 => {
+                                    let mut units: Vec<u16> = dst
+                                        .chunks(2)
+                                        .map(|c| {
+                                            u16::from_be_bytes([
+                                                c[0],
+                                                c.get(1).copied().unwrap_or(0),
+                                            ])
+                                        })
+                                        .collect();
+                                    for code in lo..=hi {
+                                        map.insert(code, String::from_utf16_lossy(&units));
+                                        if let Some(last) = units.last_mut() {
+                                            *last = last.wrapping_add(1);
+                                        }
+                                    }
+                                }
+                                Obj::Array(items) => {
+                                    for (code, item) in (lo..=hi).zip(items) {
+                                        if let Obj::Str(dst) = item {
+                                            map.insert(code, utf16_be(dst));
+                                        }
+                                    }
+                                }
+                                _ => {}
+                            }
+                        }
+                        operands.clear();
+                    }
+                    _ => {}
+                }
+            }
+            Token::Op(op) => {
+                section = match op.as_str() {
+                    "begincodespacerange" => "codespace",
+                    "beginbfchar" => "bfchar",
+                    "beginbfrange" => "bfrange",
+                    _ => "",
+                };
+                operands.clear();
+            }
+        }
+    }
+    CMap {
+        width: width.clamp(1, 4),
+        map,
+    }
+}
+
+fn decode_string(bytes: &[u8], font: Option<&Font>) -> String {
+    match font {
+        Some(Font {
+            cmap: Some(cmap), ..
+        }) => bytes
+            .chunks(cmap.width)
+            .filter_map(|code| {
+                let code = code_of(code);
+                cmap.map
+                    .get(&code)
+                    .cloned()
+                    .or_else(|| (cmap.width == 1).then(|| char::from(code as u8).to_string()))
+            })
+            .collect(),
+        // Identity 编码又无 ToUnicode，无法还原文字
+        Some(Font { identity: true, .. }) => String::new(),
+        _ if bytes.starts_with(&[0xfe, 0xff]) => utf16_be(&bytes[2..]),
+        _ => bytes.iter().map(|b| char::from(*b)).collect(),
+    }
+}
+
+fn push_break(out: &mut String) {
+    while out.ends_with(' ') {
+        out.pop();
+    }
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+fn push_space(out: &mut String) {
+    if !out.is_empty() && !out.ends_with([' ', '\n']) {
+        out.push(' ');
+    }
+}
+
+fn extract_content_text(content: &[u8], fonts: &HashMap<String, Font>) -> String {
+    let mut parser = Parser::new(content, 0);
+    let mut operands: Vec<Obj> = Vec::new();
+    let mut out = String::new();
+    let mut font: Option<&Font> = None;
+    let mut last_y: Option<f64> = None;
+
+    while let Some(token) = parser.next_token() {
+        let op = match token {
+            Token::Obj(obj) => {
+                operands.push(obj);
+                continue;
+            }
+            Token::Op(op) => op,
+        };
+        match op.as_str() {
+            "Tf" => {
+                if let Some(Obj::Name(name)) = operands.first() {
+                    font = fonts.get(name);
+                }
+            }
+            "Tj" => {
+                if let Some(Obj::Str(s)) = operands.last() {
+                    out.push_str(&decode_string(s, font));
+                }
+            }
+            "'" | "\"" => {
+                push_break(&mut out);
+                if let Some(Obj::Str(s)) = operands.last() {
+                    out.push_str(&decode_string(s, font));
+                }
+            }
+            "TJ" => {
+                if let Some(Obj::Array(items)) = operands.last() {
+                    for item in items {
+                        match item {
+                            Obj::Str(s) => out.push_str(&decode_string(s, font)),
+                            // 较大的负字距视为词间空格
+                            Obj::Num(n) if *n < -180.0 => push_space(&mut out),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            "Td" | "TD" => {
+                let ty = operands.get(1).and_then(Obj::as_num).unwrap_or(0.0);
+                if ty.abs() > 0.01 {
+                    push_break(&mut out);
+                } else {
+                    push_space(&mut out);
+                }
+            }
+            "T*" => push_break(&mut out),
+            "Tm" => {
+                let y = operands.get(5).and_then(Obj::as_num);
+                if y.is_some() && last_y.is_some_and(|last| (last - y.unwrap_or(0.0)).abs() > 0.01)
+                {
+                    push_break(&mut out);
+                } else {
+                    push_space(&mut out);
+                }
+                last_y = y;
+            }
+            "ET" => push_space(&mut out),
+            // 内联图像：跳过二进制数据
+            "ID" => match content.get(parser.pos..).and_then(|rest| find(rest, b"EI")) {
+                Some(p) => parser.pos += p + 2,
+                None => break,
+            },
+            _ => {}
+        }
+        operands.clear();
+    }
+
+    out.lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 在阻塞线程池中提取 PDF 文本（解析与解压是 CPU 密集操作，不占用异步工作线程）
+pub(crate) async fn extract_blocking(data: Vec<u8>, settings: PdfSettings) -> Result<String> {
+    tokio::task::spawn_blocking(move || extract(&data, &settings))
+        .await
+        .map_err(|e| anyhow!("PDF 提取任务异常结束: {}", e))?
+}
+
+/// 提取 PDF 文本，按页分隔并应用页数 / 字符上限
+pub(crate) fn extract(data: &[u8], settings: &PdfSettings) -> Result<String> {
+    if !data.starts_with(b"%PDF-") {
+        return Err(anyhow!("不是有效的 PDF 文件"));
+    }
+    if Document::is_encrypted(data) {
+        return Err(anyhow!("PDF 已加密，无法提取文本"));
+    }
+    let doc = Document::parse(data);
+    let pages = doc.pages();
+    if pages.is_empty() {
+        return Err(anyhow!("PDF 中未找到页面"));
+    }
+
+    let total = pages.len();
+    let mut out = String::new();
+    let mut truncated = false;
+    for (index, page) in pages.iter().enumerate() {
+        if index >= settings.max_pages {
+            truncated = true;
+            break;
+        }
+        let text = doc.page_text(*page);
+        if text.is_empty() {
+            continue;
+        }
+        out.push_str(&format!(
+            "--- 第 {} / {} 页 ---\n{}\n\n",
+            index + 1,
+            total,
+            text
+        ));
+        if out.chars().count() > settings.max_chars {
+            out = out.chars().take(settings.max_chars).collect();
+            truncated = true;
+            break;
+        }
+    }
+
+    if out.trim().is_empty() {
+        return Err(anyhow!("PDF 中没有可提取的文本（可能是扫描件）"));
+    }
+    if truncated {
+        out.push_str(&format!(
+            "\n（内容已截断：共 {} 页，上限 {} 页 / {} 字符）",
+            total, settings.max_pages, settings.max_chars
+        ));
+    }
+    Ok(out.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 拼装最小文本型 PDF（内容流未压缩）
+    fn sample_pdf(content: &str) -> Vec<u8> {
+        format!(
+            "%PDF-1.4\n\
+             1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj\n\
+             2 0 obj << /Type /Pages /Kids [3 0 R] /Count 1 >> endobj\n\
+             3 0 obj << /Type /Page /Parent 2 0 R /Contents 4 0 R >> endobj\n\
+             4 0 obj << /Length {} >>\nstream\n{}\nendstream\nendobj\n\
+             trailer << /Root 1 0 R >>\n%%EOF\n",
+            content.len(),
+            content
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn extracts_text_operators() {
+        let pdf = sample_pdf("BT (Hello) Tj 0 -12 Td [(Wor) -20 (ld)] TJ ET");
+        let text = extract(&pdf, &PdfSettings::default()).unwrap();
+        assert!(text.contains("Hello\nWorld"), "{}", text);
+    }
+
+    #[test]
+    fn truncated_input_never_panics() {
+        let pdf = sample_pdf("BT <48656c6c6f> Tj (a\\(b\\)) Tj ET");
+        for end in 0..pdf.len() {
+            let _ = extract(&pdf[..end], &PdfSettings::default());
+        }
+    }
+
+    #[test]
+    fn malformed_objects_are_rejected() {
+        let cases: &[&[u8]] = &[
+            // 字典在 `>` 处截断
+            b"%PDF-1.4\n1 0 obj << /Type /Page >",
+            b"%PDF-1.4\n1 0 obj << /Type /Page >> stre",
+            // 十六进制字符串缺少结束的 `>`
+            b"%PDF-1.4\n1 0 obj <48656c",
+            // 超大 / 负数 / 非有限的 /Length
+            b"%PDF-1.4\n1 0 obj << /Length 1e300 >>\nstream\nabc\nendstream endobj",
+            b"%PDF-1.4\n1 0 obj << /Length -5 >>\nstream\nabc\nendstream endobj",
+            b"%PDF-1.4\n1 0 obj << /Length 18446744073709551615 >>\nstream\nabc",
+            // 对象流的偏移溢出
+            b"%PDF-1.4\n1 0 obj << /Type /ObjStm /N 1 /First 18446744073709551615 >>\nstream\n5 1e300\nendstream endobj",
+            // 内联图像缺少 EI
+            b"%PDF-1.4\n1 0 obj << /Type /Page /Contents 2 0 R >> endobj 2 0 obj << >>\nstream\nBI ID \x00\x01",
+        ];
+        for case in cases {
+            assert!(extract(case, &PdfSettings::default()).is_err());
+        }
+    }
+
+    #[test]
+    fn unterminated_hex_string_is_none() {
+        let mut parser = Parser::new(b"<4142", 0);
+        assert!(parser.parse_obj().is_none());
+        let mut parser = Parser::new(b"<4142>", 0);
+        assert!(matches!(parser.parse_obj(), Some(Obj::Str(s)) if s == b"AB"));
+    }
+
+    #[test]
+    fn stream_length_is_validated() {
+        let data = b"<< /Length 3 >>\nstream\nabc\nendstream";
+        let mut parser = Parser::new(data, 0);
+        assert!(matches!(parser.parse_obj(), Some(Obj::Stream(_, c)) if c == b"abc"));
+        // 声明的长度与 endstream 位置不符时按 endstream 定位
+        let data = b"<< /Length 99999999999 >>\nstream\nabc\nendstream";
+        let mut parser = Parser::new(data, 0);
+        assert!(matches!(parser.parse_obj(), Some(Obj::Stream(_, c)) if c == b"abc"));
+        assert_eq!(parser.pos, data.len());
+    }
+
+    #[test]
+    fn name_escapes() {
+        assert_eq!(decode_name(b"A#42C"), "ABC");
+        assert_eq!(decode_name(b"A#4"), "A#4");
+        assert_eq!(decode_name(b"A#"), "A#");
+        assert_eq!(decode_name(b"#41"), "A");
+        assert_eq!(decode_name(b"A#+1"), "A#+1");
+        assert_eq!(decode_name(b"A#zz"), "A#zz");
+    }
+
+    #[test]
+    fn cmap_maps_cid_strings() {
+        let cmap = parse_cmap(
+            b"1 begincodespacerange <0000> <ffff> endcodespacerange\n\
+              1 beginbfchar <0001> <4e2d> endbfchar\n\
+              1 beginbfrange <0002> <0003> <6587> endbfrange",
+        );
+        let font = Font {
+            cmap: Some(cmap),
+            identity: true,
+        };
+        assert_eq!(decode_string(&[0, 1, 0, 2, 0, 3], Some(&font)), "中文斈");
+    }
+
+    #[tokio::test]
+    async fn extract_blocking_runs_off_thread() {
+        let pdf = sample_pdf("BT (async) Tj ET");
+        let text = extract_blocking(pdf, PdfSettings::default()).await.unwrap();
+        assert!(text.contains("async"));
+    }
+}
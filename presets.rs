@@ -0,0 +1,175 @@
+// 会话预设（Prompt Presets）
+//
+// 每个 Profile 可定义若干命名预设：附加 system 指令、默认 temperature、强制模型。
+// 选择顺序：控制头 x-dc-preset > 工作区映射（workspace_header 指定的请求头）> 不应用。
+// 例如 "review"（只读审查）与 "implement"（直接改代码）两种 Agent 模式。
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use super::transform_middleware::TransformTarget;
+use hyper::HeaderMap as HyperHeaderMap;
+
+/// 显式选择预设的控制头
+pub(crate) const PRESET_HEADER: &str = "x-dc-preset";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresetSettings {
+    /// 附加到 system / instructions / systemInstruction 末尾
+    pub system: Option<String>,
+    /// 请求未指定 temperature 时使用
+    pub temperature: Option<f64>,
+    /// 强制使用的模型
+    pub model: Option<String>,
+    /// 强制回复语言（覆盖 Profile 的 response_language）
+    pub response_language: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresetRouting {
+    pub presets: HashMap<String, PresetSettings>,
+    /// 工作区标识 → 预设名
+    pub workspace_presets: HashMap<String, String>,
+    /// 携带工作区标识的请求头
+    pub workspace_header: String,
+}
+
+impl Default for PresetRouting {
+    fn default() -> Self {
+        Self {
+            presets: HashMap::new(),
+            workspace_presets: HashMap::new(),
+            workspace_header: "x-dc-workspace".to_string(),
+        }
+    }
+}
+
+impl PresetRouting {
+    /// 解析当前请求命中的预设
+    pub(crate) fn select(&self, headers: &HyperHeaderMap) -> Option<(&str, &PresetSettings)> {
+        if self.presets.is_empty() {
+            return None;
+        }
+        let header_value = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+        let name = match header_value(PRESET_HEADER) {
+            Some(n) => n,
+            None => {
+                let workspace = header_value(&self.workspace_header)?;
+                self.workspace_presets.get(workspace)?.as_str()
+            }
+        };
+
+        match self.presets.get_key_value(name) {
+            Some((k, v)) => Some((k.as_str(), v)),
+            None => {
+                tracing::warn!("未找到预设: {}", name);
+                None
+            }
+        }
+    }
+}
+
+/// 回复语言约束指令
+pub(crate) fn language_instruction(language: &str) -> String {
+    format!(
+        "Always respond in {}, regardless of the language used in the prompt. Keep code, identifiers, file paths and tool inputs unchanged.",
+        language
+    )
+}
+
+/// 应用预设到请求体，返回 None 表示未改写
+pub(crate) fn apply(
+    target: TransformTarget,
+    preset: &PresetSettings,
+    body: &[u8],
+) -> Option<Vec<u8>> {
+    if body.is_empty() {
+        return None;
+    }
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    let obj = json.as_object_mut()?;
+
+    if let Some(model) = &preset.model {
+        if target != TransformTarget::Gemini {
+            obj.insert("model".into(), json!(model));
+        }
+    }
+
+    if let Some(temp) = preset.temperature {
+        match target {
+            TransformTarget::Gemini => {
+                let cfg = obj.entry("generationConfig").or_insert_with(|| json!({}));
+                if let Some(cfg) = cfg.as_object_mut() {
+                    cfg.entry("temperature").or_insert(json!(temp));
+                }
+            }
+            _ => {
+                obj.entry("temperature").or_insert(json!(temp));
+            }
+        }
+    }
+
+    if let Some(extra) = &preset.system {
+        match target {
+            TransformTarget::Claude => match obj.get_mut("system") {
+                Some(Value::Array(items)) => items.push(json!({ "type": "text", "text": extra })),
+                Some(Value::String(s)) => {
+                    s.push_str("\n\n");
+                    s.push_str(extra);
+                }
+                _ => {
+                    obj.insert("system".into(), json!([{ "type": "text", "text": extra }]));
+                }
+            },
+            TransformTarget::Codex => {
+                if obj.contains_key("input") {
+                    // Responses API：追加到 instructions
+                    let merged = match obj.get("instructions").and_then(|i| i.as_str()) {
+                        Some(existing) if !existing.is_empty() => {
+                            format!("{}\n\n{}", existing, extra)
+                        }
+                        _ => extra.clone(),
+                    };
+                    obj.insert("instructions".into(), json!(merged));
+                } else if let Some(messages) =
+                    obj.get_mut("messages").and_then(|m| m.as_array_mut())
+                {
+                    // Chat Completions：追加 system 消息到最前
+                    messages.insert(0, json!({ "role": "system", "content": extra }));
+                }
+            }
+            TransformTarget::Gemini => {
+                let instruction = obj
+                    .entry("systemInstruction")
+                    .or_insert_with(|| json!({ "parts": [] }));
+                if let Some(parts) = instruction.get_mut("parts").and_then(|p| p.as_array_mut()) {
+                    parts.push(json!({ "text": extra }));
+                }
+            }
+        }
+    }
+
+    serde_json::to_vec(&json).ok()
+}
+
+/// Gemini 强制模型：改写路径中的模型名
+pub(crate) fn apply_to_gemini_path(preset: &PresetSettings, path: &str) -> String {
+    match &preset.model {
+        Some(model) => {
+            let mut aliases = HashMap::new();
+            let current = path
+                .find("/models/")
+                .map(|s| &path[s + "/models/".len()..])
+                .map(|after| &after[..after.find([':', '/', '?']).unwrap_or(after.len())]);
+            if let Some(current) = current {
+                aliases.insert(current.to_string(), model.clone());
+            }
+            super::transform_middleware::apply_model_alias_to_path(path, &aliases)
+        }
+        None => path.to_string(),
+    }
+}
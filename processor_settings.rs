@@ -0,0 +1,529 @@
+// 请求处理器扩展配置
+//
+// 存放于 ~/.duckcoding/processor_settings.json，按 tool_id 分组。
+// 文件不存在或字段缺失时使用默认值（与既有行为一致）。
+// 嵌入方 / 测试可用 AmpConfig 把代码中构建的配置安装到 AppState（见 amp_config.rs、app_state.rs），
+// 该 AppState 此后不再经本模块读写文件；本模块只负责配置文件本身。
+// 配置文件中的密钥字段（见 secrets_mut）保存时存入密钥存储、文件中只写引用，读取时还原为明文；
+// 文件中残留的明文密钥在读取时透明迁移并写回（见 secret_store.rs）。
+
+use super::admin_api::AdminSettings;
+use super::amp_auth::AmpAuthSettings;
+use super::amp_poll_cache::PollCacheSettings;
+use super::amp_session::HeaderCaptureSettings;
+use super::azure_openai::AzureSettings;
+use super::bedrock_processor::BedrockSettings;
+use super::budget::BudgetSettings;
+use super::cache_diff::CacheDiffSettings;
+use super::capacity::CapacitySettings;
+use super::chaos::ChaosSettings;
+use super::cheap_model::CheapModelSettings;
+use super::dashboard::DashboardSettings;
+use super::docs_search::DocsSearchSettings;
+use super::error_lookup::ErrorLookupSettings;
+use super::experiments::ExperimentSettings;
+use super::http_tool::HttpRequestSettings;
+use super::inbound_auth::InboundAuthSettings;
+use super::maintenance::MaintenanceSettings;
+use super::memory_store::MemorySettings;
+use super::mock_upstream::MockUpstreamSettings;
+use super::model_guard::ModelGuardSettings;
+use super::openai_translate::UpstreamProtocol;
+use super::outbound::OutboundBinding;
+use super::output_cap::OutputCapSettings;
+use super::overload_queue::OverloadSettings;
+use super::package_registry::PackageRegistrySettings;
+use super::presets::PresetRouting;
+use super::request_log::RequestLogSettings;
+use super::response_cache::ResponseCacheSettings;
+use super::routing_rules::RoutingSettings;
+use super::sampling_policy::SamplingPolicy;
+use super::schema_drift::SchemaDriftSettings;
+use super::search_providers::SearchSettings;
+use super::secret_store::{self, SecretStore};
+use super::stall_guard::StallSettings;
+use super::telemetry::TelemetrySettings;
+use super::tool_batching::ToolBatchSettings;
+use super::tool_cache::ToolCacheSettings;
+use super::tool_credits::ToolCreditSettings;
+use super::tool_toggle::ToolToggleSettings;
+use super::transform_validation::StrictMode;
+use super::upstream::{FailoverSettings, RetrySettings};
+use super::url_policy::UrlPolicySettings;
+use super::user_fingerprint::UserHashAlgorithm;
+use super::utility_tools::WeatherSettings;
+use super::web_extract::WebExtractSettings;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+const SETTINGS_FILE: &str = "processor_settings.json";
+
+/// 未配置槽位的默认 Profile 配置
+static DEFAULT_PROFILE: Lazy<ProfileSettings> = Lazy::new(ProfileSettings::default);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessorSettings {
+    /// tool_id → 工具级配置
+    pub tools: HashMap<String, ToolSettings>,
+    /// Profile 级配置，键为 AMP Profile 槽位（claude / codex / gemini）
+    pub profiles: HashMap<String, ProfileSettings>,
+    /// 提示词 A/B 实验（仅第一个启用的实验生效）
+    pub experiments: Vec<ExperimentSettings>,
+    /// 长期记忆注入（Claude 路由）
+    pub memory: MemorySettings,
+    /// 转换严格校验：off / log / reject
+    pub strict_mode: StrictMode,
+    /// 本地工具（搜索 / 网页提取）出站绑定
+    pub tools_outbound: OutboundBinding,
+    /// 本地工具每日出站字节上限（None 不限制）
+    pub tools_egress_cap_bytes_per_day: Option<u64>,
+    /// 本地工具外部请求的停滞检测（只支持 abort）
+    pub tools_stall: StallSettings,
+    /// AmpInternal 轮询合并与缓存
+    pub amp_poll_cache: PollCacheSettings,
+    /// AmpInternal 端点鉴权要求
+    pub amp_auth: AmpAuthSettings,
+    /// 剥离前采集的 x-amp-* 请求头
+    pub amp_header_capture: HeaderCaptureSettings,
+    /// 请求结构漂移检测
+    pub schema_drift: SchemaDriftSettings,
+    /// 无可用 Profile 时的维护应答
+    pub maintenance: MaintenanceSettings,
+    /// 同轮并行 tool_result 合批（Claude 路由）
+    pub tool_batching: ToolBatchSettings,
+    /// Azure OpenAI Profile（/api/provider/azure/*）
+    pub azure: AzureSettings,
+    /// ProfileManager 中的 Profile 名 → AWS Bedrock 配置：Claude 槽位选中该 Profile 时经 Bedrock 转发
+    pub bedrock: HashMap<String, BedrockSettings>,
+    /// 路由规则（先于内置路径识别匹配）
+    pub routing: RoutingSettings,
+    /// 浏览器只读观测面板
+    pub dashboard: DashboardSettings,
+    /// 本地管理 API（运行时切换 Profile、清空缓存等）
+    pub admin: AdminSettings,
+    /// 上游失败时切换备用配置
+    pub failover: FailoverSettings,
+    /// 入站请求按内置 Schema 校验：off / log / reject
+    pub request_validation: StrictMode,
+    /// 上游请求重试（指数退避 + retry-after）
+    pub retry: RetrySettings,
+    /// 故障注入（仅测试 / 预发使用）
+    pub chaos: ChaosSettings,
+    /// 按分钟记录流量与容量模拟场景
+    pub capacity: CapacitySettings,
+    /// 按会话记录 Claude 请求前缀，排查缓存写入
+    pub cache_diff: CacheDiffSettings,
+    /// OpenTelemetry 链路追踪（OTLP 导出）
+    pub telemetry: TelemetrySettings,
+    /// Claude 529 过载排队
+    pub overload: OverloadSettings,
+    /// 请求 / 用量日志（SQLite）
+    pub request_log: RequestLogSettings,
+    /// 代理入站鉴权（本地签发的虚拟 Key）
+    pub inbound_auth: InboundAuthSettings,
+    /// 本地摘要 / 翻译工具使用的廉价模型与 Profile
+    pub cheap_model: CheapModelSettings,
+    /// 本地搜索提供方链（见 search_providers.rs）
+    pub search: SearchSettings,
+    /// getWeather 本地工具的天气服务
+    pub weather: WeatherSettings,
+    /// httpRequest 本地工具（域名 / 方法允许列表与大小限制）
+    pub http_request: HttpRequestSettings,
+    /// 包仓库查询工具（结果缓存）
+    pub package_registry: PackageRegistrySettings,
+    /// searchDocs 文档源，及是否合并到 webSearch2
+    pub docs_search: DocsSearchSettings,
+    /// extractWebPageContent 提取模式（raw / readability / markdown）
+    pub web_extract: WebExtractSettings,
+    /// webSearch2 报错 / 堆栈类查询的专门流程（Stack Overflow、GitHub Issues）
+    pub error_lookup: ErrorLookupSettings,
+    /// webSearch2 / extractWebPageContent 结果缓存（LRU + TTL，可持久化）
+    pub tool_cache: ToolCacheSettings,
+    /// 本地工具 URL 访问策略（内部主机允许列表 / 域名禁止列表）
+    pub url_policy: UrlPolicySettings,
+    /// 按工具禁用本地工具（并从 AMP 工具列表中删除）
+    pub tool_toggle: ToolToggleSettings,
+    /// 本地工具按次费用（计入预算，并作为 creditsConsumed 返回）
+    pub tool_credits: ToolCreditSettings,
+}
+
+/// 单个 tool_id 的配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolSettings {
+    pub transforms: Option<TransformSettings>,
+}
+
+/// 请求体转换开关（见 transform_middleware）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransformSettings {
+    /// 缺少 metadata.user_id 时注入
+    pub inject_metadata: bool,
+    /// 统一 cache_control 为 5m ttl
+    pub normalize_cache_control: bool,
+    /// 模型别名：请求模型名 → 实际模型名
+    pub model_aliases: HashMap<String, String>,
+    /// 保留最近 N 个用户轮次的工具输出，更早的替换为占位符（None 不省略）
+    pub elide_tool_results_after: Option<usize>,
+    /// 低于该 token 数的工具输出不省略，默认 200
+    pub elide_min_tokens: Option<usize>,
+    /// 修复消息顺序：合并连续同角色消息、删除孤立工具块（Claude）
+    pub repair_message_order: bool,
+    /// metadata.user_id 指纹算法，默认 sha256（与旧版本一致）
+    pub user_hash: UserHashAlgorithm,
+}
+
+/// 单个 Profile 的配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileSettings {
+    /// 固定发送的 anthropic-version（覆盖客户端传入值）
+    pub anthropic_version: Option<String>,
+    /// 后端拒绝当前版本时自动降级到更旧的已知版本
+    pub anthropic_version_auto_downgrade: bool,
+    /// 调试模式：请求 logprobs 并采集调试响应头到审计日志（不返回给 AMP）
+    pub debug: bool,
+    /// 调试模式下的 top_logprobs，默认 5
+    pub debug_top_logprobs: Option<u8>,
+    /// 命名预设与工作区映射
+    #[serde(flatten)]
+    pub presets: PresetRouting,
+    /// 出站源 IP / 网卡绑定
+    pub outbound: OutboundBinding,
+    /// 每日出站字节上限（None 不限制）
+    pub egress_cap_bytes_per_day: Option<u64>,
+    /// 各项注入行为开关（默认全部开启，与既有行为一致）
+    pub injections: InjectionFlags,
+    /// 后端不支持 tools 时启用 ReAct 兼容层（仅 Claude 路由）
+    pub react_shim: bool,
+    /// 采样参数覆盖 / 钳制 / 删除
+    pub sampling: SamplingPolicy,
+    /// 强制回复语言（如 "Chinese"），命中的预设可覆盖
+    pub response_language: Option<String>,
+    /// user_id 指纹改用本地随机生成的 Profile 标识，不再由 API Key 派生
+    pub opaque_user_id: bool,
+    /// 上游协议（claude / codex 槽位）：openai_chat 时转换为 chat/completions
+    pub upstream_protocol: UpstreamProtocol,
+    /// OpenAI-Organization 请求头（codex / azure 槽位，企业账号需要）
+    pub openai_organization: Option<String>,
+    /// OpenAI-Project 请求头（codex / azure 槽位）
+    pub openai_project: Option<String>,
+    /// 固定附加的请求头（网关 Key、路由提示等），在其他请求头处理之后写入，可覆盖同名头
+    pub static_headers: HashMap<String, String>,
+    /// 模型名（或以 * 结尾的前缀）→ ProxyConfigManager 工具 ID，按模型改用不同上游
+    pub model_profiles: HashMap<String, String>,
+    /// 昂贵模型保护：超过允许档位的模型在本地拒绝
+    pub model_guard: ModelGuardSettings,
+    /// 每日 / 每月 token 或美元上限
+    pub budget: BudgetSettings,
+    /// 单次响应输出上限（流式超出时提前结束）
+    pub output_cap: OutputCapSettings,
+    /// 相同请求的响应缓存（默认关闭）
+    pub response_cache: ResponseCacheSettings,
+    /// 流式响应停滞检测（连续无数据时中止或重发）
+    pub stall: StallSettings,
+    /// 模拟上游：本地构造响应，不请求真实服务商（集成测试 / 演示）
+    pub mock: MockUpstreamSettings,
+}
+
+/// 处理器注入行为开关，后端不兼容某项改写时可单独关闭
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InjectionFlags {
+    /// system 最前面插入 Claude Code 身份声明（Claude）
+    pub system_preamble: bool,
+    /// system 文本中的品牌词替换（Claude）
+    pub brand_sanitize: bool,
+    /// 工具名加 mcp_ 前缀（Claude；关闭后响应侧无需再剥离前缀）
+    pub mcp_prefix: bool,
+    /// metadata.user_id 注入（Claude，同时受 transforms.inject_metadata 控制）
+    pub metadata: bool,
+    /// 合并必需的 anthropic-beta（Claude）
+    pub beta_merge: bool,
+    /// 目标 URL 追加 beta=true（Claude）
+    pub beta_query: bool,
+    /// 统一 cache_control 为 5m ttl（Claude）
+    pub cache_control: bool,
+    /// 改写 user-agent / x-app 为官方客户端（全部路由）
+    pub user_agent: bool,
+}
+
+impl Default for InjectionFlags {
+    fn default() -> Self {
+        Self {
+            system_preamble: true,
+            brand_sanitize: true,
+            mcp_prefix: true,
+            metadata: true,
+            beta_merge: true,
+            beta_query: true,
+            cache_control: true,
+            user_agent: true,
+        }
+    }
+}
+
+impl TransformSettings {
+    /// 未配置时的默认值：amp-code 保持原有 metadata 注入，其余工具不做改写
+    pub fn default_for(tool_id: &str) -> Self {
+        Self {
+            inject_metadata: tool_id == "amp-code",
+            ..Default::default()
+        }
+    }
+}
+
+impl ProcessorSettings {
+    pub fn path() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(".duckcoding")
+            .join(SETTINGS_FILE)
+    }
+
+    /// 读取配置；文件不存在返回默认值，解析失败返回错误（不静默忽略）
+    pub fn load() -> Result<Self> {
+        let Some(mut settings) = Self::read_file()? else {
+            return Ok(Self::default());
+        };
+        let store = secret_store::store();
+        if settings.protect_secrets(store.as_deref()) {
+            match Self::write_file(&settings) {
+                Ok(()) => tracing::info!("处理器配置中的明文密钥已迁移到密钥存储"),
+                Err(e) => tracing::warn!("{}，明文密钥暂未迁移", e),
+            }
+        }
+        settings.reveal_secrets(store.as_deref());
+        Ok(settings)
+    }
+
+    /// 配置文件原文（密钥为引用）；文件不存在返回 None
+    fn read_file() -> Result<Option<Self>> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = std::fs::read(&path).map_err(|e| anyhow!("读取处理器配置失败: {}", e))?;
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| anyhow!("处理器配置解析失败: {}", e))
+    }
+
+    /// 写入配置文件（先写临时文件再替换）
+    fn write_file(stored: &Self) -> Result<()> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| anyhow!("创建配置目录失败: {}", e))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(stored)?)
+            .map_err(|e| anyhow!("写入处理器配置失败: {}", e))?;
+        std::fs::rename(&tmp, &path).map_err(|e| anyhow!("替换处理器配置失败: {}", e))
+    }
+
+    /// 配置中的密钥字段（密钥存储账号名, 值）
+    fn secrets_mut(&mut self) -> Vec<(String, &mut String)> {
+        let mut secrets = vec![("azure/api_key".to_string(), &mut self.azure.api_key)];
+        for (name, bedrock) in self.bedrock.iter_mut() {
+            secrets.push((
+                format!("bedrock/{}/secret_access_key", name),
+                &mut bedrock.secret_access_key,
+            ));
+            if let Some(token) = bedrock.session_token.as_mut() {
+                secrets.push((format!("bedrock/{}/session_token", name), token));
+            }
+        }
+        for (name, token) in self.amp_auth.accounts.iter_mut() {
+            secrets.push((format!("amp/accounts/{}", name), token));
+        }
+        for (name, token) in self.amp_auth.oauth.refresh_tokens.iter_mut() {
+            secrets.push((format!("amp/oauth/{}", name), token));
+        }
+        if let Some(key) = self.search.brave_api_key.as_mut() {
+            secrets.push(("search/brave_api_key".to_string(), key));
+        }
+        if let Some(key) = self.weather.api_key.as_mut() {
+            secrets.push(("weather/api_key".to_string(), key));
+        }
+        if let Some(token) = self.admin.token.as_mut() {
+            secrets.push(("admin/token".to_string(), token));
+        }
+        if let Some(token) = self.dashboard.token.as_mut() {
+            secrets.push(("dashboard/token".to_string(), token));
+        }
+        if let Some(token) = self.error_lookup.github_token.as_mut() {
+            secrets.push(("error_lookup/github_token".to_string(), token));
+        }
+        if let Some(key) = self.web_extract.reader.api_key.as_mut() {
+            secrets.push(("web_extract/reader/api_key".to_string(), key));
+        }
+        secrets
+    }
+
+    /// 明文密钥存入密钥存储并替换为引用，返回是否有变化（存储不可用时保持明文）
+    fn protect_secrets(&mut self, store: Option<&dyn SecretStore>) -> bool {
+        let mut changed = false;
+        for (field, value) in self.secrets_mut() {
+            let protected =
+                secret_store::protect_in(store, &secret_store::settings_account(&field), value);
+            if protected != *value {
+                *value = protected;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// 引用还原为明文；存储中不存在时保留引用并告警（请求将因 Key 无效失败，而不是静默发送空 Key）
+    fn reveal_secrets(&mut self, store: Option<&dyn SecretStore>) {
+        for (field, value) in self.secrets_mut() {
+            if !secret_store::is_reference(value) {
+                continue;
+            }
+            match secret_store::reveal_in(store, value) {
+                Some(secret) => *value = secret,
+                None => tracing::warn!("处理器配置 {} 的密钥无法从密钥存储读取", field),
+            }
+        }
+    }
+
+    /// 配置中引用的密钥
+    fn secret_references(&mut self) -> Vec<String> {
+        self.secrets_mut()
+            .into_iter()
+            .map(|(_, value)| value.clone())
+            .filter(|value| secret_store::is_reference(value))
+            .collect()
+    }
+
+    /// 读取配置，失败时告警并回退默认值（用于请求路径，避免配置错误中断转发）
+    pub fn load_or_default() -> Self {
+        Self::load().unwrap_or_else(|e| {
+            tracing::warn!("{}，使用默认配置", e);
+            Self::default()
+        })
+    }
+
+    /// 写回配置文件（先写临时文件再替换，密钥写为引用）；
+    /// 经 AppState::save_settings 调用时该实例缓存的配置随之失效
+    pub fn save(&self) -> Result<()> {
+        let mut stored = self.clone();
+        stored.protect_secrets(secret_store::store().as_deref());
+        let previous = Self::read_file().ok().flatten();
+        Self::write_file(&stored)?;
+        // 配置中已移除的密钥同时从存储删除
+        if let Some(mut previous) = previous {
+            let current = stored.secret_references();
+            for reference in previous.secret_references() {
+                if !current.contains(&reference) {
+                    secret_store::forget(&reference);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 槽位配置；未配置时为默认值（借用，不复制）
+    pub fn profile(&self, profile_key: &str) -> &ProfileSettings {
+        self.profiles.get(profile_key).unwrap_or(&DEFAULT_PROFILE)
+    }
+
+    pub fn transforms_for(&self, tool_id: &str) -> TransformSettings {
+        self.tools
+            .get(tool_id)
+            .and_then(|t| t.transforms.clone())
+            .unwrap_or_else(|| TransformSettings::default_for(tool_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::secret_store::MemoryStore;
+    use super::*;
+
+    #[test]
+    fn secrets_stored_as_references() {
+        let store = MemoryStore::default();
+        let mut settings = ProcessorSettings::default();
+        settings.azure.api_key = "azure-key".to_string();
+        settings
+            .amp_auth
+            .accounts
+            .insert("work".to_string(), "amp-token".to_string());
+        settings.weather.api_key = Some("weather-key".to_string());
+        settings.admin.token = Some("admin-token".to_string());
+        settings.dashboard.token = Some("dashboard-token".to_string());
+        settings.error_lookup.github_token = Some("github-token".to_string());
+        settings.web_extract.reader.api_key = Some("reader-key".to_string());
+        settings.bedrock.insert(
+            "aws".to_string(),
+            BedrockSettings {
+                secret_access_key: "aws-secret".to_string(),
+                ..Default::default()
+            },
+        );
+
+        let mut stored = settings.clone();
+        assert!(stored.protect_secrets(Some(&store)));
+        assert!(!stored.protect_secrets(Some(&store)));
+        assert_eq!(stored.azure.api_key, "keychain:settings/azure/api_key");
+        let text = serde_json::to_string(&stored).unwrap();
+        for secret in [
+            "azure-key",
+            "amp-token",
+            "weather-key",
+            "aws-secret",
+            "admin-token",
+            "dashboard-token",
+            "github-token",
+            "reader-key",
+        ] {
+            assert!(!text.contains(secret), "{} 未替换为引用", secret);
+        }
+        assert_eq!(stored.secret_references().len(), 8);
+
+        stored.reveal_secrets(Some(&store));
+        assert_eq!(
+            serde_json::to_value(&stored).unwrap(),
+            serde_json::to_value(&settings).unwrap()
+        );
+    }
+
+    #[test]
+    fn missing_reference_kept() {
+        let mut settings = ProcessorSettings::default();
+        settings.search.brave_api_key = Some("keychain:settings/search/missing".to_string());
+        settings.reveal_secrets(Some(&MemoryStore::default()));
+        assert_eq!(
+            settings.search.brave_api_key.as_deref(),
+            Some("keychain:settings/search/missing")
+        );
+    }
+
+    #[test]
+    fn profile_borrows_configured_or_default() {
+        let mut settings = ProcessorSettings::default();
+        settings.profiles.insert(
+            "claude".to_string(),
+            ProfileSettings {
+                react_shim: true,
+                ..Default::default()
+            },
+        );
+        assert!(settings.profile("claude").react_shim);
+        assert!(std::ptr::eq(
+            settings.profile("claude"),
+            &settings.profiles["claude"]
+        ));
+        assert!(!settings.profile("gemini").react_shim);
+        assert!(std::ptr::eq(
+            settings.profile("gemini"),
+            settings.profile("codex")
+        ));
+    }
+}
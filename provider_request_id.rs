@@ -0,0 +1,101 @@
+// 上游请求 ID 追踪
+//
+// 上游每次响应（含重试 / 故障转移过程中的失败响应）都提取提供方请求 ID
+// （Anthropic request-id、OpenAI x-request-id、Gemini x-goog-request-id、Azure apim-request-id 等），
+// 连同路由、实际服务的配置与状态码写入审计日志（kind=upstream_response），
+// lookup() 按提供方请求 ID 反查本地记录，便于与 Anthropic / OpenAI 工单对照。
+// 最近的记录同时保留在内存索引中，命中时无需扫描审计日志。
+
+use super::audit_log::{self, AuditRecord};
+use hyper::HeaderMap as HyperHeaderMap;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// 按优先级排列的提供方请求 ID 头
+const ID_HEADERS: [&str; 7] = [
+    "request-id",
+    "x-request-id",
+    "x-goog-request-id",
+    "apim-request-id",
+    "x-amzn-requestid",
+    "x-ms-request-id",
+    "cf-ray",
+];
+
+const AUDIT_KIND: &str = "upstream_response";
+const MAX_INDEXED: usize = 1000;
+
+static RECENT: Lazy<Mutex<VecDeque<AuditRecord>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// 提取提供方请求 ID：(响应头名, 值)
+pub(crate) fn extract(headers: &HyperHeaderMap) -> Option<(&'static str, String)> {
+    ID_HEADERS.iter().find_map(|name| {
+        headers
+            .get(*name)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .map(|v| (*name, v.to_string()))
+    })
+}
+
+/// 记录一次上游响应（无请求 ID 时仍记录状态码，便于按时间对照）
+pub(crate) fn record(route: &str, served_by: &str, status: u16, headers: &HyperHeaderMap) {
+    let mut record = AuditRecord::new(AUDIT_KIND, route, served_by).with("status", json!(status));
+    if let Some((header, id)) = extract(headers) {
+        record = record
+            .with("provider_request_id", json!(id))
+            .with("provider_request_id_header", json!(header));
+    }
+    audit_log::append(&record);
+    if record.data.contains_key("provider_request_id") {
+        let mut recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() >= MAX_INDEXED {
+            recent.pop_front();
+        }
+        recent.push_back(record);
+    }
+}
+
+fn matches(record: &AuditRecord, id: &str) -> bool {
+    if record
+        .data
+        .get("provider_request_id")
+        .and_then(Value::as_str)
+        == Some(id)
+    {
+        return true;
+    }
+    // 调试模式采集的响应头中也可能带有请求 ID
+    record
+        .data
+        .get("headers")
+        .and_then(Value::as_object)
+        .is_some_and(|h| h.values().any(|v| v.as_str() == Some(id)))
+}
+
+/// 按提供方请求 ID 查找本地记录（内存索引优先，未命中时扫描审计日志）
+pub fn lookup(id: &str) -> Vec<AuditRecord> {
+    let id = id.trim();
+    if id.is_empty() {
+        return Vec::new();
+    }
+    let cached: Vec<AuditRecord> = RECENT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter(|r| matches(r, id))
+        .cloned()
+        .collect();
+    if !cached.is_empty() {
+        return cached;
+    }
+    let mut found = Vec::new();
+    audit_log::scan(|record| {
+        if matches(&record, id) {
+            found.push(record);
+        }
+    });
+    found
+}
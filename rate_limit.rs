@@ -0,0 +1,310 @@
+// 虚拟 Key 限流（令牌桶）
+//
+// 每个虚拟 Key 按路由（claude / codex / gemini / azure）分别维护两个令牌桶：
+// 请求数 / 分钟与 token / 分钟，容量等于每分钟额度，按秒匀速回填。
+// 限额配置在虚拟 Key 的 rate_limits 中（键为路由名，"*" 为未单独配置路由的默认值）；
+// ProxyConfigManager 不在本仓库内，无法扩展字段，因此与虚拟 Key 一起保存在处理器配置中。
+// - 准入时扣除 1 个请求与请求体的估算 token；响应结束后再扣除实际输出 token（可透支，
+//   透支部分由后续请求等待回填）
+// - 任一桶不足时不发往上游，直接返回 429 与 retry-after（各协议对应的错误体）
+// - 额度为 0 表示禁止该路由（一律 429）；不限流时省略该字段
+// 仅作用于经上游转发层发送的 LLM 请求；进程内计数，重启后清零，吊销虚拟 Key 时清除其计数。
+
+use super::inbound_auth::InboundAuthSettings;
+use super::transform_middleware::estimate_tokens;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// 额度为 0 时返回的 retry-after（秒）
+const DENIED_RETRY_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u64>,
+}
+
+struct Bucket {
+    capacity: f64,
+    level: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(capacity: f64) -> Self {
+        Self {
+            capacity,
+            level: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    /// 按经过时间回填，额度变更时同步容量
+    fn refill(&mut self, capacity: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.capacity = capacity;
+        self.level = (self.level + elapsed * capacity / 60.0).min(capacity);
+        self.updated = now;
+    }
+
+    /// 达到 needed 还需等待的秒数；容量为 0 时永远不足
+    fn wait_secs(&self, needed: f64) -> u64 {
+        if self.capacity <= 0.0 {
+            return DENIED_RETRY_SECS;
+        }
+        let missing = needed - self.level;
+        if missing <= 0.0 {
+            return 0;
+        }
+        (missing * 60.0 / self.capacity).ceil() as u64
+    }
+
+    /// 透支下限为一个完整容量，避免超大响应长期锁死
+    fn debit(&mut self, amount: f64) {
+        self.level = (self.level - amount).max(-self.capacity);
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+}
+
+/// (虚拟 Key, 路由) → 令牌桶
+static BUCKETS: Lazy<Mutex<HashMap<(String, String), Buckets>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn limit_for<'a>(
+    settings: &'a InboundAuthSettings,
+    key: &str,
+    route: &str,
+) -> Option<&'a RateLimit> {
+    let limits = &settings.keys.iter().find(|k| k.name == key)?.rate_limits;
+    limits.get(route).or_else(|| limits.get("*"))
+}
+
+/// 准入检查：通过时扣除额度，超限时返回建议等待秒数
+pub(crate) fn acquire(
+    settings: &InboundAuthSettings,
+    key: &str,
+    route: &str,
+    body: &[u8],
+) -> Result<(), u64> {
+    let Some(limit) = limit_for(settings, key, route) else {
+        return Ok(());
+    };
+    let estimated = estimate_tokens(&String::from_utf8_lossy(body)) as f64;
+    let mut buckets = BUCKETS.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = buckets
+        .entry((key.to_string(), route.to_string()))
+        .or_default();
+
+    let mut wait = 0;
+    for (bucket, capacity, needed) in [
+        (
+            &mut entry.requests,
+            limit.requests_per_minute.map(f64::from),
+            1.0,
+        ),
+        (
+            &mut entry.tokens,
+            limit.tokens_per_minute.map(|t| t as f64),
+            // token 桶只要求有余额，单个大请求不至于永远无法通过
+            f64::MIN_POSITIVE,
+        ),
+    ] {
+        match capacity {
+            Some(capacity) => {
+                let bucket = bucket.get_or_insert_with(|| Bucket::new(capacity));
+                bucket.refill(capacity);
+                wait = wait.max(bucket.wait_secs(needed));
+            }
+            None => *bucket = None,
+        }
+    }
+    if wait > 0 {
+        tracing::warn!(
+            "虚拟 Key {} 在 {} 路由超出限流，需等待 {} 秒",
+            key,
+            route,
+            wait
+        );
+        return Err(wait);
+    }
+    if let Some(bucket) = entry.requests.as_mut() {
+        bucket.debit(1.0);
+    }
+    if let Some(bucket) = entry.tokens.as_mut() {
+        bucket.debit(estimated);
+    }
+    Ok(())
+}
+
+/// 响应结束后扣除实际输出 token
+pub(crate) fn record_output(key: &str, route: &str, output_tokens: u64) {
+    let mut buckets = BUCKETS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(bucket) = buckets
+        .get_mut(&(key.to_string(), route.to_string()))
+        .and_then(|b| b.tokens.as_mut())
+    {
+        bucket.debit(output_tokens as f64);
+    }
+}
+
+/// 清除虚拟 Key 在各路由的计数（吊销时调用）
+pub(crate) fn forget_key(key: &str) {
+    BUCKETS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|(name, _), _| name != key);
+}
+
+/// 按路由协议构造 429 响应
+pub(crate) fn limited_response(route: &str, retry_after: u64) -> reqwest::Response {
+    let message = format!("虚拟 Key 超出限流，请 {} 秒后重试", retry_after);
+    let body = match route {
+        "claude" => json!({
+            "type": "error",
+            "error": { "type": "rate_limit_error", "message": message }
+        }),
+        "gemini" => json!({
+            "error": { "code": 429, "message": message, "status": "RESOURCE_EXHAUSTED" }
+        }),
+        _ => json!({
+            "error": { "message": message, "type": "rate_limit_exceeded", "code": "rate_limit_exceeded" }
+        }),
+    };
+    hyper::http::Response::builder()
+        .status(429)
+        .header("content-type", "application/json")
+        .header("retry-after", retry_after.to_string())
+        .body(reqwest::Body::from(body.to_string()))
+        .map(reqwest::Response::from)
+        .unwrap_or_else(|_| {
+            reqwest::Response::from(hyper::http::Response::new(reqwest::Body::from("")))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::inbound_auth::VirtualKey;
+    use super::*;
+
+    /// 每个测试使用独立的 Key 名，计数表为进程级
+    fn settings(key: &str, route: &str, limit: RateLimit) -> InboundAuthSettings {
+        InboundAuthSettings {
+            enabled: true,
+            keys: vec![VirtualKey {
+                name: key.into(),
+                rate_limits: HashMap::from([(route.to_string(), limit)]),
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn requests_per_minute_enforced() {
+        let s = settings(
+            "rpm",
+            "claude",
+            RateLimit {
+                requests_per_minute: Some(2),
+                tokens_per_minute: None,
+            },
+        );
+        assert!(acquire(&s, "rpm", "claude", b"{}").is_ok());
+        assert!(acquire(&s, "rpm", "claude", b"{}").is_ok());
+        let wait = acquire(&s, "rpm", "claude", b"{}").unwrap_err();
+        assert!((1..=30).contains(&wait), "wait={}", wait);
+        // 其他路由未配置限额
+        assert!(acquire(&s, "rpm", "codex", b"{}").is_ok());
+        // 未配置限额的 Key 不限流
+        assert!(acquire(&s, "other", "claude", b"{}").is_ok());
+    }
+
+    #[test]
+    fn zero_limit_denies() {
+        let s = settings(
+            "zero",
+            "*",
+            RateLimit {
+                requests_per_minute: Some(0),
+                tokens_per_minute: None,
+            },
+        );
+        assert_eq!(acquire(&s, "zero", "gemini", b"{}"), Err(DENIED_RETRY_SECS));
+
+        let s = settings(
+            "zero-tokens",
+            "claude",
+            RateLimit {
+                requests_per_minute: None,
+                tokens_per_minute: Some(0),
+            },
+        );
+        assert_eq!(
+            acquire(&s, "zero-tokens", "claude", b"{}"),
+            Err(DENIED_RETRY_SECS)
+        );
+    }
+
+    #[test]
+    fn token_bucket_allows_overdraft_then_waits() {
+        let s = settings(
+            "tpm",
+            "claude",
+            RateLimit {
+                requests_per_minute: None,
+                tokens_per_minute: Some(100),
+            },
+        );
+        // 单个大请求只要求有余额
+        let big = vec![b'x'; 4000];
+        assert!(acquire(&s, "tpm", "claude", &big).is_ok());
+        assert!(acquire(&s, "tpm", "claude", b"{}").is_err());
+
+        let s = settings(
+            "tpm-output",
+            "claude",
+            RateLimit {
+                requests_per_minute: None,
+                tokens_per_minute: Some(100),
+            },
+        );
+        assert!(acquire(&s, "tpm-output", "claude", b"{}").is_ok());
+        record_output("tpm-output", "claude", 500);
+        assert!(acquire(&s, "tpm-output", "claude", b"{}").is_err());
+    }
+
+    #[test]
+    fn forget_key_resets_buckets() {
+        let s = settings(
+            "revoked",
+            "claude",
+            RateLimit {
+                requests_per_minute: Some(1),
+                tokens_per_minute: None,
+            },
+        );
+        assert!(acquire(&s, "revoked", "claude", b"{}").is_ok());
+        assert!(acquire(&s, "revoked", "claude", b"{}").is_err());
+        forget_key("revoked");
+        assert!(acquire(&s, "revoked", "claude", b"{}").is_ok());
+    }
+
+    #[test]
+    fn limited_response_matches_route_protocol() {
+        let response = limited_response("claude", 7);
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers()["retry-after"], "7");
+        let response = limited_response("gemini", 7);
+        assert_eq!(response.status(), 429);
+    }
+}
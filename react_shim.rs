@@ -0,0 +1,455 @@
+// 无工具后端的 ReAct 兼容层（Claude 路由）
+//
+// 部分后端完全不支持 tools。启用后：
+// - 请求侧：把 tools 定义序列化进 system，移除 tools / tool_choice；
+//   历史中的 tool_use / tool_result 改写为文本形式的调用与观察结果
+// - 响应侧：从模型文本中解析 <tool_call>{...}</tool_call>，生成合成的 tool_use 块，
+//   stop_reason 改为 tool_use，使 AMP 仍可（降级）工作
+// 流式响应会缓存全部文本，在消息结束时一次性输出，失去逐字输出效果。
+
+use super::determinism;
+use super::sse::{self, SseEvent, SseParser};
+use bytes::Bytes;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+const CALL_OPEN: &str = "<tool_call>";
+const CALL_CLOSE: &str = "</tool_call>";
+
+/// 请求侧改写，返回 None 表示请求未携带工具、无需改写
+pub(crate) fn apply_request(body: &[u8]) -> Option<Vec<u8>> {
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    let obj = json.as_object_mut()?;
+    let tools = obj.remove("tools")?;
+    obj.remove("tool_choice");
+    let tools = tools.as_array().cloned().unwrap_or_default();
+
+    let mut modified = !tools.is_empty();
+    if let Some(messages) = obj.get_mut("messages").and_then(|m| m.as_array_mut()) {
+        modified |= rewrite_history(messages);
+    }
+    if !tools.is_empty() {
+        append_system(obj, &tool_instructions(&tools));
+    }
+
+    if !modified {
+        return None;
+    }
+    serde_json::to_vec(&json).ok()
+}
+
+fn tool_instructions(tools: &[Value]) -> String {
+    let mut out = String::from(
+        "You can call tools. To call a tool, reply with one or more blocks in exactly this form:\n\
+         <tool_call>{\"name\": \"TOOL_NAME\", \"input\": {...}}</tool_call>\n\
+         The input must be valid JSON matching the tool's input schema. After emitting tool calls, \
+         stop and wait: results arrive in the next user message as <tool_result> blocks.\n\n\
+         Available tools:\n",
+    );
+    for tool in tools {
+        let name = tool["name"].as_str().unwrap_or("unknown");
+        out.push_str(&format!("\n## {}\n", name));
+        if let Some(desc) = tool["description"].as_str() {
+            out.push_str(desc);
+            out.push('\n');
+        }
+        out.push_str(&format!("Input schema: {}\n", tool["input_schema"]));
+    }
+    out
+}
+
+fn append_system(obj: &mut Map<String, Value>, extra: &str) {
+    match obj.get_mut("system") {
+        Some(Value::Array(items)) => items.push(json!({ "type": "text", "text": extra })),
+        Some(Value::String(s)) => {
+            s.push_str("\n\n");
+            s.push_str(extra);
+        }
+        _ => {
+            obj.insert("system".into(), json!([{ "type": "text", "text": extra }]));
+        }
+    }
+}
+
+/// tool_use / tool_result 块改写为文本
+fn rewrite_history(messages: &mut [Value]) -> bool {
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut modified = false;
+
+    for msg in messages.iter_mut() {
+        let Some(content) = msg.get_mut("content").and_then(|c| c.as_array_mut()) else {
+            continue;
+        };
+        for block in content.iter_mut() {
+            let text = match block["type"].as_str() {
+                Some("tool_use") => {
+                    let name = block["name"].as_str().unwrap_or("").to_string();
+                    if let Some(id) = block["id"].as_str() {
+                        names.insert(id.to_string(), name.clone());
+                    }
+                    let call = json!({ "name": name, "input": block["input"] });
+                    format!("{}{}{}", CALL_OPEN, call, CALL_CLOSE)
+                }
+                Some("tool_result") => {
+                    let name = block["tool_use_id"]
+                        .as_str()
+                        .and_then(|id| names.get(id))
+                        .map(|s| s.as_str())
+                        .unwrap_or("unknown");
+                    let error = if block["is_error"].as_bool() == Some(true) {
+                        " error=\"true\""
+                    } else {
+                        ""
+                    };
+                    format!(
+                        "<tool_result name=\"{}\"{}>\n{}\n</tool_result>",
+                        name,
+                        error,
+                        tool_result_text(&block["content"])
+                    )
+                }
+                _ => continue,
+            };
+            *block = json!({ "type": "text", "text": text });
+            modified = true;
+        }
+    }
+    modified
+}
+
+fn tool_result_text(content: &Value) -> String {
+    match content {
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item["type"].as_str() {
+                Some("text") => item["text"].as_str().unwrap_or("").to_string(),
+                Some(other) => format!("[{} omitted]", other),
+                None => String::new(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// 解析文本中的工具调用，返回 (剩余文本, 调用列表)
+fn parse_calls(text: &str) -> (String, Vec<Value>) {
+    let mut remaining = String::new();
+    let mut calls = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find(CALL_OPEN) {
+        let after = &rest[start + CALL_OPEN.len()..];
+        let Some(end) = after.find(CALL_CLOSE) else {
+            break;
+        };
+        match serde_json::from_str::<Value>(after[..end].trim()) {
+            Ok(call) if call["name"].is_string() => {
+                remaining.push_str(&rest[..start]);
+                calls.push(json!({
+                    "type": "tool_use",
+                    "id": format!("toolu_shim_{}", determinism::ids().new_uuid().simple()),
+                    "name": call["name"],
+                    "input": if call["input"].is_object() { call["input"].clone() } else { json!({}) }
+                }));
+            }
+            _ => {
+                tracing::debug!("ReAct 兼容层: 无法解析的工具调用，按文本保留");
+                remaining.push_str(&rest[..start + CALL_OPEN.len() + end + CALL_CLOSE.len()]);
+            }
+        }
+        rest = &after[end + CALL_CLOSE.len()..];
+    }
+    remaining.push_str(rest);
+    (remaining.trim().to_string(), calls)
+}
+
+/// 非流式响应改写，返回 None 表示无工具调用
+pub(crate) fn rewrite_response(body: &[u8]) -> Option<Vec<u8>> {
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    let text: String = json["content"]
+        .as_array()?
+        .iter()
+        .filter(|b| b["type"].as_str() == Some("text"))
+        .filter_map(|b| b["text"].as_str())
+        .collect();
+    let (remaining, calls) = parse_calls(&text);
+    if calls.is_empty() {
+        return None;
+    }
+
+    let mut content = Vec::new();
+    if !remaining.is_empty() {
+        content.push(json!({ "type": "text", "text": remaining }));
+    }
+    content.extend(calls);
+    json["content"] = Value::Array(content);
+    json["stop_reason"] = json!("tool_use");
+    serde_json::to_vec(&json).ok()
+}
+
+/// 流式响应改写：缓存文本块，在 message_delta 前输出解析后的内容块
+#[derive(Default)]
+pub(crate) struct ReactStreamRewriter {
+    parser: SseParser,
+    text: String,
+    /// 原样透传的非文本块（thinking 等）数量，用于合成块的 index 偏移
+    passthrough_blocks: usize,
+    /// 当前文本块是否被吞掉（其 delta / stop 一并吞掉）
+    in_text_block: bool,
+}
+
+impl ReactStreamRewriter {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        let mut out = Vec::new();
+        for event in self.parser.feed(chunk) {
+            self.handle_event(event, &mut out);
+        }
+        Bytes::from(out)
+    }
+
+    pub(crate) fn finish(&mut self) -> Bytes {
+        let mut out = Vec::new();
+        if let Some(rest) = self.parser.finish() {
+            self.handle_event(rest, &mut out);
+        }
+        Bytes::from(out)
+    }
+
+    fn handle_event(&mut self, raw: SseEvent, out: &mut Vec<u8>) {
+        let Some(event) = raw.json() else {
+            out.extend_from_slice(raw.raw());
+            return;
+        };
+
+        match event["type"].as_str() {
+            Some("content_block_start") => {
+                if event["content_block"]["type"].as_str() == Some("text") {
+                    self.in_text_block = true;
+                    return;
+                }
+                self.passthrough_blocks += 1;
+            }
+            Some("content_block_delta") if self.in_text_block => {
+                if let Some(t) = event["delta"]["text"].as_str() {
+                    self.text.push_str(t);
+                }
+                return;
+            }
+            Some("content_block_stop") if self.in_text_block => {
+                self.in_text_block = false;
+                return;
+            }
+            Some("message_delta") => {
+                let has_calls = self.flush_blocks(out);
+                let mut event = event;
+                if has_calls {
+                    event["delta"]["stop_reason"] = json!("tool_use");
+                }
+                push_event(out, "message_delta", &event);
+                return;
+            }
+            _ => {}
+        }
+        out.extend_from_slice(raw.raw());
+    }
+
+    /// 输出缓存文本解析出的内容块，返回是否包含工具调用
+    fn flush_blocks(&mut self, out: &mut Vec<u8>) -> bool {
+        let text = std::mem::take(&mut self.text);
+        let (remaining, calls) = parse_calls(&text);
+        let mut index = self.passthrough_blocks;
+
+        if !remaining.is_empty() {
+            push_event(
+                out,
+                "content_block_start",
+                &json!({ "type": "content_block_start", "index": index, "content_block": { "type": "text", "text": "" } }),
+            );
+            push_event(
+                out,
+                "content_block_delta",
+                &json!({ "type": "content_block_delta", "index": index, "delta": { "type": "text_delta", "text": remaining } }),
+            );
+            push_event(
+                out,
+                "content_block_stop",
+                &json!({ "type": "content_block_stop", "index": index }),
+            );
+            index += 1;
+        }
+
+        let has_calls = !calls.is_empty();
+        for call in calls {
+            let start =
+                json!({ "type": "tool_use", "id": call["id"], "name": call["name"], "input": {} });
+            push_event(
+                out,
+                "content_block_start",
+                &json!({ "type": "content_block_start", "index": index, "content_block": start }),
+            );
+            push_event(
+                out,
+                "content_block_delta",
+                &json!({ "type": "content_block_delta", "index": index, "delta": { "type": "input_json_delta", "partial_json": call["input"].to_string() } }),
+            );
+            push_event(
+                out,
+                "content_block_stop",
+                &json!({ "type": "content_block_stop", "index": index }),
+            );
+            index += 1;
+        }
+        has_calls
+    }
+}
+
+fn push_event(out: &mut Vec<u8>, name: &str, data: &Value) {
+    out.extend_from_slice(&sse::emit(name, data));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sse_input(events: &[Value]) -> String {
+        events
+            .iter()
+            .map(|e| sse::format(e["type"].as_str(), e))
+            .collect()
+    }
+
+    /// 按 size 字节切分输入逐块改写，返回全部事件
+    fn stream_events(input: &str, size: usize) -> Vec<Value> {
+        let mut rewriter = ReactStreamRewriter::new();
+        let mut out = Vec::new();
+        for chunk in input.as_bytes().chunks(size) {
+            out.extend_from_slice(&rewriter.feed(chunk));
+        }
+        out.extend_from_slice(&rewriter.finish());
+        let mut parser = SseParser::new();
+        let mut events = parser.feed(&out);
+        events.extend(parser.finish());
+        events.iter().filter_map(SseEvent::json).collect()
+    }
+
+    #[test]
+    fn request_moves_tools_into_system_and_rewrites_history() {
+        let body = json!({
+            "system": "base",
+            "tools": [{ "name": "read", "description": "read file", "input_schema": { "type": "object" } }],
+            "tool_choice": { "type": "auto" },
+            "messages": [
+                { "role": "assistant", "content": [
+                    { "type": "tool_use", "id": "t1", "name": "read", "input": { "path": "a" } }
+                ]},
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "t1", "is_error": true, "content": "missing" }
+                ]}
+            ]
+        });
+        let out: Value =
+            serde_json::from_slice(&apply_request(&serde_json::to_vec(&body).unwrap()).unwrap())
+                .unwrap();
+        assert!(out.get("tools").is_none() && out.get("tool_choice").is_none());
+        let system = out["system"].as_str().unwrap();
+        assert!(system.starts_with("base\n\n"));
+        assert!(system.contains("## read"));
+        let call = out["messages"][0]["content"][0]["text"]
+            .as_str()
+            .and_then(|t| t.strip_prefix(CALL_OPEN)?.strip_suffix(CALL_CLOSE))
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(call).unwrap(),
+            json!({ "name": "read", "input": { "path": "a" } })
+        );
+        assert_eq!(
+            out["messages"][1]["content"][0]["text"],
+            "<tool_result name=\"read\" error=\"true\">\nmissing\n</tool_result>"
+        );
+
+        let plain = json!({ "messages": [{ "role": "user", "content": "hi" }] });
+        assert!(apply_request(&serde_json::to_vec(&plain).unwrap()).is_none());
+    }
+
+    #[test]
+    fn non_streaming_calls_become_tool_use() {
+        let body = json!({
+            "type": "message",
+            "content": [{ "type": "text", "text": "Looking.\n<tool_call>{\"name\":\"read\",\"input\":{\"path\":\"a\"}}</tool_call>\n<tool_call>not json</tool_call>" }],
+            "stop_reason": "end_turn"
+        });
+        let out: Value =
+            serde_json::from_slice(&rewrite_response(&serde_json::to_vec(&body).unwrap()).unwrap())
+                .unwrap();
+        assert_eq!(out["stop_reason"], "tool_use");
+        // 无法解析的调用按文本保留
+        assert_eq!(
+            out["content"][0]["text"],
+            "Looking.\n\n<tool_call>not json</tool_call>"
+        );
+        assert_eq!(out["content"][1]["type"], "tool_use");
+        assert_eq!(out["content"][1]["name"], "read");
+        assert_eq!(out["content"][1]["input"], json!({ "path": "a" }));
+
+        let no_calls = json!({ "content": [{ "type": "text", "text": "hi" }] });
+        assert!(rewrite_response(&serde_json::to_vec(&no_calls).unwrap()).is_none());
+    }
+
+    #[test]
+    fn error_body_passes_through() {
+        let error =
+            json!({ "type": "error", "error": { "type": "overloaded_error", "message": "busy" } });
+        assert!(rewrite_response(&serde_json::to_vec(&error).unwrap()).is_none());
+        assert_eq!(stream_events(&sse_input(&[error.clone()]), 4), [error]);
+    }
+
+    #[test]
+    fn split_stream_calls_become_tool_use_blocks() {
+        let input = sse_input(&[
+            json!({ "type": "message_start", "message": { "id": "msg_1" } }),
+            json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "thinking", "thinking": "" } }),
+            json!({ "type": "content_block_stop", "index": 0 }),
+            json!({ "type": "content_block_start", "index": 1, "content_block": { "type": "text", "text": "" } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "text_delta", "text": "Sure <tool_" } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "text_delta", "text": "call>{\"name\":\"read\",\"input\":{}}</tool_call>" } }),
+            json!({ "type": "content_block_stop", "index": 1 }),
+            json!({ "type": "message_delta", "delta": { "stop_reason": "end_turn" } }),
+            json!({ "type": "message_stop" }),
+        ]);
+        for size in [1, 11, input.len()] {
+            let events = stream_events(&input, size);
+            let types: Vec<&str> = events.iter().filter_map(|e| e["type"].as_str()).collect();
+            assert_eq!(
+                types,
+                [
+                    "message_start",
+                    "content_block_start",
+                    "content_block_stop",
+                    "content_block_start",
+                    "content_block_delta",
+                    "content_block_stop",
+                    "content_block_start",
+                    "content_block_delta",
+                    "content_block_stop",
+                    "message_delta",
+                    "message_stop",
+                ],
+                "size={}",
+                size
+            );
+            // 合成块的 index 接在透传的 thinking 块之后
+            assert_eq!(events[3]["index"], 1);
+            assert_eq!(events[4]["delta"]["text"], "Sure");
+            assert_eq!(events[6]["index"], 2);
+            assert_eq!(events[6]["content_block"]["name"], "read");
+            assert_eq!(events[7]["delta"]["partial_json"], "{}");
+            assert_eq!(events[9]["delta"]["stop_reason"], "tool_use");
+        }
+    }
+}
@@ -0,0 +1,584 @@
+// 请求 / 用量日志（SQLite）
+//
+// 每个经转发层发出的请求在响应结束（或客户端断开）时写入一行摘要：
+// 时间、路由（ApiType）、模型、Profile、实际服务的配置、状态码、
+// 输入 / 输出 / 缓存 token（从响应体或 SSE 流中解析）、首字节与总耗时、提供方请求 ID。
+// 响应体边透传边解析，不改变返回给客户端的字节；非流式响应最多缓存 MAX_JSON_BODY 用于解析。
+// 数据库默认位于 ~/.duckcoding/amp/requests.db，query() / totals() 提供查询。
+// 需要 feature = "sqlite"；未启用该特性时记录为空操作，查询返回错误。
+// 同一解析结果也用于 Profile 预算计量与虚拟 Key 的 token 限流（UsageTargets，见 budget.rs / rate_limit.rs），与是否写库无关。
+
+use super::budget;
+use super::provider_request_id;
+use super::rate_limit;
+use super::sse::SseParser;
+use super::upstream::Forwarded;
+#[cfg(not(feature = "sqlite"))]
+use anyhow::anyhow;
+use anyhow::Result;
+use bytes::Bytes;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Instant;
+
+/// 非流式响应用于解析用量的最大缓存
+const MAX_JSON_BODY: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestLogSettings {
+    pub enabled: bool,
+    /// 数据库路径，为空时使用 ~/.duckcoding/amp/requests.db
+    pub path: Option<String>,
+    /// 保留天数（None 表示永久保留）
+    pub retention_days: Option<u32>,
+}
+
+impl Default for RequestLogSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            retention_days: Some(90),
+        }
+    }
+}
+
+/// 一条请求摘要
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RequestSummary {
+    pub ts_ms: u64,
+    pub route: String,
+    pub model: Option<String>,
+    pub profile: String,
+    pub served_by: String,
+    pub status: u16,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cache_write_tokens: u64,
+    pub first_byte_ms: u64,
+    pub latency_ms: u64,
+    pub provider_request_id: Option<String>,
+    /// 客户端在响应结束前断开
+    pub aborted: bool,
+}
+
+/// 查询条件（均为可选）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestQuery {
+    pub since_ms: Option<u64>,
+    pub until_ms: Option<u64>,
+    pub route: Option<String>,
+    pub model: Option<String>,
+    pub profile: Option<String>,
+    pub status: Option<u16>,
+    /// 默认 100，最多 10000
+    pub limit: Option<u32>,
+}
+
+/// 汇总
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RequestTotals {
+    pub requests: u64,
+    pub errors: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cache_write_tokens: u64,
+    pub avg_latency_ms: f64,
+}
+
+/// 从响应体 / SSE 事件中解析 token 用量（Anthropic / OpenAI / Responses / Gemini）
+#[derive(Default)]
+pub(crate) struct UsageParser {
+    streaming: bool,
+    parser: SseParser,
+    body: Vec<u8>,
+    overflow: bool,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cache_write_tokens: u64,
+}
+
+impl UsageParser {
+    pub(crate) fn new(content_type: &str) -> Self {
+        Self {
+            streaming: content_type.contains("text/event-stream"),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) {
+        if !self.streaming {
+            if self.body.len() + chunk.len() > MAX_JSON_BODY {
+                self.overflow = true;
+            } else {
+                self.body.extend_from_slice(chunk);
+            }
+            return;
+        }
+        for event in self.parser.feed(chunk) {
+            if let Some(json) = event.json() {
+                self.absorb(&json);
+            }
+        }
+    }
+
+    pub(crate) fn finish(&mut self) {
+        if self.streaming {
+            if let Some(json) = self.parser.finish().and_then(|e| e.json()) {
+                self.absorb(&json);
+            }
+        } else if !self.overflow {
+            match serde_json::from_slice::<Value>(&self.body) {
+                // Gemini 非流式也可能返回数组
+                Ok(Value::Array(items)) => items.iter().for_each(|i| self.absorb(i)),
+                Ok(json) => self.absorb(&json),
+                Err(_) => {}
+            }
+        }
+    }
+
+    fn absorb(&mut self, json: &Value) {
+        let n = |v: &Value| v.as_u64().unwrap_or(0);
+        // Anthropic：message_start 带输入用量，message_delta 带累计输出
+        let anthropic = json
+            .get("message")
+            .and_then(|m| m.get("usage"))
+            .or_else(|| {
+                json.get("usage")
+                    .filter(|u| u.get("output_tokens").is_some())
+            });
+        if let Some(u) = anthropic.filter(|u| u.get("prompt_tokens").is_none()) {
+            if u.get("input_tokens").is_some() {
+                self.input_tokens = n(&u["input_tokens"]);
+            }
+            if u.get("output_tokens").is_some() {
+                self.output_tokens = n(&u["output_tokens"]);
+            }
+            if u.get("cache_read_input_tokens").is_some() {
+                self.cache_read_tokens = n(&u["cache_read_input_tokens"]);
+            }
+            if u.get("cache_creation_input_tokens").is_some() {
+                self.cache_write_tokens = n(&u["cache_creation_input_tokens"]);
+            }
+            if let Some(cached) = u.pointer("/input_tokens_details/cached_tokens") {
+                self.cache_read_tokens = n(cached);
+            }
+        }
+        // Responses：response.completed 的 response.usage
+        if let Some(u) = json.pointer("/response/usage").filter(|u| u.is_object()) {
+            self.input_tokens = n(&u["input_tokens"]);
+            self.output_tokens = n(&u["output_tokens"]);
+            self.cache_read_tokens = u
+                .pointer("/input_tokens_details/cached_tokens")
+                .map(n)
+                .unwrap_or(0);
+        }
+        // OpenAI Chat
+        if let Some(u) = json
+            .get("usage")
+            .filter(|u| u.get("prompt_tokens").is_some())
+        {
+            self.input_tokens = n(&u["prompt_tokens"]);
+            self.output_tokens = n(&u["completion_tokens"]);
+            self.cache_read_tokens = u
+                .pointer("/prompt_tokens_details/cached_tokens")
+                .map(n)
+                .unwrap_or(0);
+        }
+        // Gemini（流式每块为累计值）
+        if let Some(u) = json.get("usageMetadata") {
+            self.input_tokens = n(&u["promptTokenCount"]);
+            self.output_tokens = n(&u["candidatesTokenCount"]) + n(&u["thoughtsTokenCount"]);
+            self.cache_read_tokens = n(&u["cachedContentTokenCount"]);
+        }
+    }
+}
+
+/// 从请求体或 Gemini 路径中取模型名
+pub(crate) fn request_model(target_url: &str, body: &[u8]) -> Option<String> {
+    let from_body = serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|j| j.get("model").and_then(Value::as_str).map(str::to_string));
+    from_body.or_else(|| {
+        let rest = &target_url[target_url.find("/models/")? + "/models/".len()..];
+        let end = rest.find([':', '?', '/']).unwrap_or(rest.len());
+        Some(rest[..end].to_string()).filter(|m| !m.is_empty())
+    })
+}
+
+/// 写入时的状态：流结束或被丢弃（客户端断开）时落库
+/// 用量除写日志外还需计入的对象
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UsageTargets {
+    /// 计入 Profile 预算
+    pub budget: bool,
+    /// 计入该虚拟 Key 的 token 限流桶
+    pub rate_limit_key: Option<String>,
+}
+
+struct Recorder {
+    settings: RequestLogSettings,
+    log_enabled: bool,
+    targets: UsageTargets,
+    summary: RequestSummary,
+    parser: UsageParser,
+    started: Instant,
+    done: bool,
+}
+
+impl Recorder {
+    fn complete(&mut self, aborted: bool) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        self.parser.finish();
+        let summary = &mut self.summary;
+        summary.input_tokens = self.parser.input_tokens;
+        summary.output_tokens = self.parser.output_tokens;
+        summary.cache_read_tokens = self.parser.cache_read_tokens;
+        summary.cache_write_tokens = self.parser.cache_write_tokens;
+        summary.latency_ms = self.started.elapsed().as_millis() as u64;
+        summary.aborted = aborted;
+        if let Some(key) = self.targets.rate_limit_key.as_deref() {
+            rate_limit::record_output(key, &summary.route, summary.output_tokens);
+        }
+        if self.targets.budget {
+            // 输入计入缓存写入，不含缓存读取
+            budget::record(
+                &summary.route,
+                summary.model.as_deref(),
+                summary.input_tokens + summary.cache_write_tokens,
+                summary.output_tokens,
+            );
+        }
+        if self.log_enabled {
+            if let Err(e) = insert(&self.settings, summary) {
+                tracing::warn!("写入请求日志失败: {}", e);
+            }
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.complete(true);
+    }
+}
+
+/// 包装转发结果：透传响应体的同时解析用量，结束时写入日志 / 计入预算
+pub(crate) fn observe(
+    settings: &RequestLogSettings,
+    targets: UsageTargets,
+    route: &str,
+    profile: &str,
+    model: Option<String>,
+    started: Instant,
+    forwarded: Forwarded,
+) -> Forwarded {
+    let log_enabled = settings.enabled && cfg!(feature = "sqlite");
+    if !log_enabled && !targets.budget && targets.rate_limit_key.is_none() {
+        return forwarded;
+    }
+    let response = forwarded.response;
+    let content_type = response
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let recorder = Recorder {
+        settings: settings.clone(),
+        log_enabled,
+        targets,
+        summary: RequestSummary {
+            ts_ms: super::audit_log::now_ms(),
+            route: route.to_string(),
+            model,
+            profile: profile.to_string(),
+            served_by: forwarded.served_by.clone(),
+            status: response.status().as_u16(),
+            first_byte_ms: started.elapsed().as_millis() as u64,
+            provider_request_id: provider_request_id::extract(response.headers()).map(|(_, id)| id),
+            ..Default::default()
+        },
+        parser: UsageParser::new(&content_type),
+        started,
+        done: false,
+    };
+
+    let mut builder = hyper::http::Response::builder().status(response.status());
+    for (key, value) in response.headers() {
+        builder = builder.header(key, value);
+    }
+    let inner = response.bytes_stream();
+    let body =
+        futures_util::stream::unfold((inner, recorder), |(mut inner, mut recorder)| async move {
+            match inner.next().await {
+                Some(Ok(bytes)) => {
+                    recorder.parser.feed(&bytes);
+                    Some((Ok::<Bytes, reqwest::Error>(bytes), (inner, recorder)))
+                }
+                Some(Err(e)) => {
+                    recorder.complete(true);
+                    Some((Err(e), (inner, recorder)))
+                }
+                None => {
+                    recorder.complete(false);
+                    None
+                }
+            }
+        });
+    let response = builder
+        .body(reqwest::Body::wrap_stream(body))
+        .map(reqwest::Response::from)
+        .unwrap_or_else(|_| {
+            reqwest::Response::from(hyper::http::Response::new(reqwest::Body::from("")))
+        });
+    Forwarded {
+        response,
+        served_by: forwarded.served_by,
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod db {
+    use super::{RequestLogSettings, RequestQuery, RequestSummary, RequestTotals};
+    use anyhow::{anyhow, Result};
+    use once_cell::sync::Lazy;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    static CONN: Lazy<Mutex<Option<(PathBuf, rusqlite::Connection)>>> =
+        Lazy::new(|| Mutex::new(None));
+
+    const SCHEMA: &str = "PRAGMA journal_mode = WAL;
+        CREATE TABLE IF NOT EXISTS requests (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            ts_ms INTEGER NOT NULL,
+            route TEXT NOT NULL,
+            model TEXT,
+            profile TEXT NOT NULL,
+            served_by TEXT NOT NULL,
+            status INTEGER NOT NULL,
+            input_tokens INTEGER NOT NULL,
+            output_tokens INTEGER NOT NULL,
+            cache_read_tokens INTEGER NOT NULL,
+            cache_write_tokens INTEGER NOT NULL,
+            first_byte_ms INTEGER NOT NULL,
+            latency_ms INTEGER NOT NULL,
+            provider_request_id TEXT,
+            aborted INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS requests_ts ON requests (ts_ms);
+        CREATE INDEX IF NOT EXISTS requests_provider_id ON requests (provider_request_id);";
+
+    fn db_path(settings: &RequestLogSettings) -> PathBuf {
+        match settings.path.as_deref().filter(|p| !p.is_empty()) {
+            Some(p) => PathBuf::from(p),
+            None => dirs::home_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join(".duckcoding")
+                .join("amp")
+                .join("requests.db"),
+        }
+    }
+
+    /// 打开（或复用）连接；路径变化时重新打开
+    fn with_conn<T>(
+        settings: &RequestLogSettings,
+        f: impl FnOnce(&rusqlite::Connection) -> Result<T>,
+    ) -> Result<T> {
+        let path = db_path(settings);
+        let mut guard = CONN.lock().unwrap_or_else(|e| e.into_inner());
+        if guard.as_ref().map(|(p, _)| p != &path).unwrap_or(true) {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|e| anyhow!("创建数据库目录失败: {}", e))?;
+            }
+            let conn = rusqlite::Connection::open(&path)
+                .map_err(|e| anyhow!("打开请求日志数据库失败: {}", e))?;
+            conn.execute_batch(SCHEMA)
+                .map_err(|e| anyhow!("初始化请求日志数据库失败: {}", e))?;
+            *guard = Some((path, conn));
+        }
+        match guard.as_ref() {
+            Some((_, conn)) => f(conn),
+            None => Err(anyhow!("请求日志数据库未打开")),
+        }
+    }
+
+    pub(super) fn insert(settings: &RequestLogSettings, s: &RequestSummary) -> Result<()> {
+        with_conn(settings, |conn| {
+            conn.execute(
+                "INSERT INTO requests (ts_ms, route, model, profile, served_by, status,
+                     input_tokens, output_tokens, cache_read_tokens, cache_write_tokens,
+                     first_byte_ms, latency_ms, provider_request_id, aborted)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                rusqlite::params![
+                    s.ts_ms as i64,
+                    s.route,
+                    s.model,
+                    s.profile,
+                    s.served_by,
+                    s.status,
+                    s.input_tokens as i64,
+                    s.output_tokens as i64,
+                    s.cache_read_tokens as i64,
+                    s.cache_write_tokens as i64,
+                    s.first_byte_ms as i64,
+                    s.latency_ms as i64,
+                    s.provider_request_id,
+                    s.aborted,
+                ],
+            )
+            .map_err(|e| anyhow!("写入请求日志失败: {}", e))?;
+            if let Some(days) = settings.retention_days {
+                let cutoff = s.ts_ms.saturating_sub(days as u64 * 86_400_000) as i64;
+                conn.execute("DELETE FROM requests WHERE ts_ms < ?1", [cutoff])
+                    .map_err(|e| anyhow!("清理过期请求日志失败: {}", e))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// WHERE 子句与参数
+    fn filter(q: &RequestQuery) -> (String, Vec<rusqlite::types::Value>) {
+        use rusqlite::types::Value as V;
+        let mut clauses = Vec::new();
+        let mut params = Vec::new();
+        if let Some(v) = q.since_ms {
+            clauses.push("ts_ms >= ?");
+            params.push(V::Integer(v as i64));
+        }
+        if let Some(v) = q.until_ms {
+            clauses.push("ts_ms < ?");
+            params.push(V::Integer(v as i64));
+        }
+        for (column, value) in [
+            ("route = ?", &q.route),
+            ("model = ?", &q.model),
+            ("profile = ?", &q.profile),
+        ] {
+            if let Some(v) = value {
+                clauses.push(column);
+                params.push(V::Text(v.clone()));
+            }
+        }
+        if let Some(v) = q.status {
+            clauses.push("status = ?");
+            params.push(V::Integer(v as i64));
+        }
+        let sql = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", clauses.join(" AND "))
+        };
+        (sql, params)
+    }
+
+    pub(super) fn query(
+        settings: &RequestLogSettings,
+        q: &RequestQuery,
+    ) -> Result<Vec<RequestSummary>> {
+        let (where_sql, params) = filter(q);
+        let limit = q.limit.unwrap_or(100).min(10_000);
+        with_conn(settings, |conn| {
+            let sql = format!(
+                "SELECT ts_ms, route, model, profile, served_by, status, input_tokens, output_tokens,
+                        cache_read_tokens, cache_write_tokens, first_byte_ms, latency_ms,
+                        provider_request_id, aborted
+                 FROM requests{} ORDER BY ts_ms DESC LIMIT {}",
+                where_sql, limit
+            );
+            let mut stmt = conn
+                .prepare(&sql)
+                .map_err(|e| anyhow!("查询请求日志失败: {}", e))?;
+            let rows = stmt
+                .query_map(rusqlite::params_from_iter(params), |row| {
+                    Ok(RequestSummary {
+                        ts_ms: row.get::<_, i64>(0)? as u64,
+                        route: row.get(1)?,
+                        model: row.get(2)?,
+                        profile: row.get(3)?,
+                        served_by: row.get(4)?,
+                        status: row.get(5)?,
+                        input_tokens: row.get::<_, i64>(6)? as u64,
+                        output_tokens: row.get::<_, i64>(7)? as u64,
+                        cache_read_tokens: row.get::<_, i64>(8)? as u64,
+                        cache_write_tokens: row.get::<_, i64>(9)? as u64,
+                        first_byte_ms: row.get::<_, i64>(10)? as u64,
+                        latency_ms: row.get::<_, i64>(11)? as u64,
+                        provider_request_id: row.get(12)?,
+                        aborted: row.get(13)?,
+                    })
+                })
+                .map_err(|e| anyhow!("查询请求日志失败: {}", e))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+                .map_err(|e| anyhow!("读取请求日志失败: {}", e))
+        })
+    }
+
+    pub(super) fn totals(settings: &RequestLogSettings, q: &RequestQuery) -> Result<RequestTotals> {
+        let (where_sql, params) = filter(q);
+        with_conn(settings, |conn| {
+            let sql = format!(
+                "SELECT COUNT(*), COALESCE(SUM(status >= 400), 0),
+                        COALESCE(SUM(input_tokens), 0), COALESCE(SUM(output_tokens), 0),
+                        COALESCE(SUM(cache_read_tokens), 0), COALESCE(SUM(cache_write_tokens), 0),
+                        COALESCE(AVG(latency_ms), 0)
+                 FROM requests{}",
+                where_sql
+            );
+            conn.query_row(&sql, rusqlite::params_from_iter(params), |row| {
+                Ok(RequestTotals {
+                    requests: row.get::<_, i64>(0)? as u64,
+                    errors: row.get::<_, i64>(1)? as u64,
+                    input_tokens: row.get::<_, i64>(2)? as u64,
+                    output_tokens: row.get::<_, i64>(3)? as u64,
+                    cache_read_tokens: row.get::<_, i64>(4)? as u64,
+                    cache_write_tokens: row.get::<_, i64>(5)? as u64,
+                    avg_latency_ms: row.get(6)?,
+                })
+            })
+            .map_err(|e| anyhow!("汇总请求日志失败: {}", e))
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn insert(settings: &RequestLogSettings, summary: &RequestSummary) -> Result<()> {
+    db::insert(settings, summary)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn insert(_settings: &RequestLogSettings, _summary: &RequestSummary) -> Result<()> {
+    Ok(())
+}
+
+/// 按条件查询，按时间倒序
+#[cfg(feature = "sqlite")]
+pub fn query(settings: &RequestLogSettings, q: &RequestQuery) -> Result<Vec<RequestSummary>> {
+    db::query(settings, q)
+}
+
+/// 按条件汇总
+#[cfg(feature = "sqlite")]
+pub fn totals(settings: &RequestLogSettings, q: &RequestQuery) -> Result<RequestTotals> {
+    db::totals(settings, q)
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub fn query(_settings: &RequestLogSettings, _q: &RequestQuery) -> Result<Vec<RequestSummary>> {
+    Err(anyhow!("请求日志需要启用 sqlite 特性"))
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub fn totals(_settings: &RequestLogSettings, _q: &RequestQuery) -> Result<RequestTotals> {
+    Err(anyhow!("请求日志需要启用 sqlite 特性"))
+}
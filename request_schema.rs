@@ -0,0 +1,691 @@
+// 入站请求结构校验
+//
+// 按路由 / 端点选择内置 JSON Schema，校验 AMP 发来的 LLM 请求体，
+// 错误精确到 JSON Pointer（如 /messages/3/content/0/tool_use_id: 缺少必填字段），
+// 在浪费一次上游往返之前发现客户端缺陷。
+// 只校验已知端点；未知字段不视为错误（新字段由 schema_drift 提示）。
+// 模式与转换严格校验相同：off / log / reject（ProcessorSettings.request_validation）。
+//
+// 支持的 Schema 子集：type、required、properties、additionalProperties、items、
+// enum、const、minimum、maximum、minItems、minLength、anyOf、oneOf、not、$ref（#/$defs/*）。
+// anyOf / oneOf 各分支以 properties.type.const 区分时，按实例的 type 选分支报告错误；
+// 否则在声明类型与实例相符的分支中选错误最少的一个报告。
+
+use super::transform_validation::StrictMode;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde_json::{Map, Value};
+
+/// 单次校验最多报告的错误数
+const MAX_ERRORS: usize = 20;
+
+const CLAUDE_MESSAGES: &str = r##"{
+  "type": "object",
+  "required": ["model", "messages", "max_tokens"],
+  "properties": {
+    "model": { "type": "string", "minLength": 1 },
+    "max_tokens": { "type": "integer", "minimum": 1 },
+    "stream": { "type": "boolean" },
+    "temperature": { "type": "number", "minimum": 0, "maximum": 1 },
+    "top_p": { "type": "number", "minimum": 0, "maximum": 1 },
+    "top_k": { "type": "integer", "minimum": 0 },
+    "stop_sequences": { "type": "array", "items": { "type": "string" } },
+    "system": {
+      "anyOf": [
+        { "type": "string" },
+        { "type": "array", "items": { "$ref": "#/$defs/text_block" } }
+      ]
+    },
+    "messages": {
+      "type": "array",
+      "minItems": 1,
+      "items": {
+        "type": "object",
+        "required": ["role", "content"],
+        "properties": {
+          "role": { "enum": ["user", "assistant"] },
+          "content": {
+            "anyOf": [
+              { "type": "string" },
+              { "type": "array", "items": { "$ref": "#/$defs/content_block" } }
+            ]
+          }
+        }
+      }
+    },
+    "tools": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["name"],
+        "properties": {
+          "name": { "type": "string", "minLength": 1 },
+          "description": { "type": "string" },
+          "input_schema": { "type": "object" }
+        }
+      }
+    },
+    "tool_choice": {
+      "type": "object",
+      "required": ["type"],
+      "properties": { "type": { "enum": ["auto", "any", "tool", "none"] } }
+    },
+    "metadata": { "type": "object" },
+    "thinking": { "type": "object", "required": ["type"] }
+  },
+  "$defs": {
+    "text_block": {
+      "type": "object",
+      "required": ["type", "text"],
+      "properties": { "type": { "const": "text" }, "text": { "type": "string" } }
+    },
+    "content_block": {
+      "anyOf": [
+        { "$ref": "#/$defs/text_block" },
+        {
+          "type": "object",
+          "required": ["type", "source"],
+          "properties": { "type": { "const": "image" }, "source": { "type": "object", "required": ["type"] } }
+        },
+        {
+          "type": "object",
+          "required": ["type", "id", "name", "input"],
+          "properties": {
+            "type": { "const": "tool_use" },
+            "id": { "type": "string", "minLength": 1 },
+            "name": { "type": "string", "minLength": 1 },
+            "input": { "type": "object" }
+          }
+        },
+        {
+          "type": "object",
+          "required": ["type", "tool_use_id"],
+          "properties": {
+            "type": { "const": "tool_result" },
+            "tool_use_id": { "type": "string", "minLength": 1 },
+            "is_error": { "type": "boolean" },
+            "content": { "anyOf": [{ "type": "string" }, { "type": "array" }] }
+          }
+        },
+        {
+          "type": "object",
+          "required": ["type"],
+          "properties": {
+            "type": { "type": "string", "not": { "enum": ["text", "image", "tool_use", "tool_result"] } }
+          }
+        }
+      ]
+    }
+  }
+}"##;
+
+const OPENAI_CHAT: &str = r##"{
+  "type": "object",
+  "required": ["model", "messages"],
+  "properties": {
+    "model": { "type": "string", "minLength": 1 },
+    "stream": { "type": "boolean" },
+    "temperature": { "type": "number", "minimum": 0, "maximum": 2 },
+    "top_p": { "type": "number", "minimum": 0, "maximum": 1 },
+    "max_tokens": { "type": "integer", "minimum": 1 },
+    "max_completion_tokens": { "type": "integer", "minimum": 1 },
+    "messages": {
+      "type": "array",
+      "minItems": 1,
+      "items": {
+        "anyOf": [
+          {
+            "type": "object",
+            "required": ["role", "tool_call_id"],
+            "properties": { "role": { "const": "tool" }, "tool_call_id": { "type": "string", "minLength": 1 } }
+          },
+          {
+            "type": "object",
+            "required": ["role"],
+            "properties": { "role": { "enum": ["system", "developer", "user", "assistant", "function"] } }
+          }
+        ]
+      }
+    },
+    "tools": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["type", "function"],
+        "properties": {
+          "type": { "const": "function" },
+          "function": {
+            "type": "object",
+            "required": ["name"],
+            "properties": { "name": { "type": "string", "minLength": 1 }, "parameters": { "type": "object" } }
+          }
+        }
+      }
+    }
+  }
+}"##;
+
+const OPENAI_RESPONSES: &str = r##"{
+  "type": "object",
+  "required": ["model"],
+  "properties": {
+    "model": { "type": "string", "minLength": 1 },
+    "stream": { "type": "boolean" },
+    "instructions": { "type": "string" },
+    "max_output_tokens": { "type": "integer", "minimum": 1 },
+    "temperature": { "type": "number", "minimum": 0, "maximum": 2 },
+    "input": {
+      "anyOf": [
+        { "type": "string" },
+        {
+          "type": "array",
+          "items": {
+            "anyOf": [
+              {
+                "type": "object",
+                "required": ["type", "call_id", "name", "arguments"],
+                "properties": {
+                  "type": { "const": "function_call" },
+                  "call_id": { "type": "string", "minLength": 1 },
+                  "name": { "type": "string", "minLength": 1 },
+                  "arguments": { "type": "string" }
+                }
+              },
+              {
+                "type": "object",
+                "required": ["type", "call_id", "output"],
+                "properties": {
+                  "type": { "const": "function_call_output" },
+                  "call_id": { "type": "string", "minLength": 1 }
+                }
+              },
+              {
+                "type": "object",
+                "required": ["role", "content"],
+                "properties": {
+                  "type": { "const": "message" },
+                  "role": { "enum": ["user", "assistant", "system", "developer"] },
+                  "content": { "anyOf": [{ "type": "string" }, { "type": "array" }] }
+                }
+              },
+              {
+                "type": "object",
+                "required": ["type"],
+                "properties": {
+                  "type": { "type": "string", "not": { "enum": ["function_call", "function_call_output", "message"] } }
+                }
+              }
+            ]
+          }
+        }
+      ]
+    },
+    "tools": {
+      "type": "array",
+      "items": {
+        "anyOf": [
+          {
+            "type": "object",
+            "required": ["type", "name"],
+            "properties": { "type": { "const": "function" }, "name": { "type": "string", "minLength": 1 } }
+          },
+          {
+            "type": "object",
+            "required": ["type"],
+            "properties": { "type": { "type": "string", "not": { "const": "function" } } }
+          }
+        ]
+      }
+    }
+  }
+}"##;
+
+const GEMINI_GENERATE: &str = r##"{
+  "type": "object",
+  "required": ["contents"],
+  "properties": {
+    "contents": {
+      "type": "array",
+      "minItems": 1,
+      "items": {
+        "type": "object",
+        "required": ["parts"],
+        "properties": {
+          "role": { "enum": ["user", "model", "function"] },
+          "parts": { "type": "array", "minItems": 1, "items": { "type": "object" } }
+        }
+      }
+    },
+    "systemInstruction": { "type": "object", "properties": { "parts": { "type": "array" } } },
+    "generationConfig": {
+      "type": "object",
+      "properties": {
+        "temperature": { "type": "number", "minimum": 0, "maximum": 2 },
+        "topP": { "type": "number", "minimum": 0, "maximum": 1 },
+        "maxOutputTokens": { "type": "integer", "minimum": 1 }
+      }
+    },
+    "tools": { "type": "array", "items": { "type": "object" } }
+  }
+}"##;
+
+fn parse_schema(text: &str) -> Value {
+    serde_json::from_str(text).expect("内置请求 Schema 非法")
+}
+
+static SCHEMAS: Lazy<Vec<(&'static str, Value)>> = Lazy::new(|| {
+    vec![
+        ("claude_messages", parse_schema(CLAUDE_MESSAGES)),
+        ("openai_chat", parse_schema(OPENAI_CHAT)),
+        ("openai_responses", parse_schema(OPENAI_RESPONSES)),
+        ("gemini_generate", parse_schema(GEMINI_GENERATE)),
+    ]
+});
+
+/// 按路由与 LLM 路径选择 Schema（未知端点返回 None，不校验）
+fn schema_for(route: &str, llm_path: &str) -> Option<&'static Value> {
+    let path = llm_path
+        .split('?')
+        .next()
+        .unwrap_or(llm_path)
+        .trim_end_matches('/');
+    let name = match route {
+        "claude" if path.ends_with("/messages") => "claude_messages",
+        "codex" | "azure" if path.ends_with("/chat/completions") => "openai_chat",
+        "codex" | "azure" if path.ends_with("/responses") => "openai_responses",
+        "gemini" => {
+            let lower = path.to_ascii_lowercase();
+            if lower.ends_with(":generatecontent") || lower.ends_with(":streamgeneratecontent") {
+                "gemini_generate"
+            } else {
+                return None;
+            }
+        }
+        _ => return None,
+    };
+    SCHEMAS.iter().find(|(n, _)| *n == name).map(|(_, s)| s)
+}
+
+/// 校验请求体，返回错误列表（空表示通过或无对应 Schema）
+pub(crate) fn validate(route: &str, llm_path: &str, body: &[u8]) -> Vec<String> {
+    let Some(schema) = schema_for(route, llm_path) else {
+        return Vec::new();
+    };
+    if body.is_empty() {
+        return Vec::new();
+    }
+    let instance: Value = match serde_json::from_slice(body) {
+        Ok(v) => v,
+        Err(e) => return vec![format!(": JSON 解析失败: {}", e)],
+    };
+    let mut errors = Vec::new();
+    Validator { root: schema }.check(schema, &instance, "", &mut errors);
+    errors.truncate(MAX_ERRORS);
+    errors
+}
+
+/// 按模式处理：reject 时返回错误（不转发上游），log 时仅记录
+pub(crate) fn enforce(mode: StrictMode, route: &str, llm_path: &str, body: &[u8]) -> Result<()> {
+    let mode = StrictMode::resolve(mode);
+    if mode == StrictMode::Off {
+        return Ok(());
+    }
+    let errors = validate(route, llm_path, body);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    tracing::warn!("{} 请求未通过结构校验: {:?}", route, errors);
+    if mode == StrictMode::Reject {
+        return Err(anyhow!("请求结构校验失败: {}", errors.join("; ")));
+    }
+    Ok(())
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+}
+
+fn type_name(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn type_matches(expected: &str, v: &Value) -> bool {
+    let actual = type_name(v);
+    actual == expected || (expected == "number" && actual == "integer")
+}
+
+/// Schema 的 type 关键字是否接受该实例（未声明 type 视为接受）
+fn schema_type_matches(expected: Option<&Value>, v: &Value) -> bool {
+    match expected {
+        Some(Value::String(t)) => type_matches(t, v),
+        Some(Value::Array(ts)) => ts
+            .iter()
+            .filter_map(|t| t.as_str())
+            .any(|t| type_matches(t, v)),
+        _ => true,
+    }
+}
+
+fn escape_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+impl<'a> Validator<'a> {
+    fn resolve(&self, schema: &'a Value) -> &'a Value {
+        match schema["$ref"].as_str().and_then(|r| r.strip_prefix("#/")) {
+            Some(path) => path
+                .split('/')
+                .try_fold(self.root, |v, seg| v.get(seg))
+                .unwrap_or(&Value::Null),
+            None => schema,
+        }
+    }
+
+    fn check(&self, schema: &'a Value, v: &Value, ptr: &str, errors: &mut Vec<String>) {
+        let schema = self.resolve(schema);
+        if errors.len() >= MAX_ERRORS {
+            return;
+        }
+        let err = |errors: &mut Vec<String>, msg: String| errors.push(format!("{}: {}", ptr, msg));
+
+        if let Some(expected) = schema.get("type") {
+            if !schema_type_matches(Some(expected), v) {
+                err(
+                    errors,
+                    format!("类型应为 {}，实际为 {}", expected, type_name(v)),
+                );
+                return;
+            }
+        }
+        if let Some(c) = schema.get("const") {
+            if c != v {
+                err(errors, format!("应为 {}", c));
+                return;
+            }
+        }
+        if let Some(options) = schema["enum"].as_array() {
+            if !options.contains(v) {
+                err(errors, format!("取值 {} 不在允许范围 {:?}", v, options));
+            }
+        }
+        if let Some(not) = schema.get("not") {
+            let mut sub = Vec::new();
+            self.check(not, v, ptr, &mut sub);
+            if sub.is_empty() {
+                err(errors, format!("取值 {} 不允许", v));
+            }
+        }
+        if let Some(n) = v.as_f64() {
+            if let Some(min) = schema["minimum"].as_f64().filter(|m| n < *m) {
+                err(errors, format!("{} 小于下限 {}", n, min));
+            }
+            if let Some(max) = schema["maximum"].as_f64().filter(|m| n > *m) {
+                err(errors, format!("{} 大于上限 {}", n, max));
+            }
+        }
+        if let (Some(s), Some(min)) = (v.as_str(), schema["minLength"].as_u64()) {
+            if (s.chars().count() as u64) < min {
+                err(errors, format!("长度不能小于 {}", min));
+            }
+        }
+        for key in ["anyOf", "oneOf"] {
+            if let Some(branches) = schema[key].as_array() {
+                self.check_branches(key, branches, v, ptr, errors);
+            }
+        }
+        if let Value::Array(items) = v {
+            if let Some(min) = schema["minItems"].as_u64() {
+                if (items.len() as u64) < min {
+                    err(errors, format!("至少需要 {} 项", min));
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    self.check(item_schema, item, &format!("{}/{}", ptr, i), errors);
+                }
+            }
+        }
+        if let Value::Object(obj) = v {
+            self.check_object(schema, obj, ptr, errors);
+        }
+    }
+
+    fn check_object(
+        &self,
+        schema: &'a Value,
+        obj: &Map<String, Value>,
+        ptr: &str,
+        errors: &mut Vec<String>,
+    ) {
+        for field in schema["required"].as_array().into_iter().flatten() {
+            if let Some(name) = field.as_str() {
+                if !obj.contains_key(name) {
+                    errors.push(format!("{}/{}: 缺少必填字段", ptr, escape_pointer(name)));
+                }
+            }
+        }
+        let properties = schema["properties"].as_object();
+        for (key, value) in obj {
+            let child = format!("{}/{}", ptr, escape_pointer(key));
+            match properties.and_then(|p| p.get(key)) {
+                Some(prop) => self.check(prop, value, &child, errors),
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => errors.push(format!("{}: 不允许的字段", child)),
+                    Some(extra @ Value::Object(_)) => self.check(extra, value, &child, errors),
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    /// 任一分支通过即可（oneOf 要求恰好一个）；全部失败时优先报告 type 匹配的分支
+    fn check_branches(
+        &self,
+        key: &str,
+        branches: &'a [Value],
+        v: &Value,
+        ptr: &str,
+        errors: &mut Vec<String>,
+    ) {
+        let results: Vec<Vec<String>> = branches
+            .iter()
+            .map(|b| {
+                let mut sub = Vec::new();
+                self.check(b, v, ptr, &mut sub);
+                sub
+            })
+            .collect();
+        let passed = results.iter().filter(|r| r.is_empty()).count();
+        if passed > 0 {
+            if key == "oneOf" && passed > 1 {
+                errors.push(format!("{}: 同时匹配 {} 个 oneOf 分支", ptr, passed));
+            }
+            return;
+        }
+        let discriminated = v.get("type").and_then(|t| {
+            branches.iter().position(|b| {
+                self.resolve(b)["properties"]["type"]
+                    .get("const")
+                    .is_some_and(|c| c == t)
+            })
+        });
+        match discriminated {
+            Some(i) => errors.extend(results[i].iter().cloned()),
+            None => {
+                // 取类型相符且错误最少的分支作为最接近的候选，
+                // 避免 string | array 这类联合总是报告“类型应为 string”
+                let best = results
+                    .iter()
+                    .zip(branches)
+                    .filter(|(_, b)| schema_type_matches(self.resolve(b).get("type"), v))
+                    .map(|(r, _)| r)
+                    .min_by_key(|r| r.len())
+                    .or_else(|| results.iter().min_by_key(|r| r.len()));
+                if let Some(best) = best {
+                    errors.extend(best.iter().cloned());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claude(body: &str) -> Vec<String> {
+        validate("claude", "/v1/messages", body.as_bytes())
+    }
+
+    #[test]
+    fn builtin_schemas_parse() {
+        assert_eq!(SCHEMAS.len(), 4);
+    }
+
+    #[test]
+    fn valid_claude_request_passes() {
+        let body = r#"{
+            "model": "claude-sonnet",
+            "max_tokens": 1024,
+            "system": [{"type": "text", "text": "sys"}],
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "ok"},
+                    {"type": "tool_use", "id": "t1", "name": "read", "input": {}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "t1", "content": "done"},
+                    {"type": "document", "source": {}}
+                ]}
+            ],
+            "future_field": true
+        }"#;
+        assert_eq!(claude(body), Vec::<String>::new());
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_field() {
+        let errors = claude(
+            r#"{"model":"m","max_tokens":0,"messages":[
+                {"role":"user","content":[{"type":"tool_result","content":"x"}]},
+                {"role":"robot","content":"hi"}
+            ]}"#,
+        );
+        assert!(
+            errors.contains(&"/max_tokens: 0 小于下限 1".to_string()),
+            "{:?}",
+            errors
+        );
+        // tool_result 分支按 type 选中，只报告该分支的错误
+        assert!(
+            errors.contains(&"/messages/0/content/0/tool_use_id: 缺少必填字段".to_string()),
+            "{:?}",
+            errors
+        );
+        assert!(errors
+            .iter()
+            .any(|e| e.starts_with("/messages/1/role: 取值 \"robot\"")));
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+
+        let errors = claude(r#"{"model":"","messages":[]}"#);
+        assert!(errors.contains(&"/max_tokens: 缺少必填字段".to_string()));
+        assert!(errors.contains(&"/model: 长度不能小于 1".to_string()));
+        assert!(errors.contains(&"/messages: 至少需要 1 项".to_string()));
+    }
+
+    #[test]
+    fn type_mismatch_reported_once() {
+        let errors =
+            claude(r#"{"model":"m","max_tokens":"8","messages":[{"role":"user","content":"x"}]}"#);
+        assert_eq!(
+            errors,
+            vec!["/max_tokens: 类型应为 \"integer\"，实际为 string".to_string()]
+        );
+        let errors = claude("[1");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with(": JSON 解析失败"));
+    }
+
+    #[test]
+    fn openai_and_gemini_endpoints() {
+        let errors = validate(
+            "codex",
+            "/v1/chat/completions",
+            br#"{"model":"gpt","messages":[{"role":"tool"}],"tools":[{"type":"function","function":{}}]}"#,
+        );
+        assert!(
+            errors.contains(&"/messages/0/tool_call_id: 缺少必填字段".to_string()),
+            "{:?}",
+            errors
+        );
+        assert!(
+            errors.contains(&"/tools/0/function/name: 缺少必填字段".to_string()),
+            "{:?}",
+            errors
+        );
+
+        let errors = validate(
+            "azure",
+            "/openai/responses?api-version=1",
+            br#"{"model":"gpt","input":[{"type":"function_call_output","output":"x"}]}"#,
+        );
+        assert_eq!(errors, vec!["/input/0/call_id: 缺少必填字段".to_string()]);
+
+        let errors = validate(
+            "gemini",
+            "/v1beta/models/gemini-pro:streamGenerateContent",
+            br#"{"contents":[{"role":"user","parts":[]}]}"#,
+        );
+        assert_eq!(errors, vec!["/contents/0/parts: 至少需要 1 项".to_string()]);
+    }
+
+    #[test]
+    fn unknown_endpoints_and_empty_bodies_skipped() {
+        assert!(validate("claude", "/v1/models", b"not json").is_empty());
+        assert!(validate("amp", "/v1/messages", b"{}").is_empty());
+        assert!(validate("gemini", "/v1beta/models/gemini-pro:countTokens", b"{}").is_empty());
+        assert!(validate("claude", "/v1/messages", b"").is_empty());
+    }
+
+    #[test]
+    fn pointer_segments_escaped() {
+        let schema = serde_json::json!({ "type": "object", "additionalProperties": false });
+        let mut errors = Vec::new();
+        Validator { root: &schema }.check(
+            &schema,
+            &serde_json::json!({ "a/b~c": 1 }),
+            "",
+            &mut errors,
+        );
+        assert_eq!(errors, vec!["/a~1b~0c: 不允许的字段".to_string()]);
+    }
+
+    #[test]
+    fn one_of_requires_exactly_one_branch() {
+        let schema = serde_json::json!({ "oneOf": [{ "type": "number" }, { "type": "integer" }] });
+        let mut errors = Vec::new();
+        Validator { root: &schema }.check(&schema, &serde_json::json!(1), "", &mut errors);
+        assert_eq!(errors, vec![": 同时匹配 2 个 oneOf 分支".to_string()]);
+        errors.clear();
+        Validator { root: &schema }.check(&schema, &serde_json::json!(1.5), "", &mut errors);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn enforce_modes() {
+        let bad = br#"{"model":"m"}"#;
+        assert!(enforce(StrictMode::Off, "claude", "/v1/messages", bad).is_ok());
+        assert!(enforce(StrictMode::Log, "claude", "/v1/messages", bad).is_ok());
+        let err = enforce(StrictMode::Reject, "claude", "/v1/messages", bad).unwrap_err();
+        assert!(err.to_string().starts_with("请求结构校验失败: "));
+    }
+}
@@ -0,0 +1,453 @@
+// 请求形态快照（golden file）
+//
+// 对每条路由准备一组有代表性的 AMP 请求样本，经处理器转换后渲染为规范文本
+// （目标 URL + 排序后的请求头 + 格式化请求体），与快照目录中的 golden 文件逐字比对。
+// 处理器重构改变线上字节时比对失败，差异在评审中可见。
+// - 缺少 golden 文件时报告为 Missing（视为失败）
+// - 环境变量 DC_UPDATE_SNAPSHOTS=1 时写入缺少的 golden 文件（New），并用当前输出覆盖不一致的（Updated）
+// - 每个样本在任务内的确定性模式（固定时钟 + 种子 ID）下处理，不影响进程级设置；鉴权头只保留是否存在
+// - 单元测试使用 AppState::fixed() 的固定配置与 Profile 运行 AmpHeadersProcessor，
+//   golden 文件位于本文件旁的 snapshots/ 目录
+
+use super::determinism;
+use super::{ProcessedRequest, RequestProcessor};
+use anyhow::{anyhow, Result};
+use hyper::HeaderMap as HyperHeaderMap;
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// 快照样本
+pub(crate) struct SnapshotCase {
+    pub name: &'static str,
+    pub path: &'static str,
+    pub query: Option<&'static str>,
+    pub headers: &'static [(&'static str, &'static str)],
+    pub body: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SnapshotStatus {
+    Match,
+    /// 缺少 golden 文件
+    Missing,
+    New,
+    Updated,
+    /// 与 golden 不一致，附带首个差异行（行号从 1 开始）
+    Mismatch {
+        line: usize,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl SnapshotStatus {
+    /// 是否通过（Missing / Mismatch 为失败）
+    pub(crate) fn passed(&self) -> bool {
+        matches!(self, Self::Match | Self::New | Self::Updated)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SnapshotResult {
+    pub name: String,
+    pub status: SnapshotStatus,
+}
+
+const SNAPSHOT_SEED: u64 = 42;
+
+const SECRET_HEADERS: [&str; 4] = ["authorization", "x-api-key", "api-key", "x-goog-api-key"];
+
+/// 各路由的代表性样本
+pub(crate) fn corpus() -> Vec<SnapshotCase> {
+    vec![
+        SnapshotCase {
+            name: "claude_messages_tools",
+            path: "/api/provider/anthropic/v1/messages",
+            query: None,
+            headers: &[
+                ("anthropic-version", "2023-06-01"),
+                ("anthropic-beta", "fine-grained-tool-streaming-2025-05-14"),
+                ("x-amp-thread-id", "T-snapshot"),
+            ],
+            body: json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 1024,
+                "stream": true,
+                "system": [{ "type": "text", "text": "You are Amp, a coding agent." }],
+                "tools": [{ "name": "read_file", "description": "Read a file", "input_schema": { "type": "object" } }],
+                "messages": [
+                    { "role": "user", "content": [{ "type": "text", "text": "Open main.rs" }] },
+                    { "role": "assistant", "content": [{ "type": "tool_use", "id": "toolu_1", "name": "read_file", "input": { "path": "main.rs" } }] },
+                    { "role": "user", "content": [{ "type": "tool_result", "tool_use_id": "toolu_1", "content": "fn main() {}" }] }
+                ]
+            }),
+        },
+        SnapshotCase {
+            name: "claude_haiku_title",
+            path: "/api/provider/anthropic/v1/messages",
+            query: None,
+            headers: &[("anthropic-version", "2023-06-01")],
+            body: json!({
+                "model": "claude-haiku-4-5",
+                "max_tokens": 64,
+                "messages": [{ "role": "user", "content": "Summarize this thread in five words" }]
+            }),
+        },
+        SnapshotCase {
+            name: "codex_responses_system_input",
+            path: "/api/provider/openai/v1/responses",
+            query: None,
+            headers: &[],
+            body: json!({
+                "model": "gpt-5",
+                "stream": true,
+                "max_output_tokens": 4096,
+                "input": [
+                    { "role": "system", "content": "You are Amp." },
+                    { "role": "user", "content": [{ "type": "input_text", "text": "hello" }] }
+                ]
+            }),
+        },
+        SnapshotCase {
+            name: "codex_chat_completions",
+            path: "/api/provider/openai/v1/chat/completions",
+            query: None,
+            headers: &[],
+            body: json!({
+                "model": "gpt-4.1",
+                "messages": [{ "role": "user", "content": "hello" }]
+            }),
+        },
+        SnapshotCase {
+            name: "azure_chat_deployment",
+            path: "/api/provider/azure/v1/chat/completions",
+            query: None,
+            headers: &[("x-amp-thread-id", "T-snapshot")],
+            body: json!({
+                "model": "gpt-4.1",
+                "stream": true,
+                "messages": [
+                    { "role": "system", "content": "You are Amp." },
+                    { "role": "user", "content": "hello" }
+                ]
+            }),
+        },
+        SnapshotCase {
+            name: "gemini_stream_vertex_path",
+            path: "/api/provider/google/v1beta1/publishers/google/models/gemini-2.5-pro:streamGenerateContent",
+            query: Some("alt=sse"),
+            headers: &[],
+            body: json!({
+                "contents": [{ "role": "user", "parts": [{ "text": "hello" }] }],
+                "generationConfig": { "temperature": 0.2 }
+            }),
+        },
+    ]
+}
+
+/// 渲染为规范文本
+pub(crate) fn render(request: &ProcessedRequest) -> String {
+    let mut out = format!("{}\n\n", request.target_url);
+
+    let mut headers: Vec<(String, String)> = request
+        .headers
+        .iter()
+        .map(|(k, v)| {
+            let name = k.as_str().to_string();
+            let value = if SECRET_HEADERS.contains(&name.as_str()) {
+                "<redacted>".to_string()
+            } else {
+                v.to_str().unwrap_or("<binary>").to_string()
+            };
+            (name, value)
+        })
+        .collect();
+    headers.sort();
+    for (k, v) in headers {
+        out.push_str(&format!("{}: {}\n", k, v));
+    }
+    out.push('\n');
+
+    match serde_json::from_slice::<Value>(&request.body) {
+        Ok(body) => out.push_str(&serde_json::to_string_pretty(&body).unwrap_or_default()),
+        Err(_) => out.push_str(&String::from_utf8_lossy(&request.body)),
+    }
+    out.push('\n');
+    out
+}
+
+/// 运行全部样本并与 snapshot_dir 下的 golden 文件比对
+pub(crate) async fn run<P: RequestProcessor>(
+    processor: &P,
+    snapshot_dir: &Path,
+) -> Result<Vec<SnapshotResult>> {
+    run_cases(processor, snapshot_dir, update_requested(), corpus()).await
+}
+
+fn update_requested() -> bool {
+    std::env::var("DC_UPDATE_SNAPSHOTS").as_deref() == Ok("1")
+}
+
+async fn run_cases<P: RequestProcessor>(
+    processor: &P,
+    snapshot_dir: &Path,
+    update: bool,
+    cases: Vec<SnapshotCase>,
+) -> Result<Vec<SnapshotResult>> {
+    if update {
+        std::fs::create_dir_all(snapshot_dir).map_err(|e| anyhow!("创建快照目录失败: {}", e))?;
+    }
+    let mut results = Vec::new();
+    for case in cases {
+        let mut headers = HyperHeaderMap::new();
+        for (k, v) in case.headers {
+            headers.insert(*k, v.parse()?);
+        }
+        headers.insert("content-type", "application/json".parse()?);
+        let body = serde_json::to_vec(&case.body)?;
+
+        // 每个样本从相同的 ID 序列开始，互不影响
+        let processed = determinism::scoped(
+            SNAPSHOT_SEED,
+            None,
+            processor.process_outgoing_request("", "", case.path, case.query, &headers, &body),
+        )
+        .await;
+        let request = processed.map_err(|e| anyhow!("快照样本 {} 处理失败: {}", case.name, e))?;
+        let actual = render(&request);
+
+        let golden_path = snapshot_dir.join(format!("{}.snap", case.name));
+        let status = match std::fs::read_to_string(&golden_path) {
+            Err(_) if update => {
+                std::fs::write(&golden_path, &actual)?;
+                SnapshotStatus::New
+            }
+            Err(_) => SnapshotStatus::Missing,
+            Ok(expected) if expected == actual => SnapshotStatus::Match,
+            Ok(_) if update => {
+                std::fs::write(&golden_path, &actual)?;
+                SnapshotStatus::Updated
+            }
+            Ok(expected) => first_difference(&expected, &actual),
+        };
+        match &status {
+            SnapshotStatus::Mismatch { line, .. } => {
+                tracing::warn!("请求快照不一致: {}（第 {} 行）", case.name, line)
+            }
+            SnapshotStatus::Missing => tracing::warn!(
+                "请求快照缺少 golden 文件: {}（设置 DC_UPDATE_SNAPSHOTS=1 生成）",
+                golden_path.display()
+            ),
+            _ => {}
+        }
+        results.push(SnapshotResult {
+            name: case.name.to_string(),
+            status,
+        });
+    }
+    Ok(results)
+}
+
+fn first_difference(expected: &str, actual: &str) -> SnapshotStatus {
+    let mut exp = expected.lines();
+    let mut act = actual.lines();
+    let mut line = 0;
+    loop {
+        line += 1;
+        match (exp.next(), act.next()) {
+            (Some(e), Some(a)) if e == a => continue,
+            (None, None) => {
+                // 仅行尾差异
+                return SnapshotStatus::Mismatch {
+                    line,
+                    expected: String::new(),
+                    actual: String::new(),
+                };
+            }
+            (e, a) => {
+                return SnapshotStatus::Mismatch {
+                    line,
+                    expected: e.unwrap_or("<EOF>").to_string(),
+                    actual: a.unwrap_or("<EOF>").to_string(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::amp_processor::AmpHeadersProcessor;
+    use super::super::app_state::{AppState, SelectedProfile};
+    use super::super::inbound_auth::{hash_key, VirtualKey};
+    use super::super::processor_settings::ProcessorSettings;
+    use super::super::storage::{self, FileStorage};
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Once};
+
+    fn profile(name: &str, base_url: &str) -> Option<SelectedProfile> {
+        Some(SelectedProfile {
+            name: name.to_string(),
+            base_url: base_url.to_string(),
+            api_key: format!("{}-key", name),
+        })
+    }
+
+    fn processor() -> AmpHeadersProcessor {
+        processor_with(|_| {})
+    }
+
+    fn processor_with(configure: impl FnOnce(&mut ProcessorSettings)) -> AmpHeadersProcessor {
+        // 用量等持久化数据写入临时目录，不落到 ~/.duckcoding
+        static STORAGE: Once = Once::new();
+        STORAGE.call_once(|| {
+            let root = std::env::temp_dir().join("dc-request-snapshots");
+            storage::install(Arc::new(FileStorage::new(root)));
+        });
+        let mut settings = ProcessorSettings::default();
+        settings.amp_header_capture.enabled = false;
+        settings.azure.base_url = "https://snapshot.openai.azure.com".to_string();
+        settings.azure.api_key = "azure-key".to_string();
+        settings
+            .azure
+            .deployments
+            .insert("gpt-4.1".to_string(), "gpt41-prod".to_string());
+        configure(&mut settings);
+        let selection = (
+            profile("snapshot-claude", "https://api.anthropic.com"),
+            profile("snapshot-codex", "https://api.openai.com"),
+            profile(
+                "snapshot-gemini",
+                "https://generativelanguage.googleapis.com",
+            ),
+        );
+        AmpHeadersProcessor::new(Arc::new(AppState::fixed(settings, selection)))
+    }
+
+    /// 本文件旁的 snapshots/（file!() 为相对路径时相对于 crate 根目录）
+    fn snapshot_dir() -> PathBuf {
+        let source = Path::new(file!());
+        let source = if source.is_absolute() {
+            source.to_path_buf()
+        } else {
+            Path::new(env!("CARGO_MANIFEST_DIR")).join(source)
+        };
+        source.with_file_name("snapshots")
+    }
+
+    async fn check(route: &str) {
+        let cases: Vec<_> = corpus()
+            .into_iter()
+            .filter(|c| c.name.starts_with(route))
+            .collect();
+        assert!(!cases.is_empty(), "{} 没有快照样本", route);
+        let results = run_cases(&processor(), &snapshot_dir(), update_requested(), cases)
+            .await
+            .unwrap();
+        for result in results {
+            assert!(
+                result.status.passed(),
+                "{}: {:?}",
+                result.name,
+                result.status
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn claude_requests_match_golden() {
+        check("claude_").await;
+    }
+
+    #[tokio::test]
+    async fn codex_requests_match_golden() {
+        check("codex_").await;
+    }
+
+    #[tokio::test]
+    async fn virtual_key_never_reaches_upstream() {
+        let processor = processor_with(|settings| {
+            settings.inbound_auth.enabled = true;
+            settings.inbound_auth.keys.push(VirtualKey {
+                name: "laptop".to_string(),
+                key_hash: hash_key("dc-vk-secret"),
+                ..Default::default()
+            });
+        });
+        for case in corpus() {
+            let mut headers = HyperHeaderMap::new();
+            for (k, v) in case.headers {
+                headers.insert(*k, v.parse().unwrap());
+            }
+            headers.insert("authorization", "Bearer dc-vk-secret".parse().unwrap());
+            headers.insert("x-goog-api-key", "dc-vk-secret".parse().unwrap());
+            let query = match case.query {
+                Some(q) => format!("{}&key=dc-vk-secret", q),
+                None => "key=dc-vk-secret".to_string(),
+            };
+            let body = serde_json::to_vec(&case.body).unwrap();
+            let request = processor
+                .process_outgoing_request("", "", case.path, Some(&query), &headers, &body)
+                .await
+                .unwrap_or_else(|e| panic!("{}: {}", case.name, e));
+            assert!(
+                !request.target_url.contains("dc-vk-"),
+                "{}: {}",
+                case.name,
+                request.target_url
+            );
+            for (name, value) in &request.headers {
+                assert!(
+                    !value.to_str().unwrap_or_default().contains("dc-vk-"),
+                    "{}: 请求头 {} 携带虚拟 Key",
+                    case.name,
+                    name
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn gemini_requests_match_golden() {
+        check("gemini_").await;
+    }
+
+    #[tokio::test]
+    async fn azure_requests_match_golden() {
+        check("azure_").await;
+    }
+
+    #[test]
+    fn every_case_has_a_golden_file() {
+        let dir = snapshot_dir();
+        for case in corpus() {
+            let path = dir.join(format!("{}.snap", case.name));
+            assert!(
+                path.exists() || update_requested(),
+                "缺少 {}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn first_difference_reports_line() {
+        assert_eq!(
+            first_difference("a\nb\nc\n", "a\nx\nc\n"),
+            SnapshotStatus::Mismatch {
+                line: 2,
+                expected: "b".to_string(),
+                actual: "x".to_string(),
+            }
+        );
+        assert_eq!(
+            first_difference("a\n", "a\nb\n"),
+            SnapshotStatus::Mismatch {
+                line: 2,
+                expected: "<EOF>".to_string(),
+                actual: "b".to_string(),
+            }
+        );
+    }
+}
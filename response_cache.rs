@@ -0,0 +1,379 @@
+// LLM 响应精确匹配缓存（按 Profile 开启）
+//
+// AMP 重试或恢复线程时会重放完全相同的请求，开启后由本地缓存直接应答，不再消耗 token：
+// - 键：路由 + 上游路径 + 归一化请求体的 SHA-256。归一化去掉不影响输出的字段
+//   （metadata / user / prompt_cache_key / safety_identifier / cache_control 等），
+//   对象键排序后序列化，因此字段顺序与缓存断点位置不同也能命中
+// - 仅缓存完整结束的 2xx 响应（流式响应在流正常结束后才写入），单条与总量均有上限
+// - 内存 LRU + TTL，不落盘（请求内容可能包含敏感代码）
+// 命中时在转发层直接返回（x-dc-cache: hit），不经限流、预算与请求日志。
+
+use super::upstream::Forwarded;
+use bytes::Bytes;
+use futures_util::StreamExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 归一化时移除的字段（不影响模型输出）
+const VOLATILE_FIELDS: &[&str] = &[
+    "metadata",
+    "user",
+    "prompt_cache_key",
+    "safety_identifier",
+    "cache_control",
+    "stream_options",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResponseCacheSettings {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    /// 最多缓存的响应数
+    pub max_entries: usize,
+    /// 单条响应体上限（超过不缓存）
+    pub max_entry_bytes: usize,
+    /// 全部缓存响应体总量上限
+    pub max_total_bytes: usize,
+}
+
+impl Default for ResponseCacheSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl_secs: 600,
+            max_entries: 128,
+            max_entry_bytes: 4 * 1024 * 1024,
+            max_total_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+struct Entry {
+    stored_at: Instant,
+    last_used: u64,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, Entry>,
+    total_bytes: usize,
+    clock: u64,
+}
+
+static CACHE: Lazy<Mutex<Lru>> = Lazy::new(|| Mutex::new(Lru::default()));
+
+/// 递归排序对象键并移除无关字段
+fn canonicalize(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map
+                .keys()
+                .filter(|k| !VOLATILE_FIELDS.contains(&k.as_str()))
+                .collect();
+            keys.sort();
+            let mut out = Map::new();
+            for key in keys {
+                out.insert(key.clone(), canonicalize(&map[key]));
+            }
+            Value::Object(out)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(canonicalize).collect()),
+        other => other.clone(),
+    }
+}
+
+/// 缓存键；请求体不是 JSON 时不缓存
+pub(crate) fn key(route: &str, target_url: &str, body: &[u8]) -> Option<String> {
+    let value: Value = serde_json::from_slice(body).ok()?;
+    let path = target_url.split('?').next().unwrap_or(target_url);
+    let mut hasher = Sha256::new();
+    hasher.update(route.as_bytes());
+    hasher.update([0]);
+    hasher.update(path.as_bytes());
+    hasher.update([0]);
+    hasher.update(canonicalize(&value).to_string().as_bytes());
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+/// 查询缓存，命中时构造本地响应
+pub(crate) fn lookup(settings: &ResponseCacheSettings, key: &str) -> Option<Forwarded> {
+    if !settings.enabled {
+        return None;
+    }
+    let ttl = Duration::from_secs(settings.ttl_secs);
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache.clock += 1;
+    let clock = cache.clock;
+    let expired = cache.entries.get(key)?.stored_at.elapsed() >= ttl;
+    if expired {
+        if let Some(entry) = cache.entries.remove(key) {
+            cache.total_bytes -= entry.body.len();
+        }
+        return None;
+    }
+    let entry = cache.entries.get_mut(key)?;
+    entry.last_used = clock;
+
+    let mut builder = hyper::http::Response::builder().status(200);
+    for (name, value) in &entry.headers {
+        builder = builder.header(name, value);
+    }
+    let response = builder
+        .header("x-dc-cache", "hit")
+        .body(reqwest::Body::from(entry.body.clone()))
+        .ok()?;
+    Some(Forwarded {
+        response: reqwest::Response::from(response),
+        served_by: "cache".to_string(),
+    })
+}
+
+fn insert(settings: &ResponseCacheSettings, key: String, entry: Entry) {
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(old) = cache.entries.remove(&key) {
+        cache.total_bytes -= old.body.len();
+    }
+    while !cache.entries.is_empty()
+        && (cache.entries.len() >= settings.max_entries.max(1)
+            || cache.total_bytes + entry.body.len() > settings.max_total_bytes)
+    {
+        let Some(oldest) = cache
+            .entries
+            .iter()
+            .min_by_key(|(_, e)| e.last_used)
+            .map(|(k, _)| k.clone())
+        else {
+            break;
+        };
+        if let Some(evicted) = cache.entries.remove(&oldest) {
+            cache.total_bytes -= evicted.body.len();
+        }
+    }
+    cache.clock += 1;
+    cache.total_bytes += entry.body.len();
+    let entry = Entry {
+        last_used: cache.clock,
+        ..entry
+    };
+    cache.entries.insert(key, entry);
+}
+
+/// 当前条目数与总字节数
+pub(crate) fn stats() -> (usize, usize) {
+    let cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    (cache.entries.len(), cache.total_bytes)
+}
+
+/// 清空缓存，返回清除的条目数
+#[cfg(feature = "admin")]
+pub(crate) fn clear() -> usize {
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let n = cache.entries.len();
+    cache.entries.clear();
+    cache.total_bytes = 0;
+    n
+}
+
+/// 包装响应：流正常结束且未超过单条上限时写入缓存
+pub(crate) fn store(
+    settings: &ResponseCacheSettings,
+    key: String,
+    forwarded: Forwarded,
+) -> Forwarded {
+    if !settings.enabled || !forwarded.response.status().is_success() {
+        return forwarded;
+    }
+    let response = forwarded.response;
+    let mut builder = hyper::http::Response::builder().status(response.status());
+    let mut headers = Vec::new();
+    for (name, value) in response.headers() {
+        builder = builder.header(name, value);
+        // 逐跳头与长度由本地响应重新生成
+        if !matches!(
+            name.as_str(),
+            "content-length" | "transfer-encoding" | "connection" | "date"
+        ) {
+            if let Ok(value) = value.to_str() {
+                headers.push((name.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    let settings = settings.clone();
+    let state = (
+        Box::pin(response.bytes_stream()),
+        Some(Vec::new()),
+        key,
+        headers,
+    );
+    let body = futures_util::stream::unfold(state, move |(mut inner, mut buf, key, headers)| {
+        let settings = settings.clone();
+        async move {
+            match inner.next().await {
+                Some(Ok(bytes)) => {
+                    if let Some(data) = buf.as_mut() {
+                        data.extend_from_slice(&bytes);
+                        if data.len() > settings.max_entry_bytes {
+                            buf = None;
+                        }
+                    }
+                    Some((
+                        Ok::<Bytes, reqwest::Error>(bytes),
+                        (inner, buf, key, headers),
+                    ))
+                }
+                Some(Err(e)) => Some((Err(e), (inner, None, key, headers))),
+                None => {
+                    if let Some(data) = buf.take() {
+                        let entry = Entry {
+                            stored_at: Instant::now(),
+                            last_used: 0,
+                            headers: headers.clone(),
+                            body: Bytes::from(data),
+                        };
+                        insert(&settings, key.clone(), entry);
+                    }
+                    None
+                }
+            }
+        }
+    });
+    let response = builder
+        .body(reqwest::Body::wrap_stream(body))
+        .map(reqwest::Response::from)
+        .unwrap_or_else(|_| {
+            reqwest::Response::from(hyper::http::Response::new(reqwest::Body::from("")))
+        });
+    Forwarded {
+        response,
+        served_by: forwarded.served_by,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> ResponseCacheSettings {
+        ResponseCacheSettings {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    fn forwarded(status: u16, body: &str) -> Forwarded {
+        Forwarded {
+            response: reqwest::Response::from(
+                hyper::http::Response::builder()
+                    .status(status)
+                    .header("content-type", "application/json")
+                    .header("content-length", body.len())
+                    .body(reqwest::Body::from(body.to_string()))
+                    .unwrap(),
+            ),
+            served_by: "upstream".to_string(),
+        }
+    }
+
+    #[test]
+    fn key_ignores_field_order_and_volatile_fields() {
+        let a = key(
+            "claude",
+            "https://api/v1/messages",
+            br#"{"model":"m","messages":[{"role":"user","content":"hi"}],"metadata":{"user_id":"a"}}"#,
+        )
+        .unwrap();
+        let b = key(
+            "claude",
+            "https://api/v1/messages?beta=true",
+            br#"{"messages":[{"content":"hi","role":"user","cache_control":{"type":"ephemeral"}}],"model":"m","user":"b"}"#,
+        )
+        .unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn key_separates_route_path_and_body() {
+        let body = br#"{"model":"m"}"#;
+        let base = key("claude", "https://api/v1/messages", body).unwrap();
+        assert_ne!(base, key("codex", "https://api/v1/messages", body).unwrap());
+        assert_ne!(
+            base,
+            key("claude", "https://api/v1/complete", body).unwrap()
+        );
+        assert_ne!(
+            base,
+            key("claude", "https://api/v1/messages", br#"{"model":"n"}"#).unwrap()
+        );
+        assert!(key("claude", "https://api/v1/messages", b"not json").is_none());
+    }
+
+    #[tokio::test]
+    async fn completed_response_served_from_cache() {
+        let settings = enabled();
+        let k = key("cache-hit", "https://api/v1/messages", b"{}").unwrap();
+        assert!(lookup(&settings, &k).is_none());
+
+        let out = store(&settings, k.clone(), forwarded(200, r#"{"ok":true}"#));
+        // 流读完前不写入
+        assert!(lookup(&settings, &k).is_none());
+        assert_eq!(out.response.text().await.unwrap(), r#"{"ok":true}"#);
+
+        let hit = lookup(&settings, &k).unwrap();
+        assert_eq!(hit.served_by, "cache");
+        assert_eq!(hit.response.headers()["x-dc-cache"], "hit");
+        assert_eq!(hit.response.headers()["content-type"], "application/json");
+        assert_eq!(hit.response.text().await.unwrap(), r#"{"ok":true}"#);
+
+        // 关闭后不再命中
+        assert!(lookup(&ResponseCacheSettings::default(), &k).is_none());
+    }
+
+    #[tokio::test]
+    async fn errors_and_oversized_bodies_not_cached() {
+        let settings = enabled();
+        let k = key("cache-error", "https://api/v1/messages", b"{}").unwrap();
+        let out = store(&settings, k.clone(), forwarded(500, "boom"));
+        assert_eq!(out.response.text().await.unwrap(), "boom");
+        assert!(lookup(&settings, &k).is_none());
+
+        let small = ResponseCacheSettings {
+            max_entry_bytes: 4,
+            ..enabled()
+        };
+        let k = key("cache-large", "https://api/v1/messages", b"{}").unwrap();
+        let out = store(&small, k.clone(), forwarded(200, "0123456789"));
+        assert_eq!(out.response.text().await.unwrap(), "0123456789");
+        assert!(lookup(&small, &k).is_none());
+    }
+
+    #[test]
+    fn expired_entry_dropped() {
+        let k = key("cache-ttl", "https://api/v1/messages", b"{}").unwrap();
+        insert(
+            &enabled(),
+            k.clone(),
+            Entry {
+                stored_at: Instant::now(),
+                last_used: 0,
+                headers: Vec::new(),
+                body: Bytes::from_static(b"x"),
+            },
+        );
+        let expired = ResponseCacheSettings {
+            ttl_secs: 0,
+            ..enabled()
+        };
+        assert!(lookup(&expired, &k).is_none());
+        // 过期条目已移除
+        assert!(lookup(&enabled(), &k).is_none());
+    }
+}
@@ -0,0 +1,738 @@
+// 响应侧改写（与处理器的请求侧协议转换 / 兼容层对应）
+//
+// 处理器构造请求时确定响应需要的改写（ResponsePlan，随 UpstreamTag 交给转发层），
+// forward() 在停滞检测之后、输出上限之前调用 apply()：
+// - 流式响应逐块经各改写阶段串联处理，不缓冲整个响应
+// - 非流式响应读完后整体改写（含错误响应，转换为客户端协议的错误格式），
+//   Profile 开启调试时采集调试头与 logprobs 并从响应体移除（见 debug_capture.rs），
+//   再按客户端协议识别内容安全拦截，改写为 200 的普通消息（见 safety_block.rs）
+// 未计划任何改写时原样返回，不读取响应体。
+
+use super::bedrock_processor::BedrockStreamConverter;
+use super::debug_capture;
+use super::gemini_stream::{self, GeminiStreamConverter, GeminiStreamFormat};
+use super::openai_translate::{self, OpenAiStreamTranslator};
+use super::processor_settings::ProfileSettings;
+use super::react_shim::{self, ReactStreamRewriter};
+use super::responses_downgrade::{self, ResponsesStreamTranslator};
+use super::safety_block::{self, SAFETY_BLOCK_HEADER};
+use super::transform_middleware::TransformTarget;
+use super::upstream::Forwarded;
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use futures_util::{Stream, StreamExt};
+use std::pin::Pin;
+
+/// Bedrock 流式响应的 content-type
+const BEDROCK_STREAM_TYPE: &str = "application/vnd.amazon.eventstream";
+
+/// 上游协议 → 客户端协议的转换
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Conversion {
+    #[default]
+    None,
+    /// 上游为 OpenAI Chat，响应转回 Anthropic Messages（见 openai_translate.rs）
+    OpenAiChat,
+    /// Responses 请求降级为 OpenAI Chat，响应转回 Responses 格式（见 responses_downgrade.rs）
+    ResponsesDowngrade,
+    /// AWS Bedrock：流式响应为 event stream 二进制帧，还原为 Anthropic SSE（非流式已是 Anthropic 格式）
+    Bedrock,
+}
+
+/// 响应侧改写计划，由处理器随 UpstreamTag 返回
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponsePlan {
+    /// 客户端协议（转换后的响应按该协议识别安全拦截）
+    pub(crate) target: Option<TransformTarget>,
+    pub(crate) conversion: Conversion,
+    /// ReAct 兼容层：从（转换后的）Anthropic 文本中解析工具调用（见 react_shim.rs）
+    pub(crate) react_shim: bool,
+    /// Gemini 流式请求：客户端期望的流式格式（由 query 的 alt=sse 决定，见 gemini_stream.rs）
+    pub(crate) gemini_stream: Option<GeminiStreamFormat>,
+}
+
+impl ResponsePlan {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// 流式改写阶段：按块输入，按块输出（跨块边界的事件由各阶段自行缓存）
+trait StreamRewriter: Send {
+    fn feed(&mut self, chunk: &[u8]) -> Bytes;
+    /// 上游流结束：输出残余数据与收尾事件
+    fn finish(&mut self) -> Bytes;
+}
+
+impl StreamRewriter for OpenAiStreamTranslator {
+    fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        OpenAiStreamTranslator::feed(self, chunk)
+    }
+
+    fn finish(&mut self) -> Bytes {
+        OpenAiStreamTranslator::finish(self)
+    }
+}
+
+impl StreamRewriter for ResponsesStreamTranslator {
+    fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        ResponsesStreamTranslator::feed(self, chunk)
+    }
+
+    fn finish(&mut self) -> Bytes {
+        ResponsesStreamTranslator::finish(self)
+    }
+}
+
+impl StreamRewriter for ReactStreamRewriter {
+    fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        ReactStreamRewriter::feed(self, chunk)
+    }
+
+    fn finish(&mut self) -> Bytes {
+        ReactStreamRewriter::finish(self)
+    }
+}
+
+impl StreamRewriter for BedrockStreamConverter {
+    fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        BedrockStreamConverter::feed(self, chunk)
+    }
+
+    fn finish(&mut self) -> Bytes {
+        Bytes::new()
+    }
+}
+
+/// Gemini 流式格式转换：首个非空白块到达后检测后端格式，再创建转换器
+struct GeminiStage {
+    expected: GeminiStreamFormat,
+    content_type: Option<String>,
+    /// 检测前收到的空白块
+    pending: Vec<u8>,
+    converter: Option<GeminiStreamConverter>,
+}
+
+impl GeminiStage {
+    fn converter(&mut self, head: &[u8]) -> &mut GeminiStreamConverter {
+        let (content_type, expected) = (self.content_type.as_deref(), self.expected);
+        self.converter.get_or_insert_with(|| {
+            let from = gemini_stream::detect_format(content_type, head);
+            if from != expected {
+                tracing::debug!("Gemini 流式格式转换: {:?} → {:?}", from, expected);
+            }
+            GeminiStreamConverter::new(from, expected)
+        })
+    }
+}
+
+impl StreamRewriter for GeminiStage {
+    fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        if self.converter.is_none() {
+            self.pending.extend_from_slice(chunk);
+            let is_sse = self
+                .content_type
+                .as_deref()
+                .is_some_and(|v| v.contains("text/event-stream"));
+            if !is_sse && self.pending.iter().all(|b| b.is_ascii_whitespace()) {
+                return Bytes::new();
+            }
+            let head = std::mem::take(&mut self.pending);
+            return self.converter(&head).feed(&head);
+        }
+        self.converter(&[]).feed(chunk)
+    }
+
+    fn finish(&mut self) -> Bytes {
+        let head = std::mem::take(&mut self.pending);
+        let converter = self.converter(&head);
+        let mut out = BytesMut::from(&converter.feed(&head)[..]);
+        out.extend_from_slice(&converter.finish());
+        out.freeze()
+    }
+}
+
+/// 多个阶段串联，前一阶段的输出作为后一阶段的输入
+struct Pipeline(Vec<Box<dyn StreamRewriter>>);
+
+impl Pipeline {
+    fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        let mut data = Bytes::copy_from_slice(chunk);
+        for stage in &mut self.0 {
+            data = stage.feed(&data);
+        }
+        data
+    }
+
+    fn finish(&mut self) -> Bytes {
+        let mut carry = Bytes::new();
+        for stage in &mut self.0 {
+            let mut out = BytesMut::from(&stage.feed(&carry)[..]);
+            out.extend_from_slice(&stage.finish());
+            carry = out.freeze();
+        }
+        carry
+    }
+}
+
+fn content_type(response: &reqwest::Response) -> Option<&str> {
+    response
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+}
+
+fn stream_stages(plan: &ResponsePlan, content_type: Option<&str>) -> Vec<Box<dyn StreamRewriter>> {
+    let mut stages: Vec<Box<dyn StreamRewriter>> = Vec::new();
+    if let Some(expected) = plan.gemini_stream {
+        stages.push(Box::new(GeminiStage {
+            expected,
+            content_type: content_type.map(|v| v.to_string()),
+            pending: Vec::new(),
+            converter: None,
+        }));
+    }
+    match plan.conversion {
+        Conversion::OpenAiChat => stages.push(Box::new(OpenAiStreamTranslator::new())),
+        Conversion::ResponsesDowngrade => stages.push(Box::new(ResponsesStreamTranslator::new())),
+        Conversion::Bedrock => stages.push(Box::new(BedrockStreamConverter::new())),
+        Conversion::None => {}
+    }
+    if plan.react_shim {
+        stages.push(Box::new(ReactStreamRewriter::new()));
+    }
+    stages
+}
+
+/// 非流式响应体改写，返回 None 表示无需改写
+fn rewrite_body(plan: &ResponsePlan, body: &[u8]) -> Option<Vec<u8>> {
+    let converted = match plan.conversion {
+        Conversion::OpenAiChat => openai_translate::translate_response(body),
+        Conversion::ResponsesDowngrade => responses_downgrade::translate_response(body),
+        Conversion::Bedrock | Conversion::None => None,
+    };
+    if !plan.react_shim {
+        return converted;
+    }
+    react_shim::rewrite_response(converted.as_deref().unwrap_or(body)).or(converted)
+}
+
+/// 按原状态码与响应头（去掉 content-length）重建响应
+pub(crate) fn rebuild(response: &reqwest::Response, body: reqwest::Body) -> reqwest::Response {
+    rebuild_as(response, response.status(), None, body)
+}
+
+/// 同 rebuild()，替换状态码；content_type 非空时替换响应的 content-type
+fn rebuild_as(
+    response: &reqwest::Response,
+    status: reqwest::StatusCode,
+    content_type: Option<&str>,
+    body: reqwest::Body,
+) -> reqwest::Response {
+    let mut builder = hyper::http::Response::builder().status(status);
+    for (key, value) in response.headers() {
+        let replaced = content_type.is_some() && key == hyper::header::CONTENT_TYPE;
+        if key != hyper::header::CONTENT_LENGTH && !replaced {
+            builder = builder.header(key, value);
+        }
+    }
+    if let Some(content_type) = content_type {
+        builder = builder.header(hyper::header::CONTENT_TYPE, content_type);
+    }
+    builder
+        .body(body)
+        .map(reqwest::Response::from)
+        .unwrap_or_else(|_| {
+            reqwest::Response::from(hyper::http::Response::new(reqwest::Body::from("")))
+        })
+}
+
+struct StreamState {
+    inner: Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>,
+    pipeline: Pipeline,
+    finished: bool,
+}
+
+fn rewrite_stream(
+    response: reqwest::Response,
+    content_type: Option<&str>,
+    pipeline: Pipeline,
+) -> reqwest::Response {
+    let template = rebuild(&response, reqwest::Body::from(""));
+    let state = StreamState {
+        inner: Box::pin(response.bytes_stream()),
+        pipeline,
+        finished: false,
+    };
+    let body = futures_util::stream::unfold(state, |mut state| async move {
+        loop {
+            if state.finished {
+                return None;
+            }
+            let out = match state.inner.next().await {
+                Some(Ok(chunk)) => state.pipeline.feed(&chunk),
+                Some(Err(e)) => {
+                    state.finished = true;
+                    return Some((Err(e), state));
+                }
+                None => {
+                    state.finished = true;
+                    state.pipeline.finish()
+                }
+            };
+            if !out.is_empty() {
+                return Some((Ok::<Bytes, reqwest::Error>(out), state));
+            }
+        }
+    });
+    let status = template.status();
+    rebuild_as(
+        &template,
+        status,
+        content_type,
+        reqwest::Body::wrap_stream(body),
+    )
+}
+
+/// 按计划改写响应；未计划改写时原样返回
+pub(crate) async fn apply(
+    plan: &ResponsePlan,
+    settings: &ProfileSettings,
+    forwarded: Forwarded,
+) -> Result<Forwarded> {
+    if plan.is_empty() {
+        return Ok(forwarded);
+    }
+    let Forwarded {
+        response,
+        served_by,
+    } = forwarded;
+    // Gemini 流式响应可能是 JSON 数组，按请求判断而非 content-type
+    let is_stream = plan.gemini_stream.is_some()
+        || content_type(&response).is_some_and(|v| {
+            v.contains("text/event-stream")
+                || (plan.conversion == Conversion::Bedrock && v.contains(BEDROCK_STREAM_TYPE))
+        });
+    let response = if is_stream {
+        if response.status().is_success() {
+            let output_type = match plan.gemini_stream {
+                Some(GeminiStreamFormat::Sse) => Some("text/event-stream"),
+                Some(GeminiStreamFormat::Json) => Some("application/json"),
+                None => (plan.conversion == Conversion::Bedrock).then_some("text/event-stream"),
+            };
+            let stages = stream_stages(plan, content_type(&response));
+            rewrite_stream(response, output_type, Pipeline(stages))
+        } else {
+            response
+        }
+    } else {
+        let template = rebuild(&response, reqwest::Body::from(""));
+        let body = response.bytes().await?;
+        let body = rewrite_body(plan, &body).map(Bytes::from).unwrap_or(body);
+        let body = plan
+            .target
+            .and_then(|target| {
+                debug_capture::capture_response(
+                    settings,
+                    target,
+                    &served_by,
+                    template.headers(),
+                    &body,
+                )
+            })
+            .map(Bytes::from)
+            .unwrap_or(body);
+        match plan
+            .target
+            .and_then(|target| safety_block::normalize_response(target, &body))
+        {
+            Some((replacement, marker)) => {
+                let mut response = rebuild_as(
+                    &template,
+                    reqwest::StatusCode::OK,
+                    None,
+                    reqwest::Body::from(replacement),
+                );
+                if let Ok(v) = marker.parse() {
+                    response.headers_mut().insert(SAFETY_BLOCK_HEADER, v);
+                }
+                response
+            }
+            None => rebuild(&template, reqwest::Body::from(body)),
+        }
+    };
+    Ok(Forwarded {
+        response,
+        served_by,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(content_type: &str, body: &'static str) -> reqwest::Response {
+        reqwest::Response::from(
+            hyper::http::Response::builder()
+                .status(200)
+                .header("content-type", content_type)
+                .header("content-length", body.len())
+                .body(reqwest::Body::from(body))
+                .unwrap(),
+        )
+    }
+
+    async fn run(plan: &ResponsePlan, content_type: &str, body: &'static str) -> String {
+        let forwarded = Forwarded {
+            response: response(content_type, body),
+            served_by: "primary".to_string(),
+        };
+        let out = apply(plan, &ProfileSettings::default(), forwarded)
+            .await
+            .unwrap();
+        assert_eq!(out.served_by, "primary");
+        assert!(out.response.headers().get("content-length").is_none());
+        out.response.text().await.unwrap()
+    }
+
+    fn plan(conversion: Conversion) -> ResponsePlan {
+        ResponsePlan {
+            conversion,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_plan_passes_through() {
+        let body = r#"{"choices":[]}"#;
+        let forwarded = Forwarded {
+            response: response("application/json", body),
+            served_by: String::new(),
+        };
+        let out = apply(
+            &ResponsePlan::default(),
+            &ProfileSettings::default(),
+            forwarded,
+        )
+        .await
+        .unwrap();
+        assert!(out.response.headers().get("content-length").is_some());
+        assert_eq!(out.response.text().await.unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn openai_json_translated_to_anthropic() {
+        let body = r#"{"id":"chatcmpl-1","model":"gpt","choices":[{"message":{"content":"hi"},"finish_reason":"stop"}],"usage":{"prompt_tokens":3,"completion_tokens":1}}"#;
+        let text = run(&plan(Conversion::OpenAiChat), "application/json", body).await;
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json["type"], "message");
+        assert_eq!(json["content"][0]["text"], "hi");
+        assert_eq!(json["stop_reason"], "end_turn");
+    }
+
+    #[tokio::test]
+    async fn openai_stream_translated_to_anthropic() {
+        let body = "data: {\"id\":\"c1\",\"model\":\"gpt\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}]}\n\n\
+                    data: {\"id\":\"c1\",\"model\":\"gpt\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n\
+                    data: [DONE]\n\n";
+        let text = run(&plan(Conversion::OpenAiChat), "text/event-stream", body).await;
+        assert!(text.contains("event: message_start"));
+        assert!(text.contains("\"text\":\"hi\""));
+        assert!(text.contains("event: message_stop"));
+        assert!(!text.contains("[DONE]"));
+    }
+
+    #[tokio::test]
+    async fn downgraded_json_translated_to_responses() {
+        let body = r#"{"id":"chatcmpl-1","model":"gpt","created":1,"choices":[{"message":{"content":"hi"},"finish_reason":"stop"}]}"#;
+        let text = run(
+            &plan(Conversion::ResponsesDowngrade),
+            "application/json",
+            body,
+        )
+        .await;
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json["object"], "response");
+        assert_eq!(json["status"], "completed");
+        assert_eq!(json["output"][0]["content"][0]["text"], "hi");
+    }
+
+    #[tokio::test]
+    async fn downgraded_error_passes_through() {
+        let body = r#"{"error":{"type":"invalid_request_error","message":"bad"}}"#;
+        let text = run(
+            &plan(Conversion::ResponsesDowngrade),
+            "application/json",
+            body,
+        )
+        .await;
+        assert_eq!(text, body);
+    }
+
+    #[tokio::test]
+    async fn downgraded_stream_translated_to_responses() {
+        let body = "data: {\"id\":\"c1\",\"model\":\"gpt\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"}}]}\n\n\
+                    data: [DONE]\n\n";
+        let text = run(
+            &plan(Conversion::ResponsesDowngrade),
+            "text/event-stream",
+            body,
+        )
+        .await;
+        assert!(text.contains("response.created"));
+        assert!(text.contains("response.output_text.delta"));
+        assert!(text.contains("response.completed"));
+    }
+
+    #[tokio::test]
+    async fn react_shim_applies_after_conversion() {
+        let body = r#"{"id":"chatcmpl-1","model":"gpt","choices":[{"message":{"content":"ok <tool_call>{\"name\":\"read\",\"input\":{\"path\":\"a\"}}</tool_call>"},"finish_reason":"stop"}]}"#;
+        let plan = ResponsePlan {
+            conversion: Conversion::OpenAiChat,
+            react_shim: true,
+            ..Default::default()
+        };
+        let text = run(&plan, "application/json", body).await;
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json["stop_reason"], "tool_use");
+        assert_eq!(json["content"][0]["text"], "ok");
+        assert_eq!(json["content"][1]["name"], "read");
+    }
+
+    #[tokio::test]
+    async fn react_shim_without_calls_keeps_body() {
+        let body = r#"{"type":"message","content":[{"type":"text","text":"plain"}],"stop_reason":"end_turn"}"#;
+        let plan = ResponsePlan {
+            react_shim: true,
+            ..Default::default()
+        };
+        assert_eq!(run(&plan, "application/json", body).await, body);
+    }
+
+    fn base64(data: &[u8]) -> String {
+        const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = String::new();
+        for chunk in data.chunks(3) {
+            let n =
+                chunk.iter().fold(0u32, |acc, b| acc << 8 | *b as u32) << (8 * (3 - chunk.len()));
+            for i in 0..=chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 63) as usize] as char);
+            }
+        }
+        out
+    }
+
+    /// 构造一帧 AWS event stream（CRC 不校验，填 0）
+    fn bedrock_frame(event: &serde_json::Value) -> Vec<u8> {
+        let encoded = base64(event.to_string().as_bytes());
+        let payload = serde_json::json!({ "bytes": encoded }).to_string();
+        let mut headers = Vec::new();
+        for (name, value) in [(":message-type", "event"), (":event-type", "chunk")] {
+            headers.push(name.len() as u8);
+            headers.extend_from_slice(name.as_bytes());
+            headers.push(7);
+            headers.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            headers.extend_from_slice(value.as_bytes());
+        }
+        let total = 12 + headers.len() + payload.len() + 4;
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&(total as u32).to_be_bytes());
+        frame.extend_from_slice(&(headers.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        frame.extend_from_slice(&headers);
+        frame.extend_from_slice(payload.as_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        frame
+    }
+
+    #[tokio::test]
+    async fn bedrock_event_stream_becomes_sse() {
+        let mut body = bedrock_frame(&serde_json::json!({ "type": "message_start" }));
+        body.extend(bedrock_frame(
+            &serde_json::json!({ "type": "message_stop" }),
+        ));
+        let forwarded = Forwarded {
+            response: reqwest::Response::from(
+                hyper::http::Response::builder()
+                    .status(200)
+                    .header("content-type", BEDROCK_STREAM_TYPE)
+                    .body(reqwest::Body::from(body))
+                    .unwrap(),
+            ),
+            served_by: String::new(),
+        };
+        let out = apply(
+            &plan(Conversion::Bedrock),
+            &ProfileSettings::default(),
+            forwarded,
+        )
+        .await
+        .unwrap();
+        assert_eq!(out.response.headers()["content-type"], "text/event-stream");
+        let text = out.response.text().await.unwrap();
+        assert!(text.starts_with("event: message_start\n"));
+        assert!(text.contains("event: message_stop\n"));
+    }
+
+    #[tokio::test]
+    async fn bedrock_json_passes_through() {
+        let body = r#"{"type":"message","content":[]}"#;
+        assert_eq!(
+            run(&plan(Conversion::Bedrock), "application/json", body).await,
+            body
+        );
+    }
+
+    fn gemini_plan(expected: GeminiStreamFormat) -> ResponsePlan {
+        ResponsePlan {
+            gemini_stream: Some(expected),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn gemini_json_array_converted_to_sse() {
+        let body = "[{\"candidates\":[{\"index\":0}]}\n,\r\n{\"candidates\":[{\"index\":1}]}]";
+        let forwarded = Forwarded {
+            response: response("application/json", body),
+            served_by: String::new(),
+        };
+        let out = apply(
+            &gemini_plan(GeminiStreamFormat::Sse),
+            &ProfileSettings::default(),
+            forwarded,
+        )
+        .await
+        .unwrap();
+        assert_eq!(out.response.headers()["content-type"], "text/event-stream");
+        let text = out.response.text().await.unwrap();
+        assert_eq!(text.matches("data: ").count(), 2);
+        assert!(text.starts_with("data: "));
+    }
+
+    #[tokio::test]
+    async fn gemini_sse_converted_to_json_array() {
+        let body = "data: {\"candidates\":[]}\n\ndata: {\"candidates\":[]}\n\n";
+        let text = run(
+            &gemini_plan(GeminiStreamFormat::Json),
+            "text/event-stream",
+            body,
+        )
+        .await;
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn gemini_matching_format_passes_through() {
+        let body = "data: {\"candidates\":[]}\n\n";
+        let text = run(
+            &gemini_plan(GeminiStreamFormat::Sse),
+            "text/event-stream",
+            body,
+        )
+        .await;
+        assert_eq!(text, body);
+    }
+
+    #[test]
+    fn gemini_stage_waits_for_first_non_blank_chunk() {
+        let mut stage = GeminiStage {
+            expected: GeminiStreamFormat::Sse,
+            content_type: None,
+            pending: Vec::new(),
+            converter: None,
+        };
+        assert!(stage.feed(b"\n ").is_empty());
+        let mut out = stage.feed(b"[{\"a\":1}").to_vec();
+        out.extend_from_slice(&stage.feed(b"]"));
+        out.extend_from_slice(&stage.finish());
+        assert_eq!(String::from_utf8(out).unwrap().matches("data: ").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn safety_block_normalized_to_ok_message() {
+        let body = r#"{"promptFeedback":{"blockReason":"SAFETY"}}"#;
+        let forwarded = Forwarded {
+            response: reqwest::Response::from(
+                hyper::http::Response::builder()
+                    .status(400)
+                    .header("content-type", "application/json")
+                    .body(reqwest::Body::from(body))
+                    .unwrap(),
+            ),
+            served_by: String::new(),
+        };
+        let plan = ResponsePlan {
+            target: Some(TransformTarget::Gemini),
+            ..Default::default()
+        };
+        let out = apply(&plan, &ProfileSettings::default(), forwarded)
+            .await
+            .unwrap();
+        assert_eq!(out.response.status(), 200);
+        assert!(out.response.headers()[SAFETY_BLOCK_HEADER]
+            .to_str()
+            .unwrap()
+            .contains("reason=SAFETY"));
+        let json: serde_json::Value = out.response.json().await.unwrap();
+        assert!(json["candidates"][0]["content"].is_object());
+    }
+
+    #[tokio::test]
+    async fn safety_block_checks_converted_body() {
+        let body = r#"{"id":"chatcmpl-1","model":"gpt","choices":[{"message":{"content":"ok"},"finish_reason":"stop"}]}"#;
+        let plan = ResponsePlan {
+            target: Some(TransformTarget::Claude),
+            conversion: Conversion::OpenAiChat,
+            ..Default::default()
+        };
+        let text = run(&plan, "application/json", body).await;
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json["type"], "message");
+    }
+
+    // 开启调试时会写入审计日志（用户目录），此处只验证未开启时不改写响应体
+    #[tokio::test]
+    async fn debug_fields_kept_without_profile_debug() {
+        let body = r#"{"candidates":[{"content":{"parts":[]},"avgLogprobs":-0.1}]}"#;
+        let plan = ResponsePlan {
+            target: Some(TransformTarget::Gemini),
+            ..Default::default()
+        };
+        let forwarded = Forwarded {
+            response: response("application/json", body),
+            served_by: "primary".to_string(),
+        };
+        let out = apply(&plan, &ProfileSettings::default(), forwarded)
+            .await
+            .unwrap();
+        assert_eq!(out.response.text().await.unwrap(), body);
+    }
+
+    #[test]
+    fn pipeline_finish_flushes_through_later_stages() {
+        struct Upper;
+        impl StreamRewriter for Upper {
+            fn feed(&mut self, chunk: &[u8]) -> Bytes {
+                Bytes::from(chunk.to_ascii_uppercase())
+            }
+            fn finish(&mut self) -> Bytes {
+                Bytes::new()
+            }
+        }
+        struct Tail(Vec<u8>);
+        impl StreamRewriter for Tail {
+            fn feed(&mut self, chunk: &[u8]) -> Bytes {
+                self.0.extend_from_slice(chunk);
+                Bytes::new()
+            }
+            fn finish(&mut self) -> Bytes {
+                Bytes::from(std::mem::take(&mut self.0))
+            }
+        }
+        let mut pipeline = Pipeline(vec![Box::new(Tail(Vec::new())), Box::new(Upper)]);
+        assert!(pipeline.feed(b"ab").is_empty());
+        assert_eq!(&pipeline.finish()[..], b"AB");
+    }
+}
@@ -0,0 +1,708 @@
+// OpenAI Responses → Chat Completions 降级
+//
+// 自建端点多数只支持 /v1/chat/completions，而 AMP 的 Codex 路由发送 /v1/responses。
+// Codex Profile 的 upstream_protocol 为 openai_chat 时：
+// - input 数组（message / function_call / function_call_output）→ messages
+// - instructions → system 消息；developer 角色 → system
+// - 函数工具、tool_choice、max_output_tokens、text.format(json_schema) 对应映射
+// - 内置工具（web_search 等）、reasoning、previous_response_id 无对应能力，丢弃并记录
+// 响应侧由代理按同一配置调用 translate_response / ResponsesStreamTranslator 还原为 Responses 格式。
+
+use super::determinism;
+use super::sse::{self, SseEvent, SseParser};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use serde_json::{json, Map, Value};
+
+/// 仅 /responses 创建请求需要降级（其余子路径透传）
+pub(crate) fn applies_to(path: &str) -> bool {
+    path.trim_end_matches('/').ends_with("/responses")
+}
+
+/// Responses 请求体 → chat/completions 请求体
+pub(crate) fn translate_request(body: &[u8]) -> Result<Vec<u8>> {
+    let src: Value =
+        serde_json::from_slice(body).map_err(|e| anyhow!("Responses 请求体解析失败: {}", e))?;
+    let mut out = Map::new();
+    out.insert("model".into(), src["model"].clone());
+
+    let mut messages = Vec::new();
+    if let Some(instructions) = src["instructions"].as_str().filter(|s| !s.is_empty()) {
+        messages.push(json!({ "role": "system", "content": instructions }));
+    }
+    match &src["input"] {
+        Value::String(s) => messages.push(json!({ "role": "user", "content": s })),
+        Value::Array(items) => {
+            for item in items {
+                translate_item(item, &mut messages);
+            }
+        }
+        _ => {}
+    }
+    out.insert("messages".into(), Value::Array(messages));
+
+    for (from, to) in [
+        ("max_output_tokens", "max_tokens"),
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("user", "user"),
+        ("parallel_tool_calls", "parallel_tool_calls"),
+    ] {
+        if let Some(v) = src.get(from).filter(|v| !v.is_null()) {
+            out.insert(to.into(), v.clone());
+        }
+    }
+    if src["stream"].as_bool() == Some(true) {
+        out.insert("stream".into(), json!(true));
+        out.insert("stream_options".into(), json!({ "include_usage": true }));
+    }
+
+    let format = &src["text"]["format"];
+    match format["type"].as_str() {
+        Some("json_schema") => {
+            out.insert(
+                "response_format".into(),
+                json!({
+                    "type": "json_schema",
+                    "json_schema": {
+                        "name": format["name"],
+                        "schema": format["schema"],
+                        "strict": format["strict"].as_bool().unwrap_or(false),
+                    }
+                }),
+            );
+        }
+        Some("json_object") => {
+            out.insert("response_format".into(), json!({ "type": "json_object" }));
+        }
+        _ => {}
+    }
+
+    let mut dropped = Vec::new();
+    let tools: Vec<Value> = src["tools"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|t| {
+            if t["type"].as_str() != Some("function") {
+                dropped.push(t["type"].as_str().unwrap_or("unknown").to_string());
+                return None;
+            }
+            Some(json!({
+                "type": "function",
+                "function": {
+                    "name": t["name"],
+                    "description": t["description"].as_str().unwrap_or_default(),
+                    "parameters": t["parameters"],
+                }
+            }))
+        })
+        .collect();
+    if !tools.is_empty() {
+        out.insert("tools".into(), Value::Array(tools));
+        let choice = match &src["tool_choice"] {
+            Value::String(s) => Some(json!(s)),
+            Value::Object(o) if o.get("type").and_then(|t| t.as_str()) == Some("function") => {
+                Some(json!({ "type": "function", "function": { "name": o["name"] } }))
+            }
+            _ => None,
+        };
+        if let Some(choice) = choice {
+            out.insert("tool_choice".into(), choice);
+        }
+    }
+    for key in ["reasoning", "previous_response_id"] {
+        if src.get(key).is_some_and(|v| !v.is_null()) {
+            dropped.push(key.to_string());
+        }
+    }
+    if !dropped.is_empty() {
+        tracing::warn!(
+            "Responses 降级: chat/completions 不支持，已丢弃 {:?}",
+            dropped
+        );
+    }
+
+    Ok(serde_json::to_vec(&Value::Object(out))?)
+}
+
+fn translate_item(item: &Value, messages: &mut Vec<Value>) {
+    let item_type = item["type"]
+        .as_str()
+        .unwrap_or(if item.get("role").is_some() {
+            "message"
+        } else {
+            ""
+        });
+    match item_type {
+        "message" => {
+            let role = match item["role"].as_str() {
+                Some("developer") | Some("system") => "system",
+                Some("assistant") => "assistant",
+                _ => "user",
+            };
+            let content = match &item["content"] {
+                Value::String(s) => json!(s),
+                Value::Array(parts) => {
+                    let parts: Vec<Value> = parts
+                        .iter()
+                        .filter_map(|p| match p["type"].as_str() {
+                            Some("input_text") | Some("output_text") | Some("text") => {
+                                Some(json!({ "type": "text", "text": p["text"] }))
+                            }
+                            Some("input_image") => Some(json!({
+                                "type": "image_url",
+                                "image_url": { "url": p["image_url"] },
+                            })),
+                            _ => None,
+                        })
+                        .collect();
+                    // 非 user 角色或纯文本时使用字符串，兼容性更好
+                    if role != "user" || parts.iter().all(|p| p["type"] == "text") {
+                        json!(parts
+                            .iter()
+                            .filter_map(|p| p["text"].as_str())
+                            .collect::<Vec<_>>()
+                            .join("\n"))
+                    } else {
+                        Value::Array(parts)
+                    }
+                }
+                _ => return,
+            };
+            messages.push(json!({ "role": role, "content": content }));
+        }
+        "function_call" => {
+            let call = json!({
+                "id": item["call_id"],
+                "type": "function",
+                "function": { "name": item["name"], "arguments": item["arguments"] },
+            });
+            // 连续的 function_call 合并到同一条 assistant 消息
+            if let Some(last) = messages.last_mut().filter(|m| {
+                m["role"] == "assistant" && m.get("tool_calls").is_some_and(|t| t.is_array())
+            }) {
+                if let Some(calls) = last["tool_calls"].as_array_mut() {
+                    calls.push(call);
+                }
+                return;
+            }
+            if let Some(last) = messages
+                .last_mut()
+                .filter(|m| m["role"] == "assistant" && m.get("tool_calls").is_none())
+            {
+                last["tool_calls"] = json!([call]);
+                return;
+            }
+            messages.push(json!({ "role": "assistant", "content": null, "tool_calls": [call] }));
+        }
+        "function_call_output" => {
+            let output = match &item["output"] {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": item["call_id"],
+                "content": output,
+            }));
+        }
+        _ => {}
+    }
+}
+
+fn new_id(prefix: &str) -> String {
+    format!("{}_{}", prefix, determinism::ids().new_uuid().simple())
+}
+
+fn usage(chat_usage: &Value) -> Value {
+    let input = chat_usage["prompt_tokens"].as_u64().unwrap_or(0);
+    let output = chat_usage["completion_tokens"].as_u64().unwrap_or(0);
+    json!({ "input_tokens": input, "output_tokens": output, "total_tokens": input + output })
+}
+
+fn message_item(id: &str, text: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "message",
+        "role": "assistant",
+        "status": "completed",
+        "content": [{ "type": "output_text", "text": text, "annotations": [] }],
+    })
+}
+
+fn function_item(id: &str, call_id: &Value, name: &Value, arguments: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "function_call",
+        "status": "completed",
+        "call_id": call_id,
+        "name": name,
+        "arguments": arguments,
+    })
+}
+
+/// finish_reason 为 length 时 Responses 状态为 incomplete
+fn status_for(finish_reason: Option<&str>) -> (&'static str, Value) {
+    if finish_reason == Some("length") {
+        ("incomplete", json!({ "reason": "max_output_tokens" }))
+    } else {
+        ("completed", Value::Null)
+    }
+}
+
+/// 非流式响应 → Responses 格式（错误响应原样透传，两者错误结构一致）
+pub(crate) fn translate_response(body: &[u8]) -> Option<Vec<u8>> {
+    let src: Value = serde_json::from_slice(body).ok()?;
+    if src.get("error").is_some() {
+        return None;
+    }
+    let choice = &src["choices"][0];
+    let message = &choice["message"];
+    let mut output = Vec::new();
+    if let Some(text) = message["content"].as_str().filter(|t| !t.is_empty()) {
+        output.push(message_item(&new_id("msg"), text));
+    }
+    for call in message["tool_calls"].as_array().into_iter().flatten() {
+        output.push(function_item(
+            &new_id("fc"),
+            &call["id"],
+            &call["function"]["name"],
+            call["function"]["arguments"].as_str().unwrap_or("{}"),
+        ));
+    }
+    let (status, incomplete) = status_for(choice["finish_reason"].as_str());
+    let out = json!({
+        "id": new_id("resp"),
+        "object": "response",
+        "created_at": src["created"].as_u64().unwrap_or(determinism::clock().now_ms() / 1000),
+        "status": status,
+        "incomplete_details": incomplete,
+        "model": src["model"],
+        "output": output,
+        "usage": usage(&src["usage"]),
+    });
+    serde_json::to_vec(&out).ok()
+}
+
+enum OpenItem {
+    Text {
+        id: String,
+        text: String,
+    },
+    Tool {
+        index: u64,
+        id: String,
+        call_id: Value,
+        name: Value,
+        arguments: String,
+    },
+}
+
+/// 流式响应转换：chat.completion.chunk SSE → Responses 事件流
+#[derive(Default)]
+pub(crate) struct ResponsesStreamTranslator {
+    parser: SseParser,
+    response_id: String,
+    model: Value,
+    created_at: u64,
+    started: bool,
+    finished: bool,
+    sequence: u64,
+    open: Option<OpenItem>,
+    output: Vec<Value>,
+    finish_reason: Option<String>,
+    usage: Value,
+}
+
+impl ResponsesStreamTranslator {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        let mut out = String::new();
+        for event in self.parser.feed(chunk) {
+            self.handle_event(&event, &mut out);
+        }
+        Bytes::from(out)
+    }
+
+    /// 上游未发送 [DONE] 时补齐结束事件
+    pub(crate) fn finish(&mut self) -> Bytes {
+        let mut out = String::new();
+        if let Some(event) = self.parser.finish() {
+            self.handle_event(&event, &mut out);
+        }
+        self.finish_response(&mut out);
+        Bytes::from(out)
+    }
+
+    fn handle_event(&mut self, event: &SseEvent, out: &mut String) {
+        for data in event.data_lines() {
+            let data = data.trim();
+            if data == "[DONE]" {
+                self.finish_response(out);
+                continue;
+            }
+            if let Ok(chunk) = serde_json::from_str::<Value>(data) {
+                self.handle_chunk(&chunk, out);
+            }
+        }
+    }
+
+    fn emit(&mut self, out: &mut String, mut event: Value) {
+        event["sequence_number"] = json!(self.sequence);
+        self.sequence += 1;
+        let name = event["type"].as_str().unwrap_or("message");
+        out.push_str(&sse::format(Some(name), &event));
+    }
+
+    fn response_object(&self, status: &str) -> Value {
+        json!({
+            "id": self.response_id,
+            "object": "response",
+            "created_at": self.created_at,
+            "status": status,
+            "model": self.model,
+            "output": self.output,
+        })
+    }
+
+    fn handle_chunk(&mut self, chunk: &Value, out: &mut String) {
+        if let Some(err) = chunk.get("error") {
+            let event = json!({
+                "type": "error",
+                "code": err["code"],
+                "message": err["message"].as_str().unwrap_or_default(),
+            });
+            self.emit(out, event);
+            return;
+        }
+        if !self.started {
+            self.started = true;
+            self.response_id = new_id("resp");
+            self.model = chunk["model"].clone();
+            self.created_at = chunk["created"]
+                .as_u64()
+                .unwrap_or(determinism::clock().now_ms() / 1000);
+            let response = self.response_object("in_progress");
+            self.emit(
+                out,
+                json!({ "type": "response.created", "response": response }),
+            );
+        }
+        if chunk["usage"].is_object() {
+            self.usage = usage(&chunk["usage"]);
+        }
+
+        let choice = &chunk["choices"][0];
+        let delta = &choice["delta"];
+        if let Some(text) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+            if !matches!(self.open, Some(OpenItem::Text { .. })) {
+                self.close_item(out);
+                let id = new_id("msg");
+                let output_index = self.output.len();
+                let event = json!({
+                    "type": "response.output_item.added",
+                    "output_index": output_index,
+                    "item": {
+                        "id": id, "type": "message", "role": "assistant",
+                        "status": "in_progress", "content": [],
+                    },
+                });
+                self.emit(out, event);
+                let event = json!({
+                    "type": "response.content_part.added",
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": { "type": "output_text", "text": "", "annotations": [] },
+                });
+                self.emit(out, event);
+                self.open = Some(OpenItem::Text {
+                    id,
+                    text: String::new(),
+                });
+            }
+            if let Some(OpenItem::Text { id, text: buf }) = &mut self.open {
+                buf.push_str(text);
+                let event = json!({
+                    "type": "response.output_text.delta",
+                    "item_id": id,
+                    "output_index": self.output.len(),
+                    "content_index": 0,
+                    "delta": text,
+                });
+                self.emit(out, event);
+            }
+        }
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            let index = call["index"].as_u64().unwrap_or(0);
+            if !matches!(&self.open, Some(OpenItem::Tool { index: i, .. }) if *i == index) {
+                self.close_item(out);
+                let id = new_id("fc");
+                let event = json!({
+                    "type": "response.output_item.added",
+                    "output_index": self.output.len(),
+                    "item": {
+                        "id": id, "type": "function_call", "status": "in_progress",
+                        "call_id": call["id"], "name": call["function"]["name"], "arguments": "",
+                    },
+                });
+                self.emit(out, event);
+                self.open = Some(OpenItem::Tool {
+                    index,
+                    id,
+                    call_id: call["id"].clone(),
+                    name: call["function"]["name"].clone(),
+                    arguments: String::new(),
+                });
+            }
+            let args = call["function"]["arguments"].as_str().unwrap_or_default();
+            if args.is_empty() {
+                continue;
+            }
+            if let Some(OpenItem::Tool { id, arguments, .. }) = &mut self.open {
+                arguments.push_str(args);
+                let event = json!({
+                    "type": "response.function_call_arguments.delta",
+                    "item_id": id,
+                    "output_index": self.output.len(),
+                    "delta": args,
+                });
+                self.emit(out, event);
+            }
+        }
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+    }
+
+    fn close_item(&mut self, out: &mut String) {
+        let output_index = self.output.len();
+        let item = match self.open.take() {
+            None => return,
+            Some(OpenItem::Text { id, text }) => {
+                let event = json!({
+                    "type": "response.output_text.done",
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "text": text,
+                });
+                self.emit(out, event);
+                let event = json!({
+                    "type": "response.content_part.done",
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": { "type": "output_text", "text": text, "annotations": [] },
+                });
+                self.emit(out, event);
+                message_item(&id, &text)
+            }
+            Some(OpenItem::Tool {
+                id,
+                call_id,
+                name,
+                arguments,
+                ..
+            }) => {
+                let event = json!({
+                    "type": "response.function_call_arguments.done",
+                    "item_id": id,
+                    "output_index": output_index,
+                    "arguments": arguments,
+                });
+                self.emit(out, event);
+                function_item(&id, &call_id, &name, &arguments)
+            }
+        };
+        let event = json!({
+            "type": "response.output_item.done",
+            "output_index": output_index,
+            "item": item,
+        });
+        self.emit(out, event);
+        self.output.push(item);
+    }
+
+    fn finish_response(&mut self, out: &mut String) {
+        if self.finished || !self.started {
+            return;
+        }
+        self.finished = true;
+        self.close_item(out);
+        let (status, incomplete) = status_for(self.finish_reason.as_deref());
+        let mut response = self.response_object(status);
+        response["incomplete_details"] = incomplete;
+        response["usage"] = self.usage.clone();
+        let event_type = if status == "incomplete" {
+            "response.incomplete"
+        } else {
+            "response.completed"
+        };
+        self.emit(out, json!({ "type": event_type, "response": response }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按 size 字节切分输入逐块转换，返回全部 Responses 事件
+    fn stream_events(input: &str, size: usize) -> Vec<Value> {
+        let mut translator = ResponsesStreamTranslator::new();
+        let mut out = Vec::new();
+        for chunk in input.as_bytes().chunks(size) {
+            out.extend_from_slice(&translator.feed(chunk));
+        }
+        out.extend_from_slice(&translator.finish());
+        let mut parser = SseParser::new();
+        let mut events = parser.feed(&out);
+        events.extend(parser.finish());
+        events.iter().filter_map(SseEvent::json).collect()
+    }
+
+    #[test]
+    fn only_create_path_applies() {
+        assert!(applies_to("/v1/responses"));
+        assert!(applies_to("/v1/responses/"));
+        assert!(!applies_to("/v1/responses/resp_1/cancel"));
+    }
+
+    #[test]
+    fn request_maps_items_and_drops_builtin_tools() {
+        let body = json!({
+            "model": "gpt-5",
+            "instructions": "be brief",
+            "max_output_tokens": 100,
+            "stream": true,
+            "reasoning": { "effort": "high" },
+            "text": { "format": { "type": "json_schema", "name": "out", "schema": { "type": "object" }, "strict": true } },
+            "tools": [
+                { "type": "function", "name": "shell", "parameters": { "type": "object" } },
+                { "type": "web_search" }
+            ],
+            "tool_choice": { "type": "function", "name": "shell" },
+            "input": [
+                { "role": "developer", "content": [{ "type": "input_text", "text": "rules" }] },
+                { "type": "message", "role": "user", "content": [{ "type": "input_text", "text": "ls" }] },
+                { "type": "function_call", "call_id": "c1", "name": "shell", "arguments": "{\"cmd\":\"ls\"}" },
+                { "type": "function_call", "call_id": "c2", "name": "shell", "arguments": "{}" },
+                { "type": "function_call_output", "call_id": "c1", "output": "a.txt" }
+            ]
+        });
+        let out: Value = serde_json::from_slice(
+            &translate_request(&serde_json::to_vec(&body).unwrap()).unwrap(),
+        )
+        .unwrap();
+        let messages = out["messages"].as_array().unwrap();
+        assert_eq!(
+            messages[0],
+            json!({ "role": "system", "content": "be brief" })
+        );
+        assert_eq!(messages[1], json!({ "role": "system", "content": "rules" }));
+        assert_eq!(messages[2], json!({ "role": "user", "content": "ls" }));
+        // 连续 function_call 合并为一条 assistant 消息
+        assert_eq!(messages[3]["tool_calls"].as_array().unwrap().len(), 2);
+        assert_eq!(messages[3]["tool_calls"][0]["id"], "c1");
+        assert_eq!(
+            messages[4],
+            json!({ "role": "tool", "tool_call_id": "c1", "content": "a.txt" })
+        );
+        assert_eq!(out["max_tokens"], 100);
+        assert_eq!(out["tools"].as_array().unwrap().len(), 1);
+        assert_eq!(out["tool_choice"]["function"]["name"], "shell");
+        assert_eq!(out["response_format"]["json_schema"]["strict"], true);
+        assert!(out.get("reasoning").is_none());
+    }
+
+    #[test]
+    fn non_streaming_response_becomes_output_items() {
+        let body = json!({
+            "model": "gpt-5",
+            "created": 1700000000,
+            "choices": [{
+                "message": {
+                    "content": "done",
+                    "tool_calls": [{ "id": "c1", "function": { "name": "shell", "arguments": "{\"cmd\":\"ls\"}" } }]
+                },
+                "finish_reason": "length"
+            }],
+            "usage": { "prompt_tokens": 3, "completion_tokens": 4 }
+        });
+        let out: Value = serde_json::from_slice(
+            &translate_response(&serde_json::to_vec(&body).unwrap()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(out["object"], "response");
+        assert_eq!(out["created_at"], 1700000000);
+        assert_eq!(out["status"], "incomplete");
+        assert_eq!(out["incomplete_details"]["reason"], "max_output_tokens");
+        assert_eq!(out["output"][0]["content"][0]["text"], "done");
+        assert_eq!(out["output"][1]["type"], "function_call");
+        assert_eq!(out["output"][1]["call_id"], "c1");
+        assert_eq!(out["usage"]["total_tokens"], 7);
+    }
+
+    #[test]
+    fn error_body_passes_through() {
+        assert!(translate_response(br#"{"error":{"message":"bad key"}}"#).is_none());
+
+        let events = stream_events(
+            "data: {\"error\":{\"code\":\"rate_limit\",\"message\":\"slow\"}}\n\n",
+            3,
+        );
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["type"], "error");
+        assert_eq!(events[0]["code"], "rate_limit");
+        assert_eq!(events[0]["message"], "slow");
+    }
+
+    #[test]
+    fn split_stream_with_tool_call_deltas() {
+        let input = concat!(
+            "data: {\"model\":\"gpt-5\",\"created\":1,\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"c1\",\"function\":{\"name\":\"shell\",\"arguments\":\"{\\\"cmd\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\":\\\"ls\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}],\"usage\":{\"prompt_tokens\":2,\"completion_tokens\":5}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        for size in [1, 9, input.len()] {
+            let events = stream_events(input, size);
+            let types: Vec<&str> = events.iter().filter_map(|e| e["type"].as_str()).collect();
+            assert_eq!(
+                types,
+                [
+                    "response.created",
+                    "response.output_item.added",
+                    "response.content_part.added",
+                    "response.output_text.delta",
+                    "response.output_text.done",
+                    "response.content_part.done",
+                    "response.output_item.done",
+                    "response.output_item.added",
+                    "response.function_call_arguments.delta",
+                    "response.function_call_arguments.delta",
+                    "response.function_call_arguments.done",
+                    "response.output_item.done",
+                    "response.completed",
+                ],
+                "size={}",
+                size
+            );
+            // sequence_number 连续递增
+            for (i, event) in events.iter().enumerate() {
+                assert_eq!(event["sequence_number"], i as u64);
+            }
+            assert_eq!(events[10]["arguments"], "{\"cmd\":\"ls\"}");
+            assert_eq!(events[11]["output_index"], 1);
+            let completed = &events[12]["response"];
+            assert_eq!(completed["status"], "completed");
+            assert_eq!(completed["output"].as_array().unwrap().len(), 2);
+            assert_eq!(completed["output"][1]["call_id"], "c1");
+            assert_eq!(completed["usage"]["total_tokens"], 7);
+        }
+    }
+}
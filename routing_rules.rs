@@ -0,0 +1,366 @@
+// 可配置路由规则
+//
+// 在内置路径识别（detect_api_type）之前按顺序匹配规则，首条命中生效：
+// - 条件：路径前缀 / 请求头（可带值正则）/ 模型名正则，均为可选，全部满足才算命中
+// - 动作：改走指定路由（claude / codex / gemini / azure / amp），
+//   和/或改用 ProxyConfigManager 中另一工具配置的上游地址与 Key
+// 例：/api/provider/anthropic/* 中 model 匹配 ^claude-3-haiku 的请求转到 "claude-haiku" 配置。
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub(crate) const ROUTE_NAMES: [&str; 5] = ["claude", "codex", "gemini", "azure", "amp"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingRule {
+    /// 规则名，用于日志
+    pub name: String,
+    pub enabled: bool,
+    /// 路径前缀（不区分大小写）
+    pub path_prefix: Option<String>,
+    /// 需存在的请求头名
+    pub header: Option<String>,
+    /// 请求头值正则（需同时设置 header）
+    pub header_value: Option<String>,
+    /// 模型名正则（请求体 model 或 Gemini 路径中的模型）
+    pub model: Option<String>,
+    /// 目标路由，None 时沿用内置识别结果
+    pub route: Option<String>,
+    /// ProxyConfigManager 工具 ID，使用其 real_base_url / real_api_key 作为上游
+    pub profile: Option<String>,
+}
+
+impl Default for RoutingRule {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            enabled: true,
+            path_prefix: None,
+            header: None,
+            header_value: None,
+            model: None,
+            route: None,
+            profile: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingSettings {
+    pub rules: Vec<RoutingRule>,
+}
+
+/// 命中结果
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RouteDecision {
+    pub rule: String,
+    pub route: Option<String>,
+    pub profile: Option<String>,
+}
+
+/// 正则编译缓存（编译失败记为 None，规则视为不命中）
+static REGEX_CACHE: Lazy<Mutex<HashMap<String, Option<Regex>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn regex_matches(pattern: &str, text: &str) -> bool {
+    let mut cache = REGEX_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let re = cache.entry(pattern.to_string()).or_insert_with(|| {
+        Regex::new(pattern)
+            .map_err(|e| tracing::warn!("路由规则正则非法 {}: {}", pattern, e))
+            .ok()
+    });
+    re.as_ref().is_some_and(|re| re.is_match(text))
+}
+
+/// 请求中的模型名：Gemini 路径 /models/{model}:xxx 优先，其次请求体 model
+pub(crate) fn request_model(path: &str, body: &[u8]) -> Option<String> {
+    if let Some(start) = path.find("/models/") {
+        let after = &path[start + 8..];
+        let end = after.find([':', '/', '?']).unwrap_or(after.len());
+        return Some(after[..end].to_string());
+    }
+    serde_json::from_slice::<Value>(body)
+        .ok()?
+        .get("model")?
+        .as_str()
+        .map(|s| s.to_string())
+}
+
+impl RoutingRule {
+    fn matches(&self, path: &str, headers: &hyper::HeaderMap, model: Option<&str>) -> bool {
+        if let Some(prefix) = &self.path_prefix {
+            if !path.to_lowercase().starts_with(&prefix.to_lowercase()) {
+                return false;
+            }
+        }
+        if let Some(name) = &self.header {
+            let Some(value) = headers.get(name.as_str()) else {
+                return false;
+            };
+            if let Some(pattern) = &self.header_value {
+                let Ok(value) = value.to_str() else {
+                    return false;
+                };
+                if !regex_matches(pattern, value) {
+                    return false;
+                }
+            }
+        }
+        if let Some(pattern) = &self.model {
+            if !model.is_some_and(|m| regex_matches(pattern, m)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// 按顺序匹配，返回首条命中规则的决定
+pub(crate) fn evaluate(
+    settings: &RoutingSettings,
+    path: &str,
+    headers: &hyper::HeaderMap,
+    body: &[u8],
+) -> Option<RouteDecision> {
+    if settings.rules.iter().all(|r| !r.enabled) {
+        return None;
+    }
+    let model = request_model(path, body);
+    let rule = settings
+        .rules
+        .iter()
+        .filter(|r| r.enabled)
+        .find(|r| r.matches(path, headers, model.as_deref()))?;
+    tracing::debug!(
+        "路由规则命中: {} → route={:?}, profile={:?}",
+        rule.name,
+        rule.route,
+        rule.profile
+    );
+    Some(RouteDecision {
+        rule: rule.name.clone(),
+        route: rule.route.clone(),
+        profile: rule.profile.clone(),
+    })
+}
+
+/// 按模型名查找 Profile 映射：键为模型名，或以 * 结尾的前缀（最长前缀优先）
+/// 返回 (命中的键, ProxyConfigManager 工具 ID)
+pub(crate) fn model_profile<'a>(
+    map: &'a HashMap<String, String>,
+    model: &str,
+) -> Option<(&'a str, &'a str)> {
+    if let Some((k, v)) = map.get_key_value(model) {
+        return Some((k.as_str(), v.as_str()));
+    }
+    map.iter()
+        .filter_map(|(k, v)| {
+            let prefix = k.strip_suffix('*')?;
+            model.starts_with(prefix).then_some((prefix.len(), k, v))
+        })
+        .max_by_key(|(len, _, _)| *len)
+        .map(|(_, k, v)| (k.as_str(), v.as_str()))
+}
+
+/// 配置校验：返回错误列表（正则非法、路由名未知、规则无动作）
+pub(crate) fn validate(settings: &RoutingSettings) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (i, rule) in settings.rules.iter().enumerate() {
+        let label = if rule.name.is_empty() {
+            format!("routing.rules[{}]", i)
+        } else {
+            format!("routing.rules[{}]（{}）", i, rule.name)
+        };
+        for pattern in [&rule.header_value, &rule.model].into_iter().flatten() {
+            if let Err(e) = Regex::new(pattern) {
+                errors.push(format!("{} 正则非法 {}: {}", label, pattern, e));
+            }
+        }
+        if rule.header_value.is_some() && rule.header.is_none() {
+            errors.push(format!("{} 设置了 header_value 但缺少 header", label));
+        }
+        if let Some(route) = &rule.route {
+            if !ROUTE_NAMES.contains(&route.as_str()) {
+                errors.push(format!("{} 未知路由: {}", label, route));
+            }
+        }
+        if rule.route.is_none() && rule.profile.is_none() {
+            errors.push(format!("{} 未指定 route 或 profile", label));
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str) -> RoutingRule {
+        RoutingRule {
+            name: name.into(),
+            route: Some("claude".into()),
+            ..Default::default()
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> hyper::HeaderMap {
+        let mut map = hyper::HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let settings = RoutingSettings {
+            rules: vec![
+                RoutingRule {
+                    path_prefix: Some("/API/Provider/Anthropic".into()),
+                    model: Some("^claude-3-haiku".into()),
+                    profile: Some("claude-haiku".into()),
+                    route: None,
+                    ..rule("haiku")
+                },
+                RoutingRule {
+                    path_prefix: Some("/api/provider/anthropic".into()),
+                    ..rule("anthropic")
+                },
+            ],
+        };
+        let path = "/api/provider/anthropic/v1/messages";
+        let hit = evaluate(
+            &settings,
+            path,
+            &headers(&[]),
+            br#"{"model":"claude-3-haiku-20240307"}"#,
+        )
+        .unwrap();
+        assert_eq!(hit.rule, "haiku");
+        assert_eq!(hit.profile.as_deref(), Some("claude-haiku"));
+        assert!(hit.route.is_none());
+
+        let hit = evaluate(
+            &settings,
+            path,
+            &headers(&[]),
+            br#"{"model":"claude-opus"}"#,
+        )
+        .unwrap();
+        assert_eq!(hit.rule, "anthropic");
+        assert!(evaluate(&settings, "/api/provider/openai/v1", &headers(&[]), b"{}").is_none());
+    }
+
+    #[test]
+    fn header_and_value_conditions() {
+        let settings = RoutingSettings {
+            rules: vec![RoutingRule {
+                header: Some("x-team".into()),
+                header_value: Some("^(infra|ops)$".into()),
+                ..rule("team")
+            }],
+        };
+        let hit = |h: &hyper::HeaderMap| evaluate(&settings, "/v1", h, b"{}").is_some();
+        assert!(hit(&headers(&[("x-team", "ops")])));
+        assert!(!hit(&headers(&[("x-team", "web")])));
+        assert!(!hit(&headers(&[])));
+    }
+
+    #[test]
+    fn disabled_and_invalid_rules_never_match() {
+        let settings = RoutingSettings {
+            rules: vec![
+                RoutingRule {
+                    enabled: false,
+                    ..rule("off")
+                },
+                RoutingRule {
+                    model: Some("(".into()),
+                    ..rule("broken")
+                },
+            ],
+        };
+        assert!(evaluate(&settings, "/v1", &headers(&[]), br#"{"model":"("}"#).is_none());
+        // 缺少模型名时模型条件不成立
+        let settings = RoutingSettings {
+            rules: vec![RoutingRule {
+                model: Some(".*".into()),
+                ..rule("any-model")
+            }],
+        };
+        assert!(evaluate(&settings, "/v1", &headers(&[]), b"not json").is_none());
+    }
+
+    #[test]
+    fn model_from_gemini_path_or_body() {
+        assert_eq!(
+            request_model(
+                "/v1beta/models/gemini-2.5-pro:streamGenerateContent?alt=sse",
+                b"{}"
+            )
+            .as_deref(),
+            Some("gemini-2.5-pro")
+        );
+        assert_eq!(
+            request_model("/v1/messages", br#"{"model":"claude-sonnet"}"#).as_deref(),
+            Some("claude-sonnet")
+        );
+        assert!(request_model("/v1/messages", b"{}").is_none());
+    }
+
+    #[test]
+    fn model_profile_prefers_exact_then_longest_prefix() {
+        let map = HashMap::from([
+            ("gpt-*".to_string(), "openai".to_string()),
+            ("gpt-4o*".to_string(), "openai-4o".to_string()),
+            ("gpt-4o-mini".to_string(), "mini".to_string()),
+        ]);
+        assert_eq!(
+            model_profile(&map, "gpt-4o-mini"),
+            Some(("gpt-4o-mini", "mini"))
+        );
+        assert_eq!(
+            model_profile(&map, "gpt-4o-2024"),
+            Some(("gpt-4o*", "openai-4o"))
+        );
+        assert_eq!(model_profile(&map, "gpt-3.5"), Some(("gpt-*", "openai")));
+        assert!(model_profile(&map, "claude").is_none());
+    }
+
+    #[test]
+    fn validate_reports_each_problem() {
+        let settings = RoutingSettings {
+            rules: vec![
+                RoutingRule {
+                    model: Some("[".into()),
+                    ..rule("bad-regex")
+                },
+                RoutingRule {
+                    header_value: Some("x".into()),
+                    ..rule("no-header")
+                },
+                RoutingRule {
+                    route: Some("nowhere".into()),
+                    ..rule("")
+                },
+                RoutingRule {
+                    route: None,
+                    ..rule("no-action")
+                },
+                rule("ok"),
+            ],
+        };
+        let errors = validate(&settings);
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors[0].contains("bad-regex") && errors[0].contains("正则非法"));
+        assert!(errors[1].contains("缺少 header"));
+        assert!(errors[2].starts_with("routing.rules[2] 未知路由"));
+        assert!(errors[3].contains("未指定 route 或 profile"));
+    }
+}
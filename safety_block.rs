@@ -0,0 +1,216 @@
+// 内容安全拦截统一处理
+//
+// 各家对安全拦截的表达方式不同：
+// - Gemini：promptFeedback.blockReason / candidates[].finishReason=SAFETY 等
+// - Anthropic：stop_reason=refusal
+// - OpenAI：finish_reason=content_filter / incomplete_details.reason=content_filter /
+//   error.code=content_policy_violation
+// 统一改写为对应协议下的一条普通 assistant 消息，并附加 x-dc-safety-block 响应头，
+// 避免 AMP 直接展示各家原始错误。仅处理非流式 JSON 响应体。
+
+use super::transform_middleware::TransformTarget;
+use serde_json::{json, Value};
+
+/// 标记响应头：`provider=gemini; reason=SAFETY`
+pub(crate) const SAFETY_BLOCK_HEADER: &str = "x-dc-safety-block";
+
+const GEMINI_BLOCK_REASONS: [&str; 6] = [
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+    "IMAGE_SAFETY",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SafetyBlock {
+    pub provider: &'static str,
+    pub reason: String,
+    /// 触发拦截的类别（Gemini safetyRatings），其他协议为空
+    pub categories: Vec<String>,
+}
+
+impl SafetyBlock {
+    pub(crate) fn header_value(&self) -> String {
+        format!("provider={}; reason={}", self.provider, self.reason)
+    }
+
+    fn message(&self) -> String {
+        let mut msg = format!(
+            "[内容安全拦截] 上游服务（{}）拒绝生成此回复，原因: {}。",
+            self.provider, self.reason
+        );
+        if !self.categories.is_empty() {
+            msg.push_str(&format!("涉及类别: {}。", self.categories.join(", ")));
+        }
+        msg.push_str("请调整请求内容后重试，或切换到其他 Profile。");
+        msg
+    }
+}
+
+/// 识别响应体中的安全拦截
+pub(crate) fn detect(target: TransformTarget, body: &Value) -> Option<SafetyBlock> {
+    match target {
+        TransformTarget::Gemini => detect_gemini(body),
+        TransformTarget::Claude => detect_claude(body),
+        TransformTarget::Codex => detect_openai(body),
+    }
+}
+
+fn detect_gemini(body: &Value) -> Option<SafetyBlock> {
+    if let Some(reason) = body["promptFeedback"]["blockReason"].as_str() {
+        return Some(SafetyBlock {
+            provider: "gemini",
+            reason: reason.to_string(),
+            categories: gemini_blocked_categories(&body["promptFeedback"]["safetyRatings"]),
+        });
+    }
+
+    let candidates = body["candidates"].as_array()?;
+    // 任一候选正常结束则不视为拦截
+    if candidates
+        .iter()
+        .any(|c| c["finishReason"].as_str() == Some("STOP"))
+    {
+        return None;
+    }
+    candidates.iter().find_map(|c| {
+        let reason = c["finishReason"].as_str()?;
+        if !GEMINI_BLOCK_REASONS.contains(&reason) {
+            return None;
+        }
+        Some(SafetyBlock {
+            provider: "gemini",
+            reason: reason.to_string(),
+            categories: gemini_blocked_categories(&c["safetyRatings"]),
+        })
+    })
+}
+
+fn gemini_blocked_categories(ratings: &Value) -> Vec<String> {
+    ratings
+        .as_array()
+        .map(|arr| {
+            arr.iter()
+                .filter(|r| {
+                    r["blocked"].as_bool() == Some(true)
+                        || r["probability"].as_str() == Some("HIGH")
+                })
+                .filter_map(|r| r["category"].as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn detect_claude(body: &Value) -> Option<SafetyBlock> {
+    if body["stop_reason"].as_str() == Some("refusal") {
+        return Some(SafetyBlock {
+            provider: "anthropic",
+            reason: "refusal".to_string(),
+            categories: Vec::new(),
+        });
+    }
+    None
+}
+
+fn detect_openai(body: &Value) -> Option<SafetyBlock> {
+    let error_code = body["error"]["code"].as_str();
+    let is_filtered = body["choices"]
+        .as_array()
+        .map(|arr| {
+            arr.iter()
+                .any(|c| c["finish_reason"].as_str() == Some("content_filter"))
+        })
+        .unwrap_or(false)
+        || body["incomplete_details"]["reason"].as_str() == Some("content_filter")
+        || matches!(
+            error_code,
+            Some("content_filter") | Some("content_policy_violation")
+        );
+
+    if !is_filtered {
+        return None;
+    }
+    Some(SafetyBlock {
+        provider: "openai",
+        reason: error_code.unwrap_or("content_filter").to_string(),
+        categories: Vec::new(),
+    })
+}
+
+/// 生成对应协议的替代响应体（保留原响应的 id / model 等标识字段）
+pub(crate) fn build_replacement(
+    target: TransformTarget,
+    block: &SafetyBlock,
+    original: &Value,
+) -> Value {
+    let text = block.message();
+    match target {
+        TransformTarget::Claude => json!({
+            "id": original["id"],
+            "type": "message",
+            "role": "assistant",
+            "model": original["model"],
+            "content": [{ "type": "text", "text": text }],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": original["usage"]
+        }),
+        TransformTarget::Gemini => json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [{ "text": text }] },
+                "finishReason": "STOP",
+                "index": 0
+            }],
+            "usageMetadata": original["usageMetadata"],
+            "modelVersion": original["modelVersion"]
+        }),
+        TransformTarget::Codex => {
+            if original.get("choices").is_some() {
+                json!({
+                    "id": original["id"],
+                    "object": "chat.completion",
+                    "model": original["model"],
+                    "choices": [{
+                        "index": 0,
+                        "message": { "role": "assistant", "content": text },
+                        "finish_reason": "stop"
+                    }],
+                    "usage": original["usage"]
+                })
+            } else {
+                json!({
+                    "id": original["id"],
+                    "object": "response",
+                    "model": original["model"],
+                    "status": "completed",
+                    "output": [{
+                        "type": "message",
+                        "role": "assistant",
+                        "content": [{ "type": "output_text", "text": text, "annotations": [] }]
+                    }],
+                    "usage": original["usage"]
+                })
+            }
+        }
+    }
+}
+
+/// 响应侧入口：检测到拦截时返回 (替代响应体, 标记头值)，调用方需同时将状态码改为 200
+pub(crate) fn normalize_response(
+    target: TransformTarget,
+    body: &[u8],
+) -> Option<(Vec<u8>, String)> {
+    let json: Value = serde_json::from_slice(body).ok()?;
+    let block = detect(target, &json)?;
+    tracing::warn!(
+        "内容安全拦截: provider={}, reason={}, categories={:?}",
+        block.provider,
+        block.reason,
+        block.categories
+    );
+    let replacement = build_replacement(target, &block, &json);
+    let bytes = serde_json::to_vec(&replacement).ok()?;
+    Some((bytes, block.header_value()))
+}
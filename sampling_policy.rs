@@ -0,0 +1,165 @@
+// 采样参数策略
+//
+// 按 Profile 对 temperature / top_p / top_k / 停止序列做覆盖、钳制或删除
+// （如部分企业网关拒绝 top_k，部分要求 temperature ≤ 1.0），并支持按模型例外：
+// 模型名包含 model_overrides 中某个键时（取最长匹配）改用该键的规则。
+// 字段名按协议映射：Claude 顶层 / OpenAI 顶层（stop）/ Gemini generationConfig。
+
+use super::transform_middleware::TransformTarget;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// 数值参数规则：先 remove，再 set（覆盖），最后按 min / max 钳制已有值
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParamRule {
+    pub remove: bool,
+    pub set: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StopRule {
+    pub remove: bool,
+    /// 覆盖为固定的停止序列
+    pub set: Option<Vec<String>>,
+    /// 最多保留的停止序列数（超出截断）
+    pub max_count: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplingRules {
+    pub temperature: ParamRule,
+    pub top_p: ParamRule,
+    pub top_k: ParamRule,
+    pub stop: StopRule,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplingPolicy {
+    #[serde(flatten)]
+    pub rules: SamplingRules,
+    /// 模型名子串 → 例外规则（整体替换默认规则）
+    pub model_overrides: HashMap<String, SamplingRules>,
+}
+
+impl SamplingPolicy {
+    fn rules_for(&self, model: Option<&str>) -> &SamplingRules {
+        model
+            .and_then(|m| {
+                self.model_overrides
+                    .iter()
+                    .filter(|(key, _)| m.contains(key.as_str()))
+                    .max_by_key(|(key, _)| key.len())
+                    .map(|(_, rules)| rules)
+            })
+            .unwrap_or(&self.rules)
+    }
+}
+
+/// 应用策略，返回 None 表示未改写；model 为空时从请求体读取（Gemini 由调用方从路径传入）
+pub(crate) fn apply(
+    policy: &SamplingPolicy,
+    target: TransformTarget,
+    model: Option<&str>,
+    body: &[u8],
+) -> Option<Vec<u8>> {
+    if body.is_empty() {
+        return None;
+    }
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    let model = model
+        .map(|m| m.to_string())
+        .or_else(|| json["model"].as_str().map(|m| m.to_string()));
+    let rules = policy.rules_for(model.as_deref());
+    if *rules == SamplingRules::default() {
+        return None;
+    }
+
+    let (params, names) = match target {
+        TransformTarget::Claude => (
+            json.as_object_mut()?,
+            ["temperature", "top_p", "top_k", "stop_sequences"],
+        ),
+        // OpenAI 无 top_k，规则仍生效于 remove（部分兼容网关会透传）
+        TransformTarget::Codex => (
+            json.as_object_mut()?,
+            ["temperature", "top_p", "top_k", "stop"],
+        ),
+        TransformTarget::Gemini => (
+            json.as_object_mut()?
+                .entry("generationConfig")
+                .or_insert_with(|| json!({}))
+                .as_object_mut()?,
+            ["temperature", "topP", "topK", "stopSequences"],
+        ),
+    };
+
+    let mut modified = false;
+    for (rule, name) in [&rules.temperature, &rules.top_p, &rules.top_k]
+        .into_iter()
+        .zip(&names[..3])
+    {
+        modified |= apply_param(params, name, rule);
+    }
+    modified |= apply_stop(params, names[3], &rules.stop);
+
+    if !modified {
+        return None;
+    }
+    if let Some(m) = &model {
+        tracing::debug!("采样参数策略已应用: model={}", m);
+    }
+    serde_json::to_vec(&json).ok()
+}
+
+fn apply_param(params: &mut Map<String, Value>, name: &str, rule: &ParamRule) -> bool {
+    if rule.remove {
+        return params.remove(name).is_some();
+    }
+    if let Some(v) = rule.set {
+        params.insert(name.to_string(), json!(v));
+        return true;
+    }
+    let Some(current) = params.get(name).and_then(|v| v.as_f64()) else {
+        return false;
+    };
+    let mut clamped = current;
+    if let Some(min) = rule.min {
+        clamped = clamped.max(min);
+    }
+    if let Some(max) = rule.max {
+        clamped = clamped.min(max);
+    }
+    if clamped == current {
+        return false;
+    }
+    tracing::debug!("采样参数 {} 钳制: {} → {}", name, current, clamped);
+    params.insert(name.to_string(), json!(clamped));
+    true
+}
+
+fn apply_stop(params: &mut Map<String, Value>, name: &str, rule: &StopRule) -> bool {
+    if rule.remove {
+        return params.remove(name).is_some();
+    }
+    if let Some(set) = &rule.set {
+        params.insert(name.to_string(), json!(set));
+        return true;
+    }
+    let Some(max) = rule.max_count else {
+        return false;
+    };
+    match params.get_mut(name) {
+        Some(Value::Array(items)) if items.len() > max => {
+            items.truncate(max);
+            true
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,314 @@
+// 请求结构漂移检测
+//
+// AMP 升级后可能发送代理未处理的新字段或新端点，导致静默误路由。此处：
+// - 抽样比对请求体顶层字段与消息内容块类型，与内置的已知字段快照对照
+// - 检查 /api/provider/* 下无法识别的提供方或端点
+// 新发现的条目告警并写入审计日志（kind=schema_drift），同时记录到
+// ~/.duckcoding/amp/schema_drift.json，每项只提醒一次（重启后不重复）。
+
+use super::audit_log::{self, AuditRecord};
+use super::storage;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+const CLAUDE_FIELDS: &[&str] = &[
+    "model",
+    "messages",
+    "system",
+    "max_tokens",
+    "metadata",
+    "stop_sequences",
+    "stream",
+    "temperature",
+    "top_p",
+    "top_k",
+    "tools",
+    "tool_choice",
+    "thinking",
+    "service_tier",
+    "container",
+    "mcp_servers",
+    "context_management",
+];
+
+const CLAUDE_BLOCK_TYPES: &[&str] = &[
+    "text",
+    "image",
+    "document",
+    "tool_use",
+    "tool_result",
+    "thinking",
+    "redacted_thinking",
+    "server_tool_use",
+    "web_search_tool_result",
+    "search_result",
+];
+
+const OPENAI_FIELDS: &[&str] = &[
+    // Responses
+    "model",
+    "input",
+    "instructions",
+    "tools",
+    "tool_choice",
+    "stream",
+    "store",
+    "reasoning",
+    "text",
+    "include",
+    "parallel_tool_calls",
+    "prompt_cache_key",
+    "previous_response_id",
+    "max_output_tokens",
+    "temperature",
+    "top_p",
+    "top_logprobs",
+    "metadata",
+    "truncation",
+    "user",
+    "service_tier",
+    "safety_identifier",
+    "background",
+    "prompt",
+    // Chat Completions
+    "messages",
+    "max_tokens",
+    "max_completion_tokens",
+    "n",
+    "stop",
+    "stream_options",
+    "response_format",
+    "seed",
+    "logprobs",
+    "frequency_penalty",
+    "presence_penalty",
+    "logit_bias",
+    "functions",
+    "function_call",
+    "modalities",
+    "audio",
+    "prediction",
+    "reasoning_effort",
+    "web_search_options",
+    "verbosity",
+];
+
+const GEMINI_FIELDS: &[&str] = &[
+    "model",
+    "contents",
+    "systemInstruction",
+    "tools",
+    "toolConfig",
+    "generationConfig",
+    "safetySettings",
+    "cachedContent",
+    "labels",
+];
+
+/// 代理可处理的 /api/provider/{provider} 及其端点特征
+const PROVIDER_ENDPOINTS: &[(&str, &[&str])] = &[
+    ("anthropic", &["/v1/messages"]),
+    ("openai", &["/v1/responses", "/v1/chat/completions"]),
+    (
+        "google",
+        &["/v1beta/models/", "/v1beta1/publishers/google/models/"],
+    ),
+    (
+        "azure",
+        &["/openai/", "/v1/chat/completions", "/v1/responses"],
+    ),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchemaDriftSettings {
+    pub enabled: bool,
+    /// 每 N 个请求抽样检查一次请求体
+    pub sample_every: u64,
+}
+
+impl Default for SchemaDriftSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_every: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriftFinding {
+    /// field / block_type / endpoint
+    pub kind: String,
+    pub route: String,
+    pub name: String,
+    pub first_seen_ms: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DriftFile {
+    /// "{kind}:{route}:{name}" → 发现记录
+    findings: BTreeMap<String, DriftFinding>,
+}
+
+static SAMPLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+static FINDINGS: Lazy<Mutex<Option<DriftFile>>> = Lazy::new(|| Mutex::new(None));
+
+const NAMESPACE: &str = "amp";
+const KEY: &str = "schema_drift.json";
+
+fn load() -> DriftFile {
+    storage::get_json(NAMESPACE, KEY)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+fn save(file: &DriftFile) -> Result<()> {
+    storage::put_json(NAMESPACE, KEY, file).map_err(|e| anyhow!("写入漂移记录失败: {}", e))
+}
+
+/// 检查一次请求（路由判定前调用）；route 为 claude / codex / gemini / amp
+pub(crate) fn observe(settings: &SchemaDriftSettings, route: &str, path: &str, body: &[u8]) {
+    if !settings.enabled {
+        return;
+    }
+
+    let mut found = Vec::new();
+    if let Some(name) = unknown_provider_endpoint(path) {
+        found.push(("endpoint", name));
+    }
+
+    let sample_every = settings.sample_every.max(1);
+    let sampled = SAMPLE_COUNTER
+        .fetch_add(1, Ordering::Relaxed)
+        .is_multiple_of(sample_every);
+    if sampled && !body.is_empty() {
+        if let Ok(json) = serde_json::from_slice::<Value>(body) {
+            found.extend(unknown_body_items(route, &json));
+        }
+    }
+
+    if !found.is_empty() {
+        report(route, found);
+    }
+}
+
+/// /api/provider/* 下代理无法识别的提供方或端点（查询串与模型名已去除）
+fn unknown_provider_endpoint(path: &str) -> Option<String> {
+    let lower = path.to_lowercase();
+    let rest = lower.strip_prefix("/api/provider/")?;
+    let (provider, suffix) = rest.split_once('/').unwrap_or((rest, ""));
+    let suffix = format!("/{}", suffix);
+
+    match PROVIDER_ENDPOINTS.iter().find(|(p, _)| *p == provider) {
+        None => Some(format!("/api/provider/{}", provider)),
+        Some((_, endpoints)) => {
+            if endpoints.iter().any(|e| suffix.contains(e)) {
+                None
+            } else {
+                Some(format!("/api/provider/{}{}", provider, suffix))
+            }
+        }
+    }
+}
+
+fn unknown_body_items(route: &str, json: &Value) -> Vec<(&'static str, String)> {
+    let known_fields = match route {
+        "claude" => CLAUDE_FIELDS,
+        "codex" => OPENAI_FIELDS,
+        "gemini" => GEMINI_FIELDS,
+        _ => return Vec::new(),
+    };
+    let Some(obj) = json.as_object() else {
+        return Vec::new();
+    };
+
+    let mut items: Vec<(&'static str, String)> = obj
+        .keys()
+        .filter(|k| !known_fields.contains(&k.as_str()))
+        .map(|k| ("field", k.clone()))
+        .collect();
+
+    if route == "claude" {
+        let block_types = obj
+            .get("messages")
+            .and_then(|m| m.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|m| m["content"].as_array())
+            .flatten()
+            .filter_map(|b| b["type"].as_str());
+        for ty in block_types {
+            if !CLAUDE_BLOCK_TYPES.contains(&ty) && !items.iter().any(|(_, n)| n == ty) {
+                items.push(("block_type", ty.to_string()));
+            }
+        }
+    }
+    items
+}
+
+fn report(route: &str, found: Vec<(&'static str, String)>) {
+    let mut guard = FINDINGS.lock().unwrap_or_else(|e| e.into_inner());
+    let file = guard.get_or_insert_with(load);
+
+    let mut new_findings = Vec::new();
+    for (kind, name) in found {
+        let key = format!("{}:{}:{}", kind, route, name);
+        if file.findings.contains_key(&key) {
+            continue;
+        }
+        let finding = DriftFinding {
+            kind: kind.to_string(),
+            route: route.to_string(),
+            name,
+            first_seen_ms: audit_log::now_ms(),
+        };
+        file.findings.insert(key, finding.clone());
+        new_findings.push(finding);
+    }
+    if new_findings.is_empty() {
+        return;
+    }
+    if let Err(e) = save(file) {
+        tracing::warn!("{}", e);
+    }
+    drop(guard);
+
+    for finding in new_findings {
+        tracing::warn!(
+            "检测到 AMP 请求结构变化（{}）: route={}, {}，代理可能未处理该项",
+            finding.kind,
+            finding.route,
+            finding.name
+        );
+        audit_log::append(
+            &AuditRecord::new("schema_drift", &finding.route, "")
+                .with("drift_kind", json!(finding.kind))
+                .with("name", json!(finding.name)),
+        );
+    }
+}
+
+/// 管理接口：已发现的漂移项
+pub(crate) fn findings() -> Vec<DriftFinding> {
+    let mut guard = FINDINGS.lock().unwrap_or_else(|e| e.into_inner());
+    guard
+        .get_or_insert_with(load)
+        .findings
+        .values()
+        .cloned()
+        .collect()
+}
+
+/// 管理接口：确认并清空已发现项（之后再次出现会重新提醒）
+pub(crate) fn acknowledge_all() -> Result<()> {
+    let mut guard = FINDINGS.lock().unwrap_or_else(|e| e.into_inner());
+    let file = guard.insert(DriftFile::default());
+    save(file)
+}
@@ -0,0 +1,491 @@
+// 本地搜索提供方（webSearch2）
+//
+// 每个搜索引擎实现 SearchProvider，search.providers 配置按顺序尝试的提供方链，
+// 例如 ["brave", "tavily", "duckduckgo"]：未配置 Key / 地址的提供方直接跳过，
+// 请求失败时依次降级到下一个，全部失败返回最后一个错误。新增引擎只需实现该 trait
+// 并在 build_chain 中登记名称，不必改动 handle_web_search。
+// 内置：searxng（自建实例 JSON API）、brave（Brave Search API）、
+// tavily（Key 取自 AMP Code 代理配置）、duckduckgo（HTML 解析，无需 Key）。
+
+use super::bandwidth::{self, Subject};
+use super::local_corpus::LocalCorpusSettings;
+use super::tool_credits::{self, ToolCreditSettings};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchSettings {
+    /// 提供方链（按顺序尝试）
+    pub providers: Vec<String>,
+    /// Brave Search API Key
+    pub brave_api_key: Option<String>,
+    /// 自建 SearXNG 实例
+    pub searxng: SearxngSettings,
+    /// 默认是否用廉价模型基于前几条结果生成带引用的简答（params.synthesize 可覆盖）
+    pub synthesize: bool,
+    /// 参与生成简答的结果条数
+    pub synthesis_sources: usize,
+    /// 核查简答中的引用（见 citation_check.rs）
+    pub verify_citations: bool,
+    /// 离线兜底的本地文档库（见 local_corpus.rs）
+    pub local_corpus: LocalCorpusSettings,
+}
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        Self {
+            providers: vec![
+                "searxng".to_string(),
+                "brave".to_string(),
+                "tavily".to_string(),
+                "duckduckgo".to_string(),
+            ],
+            brave_api_key: None,
+            searxng: SearxngSettings::default(),
+            synthesize: false,
+            synthesis_sources: 5,
+            verify_citations: true,
+            local_corpus: LocalCorpusSettings::default(),
+        }
+    }
+}
+
+/// 自建 SearXNG 搜索
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearxngSettings {
+    /// 实例地址，如 http://127.0.0.1:8888（None 不使用）
+    pub base_url: Option<String>,
+    /// 搜索分类（逗号分隔）
+    pub categories: String,
+    /// 结果语言，如 zh-CN（None 使用实例默认）
+    pub language: Option<String>,
+    /// 安全搜索级别：0 关闭 / 1 适中 / 2 严格
+    pub safesearch: u8,
+}
+
+impl Default for SearxngSettings {
+    fn default() -> Self {
+        Self {
+            base_url: None,
+            categories: "general".to_string(),
+            language: None,
+            safesearch: 0,
+        }
+    }
+}
+
+/// 单条搜索结果
+pub(crate) struct SearchHit {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+}
+
+#[async_trait]
+pub(crate) trait SearchProvider: Send + Sync {
+    /// 响应中的 provider 标识
+    fn label(&self) -> &'static str;
+
+    /// 单个查询
+    async fn search(&self, query: &str, max_results: usize) -> Result<Vec<SearchHit>>;
+}
+
+/// 按配置构建提供方链（经本地工具 Client 发送请求）；全部不可用时退回 DuckDuckGo
+pub(crate) fn build_chain(
+    settings: &SearchSettings,
+    tavily_api_key: Option<&str>,
+    client: &reqwest::Client,
+) -> Vec<Box<dyn SearchProvider>> {
+    let mut chain: Vec<Box<dyn SearchProvider>> = Vec::new();
+    for name in &settings.providers {
+        match name.as_str() {
+            "searxng" => {
+                if let Some(base_url) = settings.searxng.base_url.clone() {
+                    chain.push(Box::new(Searxng {
+                        settings: settings.searxng.clone(),
+                        base_url,
+                        client: client.clone(),
+                    }));
+                }
+            }
+            "brave" => {
+                if let Some(api_key) = settings.brave_api_key.clone() {
+                    chain.push(Box::new(Brave {
+                        api_key,
+                        client: client.clone(),
+                    }));
+                }
+            }
+            "tavily" => {
+                if let Some(api_key) = tavily_api_key {
+                    chain.push(Box::new(Tavily {
+                        api_key: api_key.to_string(),
+                        client: client.clone(),
+                    }));
+                }
+            }
+            "duckduckgo" => chain.push(Box::new(DuckDuckGo {
+                client: client.clone(),
+            })),
+            other => tracing::warn!("未知搜索提供方: {}", other),
+        }
+    }
+    if chain.is_empty() {
+        chain.push(Box::new(DuckDuckGo {
+            client: client.clone(),
+        }));
+    }
+    chain
+}
+
+/// 依次尝试提供方链，返回 (结果, provider 标识)；成功调用按 credits 计费
+pub(crate) async fn search(
+    chain: &[Box<dyn SearchProvider>],
+    credits: &ToolCreditSettings,
+    queries: &[&str],
+    max_results: usize,
+) -> Result<(Vec<Value>, &'static str)> {
+    let mut last_error = anyhow!("没有可用的搜索提供方");
+    for (i, provider) in chain.iter().enumerate() {
+        tracing::info!("使用 {} 搜索", provider.label());
+        match collect(provider.as_ref(), credits, queries, max_results).await {
+            Ok(results) => return Ok((results, provider.label())),
+            Err(e) => {
+                if let Some(next) = chain.get(i + 1) {
+                    tracing::warn!(
+                        "{} 搜索失败，降级 {}: {}",
+                        provider.label(),
+                        next.label(),
+                        e
+                    );
+                }
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// 多个查询合并结果，按 URL 去重
+async fn collect(
+    provider: &dyn SearchProvider,
+    credits: &ToolCreditSettings,
+    queries: &[&str],
+    max_results: usize,
+) -> Result<Vec<Value>> {
+    let mut all_results = Vec::new();
+    let mut seen_urls = HashSet::new();
+
+    for query in queries {
+        if all_results.len() >= max_results {
+            break;
+        }
+        let hits = provider.search(query, max_results).await?;
+        tool_credits::charge_call(credits, "webSearch2", provider.label());
+        for hit in hits {
+            if hit.url.is_empty() || !seen_urls.insert(hit.url.clone()) {
+                continue;
+            }
+            all_results.push(json!({
+                "title": hit.title,
+                "url": hit.url,
+                "excerpts": if hit.snippet.is_empty() { vec![] } else { vec![hit.snippet] }
+            }));
+            if all_results.len() >= max_results {
+                break;
+            }
+        }
+    }
+
+    Ok(all_results)
+}
+
+/// Tavily 搜索（使用本地工具 Client）
+struct Tavily {
+    api_key: String,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl SearchProvider for Tavily {
+    fn label(&self) -> &'static str {
+        "tavily"
+    }
+
+    async fn search(&self, query: &str, max_results: usize) -> Result<Vec<SearchHit>> {
+        let request_body = json!({
+            "api_key": self.api_key,
+            "query": query,
+            "search_depth": "basic",
+            "max_results": max_results.min(10),
+            "include_answer": false
+        });
+
+        let resp = self
+            .client
+            .post("https://api.tavily.com/search")
+            .header("Content-Type", "application/json")
+            .json(&request_body)
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let text = resp.text().await.unwrap_or_default();
+            return Err(anyhow!("Tavily API 错误: {} - {}", status, text));
+        }
+
+        let raw = resp.bytes().await?;
+        bandwidth::record(
+            Subject::Tool("webSearch2"),
+            request_body.to_string().len() as u64,
+            raw.len() as u64,
+        );
+        let data: Value = serde_json::from_slice(&raw)?;
+        Ok(json_hits(&data["results"], "content"))
+    }
+}
+
+/// 自建 SearXNG 搜索（JSON API，需在实例 settings.yml 的 search.formats 中启用 json）
+struct Searxng {
+    settings: SearxngSettings,
+    base_url: String,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl SearchProvider for Searxng {
+    fn label(&self) -> &'static str {
+        "local-searxng"
+    }
+
+    async fn search(&self, query: &str, _max_results: usize) -> Result<Vec<SearchHit>> {
+        let endpoint = format!("{}/search", self.base_url.trim_end_matches('/'));
+        let mut params = vec![
+            ("q", query.to_string()),
+            ("format", "json".to_string()),
+            ("categories", self.settings.categories.clone()),
+            ("safesearch", self.settings.safesearch.to_string()),
+        ];
+        if let Some(language) = self.settings.language.as_deref() {
+            params.push(("language", language.to_string()));
+        }
+
+        let resp = self
+            .client
+            .get(&endpoint)
+            .query(&params)
+            .header("Accept", "application/json")
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            return Err(anyhow!("SearXNG 错误: {}", resp.status()));
+        }
+
+        let raw = resp.bytes().await?;
+        bandwidth::record(Subject::Tool("webSearch2"), 0, raw.len() as u64);
+        let data: Value = serde_json::from_slice(&raw)
+            .map_err(|e| anyhow!("SearXNG 响应不是 JSON（是否已启用 json 格式）: {}", e))?;
+        Ok(json_hits(&data["results"], "content"))
+    }
+}
+
+/// Brave Search API
+struct Brave {
+    api_key: String,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl SearchProvider for Brave {
+    fn label(&self) -> &'static str {
+        "brave"
+    }
+
+    async fn search(&self, query: &str, max_results: usize) -> Result<Vec<SearchHit>> {
+        let resp = self
+            .client
+            .get("https://api.search.brave.com/res/v1/web/search")
+            .query(&[
+                ("q", query.to_string()),
+                ("count", max_results.clamp(1, 20).to_string()),
+            ])
+            .header("Accept", "application/json")
+            .header("X-Subscription-Token", &self.api_key)
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let text = resp.text().await.unwrap_or_default();
+            return Err(anyhow!("Brave Search API 错误: {} - {}", status, text));
+        }
+
+        let raw = resp.bytes().await?;
+        bandwidth::record(Subject::Tool("webSearch2"), 0, raw.len() as u64);
+        let data: Value = serde_json::from_slice(&raw)?;
+        Ok(json_hits(&data["web"]["results"], "description")
+            .into_iter()
+            .map(|hit| SearchHit {
+                snippet: clean_html(&hit.snippet),
+                ..hit
+            })
+            .collect())
+    }
+}
+
+/// JSON 结果数组 → SearchHit（title / url / 摘要字段）
+fn json_hits(results: &Value, snippet_field: &str) -> Vec<SearchHit> {
+    results
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .map(|r| SearchHit {
+                    title: r["title"].as_str().unwrap_or("").to_string(),
+                    url: r["url"].as_str().unwrap_or("").to_string(),
+                    snippet: r[snippet_field].as_str().unwrap_or("").to_string(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// DuckDuckGo HTML 搜索（降级方案，使用本地工具 Client）
+struct DuckDuckGo {
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl SearchProvider for DuckDuckGo {
+    fn label(&self) -> &'static str {
+        "local-duckduckgo"
+    }
+
+    async fn search(&self, query: &str, _max_results: usize) -> Result<Vec<SearchHit>> {
+        let url = format!(
+            "https://html.duckduckgo.com/html/?q={}",
+            urlencoding::encode(query)
+        );
+
+        let resp = self
+            .client
+            .get(&url)
+            .header("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36")
+            .header("Accept", "text/html")
+            .header("Accept-Language", "zh-CN,zh;q=0.9,en;q=0.8")
+            .send()
+            .await?;
+
+        let html = resp.text().await?;
+        bandwidth::record(Subject::Tool("webSearch2"), 0, html.len() as u64);
+        Ok(parse_duckduckgo_html(&html))
+    }
+}
+
+/// 解析 DuckDuckGo HTML 结果
+fn parse_duckduckgo_html(html: &str) -> Vec<SearchHit> {
+    let mut results = Vec::new();
+
+    // 简单解析：查找 class="result__a" 的链接
+    for part in html.split("class=\"result__a\"").skip(1) {
+        // 提取 URL
+        let url = if let Some(start) = part.find("href=\"") {
+            let after = &part[start + 6..];
+            if let Some(end) = after.find('"') {
+                extract_ddg_actual_url(&after[..end])
+            } else {
+                continue;
+            }
+        } else {
+            continue;
+        };
+
+        if url.is_empty() {
+            continue;
+        }
+
+        // 提取标题
+        let title = if let Some(start) = part.find('>') {
+            let after = &part[start + 1..];
+            if let Some(end) = after.find("</a>") {
+                clean_html(&after[..end])
+            } else {
+                String::new()
+            }
+        } else {
+            String::new()
+        };
+
+        // 提取摘要
+        let snippet = if let Some(snip_start) = part.find("result__snippet") {
+            let snip_part = &part[snip_start..];
+            if let Some(start) = snip_part.find('>') {
+                let after = &snip_part[start + 1..];
+                if let Some(end) = after.find("</a>") {
+                    clean_html(&after[..end])
+                } else {
+                    String::new()
+                }
+            } else {
+                String::new()
+            }
+        } else {
+            String::new()
+        };
+
+        results.push(SearchHit {
+            title,
+            url,
+            snippet,
+        });
+    }
+
+    results
+}
+
+/// 从 DuckDuckGo 重定向 URL 提取实际 URL
+fn extract_ddg_actual_url(ddg_url: &str) -> String {
+    if ddg_url.contains("uddg=") {
+        if let Some(pos) = ddg_url.find("uddg=") {
+            let encoded = &ddg_url[pos + 5..];
+            let end = encoded.find('&').unwrap_or(encoded.len());
+            if let Ok(decoded) = urlencoding::decode(&encoded[..end]) {
+                return decoded.into_owned();
+            }
+        }
+    }
+    if ddg_url.starts_with("http") {
+        ddg_url.to_string()
+    } else {
+        String::new()
+    }
+}
+
+/// 清理 HTML 标签和实体
+pub(crate) fn clean_html(s: &str) -> String {
+    let mut result = s.to_string();
+    // 移除 HTML 标签
+    while let Some(start) = result.find('<') {
+        if let Some(end) = result[start..].find('>') {
+            result = format!("{}{}", &result[..start], &result[start + end + 1..]);
+        } else {
+            break;
+        }
+    }
+    // 解码常见 HTML 实体
+    result = result
+        .replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ");
+    result.trim().to_string()
+}
@@ -0,0 +1,264 @@
+// 密钥存储（SecretStore）
+//
+// 代理配置的 real_api_key / tavily_api_key、处理器配置中的密钥
+// （Azure Key、Bedrock 凭证、AMP 账号 Token、搜索 / 天气 Key、管理 API / 观测面板 token、GitHub token、外部提取服务 Key 等）不再以明文写入配置文件，
+// 而是存入系统钥匙串（macOS Keychain / Windows 凭据管理器 / Secret Service），
+// 配置中只保留引用 keychain:<账号>：
+// - 账号命名：proxy/<tool_id>/<字段>（代理配置）、settings/<字段路径>（处理器配置）
+// - reveal()：读取配置值，引用从存储中取出，明文原样返回（未迁移的旧配置照常可用）；
+//   请求必需的 Key 用 reveal_required()，取不到时返回指明归属的错误，不以空 Key 请求上游
+// - protect() / migrate()：写入配置时调用，明文存入钥匙串并替换为引用；存储不可用时保留明文并告警，不中断保存
+//   - 代理配置：AppState 首次构造 ProxyConfigManager 时经 migrate_proxy_config() 透明迁移并写回
+//   - Profile：ProfileManager 的存储由桌面端激活 Profile、直连处理器等共同读取，本模块不改写；
+//     其中的 Key 若已是引用，请求时经 reveal_required() 解析
+//   - 处理器配置：ProcessorSettings 读取时迁移文件中的明文、保存时写入引用，内存中始终为明文
+// - forget()：配置中移除的密钥（以及作废的 Token 刷新记录）同时从存储删除
+// 钥匙串后端需启用 keychain 特性（引入 keyring）；未启用时没有默认存储，配置保持明文。
+// 嵌入方可用 install() 换成 MemoryStore 等自定义实现；测试经 protect_in() / reveal_in() 直接传入存储，
+// 不安装全局存储（避免并行测试互相覆盖）。
+
+use crate::services::proxy_config_manager::ProxyConfigManager;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// 配置中的引用前缀
+const REFERENCE_PREFIX: &str = "keychain:";
+
+/// 钥匙串服务名
+#[cfg(feature = "keychain")]
+const SERVICE: &str = "DuckCoding";
+
+pub trait SecretStore: Send + Sync {
+    /// 存储名（日志用）
+    fn name(&self) -> &'static str;
+    fn get(&self, account: &str) -> Result<Option<String>>;
+    fn set(&self, account: &str, secret: &str) -> Result<()>;
+    fn delete(&self, account: &str) -> Result<()>;
+}
+
+/// 系统钥匙串
+#[cfg(feature = "keychain")]
+#[derive(Debug, Default)]
+pub struct KeychainStore;
+
+#[cfg(feature = "keychain")]
+impl KeychainStore {
+    fn entry(account: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new(SERVICE, account)
+            .map_err(|e| anyhow::anyhow!("打开钥匙串条目 {} 失败: {}", account, e))
+    }
+}
+
+#[cfg(feature = "keychain")]
+impl SecretStore for KeychainStore {
+    fn name(&self) -> &'static str {
+        "keychain"
+    }
+
+    fn get(&self, account: &str) -> Result<Option<String>> {
+        match Self::entry(account)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(anyhow::anyhow!("读取钥匙串 {} 失败: {}", account, e)),
+        }
+    }
+
+    fn set(&self, account: &str, secret: &str) -> Result<()> {
+        Self::entry(account)?
+            .set_password(secret)
+            .map_err(|e| anyhow::anyhow!("写入钥匙串 {} 失败: {}", account, e))
+    }
+
+    fn delete(&self, account: &str) -> Result<()> {
+        match Self::entry(account)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(anyhow::anyhow!("删除钥匙串 {} 失败: {}", account, e)),
+        }
+    }
+}
+
+/// 进程内存储（测试 / 无钥匙串环境）
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    secrets: Mutex<HashMap<String, String>>,
+}
+
+impl SecretStore for MemoryStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn get(&self, account: &str) -> Result<Option<String>> {
+        Ok(self
+            .secrets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(account)
+            .cloned())
+    }
+
+    fn set(&self, account: &str, secret: &str) -> Result<()> {
+        self.secrets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(account.to_string(), secret.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, account: &str) -> Result<()> {
+        self.secrets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(account);
+        Ok(())
+    }
+}
+
+static STORE: Lazy<RwLock<Option<Arc<dyn SecretStore>>>> = Lazy::new(|| {
+    #[cfg(feature = "keychain")]
+    let store: Option<Arc<dyn SecretStore>> = Some(Arc::new(KeychainStore));
+    #[cfg(not(feature = "keychain"))]
+    let store: Option<Arc<dyn SecretStore>> = None;
+    RwLock::new(store)
+});
+
+/// 当前安装的密钥存储；需要显式指定存储的调用方（测试等）使用 *_in 系列函数
+pub(crate) fn store() -> Option<Arc<dyn SecretStore>> {
+    STORE.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// 替换密钥存储（进程级，对所有引擎 / AppState 生效）
+pub fn install(store: Arc<dyn SecretStore>) {
+    *STORE.write().unwrap_or_else(|e| e.into_inner()) = Some(store);
+}
+
+/// 代理配置字段的账号名，如 proxy/amp-code/real_api_key
+pub fn proxy_account(tool_id: &str, field: &str) -> String {
+    format!("proxy/{}/{}", tool_id, field)
+}
+
+/// 处理器配置字段的账号名，如 settings/azure/api_key
+pub fn settings_account(field: &str) -> String {
+    format!("settings/{}", field)
+}
+
+pub fn is_reference(value: &str) -> bool {
+    value.starts_with(REFERENCE_PREFIX)
+}
+
+/// 解析配置中的密钥：引用从存储读取（读取失败或不存在时返回 None），明文原样返回
+pub fn reveal(value: &str) -> Option<String> {
+    reveal_in(store().as_deref(), value)
+}
+
+/// 同 reveal()，使用指定的存储
+pub fn reveal_in(store: Option<&dyn SecretStore>, value: &str) -> Option<String> {
+    let Some(account) = value.strip_prefix(REFERENCE_PREFIX) else {
+        return Some(value.to_string());
+    };
+    let Some(store) = store else {
+        tracing::warn!("配置引用了钥匙串密钥 {}，但当前没有可用的密钥存储", account);
+        return None;
+    };
+    match store.get(account) {
+        Ok(Some(secret)) => Some(secret),
+        Ok(None) => {
+            tracing::warn!("{} 中不存在密钥 {}", store.name(), account);
+            None
+        }
+        Err(e) => {
+            tracing::warn!("{}", e);
+            None
+        }
+    }
+}
+
+pub(crate) fn reveal_opt(value: Option<String>) -> Option<String> {
+    value.as_deref().and_then(reveal)
+}
+
+/// 请求必需的密钥：引用无法解析时返回指明归属的错误（owner 如 "Profile xxx"）
+pub(crate) fn reveal_required(value: &str, owner: &str) -> Result<String> {
+    reveal(value).ok_or_else(|| {
+        anyhow!(
+            "{} 的 API Key 无法从密钥存储读取（{}），请检查钥匙串或重新填写",
+            owner,
+            value
+        )
+    })
+}
+
+/// 存入密钥存储并返回写入配置的引用；存储不可用时返回明文
+pub fn protect(account: &str, secret: &str) -> String {
+    protect_in(store().as_deref(), account, secret)
+}
+
+/// 同 protect()，使用指定的存储
+pub fn protect_in(store: Option<&dyn SecretStore>, account: &str, secret: &str) -> String {
+    if secret.is_empty() || is_reference(secret) {
+        return secret.to_string();
+    }
+    let Some(store) = store else {
+        return secret.to_string();
+    };
+    match store.set(account, secret) {
+        Ok(()) => format!("{}{}", REFERENCE_PREFIX, account),
+        Err(e) => {
+            tracing::warn!("{}，密钥保持明文", e);
+            secret.to_string()
+        }
+    }
+}
+
+/// 迁移明文密钥：存入密钥存储并替换为引用，返回是否有变化（调用方随后写回配置）
+pub fn migrate(account: &str, value: &mut Option<String>) -> bool {
+    let Some(current) = value.as_deref() else {
+        return false;
+    };
+    let protected = protect(account, current);
+    if protected == current {
+        return false;
+    }
+    tracing::info!(
+        "密钥 {} 已迁移到 {}",
+        account,
+        store().map_or("?", |s| s.name())
+    );
+    *value = Some(protected);
+    true
+}
+
+/// 迁移代理配置中的明文密钥（real_api_key / tavily_api_key），有变化时写回，返回是否写回
+pub(crate) fn migrate_proxy_config(manager: &ProxyConfigManager, tool_id: &str) -> Result<bool> {
+    let Some(mut config) = manager
+        .get_config(tool_id)
+        .map_err(|e| anyhow!("读取代理配置 {} 失败: {}", tool_id, e))?
+    else {
+        return Ok(false);
+    };
+    let migrated = migrate(
+        &proxy_account(tool_id, "real_api_key"),
+        &mut config.real_api_key,
+    ) | migrate(
+        &proxy_account(tool_id, "tavily_api_key"),
+        &mut config.tavily_api_key,
+    );
+    if migrated {
+        manager
+            .update_config(tool_id, config)
+            .map_err(|e| anyhow!("写回代理配置 {} 失败: {}", tool_id, e))?;
+    }
+    Ok(migrated)
+}
+
+/// 删除引用指向的密钥（配置删除时调用）；明文值无需处理
+pub fn forget(value: &str) {
+    let (Some(account), Some(store)) = (value.strip_prefix(REFERENCE_PREFIX), store()) else {
+        return;
+    };
+    if let Err(e) = store.delete(account) {
+        tracing::warn!("{}", e);
+    }
+}
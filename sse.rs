@@ -0,0 +1,328 @@
+// 增量 SSE 解析与输出（响应侧中间件共用）
+//
+// 上游流式响应按任意边界分块到达，事件可能被切在任意字节处（包括 CRLF 中间）。
+// SseParser 逐块输入，切出完整事件：
+// - 行结束符兼容 LF / CRLF / CR（WHATWG 规范），空行为事件边界；块末尾的单独 CR
+//   暂不判定，等下一块确认是否为 CRLF
+// - 事件以 Bytes 切片返回（split_to + freeze，不复制），raw() 为含结尾空行的原始字节，
+//   可原样透传；data 字段按需解析，多行 data 以 \n 拼接，注释行（以 : 开头）忽略；
+//   data_lines() 逐行取值，供 OpenAI 兼容流中事件间缺少空行的后端使用
+// - 已扫描位置与行状态跨块保留，长事件分多块到达时不重复扫描
+// - finish() 取出流结束时缺少结尾空行的残余事件
+// format / emit / emit_data 按统一格式输出事件，供各中间件合成事件时使用。
+
+use bytes::{Bytes, BytesMut};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fmt::Display;
+
+/// 一个完整的 SSE 事件
+#[derive(Debug, Clone)]
+pub(crate) struct SseEvent {
+    raw: Bytes,
+}
+
+impl SseEvent {
+    /// 原始字节（含结尾空行）
+    pub(crate) fn raw(&self) -> &Bytes {
+        &self.raw
+    }
+
+    pub(crate) fn into_raw(self) -> Bytes {
+        self.raw
+    }
+
+    /// 按行拆分（去掉行结束符）
+    fn lines(&self) -> impl Iterator<Item = &[u8]> {
+        let raw = &self.raw[..];
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            if pos >= raw.len() {
+                return None;
+            }
+            let rest = &raw[pos..];
+            let end = rest
+                .iter()
+                .position(|b| *b == b'\n' || *b == b'\r')
+                .unwrap_or(rest.len());
+            let line = &rest[..end];
+            pos += end;
+            if raw.get(pos) == Some(&b'\r') && raw.get(pos + 1) == Some(&b'\n') {
+                pos += 2;
+            } else {
+                pos += 1;
+            }
+            Some(line)
+        })
+    }
+
+    /// 指定字段的全部取值（冒号后的单个空格去掉）
+    fn field_values<'a>(&'a self, name: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.lines().filter_map(move |line| {
+            if line.first() == Some(&b':') {
+                return None;
+            }
+            let (field, value) = match line.iter().position(|b| *b == b':') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => (line, &[][..]),
+            };
+            (field == name).then(|| value.strip_prefix(b" ").unwrap_or(value))
+        })
+    }
+
+    /// data 字段（多行以 \n 拼接）；没有 data 行时为 None
+    pub(crate) fn data(&self) -> Option<Cow<'_, str>> {
+        let mut values = self.field_values(b"data");
+        let first = values.next()?;
+        match values.next() {
+            None => Some(String::from_utf8_lossy(first)),
+            Some(second) => {
+                let mut joined = String::from_utf8_lossy(first).into_owned();
+                for value in std::iter::once(second).chain(values) {
+                    joined.push('\n');
+                    joined.push_str(&String::from_utf8_lossy(value));
+                }
+                Some(Cow::Owned(joined))
+            }
+        }
+    }
+
+    /// 逐行的 data 取值；兼容事件间缺少空行、每行 data 各自为一个 JSON 的后端
+    pub(crate) fn data_lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.field_values(b"data").map(String::from_utf8_lossy)
+    }
+
+    /// data 解析为 JSON
+    pub(crate) fn json(&self) -> Option<Value> {
+        serde_json::from_str(self.data()?.trim()).ok()
+    }
+
+    /// OpenAI 风格的结束标记 data: [DONE]
+    pub(crate) fn is_done(&self) -> bool {
+        self.data().is_some_and(|d| d.trim() == "[DONE]")
+    }
+
+    /// 只有空行 / 空白（无任何字段）
+    pub(crate) fn is_blank(&self) -> bool {
+        self.raw.iter().all(u8::is_ascii_whitespace)
+    }
+}
+
+/// 增量解析器
+#[derive(Debug)]
+pub(crate) struct SseParser {
+    buf: BytesMut,
+    /// 已扫描到的位置
+    scanned: usize,
+    /// scanned 处是否为行首
+    at_line_start: bool,
+}
+
+impl Default for SseParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SseParser {
+    pub(crate) fn new() -> Self {
+        Self {
+            buf: BytesMut::new(),
+            scanned: 0,
+            at_line_start: true,
+        }
+    }
+
+    /// 追加一块数据
+    pub(crate) fn push(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// 追加一块数据并取出其中全部完整事件（跳过空事件）
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.push(chunk);
+        std::iter::from_fn(|| self.next_event()).collect()
+    }
+
+    /// 取出下一个完整事件（跳过空事件）
+    pub(crate) fn next_event(&mut self) -> Option<SseEvent> {
+        loop {
+            let end = self.scan()?;
+            let raw = self.buf.split_to(end).freeze();
+            self.scanned = 0;
+            self.at_line_start = true;
+            let event = SseEvent { raw };
+            if !event.is_blank() {
+                return Some(event);
+            }
+        }
+    }
+
+    /// 查找事件边界，返回事件结束（含空行）的位置
+    fn scan(&mut self) -> Option<usize> {
+        let buf = &self.buf[..];
+        let mut i = self.scanned;
+        while i < buf.len() {
+            let b = buf[i];
+            match b {
+                b'\r' | b'\n' => {
+                    // 块末尾的 CR 可能是 CRLF 的前半，等待下一块
+                    if b == b'\r' && i + 1 == buf.len() {
+                        break;
+                    }
+                    let mut end = i + 1;
+                    if b == b'\r' && buf[end] == b'\n' {
+                        end += 1;
+                    }
+                    if self.at_line_start {
+                        return Some(end);
+                    }
+                    self.at_line_start = true;
+                    i = end;
+                }
+                _ => {
+                    self.at_line_start = false;
+                    i += 1;
+                }
+            }
+        }
+        self.scanned = i;
+        None
+    }
+
+    /// 流结束：取出缺少结尾空行的残余事件（仅空白时为 None）
+    pub(crate) fn finish(&mut self) -> Option<SseEvent> {
+        if let Some(event) = self.next_event() {
+            return Some(event);
+        }
+        let raw = self.buf.split().freeze();
+        self.scanned = 0;
+        self.at_line_start = true;
+        let event = SseEvent { raw };
+        (!event.is_blank()).then_some(event)
+    }
+}
+
+/// 格式化一个事件（event 为 None 时只有 data 行）
+pub(crate) fn format(event: Option<&str>, data: impl Display) -> String {
+    match event {
+        Some(name) => format!("event: {}\ndata: {}\n\n", name, data),
+        None => format!("data: {}\n\n", data),
+    }
+}
+
+/// 输出带事件名的事件
+pub(crate) fn emit(event: &str, data: impl Display) -> Bytes {
+    Bytes::from(format(Some(event), data))
+}
+
+/// 输出只有 data 的事件
+pub(crate) fn emit_data(data: impl Display) -> Bytes {
+    Bytes::from(format(None, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按给定切分点分块输入，返回全部事件的原始字节（含 finish 的残余事件）
+    fn split_feed(input: &[u8], cuts: &[usize]) -> Vec<Bytes> {
+        let mut parser = SseParser::new();
+        let mut events = Vec::new();
+        let mut start = 0;
+        for &cut in cuts.iter().chain(std::iter::once(&input.len())) {
+            events.extend(parser.feed(&input[start..cut]));
+            start = cut;
+        }
+        events.extend(parser.finish());
+        events.into_iter().map(SseEvent::into_raw).collect()
+    }
+
+    fn datas(input: &[u8], cuts: &[usize]) -> Vec<String> {
+        let mut parser = SseParser::new();
+        let mut events = Vec::new();
+        let mut start = 0;
+        for &cut in cuts.iter().chain(std::iter::once(&input.len())) {
+            events.extend(parser.feed(&input[start..cut]));
+            start = cut;
+        }
+        events.extend(parser.finish());
+        events
+            .iter()
+            .map(|e| e.data().unwrap_or_default().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn every_split_point_yields_same_events() {
+        let input: &[u8] =
+            b"event: a\r\ndata: {\"x\":1}\r\n\r\ndata: one\ndata: two\n\n: ping\n\ndata: tail\r\rdata: end\n\n";
+        let whole = split_feed(input, &[]);
+        assert_eq!(whole.len(), 5);
+        for cut in 1..input.len() {
+            assert_eq!(split_feed(input, &[cut]), whole, "cut at {}", cut);
+        }
+        for step in 1..4 {
+            let cuts: Vec<usize> = (step..input.len()).step_by(step).collect();
+            assert_eq!(split_feed(input, &cuts), whole, "step {}", step);
+        }
+    }
+
+    #[test]
+    fn crlf_split_across_chunks() {
+        let input = b"data: a\r\n\r\ndata: b\r\n\r\n";
+        // 每个 CR 与其后的 LF 都落在不同块中
+        let cuts: Vec<usize> = (0..input.len())
+            .filter(|i| input[*i] == b'\r')
+            .map(|i| i + 1)
+            .collect();
+        assert_eq!(datas(input, &cuts), ["a", "b"]);
+        // 事件原样透传，不会被拆出额外的空事件
+        assert_eq!(split_feed(input, &cuts)[0], &b"data: a\r\n\r\n"[..]);
+    }
+
+    #[test]
+    fn multibyte_utf8_split_across_chunks() {
+        let input = "data: {\"text\":\"你好，世界\"}\n\n".as_bytes();
+        let first = input.iter().position(|b| *b >= 0x80).unwrap();
+        for cut in first..first + 6 {
+            let mut parser = SseParser::new();
+            assert!(parser.feed(&input[..cut]).is_empty());
+            let events = parser.feed(&input[cut..]);
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].json().unwrap()["text"], "你好，世界");
+        }
+    }
+
+    #[test]
+    fn missing_trailing_newline_at_eof() {
+        assert_eq!(datas(b"data: a\n\ndata: last", &[]), ["a", "last"]);
+        assert_eq!(datas(b"data: last\n", &[]), ["last"]);
+        assert_eq!(datas(b"data: last\r", &[]), ["last"]);
+        let mut parser = SseParser::new();
+        parser.push(b"\r\n \n");
+        assert!(parser.finish().is_none());
+    }
+
+    #[test]
+    fn comments_and_retry_lines() {
+        let mut parser = SseParser::new();
+        let events =
+            parser.feed(b": keep-alive\n\nretry: 3000\nid: 7\ndata:x\n\n:only comment\n\n");
+        assert_eq!(events.len(), 3);
+        assert!(events[0].data().is_none());
+        assert_eq!(events[1].data().unwrap(), "x");
+        assert!(events[2].json().is_none());
+    }
+
+    #[test]
+    fn data_lines_and_done() {
+        let mut parser = SseParser::new();
+        let events = parser.feed(b"data: {\"a\":1}\ndata: {\"a\":2}\n\ndata: [DONE]\n\n");
+        let lines: Vec<_> = events[0].data_lines().collect();
+        assert_eq!(lines, ["{\"a\":1}", "{\"a\":2}"]);
+        assert!(events[1].is_done());
+        assert_eq!(format(Some("ping"), "{}"), "event: ping\ndata: {}\n\n");
+        assert_eq!(emit_data("x"), Bytes::from_static(b"data: x\n\n"));
+    }
+}
@@ -0,0 +1,349 @@
+// 流式响应停滞检测
+//
+// 上游以极慢速度持续输出（或发完响应头后不再发送数据）时，总超时不会触发，Agent 会一直挂起。
+// 这里按“空闲时长”检测：连续 idle_secs 秒没有收到任何数据即视为停滞，按 action 处理：
+// - abort：中止上游连接；SSE 响应补发协议对应的错误事件后结束（Claude 为 event: error，
+//   其余路由为 data: {"error": ...}），非 SSE 响应以传输错误结束
+// - resume：尚未向客户端发出任何数据时重新发送整条转发链（含故障转移）并透传新响应，
+//   最多 max_resumes 次；已发出部分数据时无法拼接新响应（输出会重复），退化为 abort
+// LLM 转发按 Profile 配置（stall），本地工具与廉价模型的外部请求按 tools_stall 配置（只支持 abort）。
+
+use super::audit_log::{self, AuditRecord};
+use super::dashboard;
+use super::sse;
+use super::telemetry::Span;
+use super::upstream::{Chain, Forwarded};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StallAction {
+    #[default]
+    Abort,
+    Resume,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StallSettings {
+    pub enabled: bool,
+    /// 连续无数据多少秒视为停滞
+    pub idle_secs: u64,
+    pub action: StallAction,
+    /// resume 时最多重发次数
+    pub max_resumes: u32,
+}
+
+impl Default for StallSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_secs: 120,
+            action: StallAction::Abort,
+            max_resumes: 1,
+        }
+    }
+}
+
+impl StallSettings {
+    fn idle(&self) -> Option<Duration> {
+        (self.enabled && self.idle_secs > 0).then(|| Duration::from_secs(self.idle_secs))
+    }
+}
+
+/// 停滞时补发的错误事件
+fn stall_event(route: &str, message: &str) -> Bytes {
+    if route == "claude" {
+        let error = json!({
+            "type": "error",
+            "error": { "type": "timeout_error", "message": message }
+        });
+        sse::emit("error", error)
+    } else {
+        let error = json!({ "error": { "type": "timeout_error", "message": message } });
+        sse::emit_data(error)
+    }
+}
+
+fn record(chain: &Chain, outcome: &str, delivered: u64) {
+    audit_log::append(
+        &AuditRecord::new("stall", &chain.route, chain.primary())
+            .with("outcome", json!(outcome))
+            .with("delivered_bytes", json!(delivered)),
+    );
+}
+
+/// 包装 LLM 响应流：停滞时按配置中止或重发
+pub(crate) fn apply(
+    settings: &StallSettings,
+    chain: Arc<Chain>,
+    context: Option<String>,
+    forwarded: Forwarded,
+) -> Forwarded {
+    let Some(idle) = settings.idle() else {
+        return forwarded;
+    };
+    if !forwarded.response.status().is_success() {
+        return forwarded;
+    }
+    let response = forwarded.response;
+    let is_sse = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.contains("text/event-stream"));
+    let mut builder = hyper::http::Response::builder().status(response.status());
+    for (name, value) in response.headers() {
+        builder = builder.header(name, value);
+    }
+
+    let settings = settings.clone();
+    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(16);
+    tokio::spawn(async move {
+        let mut body = response.bytes_stream().boxed();
+        let mut delivered = 0u64;
+        let mut resumes = 0u32;
+        loop {
+            let chunk = match tokio::time::timeout(idle, body.next()).await {
+                Ok(Some(chunk)) => chunk.map_err(std::io::Error::other),
+                Ok(None) => return,
+                Err(_) => {
+                    let can_resume = settings.action == StallAction::Resume
+                        && delivered == 0
+                        && resumes < settings.max_resumes;
+                    if can_resume {
+                        resumes += 1;
+                        tracing::warn!(
+                            "{} 响应流 {}s 无数据，重新发送请求（第 {} 次）",
+                            chain.route,
+                            idle.as_secs(),
+                            resumes
+                        );
+                        let span = Span::from_context("upstream.stall_resume", context.as_deref());
+                        match chain.run(&span).await {
+                            Ok(f) if f.response.status().is_success() => {
+                                record(&chain, "resumed", delivered);
+                                body = f.response.bytes_stream().boxed();
+                                continue;
+                            }
+                            Ok(f) => tracing::warn!(
+                                "停滞重发返回 {}，中止响应",
+                                f.response.status().as_u16()
+                            ),
+                            Err(e) => tracing::warn!("停滞重发失败，中止响应: {}", e),
+                        }
+                    }
+                    let message = format!(
+                        "上游响应流 {}s 无数据，已中止（已输出 {} bytes）",
+                        idle.as_secs(),
+                        delivered
+                    );
+                    tracing::warn!("{}: {}", chain.route, message);
+                    dashboard::record_error(&chain.route, &message);
+                    record(&chain, "aborted", delivered);
+                    let item = if is_sse {
+                        Ok(stall_event(&chain.route, &message))
+                    } else {
+                        Err(std::io::Error::new(std::io::ErrorKind::TimedOut, message))
+                    };
+                    let _ = tx.send(item).await;
+                    return;
+                }
+            };
+            if let Ok(bytes) = &chunk {
+                delivered += bytes.len() as u64;
+            }
+            let failed = chunk.is_err();
+            if tx.send(chunk).await.is_err() || failed {
+                // 客户端已断开或上游出错：丢弃上游连接
+                return;
+            }
+        }
+    });
+
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    });
+    let response = builder
+        .body(reqwest::Body::wrap_stream(stream))
+        .map(reqwest::Response::from)
+        .unwrap_or_else(|_| {
+            reqwest::Response::from(hyper::http::Response::new(reqwest::Body::from("")))
+        });
+    Forwarded {
+        response,
+        served_by: forwarded.served_by,
+    }
+}
+
+/// 读取下一个数据块，超过空闲时长未收到数据时报错（本地工具使用）
+pub(crate) async fn next_chunk<S>(settings: &StallSettings, stream: &mut S) -> Result<Option<Bytes>>
+where
+    S: futures_util::Stream<Item = reqwest::Result<Bytes>> + Unpin,
+{
+    let next = match settings.idle() {
+        Some(idle) => tokio::time::timeout(idle, stream.next())
+            .await
+            .map_err(|_| anyhow!("响应 {}s 无数据，已中止", idle.as_secs()))?,
+        None => stream.next().await,
+    };
+    next.transpose().map_err(|e| anyhow!("读取响应失败: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(action: StallAction) -> StallSettings {
+        StallSettings {
+            idle_secs: 1,
+            action,
+            ..Default::default()
+        }
+    }
+
+    /// 先发出 first（可为空），之后不再有数据
+    fn stalled(content_type: &str, first: &'static str) -> Forwarded {
+        let chunks: Vec<Result<Bytes, std::io::Error>> = if first.is_empty() {
+            Vec::new()
+        } else {
+            vec![Ok(Bytes::from_static(first.as_bytes()))]
+        };
+        let body = futures_util::stream::iter(chunks).chain(futures_util::stream::pending());
+        Forwarded {
+            response: reqwest::Response::from(
+                hyper::http::Response::builder()
+                    .status(200)
+                    .header("content-type", content_type)
+                    .body(reqwest::Body::wrap_stream(body))
+                    .unwrap(),
+            ),
+            served_by: String::new(),
+        }
+    }
+
+    /// 本地上游：对每个连接返回固定 SSE 响应体
+    async fn sse_upstream(body: &'static str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}/v1/messages", addr)
+    }
+
+    #[tokio::test]
+    async fn stalled_sse_aborted_with_error_event() {
+        let chain = Arc::new(Chain::for_test("claude", &[], b"{}"));
+        let out = apply(
+            &settings(StallAction::Abort),
+            chain,
+            None,
+            stalled("text/event-stream", "data: partial\n\n"),
+        );
+        let text = out.response.text().await.unwrap();
+        assert!(text.starts_with("data: partial\n\n"), "{}", text);
+        assert!(text.contains("event: error\n"), "{}", text);
+        assert!(text.contains("timeout_error"));
+        assert!(text.contains("已输出 15 bytes"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn stalled_non_sse_ends_with_transport_error() {
+        let chain = Arc::new(Chain::for_test("codex", &[], b"{}"));
+        let out = apply(
+            &settings(StallAction::Abort),
+            chain,
+            None,
+            stalled("application/json", "{"),
+        );
+        assert!(out.response.bytes().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stall_before_first_byte_resends_request() {
+        let upstream = sse_upstream("data: resumed\n\n").await;
+        let chain = Arc::new(Chain::for_test("claude", &[upstream.as_str()], b"{}"));
+        let out = apply(
+            &settings(StallAction::Resume),
+            chain,
+            None,
+            stalled("text/event-stream", ""),
+        );
+        assert_eq!(out.response.text().await.unwrap(), "data: resumed\n\n");
+    }
+
+    #[tokio::test]
+    async fn resume_after_partial_output_degrades_to_abort() {
+        // 已输出部分数据时重发会导致输出重复，直接中止
+        let upstream = sse_upstream("data: resumed\n\n").await;
+        let chain = Arc::new(Chain::for_test("gemini", &[upstream.as_str()], b"{}"));
+        let out = apply(
+            &settings(StallAction::Resume),
+            chain,
+            None,
+            stalled("text/event-stream", "data: partial\n\n"),
+        );
+        let text = out.response.text().await.unwrap();
+        assert!(!text.contains("resumed"), "{}", text);
+        assert!(text.contains("data: {\"error\""), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn disabled_or_failed_responses_pass_through() {
+        let chain = Arc::new(Chain::for_test("claude", &[], b"{}"));
+        let disabled = StallSettings {
+            enabled: false,
+            ..settings(StallAction::Abort)
+        };
+        assert!(disabled.idle().is_none());
+        assert!(StallSettings {
+            idle_secs: 0,
+            ..Default::default()
+        }
+        .idle()
+        .is_none());
+
+        let error = Forwarded {
+            response: reqwest::Response::from(
+                hyper::http::Response::builder()
+                    .status(500)
+                    .body(reqwest::Body::from("boom"))
+                    .unwrap(),
+            ),
+            served_by: String::new(),
+        };
+        let out = apply(&settings(StallAction::Abort), chain, None, error);
+        assert_eq!(out.response.text().await.unwrap(), "boom");
+    }
+
+    #[tokio::test]
+    async fn next_chunk_times_out_when_idle() {
+        let mut data =
+            futures_util::stream::iter(vec![Ok::<_, reqwest::Error>(Bytes::from_static(b"a"))]);
+        let s = settings(StallAction::Abort);
+        assert_eq!(next_chunk(&s, &mut data).await.unwrap().unwrap(), "a");
+        assert!(next_chunk(&s, &mut data).await.unwrap().is_none());
+
+        let mut idle = futures_util::stream::pending::<reqwest::Result<Bytes>>();
+        let err = next_chunk(&s, &mut idle).await.unwrap_err();
+        assert_eq!(err.to_string(), "响应 1s 无数据，已中止");
+    }
+}
@@ -0,0 +1,236 @@
+// 可替换的持久化后端
+//
+// Storage 以 (命名空间, 键) 存取字节值，scan 按键前缀列举。
+// - FileStorage（默认）：根目录 ~/.duckcoding，命名空间为子目录、键为文件名，
+//   与各模块原有文件位置一致（如 amp/memory.json），升级无需迁移
+// - SqliteStorage（feature = "sqlite"）：单表 kv(ns, key, value)
+// 嵌入方可在启动时 install() 自己的实现（例如桌面端已有数据库），
+// 会话、用量、记忆库、结构漂移、指纹密钥等随之写入该后端。
+// 审计日志（追加写）与用量 WAL 仍使用本地文件：仅在默认后端下启用 WAL，
+// 自定义后端时用量直接读改写到 usage 命名空间。
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+pub trait Storage: Send + Sync {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>>;
+    fn put(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()>;
+    fn delete(&self, namespace: &str, key: &str) -> Result<()>;
+    /// 按键前缀列举（prefix 为空时列举整个命名空间），按键排序
+    fn scan(&self, namespace: &str, prefix: &str) -> Result<Vec<(String, Vec<u8>)>>;
+}
+
+/// 文件后端：{root}/{namespace}/{urlencode(key)}
+pub struct FileStorage {
+    root: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn dir(&self, namespace: &str) -> PathBuf {
+        namespace
+            .split('/')
+            .filter(|s| !s.is_empty() && *s != "..")
+            .fold(self.root.clone(), |p, s| p.join(s))
+    }
+
+    fn file(&self, namespace: &str, key: &str) -> PathBuf {
+        self.dir(namespace).join(urlencoding::encode(key).as_ref())
+    }
+}
+
+impl Default for FileStorage {
+    fn default() -> Self {
+        Self::new(
+            dirs::home_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join(".duckcoding"),
+        )
+    }
+}
+
+impl Storage for FileStorage {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.file(namespace, key);
+        match std::fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow!("读取 {} 失败: {}", path.display(), e)),
+        }
+    }
+
+    /// 先写临时文件再替换
+    fn put(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()> {
+        let dir = self.dir(namespace);
+        std::fs::create_dir_all(&dir).map_err(|e| anyhow!("创建目录失败: {}", e))?;
+        let path = self.file(namespace, key);
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, value).map_err(|e| anyhow!("写入 {} 失败: {}", path.display(), e))?;
+        std::fs::rename(&tmp, &path).map_err(|e| anyhow!("替换 {} 失败: {}", path.display(), e))
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> Result<()> {
+        match std::fs::remove_file(self.file(namespace, key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(anyhow!("删除失败: {}", e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn scan(&self, namespace: &str, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let entries = match std::fs::read_dir(self.dir(namespace)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(anyhow!("列举目录失败: {}", e)),
+        };
+        let mut out = Vec::new();
+        for entry in entries.flatten() {
+            if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".tmp") {
+                continue;
+            }
+            let Ok(key) = urlencoding::decode(&name) else {
+                continue;
+            };
+            if !key.starts_with(prefix) {
+                continue;
+            }
+            if let Ok(data) = std::fs::read(entry.path()) {
+                out.push((key.into_owned(), data));
+            }
+        }
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(out)
+    }
+}
+
+/// SQLite 后端：kv(ns, key, value)，主键 (ns, key)
+#[cfg(feature = "sqlite")]
+pub struct SqliteStorage {
+    conn: std::sync::Mutex<rusqlite::Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStorage {
+    pub fn open(path: &std::path::Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| anyhow!("创建数据库目录失败: {}", e))?;
+        }
+        let conn =
+            rusqlite::Connection::open(path).map_err(|e| anyhow!("打开数据库失败: {}", e))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS kv (
+                 ns TEXT NOT NULL,
+                 key TEXT NOT NULL,
+                 value BLOB NOT NULL,
+                 PRIMARY KEY (ns, key)
+             );",
+        )
+        .map_err(|e| anyhow!("初始化数据库失败: {}", e))?;
+        Ok(Self {
+            conn: std::sync::Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, rusqlite::Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Storage for SqliteStorage {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        use rusqlite::OptionalExtension;
+        self.conn()
+            .query_row(
+                "SELECT value FROM kv WHERE ns = ?1 AND key = ?2",
+                rusqlite::params![namespace, key],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(|e| anyhow!("数据库读取失败: {}", e))
+    }
+
+    fn put(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()> {
+        self.conn()
+            .execute(
+                "INSERT INTO kv (ns, key, value) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (ns, key) DO UPDATE SET value = excluded.value",
+                rusqlite::params![namespace, key, value],
+            )
+            .map(|_| ())
+            .map_err(|e| anyhow!("数据库写入失败: {}", e))
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> Result<()> {
+        self.conn()
+            .execute(
+                "DELETE FROM kv WHERE ns = ?1 AND key = ?2",
+                rusqlite::params![namespace, key],
+            )
+            .map(|_| ())
+            .map_err(|e| anyhow!("数据库删除失败: {}", e))
+    }
+
+    fn scan(&self, namespace: &str, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let conn = self.conn();
+        let mut stmt = conn
+            .prepare(
+                "SELECT key, value FROM kv
+                 WHERE ns = ?1 AND substr(key, 1, length(?2)) = ?2
+                 ORDER BY key",
+            )
+            .map_err(|e| anyhow!("数据库查询失败: {}", e))?;
+        let rows = stmt
+            .query_map(rusqlite::params![namespace, prefix], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })
+            .map_err(|e| anyhow!("数据库查询失败: {}", e))?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!("数据库查询失败: {}", e))
+    }
+}
+
+static STORAGE: Lazy<RwLock<Arc<dyn Storage>>> =
+    Lazy::new(|| RwLock::new(Arc::new(FileStorage::default())));
+static CUSTOM: AtomicBool = AtomicBool::new(false);
+
+/// 嵌入方替换持久化后端（应在处理首个请求前调用）
+pub fn install(storage: Arc<dyn Storage>) {
+    *STORAGE.write().unwrap_or_else(|e| e.into_inner()) = storage;
+    CUSTOM.store(true, Ordering::SeqCst);
+}
+
+pub(crate) fn shared() -> Arc<dyn Storage> {
+    STORAGE.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// 是否已替换为自定义后端
+pub(crate) fn is_custom() -> bool {
+    CUSTOM.load(Ordering::SeqCst)
+}
+
+/// 读取 JSON 值，不存在返回 None
+pub(crate) fn get_json<T: DeserializeOwned>(namespace: &str, key: &str) -> Result<Option<T>> {
+    match shared().get(namespace, key)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| anyhow!("{}/{} 解析失败: {}", namespace, key, e)),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn put_json<T: Serialize>(namespace: &str, key: &str, value: &T) -> Result<()> {
+    shared().put(namespace, key, &serde_json::to_vec_pretty(value)?)
+}
@@ -0,0 +1,287 @@
+// OpenTelemetry 链路追踪
+//
+// 启用后为请求处理流水线生成 span，按 OTLP/HTTP JSON 批量导出到 otlp_endpoint：
+// - process_outgoing_request（根 span，若 AMP 请求带 traceparent 则延续其 trace）
+//   └ detect_route / local_tool / rewrite_body
+// - upstream.forward（代理发送时）└ upstream.attempt（每次尝试，含重试与故障转移）
+// 处理器通过 UpstreamTag.traceparent 把上下文交给转发层（不写入请求头）；
+// propagate_traceparent 开启时向上游发送标准 traceparent 头。
+// 导出在后台批量进行，失败只记 debug 日志，不影响请求。
+
+use hyper::HeaderMap as HyperHeaderMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::runtime::Handle;
+
+/// 缓冲上限，超出时丢弃最旧的 span
+const MAX_BUFFERED: usize = 2048;
+const FLUSH_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TelemetrySettings {
+    pub enabled: bool,
+    /// OTLP/HTTP 地址（导出到 {endpoint}/v1/traces）
+    pub otlp_endpoint: String,
+    /// 导出请求附加头（如鉴权）
+    pub otlp_headers: HashMap<String, String>,
+    pub service_name: String,
+    /// 向上游发送 traceparent 头
+    pub propagate_traceparent: bool,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            otlp_endpoint: "http://127.0.0.1:4318".to_string(),
+            otlp_headers: HashMap::new(),
+            service_name: "duckcoding-amp".to_string(),
+            propagate_traceparent: false,
+        }
+    }
+}
+
+/// 最近一次根 span 使用的配置（转发层据此导出与传播）
+static ACTIVE: Lazy<Mutex<Option<TelemetrySettings>>> = Lazy::new(|| Mutex::new(None));
+static BUFFER: Lazy<Mutex<Vec<Value>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static FLUSH_SCHEDULED: AtomicBool = AtomicBool::new(false);
+
+fn now_ns() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+fn random_hex(bytes: usize) -> String {
+    let raw = uuid::Uuid::new_v4().simple().to_string();
+    raw[..bytes * 2].to_string()
+}
+
+/// 解析 W3C traceparent：00-{trace_id}-{span_id}-{flags}
+fn parse_traceparent(value: &str) -> Option<(String, String)> {
+    let parts: Vec<&str> = value.trim().split('-').collect();
+    match parts.as_slice() {
+        [_, trace, span, _] if trace.len() == 32 && span.len() == 16 => {
+            Some((trace.to_string(), span.to_string()))
+        }
+        _ => None,
+    }
+}
+
+struct SpanData {
+    trace_id: String,
+    span_id: String,
+    parent_id: Option<String>,
+    name: String,
+    client: bool,
+    start_ns: u128,
+    attributes: Mutex<Vec<(String, Value)>>,
+    error: Mutex<Option<String>>,
+}
+
+/// span 句柄；未启用时为空操作，drop 时结束并进入导出缓冲
+pub(crate) struct Span {
+    data: Option<SpanData>,
+}
+
+impl Span {
+    fn start(name: &str, trace: Option<(String, String)>, client: bool) -> Self {
+        let (trace_id, parent_id) = match trace {
+            Some((t, p)) => (t, Some(p)),
+            None => (random_hex(16), None),
+        };
+        Self {
+            data: Some(SpanData {
+                trace_id,
+                span_id: random_hex(8),
+                parent_id,
+                name: name.to_string(),
+                client,
+                start_ns: now_ns(),
+                attributes: Mutex::new(Vec::new()),
+                error: Mutex::new(None),
+            }),
+        }
+    }
+
+    fn disabled() -> Self {
+        Self { data: None }
+    }
+
+    /// 处理器入口：按配置开启根 span（延续客户端 traceparent）
+    pub(crate) fn root(settings: &TelemetrySettings, name: &str, headers: &HyperHeaderMap) -> Self {
+        *ACTIVE.lock().unwrap_or_else(|e| e.into_inner()) =
+            settings.enabled.then(|| settings.clone());
+        if !settings.enabled {
+            return Self::disabled();
+        }
+        let incoming = headers
+            .get("traceparent")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_traceparent);
+        Self::start(name, incoming, false)
+    }
+
+    /// 转发层：从内部上下文头恢复（处理器未启用追踪时为空操作）
+    pub(crate) fn from_context(name: &str, context: Option<&str>) -> Self {
+        match context.and_then(parse_traceparent) {
+            Some(parent) if ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).is_some() => {
+                Self::start(name, Some(parent), false)
+            }
+            _ => Self::disabled(),
+        }
+    }
+
+    pub(crate) fn child(&self, name: &str) -> Self {
+        self.child_of_kind(name, false)
+    }
+
+    /// 对外调用（上游 HTTP 请求）
+    pub(crate) fn client_child(&self, name: &str) -> Self {
+        self.child_of_kind(name, true)
+    }
+
+    fn child_of_kind(&self, name: &str, client: bool) -> Self {
+        match &self.data {
+            Some(d) => Self::start(name, Some((d.trace_id.clone(), d.span_id.clone())), client),
+            None => Self::disabled(),
+        }
+    }
+
+    pub(crate) fn attr(&self, key: &str, value: impl Into<Value>) {
+        if let Some(d) = &self.data {
+            d.attributes
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push((key.to_string(), value.into()));
+        }
+    }
+
+    pub(crate) fn fail(&self, message: &str) {
+        if let Some(d) = &self.data {
+            *d.error.lock().unwrap_or_else(|e| e.into_inner()) = Some(message.to_string());
+        }
+    }
+
+    /// W3C traceparent（未启用时为 None）
+    pub(crate) fn traceparent(&self) -> Option<String> {
+        self.data
+            .as_ref()
+            .map(|d| format!("00-{}-{}-01", d.trace_id, d.span_id))
+    }
+
+    /// 是否需要向上游发送 traceparent
+    pub(crate) fn propagates(&self) -> bool {
+        self.data.is_some()
+            && ACTIVE
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_ref()
+                .is_some_and(|s| s.propagate_traceparent)
+    }
+}
+
+fn otlp_value(value: &Value) -> Value {
+    match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) => json!({ "doubleValue": n }),
+        Value::String(s) => json!({ "stringValue": s }),
+        other => json!({ "stringValue": other.to_string() }),
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(d) = self.data.take() else {
+            return;
+        };
+        let attributes: Vec<Value> = d
+            .attributes
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(k, v)| json!({ "key": k, "value": otlp_value(v) }))
+            .collect();
+        let status = match d.error.into_inner().unwrap_or_else(|e| e.into_inner()) {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({ "code": 1 }),
+        };
+        let mut span = json!({
+            "traceId": d.trace_id,
+            "spanId": d.span_id,
+            "name": d.name,
+            // 1 = INTERNAL，3 = CLIENT
+            "kind": if d.client { 3 } else { 1 },
+            "startTimeUnixNano": d.start_ns.to_string(),
+            "endTimeUnixNano": now_ns().to_string(),
+            "attributes": attributes,
+            "status": status,
+        });
+        if let Some(parent) = d.parent_id {
+            span["parentSpanId"] = json!(parent);
+        }
+        enqueue(span);
+    }
+}
+
+fn enqueue(span: Value) {
+    {
+        let mut buffer = BUFFER.lock().unwrap_or_else(|e| e.into_inner());
+        if buffer.len() >= MAX_BUFFERED {
+            buffer.remove(0);
+        }
+        buffer.push(span);
+    }
+    // span 可能在运行时外结束（如同步代码中 drop），此时只缓冲，下次导出时一并发送
+    let Ok(handle) = Handle::try_current() else {
+        return;
+    };
+    if FLUSH_SCHEDULED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    handle.spawn(async {
+        tokio::time::sleep(FLUSH_DELAY).await;
+        FLUSH_SCHEDULED.store(false, Ordering::SeqCst);
+        flush().await;
+    });
+}
+
+/// 立即导出缓冲中的 span
+pub(crate) async fn flush() {
+    let spans = std::mem::take(&mut *BUFFER.lock().unwrap_or_else(|e| e.into_inner()));
+    let settings = ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let Some(settings) = settings.filter(|_| !spans.is_empty()) else {
+        return;
+    };
+    let payload = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{ "key": "service.name", "value": { "stringValue": settings.service_name } }]
+            },
+            "scopeSpans": [{ "scope": { "name": "duckcoding-amp" }, "spans": spans }]
+        }]
+    });
+    let url = format!("{}/v1/traces", settings.otlp_endpoint.trim_end_matches('/'));
+    let mut request = reqwest::Client::new()
+        .post(&url)
+        .timeout(Duration::from_secs(10))
+        .json(&payload);
+    for (name, value) in &settings.otlp_headers {
+        request = request.header(name, value);
+    }
+    match request.send().await {
+        Ok(resp) if !resp.status().is_success() => {
+            tracing::debug!("OTLP 导出失败: {}", resp.status())
+        }
+        Err(e) => tracing::debug!("OTLP 导出失败: {}", e),
+        Ok(_) => {}
+    }
+}
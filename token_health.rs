@@ -0,0 +1,271 @@
+// AMP Access Token 有效性检查
+//
+// - 后台定期用已鉴权的轻量端点探测默认 Token 与多账号 Token
+// - JWT 形式的 Token 解析 exp，临近过期时告警
+// - 状态变化写入审计日志（kind=token_status），snapshot() 供管理接口展示
+// - AmpInternal 失败时由 annotate_failure 标注"Token 无效"
+// - 配置了 refresh_token 的账号检查刷新后的 Token（见 amp_oauth.rs），临近过期时顺带刷新
+
+use super::amp_auth::AmpAuthSettings;
+use super::amp_oauth;
+use super::app_state::AppState;
+use super::audit_log::{self, AuditRecord};
+use super::secret_store;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+/// 代理配置中的默认 Token 对应的账号名
+pub(crate) const DEFAULT_ACCOUNT: &str = "default";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TokenState {
+    Unknown,
+    Valid,
+    /// 有效但即将过期
+    Expiring,
+    Expired,
+    Invalid,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct TokenHealth {
+    pub account: String,
+    pub state: TokenState,
+    pub checked_at_ms: u64,
+    /// JWT exp（毫秒），非 JWT 为 None
+    pub expires_at_ms: Option<u64>,
+    pub detail: Option<String>,
+}
+
+static HEALTH: Lazy<Mutex<HashMap<String, TokenHealth>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 当前各账号 Token 状态（按账号名排序）
+pub(crate) fn snapshot() -> Vec<TokenHealth> {
+    let mut list: Vec<_> = HEALTH
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .values()
+        .cloned()
+        .collect();
+    list.sort_by(|a, b| a.account.cmp(&b.account));
+    list
+}
+
+/// 每个 AppState 首次调用时启动后台检查任务（check_interval_secs 为 0 时不启动），按 state 的配置检查；
+/// state 释放后任务自行结束。检查结果（snapshot）为进程级，多个实例按账号名写入同一份
+pub(crate) fn ensure_checker(state: &Arc<AppState>, runtime: &Handle) {
+    let interval = state.settings().amp_auth.check_interval_secs;
+    if interval == 0 || !state.claim_started("token_health") {
+        return;
+    }
+    let interval = Duration::from_secs(interval);
+    let state: Weak<AppState> = Arc::downgrade(state);
+    runtime.spawn(async move {
+        loop {
+            let Some(current) = state.upgrade() else {
+                return;
+            };
+            check_all(&current).await;
+            drop(current);
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+/// 检查所有已配置的 Token
+pub(crate) async fn check_all(state: &AppState) {
+    let processor_settings = state.settings();
+    let settings = &processor_settings.amp_auth;
+    let (base_url, default_token) = match default_config(state) {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::debug!("跳过 Token 检查: {}", e);
+            return;
+        }
+    };
+
+    let mut tokens: Vec<(String, String)> = settings
+        .accounts
+        .iter()
+        .map(|(name, token)| (name.clone(), token.clone()))
+        .collect();
+    if let Some(token) = default_token {
+        tokens.push((DEFAULT_ACCOUNT.to_string(), token));
+    }
+
+    for (account, token) in tokens {
+        let token = amp_oauth::access_token(state, &base_url, &account, token).await;
+        let client = state
+            .outbound()
+            .client_for_profile(&processor_settings, "amp");
+        let health = check_token(&client, settings, &base_url, &account, &token).await;
+        update(health);
+    }
+}
+
+fn default_config(state: &AppState) -> anyhow::Result<(String, Option<String>)> {
+    let config = state
+        .proxy_config_manager()?
+        .get_config("amp-code")
+        .map_err(|e| anyhow::anyhow!("读取配置失败: {}", e))?
+        .ok_or_else(|| anyhow::anyhow!("AMP Code 代理未配置"))?;
+    let base_url = config
+        .real_base_url
+        .unwrap_or_else(|| "https://ampcode.com".to_string());
+    Ok((base_url, secret_store::reveal_opt(config.real_api_key)))
+}
+
+async fn check_token(
+    client: &reqwest::Client,
+    settings: &AmpAuthSettings,
+    base_url: &str,
+    account: &str,
+    token: &str,
+) -> TokenHealth {
+    let now = audit_log::now_ms();
+    let expires_at_ms = jwt_expiry_ms(token);
+    let mut health = TokenHealth {
+        account: account.to_string(),
+        state: TokenState::Unknown,
+        checked_at_ms: now,
+        expires_at_ms,
+        detail: None,
+    };
+
+    if let Some(exp) = expires_at_ms {
+        if exp <= now {
+            health.state = TokenState::Expired;
+            return health;
+        }
+    }
+
+    let url = format!("{}{}", base_url.trim_end_matches('/'), settings.check_path);
+    let resp = client
+        .get(&url)
+        .bearer_auth(token)
+        .header("x-api-key", token)
+        .send()
+        .await;
+
+    match resp {
+        Ok(r) if r.status().is_success() => {
+            let warn_ms = settings.expiry_warn_days * 86_400_000;
+            health.state = match expires_at_ms {
+                Some(exp) if exp.saturating_sub(now) < warn_ms => TokenState::Expiring,
+                _ => TokenState::Valid,
+            };
+        }
+        Ok(r) if matches!(r.status().as_u16(), 401 | 403) => {
+            health.state = TokenState::Invalid;
+            health.detail = Some(format!("HTTP {}", r.status().as_u16()));
+        }
+        Ok(r) => health.detail = Some(format!("HTTP {}", r.status().as_u16())),
+        Err(e) => health.detail = Some(e.to_string()),
+    }
+    health
+}
+
+/// 写入状态，状态变化时告警并记录审计日志
+fn update(health: TokenHealth) {
+    let previous = HEALTH
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(health.account.clone(), health.clone())
+        .map(|h| h.state);
+    if previous.as_ref() == Some(&health.state) {
+        return;
+    }
+
+    match health.state {
+        TokenState::Invalid | TokenState::Expired => tracing::warn!(
+            "AMP 账号 {} 的 Access Token 无效或已过期，请重新生成",
+            health.account
+        ),
+        TokenState::Expiring => {
+            tracing::warn!("AMP 账号 {} 的 Access Token 即将过期", health.account)
+        }
+        _ => {}
+    }
+    audit_log::append(
+        &AuditRecord::new("token_status", "amp", &health.account)
+            .with("state", json!(health.state))
+            .with("expires_at_ms", json!(health.expires_at_ms))
+            .with("detail", json!(health.detail)),
+    );
+}
+
+/// Token 刷新成功后调用
+pub(crate) fn record_refreshed(account: &str, expires_at_ms: Option<u64>) {
+    update(TokenHealth {
+        account: account.to_string(),
+        state: TokenState::Valid,
+        checked_at_ms: audit_log::now_ms(),
+        expires_at_ms,
+        detail: Some("refreshed".to_string()),
+    });
+}
+
+/// AmpInternal 失败时调用：401/403 或已知 Token 失效时返回标注说明，并更新状态
+pub(crate) fn annotate_failure(account: Option<&str>, status: u16) -> Option<String> {
+    let account = account.unwrap_or(DEFAULT_ACCOUNT);
+    if matches!(status, 401 | 403) {
+        update(TokenHealth {
+            account: account.to_string(),
+            state: TokenState::Invalid,
+            checked_at_ms: audit_log::now_ms(),
+            expires_at_ms: None,
+            detail: Some(format!("HTTP {}", status)),
+        });
+    }
+    let state = HEALTH
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(account)
+        .map(|h| h.state.clone())?;
+    match state {
+        TokenState::Invalid | TokenState::Expired => Some(format!(
+            "token invalid: AMP 账号 {} 的 Access Token 无效或已过期",
+            account
+        )),
+        _ => None,
+    }
+}
+
+/// 解析 JWT payload 中的 exp（秒）并转为毫秒
+pub(crate) fn jwt_expiry_ms(token: &str) -> Option<u64> {
+    let mut parts = token.split('.');
+    let (_, payload, _) = (parts.next()?, parts.next()?, parts.next()?);
+    let decoded = base64url_decode(payload)?;
+    let claims: Value = serde_json::from_slice(&decoded).ok()?;
+    claims["exp"].as_u64().map(|s| s * 1000)
+}
+
+pub(crate) fn base64url_decode(input: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in input.bytes().filter(|c| *c != b'=') {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' | b'+' => 62,
+            b'_' | b'/' => 63,
+            _ => return None,
+        } as u32;
+        acc = (acc << 6) | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,313 @@
+// tool_result 合批（Claude 路由）
+//
+// AMP 有时把同一轮并行工具调用的结果拆成多个请求依次发送。启用后：
+// - 最后一条 user 消息只含小体积 tool_result 的请求先等待 debounce_ms
+// - 等待期间同一轮（同会话、同一组 tool_use）的新请求到达时，旧请求的结果并入新请求，
+//   旧请求以本地空回复结束（响应头 x-dc-batched: superseded），不再发往上游
+// - 最后到达的请求携带全部结果，作为一次上游调用发出
+// 减少请求次数与缓存前缀的重复读取。默认关闭。
+
+use super::outcome::ProcessOutcome;
+use super::transform_middleware::{estimate_tokens, generate_session_uuid};
+use hyper::HeaderMap as HyperHeaderMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 被合并请求的标记响应头
+pub(crate) const BATCHED_HEADER: &str = "x-dc-batched";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolBatchSettings {
+    pub enabled: bool,
+    /// 等待后续 tool_result 的时间（毫秒）
+    pub debounce_ms: u64,
+    /// 单个 tool_result 超过该 token 数时不等待（大结果直接发送）
+    pub max_result_tokens: usize,
+}
+
+impl Default for ToolBatchSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            debounce_ms: 150,
+            max_result_tokens: 500,
+        }
+    }
+}
+
+pub(crate) enum BatchOutcome {
+    /// 继续转发；Some 为合并了其他请求结果的新请求体
+    Forward(Option<Vec<u8>>),
+    /// 已被同轮后续请求合并，直接返回本地响应
+    Superseded(Box<ProcessOutcome>),
+}
+
+#[derive(Default)]
+struct PendingTurn {
+    generation: u64,
+    /// 已收到的 tool_result（按到达顺序，tool_use_id 去重）
+    results: Vec<Value>,
+}
+
+static PENDING: Lazy<Mutex<HashMap<String, PendingTurn>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub(crate) async fn coalesce(settings: &ToolBatchSettings, body: &[u8]) -> BatchOutcome {
+    if !settings.enabled || body.is_empty() {
+        return BatchOutcome::Forward(None);
+    }
+    let Ok(json) = serde_json::from_slice::<Value>(body) else {
+        return BatchOutcome::Forward(None);
+    };
+    let Some((key, results)) = batch_key(settings, &json) else {
+        return BatchOutcome::Forward(None);
+    };
+
+    let my_generation = {
+        let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+        let turn = pending.entry(key.clone()).or_default();
+        turn.generation += 1;
+        for result in results {
+            let id = result["tool_use_id"].as_str();
+            if !turn.results.iter().any(|r| r["tool_use_id"].as_str() == id) {
+                turn.results.push(result);
+            }
+        }
+        turn.generation
+    };
+
+    tokio::time::sleep(Duration::from_millis(settings.debounce_ms)).await;
+
+    let merged = {
+        let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+        match pending.get(&key) {
+            Some(turn) if turn.generation != my_generation => {
+                tracing::debug!("tool_result 合批: 请求已并入同轮后续请求");
+                return BatchOutcome::Superseded(Box::new(superseded_response(&json)));
+            }
+            _ => pending.remove(&key).map(|t| t.results).unwrap_or_default(),
+        }
+    };
+
+    BatchOutcome::Forward(merge_results(json, merged))
+}
+
+/// 符合合批条件时返回 (同轮标识, 本请求的 tool_result 列表)
+fn batch_key(settings: &ToolBatchSettings, json: &Value) -> Option<(String, Vec<Value>)> {
+    let messages = json["messages"].as_array()?;
+    let (last, history) = messages.split_last()?;
+    if last["role"].as_str() != Some("user") {
+        return None;
+    }
+    let blocks = last["content"].as_array()?;
+    let all_small_results = !blocks.is_empty()
+        && blocks.iter().all(|b| {
+            b["type"].as_str() == Some("tool_result")
+                && estimate_tokens(&b["content"].to_string()) <= settings.max_result_tokens
+        });
+    if !all_small_results {
+        return None;
+    }
+
+    let assistant = history.last()?;
+    let mut tool_use_ids: Vec<&str> = assistant["content"]
+        .as_array()?
+        .iter()
+        .filter(|b| b["type"].as_str() == Some("tool_use"))
+        .filter_map(|b| b["id"].as_str())
+        .collect();
+    // 单个调用无需等待
+    if tool_use_ids.len() < 2 {
+        return None;
+    }
+    tool_use_ids.sort_unstable();
+
+    let key = format!(
+        "{}:{}",
+        generate_session_uuid(&json["messages"]),
+        tool_use_ids.join(",")
+    );
+    Some((key, blocks.clone()))
+}
+
+/// 用合并后的结果替换最后一条 user 消息，返回 None 表示无新增结果
+fn merge_results(mut json: Value, merged: Vec<Value>) -> Option<Vec<u8>> {
+    let last = json["messages"].as_array_mut()?.last_mut()?;
+    let current = last["content"].as_array()?.len();
+    if merged.len() <= current {
+        return None;
+    }
+    tracing::debug!(
+        "tool_result 合批: 合并 {} 个结果为一次上游请求",
+        merged.len()
+    );
+    last["content"] = Value::Array(merged);
+    serde_json::to_vec(&json).ok()
+}
+
+/// 被合并请求的本地空回复
+fn superseded_response(request: &Value) -> ProcessOutcome {
+    let model = request["model"].as_str().unwrap_or("");
+    let message = json!({
+        "id": "msg_batched",
+        "type": "message",
+        "role": "assistant",
+        "model": model,
+        "content": [],
+        "stop_reason": "end_turn",
+        "stop_sequence": null,
+        "usage": { "input_tokens": 0, "output_tokens": 0 }
+    });
+
+    let mut headers = HyperHeaderMap::new();
+    headers.insert(BATCHED_HEADER, "superseded".parse().unwrap());
+    let body = if request["stream"].as_bool() == Some(true) {
+        headers.insert("content-type", "text/event-stream".parse().unwrap());
+        let mut start = message.clone();
+        start["stop_reason"] = Value::Null;
+        format!(
+            "event: message_start\ndata: {}\n\n\
+             event: message_delta\ndata: {}\n\n\
+             event: message_stop\ndata: {}\n\n",
+            json!({ "type": "message_start", "message": start }),
+            json!({ "type": "message_delta", "delta": { "stop_reason": "end_turn", "stop_sequence": null }, "usage": { "output_tokens": 0 } }),
+            json!({ "type": "message_stop" })
+        )
+    } else {
+        headers.insert("content-type", "application/json".parse().unwrap());
+        message.to_string()
+    };
+
+    ProcessOutcome::local("tool-batch", headers, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::outcome::LocalBody;
+    use super::*;
+
+    fn settings() -> ToolBatchSettings {
+        ToolBatchSettings {
+            enabled: true,
+            debounce_ms: 80,
+            ..Default::default()
+        }
+    }
+
+    /// 同一轮两个并行调用（t1 / t2）之后、只携带 results 的请求体
+    fn turn(prompt: &str, results: &[(&str, &str)]) -> Value {
+        json!({
+            "model": "claude-sonnet-4",
+            "messages": [
+                { "role": "user", "content": prompt },
+                { "role": "assistant", "content": [
+                    { "type": "tool_use", "id": "t2", "name": "read", "input": {} },
+                    { "type": "tool_use", "id": "t1", "name": "read", "input": {} }
+                ]},
+                { "role": "user", "content": results
+                    .iter()
+                    .map(|(id, text)| json!({ "type": "tool_result", "tool_use_id": id, "content": text }))
+                    .collect::<Vec<_>>() }
+            ]
+        })
+    }
+
+    #[test]
+    fn only_small_results_of_parallel_calls_wait() {
+        let s = settings();
+        let (key, results) = batch_key(&s, &turn("key", &[("t1", "ok")])).unwrap();
+        assert!(key.ends_with(":t1,t2"));
+        assert_eq!(results.len(), 1);
+
+        // 大结果直接发送
+        let big = "x".repeat(s.max_result_tokens * 8);
+        assert!(batch_key(&s, &turn("key", &[("t1", &big)])).is_none());
+        // 单个调用无需等待
+        let mut single = turn("key", &[("t1", "ok")]);
+        single["messages"][1]["content"]
+            .as_array_mut()
+            .unwrap()
+            .remove(0);
+        assert!(batch_key(&s, &single).is_none());
+        // 最后一条不是纯 tool_result
+        let mut mixed = turn("key", &[("t1", "ok")]);
+        mixed["messages"][2]["content"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({ "type": "text", "text": "and" }));
+        assert!(batch_key(&s, &mixed).is_none());
+    }
+
+    #[tokio::test]
+    async fn later_request_absorbs_earlier_results() {
+        let s = settings();
+        let first = serde_json::to_vec(&turn("absorb", &[("t1", "one")])).unwrap();
+        let second = serde_json::to_vec(&turn("absorb", &[("t2", "two")])).unwrap();
+        let (earlier, later) = tokio::join!(coalesce(&s, &first), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            coalesce(&s, &second).await
+        });
+
+        let BatchOutcome::Superseded(outcome) = earlier else {
+            panic!("先到的请求应被合并");
+        };
+        let ProcessOutcome::LocalResponse { headers, body, .. } = *outcome else {
+            panic!("应为本地响应");
+        };
+        assert_eq!(headers[BATCHED_HEADER], "superseded");
+        let LocalBody::Full(body) = body else {
+            panic!("非流式请求应为完整响应体");
+        };
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["content"], json!([]));
+
+        let BatchOutcome::Forward(Some(merged)) = later else {
+            panic!("后到的请求应携带全部结果发出");
+        };
+        let merged: Value = serde_json::from_slice(&merged).unwrap();
+        let ids: Vec<&str> = merged["messages"][2]["content"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|b| b["tool_use_id"].as_str())
+            .collect();
+        assert_eq!(ids, ["t1", "t2"]);
+    }
+
+    #[tokio::test]
+    async fn disabled_or_lone_request_forwards_unchanged() {
+        let body = serde_json::to_vec(&turn("lone", &[("t1", "one")])).unwrap();
+        let disabled = ToolBatchSettings::default();
+        assert!(matches!(
+            coalesce(&disabled, &body).await,
+            BatchOutcome::Forward(None)
+        ));
+        // 等待期间没有后续请求：原样发出
+        assert!(matches!(
+            coalesce(&settings(), &body).await,
+            BatchOutcome::Forward(None)
+        ));
+    }
+
+    #[test]
+    fn superseded_stream_reply_is_complete_sse() {
+        let mut request = turn("sse", &[("t1", "one")]);
+        request["stream"] = json!(true);
+        let ProcessOutcome::LocalResponse { headers, body, .. } = superseded_response(&request)
+        else {
+            panic!("应为本地响应");
+        };
+        assert_eq!(headers["content-type"], "text/event-stream");
+        let LocalBody::Full(body) = body else {
+            panic!("应为完整响应体");
+        };
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.starts_with("event: message_start\n"));
+        assert!(text.ends_with("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n"));
+    }
+}
@@ -0,0 +1,218 @@
+// 本地工具结果缓存（webSearch2 / extractWebPageContent）
+//
+// Agent 循环中常重复同一搜索或页面，缓存避免重复消耗 Tavily 等额度与重复下载：
+// - 键为工具名 + 请求 params 的 SHA-256，命中时直接返回上次的本地工具应答
+// - 内存 LRU（max_entries）+ 按工具区分的 TTL：搜索结果较短，页面内容较长
+// - persist 开启时同时写入存储后端 amp/tool_cache 命名空间（见 storage.rs），重启后仍可命中；
+//   首次使用时清理已过期的持久化条目，LRU 淘汰时同步删除
+// 仅缓存成功应答（ok=true）。
+
+use super::audit_log;
+use super::storage;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const NAMESPACE: &str = "amp/tool_cache";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolCacheSettings {
+    pub enabled: bool,
+    /// 内存中最多保留的条目数
+    pub max_entries: usize,
+    /// webSearch2 结果缓存时间（秒）
+    pub search_ttl_secs: u64,
+    /// extractWebPageContent 结果缓存时间（秒）
+    pub extract_ttl_secs: u64,
+    /// 是否持久化到存储后端
+    pub persist: bool,
+}
+
+impl Default for ToolCacheSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_entries: 256,
+            search_ttl_secs: 3600,
+            extract_ttl_secs: 86_400,
+            persist: false,
+        }
+    }
+}
+
+impl ToolCacheSettings {
+    fn ttl_ms(&self, tool: &str) -> u64 {
+        let secs = match tool {
+            "webSearch2" => self.search_ttl_secs,
+            _ => self.extract_ttl_secs,
+        };
+        secs.saturating_mul(1000)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    tool: String,
+    stored_at_ms: u64,
+    value: Value,
+    #[serde(skip)]
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, Entry>,
+    clock: u64,
+    pruned: bool,
+}
+
+static CACHE: Lazy<Mutex<Lru>> = Lazy::new(|| Mutex::new(Lru::default()));
+
+/// 缓存键：工具名 + 请求参数
+pub(crate) fn key(tool: &str, params: &Value) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(tool.as_bytes());
+    hasher.update([0]);
+    hasher.update(params.to_string().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// 清理已过期的持久化条目（每个进程一次）
+fn prune_persisted(settings: &ToolCacheSettings, now: u64) {
+    let entries = match storage::shared().scan(NAMESPACE, "") {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!("扫描工具缓存失败: {}", e);
+            return;
+        }
+    };
+    for (key, raw) in entries {
+        let expired = serde_json::from_slice::<Entry>(&raw)
+            .map(|e| now.saturating_sub(e.stored_at_ms) >= settings.ttl_ms(&e.tool))
+            .unwrap_or(true);
+        if expired {
+            if let Err(e) = storage::shared().delete(NAMESPACE, &key) {
+                tracing::warn!("删除过期工具缓存失败: {}", e);
+            }
+        }
+    }
+}
+
+/// 查询缓存，未命中或已过期返回 None
+pub(crate) fn get(settings: &ToolCacheSettings, tool: &str, key: &str) -> Option<Value> {
+    if !settings.enabled {
+        return None;
+    }
+    let now = audit_log::now_ms();
+    let ttl = settings.ttl_ms(tool);
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if settings.persist && !cache.pruned {
+        cache.pruned = true;
+        prune_persisted(settings, now);
+    }
+    cache.clock += 1;
+    let clock = cache.clock;
+
+    if let Some(entry) = cache.entries.get_mut(key) {
+        if now.saturating_sub(entry.stored_at_ms) < ttl {
+            entry.last_used = clock;
+            return Some(entry.value.clone());
+        }
+        cache.entries.remove(key);
+    }
+
+    if !settings.persist {
+        return None;
+    }
+    let mut entry: Entry = match storage::get_json(NAMESPACE, key) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return None,
+        Err(e) => {
+            tracing::warn!("读取工具缓存失败: {}", e);
+            return None;
+        }
+    };
+    if now.saturating_sub(entry.stored_at_ms) >= ttl {
+        return None;
+    }
+    entry.last_used = clock;
+    let value = entry.value.clone();
+    insert(&mut cache, settings, key.to_string(), entry);
+    Some(value)
+}
+
+fn insert(cache: &mut Lru, settings: &ToolCacheSettings, key: String, entry: Entry) {
+    while cache.entries.len() >= settings.max_entries.max(1) {
+        let Some(oldest) = cache
+            .entries
+            .iter()
+            .min_by_key(|(_, e)| e.last_used)
+            .map(|(k, _)| k.clone())
+        else {
+            break;
+        };
+        cache.entries.remove(&oldest);
+        if settings.persist {
+            if let Err(e) = storage::shared().delete(NAMESPACE, &oldest) {
+                tracing::warn!("删除工具缓存失败: {}", e);
+            }
+        }
+    }
+    cache.entries.insert(key, entry);
+}
+
+/// 写入缓存（仅成功应答）
+pub(crate) fn put(settings: &ToolCacheSettings, tool: &str, key: &str, value: &Value) {
+    if !settings.enabled || settings.ttl_ms(tool) == 0 || value["ok"] != true {
+        return;
+    }
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache.clock += 1;
+    let entry = Entry {
+        tool: tool.to_string(),
+        stored_at_ms: audit_log::now_ms(),
+        value: value.clone(),
+        last_used: cache.clock,
+    };
+    if settings.persist {
+        if let Err(e) = storage::put_json(NAMESPACE, key, &entry) {
+            tracing::warn!("写入工具缓存失败: {}", e);
+        }
+    }
+    insert(&mut cache, settings, key.to_string(), entry);
+}
+
+/// 内存中的条目数
+pub(crate) fn len() -> usize {
+    CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entries
+        .len()
+}
+
+/// 清空内存与持久化条目，返回清除的内存条目数
+#[cfg(feature = "admin")]
+pub(crate) fn clear() -> usize {
+    let n = {
+        let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        let n = cache.entries.len();
+        cache.entries.clear();
+        n
+    };
+    match storage::shared().scan(NAMESPACE, "") {
+        Ok(entries) => {
+            for (key, _) in entries {
+                if let Err(e) = storage::shared().delete(NAMESPACE, &key) {
+                    tracing::warn!("删除工具缓存失败: {}", e);
+                }
+            }
+        }
+        Err(e) => tracing::warn!("扫描工具缓存失败: {}", e),
+    }
+    n
+}
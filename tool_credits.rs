@@ -0,0 +1,101 @@
+// 本地工具费用核算
+//
+// 本地工具会消耗外部额度：搜索 API 按次计费，答案综合 / 摘要 / 翻译消耗廉价模型 token。
+// - 按次费用：搜索提供方与外部提取服务每次成功调用按 provider_costs_usd 计价（未列出的为 0），
+//   记入 slot 槽位的 Profile 预算（见 budget.rs，只计入美元上限）
+// - 模型 token：廉价模型调用本身已按其槽位记入预算，这里只累计金额用于应答
+// 每次工具调用在 scope 内累计费用，应答的 creditsConsumed 为本次调用的美元金额（命中缓存为 0）。
+
+use super::budget::{self, BudgetSettings};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::future::Future;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolCreditSettings {
+    /// 按次费用计入的预算槽位
+    pub slot: String,
+    /// 提供方标签 → 单次调用美元价格
+    pub provider_costs_usd: HashMap<String, f64>,
+}
+
+impl Default for ToolCreditSettings {
+    fn default() -> Self {
+        Self {
+            slot: "claude".to_string(),
+            provider_costs_usd: [
+                ("tavily", 0.008),
+                ("brave", 0.005),
+                ("jina", 0.0),
+                ("firecrawl", 0.001),
+            ]
+            .iter()
+            .map(|(k, v)| (k.to_string(), *v))
+            .collect(),
+        }
+    }
+}
+
+tokio::task_local! {
+    /// 当前工具调用累计的美元费用
+    static CALL_COST: Cell<f64>;
+}
+
+/// 在费用累计作用域内执行一次工具调用，返回 (结果, 美元费用)
+pub(crate) async fn scope<T, F>(fut: F) -> (T, f64)
+where
+    F: Future<Output = T>,
+{
+    CALL_COST
+        .scope(Cell::new(0.0), async {
+            let output = fut.await;
+            (output, current())
+        })
+        .await
+}
+
+/// 当前作用域已累计的费用（不在作用域内时为 0）
+pub(crate) fn current() -> f64 {
+    CALL_COST.try_with(Cell::get).unwrap_or(0.0)
+}
+
+fn add(usd: f64) {
+    let _ = CALL_COST.try_with(|c| c.set(c.get() + usd));
+}
+
+/// 记一次外部服务的成功调用（按提供方单价）
+pub(crate) fn charge_call(settings: &ToolCreditSettings, tool: &str, provider: &str) {
+    let Some(usd) = settings
+        .provider_costs_usd
+        .get(provider)
+        .copied()
+        .filter(|c| *c > 0.0)
+    else {
+        return;
+    };
+    add(usd);
+    budget::record_tool_cost(&settings.slot, tool, usd);
+}
+
+/// 记一次廉价模型调用的 token 费用（按该槽位的预算价格表；预算已由调用方记入）
+pub(crate) fn charge_tokens(
+    budget: &BudgetSettings,
+    model: &str,
+    input_tokens: u64,
+    output_tokens: u64,
+) {
+    add(budget::cost_usd(budget, model, input_tokens, output_tokens));
+}
+
+/// creditsConsumed 字段值
+pub(crate) fn format_credits(usd: f64) -> String {
+    if usd <= 0.0 {
+        return "0".to_string();
+    }
+    format!("{:.6}", usd)
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
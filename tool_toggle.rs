@@ -0,0 +1,138 @@
+// 按工具禁用本地工具
+//
+// tool_toggle.disabled 中的工具（如 httpRequest、getWeather）不再由代理处理：
+// - AMP 直接调用时返回错误，不访问外部服务
+// - AmpInternal 中列出可用服务端工具的接口（list_methods，按 query 键匹配，形式同本地工具）
+//   由代理取回上游响应，删除被禁用的工具后以本地响应返回，AMP 不会再调用它们
+// 工具列表的删除规则：数组中等于工具名的字符串、name / toolName / id 等于工具名的对象，
+// 以及键为工具名的对象字段。上游失败或响应不是 JSON 时回退为正常转发。
+
+use super::amp_oauth;
+use super::app_state::AppState;
+use super::outcome::ProcessOutcome;
+use super::ProcessedRequest;
+use hyper::HeaderMap as HyperHeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolToggleSettings {
+    /// 禁用的本地工具名
+    pub disabled: Vec<String>,
+    /// 返回可用工具列表的 AmpInternal 方法（query 键）
+    pub list_methods: Vec<String>,
+}
+
+impl Default for ToolToggleSettings {
+    fn default() -> Self {
+        Self {
+            disabled: Vec::new(),
+            list_methods: vec![
+                "getServerTools".to_string(),
+                "listServerTools".to_string(),
+                "getTools".to_string(),
+            ],
+        }
+    }
+}
+
+impl ToolToggleSettings {
+    pub(crate) fn is_disabled(&self, tool: &str) -> bool {
+        self.disabled.iter().any(|t| t == tool)
+    }
+
+    /// 请求是否为需要改写的工具列表接口
+    pub(crate) fn is_tool_list(&self, query: Option<&str>) -> bool {
+        if self.disabled.is_empty() {
+            return false;
+        }
+        query.is_some_and(|q| {
+            q.split('&')
+                .map(|part| part.split('=').next().unwrap_or(part))
+                .any(|key| self.list_methods.iter().any(|m| m == key))
+        })
+    }
+}
+
+fn is_disabled_entry(settings: &ToolToggleSettings, value: &Value) -> bool {
+    match value {
+        Value::String(name) => settings.is_disabled(name),
+        Value::Object(map) => ["name", "toolName", "id"]
+            .iter()
+            .filter_map(|k| map.get(*k).and_then(Value::as_str))
+            .any(|name| settings.is_disabled(name)),
+        _ => false,
+    }
+}
+
+/// 从工具列表中删除被禁用的工具，返回删除的条目数
+fn strip_disabled(settings: &ToolToggleSettings, value: &mut Value) -> usize {
+    match value {
+        Value::Array(items) => {
+            let before = items.len();
+            items.retain(|item| !is_disabled_entry(settings, item));
+            let removed = before - items.len();
+            removed
+                + items
+                    .iter_mut()
+                    .map(|item| strip_disabled(settings, item))
+                    .sum::<usize>()
+        }
+        Value::Object(map) => {
+            let before = map.len();
+            map.retain(|key, v| !(settings.is_disabled(key) && (v.is_object() || v.is_boolean())));
+            let removed = before - map.len();
+            removed
+                + map
+                    .values_mut()
+                    .map(|v| strip_disabled(settings, v))
+                    .sum::<usize>()
+        }
+        _ => 0,
+    }
+}
+
+/// 取回上游工具列表并删除被禁用的工具；返回 None 表示应正常转发
+pub(crate) async fn fetch_filtered(
+    state: &AppState,
+    settings: &ToolToggleSettings,
+    forward: &ProcessedRequest,
+) -> Option<ProcessOutcome> {
+    let method = if forward.body.is_empty() {
+        reqwest::Method::GET
+    } else {
+        reqwest::Method::POST
+    };
+    let resp = match amp_oauth::send(
+        state,
+        method,
+        &forward.target_url,
+        forward.headers.clone(),
+        forward.body.clone(),
+    )
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::debug!("获取 AMP 工具列表失败，回退正常转发: {}", e);
+            return None;
+        }
+    };
+    if !resp.status().is_success() {
+        tracing::debug!("AMP 工具列表返回 {}，回退正常转发", resp.status());
+        return None;
+    }
+    let raw = resp.bytes().await.ok()?;
+    let mut data: Value = serde_json::from_slice(&raw).ok()?;
+    let removed = strip_disabled(settings, &mut data);
+    tracing::info!("AMP 工具列表: 删除 {} 个已禁用的工具", removed);
+
+    let mut headers = HyperHeaderMap::new();
+    headers.insert("content-type", "application/json".parse().unwrap());
+    Some(ProcessOutcome::local(
+        "amp-tool-list",
+        headers,
+        serde_json::to_vec(&data).ok()?,
+    ))
+}
@@ -0,0 +1,531 @@
+// 请求体转换中间件
+//
+// 从 AMP 路由中抽出的通用转换，AMP 的 Claude / Codex / Gemini 分支共用：
+// - metadata.user_id 注入（Claude）
+// - cache_control 统一为 5m ttl（Claude）
+// - 模型别名（全部，Gemini 作用于路径中的模型名）
+// - 过期工具输出省略（Claude tool_result / Codex function_call_output）
+// - 消息顺序修复（Claude，见 message_repair）
+// 是否启用由 ProcessorSettings 按 tool_id 配置（transforms_for）。
+// 直连的 Claude / Codex / Gemini 处理器不在本仓库内，未接入这些转换。
+
+use super::determinism;
+use super::log_redact::redact;
+use super::message_repair;
+use super::processor_settings::TransformSettings;
+use super::user_fingerprint::{self, UserHashAlgorithm};
+use anyhow::{anyhow, Result};
+use hyper::HeaderMap as HyperHeaderMap;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+/// 低于该 token 数的工具输出不省略（占位符本身也有开销）
+const DEFAULT_ELIDE_MIN_TOKENS: usize = 200;
+
+/// 转换目标协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TransformTarget {
+    Claude,
+    Codex,
+    Gemini,
+}
+
+/// 应用转换，返回 None 表示请求体无需改写
+pub(crate) fn apply(
+    settings: &TransformSettings,
+    target: TransformTarget,
+    headers: &HyperHeaderMap,
+    api_key: &str,
+    body: &[u8],
+) -> Option<Vec<u8>> {
+    if body.is_empty() {
+        return None;
+    }
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    let mut modified = false;
+
+    if apply_model_alias(&mut json, &settings.model_aliases) {
+        modified = true;
+    }
+
+    if target == TransformTarget::Claude
+        && settings.repair_message_order
+        && !message_repair::repair(&mut json).is_empty()
+    {
+        modified = true;
+    }
+
+    if let Some(turns) = settings.elide_tool_results_after {
+        let min_tokens = settings
+            .elide_min_tokens
+            .unwrap_or(DEFAULT_ELIDE_MIN_TOKENS);
+        if elide_stale_tool_results(&mut json, turns, min_tokens) > 0 {
+            modified = true;
+        }
+    }
+
+    if target == TransformTarget::Claude && settings.normalize_cache_control {
+        normalize_cache_control_all(&mut json);
+        modified = true;
+    }
+
+    let inject_metadata = target == TransformTarget::Claude && settings.inject_metadata;
+    if !modified && !inject_metadata {
+        return None;
+    }
+
+    let out = serde_json::to_vec(&json).ok()?;
+    if inject_metadata {
+        return Some(ensure_metadata_user_id(
+            &out,
+            headers,
+            api_key,
+            settings.user_hash,
+        ));
+    }
+    Some(out)
+}
+
+/// 将请求体 model 字段替换为别名目标，返回是否发生替换
+pub(crate) fn apply_model_alias(
+    json: &mut Value,
+    aliases: &std::collections::HashMap<String, String>,
+) -> bool {
+    if aliases.is_empty() {
+        return false;
+    }
+    let Some(model) = json.get("model").and_then(|m| m.as_str()) else {
+        return false;
+    };
+    let Some(target) = aliases.get(model) else {
+        return false;
+    };
+    tracing::debug!("模型别名: {} → {}", model, target);
+    json["model"] = Value::String(target.clone());
+    true
+}
+
+/// Gemini 路径中的模型别名：/v1beta/models/{model}:xxx
+pub(crate) fn apply_model_alias_to_path(
+    path: &str,
+    aliases: &std::collections::HashMap<String, String>,
+) -> String {
+    let Some(start) = path.find("/models/") else {
+        return path.to_string();
+    };
+    let model_start = start + "/models/".len();
+    let after = &path[model_start..];
+    let end = after.find([':', '/', '?']).unwrap_or(after.len());
+    match aliases.get(&after[..end]) {
+        Some(target) => format!("{}{}{}", &path[..model_start], target, &after[end..]),
+        None => path.to_string(),
+    }
+}
+
+/// 粗略 token 估算：CJK 字符按 1 token，其余按 4 字符 1 token
+pub(crate) fn estimate_tokens(text: &str) -> usize {
+    let (cjk, other) = text.chars().fold((0usize, 0usize), |(c, o), ch| {
+        if ch as u32 >= 0x2E80 {
+            (c + 1, o)
+        } else {
+            (c, o + 1)
+        }
+    });
+    cjk + other.div_ceil(4)
+}
+
+/// 将最近 N 个用户轮次之前的工具输出替换为占位符，返回省略条数
+/// 只替换内容，保留 tool_use_id / call_id 与块类型，配对关系不受影响
+pub(crate) fn elide_stale_tool_results(
+    json: &mut Value,
+    keep_turns: usize,
+    min_tokens: usize,
+) -> usize {
+    // Claude: messages[]；Codex Responses: input[]
+    let key = if json.get("messages").is_some() {
+        "messages"
+    } else {
+        "input"
+    };
+    let Some(items) = json.get_mut(key).and_then(|m| m.as_array_mut()) else {
+        return 0;
+    };
+
+    // 从末尾数 keep_turns 个"真实"用户轮次（纯 tool_result 的 user 消息不算一轮）
+    let mut turns = 0;
+    let mut cutoff = 0;
+    for (idx, item) in items.iter().enumerate().rev() {
+        if is_user_turn(item) {
+            turns += 1;
+            if turns == keep_turns {
+                cutoff = idx;
+                break;
+            }
+        }
+    }
+    if turns < keep_turns {
+        return 0;
+    }
+
+    let mut elided = 0;
+    for item in items[..cutoff].iter_mut() {
+        // Codex: { type: function_call_output, call_id, output }
+        if item.get("type").and_then(|t| t.as_str()) == Some("function_call_output") {
+            if let Some(placeholder) = elision_placeholder(&item["output"], min_tokens) {
+                item["output"] = Value::String(placeholder);
+                elided += 1;
+            }
+            continue;
+        }
+        // Claude: { role: user, content: [{ type: tool_result, tool_use_id, content }] }
+        let Some(content) = item.get_mut("content").and_then(|c| c.as_array_mut()) else {
+            continue;
+        };
+        for block in content.iter_mut() {
+            if block.get("type").and_then(|t| t.as_str()) != Some("tool_result") {
+                continue;
+            }
+            let Some(placeholder) = elision_placeholder(&block["content"], min_tokens) else {
+                continue;
+            };
+            block["content"] = Value::String(placeholder);
+            elided += 1;
+        }
+    }
+
+    if elided > 0 {
+        tracing::debug!(
+            "省略过期工具输出: {} 条（保留最近 {} 轮）",
+            elided,
+            keep_turns
+        );
+    }
+    elided
+}
+
+fn is_user_turn(item: &Value) -> bool {
+    if item.get("role").and_then(|r| r.as_str()) != Some("user") {
+        return false;
+    }
+    match item.get("content") {
+        Some(Value::Array(blocks)) => blocks
+            .iter()
+            .any(|b| b.get("type").and_then(|t| t.as_str()) != Some("tool_result")),
+        _ => true,
+    }
+}
+
+/// 生成占位符；内容过短或已是占位符时返回 None
+fn elision_placeholder(content: &Value, min_tokens: usize) -> Option<String> {
+    let text = match content {
+        Value::String(s) => s.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => return None,
+    };
+    if text.starts_with("[tool output elided") {
+        return None;
+    }
+    let tokens = estimate_tokens(&text);
+    if tokens < min_tokens {
+        return None;
+    }
+    let size = if tokens >= 1000 {
+        format!("{:.1}k", tokens as f64 / 1000.0)
+    } else {
+        tokens.to_string()
+    };
+    Some(format!("[tool output elided, {} tokens]", size))
+}
+
+/// 统一 cache_control 为标准 5m ttl
+pub(crate) fn normalize_cache_control(item: &mut Value) {
+    if let Some(obj) = item.as_object_mut() {
+        if obj.contains_key("cache_control") {
+            obj.insert(
+                "cache_control".to_string(),
+                json!({ "type": "ephemeral", "ttl": "5m" }),
+            );
+        }
+    }
+}
+
+/// 对 system / tools / messages[].content[] 统一 cache_control
+pub(crate) fn normalize_cache_control_all(json: &mut Value) {
+    if let Some(items) = json.get_mut("system").and_then(|s| s.as_array_mut()) {
+        items.iter_mut().for_each(normalize_cache_control);
+    }
+    if let Some(tools) = json.get_mut("tools").and_then(|t| t.as_array_mut()) {
+        tools.iter_mut().for_each(normalize_cache_control);
+    }
+    if let Some(messages) = json.get_mut("messages").and_then(|m| m.as_array_mut()) {
+        for msg in messages.iter_mut() {
+            if let Some(arr) = msg.get_mut("content").and_then(|c| c.as_array_mut()) {
+                arr.iter_mut().for_each(normalize_cache_control);
+            }
+        }
+    }
+}
+
+/// 检查并注入 metadata.user_id（已有非空 user_id 时保持原样）
+pub(crate) fn ensure_metadata_user_id(
+    body: &[u8],
+    headers: &HyperHeaderMap,
+    api_key: &str,
+    algorithm: UserHashAlgorithm,
+) -> Vec<u8> {
+    let Ok(json) = serde_json::from_slice::<Value>(body) else {
+        return body.to_vec();
+    };
+
+    let has_user_id = json
+        .get("metadata")
+        .and_then(|m| m.get("user_id"))
+        .and_then(|u| u.as_str())
+        .map(|s| !s.is_empty())
+        .unwrap_or(false);
+    if has_user_id {
+        return body.to_vec();
+    }
+
+    // 生成 user_id: user_{64位hex}_account__session_{uuid}
+    let user_hash = generate_user_hash(headers, api_key, algorithm);
+    let session_uuid = generate_session_uuid(&json["messages"]);
+    let user_id = format!("user_{}_account__session_{}", user_hash, session_uuid);
+
+    tracing::debug!("生成 user_id: {}", redact(&user_id));
+
+    // 注入并保持字段顺序
+    inject_metadata_with_order(body, &user_id).unwrap_or_else(|_| body.to_vec())
+}
+
+/// 生成 64 位 hex 用户指纹：SHA256 / HMAC(标识 + UA)
+/// 标识默认为完整 API Key（避免不同 key 碰撞），也可为 Profile opaque ID；UA 作为辅助区分
+pub(crate) fn generate_user_hash(
+    headers: &HyperHeaderMap,
+    api_key: &str,
+    algorithm: UserHashAlgorithm,
+) -> String {
+    let ua = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("unknown");
+    user_fingerprint::hash(algorithm, api_key, ua)
+}
+
+/// 生成 UUID 格式会话标识
+/// - 有消息内容：基于前3条消息的 SHA256（支持会话复用）
+/// - 无消息内容：使用随机 UUID v4（避免碰撞）
+pub(crate) fn generate_session_uuid(messages: &Value) -> String {
+    let content = messages
+        .as_array()
+        .map(|arr| {
+            arr.iter()
+                .take(3)
+                .filter_map(|m| {
+                    // 支持字符串和数组格式的 content
+                    let c = m.get("content")?;
+                    if let Some(s) = c.as_str() {
+                        Some(s.to_string())
+                    } else {
+                        // 多模态内容：提取 text 类型
+                        c.as_array().map(|arr| {
+                            arr.iter()
+                                .filter_map(|item| {
+                                    if item.get("type")?.as_str()? == "text" {
+                                        item.get("text")?.as_str().map(|s| s.to_string())
+                                    } else {
+                                        None
+                                    }
+                                })
+                                .collect::<Vec<_>>()
+                                .join("")
+                        })
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("|")
+        })
+        .unwrap_or_default();
+
+    // 空消息时使用随机 UUID，避免所有空请求共享同一 session
+    if content.is_empty() {
+        return determinism::ids().new_uuid().to_string();
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(&content);
+    let hash = format!("{:x}", hasher.finalize());
+
+    // 转 UUID 格式：8-4-4-4-12
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hash[0..8],
+        &hash[8..12],
+        &hash[12..16],
+        &hash[16..20],
+        &hash[20..32]
+    )
+}
+
+/// 注入 metadata.user_id 并保持字段顺序
+pub(crate) fn inject_metadata_with_order(body: &[u8], user_id: &str) -> Result<Vec<u8>> {
+    let json: Value = serde_json::from_slice(body)?;
+    let obj = json
+        .as_object()
+        .ok_or_else(|| anyhow!("请求体不是 JSON 对象"))?;
+
+    // 定义字段顺序（官方顺序）
+    let field_order = [
+        "model",
+        "system",
+        "messages",
+        "tools",
+        "metadata",
+        "max_tokens",
+        "temperature",
+        "top_p",
+        "top_k",
+        "thinking",
+        "stream",
+    ];
+
+    let mut ordered: Map<String, Value> = Map::new();
+
+    // 按顺序插入已有字段
+    for &key in &field_order {
+        if let Some(val) = obj.get(key) {
+            if key == "metadata" {
+                // 注入 user_id 到 metadata
+                let mut meta = val.as_object().cloned().unwrap_or_default();
+                if !meta.contains_key("user_id") {
+                    meta.insert("user_id".into(), json!(user_id));
+                }
+                ordered.insert(key.into(), Value::Object(meta));
+            } else {
+                ordered.insert(key.into(), val.clone());
+            }
+        } else if key == "metadata" {
+            // metadata 不存在则创建
+            ordered.insert(key.into(), json!({ "user_id": user_id }));
+        }
+    }
+
+    // 保留其他未知字段（放末尾）
+    for (k, v) in obj.iter() {
+        if !ordered.contains_key(k) {
+            ordered.insert(k.clone(), v.clone());
+        }
+    }
+
+    Ok(serde_json::to_vec(&ordered)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::processor_settings::ProcessorSettings;
+    use super::*;
+
+    fn aliases() -> TransformSettings {
+        TransformSettings {
+            model_aliases: [("fast".to_string(), "gemini-2.5-flash".to_string())].into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn gemini_alias_rewrites_path_only() {
+        let transforms = aliases();
+        assert_eq!(
+            apply_model_alias_to_path(
+                "/v1beta/models/fast:streamGenerateContent?alt=sse",
+                &transforms.model_aliases
+            ),
+            "/v1beta/models/gemini-2.5-flash:streamGenerateContent?alt=sse"
+        );
+        let body = apply(
+            &transforms,
+            TransformTarget::Gemini,
+            &HyperHeaderMap::new(),
+            "key",
+            br#"{"contents":[]}"#,
+        );
+        assert_eq!(body, None);
+    }
+
+    #[test]
+    fn codex_alias_rewrites_body() {
+        let out = apply(
+            &aliases(),
+            TransformTarget::Codex,
+            &HyperHeaderMap::new(),
+            "key",
+            br#"{"model":"fast","input":[]}"#,
+        )
+        .unwrap();
+        let body: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(body["model"], "gemini-2.5-flash");
+    }
+
+    #[test]
+    fn unconfigured_tool_uses_defaults() {
+        // 非 amp-code 工具默认不注入 metadata，未命中任何转换时不改写
+        let transforms = ProcessorSettings::default().transforms_for("claude-code");
+        let out = apply(
+            &transforms,
+            TransformTarget::Claude,
+            &HyperHeaderMap::new(),
+            "key",
+            br#"{"model":"claude","messages":[]}"#,
+        );
+        assert_eq!(out, None);
+    }
+
+    #[test]
+    fn cache_control_normalized_for_claude_only() {
+        let transforms = TransformSettings {
+            normalize_cache_control: true,
+            ..Default::default()
+        };
+        let body = br#"{"messages":[{"role":"user","content":[{"type":"text","text":"hi","cache_control":{"type":"ephemeral"}}]}]}"#;
+        let out = apply(
+            &transforms,
+            TransformTarget::Claude,
+            &HyperHeaderMap::new(),
+            "key",
+            body,
+        )
+        .unwrap();
+        let json: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            json["messages"][0]["content"][0]["cache_control"]["ttl"],
+            "5m"
+        );
+        assert_eq!(
+            apply(
+                &transforms,
+                TransformTarget::Codex,
+                &HyperHeaderMap::new(),
+                "key",
+                body
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn invalid_json_is_left_alone() {
+        let transforms = TransformSettings::default_for("amp-code");
+        let out = apply(
+            &transforms,
+            TransformTarget::Claude,
+            &HyperHeaderMap::new(),
+            "key",
+            b"not json",
+        );
+        assert_eq!(out, None);
+    }
+}
@@ -0,0 +1,283 @@
+// 转换严格校验模式
+//
+// 每个请求体转换阶段结束后重新解析输出并检查协议不变量：
+// - 输出必须是 JSON 对象
+// - tool_use / tool_result（Codex: function_call / function_call_output）一一配对
+// - content / parts 不能为空数组
+// - Claude 消息角色合法、以 user 开始且不连续重复
+// 只报告本阶段新引入的问题（输入本身已有的问题不算转换错误）。
+// 模式由 ProcessorSettings.strict_mode 配置，环境变量 DC_STRICT_TRANSFORMS 可覆盖（便于 CI）。
+
+use super::transform_middleware::TransformTarget;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+const STRICT_ENV: &str = "DC_STRICT_TRANSFORMS";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StrictMode {
+    #[default]
+    Off,
+    /// 记录错误日志，仍然转发
+    Log,
+    /// 拒绝请求，不转发上游
+    Reject,
+}
+
+impl StrictMode {
+    /// 环境变量优先于配置文件
+    pub fn resolve(configured: StrictMode) -> StrictMode {
+        match std::env::var(STRICT_ENV).ok().as_deref() {
+            Some("off") => StrictMode::Off,
+            Some("log") => StrictMode::Log,
+            Some("reject") => StrictMode::Reject,
+            _ => configured,
+        }
+    }
+}
+
+pub(crate) struct StageChecker {
+    mode: StrictMode,
+    target: TransformTarget,
+}
+
+impl StageChecker {
+    pub(crate) fn new(mode: StrictMode, target: TransformTarget) -> Self {
+        Self {
+            mode: StrictMode::resolve(mode),
+            target,
+        }
+    }
+
+    /// 校验一个转换阶段的输出，原样返回 after（None 表示该阶段未改写）
+    pub(crate) fn check_stage(
+        &self,
+        stage: &str,
+        before: &[u8],
+        after: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        if let Some(out) = after.as_deref() {
+            self.check(stage, before, out)?;
+        }
+        Ok(after)
+    }
+
+    /// 比较转换前后的违规项，仅对新引入的问题记录 / 拒绝
+    pub(crate) fn check(&self, stage: &str, before: &[u8], after: &[u8]) -> Result<()> {
+        if self.mode == StrictMode::Off {
+            return Ok(());
+        }
+
+        let existing: HashSet<String> = validate(self.target, before).into_iter().collect();
+        let introduced: Vec<String> = validate(self.target, after)
+            .into_iter()
+            .filter(|v| !existing.contains(v))
+            .collect();
+        if introduced.is_empty() {
+            return Ok(());
+        }
+
+        tracing::error!(
+            "转换阶段 {} ({:?}) 引入 {} 处协议违规: {:?}",
+            stage,
+            self.target,
+            introduced.len(),
+            introduced
+        );
+        if self.mode == StrictMode::Reject {
+            return Err(anyhow!(
+                "转换阶段 {} 校验失败: {}",
+                stage,
+                introduced.join("; ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 返回请求体违反的不变量描述（空表示通过）
+pub(crate) fn validate(target: TransformTarget, body: &[u8]) -> Vec<String> {
+    if body.is_empty() {
+        return Vec::new();
+    }
+    let json: Value = match serde_json::from_slice(body) {
+        Ok(j) => j,
+        Err(e) => return vec![format!("JSON 解析失败: {}", e)],
+    };
+    if !json.is_object() {
+        return vec!["请求体不是 JSON 对象".to_string()];
+    }
+    match target {
+        TransformTarget::Claude => validate_claude(&json),
+        TransformTarget::Codex => validate_codex(&json),
+        TransformTarget::Gemini => validate_gemini(&json),
+    }
+}
+
+fn block_type(v: &Value) -> Option<&str> {
+    v.get("type").and_then(|t| t.as_str())
+}
+
+fn validate_claude(json: &Value) -> Vec<String> {
+    let mut violations = Vec::new();
+    let Some(messages) = json.get("messages").and_then(|m| m.as_array()) else {
+        return violations;
+    };
+
+    let mut prev_role: Option<&str> = None;
+    for (i, msg) in messages.iter().enumerate() {
+        let role = msg.get("role").and_then(|r| r.as_str()).unwrap_or("");
+        if role != "user" && role != "assistant" {
+            violations.push(format!("messages[{}] 角色非法: {:?}", i, role));
+        }
+        if i == 0 && role != "user" {
+            violations.push("messages[0] 必须为 user".to_string());
+        }
+        if prev_role == Some(role) {
+            violations.push(format!("messages[{}] 与上一条角色重复: {}", i, role));
+        }
+        prev_role = Some(role);
+
+        match msg.get("content") {
+            Some(Value::Array(arr)) if arr.is_empty() => {
+                violations.push(format!("messages[{}].content 为空数组", i));
+            }
+            Some(Value::String(s)) if s.is_empty() && i + 1 < messages.len() => {
+                violations.push(format!("messages[{}].content 为空字符串", i));
+            }
+            None => violations.push(format!("messages[{}] 缺少 content", i)),
+            _ => {}
+        }
+
+        // tool_result 必须对应上一条 assistant 消息中的 tool_use
+        let blocks = msg.get("content").and_then(|c| c.as_array());
+        let results: Vec<&str> = blocks
+            .into_iter()
+            .flatten()
+            .filter(|b| block_type(b) == Some("tool_result"))
+            .filter_map(|b| b.get("tool_use_id").and_then(|id| id.as_str()))
+            .collect();
+        if !results.is_empty() {
+            let prev_uses = tool_use_ids(i.checked_sub(1).and_then(|p| messages.get(p)));
+            for id in &results {
+                if !prev_uses.contains(id) {
+                    violations.push(format!(
+                        "messages[{}] tool_result {} 无对应 tool_use",
+                        i, id
+                    ));
+                }
+            }
+        }
+
+        // tool_use 必须在下一条 user 消息中得到 tool_result（最后一条 assistant 除外）
+        if role == "assistant" && i + 1 < messages.len() {
+            let next_results: HashSet<&str> = messages[i + 1]
+                .get("content")
+                .and_then(|c| c.as_array())
+                .into_iter()
+                .flatten()
+                .filter(|b| block_type(b) == Some("tool_result"))
+                .filter_map(|b| b.get("tool_use_id").and_then(|id| id.as_str()))
+                .collect();
+            for id in tool_use_ids(Some(msg)) {
+                if !next_results.contains(id) {
+                    violations.push(format!("messages[{}] tool_use {} 缺少 tool_result", i, id));
+                }
+            }
+        }
+    }
+
+    if let Some(Value::Array(system)) = json.get("system") {
+        if system.is_empty() {
+            violations.push("system 为空数组".to_string());
+        }
+    }
+    violations
+}
+
+fn tool_use_ids(msg: Option<&Value>) -> Vec<&str> {
+    msg.and_then(|m| m.get("content"))
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|b| block_type(b) == Some("tool_use"))
+        .filter_map(|b| b.get("id").and_then(|id| id.as_str()))
+        .collect()
+}
+
+fn validate_codex(json: &Value) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    // Responses API: input[]
+    if let Some(input) = json.get("input").and_then(|i| i.as_array()) {
+        let calls: HashSet<&str> = input
+            .iter()
+            .filter(|it| block_type(it) == Some("function_call"))
+            .filter_map(|it| it.get("call_id").and_then(|c| c.as_str()))
+            .collect();
+        let outputs: HashSet<&str> = input
+            .iter()
+            .filter(|it| block_type(it) == Some("function_call_output"))
+            .filter_map(|it| it.get("call_id").and_then(|c| c.as_str()))
+            .collect();
+        for id in outputs.difference(&calls) {
+            violations.push(format!("function_call_output {} 无对应 function_call", id));
+        }
+        for (i, item) in input.iter().enumerate() {
+            if let Some(Value::Array(arr)) = item.get("content") {
+                if arr.is_empty() {
+                    violations.push(format!("input[{}].content 为空数组", i));
+                }
+            }
+        }
+    }
+
+    // Chat Completions: messages[]
+    if let Some(messages) = json.get("messages").and_then(|m| m.as_array()) {
+        let calls: HashSet<&str> = messages
+            .iter()
+            .filter_map(|m| m.get("tool_calls").and_then(|t| t.as_array()))
+            .flatten()
+            .filter_map(|c| c.get("id").and_then(|id| id.as_str()))
+            .collect();
+        for (i, msg) in messages.iter().enumerate() {
+            if msg.get("role").and_then(|r| r.as_str()) == Some("tool") {
+                let id = msg
+                    .get("tool_call_id")
+                    .and_then(|id| id.as_str())
+                    .unwrap_or("");
+                if !calls.contains(id) {
+                    violations.push(format!("messages[{}] tool 消息 {} 无对应 tool_call", i, id));
+                }
+            }
+        }
+    }
+    violations
+}
+
+fn validate_gemini(json: &Value) -> Vec<String> {
+    let mut violations = Vec::new();
+    let Some(contents) = json.get("contents").and_then(|c| c.as_array()) else {
+        return violations;
+    };
+    for (i, content) in contents.iter().enumerate() {
+        let role = content
+            .get("role")
+            .and_then(|r| r.as_str())
+            .unwrap_or("user");
+        if role != "user" && role != "model" && role != "function" {
+            violations.push(format!("contents[{}] 角色非法: {}", i, role));
+        }
+        match content.get("parts").and_then(|p| p.as_array()) {
+            Some(parts) if parts.is_empty() => {
+                violations.push(format!("contents[{}].parts 为空数组", i));
+            }
+            None => violations.push(format!("contents[{}] 缺少 parts", i)),
+            _ => {}
+        }
+    }
+    violations
+}
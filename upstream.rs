@@ -0,0 +1,920 @@
+// 上游转发层（故障转移）
+//
+// 处理器随转发结果返回 UpstreamTag（路由 / Profile / 上游地址 / 追踪上下文 / 虚拟 Key，
+// 见 ProcessOutcome::Forward），元数据不写入请求头，不会发往上游；代理改用 forward() 发送：主 Profile 返回可重试状态码（5xx / 429 / 529）
+// 或首字节超时时，按 failover.chains 中该路由的备用配置依次重试。
+// - 请求体为 Bytes，重试时直接复用；备用请求改写上游地址与鉴权头 / key 参数
+// - 仅在收到响应头之前切换；响应开始流式输出后不再切换
+// - 实际服务的后端通过 x-dc-served-by 响应头告知客户端
+// 备用配置取自 ProxyConfigManager（real_base_url / real_api_key），协议需与主 Profile 一致。
+//
+// 切换前先对同一上游按 retry 策略重试：传输错误 / 超时 / retry.statuses 命中时
+// 指数退避（带抖动）；429 / 529 优先遵循 retry-after-ms / retry-after（秒或 HTTP 日期），
+// 超过 max_delay_ms 时不再等待，直接交给故障转移。同样只在首字节之前重试。
+// chaos 启用时在每次发送前后注入延迟 / 错误 / 断流（见 chaos.rs）。
+// 每次收到响应头都记录提供方请求 ID（见 provider_request_id.rs）。
+// AmpInternal 请求（amp 路由）返回 401 且 Token 可刷新时，换用新 Token 重发一次（见 amp_oauth.rs）。
+// 处理器未标记的请求以中性路由 direct 发送（UpstreamTag::direct()）：同样重试、记录日志，
+// 但没有备用链，也不刷新 AMP Token。
+// 后端以 400 拒绝 anthropic-version 且 Profile 开启自动降级时，改用降级版本重发一次（见 anthropic_version.rs）。
+// 处理器启用链路追踪时，转发与每次尝试生成 upstream.forward / upstream.attempt span。
+// 携带虚拟 Key 的请求先按该 Key 的令牌桶准入，超限直接返回 429（见 rate_limit.rs）。
+// Profile 开启 response_cache 时，完全相同的请求直接由本地缓存应答（先于限流，见 response_cache.rs）。
+// 最终响应先经停滞检测（见 stall_guard.rs），再按处理器给出的计划改写（协议转换等，见 response_rewrite.rs），
+// 然后经 output_cap 限制输出长度并写入响应缓存，
+// 最后经 request_log 包装，结束时写入请求 / 用量日志。
+
+use super::amp_accounting::days_from_civil;
+use super::amp_oauth;
+use super::anthropic_version;
+use super::app_state::AppState;
+use super::audit_log::{self, AuditRecord};
+use super::chaos::{self, ChaosSettings};
+use super::dashboard;
+use super::metrics;
+use super::outcome::ProcessOutcome;
+use super::output_cap;
+use super::overload_queue;
+use super::processor_settings::ProfileSettings;
+use super::provider_request_id;
+use super::rate_limit;
+use super::request_log::{self, UsageTargets};
+use super::response_cache;
+use super::response_rewrite::{self, ResponsePlan};
+use super::secret_store;
+use super::stall_guard;
+use super::telemetry::Span;
+use super::ProcessedRequest;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 响应头：实际服务请求的 Profile / 配置名
+pub const SERVED_BY_HEADER: &str = "x-dc-served-by";
+
+static KEY_QUERY_RE: Lazy<regex::Regex> =
+    Lazy::new(|| regex::Regex::new(r"([?&]key=)[^&]*").expect("key 参数正则非法"));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FailoverSettings {
+    pub enabled: bool,
+    /// 路由（claude / codex / gemini / azure）→ 备用 ProxyConfigManager 工具 ID，按顺序尝试
+    pub chains: HashMap<String, Vec<String>>,
+    /// 等待响应头的超时（秒），超时视为失败
+    pub first_byte_timeout_secs: u64,
+    /// 触发切换的状态码
+    pub statuses: Vec<u16>,
+}
+
+impl Default for FailoverSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            chains: HashMap::new(),
+            first_byte_timeout_secs: 60,
+            statuses: vec![429, 500, 502, 503, 504, 529],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetrySettings {
+    /// 每个上游的最大尝试次数（含首次），1 表示不重试
+    pub max_attempts: u32,
+    /// 首次退避（毫秒），之后逐次翻倍
+    pub base_delay_ms: u64,
+    /// 单次等待上限（毫秒），retry-after 超过该值时放弃重试
+    pub max_delay_ms: u64,
+    /// 抖动比例（0~1），实际等待在 [d*(1-jitter), d] 之间
+    pub jitter: f64,
+    /// 触发重试的状态码
+    pub statuses: Vec<u16>,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+            jitter: 0.3,
+            statuses: vec![429, 502, 503, 504, 529],
+        }
+    }
+}
+
+impl RetrySettings {
+    /// 第 attempt 次失败后的退避时长（attempt 从 1 开始）
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay_ms
+            .saturating_mul(1u64 << attempt.saturating_sub(1).min(20));
+        let capped = exp.min(self.max_delay_ms) as f64;
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let random = (uuid::Uuid::new_v4().as_u128() % 10_000) as f64 / 10_000.0;
+        Duration::from_millis((capped * (1.0 - jitter * random)) as u64)
+    }
+}
+
+pub struct Forwarded {
+    pub response: reqwest::Response,
+    /// 实际服务的 Profile / 配置名
+    pub served_by: String,
+}
+
+struct Candidate {
+    name: String,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+/// 转发层元数据：处理器随 ProcessOutcome::Forward 返回，带该标记的请求经 forward() 发送
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpstreamTag {
+    /// 路由（claude / codex / gemini / azure）
+    pub route: String,
+    /// 主 Profile 名
+    pub profile: String,
+    /// 主 Profile 的上游地址（故障转移时据此替换前缀）
+    pub base_url: String,
+    /// 处理器根 span 的 traceparent，转发层据此延续 trace
+    pub traceparent: Option<String>,
+    /// 通过入站鉴权的虚拟 Key 名，转发层据此限流
+    pub virtual_key: Option<String>,
+    /// 响应侧改写计划（协议转换等，见 response_rewrite.rs）
+    pub response: ResponsePlan,
+}
+
+/// 未标记请求使用的中性路由名（同时作为 served_by）
+pub(crate) const DIRECT_ROUTE: &str = "direct";
+
+impl UpstreamTag {
+    /// 中性标记：不属于任何 LLM 路由的请求（自定义处理器等），使用默认 Profile 配置
+    pub fn direct() -> Self {
+        Self {
+            route: DIRECT_ROUTE.to_string(),
+            profile: DIRECT_ROUTE.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// 处理器侧：标记请求所属路由与 Profile，交给转发层发送
+pub(crate) fn tag(
+    request: ProcessedRequest,
+    route: &str,
+    profile: &str,
+    base_url: &str,
+) -> ProcessOutcome {
+    tag_planned(request, route, profile, base_url, ResponsePlan::default())
+}
+
+/// 同 tag()，并附带响应侧改写计划
+pub(crate) fn tag_planned(
+    request: ProcessedRequest,
+    route: &str,
+    profile: &str,
+    base_url: &str,
+    response: ResponsePlan,
+) -> ProcessOutcome {
+    ProcessOutcome::Forward {
+        target_url: request.target_url,
+        headers: request.headers,
+        body: request.body,
+        upstream: Some(UpstreamTag {
+            route: route.to_string(),
+            profile: profile.to_string(),
+            base_url: base_url.to_string(),
+            response,
+            ..Default::default()
+        }),
+    }
+}
+
+/// 移除本地控制头（x-dc-*）：客户端传入或处理器遗漏的控制头都不发往上游
+pub(crate) fn strip_control_headers(headers: &mut hyper::HeaderMap) {
+    let keys: Vec<_> = headers
+        .keys()
+        .filter(|k| k.as_str().starts_with("x-dc-"))
+        .cloned()
+        .collect();
+    for key in keys {
+        headers.remove(&key);
+    }
+}
+
+/// 备用请求：替换上游地址前缀与鉴权
+fn retarget(
+    request: &ProcessedRequest,
+    primary_base: &str,
+    candidate: &Candidate,
+) -> ProcessedRequest {
+    let primary = primary_base.trim_end_matches('/');
+    let rest = match request.target_url.strip_prefix(primary) {
+        Some(rest) => rest.to_string(),
+        None => url::Url::parse(&request.target_url)
+            .map(|u| match u.query() {
+                Some(q) => format!("{}?{}", u.path(), q),
+                None => u.path().to_string(),
+            })
+            .unwrap_or_default(),
+    };
+    let mut target_url = format!("{}{}", candidate.base_url.trim_end_matches('/'), rest);
+    let mut headers = request.headers.clone();
+
+    if let Some(key) = &candidate.api_key {
+        for name in ["x-api-key", "x-goog-api-key", "api-key"] {
+            if headers.contains_key(name) {
+                if let Ok(v) = key.parse() {
+                    headers.insert(name, v);
+                }
+            }
+        }
+        let is_bearer = headers
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("Bearer "));
+        if is_bearer {
+            if let Ok(v) = format!("Bearer {}", key).parse() {
+                headers.insert(hyper::header::AUTHORIZATION, v);
+            }
+        }
+        target_url = KEY_QUERY_RE
+            .replace(&target_url, format!("${{1}}{}", urlencoding::encode(key)))
+            .into_owned();
+    }
+    ProcessedRequest {
+        target_url,
+        headers,
+        body: request.body.clone(),
+    }
+}
+
+/// 备用配置取自 state 的 ProxyConfigManager（与主请求使用同一份状态）
+fn candidates(state: &AppState, settings: &FailoverSettings, route: &str) -> Vec<Candidate> {
+    let Some(chain) = settings.chains.get(route).filter(|_| settings.enabled) else {
+        return Vec::new();
+    };
+    let mgr = state.proxy_config_manager().ok();
+    chain
+        .iter()
+        .filter_map(|id| {
+            let config = mgr.as_ref()?.get_config(id).ok().flatten();
+            let base_url = config.as_ref().and_then(|c| c.real_base_url.clone());
+            let Some(base_url) = base_url else {
+                tracing::warn!("备用配置 {} 缺少上游地址，跳过", id);
+                return None;
+            };
+            Some(Candidate {
+                name: id.clone(),
+                base_url,
+                api_key: config.and_then(|c| secret_store::reveal_opt(c.real_api_key)),
+            })
+        })
+        .collect()
+}
+
+async fn send_once(
+    client: &reqwest::Client,
+    method: &reqwest::Method,
+    request: &ProcessedRequest,
+    timeout: Duration,
+    span: &Span,
+) -> Result<reqwest::Response> {
+    let mut headers = request.headers.clone();
+    headers.remove(hyper::header::HOST);
+    headers.remove(hyper::header::CONTENT_LENGTH);
+    if span.propagates() {
+        if let Some(v) = span.traceparent().and_then(|t| t.parse().ok()) {
+            headers.insert("traceparent", v);
+        }
+    }
+    let send = client
+        .request(method.clone(), &request.target_url)
+        .headers(headers)
+        .body(request.body.clone())
+        .send();
+    match tokio::time::timeout(timeout, send).await {
+        Ok(result) => result.map_err(|e| anyhow!("上游请求失败: {}", e)),
+        Err(_) => Err(anyhow!("等待上游响应超时（{}s）", timeout.as_secs())),
+    }
+}
+
+/// 解析 retry-after-ms / retry-after（秒或 IMF-fixdate）
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+    };
+    if let Some(ms) = value("retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+        return Some(Duration::from_millis(ms.max(0.0) as u64));
+    }
+    let raw = value("retry-after")?;
+    if let Ok(secs) = raw.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = parse_http_date(raw)?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs() as i64;
+    Some(Duration::from_secs((at - now).max(0) as u64))
+}
+
+/// "Sun, 06 Nov 1994 08:49:37 GMT" → Unix 秒
+fn parse_http_date(raw: &str) -> Option<i64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let parts: Vec<&str> = raw.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = parts.as_slice() else {
+        return None;
+    };
+    let day: u32 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| m == month)? as u32 + 1;
+    let year: i64 = year.parse().ok()?;
+    let hms: Vec<i64> = time.split(':').filter_map(|p| p.parse().ok()).collect();
+    let [h, m, s] = hms.as_slice() else {
+        return None;
+    };
+    let days = days_from_civil(year, month, day);
+    Some(days * 86_400 + h * 3600 + m * 60 + s)
+}
+
+/// 最终响应：写入 x-dc-served-by，告知客户端实际服务的后端
+fn served_by(mut response: reqwest::Response, name: &str) -> Forwarded {
+    if let Ok(value) = name.parse() {
+        response.headers_mut().insert(SERVED_BY_HEADER, value);
+    }
+    Forwarded {
+        response,
+        served_by: name.to_string(),
+    }
+}
+
+/// 单次发送，前后经过故障注入
+#[allow(clippy::too_many_arguments)]
+async fn send_chaotic(
+    client: &reqwest::Client,
+    method: &reqwest::Method,
+    request: &ProcessedRequest,
+    timeout: Duration,
+    chaos: &ChaosSettings,
+    route: &str,
+    name: &str,
+    parent: &Span,
+) -> Result<reqwest::Response> {
+    let span = parent.client_child("upstream.attempt");
+    span.attr("dc.served_by", name);
+    if let Some(injected) = chaos::before_send(chaos, route, name).await {
+        span.attr("http.status_code", injected.status().as_u16() as u64);
+        span.attr("dc.chaos", true);
+        return Ok(injected);
+    }
+    let started = std::time::Instant::now();
+    let resp = match send_once(client, method, request, timeout, &span).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            span.fail(&e.to_string());
+            return Err(e);
+        }
+    };
+    metrics::record_upstream(route, name, resp.status().as_u16(), started.elapsed());
+    span.attr("http.status_code", resp.status().as_u16() as u64);
+    Ok(chaos::after_response(chaos, route, name, resp))
+}
+
+/// 对单个上游按重试策略发送；返回最后一次的结果
+#[allow(clippy::too_many_arguments)]
+async fn send_with_retry(
+    client: &reqwest::Client,
+    method: &reqwest::Method,
+    request: &ProcessedRequest,
+    timeout: Duration,
+    retry: &RetrySettings,
+    chaos: &ChaosSettings,
+    route: &str,
+    name: &str,
+    span: &Span,
+) -> Result<reqwest::Response> {
+    let max_attempts = retry.max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        let result = send_chaotic(client, method, request, timeout, chaos, route, name, span).await;
+        if let Ok(resp) = &result {
+            provider_request_id::record(route, name, resp.status().as_u16(), resp.headers());
+        }
+        if attempt >= max_attempts {
+            return result;
+        }
+        let (delay, reason) = match &result {
+            Ok(resp) if retry.statuses.contains(&resp.status().as_u16()) => {
+                let status = resp.status().as_u16();
+                let hinted = if matches!(status, 429 | 529) {
+                    retry_after(resp.headers())
+                } else {
+                    None
+                };
+                match hinted {
+                    Some(d) if d.as_millis() > retry.max_delay_ms as u128 => {
+                        tracing::info!(
+                            "上游 {} 要求等待 {}s，超过重试上限，不再重试",
+                            name,
+                            d.as_secs()
+                        );
+                        return result;
+                    }
+                    Some(d) => (d, format!("状态码 {}（retry-after）", status)),
+                    None => (retry.backoff(attempt), format!("状态码 {}", status)),
+                }
+            }
+            Ok(_) => return result,
+            Err(e) => (retry.backoff(attempt), e.to_string()),
+        };
+        tracing::warn!(
+            "上游 {} 第 {} 次请求失败（{}），{}ms 后重试",
+            name,
+            attempt,
+            reason,
+            delay.as_millis()
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// 一次转发的完整上下文：主 Profile 与备用配置的请求，以及发送参数
+pub(crate) struct Chain {
+    pub route: String,
+    client: reqwest::Client,
+    method: reqwest::Method,
+    timeout: Duration,
+    failover: FailoverSettings,
+    retry: RetrySettings,
+    chaos: ChaosSettings,
+    /// (Profile / 配置名, 请求)，第一个为主 Profile
+    attempts: Vec<(String, ProcessedRequest)>,
+}
+
+impl Chain {
+    /// 主 Profile 名
+    pub fn primary(&self) -> &str {
+        self.attempts
+            .first()
+            .map(|(n, _)| n.as_str())
+            .unwrap_or_default()
+    }
+
+    /// 主请求体
+    pub fn body(&self) -> &[u8] {
+        self.attempts
+            .first()
+            .map(|(_, r)| r.body.as_ref())
+            .unwrap_or_default()
+    }
+
+    /// 主请求发送的 anthropic-version
+    fn sent_anthropic_version(&self) -> Option<&str> {
+        self.attempts
+            .first()
+            .and_then(|(_, r)| r.headers.get("anthropic-version"))
+            .and_then(|v| v.to_str().ok())
+    }
+
+    /// 后端以 400 拒绝 anthropic-version 时记录降级（见 anthropic_version.rs），
+    /// 返回 true 表示各请求已改用降级版本、调用方应重发一次；响应体已读出，按原样重建
+    async fn downgrade_version(
+        &mut self,
+        settings: &ProfileSettings,
+        forwarded: &mut Forwarded,
+    ) -> Result<bool> {
+        if !settings.anthropic_version_auto_downgrade
+            || forwarded.response.status() != reqwest::StatusCode::BAD_REQUEST
+        {
+            return Ok(false);
+        }
+        let Some(sent) = self.sent_anthropic_version().map(|v| v.to_string()) else {
+            return Ok(false);
+        };
+        let response = std::mem::replace(
+            &mut forwarded.response,
+            reqwest::Response::from(hyper::http::Response::new(reqwest::Body::from(""))),
+        );
+        let template = response_rewrite::rebuild(&response, reqwest::Body::from(""));
+        let body = response.bytes().await?;
+        let downgraded =
+            anthropic_version::note_rejection(&self.route, settings, Some(&sent), 400, &body);
+        forwarded.response = response_rewrite::rebuild(&template, reqwest::Body::from(body));
+        if downgraded {
+            for (_, attempt) in self.attempts.iter_mut() {
+                anthropic_version::apply(&self.route, settings, &mut attempt.headers);
+            }
+        }
+        Ok(downgraded)
+    }
+
+    /// 依次尝试主 Profile 与备用配置（各自按 retry 重试）
+    pub(crate) async fn run(&self, span: &Span) -> Result<Forwarded> {
+        let total = self.attempts.len();
+        for (i, (name, attempt)) in self.attempts.iter().enumerate() {
+            let is_last = i + 1 == total;
+            let failure = match send_with_retry(
+                &self.client,
+                &self.method,
+                attempt,
+                self.timeout,
+                &self.retry,
+                &self.chaos,
+                &self.route,
+                name,
+                span,
+            )
+            .await
+            {
+                Ok(resp)
+                    if is_last || !self.failover.statuses.contains(&resp.status().as_u16()) =>
+                {
+                    if i > 0 {
+                        tracing::info!("故障转移: {} 由备用配置 {} 服务", self.route, name);
+                    }
+                    span.attr("dc.served_by", name.as_str());
+                    return Ok(served_by(resp, name));
+                }
+                Ok(resp) => format!("状态码 {}", resp.status().as_u16()),
+                Err(e) if is_last => {
+                    span.fail(&e.to_string());
+                    return Err(e);
+                }
+                Err(e) => e.to_string(),
+            };
+            tracing::warn!("上游 {} 失败（{}），切换备用配置", name, failure);
+            dashboard::record_error(
+                &self.route,
+                &format!("{} 失败，已切换备用: {}", name, failure),
+            );
+            audit_log::append(
+                &AuditRecord::new("failover", &self.route, name).with("reason", json!(failure)),
+            );
+        }
+        Err(anyhow!("无可用上游"))
+    }
+}
+
+/// 发送请求：同一上游按 retry 重试，仍失败时按备用链切换；
+/// Claude 路由整体仍返回 529 时交给过载排队（见 overload_queue.rs）
+/// 配置与出站 Client 均取自 state（引擎传入自身持有的 AppState）
+pub async fn forward(
+    state: &AppState,
+    method: reqwest::Method,
+    mut request: ProcessedRequest,
+    tag: UpstreamTag,
+) -> Result<Forwarded> {
+    let settings = state.settings();
+    let started = std::time::Instant::now();
+    let UpstreamTag {
+        route,
+        profile,
+        base_url: primary_base,
+        traceparent: context,
+        virtual_key,
+        response,
+    } = tag;
+    strip_control_headers(&mut request.headers);
+    let model = request_log::request_model(&request.target_url, &request.body);
+    let span = Span::from_context("upstream.forward", context.as_deref());
+    span.attr("dc.route", route.as_str());
+    let profile_settings = settings.profile(&route);
+    let cache_key = profile_settings
+        .response_cache
+        .enabled
+        .then(|| response_cache::key(&route, &request.target_url, &request.body))
+        .flatten();
+    if let Some(key) = cache_key.as_deref() {
+        if let Some(hit) = response_cache::lookup(&profile_settings.response_cache, key) {
+            span.attr("dc.response_cache", "hit");
+            tracing::info!("{} 请求命中响应缓存", route);
+            return Ok(hit);
+        }
+    }
+    if let Some(key) = virtual_key.as_deref() {
+        if let Err(retry_after) =
+            rate_limit::acquire(&settings.inbound_auth, key, &route, &request.body)
+        {
+            span.attr("dc.rate_limited", key);
+            return Ok(Forwarded {
+                response: rate_limit::limited_response(&route, retry_after),
+                served_by: String::new(),
+            });
+        }
+    }
+    let failover = &settings.failover;
+
+    let backups = if primary_base.is_empty() {
+        Vec::new()
+    } else {
+        candidates(state, failover, &route)
+    };
+    let retargeted: Vec<_> = backups
+        .iter()
+        .map(|c| (c.name.clone(), retarget(&request, &primary_base, c)))
+        .collect();
+    let mut attempts = vec![(profile, request)];
+    attempts.extend(retargeted);
+
+    let mut chain = Chain {
+        client: state.outbound().client_for_profile(&settings, &route),
+        route,
+        method,
+        timeout: Duration::from_secs(failover.first_byte_timeout_secs.max(1)),
+        failover: failover.clone(),
+        retry: settings.retry.clone(),
+        chaos: settings.chaos.clone(),
+        attempts,
+    };
+    let mut forwarded = chain.run(&span).await?;
+    if chain.route == "amp" && forwarded.response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        if let Some((_, attempt)) = chain.attempts.first_mut() {
+            if let Some(headers) = amp_oauth::refreshed_headers(state, &attempt.headers).await {
+                attempt.headers = headers;
+                forwarded = chain.run(&span).await?;
+            }
+        }
+    }
+    if chain
+        .downgrade_version(profile_settings, &mut forwarded)
+        .await?
+    {
+        tracing::info!("{} 已降级 anthropic-version，重发请求", chain.route);
+        forwarded = chain.run(&span).await?;
+    }
+    let chain = Arc::new(chain);
+    if overload_queue::should_queue(&settings.overload, &chain.route, &forwarded) {
+        forwarded = overload_queue::hold(
+            &settings.overload,
+            Arc::clone(&chain),
+            forwarded,
+            context.clone(),
+        )
+        .await;
+    }
+    let forwarded = stall_guard::apply(
+        &profile_settings.stall,
+        Arc::clone(&chain),
+        context,
+        forwarded,
+    );
+    let forwarded = response_rewrite::apply(&response, profile_settings, forwarded).await?;
+    let forwarded = output_cap::apply(&profile_settings.output_cap, &chain.route, forwarded);
+    let forwarded = match cache_key {
+        Some(key) => response_cache::store(&profile_settings.response_cache, key, forwarded),
+        None => forwarded,
+    };
+    let targets = UsageTargets {
+        budget: profile_settings.budget.is_configured(),
+        rate_limit_key: virtual_key,
+    };
+    Ok(request_log::observe(
+        &settings.request_log,
+        targets,
+        &chain.route,
+        chain.primary(),
+        model,
+        started,
+        forwarded,
+    ))
+}
+
+/// 测试用转发链：以同一请求体依次尝试 targets（配置名 target0、target1…），不重试
+#[cfg(test)]
+impl Chain {
+    pub(crate) fn for_test(route: &str, targets: &[&str], body: &'static [u8]) -> Self {
+        Chain {
+            route: route.to_string(),
+            client: reqwest::Client::builder().no_proxy().build().unwrap(),
+            method: reqwest::Method::POST,
+            timeout: Duration::from_secs(5),
+            failover: FailoverSettings::default(),
+            retry: RetrySettings {
+                max_attempts: 1,
+                ..Default::default()
+            },
+            chaos: ChaosSettings::default(),
+            attempts: targets
+                .iter()
+                .enumerate()
+                .map(|(i, url)| {
+                    let request = ProcessedRequest {
+                        target_url: url.to_string(),
+                        headers: hyper::HeaderMap::new(),
+                        body: bytes::Bytes::from_static(body),
+                    };
+                    (format!("target{}", i), request)
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::processor_settings::ProcessorSettings;
+    use super::*;
+
+    #[test]
+    fn tag_keeps_metadata_out_of_headers() {
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("x-api-key", "sk-test".parse().unwrap());
+        let request = ProcessedRequest {
+            target_url: "https://api.example/v1/messages".to_string(),
+            headers,
+            body: bytes::Bytes::from_static(b"{}"),
+        };
+        let mut outcome = tag(request, "claude", "primary", "https://api.example");
+        let upstream = outcome.upstream_tag_mut().unwrap();
+        assert_eq!(upstream.route, "claude");
+        assert_eq!(upstream.profile, "primary");
+        assert_eq!(upstream.base_url, "https://api.example");
+        let request = outcome.into_forward().unwrap();
+        assert!(request
+            .headers
+            .keys()
+            .all(|k| !k.as_str().starts_with("x-dc-")));
+    }
+
+    #[test]
+    fn control_headers_stripped() {
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("x-dc-preset", "fast".parse().unwrap());
+        headers.insert("x-dc-upstream-route", "claude".parse().unwrap());
+        headers.insert("anthropic-version", "2023-06-01".parse().unwrap());
+        strip_control_headers(&mut headers);
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key("anthropic-version"));
+    }
+
+    #[test]
+    fn retry_backoff_is_capped() {
+        let retry = RetrySettings {
+            base_delay_ms: 500,
+            max_delay_ms: 2_000,
+            jitter: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(retry.backoff(1), Duration::from_millis(500));
+        assert_eq!(retry.backoff(2), Duration::from_millis(1_000));
+        assert_eq!(retry.backoff(10), Duration::from_millis(2_000));
+        assert_eq!(retry.backoff(u32::MAX), Duration::from_millis(2_000));
+    }
+
+    fn version_chain(route: &str) -> Chain {
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("anthropic-version", "2023-06-01".parse().unwrap());
+        let request = ProcessedRequest {
+            target_url: "https://api.example/v1/messages".to_string(),
+            headers,
+            body: bytes::Bytes::from_static(b"{}"),
+        };
+        Chain {
+            route: route.to_string(),
+            client: reqwest::Client::new(),
+            method: reqwest::Method::POST,
+            timeout: Duration::from_secs(1),
+            failover: FailoverSettings::default(),
+            retry: RetrySettings::default(),
+            chaos: ChaosSettings::default(),
+            attempts: vec![("primary".to_string(), request)],
+        }
+    }
+
+    /// 本地上游：对每个连接返回固定状态码
+    async fn fixed_status_upstream(status: u16) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn failover_response_carries_served_by_header() {
+        let primary = fixed_status_upstream(503).await;
+        let backup = fixed_status_upstream(200).await;
+        let request = |base: &str| ProcessedRequest {
+            target_url: format!("{}/v1/messages", base),
+            headers: hyper::HeaderMap::new(),
+            body: bytes::Bytes::from_static(b"{}"),
+        };
+        let chain = Chain {
+            route: "claude-served-by-test".to_string(),
+            client: reqwest::Client::builder().no_proxy().build().unwrap(),
+            method: reqwest::Method::POST,
+            timeout: Duration::from_secs(5),
+            failover: FailoverSettings::default(),
+            retry: RetrySettings {
+                max_attempts: 1,
+                ..Default::default()
+            },
+            chaos: ChaosSettings::default(),
+            attempts: vec![
+                ("primary".to_string(), request(&primary)),
+                ("backup".to_string(), request(&backup)),
+            ],
+        };
+        let forwarded = chain.run(&Span::from_context("test", None)).await.unwrap();
+        assert_eq!(forwarded.served_by, "backup");
+        assert_eq!(forwarded.response.status(), 200);
+        assert_eq!(forwarded.response.headers()[SERVED_BY_HEADER], "backup");
+    }
+
+    #[tokio::test]
+    async fn direct_tag_skips_amp_refresh() {
+        let base = fixed_status_upstream(401).await;
+        let state = AppState::fixed(ProcessorSettings::default(), (None, None, None));
+        let request = ProcessedRequest {
+            target_url: format!("{}/v1/anything", base),
+            headers: hyper::HeaderMap::new(),
+            body: bytes::Bytes::from_static(b"{}"),
+        };
+        let forwarded = forward(
+            &state,
+            reqwest::Method::POST,
+            request,
+            UpstreamTag::direct(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(forwarded.response.status(), 401);
+        assert_eq!(forwarded.served_by, DIRECT_ROUTE);
+    }
+
+    fn bad_request(body: &'static str) -> Forwarded {
+        Forwarded {
+            response: reqwest::Response::from(
+                hyper::http::Response::builder()
+                    .status(400)
+                    .body(reqwest::Body::from(body))
+                    .unwrap(),
+            ),
+            served_by: "primary".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn version_rejection_downgrades_and_keeps_body() {
+        let route = "claude-version-downgrade-test";
+        let settings = ProfileSettings {
+            anthropic_version_auto_downgrade: true,
+            ..Default::default()
+        };
+        let mut chain = version_chain(route);
+        let body = r#"{"error":{"message":"anthropic-version: unsupported value"}}"#;
+        let mut forwarded = bad_request(body);
+        assert!(chain
+            .downgrade_version(&settings, &mut forwarded)
+            .await
+            .unwrap());
+        assert_eq!(chain.sent_anthropic_version(), Some("2023-01-01"));
+        assert_eq!(forwarded.response.status(), 400);
+        assert_eq!(forwarded.response.text().await.unwrap(), body);
+        anthropic_version::reset(route);
+    }
+
+    #[tokio::test]
+    async fn other_bad_request_not_retried() {
+        let settings = ProfileSettings {
+            anthropic_version_auto_downgrade: true,
+            ..Default::default()
+        };
+        let mut chain = version_chain("claude-version-other-test");
+        let mut forwarded = bad_request(r#"{"error":{"message":"max_tokens invalid"}}"#);
+        assert!(!chain
+            .downgrade_version(&settings, &mut forwarded)
+            .await
+            .unwrap());
+        assert_eq!(chain.sent_anthropic_version(), Some("2023-06-01"));
+
+        let mut forwarded = bad_request(r#"{"error":{"message":"anthropic-version unknown"}}"#);
+        assert!(!chain
+            .downgrade_version(&ProfileSettings::default(), &mut forwarded)
+            .await
+            .unwrap());
+    }
+}
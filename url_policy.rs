@@ -0,0 +1,48 @@
+// 本地工具 URL 访问策略（SSRF 校验的企业扩展）
+//
+// 默认策略只禁止内网地址与内网域名。企业部署可额外配置：
+// - allow_hosts：明确允许的内部主机（如内部 Wiki），跳过内网地址 / 内网域名检查
+// - deny_hosts：禁止访问的外部域名，优先于允许列表
+// 条目为主机名（精确匹配，IP 字面量同样适用）或 `*.example.com`（匹配该域名及所有子域名）。
+// ProxyConfigManager 不在本仓库内，无法扩展字段，因此保存在处理器配置中。
+// 作用于 extractWebPageContent / httpRequest 的校验与连接时的解析校验（见 outbound.rs）。
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UrlPolicySettings {
+    /// 允许访问的内部主机
+    pub allow_hosts: Vec<String>,
+    /// 禁止访问的域名
+    pub deny_hosts: Vec<String>,
+}
+
+fn matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim().trim_end_matches('.').to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+        None => !pattern.is_empty() && host == pattern,
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+impl UrlPolicySettings {
+    /// 主机是否在禁止列表中
+    pub(crate) fn is_denied(&self, host: &str) -> bool {
+        let host = normalize(host);
+        self.deny_hosts.iter().any(|p| matches(p, &host))
+    }
+
+    /// 主机是否为明确允许的内部主机（禁止列表优先）
+    pub(crate) fn is_allowed_internal(&self, host: &str) -> bool {
+        let host = normalize(host);
+        !self.is_denied(&host) && self.allow_hosts.iter().any(|p| matches(p, &host))
+    }
+}
@@ -0,0 +1,148 @@
+// metadata.user_id 用户指纹
+//
+// 默认 SHA256(API_Key + UA)，与旧版本保持一致；可改为：
+// - HmacSha256：以本地密钥做 HMAC，密钥首次使用时随机生成并保存在
+//   ~/.duckcoding/amp/fingerprint.json，不随配置导出
+// - Profile 级 opaque ID：以随机生成的 Profile 标识代替 API Key 参与计算，
+//   换 Key 不换指纹，上游也无法从指纹反推出 Key
+
+use super::storage;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserHashAlgorithm {
+    /// SHA256(标识 + UA)
+    #[default]
+    Sha256,
+    /// HMAC-SHA256(本地密钥, 标识 + UA)
+    HmacSha256,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct FingerprintFile {
+    /// HMAC 密钥（hex）
+    hmac_key: String,
+    /// Profile 名 → opaque ID
+    opaque_ids: HashMap<String, String>,
+}
+
+static FILE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+const NAMESPACE: &str = "amp";
+const KEY: &str = "fingerprint.json";
+
+fn load() -> Result<FingerprintFile> {
+    storage::get_json(NAMESPACE, KEY)
+        .map(Option::unwrap_or_default)
+        .map_err(|e| anyhow!("读取指纹密钥失败: {}", e))
+}
+
+fn save(file: &FingerprintFile) -> Result<()> {
+    storage::put_json(NAMESPACE, KEY, file).map_err(|e| anyhow!("写入指纹密钥失败: {}", e))
+}
+
+/// 32 字节随机 hex（密钥材料不走 determinism，避免快照模式下生成可预测的密钥）
+fn random_hex() -> String {
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// 读取或生成本地文件中的某一项
+fn get_or_create(
+    read: impl Fn(&FingerprintFile) -> Option<String>,
+    write: impl Fn(&mut FingerprintFile, String),
+) -> Result<String> {
+    let _guard = FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = load()?;
+    if let Some(value) = read(&file) {
+        return Ok(value);
+    }
+    let value = random_hex();
+    write(&mut file, value.clone());
+    save(&file)?;
+    Ok(value)
+}
+
+/// Profile 的 opaque ID，首次使用时生成
+pub(crate) fn opaque_id(profile: &str) -> Result<String> {
+    get_or_create(
+        |f| f.opaque_ids.get(profile).cloned(),
+        |f, v| {
+            f.opaque_ids.insert(profile.to_string(), v);
+        },
+    )
+}
+
+fn hmac_key() -> Result<String> {
+    get_or_create(
+        |f| Some(f.hmac_key.clone()).filter(|k| !k.is_empty()),
+        |f, v| f.hmac_key = v,
+    )
+}
+
+/// 参与指纹计算的标识：开启 opaque ID 时用 Profile 标识，否则用 API Key
+pub(crate) fn identity(use_opaque_id: bool, profile: &str, api_key: &str) -> String {
+    if !use_opaque_id {
+        return api_key.to_string();
+    }
+    match opaque_id(profile) {
+        Ok(id) => format!("opaque:{}", id),
+        Err(e) => {
+            // 宁可退回旧算法，也不让请求因指纹失败
+            tracing::warn!(
+                "获取 Profile {} 的 opaque ID 失败，使用 API Key: {}",
+                profile,
+                e
+            );
+            api_key.to_string()
+        }
+    }
+}
+
+/// 64 位 hex 指纹
+pub(crate) fn hash(algorithm: UserHashAlgorithm, identity: &str, ua: &str) -> String {
+    let input = format!("{}:{}", identity, ua);
+    if algorithm == UserHashAlgorithm::HmacSha256 {
+        match hmac_key() {
+            Ok(key) => return hex(&hmac_sha256(key.as_bytes(), input.as_bytes())),
+            Err(e) => tracing::warn!("读取指纹密钥失败，退回 SHA256: {}", e),
+        }
+    }
+    hex(&Sha256::digest(input.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// HMAC-SHA256（RFC 2104）
+pub(crate) fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    const BLOCK: usize = 64;
+    let mut k = if key.len() > BLOCK {
+        Sha256::digest(key).to_vec()
+    } else {
+        key.to_vec()
+    };
+    k.resize(BLOCK, 0);
+    let ipad: Vec<u8> = k.iter().map(|b| b ^ 0x36).collect();
+    let opad: Vec<u8> = k.iter().map(|b| b ^ 0x5c).collect();
+    let inner = Sha256::new()
+        .chain_update(&ipad)
+        .chain_update(data)
+        .finalize();
+    Sha256::new()
+        .chain_update(&opad)
+        .chain_update(inner)
+        .finalize()
+        .to_vec()
+}
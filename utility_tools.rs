@@ -0,0 +1,407 @@
+// 时间 / 天气本地工具（currentTime / getWeather）
+//
+// 这类简单问题不必动用整次网页搜索，由本地直接应答：
+// - currentTime：params.timezone 为 IANA 时区（Asia/Shanghai）或固定偏移（UTC+8、+05:30），
+//   缺省为 UTC。IANA 时区读取系统 zoneinfo（TZif，$TZDIR 或 /usr/share/zoneinfo），
+//   超出转换表的时间按文件末尾的 POSIX TZ 规则计算夏令时；系统无 zoneinfo 时请使用固定偏移。
+// - getWeather：params.location 为城市名（可带国家代码，如 "Beijing,CN"），
+//   需在 weather 中配置提供方 Key（目前支持 OpenWeatherMap），未配置时报错。
+
+use super::amp_accounting::{civil_from_days, days_from_civil};
+use super::audit_log::now_ms;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WeatherSettings {
+    /// 提供方：openweathermap
+    pub provider: String,
+    pub api_key: Option<String>,
+    /// 单位制：metric / imperial
+    pub units: String,
+    /// 描述语言，如 zh_cn
+    pub language: String,
+}
+
+impl Default for WeatherSettings {
+    fn default() -> Self {
+        Self {
+            provider: "openweathermap".to_string(),
+            api_key: None,
+            units: "metric".to_string(),
+            language: "zh_cn".to_string(),
+        }
+    }
+}
+
+/// 某一时刻的时区偏移
+struct ZoneOffset {
+    /// 东正西负（秒）
+    offset: i64,
+    abbreviation: String,
+    dst: bool,
+}
+
+/// "+08:00" / "-0530" / "8" → 秒
+fn parse_offset(raw: &str) -> Option<i64> {
+    let (sign, rest) = match raw.as_bytes().first()? {
+        b'+' => (1, &raw[1..]),
+        b'-' => (-1, &raw[1..]),
+        _ => (1, raw),
+    };
+    let parts: Vec<&str> = if rest.contains(':') {
+        rest.split(':').collect()
+    } else if rest.len() > 2 {
+        let (h, m) = rest.split_at(rest.len() - 2);
+        vec![h, m]
+    } else {
+        vec![rest]
+    };
+    let mut secs = 0;
+    for (part, unit) in parts.iter().zip([3600, 60, 1]) {
+        let value: i64 = part.parse().ok()?;
+        secs += value * unit;
+    }
+    if parts.len() > 3 || secs > 18 * 3600 {
+        return None;
+    }
+    Some(sign * secs)
+}
+
+/// 固定偏移：UTC / GMT / Z，及 UTC+8、GMT-03:00、+05:30
+fn fixed_zone(tz: &str) -> Option<ZoneOffset> {
+    let upper = tz.to_ascii_uppercase();
+    if matches!(upper.as_str(), "UTC" | "GMT" | "Z") {
+        return Some(ZoneOffset {
+            offset: 0,
+            abbreviation: "UTC".to_string(),
+            dst: false,
+        });
+    }
+    let rest = upper
+        .strip_prefix("UTC")
+        .or_else(|| upper.strip_prefix("GMT"))
+        .unwrap_or(&upper);
+    if !rest.starts_with(['+', '-']) {
+        return None;
+    }
+    let offset = parse_offset(rest)?;
+    Some(ZoneOffset {
+        offset,
+        abbreviation: format!("UTC{}", format_offset(offset)),
+        dst: false,
+    })
+}
+
+fn format_offset(offset: i64) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let abs = offset.abs();
+    format!("{}{:02}:{:02}", sign, abs / 3600, abs % 3600 / 60)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_i64(data: &[u8], pos: usize, size: usize) -> Option<i64> {
+    let bytes = data.get(pos..pos + size)?;
+    Some(if size == 8 {
+        i64::from_be_bytes(bytes.try_into().ok()?)
+    } else {
+        i32::from_be_bytes(bytes.try_into().ok()?) as i64
+    })
+}
+
+/// 解析 TZif 文件，返回 now 时刻的偏移
+fn tzif_zone(data: &[u8], now: i64) -> Option<ZoneOffset> {
+    if data.get(..4)? != b"TZif" {
+        return None;
+    }
+    // 头部计数：isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+    let counts = |header: usize| -> Option<[usize; 6]> {
+        let mut out = [0; 6];
+        for (i, slot) in out.iter_mut().enumerate() {
+            *slot = read_u32(data, header + 20 + i * 4)? as usize;
+        }
+        Some(out)
+    };
+    let block_len = |c: [usize; 6], time_size: usize| {
+        c[3] * time_size + c[3] + c[4] * 6 + c[5] + c[2] * (time_size + 4) + c[1] + c[0]
+    };
+    let mut header = 0;
+    let mut time_size = 4;
+    let mut c = counts(0)?;
+    // v2+ 跳过 32 位数据块，使用 64 位数据块与 POSIX 规则
+    if *data.get(4)? >= b'2' {
+        header = 44 + block_len(c, 4);
+        time_size = 8;
+        c = counts(header)?;
+    }
+    let [_, _, _, timecnt, typecnt, charcnt] = c;
+    let times_at = header + 44;
+    let index_at = times_at + timecnt * time_size;
+    let types_at = index_at + timecnt;
+    let chars_at = types_at + typecnt * 6;
+    let footer_at = header + 44 + block_len(c, time_size);
+
+    let ttinfo = |i: usize| -> Option<ZoneOffset> {
+        let at = types_at + i * 6;
+        let offset = read_u32(data, at)? as i32 as i64;
+        let dst = *data.get(at + 4)? != 0;
+        let abbr_at = chars_at + *data.get(at + 5)? as usize;
+        let abbr = data.get(abbr_at..chars_at + charcnt)?;
+        let end = abbr.iter().position(|b| *b == 0).unwrap_or(abbr.len());
+        Some(ZoneOffset {
+            offset,
+            abbreviation: String::from_utf8_lossy(&abbr[..end]).into_owned(),
+            dst,
+        })
+    };
+
+    let mut last = None;
+    for i in 0..timecnt {
+        if read_i64(data, times_at + i * time_size, time_size)? > now {
+            break;
+        }
+        last = Some(i);
+    }
+    // 超出转换表时按 POSIX TZ 规则
+    if time_size == 8 && last.map_or(timecnt == 0, |i| i + 1 == timecnt) {
+        let footer = data.get(footer_at..).unwrap_or_default();
+        let rule = String::from_utf8_lossy(footer);
+        if let Some(zone) = posix_zone(rule.trim_matches('\n'), now) {
+            return Some(zone);
+        }
+    }
+    match last {
+        Some(i) => ttinfo(*data.get(index_at + i)? as usize),
+        None if typecnt > 0 => ttinfo(0),
+        None => None,
+    }
+}
+
+/// POSIX TZ 名称：字母或 <...>
+fn take_name(rule: &str) -> Option<(&str, &str)> {
+    if let Some(rest) = rule.strip_prefix('<') {
+        let end = rest.find('>')?;
+        return Some((&rest[..end], &rest[end + 1..]));
+    }
+    let end = rule
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rule.len());
+    (end >= 3).then(|| rule.split_at(end))
+}
+
+/// POSIX TZ 偏移（西正东负），返回 (东正偏移秒数, 剩余)
+fn take_posix_offset(rule: &str) -> Option<(i64, &str)> {
+    let end = rule
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '+' | '-' | ':')))
+        .unwrap_or(rule.len());
+    if end == 0 {
+        return None;
+    }
+    Some((-parse_offset(&rule[..end])?, &rule[end..]))
+}
+
+/// Mm.w.d[/time] → 该年规则生效时刻（当地时间，自 1970 起秒数）
+fn rule_local_time(rule: &str, year: i64) -> Option<i64> {
+    let (date, time) = match rule.split_once('/') {
+        Some((date, time)) => (date, -take_posix_offset(time)?.0),
+        None => (rule, 7200),
+    };
+    let fields: Vec<i64> = date
+        .strip_prefix('M')?
+        .split('.')
+        .filter_map(|p| p.parse().ok())
+        .collect();
+    let [month, week, weekday] = fields.as_slice() else {
+        return None;
+    };
+    let first = days_from_civil(year, *month as u32, 1);
+    let first_weekday = (first + 4).rem_euclid(7);
+    let mut day = first + (weekday - first_weekday).rem_euclid(7) + (week - 1) * 7;
+    // 第 5 周表示当月最后一个
+    let next_month = if *month == 12 {
+        days_from_civil(year + 1, 1, 1)
+    } else {
+        days_from_civil(year, *month as u32 + 1, 1)
+    };
+    while day >= next_month {
+        day -= 7;
+    }
+    Some(day * 86_400 + time)
+}
+
+/// 按 POSIX TZ 规则（如 CET-1CEST,M3.5.0,M10.5.0/3）计算 now 时刻的偏移
+fn posix_zone(rule: &str, now: i64) -> Option<ZoneOffset> {
+    let (std_name, rest) = take_name(rule)?;
+    let (std_offset, rest) = take_posix_offset(rest)?;
+    let standard = ZoneOffset {
+        offset: std_offset,
+        abbreviation: std_name.to_string(),
+        dst: false,
+    };
+    if rest.is_empty() {
+        return Some(standard);
+    }
+    let (dst_name, rest) = take_name(rest)?;
+    let (dst_offset, rest) = match take_posix_offset(rest) {
+        Some(parsed) => parsed,
+        None => (std_offset + 3600, rest),
+    };
+    let mut rules = rest.strip_prefix(',')?.split(',');
+    let (start_rule, end_rule) = (rules.next()?, rules.next()?);
+    let (year, _, _) = civil_from_days((now + std_offset).div_euclid(86_400));
+    let start = rule_local_time(start_rule, year)? - std_offset;
+    let end = rule_local_time(end_rule, year)? - dst_offset;
+    let in_dst = if start < end {
+        now >= start && now < end
+    } else {
+        // 南半球：夏令时跨年
+        now >= start || now < end
+    };
+    Some(if in_dst {
+        ZoneOffset {
+            offset: dst_offset,
+            abbreviation: dst_name.to_string(),
+            dst: true,
+        }
+    } else {
+        standard
+    })
+}
+
+fn zoneinfo_dir() -> PathBuf {
+    std::env::var_os("TZDIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/usr/share/zoneinfo"))
+}
+
+fn resolve_zone(tz: &str, now: i64) -> Result<ZoneOffset> {
+    if let Some(zone) = fixed_zone(tz) {
+        return Ok(zone);
+    }
+    // 只接受 IANA 名称字符，防止路径穿越
+    let valid = !tz.is_empty()
+        && !tz.contains("..")
+        && !tz.starts_with('/')
+        && tz
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'));
+    if !valid {
+        bail!("无效时区: {}", tz);
+    }
+    let data = std::fs::read(zoneinfo_dir().join(tz)).map_err(|_| {
+        anyhow!(
+            "未找到时区 {}（系统无 zoneinfo 时请使用 UTC+8 之类的固定偏移）",
+            tz
+        )
+    })?;
+    tzif_zone(&data, now).ok_or_else(|| anyhow!("时区文件解析失败: {}", tz))
+}
+
+/// currentTime：指定时区的当前时间
+pub(crate) fn current_time(body: &[u8]) -> Result<Value> {
+    let req_json: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+    let tz = req_json["params"]["timezone"]
+        .as_str()
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .unwrap_or("UTC");
+    let now = (now_ms() / 1000) as i64;
+    let zone = resolve_zone(tz, now)?;
+
+    let local = now + zone.offset;
+    let days = local.div_euclid(86_400);
+    let secs = local.rem_euclid(86_400);
+    let (y, m, d) = civil_from_days(days);
+    const WEEKDAYS: [&str; 7] = [
+        "Thursday",
+        "Friday",
+        "Saturday",
+        "Sunday",
+        "Monday",
+        "Tuesday",
+        "Wednesday",
+    ];
+    let iso = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        y,
+        m,
+        d,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        format_offset(zone.offset)
+    );
+    tracing::info!("本地时间查询: {} → {}", tz, iso);
+    Ok(json!({
+        "ok": true,
+        "result": {
+            "timezone": tz,
+            "iso": iso,
+            "unixSeconds": now,
+            "utcOffset": format_offset(zone.offset),
+            "abbreviation": zone.abbreviation,
+            "isDst": zone.dst,
+            "weekday": WEEKDAYS[days.rem_euclid(7) as usize]
+        }
+    }))
+}
+
+/// getWeather：查询当前天气
+pub(crate) async fn weather(
+    client: &reqwest::Client,
+    settings: &WeatherSettings,
+    body: &[u8],
+) -> Result<Value> {
+    let req_json: Value =
+        serde_json::from_slice(body).map_err(|e| anyhow!("请求 JSON 解析失败: {}", e))?;
+    let location = req_json["params"]["location"]
+        .as_str()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .ok_or_else(|| anyhow!("缺少 location 参数"))?;
+    let api_key = settings
+        .api_key
+        .as_deref()
+        .ok_or_else(|| anyhow!("未配置天气服务 Key（weather.api_key）"))?;
+    if settings.provider != "openweathermap" {
+        bail!("不支持的天气服务: {}", settings.provider);
+    }
+
+    tracing::info!("本地天气查询: {}", location);
+    let resp = client
+        .get("https://api.openweathermap.org/data/2.5/weather")
+        .query(&[
+            ("q", location),
+            ("appid", api_key),
+            ("units", settings.units.as_str()),
+            ("lang", settings.language.as_str()),
+        ])
+        .send()
+        .await?;
+    let status = resp.status();
+    let data: Value = resp.json().await?;
+    if !status.is_success() {
+        bail!("天气服务返回 HTTP {}: {}", status, data["message"]);
+    }
+
+    Ok(json!({
+        "ok": true,
+        "result": {
+            "location": data["name"],
+            "country": data["sys"]["country"],
+            "description": data["weather"][0]["description"],
+            "temperature": data["main"]["temp"],
+            "feelsLike": data["main"]["feels_like"],
+            "humidity": data["main"]["humidity"],
+            "windSpeed": data["wind"]["speed"],
+            "units": settings.units,
+            "provider": settings.provider
+        }
+    }))
+}
@@ -0,0 +1,830 @@
+// 网页正文提取（extractWebPageContent）
+//
+// 原样返回 HTML 容易撑爆上下文窗口，提取模式可配置：
+// - raw：原始 HTML（默认，与 AMP-Manager 行为一致）
+// - readability：按 Readability 思路去掉导航 / 页脚 / 广告等样板内容，输出纯文本
+// - markdown：同样提取正文后转为 Markdown（标题、列表、链接、代码块、表格）
+// 请求可用 params.mode 覆盖配置。
+// 可选外部提取服务（reader，见 extract_services.rs）：Jina Reader / Firecrawl / 自建端点，
+// 适合 JS 渲染的页面；远程失败时自动回退本地抓取。显式 mode=raw 时不走远程。
+// HTML 解析为容错的轻量实现，不依赖外部解析库：
+// 未闭合标签按栈回退处理，script / style 等原始文本元素整体跳过。
+// 正文选取：<p> / <pre> / <td> 等文本块按长度与逗号数给父节点（及祖父节点一半）加分，
+// class / id 命中正文或样板关键词时加减分，最终分数乘以 (1 - 链接密度)，取最高者。
+
+use super::extract_services::ReaderSettings;
+use super::pdf_text::PdfSettings;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractMode {
+    #[default]
+    Raw,
+    Readability,
+    Markdown,
+}
+
+impl ExtractMode {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "raw" => Some(ExtractMode::Raw),
+            "readability" | "text" => Some(ExtractMode::Readability),
+            "markdown" | "md" => Some(ExtractMode::Markdown),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebExtractSettings {
+    /// 提取模式：raw / readability / markdown
+    pub mode: ExtractMode,
+    /// application/pdf 响应的文本提取上限
+    pub pdf: PdfSettings,
+    /// 外部提取服务（处理 JS 渲染页面，失败回退本地）
+    pub reader: ReaderSettings,
+}
+
+/// 无结束标签的元素
+const VOID_TAGS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+/// 内容按原始文本处理的元素
+const RAW_TEXT_TAGS: [&str; 5] = ["script", "style", "textarea", "title", "noscript"];
+/// 直接移除的元素
+const REMOVED_TAGS: [&str; 16] = [
+    "script", "style", "noscript", "nav", "header", "footer", "aside", "form", "iframe", "svg",
+    "button", "select", "textarea", "template", "canvas", "dialog",
+];
+/// 打开新块时自动闭合 <p>
+const BLOCK_TAGS: [&str; 24] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "div",
+    "dl",
+    "fieldset",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "main",
+    "nav",
+    "ol",
+    "pre",
+    "section",
+    "table",
+];
+/// 隐式闭合：打开该标签时回退到栈中最近的可闭合元素，遇到边界元素停止
+fn implied_close(tag: &str) -> Option<(&'static [&'static str], &'static [&'static str])> {
+    match tag {
+        "li" => Some((&["li"], &["ul", "ol"])),
+        "tr" => Some((&["tr"], &["table", "thead", "tbody", "tfoot"])),
+        "td" | "th" => Some((&["td", "th"], &["tr", "table"])),
+        "dt" | "dd" => Some((&["dt", "dd"], &["dl"])),
+        "option" => Some((&["option"], &["select"])),
+        "thead" | "tbody" | "tfoot" => Some((&["thead", "tbody", "tfoot"], &["table"])),
+        _ => None,
+    }
+}
+/// class / id 中的样板关键词
+const NEGATIVE_HINTS: [&str; 16] = [
+    "comment",
+    "sidebar",
+    "footer",
+    "footnote",
+    "nav",
+    "menu",
+    "share",
+    "social",
+    "cookie",
+    "banner",
+    "related",
+    "popup",
+    "modal",
+    "advert",
+    "sponsor",
+    "breadcrumb",
+];
+/// class / id 中的正文关键词
+const POSITIVE_HINTS: [&str; 8] = [
+    "article", "content", "main", "post", "entry", "body", "text", "story",
+];
+
+enum Node {
+    Element {
+        tag: String,
+        attrs: Vec<(String, String)>,
+        children: Vec<usize>,
+    },
+    Text(String),
+}
+
+struct Dom {
+    nodes: Vec<Node>,
+    parent: Vec<Option<usize>>,
+}
+
+impl Dom {
+    fn tag(&self, id: usize) -> &str {
+        match &self.nodes[id] {
+            Node::Element { tag, .. } => tag,
+            Node::Text(_) => "",
+        }
+    }
+
+    fn attr(&self, id: usize, name: &str) -> Option<&str> {
+        match &self.nodes[id] {
+            Node::Element { attrs, .. } => attrs
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str()),
+            Node::Text(_) => None,
+        }
+    }
+
+    fn children(&self, id: usize) -> &[usize] {
+        match &self.nodes[id] {
+            Node::Element { children, .. } => children,
+            Node::Text(_) => &[],
+        }
+    }
+
+    fn push(&mut self, parent: usize, node: Node) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(node);
+        self.parent.push(Some(parent));
+        if let Node::Element { children, .. } = &mut self.nodes[parent] {
+            children.push(id);
+        }
+        id
+    }
+
+    fn detach(&mut self, id: usize) {
+        if let Some(parent) = self.parent[id] {
+            if let Node::Element { children, .. } = &mut self.nodes[parent] {
+                children.retain(|c| *c != id);
+            }
+        }
+    }
+
+    /// 子树文本长度（去掉空白）
+    fn text_len(&self, id: usize) -> usize {
+        match &self.nodes[id] {
+            Node::Text(t) => t.split_whitespace().map(|w| w.chars().count() + 1).sum(),
+            Node::Element { children, .. } => children.iter().map(|c| self.text_len(*c)).sum(),
+        }
+    }
+
+    fn link_text_len(&self, id: usize) -> usize {
+        if self.tag(id) == "a" {
+            return self.text_len(id);
+        }
+        self.children(id)
+            .iter()
+            .map(|c| self.link_text_len(*c))
+            .sum()
+    }
+
+    fn text(&self, id: usize, out: &mut String) {
+        match &self.nodes[id] {
+            Node::Text(t) => out.push_str(t),
+            Node::Element { children, .. } => {
+                for child in children {
+                    self.text(*child, out);
+                }
+            }
+        }
+    }
+
+    fn find_first(&self, id: usize, tag: &str) -> Option<usize> {
+        if self.tag(id) == tag {
+            return Some(id);
+        }
+        self.children(id)
+            .iter()
+            .find_map(|c| self.find_first(*c, tag))
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let end = rest[..rest.len().min(12)].find(';');
+        let decoded = end.and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" | "#39" => '\'',
+                "nbsp" => ' ',
+                "mdash" => '—',
+                "ndash" => '–',
+                "hellip" => '…',
+                "lsquo" => '‘',
+                "rsquo" => '’',
+                "ldquo" => '“',
+                "rdquo" => '”',
+                "copy" => '©',
+                _ => {
+                    let code = match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => entity.strip_prefix('#').and_then(|d| d.parse().ok()),
+                    };
+                    char::from_u32(code?)?
+                }
+            };
+            Some((c, end + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 在 s 中不区分大小写查找 needle（needle 为小写 ASCII）
+fn find_ci(s: &str, needle: &str) -> Option<usize> {
+    s.as_bytes()
+        .windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+fn parse_html(html: &str) -> Dom {
+    let mut dom = Dom {
+        nodes: vec![Node::Element {
+            tag: "#root".to_string(),
+            attrs: Vec::new(),
+            children: Vec::new(),
+        }],
+        parent: vec![None],
+    };
+    let mut stack = vec![0usize];
+    let bytes = html.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let top = *stack.last().unwrap_or(&0);
+        if bytes[i] != b'<' {
+            let end = html[i..].find('<').map_or(html.len(), |p| i + p);
+            dom.push(top, Node::Text(decode_entities(&html[i..end])));
+            i = end;
+            continue;
+        }
+        let rest = &html[i..];
+        if rest.starts_with("<!--") {
+            i += rest.find("-->").map_or(rest.len(), |p| p + 3);
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            i += rest.find('>').map_or(rest.len(), |p| p + 1);
+            continue;
+        }
+        if let Some(close) = rest.strip_prefix("</") {
+            let name_len = close
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(close.len());
+            let name = close[..name_len].to_ascii_lowercase();
+            if let Some(pos) = stack.iter().rposition(|id| dom.tag(*id) == name) {
+                if pos > 0 {
+                    stack.truncate(pos);
+                }
+            }
+            i += rest.find('>').map_or(rest.len(), |p| p + 1);
+            continue;
+        }
+
+        // 开始标签
+        let body = &rest[1..];
+        let name_len = body
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+            .unwrap_or(body.len());
+        if name_len == 0 {
+            dom.push(top, Node::Text("<".to_string()));
+            i += 1;
+            continue;
+        }
+        let tag = body[..name_len].to_ascii_lowercase();
+        let mut j = 1 + name_len;
+        let mut attrs = Vec::new();
+        let mut self_closing = false;
+        let rb = rest.as_bytes();
+        while j < rb.len() {
+            match rb[j] {
+                b'>' => break,
+                b'/' => {
+                    self_closing = true;
+                    j += 1;
+                }
+                c if c.is_ascii_whitespace() => j += 1,
+                _ => {
+                    let start = j;
+                    while j < rb.len()
+                        && !matches!(rb[j], b'=' | b'>' | b'/')
+                        && !rb[j].is_ascii_whitespace()
+                    {
+                        j += 1;
+                    }
+                    let name = rest[start..j].to_ascii_lowercase();
+                    let mut value = String::new();
+                    if j < rb.len() && rb[j] == b'=' {
+                        j += 1;
+                        if j < rb.len() && (rb[j] == b'"' || rb[j] == b'\'') {
+                            let quote = rb[j];
+                            let vstart = j + 1;
+                            j = vstart;
+                            while j < rb.len() && rb[j] != quote {
+                                j += 1;
+                            }
+                            value = decode_entities(&rest[vstart..j.min(rb.len())]);
+                            j += 1;
+                        } else {
+                            let vstart = j;
+                            while j < rb.len() && rb[j] != b'>' && !rb[j].is_ascii_whitespace() {
+                                j += 1;
+                            }
+                            value = decode_entities(&rest[vstart..j]);
+                        }
+                    }
+                    self_closing = false;
+                    attrs.push((name, value));
+                }
+            }
+        }
+        i += (j + 1).min(rest.len());
+
+        // 隐式闭合
+        if let Some((closes, boundary)) = implied_close(&tag) {
+            for pos in (1..stack.len()).rev() {
+                let open = dom.tag(stack[pos]);
+                if closes.contains(&open) {
+                    stack.truncate(pos);
+                    break;
+                }
+                if boundary.contains(&open) {
+                    break;
+                }
+            }
+        }
+        let current = dom.tag(*stack.last().unwrap_or(&0));
+        if current == "p" && (tag == "p" || BLOCK_TAGS.contains(&tag.as_str())) && stack.len() > 1 {
+            stack.pop();
+        }
+        let parent = *stack.last().unwrap_or(&0);
+        let id = dom.push(
+            parent,
+            Node::Element {
+                tag: tag.clone(),
+                attrs,
+                children: Vec::new(),
+            },
+        );
+
+        if RAW_TEXT_TAGS.contains(&tag.as_str()) {
+            let content = &html[i..];
+            let end = find_ci(content, &format!("</{}", tag)).unwrap_or(content.len());
+            dom.push(id, Node::Text(decode_entities(&content[..end])));
+            i += end;
+            i += html[i..].find('>').map_or(html.len() - i, |p| p + 1);
+            continue;
+        }
+        if !self_closing && !VOID_TAGS.contains(&tag.as_str()) {
+            stack.push(id);
+        }
+    }
+    dom
+}
+
+fn is_boilerplate(dom: &Dom, id: usize) -> bool {
+    let tag = dom.tag(id);
+    if REMOVED_TAGS.contains(&tag) {
+        return true;
+    }
+    if dom.attr(id, "hidden").is_some()
+        || dom.attr(id, "aria-hidden") == Some("true")
+        || dom
+            .attr(id, "style")
+            .is_some_and(|s| s.replace(' ', "").contains("display:none"))
+    {
+        return true;
+    }
+    if matches!(tag, "body" | "main" | "article" | "#root") {
+        return false;
+    }
+    let hints = format!(
+        "{} {}",
+        dom.attr(id, "class").unwrap_or(""),
+        dom.attr(id, "id").unwrap_or("")
+    )
+    .to_lowercase();
+    NEGATIVE_HINTS.iter().any(|h| hints.contains(h))
+        && !POSITIVE_HINTS.iter().any(|h| hints.contains(h))
+}
+
+fn strip_boilerplate(dom: &mut Dom, id: usize) {
+    let children = dom.children(id).to_vec();
+    for child in children {
+        if matches!(dom.nodes[child], Node::Text(_)) {
+            continue;
+        }
+        if is_boilerplate(dom, child) {
+            dom.detach(child);
+        } else {
+            strip_boilerplate(dom, child);
+        }
+    }
+}
+
+fn class_weight(dom: &Dom, id: usize) -> f64 {
+    let hints = format!(
+        "{} {}",
+        dom.attr(id, "class").unwrap_or(""),
+        dom.attr(id, "id").unwrap_or("")
+    )
+    .to_lowercase();
+    let mut weight = 0.0;
+    if POSITIVE_HINTS.iter().any(|h| hints.contains(h)) {
+        weight += 25.0;
+    }
+    if NEGATIVE_HINTS.iter().any(|h| hints.contains(h)) {
+        weight -= 25.0;
+    }
+    weight
+}
+
+/// 选出正文节点
+fn best_candidate(dom: &Dom) -> usize {
+    let mut scores: std::collections::HashMap<usize, f64> = std::collections::HashMap::new();
+    let mut stack = vec![0usize];
+    while let Some(id) = stack.pop() {
+        stack.extend(dom.children(id));
+        if !matches!(dom.tag(id), "p" | "pre" | "td" | "blockquote" | "li") {
+            continue;
+        }
+        let mut text = String::new();
+        dom.text(id, &mut text);
+        let len = text.trim().chars().count();
+        if len < 25 {
+            continue;
+        }
+        let score = 1.0 + text.matches([',', '，']).count() as f64 + (len as f64 / 100.0).min(3.0);
+        if let Some(parent) = dom.parent[id] {
+            *scores
+                .entry(parent)
+                .or_insert_with(|| class_weight(dom, parent)) += score;
+            if let Some(grand) = dom.parent[parent] {
+                *scores
+                    .entry(grand)
+                    .or_insert_with(|| class_weight(dom, grand)) += score / 2.0;
+            }
+        }
+    }
+    scores
+        .into_iter()
+        .map(|(id, score)| {
+            let total = dom.text_len(id).max(1) as f64;
+            let density = dom.link_text_len(id) as f64 / total;
+            (id, score * (1.0 - density))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(id, _)| id)
+        .or_else(|| dom.find_first(0, "body"))
+        .unwrap_or(0)
+}
+
+struct Renderer<'a> {
+    dom: &'a Dom,
+    markdown: bool,
+    base: Option<Url>,
+    /// 列表栈：Some(序号) 为有序列表
+    lists: Vec<Option<usize>>,
+}
+
+/// 保证末尾至少 n 个换行
+fn ensure_breaks(out: &mut String, n: usize) {
+    if out.is_empty() {
+        return;
+    }
+    while out.ends_with(' ') {
+        out.pop();
+    }
+    let have = out.len() - out.trim_end_matches('\n').len();
+    for _ in have..n {
+        out.push('\n');
+    }
+}
+
+impl Renderer<'_> {
+    fn resolve(&self, href: &str) -> String {
+        self.base
+            .as_ref()
+            .and_then(|b| b.join(href).ok())
+            .map(|u| u.to_string())
+            .unwrap_or_else(|| href.to_string())
+    }
+
+    fn inner(&mut self, id: usize) -> String {
+        let mut out = String::new();
+        for child in self.dom.children(id).to_vec() {
+            self.render(child, &mut out);
+        }
+        out
+    }
+
+    fn render(&mut self, id: usize, out: &mut String) {
+        let dom = self.dom;
+        let tag = match &dom.nodes[id] {
+            Node::Text(text) => {
+                let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if collapsed.is_empty() {
+                    if !out.is_empty() && !out.ends_with([' ', '\n']) && !text.is_empty() {
+                        out.push(' ');
+                    }
+                    return;
+                }
+                if text.starts_with(char::is_whitespace)
+                    && !out.ends_with([' ', '\n'])
+                    && !out.is_empty()
+                {
+                    out.push(' ');
+                }
+                out.push_str(&collapsed);
+                if text.ends_with(char::is_whitespace) {
+                    out.push(' ');
+                }
+                return;
+            }
+            Node::Element { tag, .. } => tag.as_str(),
+        };
+        let md = self.markdown;
+        match tag {
+            "title" | "head" => {}
+            "br" => {
+                while out.ends_with(' ') {
+                    out.pop();
+                }
+                out.push('\n');
+            }
+            "hr" => {
+                ensure_breaks(out, 2);
+                if md {
+                    out.push_str("---");
+                }
+                ensure_breaks(out, 2);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let text = self.inner(id).trim().to_string();
+                if text.is_empty() {
+                    return;
+                }
+                ensure_breaks(out, 2);
+                if md {
+                    let level = tag[1..].parse().unwrap_or(1);
+                    out.push_str(&"#".repeat(level));
+                    out.push(' ');
+                }
+                out.push_str(&text.replace('\n', " "));
+                ensure_breaks(out, 2);
+            }
+            "pre" => {
+                let mut raw = String::new();
+                dom.text(id, &mut raw);
+                ensure_breaks(out, 2);
+                if md {
+                    out.push_str("```\n");
+                    out.push_str(raw.trim_matches('\n'));
+                    out.push_str("\n```");
+                } else {
+                    out.push_str(raw.trim_matches('\n'));
+                }
+                ensure_breaks(out, 2);
+            }
+            "code" if md => {
+                let text = self.inner(id);
+                if !text.trim().is_empty() {
+                    out.push('`');
+                    out.push_str(text.trim());
+                    out.push('`');
+                }
+            }
+            "strong" | "b" if md => self.wrap(id, "**", out),
+            "em" | "i" if md => self.wrap(id, "*", out),
+            "a" if md => {
+                let text = self.inner(id);
+                let text = text.trim();
+                match dom.attr(id, "href") {
+                    Some(href)
+                        if !text.is_empty()
+                            && !href.starts_with('#')
+                            && !href.starts_with("javascript:") =>
+                    {
+                        out.push_str(&format!("[{}]({})", text, self.resolve(href)));
+                    }
+                    _ => out.push_str(text),
+                }
+            }
+            "img" if md => {
+                if let Some(src) = dom.attr(id, "src").filter(|s| !s.starts_with("data:")) {
+                    let alt = dom.attr(id, "alt").unwrap_or("");
+                    out.push_str(&format!("![{}]({})", alt, self.resolve(src)));
+                }
+            }
+            "ul" | "ol" => {
+                ensure_breaks(out, if self.lists.is_empty() { 2 } else { 1 });
+                self.lists.push((tag == "ol").then_some(0));
+                for child in dom.children(id).to_vec() {
+                    self.render(child, out);
+                }
+                self.lists.pop();
+                ensure_breaks(out, if self.lists.is_empty() { 2 } else { 1 });
+            }
+            "li" => {
+                ensure_breaks(out, 1);
+                let depth = self.lists.len().saturating_sub(1);
+                out.push_str(&"  ".repeat(depth));
+                match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        out.push_str(&format!("{}. ", n));
+                    }
+                    _ => out.push_str(if md { "- " } else { "• " }),
+                }
+                let text = self.inner(id);
+                out.push_str(text.trim());
+                ensure_breaks(out, 1);
+            }
+            "blockquote" => {
+                let text = self.inner(id);
+                ensure_breaks(out, 2);
+                let prefix = if md { "> " } else { "  " };
+                let quoted: Vec<String> = text
+                    .trim()
+                    .lines()
+                    .map(|l| format!("{}{}", prefix, l))
+                    .collect();
+                out.push_str(&quoted.join("\n"));
+                ensure_breaks(out, 2);
+            }
+            "table" => {
+                ensure_breaks(out, 2);
+                let mut rows = Vec::new();
+                self.collect_rows(id, &mut rows);
+                for (i, row) in rows.iter().enumerate() {
+                    out.push_str(&format!("| {} |\n", row.join(" | ")));
+                    if md && i == 0 {
+                        out.push_str(&format!("|{}\n", " --- |".repeat(row.len())));
+                    }
+                }
+                ensure_breaks(out, 2);
+            }
+            _ => {
+                let block = matches!(
+                    tag,
+                    "p" | "div"
+                        | "section"
+                        | "article"
+                        | "main"
+                        | "figure"
+                        | "figcaption"
+                        | "dl"
+                        | "dt"
+                        | "dd"
+                        | "details"
+                        | "summary"
+                        | "body"
+                );
+                if block {
+                    ensure_breaks(out, 2);
+                }
+                for child in dom.children(id).to_vec() {
+                    self.render(child, out);
+                }
+                if block {
+                    ensure_breaks(out, 2);
+                }
+            }
+        }
+    }
+
+    fn wrap(&mut self, id: usize, marker: &str, out: &mut String) {
+        let text = self.inner(id);
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            return;
+        }
+        if text.starts_with(' ') && !out.ends_with([' ', '\n']) {
+            out.push(' ');
+        }
+        out.push_str(marker);
+        out.push_str(trimmed);
+        out.push_str(marker);
+        if text.ends_with(' ') {
+            out.push(' ');
+        }
+    }
+
+    fn collect_rows(&mut self, id: usize, rows: &mut Vec<Vec<String>>) {
+        for child in self.dom.children(id).to_vec() {
+            match self.dom.tag(child) {
+                "tr" => {
+                    let cells: Vec<String> = self
+                        .dom
+                        .children(child)
+                        .to_vec()
+                        .into_iter()
+                        .filter(|c| matches!(self.dom.tag(*c), "td" | "th"))
+                        .map(|c| {
+                            self.inner(c)
+                                .split_whitespace()
+                                .collect::<Vec<_>>()
+                                .join(" ")
+                                .replace('|', "\\|")
+                        })
+                        .collect();
+                    if !cells.is_empty() {
+                        rows.push(cells);
+                    }
+                }
+                // 嵌套表格不展开
+                "table" => {}
+                _ => self.collect_rows(child, rows),
+            }
+        }
+    }
+}
+
+/// 按模式提取正文；raw 原样返回
+pub(crate) fn extract(html: &str, mode: ExtractMode, page_url: &str) -> String {
+    if mode == ExtractMode::Raw {
+        return html.to_string();
+    }
+    let mut dom = parse_html(html);
+    let title = dom.find_first(0, "title").map(|id| {
+        let mut t = String::new();
+        dom.text(id, &mut t);
+        t.split_whitespace().collect::<Vec<_>>().join(" ")
+    });
+    strip_boilerplate(&mut dom, 0);
+    let root = best_candidate(&dom);
+
+    let mut renderer = Renderer {
+        dom: &dom,
+        markdown: mode == ExtractMode::Markdown,
+        base: Url::parse(page_url).ok(),
+        lists: Vec::new(),
+    };
+    let mut body = String::new();
+    renderer.render(root, &mut body);
+
+    // 合并多余空行
+    let mut content = String::new();
+    let mut blank = 0;
+    for line in body.lines() {
+        let line = line.trim_end();
+        if line.trim().is_empty() {
+            blank += 1;
+            continue;
+        }
+        if !content.is_empty() {
+            content.push_str(if blank > 0 { "\n\n" } else { "\n" });
+        }
+        blank = 0;
+        content.push_str(line);
+    }
+
+    let first_line = content.lines().next().unwrap_or("");
+    let heading = first_line.trim_start_matches('#').trim();
+    match title.filter(|t| !t.is_empty() && heading != t) {
+        Some(title) if mode == ExtractMode::Markdown => format!("# {}\n\n{}", title, content),
+        Some(title) => format!("{}\n\n{}", title, content),
+        None => content,
+    }
+}